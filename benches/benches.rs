@@ -1,12 +1,17 @@
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use rand::{Rng, SeedableRng};
-use rand::distributions::Standard;
+use rand::distributions::{Alphanumeric, Standard};
 use rand::rngs::{StdRng};
 use rand::seq::IteratorRandom;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::thread;
 use tempfile::TempDir;
 
-use kvs::{KvsEngine, KvStore, SledKvStore};
+use kvs::{decode_request, decode_response, encode_request, encode_response};
+use kvs::{Client, ClientBuilder, KvsEngine, KvStore, Request, Response, Server, ServerConfig};
+use kvs::{Durability, SledKvStore};
 
 fn bench_kvs(c: &mut Criterion) {
     c.bench_function("kvs_write", |b| {
@@ -70,6 +75,113 @@ fn bench_sled(c: &mut Criterion) {
     });
 }
 
+/// Compare the same small-value workload run against an embedded store, and through a server.
+///
+/// The difference between each pair of adjacent benchmarks is a part of the protocol overhead:
+///
+/// - `protocol_codec`: encoding and decoding a request and its response, without any I/O.
+/// - `protocol_in_process` minus `protocol_embedded` and `protocol_codec`: the server's request
+///   handling (validation, stats, etc.), over an in-process channel.
+/// - `protocol_loopback` minus `protocol_in_process`: connecting, and the socket syscalls.
+/// - `protocol_loopback_nagle` minus `protocol_loopback`: the cost of leaving Nagle's algorithm
+///   enabled (i.e. not setting `TCP_NODELAY`).
+fn bench_protocol(c: &mut Criterion) {
+    c.bench_function("protocol_embedded_set", |b| {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = KvStore::open(temp_dir.path()).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        b.iter_batched(
+            || gen_small_kv(&mut rng),
+            |(key, val)| engine.set(key, val).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("protocol_embedded_get", |b| {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = KvStore::open(temp_dir.path()).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let keys = gen_small_data(&mut rng, |key, val| engine.set(key, val).unwrap());
+
+        b.iter_batched(
+            || keys.iter().choose(&mut rng).unwrap().to_owned(),
+            |key| engine.get(key).unwrap().unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("protocol_codec_set", |b| {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut buffer = Vec::new();
+
+        b.iter_batched(
+            || gen_small_kv(&mut rng),
+            |(key, value)| {
+                buffer.clear();
+                let request =
+                    Request::Set { key, value, ttl_ms: None, durability: Durability::Applied };
+                encode_request(&mut buffer, &request).unwrap();
+                decode_request(&buffer[..]).unwrap();
+                buffer.clear();
+                encode_response(&mut buffer, &Response::Ok).unwrap();
+                decode_response(&buffer[..]).unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let temp_dir = TempDir::new().unwrap();
+    let engine = KvStore::open(temp_dir.path()).unwrap();
+    let (mut server, mut client) = Server::start_in_process(discard_log(), engine).unwrap();
+    thread::spawn(move || server.run());
+    let mut rng = StdRng::seed_from_u64(0);
+    let keys = gen_small_data(&mut rng, |key, val| client.set(key, val).unwrap());
+    bench_client("protocol_in_process", c, client, keys);
+
+    for &(name, nodelay) in &[("protocol_loopback", true), ("protocol_loopback_nagle", false)] {
+        let temp_dir = TempDir::new().unwrap();
+        let engine = KvStore::open(temp_dir.path()).unwrap();
+        let config = ServerConfig { nodelay, ..ServerConfig::default() };
+        let mut server =
+            Server::start_with_config(discard_log(), engine, "127.0.0.1:0", config).unwrap();
+        let address = server.local_addrs().unwrap()[0];
+        thread::spawn(move || server.run());
+
+        let mut client = ClientBuilder::new().nodelay(nodelay).connect(address).unwrap();
+        let keys = gen_small_data(&mut rng, |key, val| client.set(key, val).unwrap());
+        bench_client(name, c, client, keys);
+    }
+}
+
+/// Benchmark setting and getting small entries with `client`, which has already set `keys`.
+fn bench_client(name: &str, c: &mut Criterion, client: Client, keys: Vec<String>) {
+    let client = Rc::new(RefCell::new(client));
+
+    let set_client = Rc::clone(&client);
+    c.bench_function(&format!("{}_set", name), move |b| {
+        let mut rng = StdRng::seed_from_u64(0);
+        b.iter_batched(
+            || gen_small_kv(&mut rng),
+            |(key, val)| set_client.borrow_mut().set(key, val).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function(&format!("{}_get", name), move |b| {
+        let mut rng = StdRng::seed_from_u64(0);
+        b.iter_batched(
+            || keys.iter().choose(&mut rng).unwrap().to_owned(),
+            |key| client.borrow_mut().get(key).unwrap().unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn discard_log() -> slog::Logger {
+    slog::Logger::root(slog::Discard, slog::o!())
+}
+
 fn gen_data(mut rng: impl Rng, engine: &mut impl KvsEngine) -> HashMap<String, String> {
     let mut data = HashMap::with_capacity(1000);
     for _ in 0..1000 {
@@ -80,6 +192,24 @@ fn gen_data(mut rng: impl Rng, engine: &mut impl KvsEngine) -> HashMap<String, S
     data
 }
 
+/// Generate 1000 small entries, returning their keys.
+fn gen_small_data(mut rng: impl Rng, mut set: impl FnMut(String, String)) -> Vec<String> {
+    let mut keys = Vec::with_capacity(1000);
+    for _ in 0..1000 {
+        let (key, value) = gen_small_kv(&mut rng);
+        keys.push(key.clone());
+        set(key, value);
+    }
+    keys
+}
+
+/// Generate a small entry, so that the protocol overhead dominates the cost of a request.
+fn gen_small_kv(mut rng: impl Rng) -> (String, String) {
+    let key = rng.sample_iter(&Alphanumeric).take(16).collect();
+    let val = rng.sample_iter(&Alphanumeric).take(100).collect();
+    (key, val)
+}
+
 fn gen_kv(mut rng: impl Rng) -> (String, String) {
     let key_len = rng.gen_range(1, 100001);
     let key = rng.sample_iter::<char, _>(&Standard).take(key_len).collect();
@@ -90,5 +220,5 @@ fn gen_kv(mut rng: impl Rng) -> (String, String) {
     (key, val)
 }
 
-criterion_group!(benches, bench_kvs, bench_sled);
+criterion_group!(benches, bench_kvs, bench_sled, bench_protocol);
 criterion_main!(benches);
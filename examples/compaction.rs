@@ -0,0 +1,261 @@
+//! A harness for measuring write throughput and latency while compaction is running.
+//!
+//! ```text
+//! cargo run --release --example compaction -- --value-size 1024 --skew 1.1
+//! cargo run --release --example compaction -- --strategy background-jobs --stores 4
+//! ```
+//!
+//! Each write's latency is recorded along with whether it triggered a compaction, and percentiles
+//! are reported for all writes and for the compacting writes alone. Writes are spread over
+//! `--stores` stores, each written from its own thread.
+
+#[macro_use]
+extern crate clap;
+
+use clap::Arg;
+use rand::distributions::Alphanumeric;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+use kvs::{BackgroundJobs, KvStore, KvStoreBuilder, KvsEngine, MetricsSink, Result};
+
+/// The compaction strategies that can be measured:
+///
+/// - `inline` compacts the whole log in the write that crosses the threshold.
+/// - `oldest-files` splits the log into segments and compacts only the oldest few at a time.
+/// - `background-jobs` shares a single compaction slot between the stores, so a store that needs
+///   compacting while another is defers until a later write.
+const STRATEGIES: &[&str] = &["inline", "oldest-files", "background-jobs"];
+
+/// The segment size used by the `oldest-files` strategy.
+const SEGMENT_SIZE: u64 = 256 * 1024;
+
+/// The number of segments the `oldest-files` strategy compacts at a time.
+const OLDEST_FILES: usize = 4;
+
+fn main() -> Result<()> {
+    let matches = app_from_crate!()
+        .arg(
+            Arg::with_name("strategy")
+                .long("strategy")
+                .takes_value(true)
+                .possible_values(STRATEGIES)
+                .default_value("inline"),
+        )
+        .arg(
+            Arg::with_name("stores")
+                .long("stores")
+                .takes_value(true)
+                .default_value("1")
+                .help("Number of stores to spread the writes over"),
+        )
+        .arg(
+            Arg::with_name("writes")
+                .long("writes")
+                .takes_value(true)
+                .default_value("100000")
+                .help("Number of writes to perform"),
+        )
+        .arg(
+            Arg::with_name("keys")
+                .long("keys")
+                .takes_value(true)
+                .default_value("1000")
+                .help("Number of distinct keys to write"),
+        )
+        .arg(
+            Arg::with_name("value-size")
+                .long("value-size")
+                .takes_value(true)
+                .default_value("100")
+                .help("Size of each value in bytes"),
+        )
+        .arg(
+            Arg::with_name("skew")
+                .long("skew")
+                .takes_value(true)
+                .default_value("0")
+                .help("Zipf exponent for key selection (0 for uniform)"),
+        )
+        .get_matches();
+
+    let strategy = matches.value_of("strategy").expect("strategy has a default");
+    let stores = value_t_or_exit!(matches, "stores", usize).max(1);
+    let writes = value_t_or_exit!(matches, "writes", usize);
+    let keys = value_t_or_exit!(matches, "keys", usize).max(1);
+    let value_size = value_t_or_exit!(matches, "value-size", usize);
+    let skew = value_t_or_exit!(matches, "skew", f64);
+
+    // Builders share their background jobs when cloned, so every store gets the same slot.
+    let builder = match strategy {
+        "inline" => KvStore::builder(),
+        "oldest-files" => KvStore::builder()
+            .max_segment_size(SEGMENT_SIZE)
+            .max_compaction_files(OLDEST_FILES),
+        "background-jobs" => KvStore::builder().background_jobs(BackgroundJobs::new(1)),
+        _ => unreachable!("clap only accepts the listed strategies"),
+    };
+
+    let start = Instant::now();
+    let handles: Vec<_> = (0..stores)
+        .map(|n| {
+            let builder = builder.clone();
+            let writes = writes / stores + if n < writes % stores { 1 } else { 0 };
+            let workload = Workload { writes, keys, value_size, skew, seed: n as u64 };
+            thread::spawn(move || workload.run(builder))
+        })
+        .collect();
+    let mut results = Vec::with_capacity(stores);
+    for handle in handles {
+        results.push(handle.join().expect("writer thread panicked")?);
+    }
+    let total = start.elapsed();
+
+    let mut all = Vec::with_capacity(writes);
+    let mut compacting = Vec::new();
+    let (mut compactions, mut deferred) = (0, 0);
+    for result in results {
+        all.extend(result.all);
+        compacting.extend(result.compacting);
+        compactions += result.compactions;
+        deferred += result.deferred;
+    }
+
+    println!(
+        "strategy={} stores={} writes={} keys={} value_size={} skew={}",
+        strategy, stores, writes, keys, value_size, skew
+    );
+    println!(
+        "throughput: {:.0} writes/s ({:.1} MiB/s)",
+        writes as f64 / total.as_secs_f64(),
+        (writes * value_size) as f64 / total.as_secs_f64() / (1024.0 * 1024.0),
+    );
+    println!("compactions: {} ({} deferred)", compactions, deferred);
+    report("all writes", &mut all);
+    report("compacting writes", &mut compacting);
+
+    Ok(())
+}
+
+/// The writes made to a single store.
+struct Workload {
+    writes: usize,
+    keys: usize,
+    value_size: usize,
+    skew: f64,
+    seed: u64,
+}
+
+/// The write latencies measured on a single store.
+struct Latencies {
+    all: Vec<Duration>,
+    compacting: Vec<Duration>,
+    compactions: u64,
+    deferred: u64,
+}
+
+impl Workload {
+    /// Open a store in a new temporary directory with `builder`, and time each write to it.
+    fn run(self, builder: KvStoreBuilder) -> Result<Latencies> {
+        let temp_dir = TempDir::new()?;
+        let compactions = Compactions::default();
+        let mut store = builder.metrics(compactions.clone()).open(temp_dir.path())?;
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let zipf = Zipf::new(self.keys, self.skew);
+
+        let mut all = Vec::with_capacity(self.writes);
+        let mut compacting = Vec::new();
+        let mut compacted = compactions.count();
+        for _ in 0..self.writes {
+            let key = format!("key{}", zipf.sample(&mut rng));
+            let value: String = rng.sample_iter(&Alphanumeric).take(self.value_size).collect();
+
+            let write_start = Instant::now();
+            store.set(key, value)?;
+            let elapsed = write_start.elapsed();
+
+            all.push(elapsed);
+            if compactions.count() > compacted {
+                compacting.push(elapsed);
+                compacted = compactions.count();
+            }
+        }
+
+        let stats = store.stats()?;
+        Ok(Latencies {
+            all,
+            compacting,
+            compactions: stats.compactions,
+            deferred: stats.deferred_compactions,
+        })
+    }
+}
+
+/// A [`MetricsSink`] counting a store's compactions, so that writes that compacted can be picked
+/// out without asking the store for its stats after every write.
+#[derive(Clone, Debug, Default)]
+struct Compactions(Arc<AtomicU64>);
+
+impl Compactions {
+    fn count(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl MetricsSink for Compactions {
+    fn counter(&self, name: &str, value: u64) {
+        if name == "kvs_engine_compactions_total" {
+            self.0.fetch_add(value, Ordering::Relaxed);
+        }
+    }
+}
+
+fn report(label: &str, latencies: &mut [Duration]) {
+    if latencies.is_empty() {
+        println!("{}: none", label);
+        return;
+    }
+    latencies.sort_unstable();
+    let last = (latencies.len() - 1) as f64;
+    let percentile = |p: f64| latencies[(last * p / 100.0).round() as usize];
+    println!(
+        "{} ({}): p50={:?} p99={:?} p99.9={:?} max={:?}",
+        label,
+        latencies.len(),
+        percentile(50.0),
+        percentile(99.0),
+        percentile(99.9),
+        latencies[latencies.len() - 1],
+    );
+}
+
+/// Samples integers in `0..n` following a Zipf distribution with exponent `s`.
+struct Zipf {
+    cumulative: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, s: f64) -> Self {
+        let mut total = 0.0;
+        let cumulative = (1..=n)
+            .map(|rank| {
+                total += 1.0 / (rank as f64).powf(s);
+                total
+            })
+            .collect();
+        Zipf { cumulative }
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> usize {
+        let total = *self.cumulative.last().expect("Zipf over no values");
+        let target = rng.gen::<f64>() * total;
+        match self.cumulative.binary_search_by(|c| c.partial_cmp(&target).expect("NaN weight")) {
+            Ok(i) | Err(i) => i.min(self.cumulative.len() - 1),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! An asynchronous server and client, which send and receive requests without blocking, on a
+//! [tokio] runtime.
+//!
+//! They speak the same protocol as [`kvs::Server`] and [`kvs::Client`], so either can be used
+//! with the other's synchronous counterpart. The synchronous API remains for code that doesn't
+//! run on tokio.
+//!
+//! ```
+//! use kvs::r#async::{Client, Server};
+//! use kvs::{MemoryKvStore, Result};
+//!
+//! # fn check() -> Result<()> {
+//! let log = slog::Logger::root(slog::Discard, slog::o!());
+//! let server = Server::new(kvs::Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?)?;
+//! let address = server.local_addr()?;
+//!
+//! let runtime = tokio::runtime::Runtime::new()?;
+//! runtime.spawn(server.run());
+//! runtime.block_on(async {
+//!     let mut client = Client::connect(address).await?;
+//!     client.set("key".to_owned(), "value".to_owned()).await?;
+//!     assert_eq!(client.get("key".to_owned()).await?, Some("value".to_owned()));
+//!     Ok(())
+//! })
+//! # }
+//! ```
+//!
+//! [tokio]: https://tokio.rs
+//! [`kvs::Server`]: ../struct.Server.html
+//! [`kvs::Client`]: ../struct.Client.html
+
+mod client;
+mod server;
+
+use std::io;
+
+use serde::de::DeserializeOwned;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+use crate::error::Result;
+use crate::protocol::Decoder;
+
+pub use self::client::Client;
+pub use self::server::Server;
+
+/// Read a message from `stream` in chunks of up to `chunk_size` bytes, waiting until it's been
+/// received in full.
+async fn receive<T: DeserializeOwned>(stream: &mut TcpStream, chunk_size: usize) -> Result<T> {
+    let mut decoder = Decoder::new();
+    let mut chunk = vec![0; chunk_size.max(1)];
+    loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return match decoder.decode()? {
+                Some(message) => Ok(message),
+                None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            };
+        }
+        decoder.extend(&chunk[..read]);
+        if let Some(message) = decoder.decode()? {
+            return Ok(message);
+        }
+    }
+}
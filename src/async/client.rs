@@ -0,0 +1,160 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::client::{byte_entries_response, bytes_value_response, entries_response};
+use crate::client::{ok_response, unexpected, value_response};
+use crate::error::Result;
+use crate::protocol::{encode_request, Durability, Request, Response, ServerInfo};
+use crate::stats::Stats;
+
+/// The capacity of the buffer used to read each response.
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// A client for a key-value server, which sends requests and receives their responses without
+/// blocking, on a tokio runtime.
+///
+/// Servers handle a single request per connection, so each request after the first is sent on a
+/// new connection to the same address, as with a [`kvs::Client`].
+///
+/// [`kvs::Client`]: ../struct.Client.html
+pub struct Client {
+    address: SocketAddr,
+    stream: Option<TcpStream>,
+}
+
+impl Client {
+    /// Connect to a server, at the first of `address`'s addresses that accepts a connection.
+    pub async fn connect<A: ToSocketAddrs>(address: A) -> Result<Client> {
+        let stream = TcpStream::connect(address).await?;
+        stream.set_nodelay(true)?;
+        Ok(Client { address: stream.peer_addr()?, stream: Some(stream) })
+    }
+
+    /// Send a request to the server, and read its response.
+    async fn send(&mut self, request: &Request) -> Result<Response> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => {
+                let stream = TcpStream::connect(self.address).await?;
+                stream.set_nodelay(true)?;
+                stream
+            },
+        };
+        let mut bytes = Vec::new();
+        encode_request(&mut bytes, request)?;
+        stream.write_all(&bytes).await?;
+        super::receive(&mut stream, BUFFER_SIZE).await
+    }
+
+    /// Get the value of a key.
+    pub async fn get(&mut self, key: String) -> Result<Option<String>> {
+        let request = Request::Get { key };
+        let response = self.send(&request).await?;
+        value_response(request, response)
+    }
+
+    /// Set the value of a key.
+    pub async fn set(&mut self, key: String, value: String) -> Result<()> {
+        let durability = Durability::Applied;
+        let request = Request::Set { key, value, ttl_ms: None, durability };
+        let response = self.send(&request).await?;
+        ok_response(request, response)
+    }
+
+    /// Set the value of a key, which expires after `ttl`.
+    pub async fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        let (ttl_ms, durability) = (Some(ttl.as_millis() as u64), Durability::Applied);
+        let request = Request::Set { key, value, ttl_ms, durability };
+        let response = self.send(&request).await?;
+        ok_response(request, response)
+    }
+
+    /// Remove a key.
+    pub async fn remove(&mut self, key: String) -> Result<()> {
+        let request = Request::Remove { key };
+        let response = self.send(&request).await?;
+        ok_response(request, response)
+    }
+
+    /// Get every key starting with `prefix`, with its value, in key order.
+    pub async fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        let request = Request::Scan { prefix };
+        let response = self.send(&request).await?;
+        entries_response(request, response)
+    }
+
+    /// Get the value of a key, either of which may be arbitrary bytes rather than UTF-8.
+    pub async fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let request = Request::GetBytes { key: key.to_vec() };
+        let response = self.send(&request).await?;
+        bytes_value_response(request, response)
+    }
+
+    /// Set the value of a key, either of which may be arbitrary bytes rather than UTF-8.
+    pub async fn set_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let (key, value, durability) = (key.to_vec(), value.to_vec(), Durability::Applied);
+        let request = Request::SetBytes { key, value, durability };
+        let response = self.send(&request).await?;
+        ok_response(request, response)
+    }
+
+    /// Remove a key that may be arbitrary bytes rather than UTF-8.
+    pub async fn remove_bytes(&mut self, key: &[u8]) -> Result<()> {
+        let request = Request::RemoveBytes { key: key.to_vec() };
+        let response = self.send(&request).await?;
+        ok_response(request, response)
+    }
+
+    /// Get every key starting with `prefix`, with its value, in key order, any of which may be
+    /// arbitrary bytes rather than UTF-8.
+    pub async fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let request = Request::ScanBytes { prefix: prefix.to_vec() };
+        let response = self.send(&request).await?;
+        byte_entries_response(request, response)
+    }
+
+    /// Add `delta` to the integer value of a key on the server (treating a missing key as `0`),
+    /// returning the new value.
+    ///
+    /// Fails with [`Error::InvalidValue`] (reported by the server) if the value isn't an integer.
+    ///
+    /// [`Error::InvalidValue`]: ../enum.Error.html#variant.InvalidValue
+    pub async fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        let request = Request::Increment { key, delta };
+        match self.send(&request).await? {
+            Response::Counter { value } => Ok(value),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Check that the server is responding, returning the round-trip time of the request.
+    pub async fn ping(&mut self) -> Result<Duration> {
+        let request = Request::Ping;
+        let start = Instant::now();
+        match self.send(&request).await? {
+            Response::Pong => Ok(start.elapsed()),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Retrieve the server's statistics.
+    pub async fn stats(&mut self) -> Result<Stats> {
+        let request = Request::Stats;
+        match self.send(&request).await? {
+            Response::Stats { stats } => Ok(*stats),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Retrieve the server's version, engine and enabled features.
+    pub async fn info(&mut self) -> Result<ServerInfo> {
+        let request = Request::Info;
+        match self.send(&request).await? {
+            Response::Info { info } => Ok(info),
+            response => Err(unexpected(request, response)),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::net::{self, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use slog::{debug, warn};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::engine::Engine;
+use crate::error::Result;
+use crate::protocol::Request;
+use crate::server::{self, peer_log, Peer};
+
+/// How long the handler waits for a request before running background work (sweeps, scrubs and
+/// so on).
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A request received in full, to be handled and responded to on the handler thread.
+struct Received {
+    log: slog::Logger,
+    peer: Peer,
+    stream: net::TcpStream,
+    request: Result<Request>,
+}
+
+/// A key-value server that accepts connections and receives their requests without blocking, on
+/// a tokio runtime.
+///
+/// It's built from a configured [`kvs::Server`], and handles requests just as that would, with
+/// the same key rules, tenants, sweeper and so on. Each connection is a task, so any number of
+/// requests can be received at once without a thread each, and a slow client never holds up the
+/// others. Requests received in full are handled one at a time on a thread of their own, so the
+/// engine's blocking I/O never holds up the runtime, and concurrent clients see the same
+/// linearizable store as they would from a [`kvs::Server`]. Background work runs on that thread
+/// between requests.
+///
+/// [`kvs::Server`]: ../struct.Server.html
+pub struct Server<E> {
+    server: server::Server<E>,
+    listeners: Vec<net::TcpListener>,
+}
+
+impl<E: Engine + Send + 'static> Server<E> {
+    /// Serve the connections to `server`'s addresses asynchronously. Fails for in-process
+    /// servers.
+    pub fn new(mut server: server::Server<E>) -> Result<Self> {
+        let listeners = server.take_listeners()?;
+        Ok(Server { server, listeners })
+    }
+
+    /// The address the server is listening on.
+    ///
+    /// If the server is listening on more than one address, this is the first (see
+    /// [`local_addrs`](#method.local_addrs)).
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listeners[0].local_addr()?)
+    }
+
+    /// Every address the server is listening on.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        let addresses = self.listeners.iter().map(net::TcpListener::local_addr);
+        Ok(addresses.collect::<std::io::Result<_>>()?)
+    }
+
+    /// Run the server, accepting connections forever. This must be run on a tokio runtime.
+    pub async fn run(self) {
+        let Server { server, listeners } = self;
+        let log = server.log().clone();
+        let (sender, receiver) = mpsc::channel();
+        let acceptor = Acceptor {
+            log: log.clone(),
+            sender,
+            connections: Arc::new(AtomicU64::new(0)),
+            buffer_size: server.config().buffer_size,
+            nodelay: server.config().nodelay,
+        };
+        thread::spawn(move || handle(server, receiver));
+
+        let mut accepts = Vec::new();
+        for listener in listeners {
+            match listen(listener) {
+                Ok(listener) => accepts.push(tokio::spawn(acceptor.clone().accept(listener))),
+                Err(error) => warn!(log, "Failed to listen: {}", error),
+            }
+        }
+        drop(acceptor);
+        for accept in accepts {
+            if let Err(error) = accept.await {
+                warn!(log, "Stopped accepting connections: {}", error);
+            }
+        }
+    }
+}
+
+/// Register a listener taken from a synchronous server with the runtime.
+fn listen(listener: net::TcpListener) -> Result<TcpListener> {
+    listener.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(listener)?)
+}
+
+/// Accepts connections from a listener, passing their requests to the handler thread once
+/// they've been received.
+#[derive(Clone)]
+struct Acceptor {
+    log: slog::Logger,
+    sender: mpsc::Sender<Received>,
+    connections: Arc<AtomicU64>,
+    buffer_size: usize,
+    nodelay: bool,
+}
+
+impl Acceptor {
+    /// Accept connections from `listener` forever, receiving each one's request in a task of its
+    /// own.
+    async fn accept(self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, address)) => {
+                    let connection_id = self.connections.fetch_add(1, Ordering::Relaxed) + 1;
+                    let peer = Peer::new(connection_id, Some(address));
+                    tokio::spawn(self.clone().receive(stream, peer));
+                },
+                Err(error) => warn!(self.log, "Failed connection due to: {}", error),
+            }
+        }
+    }
+
+    /// Receive the request from a newly accepted connection, and pass it to the handler thread.
+    async fn receive(self, mut stream: TcpStream, peer: Peer) {
+        let log = peer_log(&self.log, &peer);
+        debug!(log, "Client connected");
+        if let Err(error) = stream.set_nodelay(self.nodelay) {
+            warn!(log, "Connection error: {}", error);
+            return;
+        }
+        let request = super::receive(&mut stream, self.buffer_size).await;
+        match stream.into_std() {
+            Ok(stream) => {
+                // This only fails if the handler thread has panicked, so the request is dropped.
+                let _ = self.sender.send(Received { log, peer, stream, request });
+            },
+            Err(error) => warn!(log, "Connection error: {}", error),
+        }
+    }
+}
+
+/// Handle requests as they're received, running background work in between, until the server's
+/// tasks have all stopped.
+fn handle<E: Engine>(mut server: server::Server<E>, receiver: mpsc::Receiver<Received>) {
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Received { log, peer, stream, request }) => {
+                let peer_addr = peer.to_string();
+                if let Err(error) = server.handle_received(log, peer, stream, request) {
+                    warn!(server.log(), "Connection error: {}", error; "peer_addr" => peer_addr);
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        server.run_background();
+    }
+}
@@ -1,42 +1,58 @@
 #[macro_use]
 extern crate clap;
 
-use clap::{AppSettings, Arg, SubCommand};
+use std::io;
 use std::process;
+use std::time::Duration;
 
-use kvs::{DEFAULT_ADDRESS, Client, Result};
+use kvs::{cli, DEFAULT_ADDRESS, Client, Config, Durability, Error, Query, Result, Schema, Transfer};
+
+const DEFAULT_HOT_KEYS_LIMIT: u64 = 10;
+
+// Exit codes, so that scripts can tell failures apart without parsing error messages.
+
+/// Any failure without a more specific exit code.
+const EXIT_FAILURE: i32 = 1;
+
+/// The arguments were invalid (including an unparsable query).
+const EXIT_USAGE: i32 = 2;
+
+/// The key to act on doesn't exist.
+const EXIT_NOT_FOUND: i32 = 3;
+
+/// The server refused the connection (e.g. because it isn't running).
+const EXIT_CONNECTION_REFUSED: i32 = 4;
+
+/// The request timed out, either waiting for the server or on the server itself.
+const EXIT_TIMEOUT: i32 = 5;
+
+/// Like `value_t_or_exit!`, but exiting with [`EXIT_USAGE`].
+macro_rules! value_or_exit {
+    ($m:ident, $v:expr, $t:ty) => {
+        match value_t!($m, $v, $t) {
+            Ok(value) => value,
+            Err(err) => usage_error(err),
+        }
+    };
+}
 
 fn run() -> Result<()> {
-    let matches = app_from_crate!()
-        .setting(AppSettings::SubcommandRequiredElseHelp)
-        .setting(AppSettings::VersionlessSubcommands)
-        .subcommand(
-            SubCommand::with_name("get")
-                .about("Get the value of a given key")
-                .arg(Arg::with_name("key").required(true))
-                .arg(Arg::with_name("address").long("addr").takes_value(true)),
-        )
-        .subcommand(
-            SubCommand::with_name("set")
-                .about("Set the value of a given key to a given value")
-                .arg(Arg::with_name("key").required(true))
-                .arg(Arg::with_name("value").required(true))
-                .arg(Arg::with_name("address").long("addr").takes_value(true)),
-        )
-        .subcommand(
-            SubCommand::with_name("rm")
-                .about("Remove a given key")
-                .arg(Arg::with_name("key").required(true))
-                .arg(Arg::with_name("address").long("addr").takes_value(true)),
-        )
-        .get_matches();
+    let matches = cli::client().get_matches_safe().unwrap_or_else(|err| usage_error(err));
+    if let ("completions", Some(args)) = matches.subcommand() {
+        cli::print_completions(cli::client(), "kvs-client", args);
+        return Ok(());
+    }
+
+    // Flags take precedence over the environment.
+    let config = Config::default().with_env()?;
+    let default_address = config.addr.as_deref().unwrap_or(DEFAULT_ADDRESS);
 
     match matches.subcommand() {
         ("get", Some(args)) => {
             let key = args
                 .value_of("key")
                 .expect("Missing value for required arg: key");
-            let address = args.value_of("address").unwrap_or(DEFAULT_ADDRESS);
+            let address = args.value_of("address").unwrap_or(default_address);
 
             let mut client = Client::connect(address)?;
             match client.get(key.to_owned())? {
@@ -51,19 +67,256 @@ fn run() -> Result<()> {
             let value = args
                 .value_of("value")
                 .expect("Missing value for required arg: value");
-            let address = args.value_of("address").unwrap_or(DEFAULT_ADDRESS);
+            let address = args.value_of("address").unwrap_or(default_address);
 
-            let mut client = Client::connect(address)?;
-            client.set(key.to_owned(), value.to_owned())?;
+            let durability = if args.is_present("fsync") {
+                Durability::Fsynced
+            } else {
+                Durability::Applied
+            };
+            let ttl = if args.is_present("ttl") {
+                Some(parse_duration(args, "ttl"))
+            } else {
+                None
+            };
+
+            let mut client = Client::builder().durability(durability).connect(address)?;
+            match ttl {
+                Some(ttl) => client.set_with_ttl(key.to_owned(), value.to_owned(), ttl)?,
+                None => client.set(key.to_owned(), value.to_owned())?,
+            }
         }
         ("rm", Some(args)) => {
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            match args.value_of("prefix") {
+                Some(prefix) if args.is_present("yes") => {
+                    let removed = client.remove_prefix(prefix.to_owned())?;
+                    println!("Removed {} keys", removed);
+                }
+                Some(prefix) => {
+                    let count = client.count_prefix(prefix.to_owned())?;
+                    println!("Would remove {} keys", count);
+                    if !args.is_present("dry-run") {
+                        eprintln!("Pass --yes to remove them");
+                        process::exit(EXIT_FAILURE);
+                    }
+                }
+                None => {
+                    let key = args
+                        .value_of("key")
+                        .expect("Missing value for required arg: key");
+                    if args.is_present("force") {
+                        client.force_remove(key.to_owned())?;
+                    } else {
+                        client.remove(key.to_owned())?;
+                    }
+                }
+            }
+        }
+        ("ttl", Some(args)) => {
+            let key = args
+                .value_of("key")
+                .expect("Missing value for required arg: key");
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            match client.ttl(key.to_owned())? {
+                Some(ttl) => println!("{:.3}", ttl.as_secs_f64()),
+                None => println!("No expiry"),
+            }
+        }
+        ("expire", Some(args)) => {
             let key = args
                 .value_of("key")
                 .expect("Missing value for required arg: key");
-            let address = args.value_of("address").unwrap_or(DEFAULT_ADDRESS);
+            let ttl = parse_duration(args, "seconds");
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            client.expire(key.to_owned(), ttl)?;
+        }
+        ("persist", Some(args)) => {
+            let key = args
+                .value_of("key")
+                .expect("Missing value for required arg: key");
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            client.persist(key.to_owned())?;
+        }
+        ("put-cas", Some(args)) => {
+            let value = args
+                .value_of("value")
+                .expect("Missing value for required arg: value");
+            let address = args.value_of("address").unwrap_or(default_address);
 
             let mut client = Client::connect(address)?;
-            client.remove(key.to_owned())?;
+            println!("{}", client.put_cas(value.to_owned())?);
+        }
+        ("query", Some(args)) => {
+            let query: Query = args
+                .value_of("query")
+                .expect("Missing value for required arg: query")
+                .parse()?;
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            for (key, value) in query.run(&mut client)? {
+                if query.is_delete() {
+                    println!("{}", key);
+                } else {
+                    println!("{}\t{}", key, value);
+                }
+            }
+        }
+        ("scan", Some(args)) => {
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            let entries = if args.is_present("from") || args.is_present("to") {
+                let start = args.value_of("from").unwrap_or("").to_owned();
+                client.scan_range(start, args.value_of("to").map(str::to_owned))?
+            } else {
+                client.scan(args.value_of("prefix").unwrap_or("").to_owned())?
+            };
+            for (key, value) in entries {
+                println!("{}\t{}", key, value);
+            }
+        }
+        ("hot-keys", Some(args)) => {
+            let limit = match args.value_of("limit") {
+                Some(_) => value_or_exit!(args, "limit", u64),
+                None => DEFAULT_HOT_KEYS_LIMIT,
+            };
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            for (key, count) in client.hot_keys(limit)? {
+                println!("{}\t{}", count, key);
+            }
+        }
+        ("sample", Some(args)) => {
+            let count = value_or_exit!(args, "count", u64);
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            for key in client.sample_keys(count)? {
+                println!("{}", key);
+            }
+        }
+        ("tenants", Some(args)) => {
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            println!("TENANT\tKEYS\tBYTES\tREQUESTS\tREJECTED\tRATE LIMIT");
+            for usage in client.tenants()? {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    usage.tenant,
+                    with_limit(usage.keys, usage.quota.max_keys),
+                    with_limit(usage.bytes, usage.quota.max_bytes),
+                    usage.requests,
+                    usage.rejected,
+                    usage
+                        .quota
+                        .max_requests_per_sec
+                        .map(|rate| format!("{}/s", rate))
+                        .unwrap_or_else(|| "-".to_owned()),
+                );
+            }
+        }
+        ("info", Some(args)) => {
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            let info = client.info()?;
+            println!("version: {}", info.version);
+            println!("protocol: {}", info.protocol_version);
+            println!("engine: {}", info.engine);
+            println!("features: {}", info.features.join(","));
+            println!("store id: {}", info.store_id);
+        }
+        ("maintenance", Some(args)) => {
+            let duration = parse_duration(args, "duration");
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            client.maintenance(duration)?;
+        }
+        ("watch", Some(args)) => {
+            let prefix = args
+                .value_of("prefix")
+                .expect("Missing value for required arg: prefix");
+            let json = args.value_of("output") == Some("json");
+            let address = args.value_of("address").unwrap_or(default_address);
+
+            let mut client = Client::connect(address)?;
+            for change in client.watch(prefix.to_owned())? {
+                let change = change?;
+                // Keys and values that aren't UTF-8 are printed with their invalid bytes replaced.
+                let key = String::from_utf8_lossy(&change.key);
+                let value = change.value.as_deref().map(String::from_utf8_lossy);
+                if json {
+                    let change = serde_json::json!({
+                        "seq": change.seq,
+                        "timestamp_ms": change.timestamp_ms,
+                        "key": key,
+                        "value": value,
+                    });
+                    println!("{}", change);
+                    continue;
+                }
+                let timestamp =
+                    format!("{}.{:03}", change.timestamp_ms / 1000, change.timestamp_ms % 1000);
+                match value {
+                    Some(value) => {
+                        println!("{}\t{}\tset\t{}\t{}", timestamp, change.seq, key, value)
+                    },
+                    None => println!("{}\t{}\trm\t{}", timestamp, change.seq, key),
+                }
+            }
+        }
+        ("copy", Some(args)) => {
+            let from = args.value_of("from").expect("Missing value for required arg: from");
+            let to = args.value_of("to").expect("Missing value for required arg: to");
+            let prefix = args.value_of("prefix").expect("Prefix has a default");
+
+            let mut transfer = Transfer::new(prefix);
+            if args.is_present("batch-size") {
+                transfer = transfer.batch_size(value_or_exit!(args, "batch-size", usize));
+            }
+            if args.is_present("concurrency") {
+                transfer = transfer.concurrency(value_or_exit!(args, "concurrency", usize));
+            }
+            if let Some(key) = args.value_of("resume-after") {
+                transfer = transfer.resume_after(key.to_owned());
+            }
+
+            let mut source = Client::connect(from)?;
+            let mut last_key = args.value_of("resume-after").map(str::to_owned);
+            let result = transfer.run(&mut source, || Client::connect(to), |progress| {
+                eprintln!(
+                    "Copied {} keys through {}",
+                    progress.copied,
+                    progress.last_key.as_deref().unwrap_or("")
+                );
+                last_key = progress.last_key.clone();
+            });
+            match result {
+                Ok(progress) => println!("Copied {} keys", progress.copied),
+                Err(error) => {
+                    if let Some(key) = last_key {
+                        eprintln!("Resume with --resume-after {:?}", key);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        ("schema", Some(_)) => {
+            let schema = Schema::current();
+            println!("{}", serde_json::to_string_pretty(&schema).expect("Schemas serialize"));
         }
         _ => unreachable!(),
     }
@@ -71,6 +324,26 @@ fn run() -> Result<()> {
     Ok(())
 }
 
+/// Parse the argument `name` as a number of seconds, exiting with [`EXIT_USAGE`] unless it's
+/// finite, non-negative and small enough for a `Duration`.
+fn parse_duration(args: &clap::ArgMatches, name: &str) -> Duration {
+    let seconds = value_or_exit!(args, name, f64);
+    Duration::try_from_secs_f64(seconds).unwrap_or_else(|_| {
+        usage_error(clap::Error::value_validation_auto(format!(
+            "The argument '{}' isn't a valid number of seconds: {}",
+            name, seconds
+        )))
+    })
+}
+
+/// Format a usage figure, along with its limit if it has one.
+fn with_limit(value: u64, limit: Option<u64>) -> String {
+    match limit {
+        Some(limit) => format!("{}/{}", value, limit),
+        None => value.to_string(),
+    }
+}
+
 fn main() {
     if let Err(err) = run() {
         use std::error::Error;
@@ -82,6 +355,31 @@ fn main() {
             eprintln!("Caused by: {}", err);
             source = err;
         }
-        process::exit(1);
+        process::exit(exit_code(&err));
+    }
+}
+
+/// The exit code for a failure with `err`.
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::KeyNotFound => EXIT_NOT_FOUND,
+        Error::Timeout => EXIT_TIMEOUT,
+        Error::InvalidQuery(_) => EXIT_USAGE,
+        Error::Io(err) => match err.kind() {
+            io::ErrorKind::ConnectionRefused => EXIT_CONNECTION_REFUSED,
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => EXIT_TIMEOUT,
+            _ => EXIT_FAILURE,
+        },
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// Exit after reporting `err`, from parsing the arguments, with [`EXIT_USAGE`] (unless it's a
+/// request for help or the version).
+fn usage_error(err: clap::Error) -> ! {
+    if !err.use_stderr() {
+        err.exit();
     }
+    eprintln!("{}", err.message);
+    process::exit(EXIT_USAGE)
 }
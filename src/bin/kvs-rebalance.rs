@@ -0,0 +1,76 @@
+#[macro_use]
+extern crate clap;
+
+use clap::Arg;
+use std::process;
+
+use kvs::{Client, Rebalance, Result, Ring};
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let matches = app_from_crate!()
+        .about("Move the keys of a sharded deployment whose servers change between two rings")
+        .long_about(
+            "Move the keys of a sharded deployment whose servers change between two rings.\n\n\
+             Each ring is a TOML file listing the servers' addresses as `nodes`, and optionally \
+             the number of `vnodes` per server (64 by default). Only the ranges of the ring that \
+             change servers are moved: each server losing a range is scanned, and the keys in \
+             that range are written to their new servers in batches, then removed.\n\n\
+             Moved keys are removed from their old servers, so an interrupted rebalance carries \
+             on where it left off when it's run again.",
+        )
+        .arg(Arg::with_name("old").required(true).help("The ring the keys are stored by"))
+        .arg(Arg::with_name("new").required(true).help("The ring to store the keys by"))
+        .arg(
+            Arg::with_name("batch-size")
+                .long("batch-size")
+                .takes_value(true)
+                .help("Report progress after moving this many keys"),
+        )
+        .arg(
+            Arg::with_name("rate")
+                .long("rate")
+                .takes_value(true)
+                .help("Move at most this many keys per second"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("List the ranges of the ring that would move, without moving any keys"),
+        )
+        .get_matches();
+
+    let old = Ring::load(matches.value_of("old").expect("old is required"))?;
+    let new = Ring::load(matches.value_of("new").expect("new is required"))?;
+    let mut rebalance = Rebalance::new(old, new);
+    if matches.is_present("batch-size") {
+        rebalance = rebalance.batch_size(value_t_or_exit!(matches, "batch-size", usize));
+    }
+    if matches.is_present("rate") {
+        rebalance = rebalance.max_keys_per_sec(value_t_or_exit!(matches, "rate", f64));
+    }
+
+    if matches.is_present("dry-run") {
+        for range in rebalance.moves() {
+            println!("{:016x}-{:016x}\t{}\t{}", range.start, range.end, range.from, range.to);
+        }
+        return Ok(());
+    }
+
+    let progress = rebalance.run(|node: &str| Client::connect(node), |progress| {
+        eprintln!(
+            "Moved {} keys through {} from {}",
+            progress.moved,
+            progress.last_key.as_deref().unwrap_or(""),
+            progress.source.as_deref().unwrap_or("")
+        );
+    })?;
+    println!("Moved {} keys", progress.moved);
+    Ok(())
+}
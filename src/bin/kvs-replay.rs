@@ -0,0 +1,135 @@
+#[macro_use]
+extern crate clap;
+
+use clap::Arg;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use kvs::{DEFAULT_ADDRESS, Client, Error, Histogram, Result, Sample};
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let matches = app_from_crate!()
+        .about("Replay requests captured by `kvs-server --sample-file` against a server")
+        .arg(
+            Arg::with_name("files")
+                .required(true)
+                .multiple(true)
+                .help("Capture files to replay"),
+        )
+        .arg(Arg::with_name("address").long("addr").takes_value(true))
+        .arg(
+            Arg::with_name("speed")
+                .long("speed")
+                .takes_value(true)
+                .help("Replay speed relative to the original capture (0 for as fast as possible)"),
+        )
+        .get_matches();
+
+    let address = matches.value_of("address").unwrap_or(DEFAULT_ADDRESS);
+    let speed = match matches.value_of("speed") {
+        Some(_) => value_t_or_exit!(matches, "speed", f64),
+        None => 1.0,
+    };
+
+    let mut samples = Vec::new();
+    for path in matches.values_of("files").expect("files is required") {
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let sample: Sample = serde_json::from_str(&line).map_err(|err| {
+                Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: {}", path, err),
+                ))
+            })?;
+            samples.push(sample);
+        }
+    }
+    samples.sort_by_key(|sample| sample.timestamp_micros);
+
+    let first_timestamp = samples.first().map(|s| s.timestamp_micros).unwrap_or(0);
+    let start = Instant::now();
+    let mut latency = Histogram::default();
+    let mut replayed = 0;
+    let mut skipped = 0;
+    let mut errors = 0;
+
+    for sample in &samples {
+        if speed > 0.0 {
+            let offset = (sample.timestamp_micros - first_timestamp) as f64 / speed;
+            let due = Duration::from_micros(offset as u64);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+
+        let request_start = Instant::now();
+        match replay(address, sample) {
+            Ok(true) => replayed += 1,
+            Ok(false) => {
+                skipped += 1;
+                continue;
+            },
+            Err(err) => {
+                eprintln!("Error replaying {:?}: {}", sample, err);
+                errors += 1;
+            },
+        }
+        latency.record(request_start.elapsed());
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "replayed {} requests in {:.2}s ({:.0} req/s), {} skipped, {} errors",
+        replayed,
+        elapsed.as_secs_f64(),
+        replayed as f64 / elapsed.as_secs_f64().max(0.001),
+        skipped,
+        errors,
+    );
+    println!(
+        "latency: mean={:?} p50<={:?} p99<={:?}",
+        latency.mean(),
+        latency.percentile(50.0),
+        latency.percentile(99.0),
+    );
+
+    Ok(())
+}
+
+/// Replay a single sample, returning `false` if it can't be replayed (e.g. admin requests).
+///
+/// Captures don't contain keys or values, so keys are derived from the captured key hash (which
+/// preserves the access pattern) and values are filled to the captured size.
+fn replay(address: &str, sample: &Sample) -> Result<bool> {
+    let key = match sample.key_hash {
+        Some(hash) => format!("replay-{:016x}", hash),
+        None => return Ok(false),
+    };
+
+    let mut client = Client::connect(address)?;
+    match sample.op.as_str() {
+        "get" => {
+            client.get(key)?;
+        },
+        "set" => client.set(key, "x".repeat(sample.value_size as usize))?,
+        "rm" => match client.remove(key) {
+            Ok(()) | Err(Error::KeyNotFound) => {},
+            Err(err) => return Err(err),
+        },
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
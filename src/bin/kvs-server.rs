@@ -3,18 +3,32 @@ extern crate clap;
 #[macro_use]
 extern crate slog;
 
-use clap::Arg;
+use clap::ArgMatches;
 use slog::Drain;
 use std::env;
 use std::fs;
+use std::io;
+use std::net::ToSocketAddrs;
 use std::io::ErrorKind::NotFound;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
 
-use kvs::{DEFAULT_ADDRESS, Error, KvsEngine, KvStore, Result, Server, SledKvStore};
+use kvs::cli::{self, parse_tenant_quota};
+use kvs::{
+    detect_engines, BackgroundJobs, Buckets, Chaos, Config, DEFAULT_ADDRESS, Error,
+    JsonDrain, KeyCharset, KeyRules, KvsEngine, KvStore, KvStoreBuilder, MemoryKvStore, Mirror,
+    MirrorConfig, Result, RotatingFile, RotationConfig, Sampler, SamplerConfig, Scrubber,
+    ScrubberConfig, Server, ServerConfig, SledKvStore, Sweeper, SweeperConfig, TenantQuota, Tenants,
+    Warmup, WarmupConfig, WriteOnce, WriteStall,
+};
 
-const VALID_ENGINES: &[&'static str] = &["kvs", "sled"];
-const DEFAULT_ENGINE: &'static str = "kvs";
+const DEFAULT_ENGINE: &str = "kvs";
+const DEFAULT_COMPACTION_MIN_BYTES: u64 = 64 * 1024;
+const WRITE_DELAY: Duration = Duration::from_millis(1);
+
+/// A drain for the server's logs, before it's made asynchronous.
+type LogDrain = Box<dyn Drain<Ok = (), Err = slog::Never> + Send>;
 
 fn main() {
     if let Err(err) = run() {
@@ -24,57 +38,418 @@ fn main() {
 }
 
 fn run() -> Result<()> {
-    let decorator = slog_term::TermDecorator::new().build();
-    let drain = slog_term::FullFormat::new(decorator).build().fuse();
+    let matches = cli::server().get_matches();
+    if let ("completions", Some(args)) = matches.subcommand() {
+        cli::print_completions(cli::server(), "kvs-server", args);
+        return Ok(());
+    }
+
+    let mut config = match matches.value_of("config") {
+        Some(config) => Config::load(config)?,
+        None => Config::default(),
+    }
+    .with_env()?;
+    if let Some(level) = matches.value_of("log-level") {
+        config.log_level = Some(level.to_owned());
+    }
+
+    let drain = log_drain(&matches)?;
+    let drain: LogDrain = match config.level()? {
+        Some(level) => Box::new(slog::LevelFilter::new(drain, level).ignore_res()),
+        None => drain,
+    };
     let drain = slog_async::Async::new(drain).build().fuse();
     let root = slog::Logger::root(drain, o!());
 
-    let matches = app_from_crate!()
-        .arg(Arg::with_name("engine").long("engine").takes_value(true).possible_values(VALID_ENGINES))
-        .arg(Arg::with_name("address").long("addr").takes_value(true))
-        .get_matches();
+    let engine = matches.value_of("engine").or(config.engine.as_deref()).unwrap_or(DEFAULT_ENGINE);
+    let force_engine = matches.is_present("force-engine");
+    let follow = matches.is_present("follow");
+    let path = matches.value_of("data-dir").map(PathBuf::from).or_else(|| config.data_dir.clone());
+    let path = match path {
+        Some(path) => path,
+        None => env::current_dir()?,
+    };
+    let address =
+        matches.value_of("address").or(config.addr.as_deref()).unwrap_or(DEFAULT_ADDRESS);
+    let dual_stack = matches.is_present("dual-stack") || config.dual_stack;
+    let mut socket_config = ServerConfig {
+        reuse_port: matches.is_present("reuse-port"),
+        nodelay: !matches.is_present("nagle"),
+        ..ServerConfig::default()
+    };
+    if matches.is_present("send-buffer") {
+        socket_config.send_buffer_size = Some(value_t_or_exit!(matches, "send-buffer", usize));
+    }
+    if matches.is_present("recv-buffer") {
+        socket_config.recv_buffer_size = Some(value_t_or_exit!(matches, "recv-buffer", usize));
+    }
+    if matches.is_present("buffer-size") {
+        socket_config.buffer_size = value_t_or_exit!(matches, "buffer-size", usize);
+    }
+
+    let mut builder = KvStore::builder();
+    if matches.is_present("max-index-memory") {
+        builder = builder.max_index_memory(value_t_or_exit!(matches, "max-index-memory", u64));
+    }
+    if matches.is_present("disk-index-cache") {
+        builder = builder.disk_index(value_t_or_exit!(matches, "disk-index-cache", u64));
+    }
+    if matches.is_present("rebuild-index") {
+        builder = builder.rebuild_index(true);
+    }
+    if matches.is_present("strict-recovery") {
+        builder = builder.strict_recovery(true);
+    }
+    if let Some(policy) = matches.value_of("sync") {
+        config.sync_policy = Some(policy.to_owned());
+    }
+    if let Some(policy) = config.sync_policy()? {
+        builder = builder.sync_policy(policy);
+    }
+    if matches.is_present("remove-expired-on-read") {
+        builder = builder.remove_expired_on_read(true);
+    }
+    if matches.is_present("inline-values") {
+        builder = builder.inline_values(value_t_or_exit!(matches, "inline-values", usize));
+    }
+    if matches.is_present("compression-dictionary") {
+        let max_len = value_t_or_exit!(matches, "compression-dictionary", usize);
+        builder = builder.compression_dictionary(max_len);
+    }
+    if matches.is_present("max-open-files") {
+        builder = builder.max_open_files(value_t_or_exit!(matches, "max-open-files", usize));
+    }
+    if matches.is_present("max-segment-size") {
+        let max_size = value_t_or_exit!(matches, "max-segment-size", u64);
+        builder = builder.max_segment_size(max_size);
+    }
+    if matches.is_present("compaction-threshold") {
+        let threshold = value_t_or_exit!(matches, "compaction-threshold", u64);
+        builder = builder.compaction_threshold(threshold);
+    }
+    if matches.is_present("compaction-ratio") {
+        let ratio = value_t_or_exit!(matches, "compaction-ratio", f64);
+        let min_bytes = match matches.value_of("compaction-min-bytes") {
+            Some(_) => value_t_or_exit!(matches, "compaction-min-bytes", u64),
+            None => DEFAULT_COMPACTION_MIN_BYTES,
+        };
+        builder = builder.compaction_ratio(ratio, min_bytes);
+    }
+    if matches.is_present("write-stop-bytes") {
+        let stop_bytes = value_t_or_exit!(matches, "write-stop-bytes", u64);
+        let slowdown_bytes = match matches.value_of("write-slowdown-bytes") {
+            Some(_) => value_t_or_exit!(matches, "write-slowdown-bytes", u64),
+            None => stop_bytes / 2,
+        };
+        let stall = WriteStall { slowdown_bytes, delay: WRITE_DELAY, stop_bytes };
+        builder = builder.write_stall(stall);
+    }
+    if matches.is_present("reserved-space") {
+        builder = builder.reserved_space(value_t_or_exit!(matches, "reserved-space", u64));
+    }
+    if matches.is_present("max-background-jobs") {
+        let max_jobs = value_t_or_exit!(matches, "max-background-jobs", usize);
+        builder = builder.background_jobs(BackgroundJobs::new(max_jobs));
+    }
 
-    let engine = matches.value_of("engine").unwrap_or(DEFAULT_ENGINE);
-    let path = env::current_dir()?;
-    let address = matches.value_of("address").unwrap_or(DEFAULT_ADDRESS);
+    let sampler = match matches.value_of("sample-file") {
+        Some(sample_file) => {
+            let mut config = SamplerConfig::new(sample_file);
+            if matches.is_present("sample-rate") {
+                config.rate = value_t_or_exit!(matches, "sample-rate", f64);
+            }
+            if matches.is_present("slow-ms") {
+                config.slow_threshold =
+                    Some(Duration::from_millis(value_t_or_exit!(matches, "slow-ms", u64)));
+            }
+            if matches.is_present("sample-max-size") {
+                config.max_file_size = value_t_or_exit!(matches, "sample-max-size", u64);
+            }
+            if matches.is_present("sample-max-files") {
+                config.max_files = value_t_or_exit!(matches, "sample-max-files", usize);
+            }
+            Some(Sampler::open(config)?)
+        },
+        None => None,
+    };
 
-    check_engine(&path, engine)?;
+    let key_rules = KeyRules {
+        max_length: matches
+            .value_of("max-key-length")
+            .map(|_| value_t_or_exit!(matches, "max-key-length", usize)),
+        charset: match matches.value_of("key-charset") {
+            Some("ascii") => KeyCharset::Ascii,
+            Some("printable") => KeyCharset::Printable,
+            Some("utf8") => KeyCharset::Utf8,
+            _ => KeyCharset::Any,
+        },
+        reserved_prefixes: matches
+            .values_of("reserved-key-prefix")
+            .map(|prefixes| prefixes.map(str::to_owned).collect())
+            .unwrap_or_default(),
+    };
+
+    let tenants = matches.values_of("tenant-quota").map(|quotas| {
+        let mut quotas: Vec<_> = quotas
+            .map(|quota| parse_tenant_quota(quota).expect("Quotas are validated"))
+            .collect();
+        let default_quota = match quotas.iter().position(|(tenant, _)| tenant == "*") {
+            Some(index) => quotas.remove(index).1,
+            None => TenantQuota::default(),
+        };
+        quotas
+            .into_iter()
+            .fold(Tenants::new(default_quota), |tenants, (tenant, quota)| {
+                tenants.with_quota(tenant, quota)
+            })
+    });
+
+    let mut sweeper_config = SweeperConfig::default();
+    if matches.is_present("sweep-interval-ms") {
+        sweeper_config.interval =
+            Duration::from_millis(value_t_or_exit!(matches, "sweep-interval-ms", u64));
+    }
+    if matches.is_present("sweep-max-keys") {
+        sweeper_config.max_keys_per_sweep = value_t_or_exit!(matches, "sweep-max-keys", usize);
+    }
+    if matches.is_present("sweep-rate") {
+        sweeper_config.max_keys_per_sec = value_t_or_exit!(matches, "sweep-rate", f64);
+    }
+    let sweeper = Sweeper::new(sweeper_config);
+
+    let scrubber = if matches.is_present("scrub") {
+        let mut config = ScrubberConfig::default();
+        if matches.is_present("scrub-rate") {
+            config.max_entries_per_sec = value_t_or_exit!(matches, "scrub-rate", f64);
+        }
+        Some(Scrubber::new(config))
+    } else {
+        None
+    };
+
+    let warmup = if matches.is_present("warmup") {
+        let mut config = WarmupConfig::default();
+        if matches.is_present("warmup-keys") {
+            config.max_keys = value_t_or_exit!(matches, "warmup-keys", usize);
+        }
+        if matches.is_present("warmup-rate") {
+            config.max_keys_per_sec = value_t_or_exit!(matches, "warmup-rate", f64);
+        }
+        Some(Warmup::new(config))
+    } else {
+        None
+    };
+
+    let chaos = matches.value_of("chaos").map(|chaos| {
+        Chaos::new(chaos.parse().expect("Chaos settings are validated"))
+    });
+
+    let mirror = match matches.value_of("mirror-addr") {
+        Some(mirror_addr) => {
+            let address = match mirror_addr.to_socket_addrs()?.next() {
+                Some(address) => address,
+                None => return Err(Error::Config(format!("invalid address {:?}", mirror_addr))),
+            };
+            let mut config = MirrorConfig::new(address);
+            if matches.is_present("mirror-rate") {
+                config.rate = value_t_or_exit!(matches, "mirror-rate", f64);
+            }
+            Some(Mirror::start(root.new(o!("mirror" => mirror_addr.to_owned())), config)?)
+        },
+        None => None,
+    };
 
     info!(root, "Starting engine";
         "version" => crate_version!(),
         "engine" => engine,
         "path" => path.to_str());
 
+    if force_engine {
+        warn!(root, "Ignoring any other engine's data in the data directory"; "engine" => engine);
+    }
+    let mut buckets = Buckets::new(open_engine(engine, &path, &builder, force_engine, follow)?);
+    for (bucket, bucket_config) in &config.buckets {
+        let bucket_path = path.join("buckets").join(bucket);
+        info!(root, "Starting bucket engine";
+            "bucket" => bucket,
+            "engine" => &bucket_config.engine,
+            "immutable" => bucket_config.immutable,
+            "path" => bucket_path.to_str());
+        let engine_name = &bucket_config.engine;
+        let mut engine = open_engine(engine_name, &bucket_path, &builder, force_engine, follow)?;
+        if bucket_config.immutable {
+            engine = Box::new(WriteOnce::new(engine));
+        }
+        buckets = buckets.with_bucket(bucket.as_str(), engine);
+    }
+    let torn_tail_bytes = buckets.stats()?.torn_tail_bytes;
+    if torn_tail_bytes > 0 {
+        warn!(root, "Discarded a command torn by a crash from the end of the log";
+            "bytes" => torn_tail_bytes);
+    }
+
+    if chaos.is_some() {
+        warn!(root, "Chaos mode is enabled: requests will be delayed, dropped, and failed");
+    }
+    let server = make_server(root, address, dual_stack, socket_config, buckets, sampler, tenants)?
+        .with_key_rules(key_rules)
+        .with_sweeper(sweeper)?;
+    let server = if matches.is_present("request-timeout-ms") {
+        let timeout = value_t_or_exit!(matches, "request-timeout-ms", u64);
+        server.with_request_timeout(Duration::from_millis(timeout))
+    } else {
+        server
+    };
+    let server = if matches.is_present("heartbeat-interval-ms") {
+        let interval = value_t_or_exit!(matches, "heartbeat-interval-ms", u64);
+        server.with_heartbeat_interval(Duration::from_millis(interval))
+    } else {
+        server
+    };
+    let server = match scrubber {
+        Some(scrubber) => server.with_scrubber(scrubber)?,
+        None => server,
+    };
+    let server = match warmup {
+        Some(warmup) => server.with_warmup(warmup)?,
+        None => server,
+    };
+    let server = match mirror {
+        Some(mirror) => server.with_mirror(mirror),
+        None => server,
+    };
+    let mut server = match chaos {
+        Some(chaos) => server.with_chaos(chaos),
+        None => server,
+    };
+    if matches.is_present("async") {
+        let server = kvs::r#async::Server::new(server)?;
+        tokio::runtime::Runtime::new()?.block_on(server.run());
+        return Ok(());
+    }
+    server.run()
+}
+
+/// Build the drain for the server's logs: the terminal, or a rotating `--log-file`, in the
+/// `--log-format` format.
+fn log_drain(matches: &ArgMatches) -> Result<LogDrain> {
+    let json = matches.value_of("log-format") == Some("json");
+    let path = match matches.value_of("log-file") {
+        Some(path) => path,
+        None if json => return Ok(Box::new(JsonDrain::new(io::stderr()).fuse())),
+        None => {
+            let decorator = slog_term::TermDecorator::new().build();
+            return Ok(Box::new(slog_term::FullFormat::new(decorator).build().fuse()));
+        },
+    };
+
+    let mut rotation = RotationConfig::default();
+    if matches.is_present("log-max-size") {
+        let max_size = value_t_or_exit!(matches, "log-max-size", u64);
+        rotation.max_file_size = if max_size == 0 { None } else { Some(max_size) };
+    }
+    if matches.is_present("log-max-age") {
+        rotation.max_age = Some(Duration::from_secs(value_t_or_exit!(matches, "log-max-age", u64)));
+    }
+    if matches.is_present("log-max-files") {
+        rotation.max_files = value_t_or_exit!(matches, "log-max-files", usize);
+    }
+    let file = RotatingFile::open(path, rotation)?;
+    let drain: LogDrain = if json {
+        Box::new(JsonDrain::new(file).fuse())
+    } else {
+        let decorator = slog_term::PlainSyncDecorator::new(file);
+        Box::new(slog_term::FullFormat::new(decorator).build().fuse())
+    };
+    Ok(drain)
+}
+
+/// Open the engine named `engine` in `path`, creating the directory if it doesn't exist.
+///
+/// Unless `force` is set, this fails if `path` holds data for a different engine. With `follow`,
+/// a kvs engine is opened as a read-only follower of another process's store, and nothing is
+/// written to `path`.
+fn open_engine(
+    engine: &str,
+    path: &Path,
+    builder: &KvStoreBuilder,
+    force: bool,
+    follow: bool,
+) -> Result<Box<dyn KvsEngine + Send>> {
     match engine {
-        "kvs" => {
-            let mut server = make_server(root, address, KvStore::open(path)?)?;
-            server.run()
+        "kvs" if follow => return Ok(Box::new(builder.clone().open_follower(path)?)),
+        _ if follow => {
+            return Err(Error::Config(format!("the {} engine can't be followed", engine)));
         },
-        "sled" => {
-            let mut server = make_server(root, address, SledKvStore::start_default(path)?)?;
-            server.run()
+        "kvs" | "sled" => {
+            fs::create_dir_all(path)?;
+            check_engine(path, engine, force)?;
         },
-        _ => panic!("Invalid engine: {}", engine),
+        "memory" => {},
+        _ => return Err(Error::Config(format!("invalid engine {:?}", engine))),
     }
+    Ok(match engine {
+        "kvs" => Box::new(builder.clone().open(path)?),
+        "sled" => Box::new(SledKvStore::start_default(path)?),
+        _ => Box::new(MemoryKvStore::new()),
+    })
 }
 
-fn check_engine(path: &PathBuf, engine: &str) -> Result<()> {
-    let path = path.join("engine");
-    match fs::read_to_string(&path) {
-        Ok(ref contents) if contents == engine  => Ok(()),
-        Ok(_) => Err(Error::WrongEngine),
-        Err(ref err) if err.kind() == NotFound => {
-            fs::write(&path, engine)?;
-            Ok(())
-        },
-        Err(err) => Err(err.into()),
+/// Check that the data in `path` is for `engine`, according to both the `engine` marker file and
+/// the data files themselves, and record `engine` in the marker if it isn't there already.
+///
+/// With `force`, the check is skipped and the marker is overwritten.
+fn check_engine(path: &Path, engine: &str, force: bool) -> Result<()> {
+    let marker_path = path.join("engine");
+    let marker = match fs::read_to_string(&marker_path) {
+        Ok(contents) => Some(contents),
+        Err(ref err) if err.kind() == NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    if !force {
+        let found = marker
+            .iter()
+            .map(String::as_str)
+            .chain(detect_engines(path)?)
+            .find(|&found| found != engine);
+        if let Some(found) = found {
+            let requested = engine.to_owned();
+            return Err(Error::WrongEngine { requested, found: found.to_owned() });
+        }
+    }
+    if marker.as_deref() != Some(engine) {
+        fs::write(&marker_path, engine)?;
     }
+    Ok(())
 }
 
-fn make_server<E: KvsEngine>(root: slog::Logger, address: &str, engine: E) -> Result<Server<E>> {
-    Server::start(
-        root.new(o!("address" => address.to_string())),
-        engine,
-        address
-    )
+fn make_server<E: KvsEngine>(
+    root: slog::Logger,
+    address: &str,
+    dual_stack: bool,
+    socket_config: ServerConfig,
+    engine: E,
+    sampler: Option<Sampler>,
+    tenants: Option<Tenants>,
+) -> Result<Server<E>> {
+    let log = root.new(o!("address" => address.to_string()));
+    let server = if dual_stack {
+        let port = match address.to_socket_addrs()?.next() {
+            Some(address) => address.port(),
+            None => return Err(Error::Config(format!("invalid address {:?}", address))),
+        };
+        Server::start_dual_stack_with_config(log, engine, port, socket_config)?
+    } else {
+        Server::start_with_config(log, engine, address, socket_config)?
+    };
+    let addresses: Vec<_> = server.local_addrs()?.iter().map(ToString::to_string).collect();
+    info!(root, "Listening"; "addresses" => addresses.join(","));
+    let server = match tenants {
+        Some(tenants) => server.with_tenants(tenants),
+        None => server,
+    };
+    Ok(match sampler {
+        Some(sampler) => server.with_sampler(sampler),
+        None => server,
+    })
 }
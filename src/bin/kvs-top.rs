@@ -0,0 +1,221 @@
+#[macro_use]
+extern crate clap;
+
+use clap::Arg;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use kvs::{DEFAULT_ADDRESS, Client, Histogram, OpStats, Result, Stats};
+
+/// ANSI escape sequence to clear the terminal and move the cursor to the top-left.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let matches = app_from_crate!()
+        .arg(Arg::with_name("address").long("addr").takes_value(true))
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .takes_value(true)
+                .help("Refresh interval in seconds (default 1)"),
+        )
+        .arg(
+            Arg::with_name("once")
+                .long("once")
+                .help("Print a single snapshot and exit"),
+        )
+        .get_matches();
+
+    let address = matches.value_of("address").unwrap_or(DEFAULT_ADDRESS);
+    let interval = match matches.value_of("interval") {
+        Some(_) => value_t_or_exit!(matches, "interval", f64),
+        None => 1.0,
+    };
+    let interval = match Duration::try_from_secs_f64(interval) {
+        Ok(interval) if interval > Duration::ZERO => interval,
+        _ => clap::Error::value_validation_auto(format!(
+            "The argument 'interval' isn't a positive number of seconds: {}",
+            interval
+        ))
+        .exit(),
+    };
+
+    let mut previous = Client::connect(address)?.stats()?;
+    let mut previous_at = Instant::now();
+
+    if matches.is_present("once") {
+        print!("{}", render(address, &previous, &Stats::default(), None));
+        return Ok(());
+    }
+
+    loop {
+        thread::sleep(interval);
+
+        let current = Client::connect(address)?.stats()?;
+        let elapsed = previous_at.elapsed();
+        previous_at = Instant::now();
+
+        let mut stdout = io::stdout();
+        write!(stdout, "{}{}", CLEAR_SCREEN, render(address, &current, &previous, Some(elapsed)))?;
+        stdout.flush()?;
+
+        previous = current;
+    }
+}
+
+/// Render a frame from the `current` stats, using `previous` to compute rates over `elapsed`.
+///
+/// If `elapsed` is `None`, cumulative figures are shown instead of rates.
+fn render(address: &str, current: &Stats, previous: &Stats, elapsed: Option<Duration>) -> String {
+    let mut out = String::new();
+    let secs = elapsed.map(|e| e.as_secs_f64()).unwrap_or(1.0).max(0.001);
+
+    let _ = writeln!(
+        out,
+        "kvs-top - {} ({}) - up {}",
+        address,
+        current.store_id,
+        format_uptime(current.uptime_secs)
+    );
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "{:<8}{:>10}{:>10}{:>10}{:>10}{:>10}",
+        if elapsed.is_some() { "OP" } else { "OP (ALL)" },
+        if elapsed.is_some() { "REQ/S" } else { "REQS" },
+        if elapsed.is_some() { "ERR/S" } else { "ERRS" },
+        "MEAN",
+        "P50",
+        "P99",
+    );
+    for (name, current, previous) in &[
+        ("get", &current.gets, &previous.gets),
+        ("set", &current.sets, &previous.sets),
+        ("rm", &current.removes, &previous.removes),
+    ] {
+        render_op(&mut out, name, current, previous, secs);
+    }
+
+    let lifetime = &current.lifetime;
+    let _ = writeln!(
+        out,
+        "LIFETIME  get: {}  set: {}  rm: {}  compactions: {}  written: {}",
+        lifetime.gets,
+        lifetime.sets,
+        lifetime.removes,
+        lifetime.compactions,
+        format_bytes(lifetime.written_bytes),
+    );
+    let _ = writeln!(
+        out,
+        "SIZES     key: {} mean, {} p99  value: {} mean, {} p99  distinct keys set: ~{}",
+        format_bytes(current.key_lengths.mean()),
+        format_bytes(current.key_lengths.percentile(99.0)),
+        format_bytes(current.value_sizes.mean()),
+        format_bytes(current.value_sizes.percentile(99.0)),
+        current.distinct_keys_set,
+    );
+
+    let engine = &current.engine;
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "ENGINE  keys: {} ({})  log files: {} ({} open, {})  uncompacted: {} ({:.0}%)",
+        engine.keys,
+        format_bytes(engine.index_bytes),
+        engine.log_files,
+        engine.open_files,
+        format_bytes(engine.log_bytes),
+        format_bytes(engine.uncompacted_bytes),
+        percent(engine.uncompacted_bytes, engine.log_bytes),
+    );
+    let _ = writeln!(
+        out,
+        "        compactions: {} (+{})  reclaimed: {}",
+        engine.compactions,
+        engine.compactions.saturating_sub(previous.engine.compactions),
+        format_bytes(engine.compacted_bytes),
+    );
+    if engine.read_only {
+        let _ = writeln!(out, "        READ-ONLY: disk full");
+    }
+    if let Some(last) = engine.recent_compactions.last() {
+        let took = Duration::from_millis(last.finished_at.saturating_sub(last.started_at));
+        let _ = writeln!(
+            out,
+            "        last compaction: reclaimed {} in {}",
+            format_bytes(last.reclaimed_bytes),
+            format_duration(took),
+        );
+    }
+
+    let _ = writeln!(out);
+    let _ = writeln!(out, "HOT PREFIXES");
+    for (prefix, count) in &current.hot_prefixes {
+        let _ = writeln!(out, "  {:<20}{:>10}", format!("{:?}", prefix), count);
+    }
+
+    out
+}
+
+fn render_op(out: &mut String, name: &str, current: &OpStats, previous: &OpStats, secs: f64) {
+    let latency: Histogram = current.latency.since(&previous.latency);
+    let _ = writeln!(
+        out,
+        "{:<8}{:>10.1}{:>10.1}{:>10}{:>10}{:>10}",
+        name,
+        current.count.saturating_sub(previous.count) as f64 / secs,
+        current.errors.saturating_sub(previous.errors) as f64 / secs,
+        format_duration(latency.mean()),
+        format_duration(latency.percentile(50.0)),
+        format_duration(latency.percentile(99.0)),
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    if micros < 1000 {
+        format!("{}µs", micros)
+    } else if micros < 1_000_000 {
+        format!("{:.1}ms", micros as f64 / 1000.0)
+    } else {
+        format!("{:.1}s", micros as f64 / 1_000_000.0)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+fn format_uptime(secs: u64) -> String {
+    format!("{}h{:02}m{:02}s", secs / 3600, (secs / 60) % 60, secs % 60)
+}
@@ -0,0 +1,157 @@
+#[macro_use]
+extern crate clap;
+
+use clap::Arg;
+use rand::seq::IteratorRandom;
+use std::fs;
+use std::io::{self, ErrorKind::NotFound};
+use std::path::Path;
+use std::process;
+
+use kvs::{detect_engines, Client, EmbeddedClient, Error, KvStore, KvsClient, Result};
+use kvs::SYSTEM_KEY_PREFIX;
+
+/// The number of keys spot-checked against the live store by default.
+const DEFAULT_SPOT_CHECKS: usize = 100;
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let matches = app_from_crate!()
+        .about("Check that a backup of a `kvs` data directory is consistent")
+        .long_about(
+            "Check that a backup of a `kvs` data directory is consistent, i.e. that its log files \
+             are intact, agree with its manifest, and hold an unbroken sequence of commands.\n\n\
+             If a live data directory or server address is given, random keys from the backup \
+             are also compared with the live store. Keys changed since the backup was taken are \
+             reported as mismatches.\n\n\
+             A JSON report is written to stdout. The exit status is 0 if the backup passed, and \
+             1 otherwise. The backup itself is never modified.",
+        )
+        .arg(Arg::with_name("backup").required(true).help("The backup's data directory"))
+        .arg(Arg::with_name("live").help("A live data directory, or a server address"))
+        .arg(
+            Arg::with_name("spot-checks")
+                .long("spot-checks")
+                .takes_value(true)
+                .requires("live")
+                .help("The number of random keys to compare with the live store (default 100)"),
+        )
+        .get_matches();
+
+    let backup = Path::new(matches.value_of("backup").expect("backup is required"));
+    check_engine(backup)?;
+    let spot_checks = match matches.value_of("spot-checks") {
+        Some(_) => value_t_or_exit!(matches, "spot-checks", usize),
+        None => DEFAULT_SPOT_CHECKS,
+    };
+
+    let check = KvStore::check(backup)?;
+    let mut pass = check.clean;
+    let spot_check = match matches.value_of("live") {
+        // Spot checks read the backup through a store, which can't be opened on a backup that
+        // failed its checks.
+        Some(live) if check.clean => {
+            let (checked, mismatches) = if Path::new(live).is_dir() {
+                check_engine(Path::new(live))?;
+                let mut client = EmbeddedClient::new(KvStore::open(live)?);
+                spot_check(backup, &mut client, spot_checks)?
+            } else {
+                spot_check(backup, &mut Client::connect(live)?, spot_checks)?
+            };
+            pass = pass && mismatches.is_empty();
+            Some(serde_json::json!({ "checked": checked, "mismatches": mismatches }))
+        },
+        _ => None,
+    };
+
+    let report = serde_json::json!({ "pass": pass, "check": check, "spot_check": spot_check });
+    println!("{}", serde_json::to_string_pretty(&report).expect("Reports are serializable"));
+    if !pass {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Compare up to `count` random keys from the backup in `dir` with their values in `live`,
+/// returning the number of keys compared and a description of each mismatch.
+///
+/// Opening a store can write to its directory, so the backup is copied to a temporary directory
+/// and opened from there.
+fn spot_check<C: KvsClient>(
+    dir: &Path,
+    live: &mut C,
+    count: usize,
+) -> Result<(usize, Vec<serde_json::Value>)> {
+    let copy = tempfile::TempDir::new()?;
+    copy_dir(dir, copy.path())?;
+    let mut backup = KvStore::open(copy.path())?;
+
+    let mut keys = Vec::new();
+    backup.export("", None, |key, _| {
+        if !key.starts_with(SYSTEM_KEY_PREFIX) {
+            keys.push(key.to_owned());
+        }
+        Ok(())
+    })?;
+    let mut keys = keys.into_iter().choose_multiple(&mut rand::thread_rng(), count);
+    keys.sort();
+
+    let mut backup = EmbeddedClient::new(backup);
+    let mut mismatches = Vec::new();
+    for key in &keys {
+        let expected = backup.get(key.clone())?;
+        let found = live.get(key.clone())?;
+        if found != expected {
+            mismatches.push(serde_json::json!({
+                "key": key,
+                "backup": expected,
+                "live": found,
+            }));
+        }
+    }
+    Ok((keys.len(), mismatches))
+}
+
+/// Copy the files in `from`, and its subdirectories, to `to`.
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that `dir` is an existing data directory that uses the `kvs` engine, according to its
+/// `engine` marker (if it was created by `kvs-server`) and its data files.
+fn check_engine(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        let message = format!("{} is not a directory", dir.display());
+        return Err(io::Error::new(NotFound, message).into());
+    }
+    let wrong_engine = |found: &str| Error::WrongEngine {
+        requested: "kvs".to_owned(),
+        found: found.to_owned(),
+    };
+    match fs::read_to_string(dir.join("engine")) {
+        Ok(ref engine) if engine == "kvs" => {},
+        Ok(engine) => return Err(wrong_engine(&engine)),
+        Err(ref err) if err.kind() == NotFound => {},
+        Err(err) => return Err(err.into()),
+    }
+    match detect_engines(dir)?.into_iter().find(|&engine| engine != "kvs") {
+        Some(engine) => Err(wrong_engine(engine)),
+        None => Ok(()),
+    }
+}
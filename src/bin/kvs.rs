@@ -0,0 +1,235 @@
+#[macro_use]
+extern crate clap;
+
+use clap::{AppSettings, Arg, SubCommand};
+use std::fs;
+use std::io::{self, ErrorKind::NotFound, Write};
+use std::path::Path;
+use std::process;
+
+use kvs::{detect_engines, Error, KvStore, KvsEngine, Result, FORMAT_VERSION, SYSTEM_KEY_PREFIX};
+
+/// The number of expired keys removed at a time by `kvs compact`.
+const SWEEP_BATCH: usize = 1000;
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let matches = app_from_crate!()
+        .about("Administer `kvs` data directories offline")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .subcommand(
+            SubCommand::with_name("upgrade")
+                .about("Rewrite a data directory in the current on-disk format version")
+                .arg(Arg::with_name("dir").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Check a data directory for consistency, repairing it if it's safe to do so")
+                .long_about(
+                    "Check a data directory for consistency, repairing it if it's safe to do so.\n\n\
+                     A JSON report is written to stdout. The exit status is 0 if the directory is \
+                     clean (or was repaired), and 1 otherwise.",
+                )
+                .arg(Arg::with_name("dir").required(true))
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Report the repairs that are needed without making them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("compact")
+                .about("Fully compact a data directory, reporting the space reclaimed")
+                .long_about(
+                    "Fully compact a data directory, reporting the space reclaimed.\n\n\
+                     The directory is first checked for consistency, and isn't compacted if any \
+                     problems are found (see `kvs fsck`). Expired keys are then removed, the log \
+                     is rewritten to hold only live keys, and every value is read back to verify \
+                     it. The server using the directory must be stopped first.",
+                )
+                .arg(Arg::with_name("dir").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("dump")
+                .about("Write the keys in a data directory to stdout, as JSON lines")
+                .arg(Arg::with_name("dir").required(true))
+                .arg(
+                    Arg::with_name("prefix")
+                        .long("prefix")
+                        .takes_value(true)
+                        .help("Only dump keys starting with this prefix"),
+                )
+                .arg(
+                    Arg::with_name("modified-since")
+                        .long("modified-since")
+                        .takes_value(true)
+                        .validator(|date| parse_date(&date).map(|_| ()))
+                        .help(
+                            "Only dump keys set on or after this date (YYYY-MM-DD, UTC), and keys \
+                             with no recorded modification time",
+                        ),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        ("upgrade", Some(args)) => {
+            let dir = Path::new(args.value_of("dir").expect("Missing value for required arg: dir"));
+            check_engine(dir)?;
+
+            let upgraded = KvStore::open(dir)?.upgrade()?;
+            if upgraded == 0 {
+                println!("{} is already at format version {}", dir.display(), FORMAT_VERSION);
+            } else {
+                println!(
+                    "upgraded {} log files in {} to format version {}",
+                    upgraded,
+                    dir.display(),
+                    FORMAT_VERSION
+                );
+            }
+        },
+        ("fsck", Some(args)) => {
+            let dir = Path::new(args.value_of("dir").expect("Missing value for required arg: dir"));
+            check_engine(dir)?;
+
+            let report = if args.is_present("dry-run") {
+                KvStore::check(dir)?
+            } else {
+                KvStore::repair(dir)?
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).expect("Reports are serializable")
+            );
+            if !report.clean && !report.repaired {
+                process::exit(1);
+            }
+        },
+        ("compact", Some(args)) => {
+            let dir = Path::new(args.value_of("dir").expect("Missing value for required arg: dir"));
+            check_engine(dir)?;
+
+            let report = KvStore::check(dir)?;
+            if !report.clean {
+                eprintln!(
+                    "{} has problems that must be repaired before compacting (see `kvs fsck`)",
+                    dir.display()
+                );
+                process::exit(1);
+            }
+
+            let before = log_bytes(dir)?;
+            let mut store = KvStore::open(dir)?;
+            let mut expired = 0;
+            loop {
+                let swept = store.sweep_expired(SWEEP_BATCH)?.len();
+                expired += swept;
+                if swept < SWEEP_BATCH {
+                    break;
+                }
+            }
+            store.maintain()?;
+            drop(store);
+            let after = log_bytes(dir)?;
+            println!(
+                "compacted {} from {} to {} bytes ({} reclaimed), removing {} expired keys",
+                dir.display(),
+                before,
+                after,
+                before.saturating_sub(after),
+                expired
+            );
+        },
+        ("dump", Some(args)) => {
+            let dir = Path::new(args.value_of("dir").expect("Missing value for required arg: dir"));
+            check_engine(dir)?;
+
+            let prefix = args.value_of("prefix").unwrap_or("");
+            let modified_since = args
+                .value_of("modified-since")
+                .map(|date| parse_date(date).expect("Dates are validated"));
+            let stdout = io::stdout();
+            let mut out = io::BufWriter::new(stdout.lock());
+            KvStore::open(dir)?.export(prefix, modified_since, |key, value| {
+                if !key.starts_with(SYSTEM_KEY_PREFIX) {
+                    let entry = serde_json::json!({ "key": key, "value": value });
+                    writeln!(out, "{}", entry)?;
+                }
+                Ok(())
+            })?;
+            out.flush()?;
+        },
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// The total size of the log files in `dir`, in bytes.
+fn log_bytes(dir: &Path) -> Result<u64> {
+    let mut bytes = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "log") {
+            bytes += fs::metadata(path)?.len();
+        }
+    }
+    Ok(bytes)
+}
+
+/// Parse a `--modified-since` date of the form `YYYY-MM-DD` (UTC), returning the time at the start
+/// of the day in milliseconds since the UNIX epoch.
+fn parse_date(arg: &str) -> std::result::Result<u64, String> {
+    let invalid = || format!("expected a date of the form YYYY-MM-DD, found {:?}", arg);
+    let parts = arg
+        .split('-')
+        .map(str::parse)
+        .collect::<std::result::Result<Vec<u64>, _>>()
+        .map_err(|_| invalid())?;
+    let (year, month, day) = match parts[..] {
+        [year, month, day] => (year, month, day),
+        _ => return Err(invalid()),
+    };
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    // Count days from 0000-03-01, so that leap days fall at the end of each year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year / 400, year % 400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Ok(days * 24 * 60 * 60 * 1000)
+}
+
+/// Check that `dir` is an existing data directory that uses the `kvs` engine, according to its
+/// `engine` marker (if it was created by `kvs-server`) and its data files.
+fn check_engine(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        let message = format!("{} is not a directory", dir.display());
+        return Err(io::Error::new(NotFound, message).into());
+    }
+    let wrong_engine = |found: &str| Error::WrongEngine {
+        requested: "kvs".to_owned(),
+        found: found.to_owned(),
+    };
+    match fs::read_to_string(dir.join("engine")) {
+        Ok(ref engine) if engine == "kvs" => {},
+        Ok(engine) => return Err(wrong_engine(&engine)),
+        Err(ref err) if err.kind() == NotFound => {},
+        Err(err) => return Err(err.into()),
+    }
+    match detect_engines(dir)?.into_iter().find(|&engine| engine != "kvs") {
+        Some(engine) => Err(wrong_engine(engine)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,134 @@
+//! Serde helpers for byte strings, used with `#[serde(with = "...")]`.
+//!
+//! serde writes `Vec<u8>` as a sequence of integers, so these write it as a MessagePack `bin`
+//! instead. They read either a `bin` or a `str`, so fields that used to be strings can become
+//! bytes without breaking what was already written.
+
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str;
+
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(bytes)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    deserializer.deserialize_byte_buf(BytesVisitor)
+}
+
+/// Byte strings written as a `str` if they're UTF-8 (so they're encoded just as they were when
+/// they were strings) and as a `bin` otherwise.
+pub mod text {
+    use super::Text;
+    use serde::{Serialize, Serializer};
+
+    pub use super::deserialize;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        Text(bytes).serialize(serializer)
+    }
+
+    /// An optional byte string, written as with [`text`](super) or as a `nil`.
+    pub mod option {
+        use super::super::{ByteBuf, Text};
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            bytes: &Option<Vec<u8>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            bytes.as_deref().map(Text).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vec<u8>>, D::Error> {
+            Ok(Option::<ByteBuf>::deserialize(deserializer)?.map(|bytes| bytes.0))
+        }
+    }
+}
+
+/// Key-value pairs of byte strings, each written as a two-element array of `bin`s.
+pub mod entries {
+    use super::{ByteBuf, Bytes};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+    pub fn serialize<S: Serializer>(
+        entries: &[(Vec<u8>, Vec<u8>)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(entries.iter().map(|(key, value)| (Bytes(key), Bytes(value))))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Entries, D::Error> {
+        let entries = Vec::<(ByteBuf, ByteBuf)>::deserialize(deserializer)?;
+        Ok(entries.into_iter().map(|(key, value)| (key.0, value.0)).collect())
+    }
+}
+
+/// Borrowed bytes that serialize as a `bin`.
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Borrowed bytes that serialize as a `str` if they're UTF-8, or a `bin` otherwise.
+struct Text<'a>(&'a [u8]);
+
+impl Serialize for Text<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match str::from_utf8(self.0) {
+            Ok(text) => serializer.serialize_str(text),
+            Err(_) => serializer.serialize_bytes(self.0),
+        }
+    }
+}
+
+/// Owned bytes that deserialize from a `bin` or a `str`.
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer).map(ByteBuf)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("bytes or a string")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Vec<u8>, E> {
+        Ok(value.as_bytes().to_vec())
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Vec<u8>, E> {
+        Ok(value.into_bytes())
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// One end of an in-process connection, which reads the bytes written to the other end.
+///
+/// Like a socket, reads return end-of-file once the other end has been dropped, and writes fail
+/// with [`io::ErrorKind::BrokenPipe`].
+pub struct ChannelStream {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    offset: usize,
+    read_timeout: Option<Duration>,
+}
+
+impl ChannelStream {
+    /// Construct both ends of a connection.
+    pub fn pair() -> (ChannelStream, ChannelStream) {
+        let (a_sender, b_receiver) = mpsc::channel();
+        let (b_sender, a_receiver) = mpsc::channel();
+        (ChannelStream::new(a_sender, a_receiver), ChannelStream::new(b_sender, b_receiver))
+    }
+
+    fn new(sender: Sender<Vec<u8>>, receiver: Receiver<Vec<u8>>) -> Self {
+        ChannelStream { sender, receiver, pending: Vec::new(), offset: 0, read_timeout: None }
+    }
+
+    /// Make reads fail with [`io::ErrorKind::TimedOut`] if nothing is received for `timeout`, or
+    /// block indefinitely if it's `None` (the default).
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+}
+
+impl Read for ChannelStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.pending.len() {
+            let received = match self.read_timeout {
+                Some(timeout) => self.receiver.recv_timeout(timeout),
+                None => self.receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(bytes) => {
+                    self.pending = bytes;
+                    self.offset = 0;
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::from(io::ErrorKind::TimedOut));
+                },
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.pending.len() - self.offset);
+        buf[..len].copy_from_slice(&self.pending[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+impl Write for ChannelStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Accepts in-process connections made with a [`Connector`].
+pub struct ChannelListener {
+    receiver: Receiver<ChannelStream>,
+}
+
+/// Makes in-process connections to a [`ChannelListener`].
+#[derive(Clone)]
+pub struct Connector {
+    sender: Sender<ChannelStream>,
+}
+
+/// Construct a listener and a connector for it.
+pub fn listener() -> (ChannelListener, Connector) {
+    let (sender, receiver) = mpsc::channel();
+    (ChannelListener { receiver }, Connector { sender })
+}
+
+impl ChannelListener {
+    /// Wait for a connection, for up to `timeout` if one is given.
+    ///
+    /// Returns `None` if the timeout elapsed. Once every [`Connector`] has been dropped no more
+    /// connections can be made, so this waits out the timeout (or forever).
+    pub fn accept(&self, timeout: Option<Duration>) -> Option<ChannelStream> {
+        let result = match timeout {
+            Some(timeout) => self.receiver.recv_timeout(timeout),
+            None => self.receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match result {
+            Ok(stream) => Some(stream),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => match timeout {
+                Some(timeout) => {
+                    thread::sleep(timeout);
+                    None
+                },
+                None => loop {
+                    thread::park();
+                },
+            },
+        }
+    }
+}
+
+impl Connector {
+    /// Connect to the listener.
+    pub fn connect(&self) -> io::Result<ChannelStream> {
+        let (client, server) = ChannelStream::pair();
+        self.sender
+            .send(server)
+            .map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, "server has stopped"))?;
+        Ok(client)
+    }
+}
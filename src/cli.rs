@@ -0,0 +1,655 @@
+//! The command-line interfaces of the `kvs-client` and `kvs-server` binaries.
+//!
+//! The interfaces are defined here, rather than in the binaries, so that both can generate shell
+//! completions (with their `completions` subcommands) from the same definitions they parse their
+//! arguments with.
+
+use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, value_t};
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
+use std::io;
+
+use crate::engine::SyncPolicy;
+use crate::server::{ChaosConfig, TenantQuota};
+
+const VALID_ENGINES: &[&str] = &["kvs", "sled", "memory"];
+const KEY_CHARSETS: &[&str] = &["any", "utf8", "ascii", "printable"];
+const LOG_FORMATS: &[&str] = &["human", "json"];
+const LOG_LEVELS: &[&str] = &["critical", "error", "warning", "info", "debug", "trace"];
+
+/// The command-line interface of `kvs-client`.
+pub fn client() -> App<'static, 'static> {
+    app_from_crate!()
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Get the value of a given key")
+                .arg(Arg::with_name("key").required(true))
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("set")
+                .about("Set the value of a given key to a given value")
+                .arg(Arg::with_name("key").required(true))
+                .arg(Arg::with_name("value").required(true))
+                .arg(
+                    Arg::with_name("ttl")
+                        .long("ttl")
+                        .takes_value(true)
+                        .help("Expire the key after this many seconds"),
+                )
+                .arg(
+                    Arg::with_name("fsync")
+                        .long("fsync")
+                        .help("Wait for the server to sync the value to disk"),
+                )
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Remove a given key, or every key starting with a given prefix")
+                .arg(Arg::with_name("key").required_unless("prefix").conflicts_with("prefix"))
+                .arg(
+                    Arg::with_name("prefix")
+                        .long("prefix")
+                        .takes_value(true)
+                        .help("Remove every key starting with this prefix"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .requires("prefix")
+                        .help("Show how many keys would be removed, without removing them"),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .requires("prefix")
+                        .conflicts_with("dry-run")
+                        .help("Confirm removing every key starting with the prefix"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .conflicts_with("prefix")
+                        .help("Remove the key even if it's immutable"),
+                )
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("ttl")
+                .about("Show how many seconds are left before a given key expires")
+                .arg(Arg::with_name("key").required(true))
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("expire")
+                .about("Make a given key expire after a given number of seconds")
+                .arg(Arg::with_name("key").required(true))
+                .arg(Arg::with_name("seconds").required(true))
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("put-cas")
+                .about("Store a value under a key derived from its content, and print the key")
+                .arg(Arg::with_name("value").required(true))
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("persist")
+                .about("Stop a given key from expiring")
+                .arg(Arg::with_name("key").required(true))
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("query")
+                .about("Get or remove the keys matching a query")
+                .long_about(
+                    "Get or remove the keys matching a query, of the form:\n\n    \
+                     (GET | DEL) <pattern> [WHERE <condition> [AND <condition>]...] [LIMIT <n>]\n\n\
+                     A pattern is an exact key, or a prefix followed by `*`. A condition is \
+                     `key` or `value`, followed by `=`, `!=` or `CONTAINS`, followed by a quoted \
+                     string.",
+                )
+                .arg(Arg::with_name("query").required(true))
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("List the keys with a given prefix, or in a range, with their values")
+                .arg(Arg::with_name("prefix").conflicts_with_all(&["from", "to"]))
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .help("List keys from this one, in key order"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .help("List keys before this one, in key order"),
+                )
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("hot-keys")
+                .about("List the most frequently requested keys")
+                .arg(Arg::with_name("limit").long("limit").takes_value(true))
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("sample")
+                .about("List a random sample of keys")
+                .arg(Arg::with_name("count").required(true))
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("tenants")
+                .about("Show each tenant's usage and quotas")
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show the server's version, engine and enabled features")
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("maintenance")
+                .about("Put the server into maintenance mode, rejecting writes while it compacts")
+                .arg(
+                    Arg::with_name("duration")
+                        .required(true)
+                        .help("How many seconds to stay in maintenance mode (0 to end it)"),
+                )
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Print changes to keys starting with a given prefix until interrupted")
+                .arg(Arg::with_name("prefix").required(true))
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .takes_value(true)
+                        .possible_values(&["text", "json"])
+                        .default_value("text")
+                        .help("Print each change as tab-separated text or as a JSON object"),
+                )
+                .arg(address()),
+        )
+        .subcommand(
+            SubCommand::with_name("copy")
+                .about("Copy the keys starting with a given prefix from one server to another")
+                .arg(Arg::with_name("from").long("from").takes_value(true).required(true))
+                .arg(Arg::with_name("to").long("to").takes_value(true).required(true))
+                .arg(
+                    Arg::with_name("prefix")
+                        .long("prefix")
+                        .takes_value(true)
+                        .default_value("")
+                        .help("Only copy keys starting with this prefix"),
+                )
+                .arg(
+                    Arg::with_name("batch-size")
+                        .long("batch-size")
+                        .takes_value(true)
+                        .help("Report progress after copying this many keys"),
+                )
+                .arg(
+                    Arg::with_name("concurrency")
+                        .long("concurrency")
+                        .takes_value(true)
+                        .help("Write each batch over this many connections"),
+                )
+                .arg(
+                    Arg::with_name("resume-after")
+                        .long("resume-after")
+                        .takes_value(true)
+                        .help("Only copy keys after this one, as reported by an interrupted copy"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("schema")
+                .about("Print a JSON description of the requests and responses servers speak"),
+        )
+        .subcommand(completions())
+}
+
+/// The command-line interface of `kvs-server`.
+pub fn server() -> App<'static, 'static> {
+    app_from_crate!()
+        .arg(
+            Arg::with_name("engine")
+                .long("engine")
+                .takes_value(true)
+                .possible_values(VALID_ENGINES),
+        )
+        .arg(address())
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .takes_value(true)
+                .help("Store data in this directory, rather than the current directory"),
+        )
+        .arg(
+            Arg::with_name("dual-stack")
+                .long("dual-stack")
+                .help("Listen on every interface over both IPv6 and IPv4, on the port of --addr"),
+        )
+        .arg(
+            Arg::with_name("reuse-port")
+                .long("reuse-port")
+                .help("Allow other servers to listen on the same port, sharing its connections"),
+        )
+        .arg(
+            Arg::with_name("async")
+                .long("async")
+                .help("Accept connections and receive requests without blocking, on tokio"),
+        )
+        .arg(
+            Arg::with_name("nagle")
+                .long("nagle")
+                .help("Coalesce small responses before sending them (disables TCP_NODELAY)"),
+        )
+        .arg(
+            Arg::with_name("send-buffer")
+                .long("send-buffer")
+                .takes_value(true)
+                .help("Size of each connection's send buffer in bytes"),
+        )
+        .arg(
+            Arg::with_name("recv-buffer")
+                .long("recv-buffer")
+                .takes_value(true)
+                .help("Size of each connection's receive buffer in bytes"),
+        )
+        .arg(
+            Arg::with_name("buffer-size")
+                .long("buffer-size")
+                .takes_value(true)
+                .help("Buffer up to this many bytes when reading requests and writing responses"),
+        )
+        .arg(
+            Arg::with_name("force-engine")
+                .long("force-engine")
+                .help(
+                    "Use --engine even if the data directory holds another engine's data, \
+                     and record it as the directory's engine",
+                ),
+        )
+        .arg(
+            Arg::with_name("follow")
+                .long("follow")
+                .conflicts_with("force-engine")
+                .help(
+                    "Serve reads from a kvs engine that another server writes to in the same data \
+                     directory, rejecting writes",
+                ),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("Read settings and per-bucket engines from this TOML file"),
+        )
+        .arg(
+            Arg::with_name("max-index-memory")
+                .long("max-index-memory")
+                .takes_value(true)
+                .help("Refuse new keys once the kvs engine's index uses this many bytes"),
+        )
+        .arg(
+            Arg::with_name("disk-index-cache")
+                .long("disk-index-cache")
+                .takes_value(true)
+                .help("Keep the kvs engine's index on disk, caching this many bytes in memory"),
+        )
+        .arg(
+            Arg::with_name("rebuild-index")
+                .long("rebuild-index")
+                .help(
+                    "Rebuild the kvs engine's index from its raw log, checking every command, \
+                     even if it was closed cleanly",
+                ),
+        )
+        .arg(
+            Arg::with_name("strict-recovery")
+                .long("strict-recovery")
+                .help(
+                    "Refuse to start if the kvs engine's log ends with a command torn by a crash, \
+                     rather than discarding it",
+                ),
+        )
+        .arg(
+            Arg::with_name("sync")
+                .long("sync")
+                .takes_value(true)
+                .validator(|policy| {
+                    policy.parse::<SyncPolicy>().map(|_| ()).map_err(|err| err.to_string())
+                })
+                .help(
+                    "When the kvs engine syncs writes to disk: `always` (every write), an interval \
+                     like `100ms` (in the background), or `os` (left to the operating system, the \
+                     default)",
+                ),
+        )
+        .arg(
+            Arg::with_name("remove-expired-on-read")
+                .long("remove-expired-on-read")
+                .help(
+                    "Remove expired keys from the kvs engine as soon as a read finds them, \
+                     rather than waiting for the next sweep",
+                ),
+        )
+        .arg(
+            Arg::with_name("inline-values")
+                .long("inline-values")
+                .takes_value(true)
+                .help("Keep values of at most this many bytes in the kvs engine's index"),
+        )
+        .arg(
+            Arg::with_name("compression-dictionary")
+                .long("compression-dictionary")
+                .takes_value(true)
+                .help(
+                    "Compress the kvs engine's compacted values with a dictionary of at most this \
+                     many bytes, trained during compaction",
+                ),
+        )
+        .arg(
+            Arg::with_name("max-open-files")
+                .long("max-open-files")
+                .takes_value(true)
+                .help("Keep at most this many of each kvs engine's log files open for reading"),
+        )
+        .arg(
+            Arg::with_name("max-segment-size")
+                .long("max-segment-size")
+                .takes_value(true)
+                .help("Start a new log file once each kvs engine's current one reaches this size"),
+        )
+        .arg(
+            Arg::with_name("compaction-threshold")
+                .long("compaction-threshold")
+                .takes_value(true)
+                .conflicts_with("compaction-ratio")
+                .help("Compact each kvs engine once this many bytes of its log are stale"),
+        )
+        .arg(
+            Arg::with_name("compaction-ratio")
+                .long("compaction-ratio")
+                .takes_value(true)
+                .help("Compact each kvs engine once this fraction of its log is stale, e.g. 0.5"),
+        )
+        .arg(
+            Arg::with_name("compaction-min-bytes")
+                .long("compaction-min-bytes")
+                .takes_value(true)
+                .requires("compaction-ratio")
+                .help("Never compact with --compaction-ratio until this many bytes are stale"),
+        )
+        .arg(
+            Arg::with_name("max-background-jobs")
+                .long("max-background-jobs")
+                .takes_value(true)
+                .help("Run at most this many compactions at once, across every kvs engine"),
+        )
+        .arg(
+            Arg::with_name("write-stop-bytes")
+                .long("write-stop-bytes")
+                .takes_value(true)
+                .help("Reject writes while a kvs engine has this many stale bytes to compact"),
+        )
+        .arg(
+            Arg::with_name("write-slowdown-bytes")
+                .long("write-slowdown-bytes")
+                .takes_value(true)
+                .requires("write-stop-bytes")
+                .help(
+                    "Slow down writes while a kvs engine has this many stale bytes to compact \
+                     (default: half of --write-stop-bytes)",
+                ),
+        )
+        .arg(
+            Arg::with_name("reserved-space")
+                .long("reserved-space")
+                .takes_value(true)
+                .help("Reserve this many bytes of disk per kvs engine, freed if the disk fills"),
+        )
+        .arg(
+            Arg::with_name("sample-file")
+                .long("sample-file")
+                .takes_value(true)
+                .help("Capture sampled and slow requests as JSON lines to this file"),
+        )
+        .arg(
+            Arg::with_name("sample-rate")
+                .long("sample-rate")
+                .takes_value(true)
+                .requires("sample-file")
+                .help("Fraction of requests to capture (e.g. 0.01)"),
+        )
+        .arg(
+            Arg::with_name("slow-ms")
+                .long("slow-ms")
+                .takes_value(true)
+                .requires("sample-file")
+                .help("Always capture requests slower than this many milliseconds"),
+        )
+        .arg(
+            Arg::with_name("sample-max-size")
+                .long("sample-max-size")
+                .takes_value(true)
+                .requires("sample-file")
+                .help("Rotate the capture file once it reaches this many bytes"),
+        )
+        .arg(
+            Arg::with_name("sample-max-files")
+                .long("sample-max-files")
+                .takes_value(true)
+                .requires("sample-file")
+                .help("Number of rotated capture files to keep"),
+        )
+        .arg(
+            Arg::with_name("max-key-length")
+                .long("max-key-length")
+                .takes_value(true)
+                .help("Reject keys longer than this many bytes"),
+        )
+        .arg(
+            Arg::with_name("key-charset")
+                .long("key-charset")
+                .takes_value(true)
+                .possible_values(KEY_CHARSETS)
+                .help("Reject keys containing characters outside this set"),
+        )
+        .arg(
+            Arg::with_name("reserved-key-prefix")
+                .long("reserved-key-prefix")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Reject keys starting with this prefix (may be repeated)"),
+        )
+        .arg(
+            Arg::with_name("tenant-quota")
+                .long("tenant-quota")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|quota| parse_tenant_quota(&quota).map(|_| ()))
+                .help(
+                    "Enforce a quota for a tenant, e.g. `app1=keys:1000,bytes:1048576,rps:100` \
+                     (may be repeated; `*` sets the default quota)",
+                ),
+        )
+        .arg(
+            Arg::with_name("request-timeout-ms")
+                .long("request-timeout-ms")
+                .takes_value(true)
+                .help("Fail requests that take longer than this, abandoning scans"),
+        )
+        .arg(
+            Arg::with_name("heartbeat-interval-ms")
+                .long("heartbeat-interval-ms")
+                .takes_value(true)
+                .help("Send watching clients a heartbeat after this long without changes"),
+        )
+        .arg(
+            Arg::with_name("sweep-interval-ms")
+                .long("sweep-interval-ms")
+                .takes_value(true)
+                .help("Sweep expired keys about this often (jittered by ±25%)"),
+        )
+        .arg(
+            Arg::with_name("sweep-max-keys")
+                .long("sweep-max-keys")
+                .takes_value(true)
+                .help("Remove at most this many expired keys per sweep"),
+        )
+        .arg(
+            Arg::with_name("sweep-rate")
+                .long("sweep-rate")
+                .takes_value(true)
+                .help("Remove at most this many expired keys per second"),
+        )
+        .arg(
+            Arg::with_name("scrub")
+                .long("scrub")
+                .help("Verify stored entries in the background, logging any that are corrupt"),
+        )
+        .arg(
+            Arg::with_name("scrub-rate")
+                .long("scrub-rate")
+                .takes_value(true)
+                .requires("scrub")
+                .help("Verify at most this many entries per second"),
+        )
+        .arg(
+            Arg::with_name("warmup")
+                .long("warmup")
+                .help("Record the hottest keys, and prefetch them when the server next starts"),
+        )
+        .arg(
+            Arg::with_name("warmup-keys")
+                .long("warmup-keys")
+                .takes_value(true)
+                .requires("warmup")
+                .help("Record and prefetch this many of the hottest keys"),
+        )
+        .arg(
+            Arg::with_name("warmup-rate")
+                .long("warmup-rate")
+                .takes_value(true)
+                .requires("warmup")
+                .help("Prefetch at most this many keys per second"),
+        )
+        .arg(
+            Arg::with_name("chaos")
+                .long("chaos")
+                .takes_value(true)
+                .validator(|chaos| {
+                    chaos.parse::<ChaosConfig>().map(|_| ()).map_err(|err| err.to_string())
+                })
+                .help(
+                    "Inject faults for testing clients, e.g. `latency=50ms,drop=0.01,error=0.05` \
+                     (never use in production)",
+                ),
+        )
+        .arg(
+            Arg::with_name("mirror-addr")
+                .long("mirror-addr")
+                .takes_value(true)
+                .help("Mirror requests to the server at this address, logging divergent responses"),
+        )
+        .arg(
+            Arg::with_name("mirror-rate")
+                .long("mirror-rate")
+                .takes_value(true)
+                .requires("mirror-addr")
+                .help("Fraction of requests to mirror (default 1.0)"),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .takes_value(true)
+                .help("Write logs to this file, rather than the terminal"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .takes_value(true)
+                .possible_values(LOG_LEVELS)
+                .help("Only log messages at least this severe"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .takes_value(true)
+                .possible_values(LOG_FORMATS)
+                .help("Write logs as human-readable text, or as JSON lines"),
+        )
+        .arg(
+            Arg::with_name("log-max-size")
+                .long("log-max-size")
+                .takes_value(true)
+                .requires("log-file")
+                .help("Rotate the log file once it reaches this many bytes (0 for no limit)"),
+        )
+        .arg(
+            Arg::with_name("log-max-age")
+                .long("log-max-age")
+                .takes_value(true)
+                .requires("log-file")
+                .help("Rotate the log file once it's been written to for this many seconds"),
+        )
+        .arg(
+            Arg::with_name("log-max-files")
+                .long("log-max-files")
+                .takes_value(true)
+                .requires("log-file")
+                .help("Number of rotated log files to keep"),
+        )
+        .subcommand(completions())
+}
+
+/// Write completions for `app`, which is run as `bin_name`, to stdout, for the shell given to its
+/// `completions` subcommand.
+pub fn print_completions(mut app: App<'static, 'static>, bin_name: &str, args: &ArgMatches) {
+    let shell = value_t!(args, "shell", Shell).expect("Shells are validated");
+    app.gen_completions_to(bin_name, shell, &mut io::stdout());
+}
+
+/// Parse a `--tenant-quota` of the form `TENANT=LIMIT:VALUE,...`.
+pub fn parse_tenant_quota(arg: &str) -> std::result::Result<(String, TenantQuota), String> {
+    let (tenant, limits) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected TENANT=LIMITS, found {:?}", arg))?;
+    let mut quota = TenantQuota::default();
+    for limit in limits.split(',').filter(|limit| !limit.is_empty()) {
+        let (name, value) = limit
+            .split_once(':')
+            .ok_or_else(|| format!("expected LIMIT:VALUE, found {:?}", limit))?;
+        let invalid = || format!("invalid value for {}: {:?}", name, value);
+        match name {
+            "keys" => quota.max_keys = Some(value.parse().map_err(|_| invalid())?),
+            "bytes" => quota.max_bytes = Some(value.parse().map_err(|_| invalid())?),
+            "rps" => quota.max_requests_per_sec = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("unknown limit {:?} (expected keys, bytes or rps)", name)),
+        }
+    }
+    Ok((tenant.to_owned(), quota))
+}
+
+/// The `--addr` of the server to connect to, or to listen on.
+fn address() -> Arg<'static, 'static> {
+    Arg::with_name("address").long("addr").takes_value(true)
+}
+
+/// The `completions` subcommand, which prints shell completions.
+fn completions() -> App<'static, 'static> {
+    SubCommand::with_name("completions")
+        .about("Print completions for a given shell, to be sourced by its startup script")
+        .arg(Arg::with_name("shell").required(true).possible_values(&Shell::variants()))
+}
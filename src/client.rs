@@ -1,58 +1,962 @@
-use rmp_serde::decode::from_read as read_mp;
-use rmp_serde::encode::write as write_mp;
-use std::net::{TcpStream, ToSocketAddrs};
+mod caching;
+mod transaction;
 
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use rmp_serde::decode::Error as DecodeError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::channel::{ChannelStream, Connector};
+use crate::engine::{Engine, IfModified};
 use crate::error::{Error, Result};
-use crate::protocol::{Request, Response};
+use crate::format::ValueFormat;
+use crate::protocol::{decode_response, encode_request, Change, ErrorKind, Request, Response};
+use crate::protocol::{Durability, NotFoundReason, ServerInfo};
+use crate::server::TenantUsage;
+use crate::stats::Stats;
+
+pub use self::caching::{CacheConfig, CachingClient};
+pub use self::transaction::ClientTransaction;
+
+/// How long [`Client::connect`] waits for each address to accept a connection before trying the
+/// next, when a server's address resolves to more than one (e.g. both IPv6 and IPv4).
+const CONNECT_ATTEMPT_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// The default capacity of the buffers used to write requests and read responses.
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// How long a [`Watch`] waits to hear from the server by default, before giving up on the
+/// connection. Servers send heartbeats every few seconds, so this allows for several to be missed.
+const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// Implements a client for a key-value server.
+///
+/// Servers handle a single request per connection, so each request after the first is sent on a
+/// new connection to the same address.
 pub struct Client {
-    stream: TcpStream,
+    remote: Remote,
+    stream: Option<Box<dyn Stream>>,
+    options: ClientBuilder,
+}
+
+/// Configures the connections made by a [`Client`].
+///
+/// ```
+/// use kvs::Client;
+///
+/// # fn check() -> kvs::Result<()> {
+/// let client = Client::builder()
+///     .nodelay(false)
+///     .buffer_size(64 * 1024)
+///     .connect("127.0.0.1:4000")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    nodelay: bool,
+    buffer_size: usize,
+    heartbeat_timeout: Duration,
+    key_prefix: String,
+    value_format: ValueFormat,
+    durability: Durability,
+}
+
+/// An iterator over changes to watched keys, returned by [`Client::watch`].
+///
+/// Each call to `next` blocks until the next change is made. Iteration ends after the first
+/// error, e.g. if the server closes the connection, or if nothing (not even a heartbeat) is
+/// received from it for the client's heartbeat timeout.
+pub struct Watch {
+    request: Request,
+    reader: BufReader<Box<dyn Stream>>,
+    key_prefix: String,
+    done: bool,
+}
+
+/// Where a [`Client`] makes its connections to.
+enum Remote {
+    Tcp(SocketAddr),
+    InProcess(Connector),
+}
+
+/// A connection to a server.
+trait Stream: Read + Write + Send {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Stream for ChannelStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        ChannelStream::set_read_timeout(self, timeout);
+        Ok(())
+    }
+}
+
+impl Remote {
+    fn connect(&self, options: &ClientBuilder) -> Result<Box<dyn Stream>> {
+        Ok(match *self {
+            Remote::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(options.nodelay)?;
+                Box::new(stream)
+            },
+            Remote::InProcess(ref connector) => Box::new(connector.connect()?),
+        })
+    }
+}
+
+impl ClientBuilder {
+    /// Construct a builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send requests immediately rather than waiting to coalesce small writes, i.e. disable
+    /// Nagle's algorithm (`TCP_NODELAY`). This is enabled by default.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Buffer up to `bytes` when writing each request and reading each response (8KiB by
+    /// default), so that they're sent in as few packets as possible.
+    pub fn buffer_size(mut self, bytes: usize) -> Self {
+        self.buffer_size = bytes;
+        self
+    }
+
+    /// End a [`Watch`] with a timeout error if nothing, not even a heartbeat, is received from the
+    /// server for `timeout` (30 seconds by default).
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// Namespace every key the client uses under `prefix`, e.g. `"app1/"`.
+    ///
+    /// The prefix is added to the keys and prefixes passed to the client, and removed from the
+    /// keys it returns, so applications sharing a server can't collide as long as their prefixes
+    /// don't overlap. Requests about the whole server (e.g. [`Client::hot_keys`] and
+    /// [`Client::sample_keys`]) aren't namespaced. This is only enforced by the client: the server
+    /// doesn't authenticate clients, so can't stop one using keys outside its namespace. Using a
+    /// [tenant](struct.Tenants.html) as the prefix does let the server enforce quotas on it.
+    pub fn key_prefix<P: Into<String>>(mut self, prefix: P) -> Self {
+        self.key_prefix = prefix.into();
+        self
+    }
+
+    /// Serialize values with `format` in [`Client::get_as`] and [`Client::set_from`] (JSON by
+    /// default).
+    pub fn value_format(mut self, format: ValueFormat) -> Self {
+        self.value_format = format;
+        self
+    }
+
+    /// Have the server acknowledge sets once they're `durability` durable ([`Applied`] by
+    /// default).
+    ///
+    /// Waiting for [`Fsynced`] makes each set slower, but means an acknowledged set survives the
+    /// server's machine crashing. It can only be used with servers that support it.
+    ///
+    /// [`Applied`]: enum.Durability.html#variant.Applied
+    /// [`Fsynced`]: enum.Durability.html#variant.Fsynced
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Connect to a server with this configuration (see [`Client::connect`]).
+    pub fn connect<A: ToSocketAddrs>(self, address: A) -> Result<Client> {
+        let stream = connect_any(address.to_socket_addrs()?.collect())?;
+        stream.set_nodelay(self.nodelay)?;
+        Ok(Client {
+            remote: Remote::Tcp(stream.peer_addr()?),
+            stream: Some(Box::new(stream)),
+            options: self,
+        })
+    }
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder {
+            nodelay: true,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            key_prefix: String::new(),
+            value_format: ValueFormat::default(),
+            durability: Durability::default(),
+        }
+    }
 }
 
 impl Client {
-    /// Connact to a server.
+    /// Connect to a server.
+    ///
+    /// If `address` resolves to more than one address, they're tried in turn, alternating between
+    /// IPv6 and IPv4, and giving each a short time to accept before moving on to the next. If none
+    /// accept in that time they're tried again, waiting as long as each takes.
     pub fn connect<A: ToSocketAddrs>(address: A) -> Result<Client> {
-        let stream = TcpStream::connect(address)?;
-        Ok(Client { stream })
+        ClientBuilder::new().connect(address)
+    }
+
+    /// Construct a [`ClientBuilder`], to configure a client's connections before connecting.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Connect to an in-process server (see [`Server::start_in_process`]).
+    ///
+    /// [`Server::start_in_process`]: struct.Server.html#method.start_in_process
+    pub(crate) fn in_process(connector: Connector) -> Result<Client> {
+        let stream = connector.connect()?;
+        Ok(Client {
+            remote: Remote::InProcess(connector),
+            stream: Some(Box::new(stream)),
+            options: ClientBuilder::new(),
+        })
+    }
+
+    /// Send a request to the server, and read its response.
+    fn send(&mut self, request: &Request) -> Result<Response> {
+        let mut stream = self.request_stream(request)?;
+        decode_response(BufReader::with_capacity(self.options.buffer_size, &mut stream))
+    }
+
+    /// Send a request to the server, returning the connection to read its response from.
+    fn request_stream(&mut self, request: &Request) -> Result<Box<dyn Stream>> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => self.remote.connect(&self.options)?,
+        };
+        {
+            let mut writer = BufWriter::with_capacity(self.options.buffer_size, &mut stream);
+            encode_request(&mut writer, request)?;
+            writer.flush()?;
+        }
+        Ok(stream)
+    }
+
+    /// The key the server knows `key` as, with the client's key prefix.
+    fn key(&self, key: String) -> String {
+        if self.options.key_prefix.is_empty() {
+            key
+        } else {
+            format!("{}{}", self.options.key_prefix, key)
+        }
+    }
+
+    /// The key the server knows `key` as, as for [`key`](#method.key).
+    fn key_bytes(&self, key: &[u8]) -> Vec<u8> {
+        [self.options.key_prefix.as_bytes(), key].concat()
     }
 
     /// Get the value of a key.
     pub fn get(&mut self, key: String) -> Result<Option<String>> {
-        let request = Request::Get { key };
-        write_mp(&mut self.stream, &request)?;
-        let response = read_mp(&self.stream)?;
+        let request = Request::Get { key: self.key(key) };
+        let response = self.send(&request)?;
+        value_response(request, response)
+    }
 
-        match response {
-            Response::Found { value } => Ok(Some(value)),
-            Response::NotFound => Ok(None),
-            response => Err(Error::ProtocolError(request, response)),
+    /// Get the value of a key, or why it wasn't found, if the server's engine could tell.
+    pub fn lookup(
+        &mut self,
+        key: String,
+    ) -> Result<std::result::Result<String, Option<NotFoundReason>>> {
+        let request = Request::Get { key: self.key(key) };
+        match self.send(&request)? {
+            Response::Found { value } => Ok(Ok(value)),
+            Response::NotFound { reason } => Ok(Err(reason)),
+            response => Err(unexpected(request, response)),
         }
     }
 
     /// Set the value of a key.
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
-        let request = Request::Set { key, value };
-        write_mp(&mut self.stream, &request)?;
-        let response = read_mp(&self.stream)?;
+        let durability = self.options.durability;
+        let request = Request::Set { key: self.key(key), value, ttl_ms: None, durability };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
 
-        match response {
-            Response::Ok => Ok(()),
-            Response::NotFound => Err(Error::KeyNotFound),
-            response => Err(Error::ProtocolError(request, response)),
+    /// Set the value of a key, which expires after `ttl`.
+    pub fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        let (ttl_ms, durability) = (Some(ttl.as_millis() as u64), self.options.durability);
+        let request = Request::Set { key: self.key(key), value, ttl_ms, durability };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
+
+    /// Get the time left before a key expires, or `None` if it doesn't expire.
+    pub fn ttl(&mut self, key: String) -> Result<Option<Duration>> {
+        let request = Request::Ttl { key: self.key(key) };
+        match self.send(&request)? {
+            Response::Ttl { ttl_ms } => Ok(ttl_ms.map(Duration::from_millis)),
+            Response::NotFound { .. } => Err(Error::KeyNotFound),
+            response => Err(unexpected(request, response)),
         }
     }
 
+    /// Make an existing key expire after `ttl`, replacing any time-to-live it had.
+    pub fn expire(&mut self, key: String, ttl: Duration) -> Result<()> {
+        let request = Request::Expire { key: self.key(key), ttl_ms: ttl.as_millis() as u64 };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
+
+    /// Stop a key from expiring.
+    pub fn persist(&mut self, key: String) -> Result<()> {
+        let request = Request::Persist { key: self.key(key) };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
+
     /// Remove a key.
     pub fn remove(&mut self, key: String) -> Result<()> {
-        let request = Request::Remove { key };
-        write_mp(&mut self.stream, &request)?;
-        let response = read_mp(&self.stream)?;
+        let request = Request::Remove { key: self.key(key) };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
+
+    /// Remove a key, even if it's immutable (see [`WriteOnce`]).
+    ///
+    /// [`WriteOnce`]: struct.WriteOnce.html
+    pub fn force_remove(&mut self, key: String) -> Result<()> {
+        let request = Request::ForceRemove { key: self.key(key) };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
+
+    /// Store a value under a key derived from its content (see [`content_key`]), returning the
+    /// key. Storing a value that's already stored writes nothing, and returns the same key.
+    ///
+    /// The key is the one the value is stored under, which isn't within the client's
+    /// [key prefix](struct.ClientBuilder.html#method.key_prefix), so it has to be read back with a
+    /// client without one.
+    ///
+    /// [`content_key`]: fn.content_key.html
+    pub fn put_cas(&mut self, value: String) -> Result<String> {
+        let request = Request::PutCas { value };
+        match self.send(&request)? {
+            Response::Stored { key, .. } => Ok(key),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Get the values of several keys in a single request, in the same order as `keys`.
+    pub fn get_many(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        let keys: Vec<_> = keys.into_iter().map(|key| self.key(key)).collect();
+        let count = keys.len();
+        let request = Request::MultiGet { keys };
+        match self.send(&request)? {
+            Response::Values { values } if values.len() == count => Ok(values),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Set several keys to their values in a single request.
+    ///
+    /// The batch isn't atomic: if this fails, some of the keys may have been set.
+    pub fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        let pairs = pairs.into_iter().map(|(key, value)| (self.key(key), value)).collect();
+        let request = Request::MultiSet { pairs, durability: self.options.durability };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
+
+    /// Remove several keys in a single request, skipping any that aren't in the store, returning
+    /// the number of keys removed.
+    ///
+    /// As with [`set_many`](#method.set_many), the batch isn't atomic.
+    pub fn remove_many(&mut self, keys: Vec<String>) -> Result<u64> {
+        let keys = keys.into_iter().map(|key| self.key(key)).collect();
+        let request = Request::MultiRemove { keys };
+        let response = self.send(&request)?;
+        count_response(request, response)
+    }
+
+    /// Set a key to `new` if its value is `expected` (or if it isn't in the store, for an
+    /// `expected` of `None`), returning whether it was set.
+    ///
+    /// The check and the write happen together on the server, so a concurrent write by another
+    /// client can't be overwritten by mistake.
+    pub fn compare_and_swap(
+        &mut self,
+        key: String,
+        expected: Option<String>,
+        new: String,
+    ) -> Result<bool> {
+        let request = Request::CompareAndSwap { key: self.key(key), expected, new };
+        match self.send(&request)? {
+            Response::Swapped { swapped } => Ok(swapped),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Add `delta` to the integer value of a key on the server (treating a missing key as `0`),
+    /// returning the new value.
+    ///
+    /// Fails with [`Error::InvalidValue`] (reported by the server) if the value isn't an integer.
+    ///
+    /// [`Error::InvalidValue`]: enum.Error.html#variant.InvalidValue
+    pub fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        let request = Request::Increment { key: self.key(key), delta };
+        match self.send(&request)? {
+            Response::Counter { value } => Ok(value),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Get the value of a key from the server, unless its version (see [`value_version`]) is
+    /// `known_version`, in which case only [`IfModified::NotModified`] is sent back.
+    ///
+    /// ```
+    /// # use kvs::{value_version, Client, IfModified, Result};
+    /// # fn check() -> Result<()> {
+    /// let mut client = Client::connect("127.0.0.1:4000")?;
+    /// let config = client.get("config".to_owned())?.unwrap_or_default();
+    /// match client.get_if_modified("config".to_owned(), value_version(&config))? {
+    ///     IfModified::NotModified => {},
+    ///     IfModified::Modified(value) => println!("config is now {:?}", value),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`value_version`]: fn.value_version.html
+    /// [`IfModified::NotModified`]: enum.IfModified.html#variant.NotModified
+    pub fn get_if_modified(&mut self, key: String, known_version: u64) -> Result<IfModified> {
+        let request = Request::GetIfModified { key: self.key(key), version: known_version };
+        match self.send(&request)? {
+            Response::NotModified => Ok(IfModified::NotModified),
+            Response::Found { value } => Ok(IfModified::Modified(Some(value))),
+            Response::NotFound { .. } => Ok(IfModified::Modified(None)),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Move a key's value to another key on the server, returning whether it was moved.
+    ///
+    /// If `to` is already in the store, it's only replaced if `overwrite` is true (otherwise
+    /// nothing changes and this returns `false`). Fails with [`Error::KeyNotFound`] if `from`
+    /// isn't in the store.
+    ///
+    /// [`Error::KeyNotFound`]: enum.Error.html#variant.KeyNotFound
+    pub fn rename(&mut self, from: String, to: String, overwrite: bool) -> Result<bool> {
+        let request = Request::Rename { from: self.key(from), to: self.key(to), overwrite };
+        match self.send(&request)? {
+            Response::Renamed { renamed } => Ok(renamed),
+            Response::NotFound { .. } => Err(Error::KeyNotFound),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Get the value of a key, deserialized as a `T` (see [`ClientBuilder::value_format`]).
+    ///
+    /// Fails with [`Error::InvalidValue`] if the value isn't a valid `T`.
+    ///
+    /// [`Error::InvalidValue`]: enum.Error.html#variant.InvalidValue
+    pub fn get_as<T: DeserializeOwned>(&mut self, key: String) -> Result<Option<T>> {
+        let format = self.options.value_format;
+        match self.get(key.clone())? {
+            Some(value) => format.decode(&key, &value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Set the value of a key to `value`, serialized (see [`ClientBuilder::value_format`]).
+    pub fn set_from<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<()> {
+        let value = self.options.value_format.encode(&key, value)?;
+        self.set(key, value)
+    }
+
+    /// Get every key starting with `prefix`, with its value, in key order.
+    pub fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        let request = Request::Scan { prefix: self.key(prefix) };
+        let response = self.send(&request)?;
+        let entries = entries_response(request, response)?;
+        let key_prefix = &self.options.key_prefix;
+        Ok(entries.into_iter().map(|(key, value)| (strip(key_prefix, key), value)).collect())
+    }
+
+    /// Get every key from `start` (inclusive) to `end` (exclusive, or unbounded if `None`), with
+    /// its value, in key order.
+    pub fn scan_range(
+        &mut self,
+        start: String,
+        end: Option<String>,
+    ) -> Result<Vec<(String, String)>> {
+        let unbounded = end.is_none();
+        let end = end.map(|end| self.key(end));
+        let request = Request::ScanRange { start: self.key(start), end };
+        let response = self.send(&request)?;
+        let entries = entries_response(request, response)?;
+        let key_prefix = &self.options.key_prefix;
+        Ok(entries
+            .into_iter()
+            // Without an end, the range runs past the keys with the client's prefix.
+            .filter(|(key, _)| !unbounded || key.starts_with(key_prefix.as_str()))
+            .map(|(key, value)| (strip(key_prefix, key), value))
+            .collect())
+    }
+
+    /// Get the value of a key, either of which may be arbitrary bytes rather than UTF-8.
+    pub fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let request = Request::GetBytes { key: self.key_bytes(key) };
+        let response = self.send(&request)?;
+        bytes_value_response(request, response)
+    }
+
+    /// Set the value of a key, either of which may be arbitrary bytes rather than UTF-8.
+    pub fn set_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let (key, value) = (self.key_bytes(key), value.to_vec());
+        let request = Request::SetBytes { key, value, durability: self.options.durability };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
+
+    /// Remove a key that may be arbitrary bytes rather than UTF-8.
+    pub fn remove_bytes(&mut self, key: &[u8]) -> Result<()> {
+        let request = Request::RemoveBytes { key: self.key_bytes(key) };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
+
+    /// Get every key starting with `prefix`, with its value, in key order, any of which may be
+    /// arbitrary bytes rather than UTF-8.
+    pub fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let request = Request::ScanBytes { prefix: self.key_bytes(prefix) };
+        let response = self.send(&request)?;
+        let entries = byte_entries_response(request, response)?;
+        let key_prefix = self.options.key_prefix.len();
+        Ok(entries.into_iter().map(|(key, value)| (key[key_prefix..].to_vec(), value)).collect())
+    }
+
+    /// Check that the server is responding, returning the round-trip time of the request.
+    pub fn ping(&mut self) -> Result<Duration> {
+        let request = Request::Ping;
+        let start = Instant::now();
+        let response = self.send(&request)?;
+
+        match response {
+            Response::Pong => Ok(start.elapsed()),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Retrieve the server's statistics.
+    pub fn stats(&mut self) -> Result<Stats> {
+        let request = Request::Stats;
+        let response = self.send(&request)?;
+
+        match response {
+            Response::Stats { stats } => Ok(*stats),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Retrieve the usage and quotas of each of the server's tenants.
+    pub fn tenants(&mut self) -> Result<Vec<TenantUsage>> {
+        let request = Request::Tenants;
+        let response = self.send(&request)?;
+
+        match response {
+            Response::Tenants { tenants } => Ok(tenants),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Retrieve the server's version, engine and enabled features.
+    pub fn info(&mut self) -> Result<ServerInfo> {
+        let request = Request::Info;
+        let response = self.send(&request)?;
+
+        match response {
+            Response::Info { info } => Ok(info),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Put the server into maintenance mode for `duration`, or end maintenance mode if `duration`
+    /// is zero.
+    ///
+    /// Until it ends, writes fail with [`Error::Maintenance`].
+    ///
+    /// [`Error::Maintenance`]: enum.Error.html#variant.Maintenance
+    pub fn maintenance(&mut self, duration: Duration) -> Result<()> {
+        let request = Request::Maintenance { duration_ms: duration.as_millis() as u64 };
+        let response = self.send(&request)?;
+        ok_response(request, response)
+    }
+
+    /// Retrieve up to `limit` of the server's most frequently requested keys, with approximate
+    /// request counts.
+    pub fn hot_keys(&mut self, limit: u64) -> Result<Vec<(String, u64)>> {
+        let request = Request::HotKeys { limit };
+        let response = self.send(&request)?;
+
+        match response {
+            Response::HotKeys { keys } => Ok(keys),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Retrieve a uniform random sample of up to `count` of the server's keys, in no particular
+    /// order.
+    pub fn sample_keys(&mut self, count: u64) -> Result<Vec<String>> {
+        let request = Request::SampleKeys { count };
+        let response = self.send(&request)?;
+
+        match response {
+            Response::Keys { keys } => Ok(keys),
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Find up to `limit` keys starting with `prefix` that haven't been read or written for
+    /// `unread_for` (to the second), in key order.
+    ///
+    /// Reads are tracked approximately, so a key may be missed for having seemed to be read more
+    /// recently than it was, but a key that's been read within `unread_for` is never returned.
+    pub fn cold_keys(
+        &mut self,
+        prefix: String,
+        unread_for: Duration,
+        limit: u64,
+    ) -> Result<Vec<String>> {
+        let prefix = self.key(prefix);
+        let request = Request::ColdKeys { prefix, unread_for_secs: unread_for.as_secs(), limit };
+        let response = self.send(&request)?;
 
         match response {
-            Response::Ok => Ok(()),
-            Response::NotFound => Err(Error::KeyNotFound),
-            response => Err(Error::ProtocolError(request, response)),
+            Response::Keys { keys } => {
+                let key_prefix = &self.options.key_prefix;
+                Ok(keys.into_iter().map(|key| strip(key_prefix, key)).collect())
+            },
+            response => Err(unexpected(request, response)),
+        }
+    }
+
+    /// Count the keys starting with `prefix`, without retrieving them.
+    pub fn count_prefix(&mut self, prefix: String) -> Result<u64> {
+        let request = Request::CountPrefix { prefix: self.key(prefix) };
+        let response = self.send(&request)?;
+        count_response(request, response)
+    }
+
+    /// Remove every key starting with `prefix`, returning the number of keys removed.
+    ///
+    /// The keys are removed by the server, in a single request.
+    pub fn remove_prefix(&mut self, prefix: String) -> Result<u64> {
+        let request = Request::RemovePrefix { prefix: self.key(prefix) };
+        let response = self.send(&request)?;
+        count_response(request, response)
+    }
+
+    /// Watch for changes to keys starting with `prefix`, returning an iterator over them as
+    /// they're made.
+    ///
+    /// The watch has a connection of its own, which stays open until the [`Watch`] is dropped.
+    /// The server sends heartbeats on it while no changes are made, so that a connection which
+    /// has silently gone can be noticed (see [`ClientBuilder::heartbeat_timeout`]).
+    pub fn watch(&mut self, prefix: String) -> Result<Watch> {
+        let request = Request::Watch { prefix: self.key(prefix) };
+        let mut stream = self.request_stream(&request)?;
+        stream.set_read_timeout(Some(self.options.heartbeat_timeout))?;
+        let mut reader = BufReader::with_capacity(self.options.buffer_size, stream);
+
+        match decode_response(&mut reader)? {
+            Response::Ok => {
+                let key_prefix = self.options.key_prefix.clone();
+                Ok(Watch { request, reader, key_prefix, done: false })
+            },
+            response => Err(unexpected(request, response)),
+        }
+    }
+}
+
+impl Iterator for Watch {
+    type Item = Result<Change>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = loop {
+            match decode_response(&mut self.reader) {
+                Ok(Response::Change { mut change }) => {
+                    if change.key.starts_with(self.key_prefix.as_bytes()) {
+                        change.key.drain(..self.key_prefix.len());
+                    }
+                    break Ok(change);
+                },
+                Ok(Response::Heartbeat) => {},
+                Ok(response) => break Err(unexpected(self.request.clone(), response)),
+                Err(ref error) if is_timeout(error) => {
+                    let message = "no heartbeat from the server within the heartbeat timeout";
+                    break Err(io::Error::new(io::ErrorKind::TimedOut, message).into());
+                },
+                Err(error) => break Err(error),
+            }
+        };
+        self.done = result.is_err();
+        Some(result)
+    }
+}
+
+/// The key-value operations available both from a server (through a [`Client`]) and from an
+/// embedded engine (through an [`EmbeddedClient`]).
+///
+/// Code written against this trait can be run either way:
+///
+/// ```
+/// use kvs::{EmbeddedClient, KvsClient, MemoryKvStore, Result};
+///
+/// fn count_users<C: KvsClient>(client: &mut C) -> Result<usize> {
+///     Ok(client.scan("user:".to_owned())?.len())
+/// }
+///
+/// # fn check() -> Result<()> {
+/// let mut client = EmbeddedClient::new(MemoryKvStore::new());
+/// client.set("user:1".to_owned(), "alice".to_owned())?;
+/// assert_eq!(count_users(&mut client)?, 1);
+/// # Ok(())
+/// # }
+/// ```
+pub trait KvsClient {
+    /// Get the value of a key.
+    fn get(&mut self, key: String) -> Result<Option<String>>;
+
+    /// Set the value of a key.
+    fn set(&mut self, key: String, value: String) -> Result<()>;
+
+    /// Remove a key, failing with [`Error::KeyNotFound`] if it doesn't exist.
+    ///
+    /// [`Error::KeyNotFound`]: enum.Error.html#variant.KeyNotFound
+    fn remove(&mut self, key: String) -> Result<()>;
+
+    /// Get every key starting with `prefix`, with its value, in key order.
+    fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>>;
+
+    /// Set several keys to their values, in a single request if the client can.
+    ///
+    /// The batch isn't atomic: if this fails, some of the keys may have been set.
+    fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        for (key, value) in pairs {
+            self.set(key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl KvsClient for Client {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        Client::get(self, key)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        Client::set(self, key, value)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        Client::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        Client::scan(self, prefix)
+    }
+
+    fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        Client::set_many(self, pairs)
+    }
+}
+
+/// A [`KvsClient`] that uses an engine in the same process, rather than a server.
+///
+/// Unlike a server, this doesn't apply key rules or protect the system keyspace.
+pub struct EmbeddedClient<E> {
+    engine: E,
+}
+
+impl<E: Engine> EmbeddedClient<E> {
+    /// Construct a client using `engine`.
+    pub fn new(engine: E) -> Self {
+        EmbeddedClient { engine }
+    }
+
+    /// Take back the client's engine.
+    pub fn into_inner(self) -> E {
+        self.engine
+    }
+}
+
+impl<E: Engine> KvsClient for EmbeddedClient<E> {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.engine.get(key)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.engine.set(key, value)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.engine.remove(key)
+    }
+
+    fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        self.engine.scan(&prefix)
+    }
+}
+
+/// `key` without the client's `key_prefix` (see [`ClientBuilder::key_prefix`]).
+fn strip(key_prefix: &str, key: String) -> String {
+    match key.strip_prefix(key_prefix) {
+        Some(stripped) if !key_prefix.is_empty() => stripped.to_owned(),
+        _ => key,
+    }
+}
+
+/// Connect to the first of `addresses` that accepts a connection (see [`Client::connect`]).
+fn connect_any(addresses: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    let (ipv6, ipv4): (Vec<_>, Vec<_>) = addresses.into_iter().partition(SocketAddr::is_ipv6);
+    let mut addresses = Vec::with_capacity(ipv6.len() + ipv4.len());
+    let (mut ipv6, mut ipv4) = (ipv6.into_iter(), ipv4.into_iter());
+    loop {
+        match (ipv6.next(), ipv4.next()) {
+            (None, None) => break,
+            (first, second) => addresses.extend(first.into_iter().chain(second)),
         }
     }
+    if addresses.len() <= 1 {
+        return TcpStream::connect(&addresses[..]);
+    }
+
+    let mut timed_out = false;
+    let mut last_error = None;
+    for address in &addresses {
+        match TcpStream::connect_timeout(address, CONNECT_ATTEMPT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => {
+                timed_out |= error.kind() == io::ErrorKind::TimedOut;
+                last_error = Some(error);
+            },
+        }
+    }
+    match last_error {
+        Some(error) if !timed_out => Err(error),
+        _ => TcpStream::connect(&addresses[..]),
+    }
+}
+
+/// Interpret the response to a `Get` request.
+pub(crate) fn value_response(request: Request, response: Response) -> Result<Option<String>> {
+    match response {
+        Response::Found { value } => Ok(Some(value)),
+        Response::NotFound { .. } => Ok(None),
+        response => Err(unexpected(request, response)),
+    }
+}
+
+/// Interpret the response to a `Set` or `Remove` request.
+pub(crate) fn ok_response(request: Request, response: Response) -> Result<()> {
+    match response {
+        Response::Ok => Ok(()),
+        Response::NotFound { .. } => Err(Error::KeyNotFound),
+        response => Err(unexpected(request, response)),
+    }
+}
+
+/// Interpret the response to a `Scan` request.
+pub(crate) fn entries_response(
+    request: Request,
+    response: Response,
+) -> Result<Vec<(String, String)>> {
+    match response {
+        Response::Entries { entries } => Ok(entries),
+        response => Err(unexpected(request, response)),
+    }
+}
+
+/// Interpret the response to a `GetBytes` request.
+pub(crate) fn bytes_value_response(
+    request: Request,
+    response: Response,
+) -> Result<Option<Vec<u8>>> {
+    match response {
+        Response::FoundBytes { value } => Ok(Some(value)),
+        Response::NotFound { .. } => Ok(None),
+        response => Err(unexpected(request, response)),
+    }
+}
+
+/// Interpret the response to a `ScanBytes` request.
+pub(crate) fn byte_entries_response(
+    request: Request,
+    response: Response,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    match response {
+        Response::ByteEntries { entries } => Ok(entries),
+        response => Err(unexpected(request, response)),
+    }
+}
+
+/// Interpret the response to a `CountPrefix` or `RemovePrefix` request.
+fn count_response(request: Request, response: Response) -> Result<u64> {
+    match response {
+        Response::Count { count } => Ok(count),
+        response => Err(unexpected(request, response)),
+    }
+}
+
+/// Whether `error` is from a read timing out.
+fn is_timeout(error: &Error) -> bool {
+    let io_error = match error {
+        Error::Io(error) => error,
+        Error::Decode(DecodeError::InvalidMarkerRead(error)) => error,
+        Error::Decode(DecodeError::InvalidDataRead(error)) => error,
+        _ => return false,
+    };
+    matches!(io_error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// The error to return for a `response` that isn't a successful response to `request`.
+///
+/// Errors the server reports with a specific kind are converted to the matching [`Error`], and
+/// anything else is an [`Error::ProtocolError`].
+pub(crate) fn unexpected(request: Request, response: Response) -> Error {
+    match response {
+        Response::Err { kind: ErrorKind::IndexFull, .. } => Error::IndexFull,
+        Response::Err { kind: ErrorKind::InvalidKey, message } => Error::InvalidKey(message),
+        Response::Err { kind: ErrorKind::KeyQuotaExceeded, message } => {
+            Error::KeyQuotaExceeded(message)
+        },
+        Response::Err { kind: ErrorKind::ByteQuotaExceeded, message } => {
+            Error::ByteQuotaExceeded(message)
+        },
+        Response::Err { kind: ErrorKind::RateLimited, message } => Error::RateLimited(message),
+        Response::Err { kind: ErrorKind::TtlUnsupported, .. } => Error::TtlUnsupported,
+        Response::Err { kind: ErrorKind::Backpressure, .. } => Error::Backpressure,
+        Response::Err { kind: ErrorKind::DiskFull, .. } => Error::DiskFull,
+        Response::Err { kind: ErrorKind::Maintenance, message } => {
+            Error::Maintenance(Duration::from_millis(message.parse().unwrap_or(0)))
+        },
+        Response::Err { kind: ErrorKind::Timeout, .. } => Error::Timeout,
+        Response::Err { kind: ErrorKind::Conflict, message } => Error::Conflict(message),
+        Response::Err { kind: ErrorKind::InvalidValue, message } => Error::InvalidValue {
+            key: String::from_utf8_lossy(request.key().unwrap_or_default()).into_owned(),
+            reason: message,
+        },
+        Response::Err { kind: ErrorKind::ReadOnly, .. } => Error::ReadOnly,
+        Response::Err { kind: ErrorKind::Immutable, message } => Error::Immutable(message),
+        response => Error::protocol(request, response),
+    }
 }
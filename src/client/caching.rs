@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::str;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use super::{Client, KvsClient, Watch};
+
+/// Configures a [`CachingClient`].
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    /// The maximum number of keys to cache. The least recently read key is evicted to make room
+    /// for another.
+    pub capacity: usize,
+
+    /// How long a cached value is used before it's read from the server again, in case a change
+    /// to it was missed (e.g. because the key expired, which isn't sent to watchers until the
+    /// server's sweeper removes it).
+    pub ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            capacity: 10_000,
+            ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A [`Client`] that caches the values it gets, and watches the server for changes to them.
+///
+/// Reads of cached keys are answered without contacting the server. Changes to cached keys (made
+/// by any client) are sent to a background thread watching the client's keys, which drops them
+/// from the cache as soon as they arrive, so reads see another client's writes shortly after
+/// they're made. If the watch fails (e.g. because the server restarts), the cache is cleared and
+/// every read goes to the server.
+///
+/// ```
+/// use kvs::{CacheConfig, CachingClient, Client};
+///
+/// # fn check() -> kvs::Result<()> {
+/// let client = Client::connect("127.0.0.1:4000")?;
+/// let mut client = CachingClient::new(client, CacheConfig::default())?;
+/// let name = client.get("user:1".to_owned())?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct CachingClient {
+    client: Client,
+    cache: Arc<Mutex<Cache>>,
+}
+
+/// The values cached by a [`CachingClient`], shared with its watching thread.
+struct Cache {
+    config: CacheConfig,
+    values: HashMap<String, Cached>,
+    recency: BTreeMap<u64, String>,
+    reads: u64,
+    fetching: HashSet<String>,
+    watching: bool,
+    closed: bool,
+    hits: u64,
+    misses: u64,
+}
+
+/// A cached value (`None` if the key didn't exist), with when it was read and when it was last
+/// used.
+struct Cached {
+    value: Option<String>,
+    fetched: Instant,
+    read: u64,
+}
+
+impl CachingClient {
+    /// Wrap `client`, caching its reads according to `config`.
+    ///
+    /// This starts watching every key the client can see (i.e. under its
+    /// [key prefix](struct.ClientBuilder.html#method.key_prefix)), on a connection of its own.
+    pub fn new(mut client: Client, config: CacheConfig) -> Result<Self> {
+        let watch = client.watch(String::new())?;
+        let cache = Arc::new(Mutex::new(Cache::new(config)));
+        let watched = Arc::clone(&cache);
+        thread::spawn(move || invalidate(watch, watched));
+        Ok(CachingClient { client, cache })
+    }
+
+    /// Get the value of a key, from the cache if it's there.
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        if let Some(value) = self.lock().get(&key) {
+            return Ok(value);
+        }
+        self.lock().fetching.insert(key.clone());
+        let value = self.client.get(key.clone());
+        let mut cache = self.lock();
+        match value {
+            Ok(value) => {
+                cache.insert(key, value.clone());
+                Ok(value)
+            },
+            Err(error) => {
+                cache.fetching.remove(&key);
+                Err(error)
+            },
+        }
+    }
+
+    /// Set the value of a key.
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.lock().invalidate(&key);
+        self.client.set(key, value)
+    }
+
+    /// Remove a key, failing with [`Error::KeyNotFound`] if it doesn't exist.
+    ///
+    /// [`Error::KeyNotFound`]: enum.Error.html#variant.KeyNotFound
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.lock().invalidate(&key);
+        self.client.remove(key)
+    }
+
+    /// The number of reads answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+
+    /// The number of reads sent to the server.
+    pub fn misses(&self) -> u64 {
+        self.lock().misses
+    }
+
+    /// Whether the cache is still being kept up to date with changes on the server.
+    pub fn is_watching(&self) -> bool {
+        self.lock().watching
+    }
+
+    /// Access the wrapped client, e.g. to make requests that aren't cached.
+    ///
+    /// Writes made through it are still seen through the watch, but not straight away.
+    pub fn client(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock().expect("Cache lock poisoned")
+    }
+}
+
+impl Drop for CachingClient {
+    /// Stop the watching thread. Its connection is closed once the next change arrives on it.
+    fn drop(&mut self) {
+        self.lock().closed = true;
+    }
+}
+
+impl KvsClient for CachingClient {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        CachingClient::get(self, key)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        CachingClient::set(self, key, value)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        CachingClient::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        self.client.scan(prefix)
+    }
+}
+
+impl Cache {
+    fn new(config: CacheConfig) -> Self {
+        Cache {
+            config,
+            values: HashMap::new(),
+            recency: BTreeMap::new(),
+            reads: 0,
+            fetching: HashSet::new(),
+            watching: true,
+            closed: false,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// The cached value of `key`, if it's cached and hasn't outlived the cache's TTL.
+    fn get(&mut self, key: &str) -> Option<Option<String>> {
+        let fresh = match self.values.get(key) {
+            Some(cached) => cached.fetched.elapsed() < self.config.ttl,
+            None => false,
+        };
+        if !fresh {
+            self.remove(key);
+            self.misses += 1;
+            return None;
+        }
+        self.reads += 1;
+        let cached = self.values.get_mut(key).expect("Key was just found");
+        self.recency.remove(&cached.read);
+        cached.read = self.reads;
+        self.recency.insert(self.reads, key.to_owned());
+        self.hits += 1;
+        Some(cached.value.clone())
+    }
+
+    /// Cache a value fetched from the server, unless the key changed while it was being fetched
+    /// (in which case the value may already be stale), evicting the least recently read key if
+    /// the cache is full.
+    fn insert(&mut self, key: String, value: Option<String>) {
+        if !self.fetching.remove(&key) || !self.watching || self.config.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.values.len() >= self.config.capacity {
+            let (_, oldest) = self.recency.pop_first().expect("Cache isn't empty");
+            self.values.remove(&oldest);
+        }
+        self.reads += 1;
+        self.recency.insert(self.reads, key.clone());
+        self.values.insert(key, Cached { value, fetched: Instant::now(), read: self.reads });
+    }
+
+    /// Forget the value of `key`, including one that's being fetched, because it's changed.
+    fn invalidate(&mut self, key: &str) {
+        self.fetching.remove(key);
+        self.remove(key);
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(cached) = self.values.remove(key) {
+            self.recency.remove(&cached.read);
+        }
+    }
+}
+
+/// Drop each key changed on the server from `cache`, until the client is dropped or the watch
+/// fails, in which case nothing more is cached.
+fn invalidate(watch: Watch, cache: Arc<Mutex<Cache>>) {
+    for change in watch {
+        let mut cache = cache.lock().expect("Cache lock poisoned");
+        if cache.closed {
+            return;
+        }
+        match change {
+            // Only keys that are strings can be cached.
+            Ok(change) => {
+                if let Ok(key) = str::from_utf8(&change.key) {
+                    cache.invalidate(key);
+                }
+            },
+            Err(_) => break,
+        }
+    }
+    let mut cache = cache.lock().expect("Cache lock poisoned");
+    cache.watching = false;
+    cache.values.clear();
+    cache.recency.clear();
+    cache.fetching.clear();
+}
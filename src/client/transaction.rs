@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use crate::error::Result;
+use crate::protocol::{Request, Response};
+use super::{ok_response, unexpected, value_response, Client};
+
+/// A transaction on a server, started with [`Client::begin_transaction`].
+///
+/// Reads are sent to the server, and see the store as it was when the transaction began (along
+/// with the transaction's own writes). Writes are buffered by the client and sent together by
+/// [`commit`]. If a key the transaction reads or writes is written by anyone else after it
+/// began, the read or commit fails with [`Error::Conflict`] and the transaction can be retried
+/// from the start. Dropping a transaction rolls it back.
+///
+/// ```
+/// # use kvs::{Client, Result};
+/// # fn check() -> Result<()> {
+/// let mut client = Client::connect("127.0.0.1:4000")?;
+/// let mut transaction = client.begin_transaction()?;
+/// let balance = transaction.get("balance:a".to_owned())?;
+/// transaction.set("balance:a".to_owned(), "5".to_owned());
+/// transaction.set("balance:b".to_owned(), "5".to_owned());
+/// transaction.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`commit`]: #method.commit
+/// [`Error::Conflict`]: enum.Error.html#variant.Conflict
+pub struct ClientTransaction<'a> {
+    client: &'a mut Client,
+    id: u64,
+    writes: BTreeMap<String, Option<String>>,
+    open: bool,
+}
+
+impl Client {
+    /// Begin a transaction on the server.
+    pub fn begin_transaction(&mut self) -> Result<ClientTransaction<'_>> {
+        let request = Request::Begin;
+        match self.send(&request)? {
+            Response::Transaction { id } => {
+                Ok(ClientTransaction { client: self, id, writes: BTreeMap::new(), open: true })
+            },
+            response => Err(unexpected(request, response)),
+        }
+    }
+}
+
+impl ClientTransaction<'_> {
+    /// Get the value of a key, as of when the transaction began or as the transaction has since
+    /// set it.
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        let key = self.client.key(key);
+        if let Some(value) = self.writes.get(&key) {
+            return Ok(value.clone());
+        }
+        let request = Request::TransactionGet { id: self.id, key };
+        let response = self.client.send(&request)?;
+        value_response(request, response)
+    }
+
+    /// Set a key to a given value when the transaction commits.
+    pub fn set(&mut self, key: String, value: String) {
+        self.writes.insert(self.client.key(key), Some(value));
+    }
+
+    /// Remove a key (if it exists) when the transaction commits.
+    pub fn remove(&mut self, key: String) {
+        self.writes.insert(self.client.key(key), None);
+    }
+
+    /// Send the transaction's writes to the server, failing with [`Error::Conflict`] (and
+    /// applying none of them) if any of their keys have been written since the transaction
+    /// began.
+    ///
+    /// [`Error::Conflict`]: enum.Error.html#variant.Conflict
+    pub fn commit(mut self) -> Result<()> {
+        self.open = false;
+        let writes = std::mem::take(&mut self.writes).into_iter().collect();
+        let request = Request::Commit { id: self.id, writes };
+        let response = self.client.send(&request)?;
+        ok_response(request, response)
+    }
+
+    /// Discard the transaction's writes, and tell the server it's over.
+    pub fn rollback(mut self) -> Result<()> {
+        self.open = false;
+        let request = Request::Rollback { id: self.id };
+        let response = self.client.send(&request)?;
+        ok_response(request, response)
+    }
+}
+
+impl Drop for ClientTransaction<'_> {
+    /// Roll back the transaction if it's still open, ignoring any error (the server rolls back
+    /// abandoned transactions eventually anyway).
+    fn drop(&mut self) {
+        if self.open {
+            let _ = self.client.send(&Request::Rollback { id: self.id });
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A source of wall-clock time, used to decide when keys expire.
+///
+/// Expiry times are stored as absolute wall-clock timestamps so that they survive restarts. Since
+/// wall clocks can jump, stores never let their view of the time go backwards: see
+/// [`KvStore`](struct.KvStore.html) for details.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The current time, in milliseconds since the UNIX epoch.
+    fn now_millis(&self) -> u64;
+}
+
+/// A [`Clock`] that reads the system's wall clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// A [`Clock`] that only moves when it's told to, for simulating time in tests.
+///
+/// Clones share the same time, so a clone can be given to a store and the original used to move
+/// the store's clock.
+///
+/// ```
+/// use std::time::Duration;
+/// use kvs::{Clock, ManualClock};
+///
+/// let clock = ManualClock::new(1_000);
+/// clock.advance(Duration::from_secs(1));
+/// assert_eq!(clock.now_millis(), 2_000);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    millis: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Construct a clock reading `millis` milliseconds since the UNIX epoch.
+    pub fn new(millis: u64) -> Self {
+        ManualClock {
+            millis: Arc::new(AtomicU64::new(millis)),
+        }
+    }
+
+    /// Move the clock to `millis` milliseconds since the UNIX epoch, which may be in the past.
+    pub fn set(&self, millis: u64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+
+    /// Move the clock forwards by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        self.millis.fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
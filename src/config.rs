@@ -0,0 +1,178 @@
+use serde::Deserialize;
+use slog::Level;
+use std::collections::BTreeMap;
+use std::env::{self, VarError};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::engine::{SyncPolicy, BUCKET_SEPARATOR};
+use crate::error::{Error, Result};
+
+/// The environment variable overriding the `addr` setting.
+pub const ENV_ADDR: &str = "KVS_ADDR";
+
+/// The environment variable overriding the `engine` setting.
+pub const ENV_ENGINE: &str = "KVS_ENGINE";
+
+/// The environment variable overriding the `data_dir` setting.
+pub const ENV_DATA_DIR: &str = "KVS_DATA_DIR";
+
+/// The environment variable overriding the `log_level` setting.
+pub const ENV_LOG_LEVEL: &str = "KVS_LOG_LEVEL";
+
+/// The environment variable overriding the `sync_policy` setting.
+pub const ENV_SYNC_POLICY: &str = "KVS_SYNC_POLICY";
+
+/// A configuration, as loaded from a [TOML] config file and the environment.
+///
+/// Each setting is taken from the first of these that sets it: command line flags, environment
+/// variables (see [`with_env`](#method.with_env)), the config file, and the defaults. Only the
+/// server reads a config file; the client only reads the address from the environment.
+///
+/// ```toml
+/// addr = "127.0.0.1:4001"
+/// engine = "kvs"
+/// data_dir = "/var/lib/kvs"
+/// log_level = "info"
+/// sync_policy = "100ms"
+///
+/// # Keys starting with `cache/` are kept in memory.
+/// [buckets.cache]
+/// engine = "memory"
+///
+/// # Keys starting with `blobs/` can't be changed once they're set.
+/// [buckets.blobs]
+/// engine = "kvs"
+/// immutable = true
+/// ```
+///
+/// [TOML]: https://github.com/toml-lang/toml
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The address to listen on.
+    pub addr: Option<String>,
+
+    /// Whether to listen on every interface over both IPv6 and IPv4, on the port of `addr`.
+    #[serde(default)]
+    pub dual_stack: bool,
+
+    /// The engine to store keys in, unless their bucket has its own engine.
+    pub engine: Option<String>,
+
+    /// The directory to store data in (the current directory by default).
+    pub data_dir: Option<PathBuf>,
+
+    /// The least severe level of log messages to write (e.g. `"info"` or `"debug"`).
+    pub log_level: Option<String>,
+
+    /// When the kvs engine syncs writes to disk (e.g. `"always"` or `"100ms"`, see
+    /// [`SyncPolicy`]).
+    ///
+    /// [`SyncPolicy`]: enum.SyncPolicy.html
+    pub sync_policy: Option<String>,
+
+    /// The buckets with their own engine, by name.
+    #[serde(default)]
+    pub buckets: BTreeMap<String, BucketConfig>,
+}
+
+/// The configuration of a single bucket.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    /// The engine to store the bucket's keys in.
+    pub engine: String,
+
+    /// Whether the bucket's keys are immutable once they're set (see [`WriteOnce`]).
+    ///
+    /// [`WriteOnce`]: struct.WriteOnce.html
+    #[serde(default)]
+    pub immutable: bool,
+}
+
+impl Config {
+    /// Load a config file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+
+        for bucket in config.buckets.keys() {
+            if bucket.is_empty() || bucket.contains(BUCKET_SEPARATOR) {
+                return Err(Error::Config(format!("invalid bucket name {:?}", bucket)));
+            }
+        }
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Override settings with those set by environment variables: [`ENV_ADDR`],
+    /// [`ENV_ENGINE`], [`ENV_DATA_DIR`], [`ENV_LOG_LEVEL`] and [`ENV_SYNC_POLICY`]. Empty
+    /// variables are ignored.
+    ///
+    /// [`ENV_ADDR`]: constant.ENV_ADDR.html
+    /// [`ENV_ENGINE`]: constant.ENV_ENGINE.html
+    /// [`ENV_DATA_DIR`]: constant.ENV_DATA_DIR.html
+    /// [`ENV_LOG_LEVEL`]: constant.ENV_LOG_LEVEL.html
+    /// [`ENV_SYNC_POLICY`]: constant.ENV_SYNC_POLICY.html
+    pub fn with_env(mut self) -> Result<Self> {
+        if let Some(addr) = env_var(ENV_ADDR)? {
+            self.addr = Some(addr);
+        }
+        if let Some(engine) = env_var(ENV_ENGINE)? {
+            self.engine = Some(engine);
+        }
+        if let Some(data_dir) = env_var(ENV_DATA_DIR)? {
+            self.data_dir = Some(PathBuf::from(data_dir));
+        }
+        if let Some(log_level) = env_var(ENV_LOG_LEVEL)? {
+            self.log_level = Some(log_level);
+        }
+        if let Some(sync_policy) = env_var(ENV_SYNC_POLICY)? {
+            self.sync_policy = Some(sync_policy);
+        }
+        self.check()?;
+        Ok(self)
+    }
+
+    /// The log level set by [`log_level`](#structfield.log_level), if any.
+    pub fn level(&self) -> Result<Option<Level>> {
+        let level = match &self.log_level {
+            Some(level) => level,
+            None => return Ok(None),
+        };
+        Ok(Some(match level.to_ascii_lowercase().as_str() {
+            "critical" => Level::Critical,
+            "error" => Level::Error,
+            "warning" | "warn" => Level::Warning,
+            "info" => Level::Info,
+            "debug" => Level::Debug,
+            "trace" => Level::Trace,
+            _ => return Err(Error::Config(format!("invalid log level {:?}", level))),
+        }))
+    }
+
+    /// The sync policy set by [`sync_policy`](#structfield.sync_policy), if any.
+    pub fn sync_policy(&self) -> Result<Option<SyncPolicy>> {
+        self.sync_policy.as_deref().map(str::parse).transpose()
+    }
+
+    /// Check that the settings which need parsing are valid.
+    fn check(&self) -> Result<()> {
+        self.level()?;
+        self.sync_policy()?;
+        Ok(())
+    }
+}
+
+/// The value of the environment variable `name`, unless it's unset or empty.
+fn env_var(name: &str) -> Result<Option<String>> {
+    match env::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(Error::Config(format!("{} isn't valid UTF-8", name))),
+    }
+}
@@ -1,10 +1,187 @@
+mod buckets;
 mod kvs;
+mod memory;
+mod sample;
+mod shared;
 mod sled;
+mod system;
+mod transaction;
+mod write_once;
 
-use crate::error::Result;
+use std::convert::TryInto;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-pub use self::kvs::Store as KvStore;
+use crate::error::{Error, Result};
+use crate::protocol::NotFoundReason;
+use crate::stats::EngineStats;
+
+pub use self::kvs::{Builder as KvStoreBuilder, Store as KvStore, FORMAT_VERSION};
+pub use self::kvs::{BackgroundJobs, Erasure, Follower as KvFollower, JobSlot, WriteStall};
+pub use self::kvs::{CompactionDecision, CompactionFilter, SyncPoint, SyncPolicy};
+pub use self::kvs::{CheckReport, CorruptRange, FileReport, ManifestReport, Repair, SequenceGap};
+pub use self::buckets::{Buckets, BUCKET_SEPARATOR};
+pub use self::memory::Store as MemoryKvStore;
+pub use self::sample::KeySample;
+pub use self::shared::Shared as SharedEngine;
 pub use self::sled::Db as SledKvStore;
+pub use self::system::{SystemKeys, SYSTEM_KEY_PREFIX};
+pub use self::transaction::Transaction;
+pub use self::write_once::WriteOnce;
+pub(crate) use self::transaction::WriteLog;
+
+/// The names of the persistent engines (`"kvs"` and `"sled"`) with data files in `dir`.
+///
+/// This looks at the data files themselves rather than the `engine` marker written by
+/// `kvs-server`, so it can tell whether a directory already holds another engine's data.
+pub fn detect_engines(dir: &Path) -> Result<Vec<&'static str>> {
+    let mut engines = Vec::new();
+    if !dir.is_dir() {
+        return Ok(engines);
+    }
+    if self::kvs::contains_store(dir)? {
+        engines.push("kvs");
+    }
+    if self::sled::contains_db(dir) {
+        engines.push("sled");
+    }
+    Ok(engines)
+}
+
+/// The version of a value, for [conditional gets].
+///
+/// Versions are hashes of values (like HTTP ETags) rather than counts of writes, so a client can
+/// work out the version of a value it already has, and a key set back to an earlier value has
+/// that value's version again.
+///
+/// A version is the first 8 bytes of the value's BLAKE3 hash, read as a little-endian number, so
+/// it's the same across builds, platforms and implementations in other languages.
+///
+/// ```
+/// assert_eq!(kvs::value_version("hello"), 0x9282_86b3_3d16_8fea);
+/// ```
+///
+/// [conditional gets]: trait.KvsEngine.html#method.get_if_modified
+pub fn value_version(value: &str) -> u64 {
+    let hash = blake3::hash(value.as_bytes());
+    u64::from_le_bytes(hash.as_bytes()[..8].try_into().expect("Hashes are 32 bytes"))
+}
+
+/// The prefix of the keys that [content-addressed puts] store values under.
+///
+/// Configuring this prefix's bucket as immutable (see [`WriteOnce`]) stops stored content from
+/// being changed under its key.
+///
+/// [content-addressed puts]: enum.Request.html#variant.PutCas
+/// [`WriteOnce`]: struct.WriteOnce.html
+pub const CONTENT_KEY_PREFIX: &str = "cas/";
+
+/// The key a [content-addressed put] stores `value` under: [`CONTENT_KEY_PREFIX`] followed by the
+/// value's BLAKE3 hash in hex.
+///
+/// Unlike [`value_version`], this is the whole hash rather than 64 bits of it, so storing the same
+/// value twice always gives the same key, and different values never share one.
+///
+/// ```
+/// let key = kvs::content_key("hello");
+/// assert!(key.starts_with(kvs::CONTENT_KEY_PREFIX));
+/// assert_eq!(key, kvs::content_key("hello"));
+/// assert_ne!(key, kvs::content_key("world"));
+/// ```
+///
+/// [content-addressed put]: enum.Request.html#variant.PutCas
+/// [`CONTENT_KEY_PREFIX`]: constant.CONTENT_KEY_PREFIX.html
+/// [`value_version`]: fn.value_version.html
+pub fn content_key(value: &str) -> String {
+    format!("{}{}", CONTENT_KEY_PREFIX, blake3::hash(value.as_bytes()).to_hex())
+}
+
+/// The result of a [conditional get].
+///
+/// [conditional get]: trait.KvsEngine.html#method.get_if_modified
+#[derive(Clone, Debug, PartialEq)]
+pub enum IfModified {
+    /// The key's value still has the known version.
+    NotModified,
+
+    /// The key's value has a different version, or is `None` if the key isn't in the store.
+    Modified(Option<String>),
+}
+
+/// How up to date an engine's reads are with its writes (see [`KvsEngine::consistency`]).
+///
+/// Variants are ordered from the strongest guarantee to the weakest, so the guarantee of several
+/// engines together is the greatest of theirs.
+///
+/// [`KvsEngine::consistency`]: trait.KvsEngine.html#method.consistency
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Consistency {
+    /// Every operation takes effect at a single point between its call and its return, so a read
+    /// sees every write that returned before the read was called, from any thread.
+    Linearizable,
+
+    /// Reads may miss writes made up to this long ago, but never see writes go backwards.
+    Stale(Duration),
+}
+
+/// Whether `key` is from `start` (inclusive) to `end` (exclusive, or unbounded if `None`).
+pub(crate) fn in_range(key: &str, start: &str, end: Option<&str>) -> bool {
+    key >= start && end.is_none_or(|end| key < end)
+}
+
+/// `key` as a string, failing with [`Error::InvalidKey`] if it isn't UTF-8.
+pub(crate) fn utf8_key(key: Vec<u8>) -> Result<String> {
+    String::from_utf8(key).map_err(|_| Error::InvalidKey("key is not valid UTF-8".to_owned()))
+}
+
+/// The value of `key` as a string, failing with [`Error::InvalidValue`] if it isn't UTF-8.
+pub(crate) fn utf8_value(key: &str, value: Vec<u8>) -> Result<String> {
+    String::from_utf8(value).map_err(|_| Error::InvalidValue {
+        key: key.to_owned(),
+        reason: "not valid UTF-8 (read it as bytes)".to_owned(),
+    })
+}
+
+/// An entry as strings, failing if its key or value isn't UTF-8.
+pub(crate) fn utf8_entry(key: &[u8], value: &[u8]) -> Result<(String, String)> {
+    let key = utf8_key(key.to_vec())?;
+    let value = utf8_value(&key, value.to_vec())?;
+    Ok((key, value))
+}
+
+/// Entries as strings, failing if any key or value isn't UTF-8.
+pub(crate) fn utf8_entries(entries: Vec<(Vec<u8>, Vec<u8>)>) -> Result<Vec<(String, String)>> {
+    entries
+        .into_iter()
+        .map(|(key, value)| {
+            let key = utf8_key(key)?;
+            let value = utf8_value(&key, value)?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// `key` as a string, replacing any bytes that aren't UTF-8, e.g. to report a key that was
+/// removed.
+pub(crate) fn lossy_key(key: Vec<u8>) -> String {
+    String::from_utf8(key)
+        .unwrap_or_else(|error| String::from_utf8_lossy(error.as_bytes()).into_owned())
+}
+
+/// The value of `key` (currently `value`) after adding `delta`, treating a missing value as `0`.
+///
+/// Fails with [`Error::InvalidValue`] if the value isn't an integer or the result would overflow.
+pub(crate) fn incremented(key: &str, value: Option<&str>, delta: i64) -> Result<i64> {
+    let invalid = |reason: &str| Error::InvalidValue {
+        key: key.to_owned(),
+        reason: reason.to_owned(),
+    };
+    let value = match value {
+        Some(value) => value.parse::<i64>().map_err(|_| invalid("not an integer"))?,
+        None => 0,
+    };
+    value.checked_add(delta).ok_or_else(|| invalid("increment would overflow"))
+}
 
 /// Defines the storage interface used from [`server::Server`].
 ///
@@ -13,9 +190,403 @@ pub trait Engine {
     /// Get the value of a key.
     fn get(&mut self, key: String) -> Result<Option<String>>;
 
+    /// Get the value of a key, or why it wasn't found, if the engine can tell cheaply.
+    ///
+    /// The default implementation calls [`get`](#method.get), so never gives a reason.
+    fn lookup(
+        &mut self,
+        key: String,
+    ) -> Result<std::result::Result<String, Option<NotFoundReason>>> {
+        Ok(self.get(key)?.ok_or(None))
+    }
+
+    /// Get the value of a key only if its version (see [`value_version`]) isn't `known_version`,
+    /// e.g. to poll a large value without fetching it again when it hasn't changed.
+    ///
+    /// [`value_version`]: fn.value_version.html
+    fn get_if_modified(&mut self, key: String, known_version: u64) -> Result<IfModified> {
+        let value = self.get(key)?;
+        if value.as_deref().map(value_version) == Some(known_version) {
+            return Ok(IfModified::NotModified);
+        }
+        Ok(IfModified::Modified(value))
+    }
+
     /// Set a key to a given value.
     fn set(&mut self, key: String, value: String) -> Result<()>;
 
     /// Remove a key (and its value).
     fn remove(&mut self, key: String) -> Result<()>;
+
+    /// Remove a key (and its value), even if it's immutable (see [`WriteOnce`]).
+    ///
+    /// The default implementation is a plain [`remove`](#method.remove), for engines without
+    /// immutable keys.
+    ///
+    /// [`WriteOnce`]: struct.WriteOnce.html
+    fn force_remove(&mut self, key: String) -> Result<()> {
+        self.remove(key)
+    }
+
+    /// Get the value of a key that may hold arbitrary bytes.
+    ///
+    /// The string methods are convenience wrappers for keys and values that are UTF-8, and read
+    /// and write the same entries as the byte methods. Reading a value that isn't UTF-8 as a
+    /// string fails with [`Error::InvalidValue`].
+    ///
+    /// The default implementation is for engines that only store strings, and fails with
+    /// [`Error::InvalidKey`] if `key` isn't UTF-8.
+    ///
+    /// [`Error::InvalidValue`]: enum.Error.html#variant.InvalidValue
+    /// [`Error::InvalidKey`]: enum.Error.html#variant.InvalidKey
+    fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = utf8_key(key.to_vec())?;
+        Ok(self.get(key)?.map(String::into_bytes))
+    }
+
+    /// Set a key to a value, either of which may be arbitrary bytes (see
+    /// [`get_bytes`](#method.get_bytes)).
+    ///
+    /// The default implementation is for engines that only store strings, and fails with
+    /// [`Error::InvalidKey`] or [`Error::InvalidValue`] if the key or value isn't UTF-8.
+    ///
+    /// [`Error::InvalidKey`]: enum.Error.html#variant.InvalidKey
+    /// [`Error::InvalidValue`]: enum.Error.html#variant.InvalidValue
+    fn set_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let key = utf8_key(key.to_vec())?;
+        let value = utf8_value(&key, value.to_vec())?;
+        self.set(key, value)
+    }
+
+    /// Remove a key that may be arbitrary bytes (see [`get_bytes`](#method.get_bytes)).
+    ///
+    /// The default implementation is for engines that only store strings, and fails with
+    /// [`Error::InvalidKey`] if `key` isn't UTF-8.
+    ///
+    /// [`Error::InvalidKey`]: enum.Error.html#variant.InvalidKey
+    fn remove_bytes(&mut self, key: &[u8]) -> Result<()> {
+        let key = utf8_key(key.to_vec())?;
+        self.remove(key)
+    }
+
+    /// Get the values of several keys, in the same order as `keys`.
+    ///
+    /// The default implementation gets each key in turn.
+    fn get_many(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        keys.into_iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Set several keys to their values, in order.
+    ///
+    /// Batches aren't atomic: if a key can't be set, the keys before it stay set. The default
+    /// implementation sets each key in turn, so engines that sync every write should override it
+    /// to sync once for the whole batch.
+    fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        for (key, value) in pairs {
+            self.set(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Remove several keys, skipping any that aren't in the store, returning the keys removed.
+    ///
+    /// Like [`set_many`](#method.set_many), batches aren't atomic, and the default implementation
+    /// removes each key in turn.
+    fn remove_many(&mut self, keys: Vec<String>) -> Result<Vec<String>> {
+        let mut removed = Vec::new();
+        for key in keys {
+            match self.remove(key.clone()) {
+                Ok(()) => removed.push(key),
+                Err(Error::KeyNotFound) => {},
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Set `key` to `new` if its value is `expected` (or if it isn't in the store, for an
+    /// `expected` of `None`), returning whether it was set.
+    ///
+    /// The default implementation gets the key and then sets it. Nothing else can write to the
+    /// engine in between, since both take `&mut self`; engines shared between threads (like
+    /// [`SharedEngine`]) hold their lock throughout.
+    ///
+    /// [`SharedEngine`]: struct.SharedEngine.html
+    fn compare_and_swap(
+        &mut self,
+        key: String,
+        expected: Option<String>,
+        new: String,
+    ) -> Result<bool> {
+        if self.get(key.clone())? != expected {
+            return Ok(false);
+        }
+        self.set(key, new)?;
+        Ok(true)
+    }
+
+    /// Add `delta` to the integer value of `key`, treating a key that isn't in the store as `0`,
+    /// and return the new value.
+    ///
+    /// Fails with [`Error::InvalidValue`] if the value isn't an integer or the result would
+    /// overflow. Like [`compare_and_swap`](#method.compare_and_swap), the default implementation
+    /// gets and then sets the key.
+    ///
+    /// [`Error::InvalidValue`]: enum.Error.html#variant.InvalidValue
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        let value = incremented(&key, self.get(key.clone())?.as_deref(), delta)?;
+        self.set(key, value.to_string())?;
+        Ok(value)
+    }
+
+    /// Move the value of key `from` to key `to`, so that `from` is no longer in the store.
+    ///
+    /// Returns `false` (and changes nothing) if `to` is already in the store and `overwrite` is
+    /// false, and fails with [`Error::KeyNotFound`] if `from` isn't in the store. Renaming a key
+    /// to itself changes nothing.
+    ///
+    /// The default implementation gets `from`, sets `to` and removes `from`, so a crash part way
+    /// through can leave both keys set. Engines with a log (like [`KvStore`]) write a rename as a
+    /// single record instead, and also keep the key's time-to-live.
+    ///
+    /// [`Error::KeyNotFound`]: enum.Error.html#variant.KeyNotFound
+    /// [`KvStore`]: struct.KvStore.html
+    fn rename(&mut self, from: String, to: String, overwrite: bool) -> Result<bool> {
+        let value = self.get(from.clone())?.ok_or(Error::KeyNotFound)?;
+        if from == to {
+            return Ok(true);
+        }
+        if !overwrite && self.get(to.clone())?.is_some() {
+            return Ok(false);
+        }
+        self.set(to, value)?;
+        self.remove(from)?;
+        Ok(true)
+    }
+
+    /// Make every write so far durable, e.g. by syncing the engine's log to disk.
+    ///
+    /// The default implementation does nothing, for engines that make each write durable as
+    /// it's made, or that aren't persistent.
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// A point that every write so far can be made durable at with [`SyncPoint::wait`], without
+    /// access to the engine.
+    ///
+    /// This lets an engine shared between threads (see [`SharedEngine`]) sync without holding its
+    /// lock, so writes carry on meanwhile and threads syncing at the same time share a single sync
+    /// (a group commit).
+    ///
+    /// The default implementation returns `None`, for engines that can only be synced with
+    /// [`sync`](#method.sync).
+    ///
+    /// [`SyncPoint::wait`]: struct.SyncPoint.html#method.wait
+    /// [`SharedEngine`]: struct.SharedEngine.html
+    fn sync_point(&mut self) -> Option<SyncPoint> {
+        None
+    }
+
+    /// Get every key starting with `prefix`, with its value, in key order.
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>>;
+
+    /// Get every key starting with `prefix`, with its value, in key order, where keys and values
+    /// may be arbitrary bytes (see [`get_bytes`](#method.get_bytes)).
+    ///
+    /// The default implementation is a [`scan`](#method.scan), for engines that only store
+    /// strings, so `prefix` must be UTF-8.
+    fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let prefix = utf8_key(prefix.to_vec())?;
+        let entries = self.scan(&prefix)?;
+        Ok(entries.into_iter().map(|(key, value)| (key.into_bytes(), value.into_bytes())).collect())
+    }
+
+    /// Get every key starting with `prefix`, with its value, in key order, giving up with
+    /// [`Error::Timeout`] if the scan isn't finished by `deadline`.
+    ///
+    /// The default implementation only checks the deadline once the scan is finished.
+    ///
+    /// [`Error::Timeout`]: enum.Error.html#variant.Timeout
+    fn scan_until(&mut self, prefix: &str, deadline: Instant) -> Result<Vec<(String, String)>> {
+        let entries = self.scan(prefix)?;
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout);
+        }
+        Ok(entries)
+    }
+
+    /// Get every key from `start` (inclusive) to `end` (exclusive, or unbounded if `None`), with
+    /// its value, in key order.
+    ///
+    /// The default implementation scans every entry, so reads every value.
+    fn scan_range(&mut self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>> {
+        let mut entries = self.scan("")?;
+        entries.retain(|(key, _)| in_range(key, start, end));
+        Ok(entries)
+    }
+
+    /// Count the keys starting with `prefix`, excluding system keys.
+    ///
+    /// The default implementation scans the matching entries, so reads every value.
+    fn count_prefix(&mut self, prefix: &str) -> Result<u64> {
+        let entries = self.scan(prefix)?;
+        Ok(entries.iter().filter(|(key, _)| !key.starts_with(SYSTEM_KEY_PREFIX)).count() as u64)
+    }
+
+    /// Remove every key starting with `prefix`, except system keys, returning the keys removed in
+    /// key order.
+    ///
+    /// The default implementation scans the matching entries, so reads every value, then removes
+    /// them one at a time.
+    fn remove_prefix(&mut self, prefix: &str) -> Result<Vec<String>> {
+        let mut removed = Vec::new();
+        for (key, _) in self.scan(prefix)? {
+            if !key.starts_with(SYSTEM_KEY_PREFIX) {
+                self.remove(key.clone())?;
+                removed.push(key);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Find up to `limit` keys starting with `prefix`, excluding system keys, that haven't been
+    /// read or written for `unread_for`, in key order.
+    ///
+    /// Cold keys are candidates to be moved to cheaper storage. The default implementation doesn't
+    /// track reads, so finds none.
+    fn cold_keys(
+        &mut self,
+        _prefix: &str,
+        _unread_for: Duration,
+        _limit: usize,
+    ) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Set a key to a given value, which expires after `ttl`.
+    ///
+    /// Expired keys must no longer be returned, but may continue to use storage until they're
+    /// removed by [`sweep_expired`](#method.sweep_expired). A `get` or `remove` of a key that has
+    /// expired but hasn't been swept must behave as if the key was already removed (returning
+    /// `None` or failing with [`Error::KeyNotFound`]), and should count it as expired in the
+    /// engine's stats. The default implementation fails with [`Error::TtlUnsupported`].
+    ///
+    /// [`Error::KeyNotFound`]: enum.Error.html#variant.KeyNotFound
+    ///
+    /// [`Error::TtlUnsupported`]: enum.Error.html#variant.TtlUnsupported
+    fn set_with_ttl(&mut self, _key: String, _value: String, _ttl: Duration) -> Result<()> {
+        Err(Error::TtlUnsupported)
+    }
+
+    /// Get the time left before a key expires, or `None` if it doesn't expire, failing with
+    /// [`Error::KeyNotFound`] if it isn't in the store.
+    ///
+    /// The default implementation is for engines without TTLs, whose keys never expire.
+    ///
+    /// [`Error::KeyNotFound`]: enum.Error.html#variant.KeyNotFound
+    fn ttl(&mut self, key: String) -> Result<Option<Duration>> {
+        self.get(key)?.ok_or(Error::KeyNotFound)?;
+        Ok(None)
+    }
+
+    /// Make a key expire after `ttl`, replacing any time-to-live it had, failing with
+    /// [`Error::KeyNotFound`] if it isn't in the store. The default implementation fails with
+    /// [`Error::TtlUnsupported`].
+    ///
+    /// [`Error::KeyNotFound`]: enum.Error.html#variant.KeyNotFound
+    /// [`Error::TtlUnsupported`]: enum.Error.html#variant.TtlUnsupported
+    fn expire(&mut self, _key: String, _ttl: Duration) -> Result<()> {
+        Err(Error::TtlUnsupported)
+    }
+
+    /// Stop a key from expiring, failing with [`Error::KeyNotFound`] if it isn't in the store.
+    ///
+    /// The default implementation is for engines without TTLs, so only checks that the key
+    /// exists.
+    ///
+    /// [`Error::KeyNotFound`]: enum.Error.html#variant.KeyNotFound
+    fn persist(&mut self, key: String) -> Result<()> {
+        self.get(key)?.ok_or(Error::KeyNotFound)?;
+        Ok(())
+    }
+
+    /// Remove up to `limit` expired keys, returning the keys that were removed.
+    ///
+    /// The default implementation removes nothing.
+    fn sweep_expired(&mut self, _limit: usize) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Re-read up to `limit` stored entries, continuing from where the last scrub stopped, and
+    /// verify that they're intact, returning the keys of any that aren't.
+    ///
+    /// Scrubbing finds silent disk corruption before the affected keys are next read. Each pass
+    /// over every entry should be counted in the engine's stats. The default implementation
+    /// verifies nothing.
+    fn scrub(&mut self, _limit: usize) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// The engine's name (e.g. `"kvs"` or `"sled"`), as reported by [`Request::Info`].
+    ///
+    /// The default implementation returns `"unknown"`.
+    ///
+    /// [`Request::Info`]: enum.Request.html#variant.Info
+    fn name(&self) -> &str {
+        "unknown"
+    }
+
+    /// How up to date the engine's reads are with its writes.
+    ///
+    /// The default implementation returns [`Consistency::Linearizable`], for engines that apply
+    /// each write before it returns.
+    ///
+    /// [`Consistency::Linearizable`]: enum.Consistency.html#variant.Linearizable
+    fn consistency(&self) -> Consistency {
+        Consistency::Linearizable
+    }
+
+    /// Run the engine's maintenance tasks (e.g. a full compaction), while a server is in
+    /// maintenance mode and not accepting writes.
+    ///
+    /// The default implementation does nothing.
+    fn maintain(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get a uniform random sample of up to `n` live keys, excluding system keys, without reading
+    /// their values.
+    ///
+    /// Engines should override [`offer_keys`](#method.offer_keys) rather than this.
+    fn sample_keys(&mut self, n: usize) -> Result<Vec<String>> {
+        let mut sample = KeySample::new(n);
+        self.offer_keys(&mut sample)?;
+        Ok(sample.into_keys())
+    }
+
+    /// Offer every live key to `sample`.
+    ///
+    /// The default implementation scans every entry, so reads every value.
+    fn offer_keys(&mut self, sample: &mut KeySample) -> Result<()> {
+        for (key, _) in self.scan("")? {
+            sample.offer(&key);
+        }
+        Ok(())
+    }
+
+    /// Report statistics about the engine.
+    ///
+    /// The default implementation reports nothing.
+    fn stats(&mut self) -> Result<EngineStats> {
+        Ok(EngineStats::default())
+    }
+
+    /// Access the engine's reserved system keyspace.
+    fn system_keys(&mut self) -> SystemKeys<'_, Self>
+    where
+        Self: Sized,
+    {
+        SystemKeys::new(self)
+    }
 }
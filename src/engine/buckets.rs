@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::engine::{Consistency, Engine, KeySample, SyncPoint, SYSTEM_KEY_PREFIX};
+use crate::error::{Error, Result};
+use crate::protocol::NotFoundReason;
+use crate::stats::EngineStats;
+
+/// The separator between a key's bucket and the rest of the key.
+pub const BUCKET_SEPARATOR: char = '/';
+
+/// An engine that stores each named bucket of keys in its own engine.
+///
+/// A key's bucket is the part of the key before the first [`BUCKET_SEPARATOR`] (so `cache/a`
+/// belongs to the bucket `cache`). Keys in buckets without their own engine, keys without a
+/// bucket, and system keys are stored in the default engine. Keys are passed to engines in full.
+///
+/// ```
+/// use kvs::{Buckets, KvsEngine, MemoryKvStore, Result};
+///
+/// # fn check() -> Result<()> {
+/// let mut engine = Buckets::new(Box::new(MemoryKvStore::new()))
+///     .with_bucket("cache", Box::new(MemoryKvStore::new()));
+/// engine.set("cache/a".to_owned(), "1".to_owned())?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Buckets {
+    default: Box<dyn Engine + Send>,
+    buckets: HashMap<String, Box<dyn Engine + Send>>,
+}
+
+impl Buckets {
+    /// Construct an engine storing every key in `default`.
+    pub fn new(default: Box<dyn Engine + Send>) -> Self {
+        Buckets {
+            default,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Store the keys in `bucket` in `engine`.
+    pub fn with_bucket<B>(mut self, bucket: B, engine: Box<dyn Engine + Send>) -> Self
+    where
+        B: Into<String>,
+    {
+        self.buckets.insert(bucket.into(), engine);
+        self
+    }
+
+    /// The bucket whose engine stores `key`, or `None` for the default engine.
+    fn bucket<'k>(&self, key: &'k str) -> Option<&'k str> {
+        let bucket = match key.find(BUCKET_SEPARATOR) {
+            Some(end) if !key.starts_with(SYSTEM_KEY_PREFIX) => &key[..end],
+            _ => return None,
+        };
+        Some(bucket).filter(|bucket| self.buckets.contains_key(*bucket))
+    }
+
+    /// The engine that stores `key`.
+    fn engine(&mut self, key: &str) -> &mut dyn Engine {
+        let engine = match self.bucket(key) {
+            Some(bucket) => self.buckets.get_mut(bucket),
+            None => None,
+        };
+        match engine {
+            Some(engine) => &mut **engine,
+            None => &mut *self.default,
+        }
+    }
+}
+
+impl Engine for Buckets {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.engine(&key).get(key)
+    }
+
+    fn lookup(
+        &mut self,
+        key: String,
+    ) -> Result<std::result::Result<String, Option<NotFoundReason>>> {
+        self.engine(&key).lookup(key)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.engine(&key).set(key, value)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.engine(&key).remove(key)
+    }
+
+    fn force_remove(&mut self, key: String) -> Result<()> {
+        self.engine(&key).force_remove(key)
+    }
+
+    /// Route a key that isn't UTF-8 by its valid prefix: bucket names are strings, so invalid
+    /// bytes can't be part of one.
+    fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.engine(&String::from_utf8_lossy(key)).get_bytes(key)
+    }
+
+    fn set_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.engine(&String::from_utf8_lossy(key)).set_bytes(key, value)
+    }
+
+    fn remove_bytes(&mut self, key: &[u8]) -> Result<()> {
+        self.engine(&String::from_utf8_lossy(key)).remove_bytes(key)
+    }
+
+    fn compare_and_swap(
+        &mut self,
+        key: String,
+        expected: Option<String>,
+        new: String,
+    ) -> Result<bool> {
+        self.engine(&key).compare_and_swap(key, expected, new)
+    }
+
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        self.engine(&key).increment(key, delta)
+    }
+
+    /// Rename a key, in its engine if both keys are stored in the same one.
+    ///
+    /// A key moved to another engine is set there before it's removed from its own, so a crash in
+    /// between can leave both keys set.
+    fn rename(&mut self, from: String, to: String, overwrite: bool) -> Result<bool> {
+        if self.bucket(&from) == self.bucket(&to) {
+            return self.engine(&from).rename(from, to, overwrite);
+        }
+        let value = self.engine(&from).get(from.clone())?.ok_or(Error::KeyNotFound)?;
+        if !overwrite && self.engine(&to).get(to.clone())?.is_some() {
+            return Ok(false);
+        }
+        self.engine(&to).set(to, value)?;
+        self.engine(&from).remove(from)?;
+        Ok(true)
+    }
+
+    /// Sync every engine.
+    fn sync(&mut self) -> Result<()> {
+        self.default.sync()?;
+        for engine in self.buckets.values_mut() {
+            engine.sync()?;
+        }
+        Ok(())
+    }
+
+    /// The default engine's sync point if there are no buckets: otherwise each engine needs
+    /// syncing.
+    fn sync_point(&mut self) -> Option<SyncPoint> {
+        if self.buckets.is_empty() {
+            self.default.sync_point()
+        } else {
+            None
+        }
+    }
+
+    /// Scan every engine, merging the results.
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let mut entries = self.default.scan(prefix)?;
+        for engine in self.buckets.values_mut() {
+            entries.extend(engine.scan(prefix)?);
+        }
+        entries.sort_unstable();
+        Ok(entries)
+    }
+
+    /// Scan every engine for bytes, merging the results.
+    fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = self.default.scan_bytes(prefix)?;
+        for engine in self.buckets.values_mut() {
+            entries.extend(engine.scan_bytes(prefix)?);
+        }
+        entries.sort_unstable();
+        Ok(entries)
+    }
+
+    /// Scan every engine's range, merging the results.
+    fn scan_range(&mut self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>> {
+        let mut entries = self.default.scan_range(start, end)?;
+        for engine in self.buckets.values_mut() {
+            entries.extend(engine.scan_range(start, end)?);
+        }
+        entries.sort_unstable();
+        Ok(entries)
+    }
+
+    /// Scan every engine until `deadline`, merging the results.
+    fn scan_until(&mut self, prefix: &str, deadline: Instant) -> Result<Vec<(String, String)>> {
+        let mut entries = self.default.scan_until(prefix, deadline)?;
+        for engine in self.buckets.values_mut() {
+            entries.extend(engine.scan_until(prefix, deadline)?);
+        }
+        entries.sort_unstable();
+        Ok(entries)
+    }
+
+    /// Count the matching keys in every engine.
+    fn count_prefix(&mut self, prefix: &str) -> Result<u64> {
+        let mut count = self.default.count_prefix(prefix)?;
+        for engine in self.buckets.values_mut() {
+            count += engine.count_prefix(prefix)?;
+        }
+        Ok(count)
+    }
+
+    /// Remove the matching keys from every engine, merging the keys removed.
+    fn remove_prefix(&mut self, prefix: &str) -> Result<Vec<String>> {
+        let mut removed = self.default.remove_prefix(prefix)?;
+        for engine in self.buckets.values_mut() {
+            removed.extend(engine.remove_prefix(prefix)?);
+        }
+        removed.sort_unstable();
+        Ok(removed)
+    }
+
+    /// Find cold keys in every engine, merging the first `limit` of them.
+    fn cold_keys(
+        &mut self,
+        prefix: &str,
+        unread_for: Duration,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let mut cold = self.default.cold_keys(prefix, unread_for, limit)?;
+        for engine in self.buckets.values_mut() {
+            cold.extend(engine.cold_keys(prefix, unread_for, limit)?);
+        }
+        cold.sort_unstable();
+        cold.truncate(limit);
+        Ok(cold)
+    }
+
+    fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        self.engine(&key).set_with_ttl(key, value, ttl)
+    }
+
+    fn ttl(&mut self, key: String) -> Result<Option<Duration>> {
+        self.engine(&key).ttl(key)
+    }
+
+    fn expire(&mut self, key: String, ttl: Duration) -> Result<()> {
+        self.engine(&key).expire(key, ttl)
+    }
+
+    fn persist(&mut self, key: String) -> Result<()> {
+        self.engine(&key).persist(key)
+    }
+
+    /// Sweep each engine in turn, up to `limit` keys in total.
+    fn sweep_expired(&mut self, limit: usize) -> Result<Vec<String>> {
+        let mut expired = self.default.sweep_expired(limit)?;
+        for engine in self.buckets.values_mut() {
+            if expired.len() >= limit {
+                break;
+            }
+            expired.extend(engine.sweep_expired(limit - expired.len())?);
+        }
+        Ok(expired)
+    }
+
+    /// Scrub each engine in turn, up to `limit` entries from each.
+    fn scrub(&mut self, limit: usize) -> Result<Vec<String>> {
+        let mut corrupt = self.default.scrub(limit)?;
+        for engine in self.buckets.values_mut() {
+            corrupt.extend(engine.scrub(limit)?);
+        }
+        Ok(corrupt)
+    }
+
+    /// Offer each engine's keys in turn.
+    fn offer_keys(&mut self, sample: &mut KeySample) -> Result<()> {
+        self.default.offer_keys(sample)?;
+        for engine in self.buckets.values_mut() {
+            engine.offer_keys(sample)?;
+        }
+        Ok(())
+    }
+
+    /// Maintain each engine in turn.
+    fn maintain(&mut self) -> Result<()> {
+        self.default.maintain()?;
+        for engine in self.buckets.values_mut() {
+            engine.maintain()?;
+        }
+        Ok(())
+    }
+
+    /// Report the default engine's name.
+    fn name(&self) -> &str {
+        self.default.name()
+    }
+
+    /// Report the weakest of every engine's guarantees.
+    fn consistency(&self) -> Consistency {
+        let buckets = self.buckets.values().map(|engine| engine.consistency());
+        buckets.fold(self.default.consistency(), Consistency::max)
+    }
+
+    /// Report the total of every engine's statistics.
+    fn stats(&mut self) -> Result<EngineStats> {
+        let mut total = self.default.stats()?;
+        for engine in self.buckets.values_mut() {
+            let stats = engine.stats()?;
+            total.keys += stats.keys;
+            total.index_bytes += stats.index_bytes;
+            total.log_files += stats.log_files;
+            total.open_files += stats.open_files;
+            total.file_evictions += stats.file_evictions;
+            total.uncompacted_bytes += stats.uncompacted_bytes;
+            total.log_bytes += stats.log_bytes;
+            total.compactions += stats.compactions;
+            total.compacted_bytes += stats.compacted_bytes;
+            total.written_bytes += stats.written_bytes;
+            total.files.extend(stats.files);
+            total.recent_compactions.extend(stats.recent_compactions);
+            total.deferred_compactions += stats.deferred_compactions;
+            total.slowed_writes += stats.slowed_writes;
+            total.rejected_writes += stats.rejected_writes;
+            total.synced_removes += stats.synced_removes;
+            total.recovered |= stats.recovered;
+            total.read_only |= stats.read_only;
+            total.replay_duplicates += stats.replay_duplicates;
+            total.expiring_keys += stats.expiring_keys;
+            total.expired_keys += stats.expired_keys;
+            total.memtable_hits += stats.memtable_hits;
+            total.scrubbed_entries += stats.scrubbed_entries;
+            total.scrub_passes += stats.scrub_passes;
+            total.corrupt_entries += stats.corrupt_entries;
+            total.dictionary_bytes += stats.dictionary_bytes;
+            total.compressed_values += stats.compressed_values;
+            total.uncompressed_value_bytes += stats.uncompressed_value_bytes;
+            total.compressed_value_bytes += stats.compressed_value_bytes;
+            total.rotations += stats.rotations;
+            total.follower_rebuilds += stats.follower_rebuilds;
+            total.torn_tail_bytes += stats.torn_tail_bytes;
+            total.failed_syncs += stats.failed_syncs;
+        }
+        total.recent_compactions.sort_by_key(|compaction| compaction.started_at);
+        Ok(total)
+    }
+}
@@ -1,19 +1,571 @@
+mod access;
+mod commit;
+mod dictionary;
+mod erase;
+mod filter;
+mod flush;
+mod follower;
+mod format;
+mod fsck;
+mod index;
+mod jobs;
+mod lock;
 mod log;
+mod manifest;
+mod memtable;
+mod readers;
+mod reserve;
+mod stall;
+mod usage;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs::{self, File, OpenOptions};
-use std::mem;
 use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::engine::Engine;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::clock::{Clock, SystemClock};
+use crate::engine::{lossy_key, utf8_entries, utf8_entry, utf8_value};
+use crate::engine::{Engine, KeySample, SYSTEM_KEY_PREFIX};
 use crate::error::{Error, Result};
-use self::log::{Command, Offset, Reader, Writer};
+use crate::format::ValueFormat;
+use crate::metrics::{MetricsSink, NoopMetrics};
+use crate::protocol::NotFoundReason;
+use crate::stats::{CompactionStats, EngineStats, LogFileStats};
+use self::access::AccessSketch;
+use self::dictionary::{Dictionaries, Samples};
+use self::flush::Flusher;
+use self::index::{Index, IndexEntry};
+use self::lock::DirLock;
+use self::log::{Command, Compressed, Offset, Reader, Writer};
+use self::manifest::{CleanShutdown, Manifest, MANIFEST_FILE};
+use self::memtable::Memtable;
+use self::readers::Readers;
+use self::usage::Usage;
+
+pub use self::commit::SyncPoint;
+pub use self::erase::Erasure;
+pub use self::filter::{CompactionDecision, CompactionFilter};
+pub use self::flush::SyncPolicy;
+pub use self::follower::Follower;
+pub use self::format::CURRENT_VERSION as FORMAT_VERSION;
+pub use self::fsck::{CheckReport, CorruptRange, FileReport, ManifestReport, Repair, SequenceGap};
+pub use self::jobs::{BackgroundJobs, JobSlot};
+pub use self::stall::WriteStall;
+
+/// The number of stale bytes at which to try compacting, unless a [compaction
+/// threshold](struct.KvStoreBuilder.html#method.compaction_threshold) or [compaction
+/// ratio](struct.KvStoreBuilder.html#method.compaction_ratio) is configured.
+const DEFAULT_COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+
+/// The number of log files kept open for reading, unless configured otherwise.
+const DEFAULT_MAX_OPEN_FILES: usize = 64;
+
+/// The number of recently written values kept in memory, unless configured otherwise.
+const DEFAULT_MEMTABLE_SIZE: usize = 64;
+
+/// The number of compactions reported in [`EngineStats::recent_compactions`].
+const RECENT_COMPACTIONS: usize = 16;
 
-/// The offset at which to try compacting.
+/// How often a read-only store checks whether disk space has been freed.
+const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Configures and opens a [`Store`].
 ///
-/// Note: This drives a pretty broken compaction implementation where we rewrite a single log file
-/// to remove duplicate commands.
-const COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+/// ```
+/// # use std::path::PathBuf;
+/// # use kvs::{KvStore, Result};
+/// # fn check() -> Result<()> {
+/// # let path = PathBuf::new();
+/// let store = KvStore::builder()
+///     .max_index_memory(64 * 1024 * 1024)
+///     .open(path)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Builder {
+    max_index_memory: Option<u64>,
+    disk_index_cache: Option<u64>,
+    prefix_compression: bool,
+    rebuild_index: bool,
+    strict_recovery: bool,
+    sync_policy: SyncPolicy,
+    remove_expired_on_read: bool,
+    clock: Option<Arc<dyn Clock>>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    max_open_files: Option<usize>,
+    memtable_size: Option<usize>,
+    inline_values: Option<usize>,
+    background_jobs: Option<BackgroundJobs>,
+    compaction_threshold: Option<u64>,
+    compaction_ratio: Option<(f64, u64)>,
+    max_compaction_files: Option<usize>,
+    max_segment_size: Option<u64>,
+    write_stall: Option<WriteStall>,
+    reserved_space: Option<u64>,
+    value_format: ValueFormat,
+    compression_dictionary: Option<usize>,
+    follow_interval: Option<Duration>,
+}
+
+impl Builder {
+    /// Construct a builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cap the (estimated) memory used by the in-memory index.
+    ///
+    /// Once the cap is reached, setting a key that isn't already in the store fails with
+    /// [`Error::IndexFull`]. Existing keys can still be overwritten or removed. The cap is not
+    /// enforced when loading an existing log, so a store can always be reopened.
+    ///
+    /// [`Error::IndexFull`]: enum.Error.html#variant.IndexFull
+    ///
+    /// This has no effect when using a [disk index](#method.disk_index).
+    pub fn max_index_memory(mut self, bytes: u64) -> Self {
+        self.max_index_memory = Some(bytes);
+        self
+    }
+
+    /// Keep the index in a B-tree on disk rather than in memory, caching up to `cache_capacity`
+    /// bytes of it in memory.
+    ///
+    /// This allows for keyspaces larger than memory, at the cost of slower reads and writes. The
+    /// disk index is kept in an `index` directory inside the store directory, and is rebuilt from
+    /// the log whenever the store is opened.
+    pub fn disk_index(mut self, cache_capacity: u64) -> Self {
+        self.disk_index_cache = Some(cache_capacity);
+        self
+    }
+
+    /// Store shared key prefixes only once, both in the index and in compacted log files.
+    ///
+    /// This can substantially reduce memory and disk usage when keys have long common prefixes
+    /// (e.g. URLs), at the cost of slightly slower index operations. Compacted log files are
+    /// written with keys in sorted order, each encoded relative to the previous one.
+    ///
+    /// If a [disk index](#method.disk_index) is used, only log files are compressed.
+    pub fn prefix_compression(mut self, enabled: bool) -> Self {
+        self.prefix_compression = enabled;
+        self
+    }
+
+    /// Rebuild the index from the raw log when opening, checking every command, even if the
+    /// store was closed cleanly.
+    ///
+    /// The index is always built from the log, but after a clean shutdown the log is trusted to
+    /// be as it was left (according to the manifest), and commands aren't checked for gaps in
+    /// their sequence numbers. This is the recovery path when the manifest is suspected to be
+    /// stale or corrupt: any gap fails the open with [`Error::SequenceGap`] (see [`Store::check`]
+    /// and [`Store::repair`]).
+    ///
+    /// [`Error::SequenceGap`]: enum.Error.html#variant.SequenceGap
+    /// [`Store::check`]: struct.KvStore.html#method.check
+    /// [`Store::repair`]: struct.KvStore.html#method.repair
+    pub fn rebuild_index(mut self, enabled: bool) -> Self {
+        self.rebuild_index = enabled;
+        self
+    }
+
+    /// Fail to open with [`Error::Corruption`] if the last command in the log was torn by a crash
+    /// while it was being written, rather than truncating it.
+    ///
+    /// By default a torn command is discarded, since it was never acknowledged: the log is
+    /// truncated to the end of the last intact command, and the number of bytes discarded is
+    /// reported in [`EngineStats::torn_tail_bytes`]. Corruption anywhere else in the log always
+    /// fails the open.
+    ///
+    /// [`Error::Corruption`]: enum.Error.html#variant.Corruption
+    /// [`EngineStats::torn_tail_bytes`]: struct.EngineStats.html#structfield.torn_tail_bytes
+    pub fn strict_recovery(mut self, enabled: bool) -> Self {
+        self.strict_recovery = enabled;
+        self
+    }
+
+    /// Sync written commands to disk according to `policy` (see [`SyncPolicy`]), rather than
+    /// leaving it to the operating system.
+    ///
+    /// With [`SyncPolicy::Every`], failed background syncs are reported in
+    /// [`EngineStats::failed_syncs`].
+    ///
+    /// [`SyncPolicy`]: enum.SyncPolicy.html
+    /// [`SyncPolicy::Every`]: enum.SyncPolicy.html#variant.Every
+    /// [`EngineStats::failed_syncs`]: struct.EngineStats.html#structfield.failed_syncs
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Remove expired keys as soon as a read finds them, rather than leaving them to the next
+    /// sweep.
+    ///
+    /// Either way, a read of a key that has expired but hasn't been swept yet reports it as not
+    /// found, and counts it in [`EngineStats::expired_keys`] (once, however often it's read). By
+    /// default the key is then left for [`sweep_expired`] to remove, so reads never write to the
+    /// log. With this enabled, the read writes the `Remove` itself, and a later sweep won't return
+    /// the key (so e.g. a server won't log it as expired). While the store is
+    /// [read-only](enum.Error.html#variant.DiskFull), the key is left for the sweep.
+    ///
+    /// [`EngineStats::expired_keys`]: struct.EngineStats.html#structfield.expired_keys
+    /// [`sweep_expired`]: trait.KvsEngine.html#method.sweep_expired
+    pub fn remove_expired_on_read(mut self, enabled: bool) -> Self {
+        self.remove_expired_on_read = enabled;
+        self
+    }
+
+    /// Use `clock` to decide when keys expire, instead of the system clock.
+    ///
+    /// This is mostly useful for simulating time in tests (see [`ManualClock`]).
+    ///
+    /// [`ManualClock`]: struct.ManualClock.html
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Report metrics (e.g. writes and compactions) to `metrics`.
+    ///
+    /// See [`MetricsSink`] for the metrics reported.
+    ///
+    /// [`MetricsSink`]: trait.MetricsSink.html
+    pub fn metrics<M: MetricsSink + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Pass each live entry to `filter` when compacting the log, to decide whether it's kept,
+    /// dropped or changed (see [`CompactionFilter`]).
+    ///
+    /// [`CompactionFilter`]: trait.CompactionFilter.html
+    pub fn compaction_filter<F: CompactionFilter + 'static>(mut self, filter: F) -> Self {
+        self.compaction_filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Keep at most `max_open_files` log files open for reading (64 by default).
+    ///
+    /// Once the cap is reached, the least recently read file is closed to make room for the next,
+    /// and reopened when it's next needed. The file being written (and, during compaction, the file
+    /// being compacted into) are held open in addition to these.
+    pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = Some(max_open_files);
+        self
+    }
+
+    /// Keep the values of the `size` most recently written keys in memory (64 by default), so that
+    /// reading them back doesn't need a file seek.
+    ///
+    /// A `size` of `0` reads every value from the log.
+    pub fn memtable_size(mut self, size: usize) -> Self {
+        self.memtable_size = Some(size);
+        self
+    }
+
+    /// Keep values of at most `max_len` bytes in the index itself, so that reading them never
+    /// touches the log.
+    ///
+    /// The log is still the source of truth, and inlined values are read from it when the store
+    /// is opened. Inlined values count towards the [index memory cap](#method.max_index_memory)
+    /// and [`EngineStats::index_bytes`].
+    ///
+    /// [`EngineStats::index_bytes`]: struct.EngineStats.html#structfield.index_bytes
+    pub fn inline_values(mut self, max_len: usize) -> Self {
+        self.inline_values = Some(max_len);
+        self
+    }
+
+    /// Only compact while a slot is free in `jobs`.
+    ///
+    /// The same [`BackgroundJobs`] can be given to several stores (builders share it when cloned)
+    /// to cap the number of compactions running across all of them. A store that needs compacting
+    /// while every slot is taken defers compaction until a later write.
+    pub fn background_jobs(mut self, jobs: BackgroundJobs) -> Self {
+        self.background_jobs = Some(jobs);
+        self
+    }
+
+    /// Compact once there are more than `bytes` of stale commands in the log (1MiB by default).
+    ///
+    /// This is ignored if a [compaction ratio](#method.compaction_ratio) is configured, which has
+    /// a minimum of its own.
+    pub fn compaction_threshold(mut self, bytes: u64) -> Self {
+        self.compaction_threshold = Some(bytes);
+        self
+    }
+
+    /// Compact once stale commands make up more than `ratio` of the log (e.g. `0.5` for half of
+    /// it), as long as there are at least `min_bytes` of them.
+    ///
+    /// By default, compaction is tried at a fixed [threshold](#method.compaction_threshold) of
+    /// stale commands, which compacts small stores too rarely and large stores too often.
+    pub fn compaction_ratio(mut self, ratio: f64, min_bytes: u64) -> Self {
+        self.compaction_ratio = Some((ratio, min_bytes));
+        self
+    }
+
+    /// Compact only the oldest `max_files` log files (at least two) at a time, rather than the
+    /// whole log.
+    ///
+    /// Each compaction merges the live commands from the file written by the previous compaction
+    /// and the oldest files written since into a new file, leaving newer files to later
+    /// compactions. This bounds the amount of work (and disk space) a single compaction takes, at
+    /// the cost of stale commands in newer files being kept for longer. Compactions requested
+    /// explicitly, e.g. by [`maintain`] or [`erase`], still compact the whole log.
+    ///
+    /// [`maintain`]: trait.KvsEngine.html#method.maintain
+    /// [`erase`]: struct.KvStore.html#method.erase
+    pub fn max_compaction_files(mut self, max_files: usize) -> Self {
+        self.max_compaction_files = Some(max_files.max(2));
+        self
+    }
+
+    /// Start a new log file once the one being written to reaches `bytes`, rather than writing to
+    /// a single file until the next compaction.
+    ///
+    /// A full log file is synced and left as it is, and is only rewritten when it's compacted.
+    /// Together with [`max_compaction_files`], this lets each compaction work through a bounded
+    /// number of bounded files. The file written by a compaction holds every live value it
+    /// compacted, so it isn't limited to this size.
+    ///
+    /// [`max_compaction_files`]: #method.max_compaction_files
+    pub fn max_segment_size(mut self, bytes: u64) -> Self {
+        self.max_segment_size = Some(bytes);
+        self
+    }
+
+    /// Slow down, and then reject, writes while compaction is too far behind (see
+    /// [`WriteStall`]).
+    ///
+    /// [`WriteStall`]: struct.WriteStall.html
+    pub fn write_stall(mut self, stall: WriteStall) -> Self {
+        self.write_stall = Some(stall);
+        self
+    }
+
+    /// Reserve `bytes` of disk space in the store's directory (in a `RESERVE` file), to be
+    /// released if the disk fills up.
+    ///
+    /// When a write fails because the disk is full, the store switches to read-only mode and
+    /// releases the reserved space, so that the store can still be reopened and compacted. Writes
+    /// fail with [`Error::DiskFull`] until the reserved space can be written again, which is
+    /// checked at most once a second.
+    ///
+    /// [`Error::DiskFull`]: enum.Error.html#variant.DiskFull
+    pub fn reserved_space(mut self, bytes: u64) -> Self {
+        self.reserved_space = Some(bytes);
+        self
+    }
+
+    /// Serialize values with `format` in [`Store::get_as`] and [`Store::set_from`] (JSON by
+    /// default).
+    ///
+    /// [`Store::get_as`]: struct.KvStore.html#method.get_as
+    /// [`Store::set_from`]: struct.KvStore.html#method.set_from
+    pub fn value_format(mut self, format: ValueFormat) -> Self {
+        self.value_format = format;
+        self
+    }
+
+    /// Compress values in compacted log files with a zstd dictionary of up to `max_len` bytes,
+    /// trained on a sample of the values written by the previous compaction.
+    ///
+    /// Small values compress poorly on their own, since there's little in a single value to find
+    /// repetition in. A dictionary of the content they have in common (e.g. field names in JSON
+    /// values) lets each one be compressed much further. Each compaction trains a new dictionary,
+    /// which is stored alongside the log files and used by the next compaction, so the first
+    /// compaction compresses nothing. Values that wouldn't be made smaller are stored as-is, as
+    /// are values written between compactions.
+    ///
+    /// How well the latest compaction's values compressed is reported in [`EngineStats`]. Log
+    /// files with compressed values can't be read by versions of this crate from before
+    /// dictionaries were introduced.
+    ///
+    /// [`EngineStats`]: struct.EngineStats.html
+    pub fn compression_dictionary(mut self, max_len: usize) -> Self {
+        self.compression_dictionary = Some(max_len);
+        self
+    }
+
+    /// How long a [`Follower`] goes between catching up with its store, so how stale its reads
+    /// can be. The default is 100ms, and with zero it catches up before every read.
+    ///
+    /// [`Follower`]: struct.KvFollower.html
+    pub fn follow_interval(mut self, interval: Duration) -> Self {
+        self.follow_interval = Some(interval);
+        self
+    }
+
+    /// Follow a store that another process has open, using this configuration, without writing
+    /// to it (see [`Follower`]).
+    ///
+    /// [`Follower`]: struct.KvFollower.html
+    pub fn open_follower<P: Into<PathBuf>>(self, path: P) -> Result<Follower> {
+        Follower::open(self, path.into())
+    }
+
+    /// Construct a Store from an existing, persisted log using this configuration.
+    pub fn open<P: Into<PathBuf>>(self, path: P) -> Result<Store> {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+
+        // Release any reserved space while opening, in case the disk has filled up since the
+        // store was last open.
+        reserve::release(&path)?;
+
+        let mut uncompacted = 0;
+        let mut usage = Usage::default();
+        let mut index = match self.disk_index_cache {
+            Some(cache_capacity) => Index::disk(&path, cache_capacity)?,
+            None if self.prefix_compression => Index::prefix(),
+            None => Index::memory(),
+        };
+        let max_open_files = self.max_open_files.unwrap_or(DEFAULT_MAX_OPEN_FILES);
+        let dictionaries = Dictionaries::load(&path)?;
+        let mut readers = Readers::new(&path, max_open_files, dictionaries.clone());
+        let manifest = Manifest::load(&path)?.unwrap_or_default();
+        let mut log_indices = find_log_indices(&path)?;
+
+        // Files superseded by the latest compaction are left behind if compaction is interrupted
+        // after the manifest is updated, and are safe to delete.
+        for &log_index in log_indices.iter().filter(|&&i| manifest.is_superseded(i)) {
+            fs::remove_file(log_path(&path, log_index))?;
+        }
+        log_indices.retain(|&i| !manifest.is_superseded(i));
+
+        // After a clean shutdown (or for a new store), the log is known to be consistent and only
+        // the final sequence number needs checking.
+        let clean = log_indices.is_empty() || manifest.is_clean(&path, &log_indices)?;
+
+        // Commands are applied in sequence order. The compacted file is replayed first, and holds
+        // one command per live key up to `compacted_seq`, and every other command must follow on
+        // from the one before it. Commands that have already been applied (e.g. left behind by an
+        // interrupted compaction) are skipped.
+        let mut sequence = Sequence::new(&manifest);
+        sequence.trusted = clean && !self.rebuild_index;
+        let mut expiries = BTreeSet::new();
+        let mut replayed = log_indices.clone();
+        manifest::replay_order(&mut replayed, manifest.compacted_index);
+        let mut torn_tail_bytes = 0;
+        for &log_index in &replayed {
+            let is_compacted = manifest.compacted_index == Some(log_index);
+            let is_tail = !is_compacted && log_indices.last() == Some(&log_index);
+            let reader = readers.insert(log_index)?;
+            let mut torn = None;
+            for entry in reader.load()? {
+                let entry = match entry {
+                    Err(Error::Corruption { offset, .. }) if is_tail && !self.strict_recovery => {
+                        torn = Some(offset);
+                        break;
+                    },
+                    entry => entry?,
+                };
+                usage.written(log_index, entry.2);
+                if sequence.check(entry.0.seq(), is_compacted)? {
+                    uncompacted += open_entry(
+                        log_index,
+                        &mut index,
+                        &mut expiries,
+                        &mut usage,
+                        &dictionaries,
+                        self.inline_values,
+                        entry,
+                    )?;
+                }
+            }
+            if let Some(offset) = torn {
+                torn_tail_bytes = truncate_torn_tail(&path, reader, log_index, offset)?;
+            }
+        }
+        sequence.finish(&manifest, torn_tail_bytes > 0)?;
+
+        // New commands are only ever written in the current format version, so start a new log
+        // file if the last one was written in an older version.
+        let write_index = match log_indices.last() {
+            Some(&log_index) if readers.version(log_index) < FORMAT_VERSION => log_index + 1,
+            Some(&log_index) => log_index,
+            None => 0,
+        };
+        let writer = open_writer(&path, write_index)?;
+        if !readers.contains(write_index) {
+            readers.insert(write_index)?;
+        }
+
+        let clock = self.clock.clone().unwrap_or_else(|| Arc::new(SystemClock));
+        let mut epoch = Epoch {
+            clock,
+            latest: manifest.clock_epoch,
+        };
+        let access = AccessSketch::load(&path, epoch.now());
+        let memtable = Memtable::new(self.memtable_size.unwrap_or(DEFAULT_MEMTABLE_SIZE));
+        let metrics = self.metrics.clone().unwrap_or_else(|| Arc::new(NoopMetrics));
+        let read_only = match reserve::reserve(&path, self.reserved_space.unwrap_or(0)) {
+            Ok(()) => false,
+            Err(Error::DiskFull) => true,
+            Err(err) => return Err(err),
+        };
+        let flusher = match self.sync_policy {
+            SyncPolicy::Every(interval) => Some(Flusher::start(interval)?),
+            SyncPolicy::Always | SyncPolicy::Os => None,
+        };
+        let mut store = Store {
+            config: self,
+            path,
+            log_index: write_index,
+            writer,
+            readers,
+            index,
+            memtable,
+            expiries,
+            read_expired: HashSet::new(),
+            seq: sequence.last,
+            compacted_index: manifest.compacted_index,
+            compacted_seq: manifest.compacted_seq,
+            replay_duplicates: sequence.duplicates,
+            uncompacted,
+            usage,
+            compactions: 0,
+            recent_compactions: VecDeque::new(),
+            compacted_bytes: 0,
+            written_bytes: 0,
+            deferred_compactions: 0,
+            rotations: 0,
+            torn_tail_bytes,
+            slowed_writes: 0,
+            rejected_writes: 0,
+            synced_removes: 0,
+            expired: 0,
+            scrub_cursor: None,
+            scrubbed: 0,
+            scrub_passes: 0,
+            corrupt: 0,
+            dictionary: manifest.dictionary.filter(|&id| dictionaries.contains(id)),
+            compressed_values: 0,
+            uncompressed_value_bytes: 0,
+            compressed_value_bytes: 0,
+            epoch,
+            access,
+            metrics,
+            flusher,
+            recovered: !clean,
+            read_only,
+            space_checked: Instant::now(),
+        };
+        store.watch_writer();
+        match store.save_manifest() {
+            Err(Error::DiskFull) => store.read_only = true,
+            result => result?,
+        }
+        Ok(store)
+    }
+}
 
 /// A simple log-based key value store.
 ///
@@ -22,6 +574,40 @@ const COMPACTION_THRESHOLD: u64 = 1024 * 1024;
 /// be considered opaque (currently they contain a sequence of [MessagePack]-encoded 'commands', the
 /// details of which are private to the crate).
 ///
+/// Every command is given a sequence number, one greater than the last. A `MANIFEST` file in the
+/// same directory records the last sequence number and the result of the latest compaction, and
+/// is used when opening the store to check that no commands have gone missing.
+///
+/// Keys and values are stored as bytes, so they may be anything, not just UTF-8 (see
+/// [`set_bytes`]).
+///
+/// Reads are tracked approximately (to the minute, in a fixed 256KiB of memory) and saved in an
+/// `ACCESS` file when the store is closed, so that keys which haven't been read for a while can be
+/// found with [`cold_keys`]. A key may be reported as read more recently than it was, but never
+/// less recently.
+///
+/// Keys can be given a time-to-live with [`set_with_ttl`]. Expired keys are never returned, but
+/// they stay in the log until they're removed by [`sweep_expired`] (which the server calls in the
+/// background).
+///
+/// Expiry times are stored as wall-clock timestamps, so a key's TTL keeps counting down while the
+/// store is closed. The store's view of the time never goes backwards: the latest time it has seen
+/// is recorded in the manifest, and if the clock (see [`Builder::clock`]) reads earlier than that,
+/// even after a restart, the store carries on from the latest time instead. This means expired
+/// keys stay expired and new TTLs are never extended by a backwards clock jump, although they may
+/// be cut short until the clock catches up. Forwards jumps can't be detected, and expire keys
+/// early.
+///
+/// Other processes on the same host can serve reads from the store's files with a
+/// [`KvFollower`], which keeps up with the log as it's written.
+///
+/// [`KvFollower`]: struct.KvFollower.html
+/// [`set_bytes`]: trait.KvsEngine.html#method.set_bytes
+/// [`set_with_ttl`]: trait.KvsEngine.html#method.set_with_ttl
+/// [`sweep_expired`]: trait.KvsEngine.html#method.sweep_expired
+/// [`cold_keys`]: trait.KvsEngine.html#method.cold_keys
+/// [`Builder::clock`]: struct.KvStoreBuilder.html#method.clock
+///
 /// ```
 /// # use std::path::PathBuf;
 /// use kvs::{KvsEngine, KvStore, Result};
@@ -39,20 +625,47 @@ const COMPACTION_THRESHOLD: u64 = 1024 * 1024;
 /// # }
 /// ```
 pub struct Store {
+    config: Builder,
     path: PathBuf,
     log_index: u64,
     writer: Writer,
-    readers: HashMap<u64, Reader>,
-    index: HashMap<String, IndexEntry>,
+    readers: Readers,
+    index: Index,
+    memtable: Memtable,
+    expiries: BTreeSet<(u64, Vec<u8>)>,
+    read_expired: HashSet<Vec<u8>>,
+    seq: u64,
+    compacted_index: Option<u64>,
+    compacted_seq: u64,
+    replay_duplicates: u64,
     uncompacted: u64,
-}
-
-/// An entry in a command index.
-#[derive(Debug)]
-struct IndexEntry {
-    log_index: u64,
-    offset: Offset,
-    length: u64,
+    usage: Usage,
+    compactions: u64,
+    recent_compactions: VecDeque<CompactionStats>,
+    compacted_bytes: u64,
+    written_bytes: u64,
+    deferred_compactions: u64,
+    rotations: u64,
+    torn_tail_bytes: u64,
+    slowed_writes: u64,
+    rejected_writes: u64,
+    synced_removes: u64,
+    expired: u64,
+    scrub_cursor: Option<Vec<u8>>,
+    scrubbed: u64,
+    scrub_passes: u64,
+    corrupt: u64,
+    dictionary: Option<u64>,
+    compressed_values: u64,
+    uncompressed_value_bytes: u64,
+    compressed_value_bytes: u64,
+    epoch: Epoch,
+    access: AccessSketch,
+    metrics: Arc<dyn MetricsSink>,
+    flusher: Option<Flusher>,
+    recovered: bool,
+    read_only: bool,
+    space_checked: Instant,
 }
 
 impl Store {
@@ -68,89 +681,933 @@ impl Store {
     /// # }
     /// ```
     pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self> {
-        let path = path.into();
-        fs::create_dir_all(&path)?;
+        Builder::new().open(path)
+    }
+
+    /// Construct a Store from an existing, persisted log, rebuilding its index from the raw log
+    /// and checking every command (see [`Builder::rebuild_index`]).
+    ///
+    /// [`Builder::rebuild_index`]: struct.KvStoreBuilder.html#method.rebuild_index
+    pub fn open_with_rebuild<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        Builder::new().rebuild_index(true).open(path)
+    }
+
+    /// Construct a [`Builder`] to configure a Store before opening it.
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Check a store's directory for consistency, without opening or modifying it.
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use kvs::{KvStore, Result};
+    /// # fn check() -> Result<()> {
+    /// # let path = PathBuf::new();
+    /// let report = KvStore::check(path)?;
+    /// if !report.clean {
+    ///     println!("{}", serde_json::to_string(&report).unwrap());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn check<P: AsRef<Path>>(path: P) -> Result<CheckReport> {
+        fsck::check(path.as_ref())
+    }
+
+    /// Check a store's directory for consistency, and make the repairs needed if they're
+    /// [repairable](struct.CheckReport.html#structfield.repairable).
+    ///
+    /// The store must not be open while it's being repaired.
+    pub fn repair<P: AsRef<Path>>(path: P) -> Result<CheckReport> {
+        fsck::repair(path.as_ref())
+    }
+
+    /// Pass each key starting with `prefix` to `emit` with its value, in key order, returning how
+    /// many keys were passed.
+    ///
+    /// If `modified_since` is given (in milliseconds since the UNIX epoch), only keys set at or
+    /// after that time are passed. Keys last set before modification times were recorded have no
+    /// known modification time, so they're always passed. Values are read one at a time, so an
+    /// export needn't fit in memory.
+    ///
+    /// Fails if a key or value to be passed isn't UTF-8.
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use kvs::{KvStore, Result};
+    /// # fn check() -> Result<()> {
+    /// # let path = PathBuf::new();
+    /// let mut store = KvStore::open(path)?;
+    /// store.export("user:", Some(1_704_067_200_000), |key, value| {
+    ///     println!("{} = {}", key, value);
+    ///     Ok(())
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn export<F>(
+        &mut self,
+        prefix: &str,
+        modified_since: Option<u64>,
+        mut emit: F,
+    ) -> Result<u64>
+    where
+        F: FnMut(&str, &str) -> Result<()>,
+    {
+        let now = self.epoch.now();
+        let mut exported = 0;
+        for (key, entry) in self.index.scan(prefix.as_bytes())? {
+            let modified = match (entry.modified, modified_since) {
+                (Some(modified), Some(since)) => modified >= since,
+                _ => true,
+            };
+            if modified && !is_expired(entry.expires, now) {
+                let (key, value) = utf8_entry(&key, &self.readers.read(&entry)?)?;
+                emit(&key, &value)?;
+                exported += 1;
+            }
+        }
+        Ok(exported)
+    }
+
+    /// Get the value of a key, deserialized as a `T` (see [`Builder::value_format`]).
+    ///
+    /// Fails with [`Error::InvalidValue`] if the value isn't a valid `T`.
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use kvs::{KvStore, Result};
+    /// # fn check() -> Result<()> {
+    /// # let path = PathBuf::new();
+    /// let mut store = KvStore::open(path)?;
+    /// store.set_from("scores".to_owned(), &vec![1, 2, 3])?;
+    /// let scores: Option<Vec<u32>> = store.get_as("scores".to_owned())?;
+    /// assert_eq!(scores, Some(vec![1, 2, 3]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Builder::value_format`]: struct.KvStoreBuilder.html#method.value_format
+    /// [`Error::InvalidValue`]: enum.Error.html#variant.InvalidValue
+    pub fn get_as<T: DeserializeOwned>(&mut self, key: String) -> Result<Option<T>> {
+        let format = self.config.value_format;
+        match self.get(key.clone())? {
+            Some(value) => format.decode(&key, &value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Set the value of a key to `value`, serialized (see [`Builder::value_format`]).
+    ///
+    /// [`Builder::value_format`]: struct.KvStoreBuilder.html#method.value_format
+    pub fn set_from<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<()> {
+        let value = self.config.value_format.encode(&key, value)?;
+        self.set(key, value)
+    }
+
+    /// Remove a key, and scrub every earlier version of it from the log.
+    ///
+    /// Removing a key leaves its old values on disk until the log is next compacted. Erasing it
+    /// also compacts the log immediately (whether or not a [background job] slot is free), so
+    /// that no log file still holds the key or its values, and appends an [`Erasure`] record to
+    /// the store's audit log (see [`erasures`]). Keys that have already been removed can be erased
+    /// too, to scrub their old values.
+    ///
+    /// Log files are deleted rather than overwritten, so the filesystem may keep the scrubbed data
+    /// in free blocks until they're reused. With a [disk index], the index's own files may also
+    /// hold the key until they're compacted.
+    ///
+    /// [background job]: struct.BackgroundJobs.html
+    /// [disk index]: struct.KvStoreBuilder.html#method.disk_index
+    /// [`Erasure`]: struct.Erasure.html
+    /// [`erasures`]: #method.erasures
+    pub fn erase(&mut self, key: String) -> Result<Erasure> {
+        let was_live = self.index.get(key.as_bytes())?.is_some();
+        if was_live {
+            self.remove_entry(key.clone().into_bytes())?;
+        } else {
+            self.check_writable()?;
+        }
+        self.compact()?;
+        // The compacted log's values were compressed with a dictionary trained before the key was
+        // removed, which may hold fragments of its value, so compact again with the new one.
+        if self.config.compression_dictionary.is_some() {
+            self.compact()?;
+        }
+
+        let erasure = Erasure {
+            key_hash: Erasure::hash_key(&key),
+            erased_at: self.epoch.now(),
+            seq: self.seq,
+            was_live,
+        };
+        erase::record(&self.path, &erasure)?;
+        Ok(erasure)
+    }
+
+    /// The erasures recorded in a store's audit log, oldest first.
+    pub fn erasures<P: AsRef<Path>>(path: P) -> Result<Vec<Erasure>> {
+        erase::load(path.as_ref())
+    }
+
+    /// Rewrite any log files that were written in an older format version.
+    ///
+    /// Files from older versions can be read as-is, so this is never required, but it allows
+    /// support for older versions to be removed in future. Returns the number of files that were
+    /// rewritten.
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use kvs::{KvStore, Result};
+    /// # fn check() -> Result<()> {
+    /// # let path = PathBuf::new();
+    /// let mut store = KvStore::open(path)?;
+    /// let upgraded = store.upgrade()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn upgrade(&mut self) -> Result<usize> {
+        let outdated = self
+            .readers
+            .versions()
+            .filter(|&version| version < FORMAT_VERSION)
+            .count();
+        if outdated > 0 {
+            self.compact()?;
+        }
+        Ok(outdated)
+    }
+
+    /// Write a `Set` command for a key, which expires at `expires` (in milliseconds since the UNIX
+    /// epoch) if given.
+    fn set_entry(&mut self, key: Vec<u8>, value: Vec<u8>, expires: Option<u64>) -> Result<()> {
+        self.check_writable()?;
+        self.stall_write()?;
+        let inline = inline_value(&value, self.config.inline_values);
+        if let Some(max_index_memory) = self.config.max_index_memory {
+            let cost = self.index.insert_cost(&key);
+            let inline_cost = inline.as_ref().map_or(0, |value| value.len() as u64);
+            if cost > 0
+                && self.index_memory() + cost + inline_cost > max_index_memory
+                && !self.index.contains_key(&key)?
+            {
+                return Err(Error::IndexFull);
+            }
+        }
+
+        let seq = self.seq + 1;
+        let modified = Some(self.epoch.now());
+        let command = Command::Set {
+            key: key.clone(),
+            value,
+            seq,
+            expires,
+            modified,
+        };
+
+        let (offset, length) = self.write(&command)?;
+        self.seq = seq;
+        self.usage.written(self.log_index, length);
+        match command {
+            Command::Set { value, .. } if inline.is_none() => {
+                self.memtable.insert(key.clone(), seq, value);
+            },
+            _ => {},
+        }
+        let new_entry = IndexEntry {
+            log_index: self.log_index,
+            offset,
+            length,
+            seq,
+            expires,
+            modified,
+            value: inline,
+        };
+        self.usage.added(&new_entry);
+        let old_entry = self.index.insert(key.clone(), new_entry)?;
+        untrack_expiry(&mut self.expiries, &key, old_entry.as_ref());
+        self.read_expired.remove(&key);
+        if let Some(old_entry) = old_entry {
+            self.usage.removed(&old_entry);
+            self.uncompacted += old_entry.length;
+        }
+        if let Some(expires) = expires {
+            self.expiries.insert((expires, key));
+        }
+        self.record_write(length);
+
+        if self.needs_compaction() {
+            self.compact_in_background()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a `Rename` command moving the value of `from` (whose index entry is `from_entry`) to
+    /// `to`, and apply it to both keys.
+    ///
+    /// As with removes, the command is synced before it's applied if `from`'s value may already
+    /// be durable (see [`apply_removes`](#method.apply_removes)).
+    fn rename_entry(&mut self, from: Vec<u8>, from_entry: IndexEntry, to: Vec<u8>) -> Result<()> {
+        self.check_writable()?;
+        self.stall_write()?;
+        let cached = match from_entry.value {
+            Some(_) => None,
+            None => self.memtable.get(&from, from_entry.seq),
+        };
+        let value = match cached {
+            Some(value) => value,
+            None => self.readers.read(&from_entry)?,
+        };
+        let inline = inline_value(&value, self.config.inline_values);
+        if let Some(max_index_memory) = self.config.max_index_memory {
+            let cost = self.index.insert_cost(&to);
+            if cost > 0
+                && self.index_memory() + cost > max_index_memory
+                && !self.index.contains_key(&to)?
+            {
+                return Err(Error::IndexFull);
+            }
+        }
+
+        let seq = self.seq + 1;
+        let (expires, modified) = (from_entry.expires, Some(self.epoch.now()));
+        let command = Command::Rename {
+            value,
+            to: to.clone(),
+            from: from.clone(),
+            seq,
+            expires,
+            modified,
+        };
+        let (offset, length) = self.write(&command)?;
+        self.seq = seq;
+        self.usage.written(self.log_index, length);
+        if from_entry.log_index != self.log_index || self.writer.is_synced(*from_entry.offset) {
+            self.writer.sync()?;
+            self.synced_removes += 1;
+        }
+
+        self.index.remove(&from)?;
+        self.memtable.remove(&from);
+        untrack_expiry(&mut self.expiries, &from, Some(&from_entry));
+        self.read_expired.remove(&from);
+        self.usage.removed(&from_entry);
+        self.uncompacted += from_entry.length;
+
+        if let Command::Rename { value, .. } = command {
+            if inline.is_none() {
+                self.memtable.insert(to.clone(), seq, value);
+            }
+        }
+        let new_entry = IndexEntry {
+            log_index: self.log_index,
+            offset,
+            length,
+            seq,
+            expires,
+            modified,
+            value: inline,
+        };
+        self.usage.added(&new_entry);
+        let old_entry = self.index.insert(to.clone(), new_entry)?;
+        untrack_expiry(&mut self.expiries, &to, old_entry.as_ref());
+        self.read_expired.remove(&to);
+        if let Some(old_entry) = old_entry {
+            self.usage.removed(&old_entry);
+            self.uncompacted += old_entry.length;
+        }
+        if let Some(expires) = expires {
+            self.expiries.insert((expires, to));
+        }
+        self.record_write(length);
+
+        if self.needs_compaction() {
+            self.compact_in_background()?;
+        }
+        Ok(())
+    }
+
+    /// Whether there are enough stale commands in the log to compact it.
+    fn needs_compaction(&self) -> bool {
+        match self.config.compaction_ratio {
+            Some((ratio, min_bytes)) => {
+                self.uncompacted >= min_bytes
+                    && self.uncompacted as f64 > ratio * self.usage.bytes() as f64
+            },
+            None => {
+                let threshold = self.config.compaction_threshold;
+                self.uncompacted > threshold.unwrap_or(DEFAULT_COMPACTION_THRESHOLD)
+            },
+        }
+    }
+
+    /// Delay or reject a write if the compaction debt is over the [`WriteStall`] thresholds.
+    fn stall_write(&mut self) -> Result<()> {
+        let stall = match self.config.write_stall {
+            Some(stall) if self.uncompacted > stall.slowdown_bytes => stall,
+            _ => return Ok(()),
+        };
+
+        // Compaction may have been deferred for lack of a job slot, so try again before stalling.
+        if self.needs_compaction() {
+            self.compact_in_background()?;
+        }
+        if self.uncompacted > stall.stop_bytes {
+            self.rejected_writes += 1;
+            self.metrics.counter("kvs_engine_rejected_writes_total", 1);
+            return Err(Error::Backpressure);
+        }
+        if self.uncompacted > stall.slowdown_bytes {
+            self.slowed_writes += 1;
+            self.metrics.counter("kvs_engine_slowed_writes_total", 1);
+            thread::sleep(stall.delay);
+        }
+        Ok(())
+    }
+
+    /// Append a command to the current log file, switching to read-only mode if the disk is full.
+    ///
+    /// If the current file has reached the maximum segment size, a new one is started first. The
+    /// command is then synced (or left for the background flusher) as the [`SyncPolicy`] says.
+    fn write(&mut self, command: &Command) -> Result<(Offset, u64)> {
+        if let Some(max_segment_size) = self.config.max_segment_size {
+            if self.writer.offset() >= max_segment_size {
+                self.rotate()?;
+            }
+        }
+        let result = self.writer.write(command);
+        if let Err(Error::DiskFull) = result {
+            self.enter_read_only()?;
+        }
+        let written = result?;
+        if self.config.sync_policy == SyncPolicy::Always {
+            self.writer.sync()?;
+        }
+        if let Some(flusher) = &self.flusher {
+            flusher.written();
+        }
+        Ok(written)
+    }
+
+    /// Point the background flusher, if there is one, at the current log file.
+    fn watch_writer(&self) {
+        if let Some(flusher) = &self.flusher {
+            flusher.watch(self.writer.group_commit());
+        }
+    }
+
+    /// Sync the current log file and start writing to a new one.
+    ///
+    /// The manifest is updated straight away, so that the new file is known to be part of the
+    /// store.
+    fn rotate(&mut self) -> Result<()> {
+        self.writer.sync()?;
+        let log_index = self.log_index + 1;
+        self.writer = open_writer(&self.path, log_index)?;
+        self.log_index = log_index;
+        self.watch_writer();
+        self.readers.insert(log_index)?;
+        self.rotations += 1;
+        self.metrics.counter("kvs_engine_rotations_total", 1);
+        self.save_manifest()
+    }
+
+    /// Stop accepting writes, and release the reserved disk space so that the store can still be
+    /// compacted and reopened.
+    fn enter_read_only(&mut self) -> Result<()> {
+        self.read_only = true;
+        self.space_checked = Instant::now();
+        reserve::release(&self.path)
+    }
+
+    /// Fail with [`Error::DiskFull`] while the store is read-only, unless disk space has been
+    /// freed since it was last checked.
+    fn check_writable(&mut self) -> Result<()> {
+        if !self.read_only {
+            return Ok(());
+        }
+        if self.space_checked.elapsed() < SPACE_CHECK_INTERVAL {
+            return Err(Error::DiskFull);
+        }
+        self.space_checked = Instant::now();
+
+        // With no reserved space, check that a small probe can be written instead.
+        let reserved_space = self.config.reserved_space.unwrap_or(0);
+        reserve::reserve(&self.path, reserved_space.max(reserve::PROBE_BYTES))?;
+        if reserved_space == 0 {
+            reserve::release(&self.path)?;
+        }
+        self.read_only = false;
+        Ok(())
+    }
+
+    /// Compact the log, if a background job slot is free.
+    ///
+    /// If the disk fills up while compacting, the store switches to read-only mode rather than
+    /// failing.
+    fn compact_in_background(&mut self) -> Result<()> {
+        let slot = match &self.config.background_jobs {
+            Some(jobs) => match jobs.try_start() {
+                Some(slot) => Some(slot),
+                None => {
+                    self.deferred_compactions += 1;
+                    return Ok(());
+                },
+            },
+            None => None,
+        };
+        let max_files = self.config.max_compaction_files.unwrap_or(usize::MAX);
+        match self.compact_oldest(max_files) {
+            Err(Error::DiskFull) => self.enter_read_only()?,
+            result => result?,
+        }
+        drop(slot);
+        Ok(())
+    }
+
+    /// Read the value of every unexpired key in `scanned`, giving up with [`Error::Timeout`] if
+    /// `deadline` passes first.
+    fn read_entries(
+        &mut self,
+        scanned: Vec<(Vec<u8>, IndexEntry)>,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let now = self.epoch.now();
+        let mut entries = Vec::new();
+        for (key, entry) in scanned {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Error::Timeout);
+            }
+            if !is_expired(entry.expires, now) {
+                let value = self.readers.read(&entry)?;
+                entries.push((key, value));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Count an expired key found by a read, and remove it or leave it to be swept (see
+    /// [`remove_expired_on_read`]).
+    ///
+    /// [`remove_expired_on_read`]: struct.KvStoreBuilder.html#method.remove_expired_on_read
+    fn expire_on_read(&mut self, key: Vec<u8>) -> Result<()> {
+        if self.config.remove_expired_on_read && !self.read_only {
+            let counted = self.read_expired.contains(&key);
+            self.remove_entry(key)?;
+            if !counted {
+                self.expired += 1;
+            }
+        } else if self.read_expired.insert(key) {
+            self.expired += 1;
+        }
+        Ok(())
+    }
 
-        let mut uncompacted = 0;
-        let mut index: HashMap<String, IndexEntry> = HashMap::new();
-        let mut readers = HashMap::new();
+    /// The index entry of a key that's in the index and hasn't expired, or
+    /// [`Error::KeyNotFound`].
+    fn live_entry(&mut self, key: Vec<u8>) -> Result<IndexEntry> {
+        let now = self.epoch.now();
+        match self.index.get(&key)? {
+            Some(entry) if !is_expired(entry.expires, now) => Ok(entry),
+            Some(_) => {
+                self.expire_on_read(key)?;
+                Err(Error::KeyNotFound)
+            },
+            None => Err(Error::KeyNotFound),
+        }
+    }
 
-        let log_indices = find_log_indices(&path)?;
+    /// Get the value of a key, or why it wasn't found (see [`Engine::lookup`]).
+    fn lookup_bytes(
+        &mut self,
+        key: &[u8],
+    ) -> Result<std::result::Result<Vec<u8>, Option<NotFoundReason>>> {
+        let now = self.epoch.now();
+        let entry = match self.index.get(key)? {
+            Some(entry) if !is_expired(entry.expires, now) => entry,
+            Some(_) => {
+                self.expire_on_read(key.to_vec())?;
+                return Ok(Err(Some(NotFoundReason::Expired)));
+            },
+            None => return Ok(Err(None)),
+        };
 
-        for &log_index in &log_indices {
-            let mut reader = open_reader(&path, log_index)?;
-            for entry in reader.load()? {
-                uncompacted += open_entry(log_index, &mut index, entry?);
+        self.access.record(key, now);
+        if entry.value.is_none() {
+            if let Some(value) = self.memtable.get(key, entry.seq) {
+                return Ok(Ok(value));
             }
-            readers.insert(log_index, reader);
         }
+        Ok(Ok(self.readers.read(&entry)?))
+    }
 
-        let write_index = *log_indices.last().unwrap_or(&0);
-        let writer = open_writer(&path, write_index)?;
-        if readers.is_empty() {
-            readers.insert(write_index, open_reader(&path, write_index)?);
+    /// Write a `Remove` command for a key that's in the index.
+    fn remove_entry(&mut self, key: Vec<u8>) -> Result<()> {
+        let old_entry = self.index.get(&key)?.expect("Key not found after check");
+        let length = self.write_remove(&key)?;
+        self.apply_removes(vec![(key, old_entry, length)])
+    }
+
+    /// Write a `Remove` command for `key`, without applying it, returning its length.
+    fn write_remove(&mut self, key: &[u8]) -> Result<u64> {
+        self.check_writable()?;
+        let seq = self.seq + 1;
+        let command = Command::Remove { key: key.to_vec(), seq };
+        let (_, length) = self.write(&command)?;
+        self.seq = seq;
+        self.usage.written(self.log_index, length);
+        Ok(length)
+    }
+
+    /// Apply written `Remove` commands, given each key with its old entry and the length of its
+    /// `Remove`.
+    ///
+    /// If any removed value may already be durable, the `Remove`s are synced (once) before
+    /// they're applied. Otherwise a crash could lose a `Remove` but not its value, and the key
+    /// would come back when the log is replayed.
+    fn apply_removes(&mut self, removals: Vec<(Vec<u8>, IndexEntry, u64)>) -> Result<()> {
+        let log_index = self.log_index;
+        let writer = &self.writer;
+        let durable = removals
+            .iter()
+            .filter(|(_, old_entry, _)| {
+                old_entry.log_index != log_index || writer.is_synced(*old_entry.offset)
+            })
+            .count() as u64;
+        if durable > 0 {
+            self.writer.sync()?;
+            self.synced_removes += durable;
+        }
+        for (key, old_entry, length) in removals {
+            self.index.remove(&key)?;
+            self.memtable.remove(&key);
+            untrack_expiry(&mut self.expiries, &key, Some(&old_entry));
+            self.read_expired.remove(&key);
+            self.usage.removed(&old_entry);
+            self.uncompacted += length + old_entry.length;
+            self.record_write(length);
+        }
+        Ok(())
+    }
+
+    /// Write a `Remove` command for each unexpired key in `keys` that's in the index, adding it
+    /// to `removals` with its old entry and the length of its `Remove`.
+    fn write_removes(
+        &mut self,
+        keys: Vec<Vec<u8>>,
+        removals: &mut Vec<(Vec<u8>, IndexEntry, u64)>,
+    ) -> Result<()> {
+        let now = self.epoch.now();
+        let mut seen = HashSet::new();
+        for key in keys {
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+            let old_entry = match self.index.get(&key)? {
+                Some(entry) if !is_expired(entry.expires, now) => entry,
+                Some(_) => {
+                    self.expire_on_read(key)?;
+                    continue;
+                },
+                None => continue,
+            };
+            let length = self.write_remove(&key)?;
+            removals.push((key, old_entry, length));
         }
+        Ok(())
+    }
 
-        Ok(Store {
-            path,
-            log_index: write_index,
-            writer,
-            readers,
-            index,
-            uncompacted,
-        })
+    /// The estimated memory used by the index, including inlined values.
+    fn index_memory(&self) -> u64 {
+        self.index.memory_usage() + self.usage.inlined_bytes()
+    }
+
+    /// Count a write of `length` bytes, and report it to the metrics sink.
+    fn record_write(&mut self, length: u64) {
+        self.written_bytes += length;
+        self.metrics.counter("kvs_engine_writes_total", 1);
+        self.metrics.counter("kvs_engine_written_bytes_total", length);
+        self.metrics.gauge("kvs_engine_uncompacted_bytes", self.uncompacted as f64);
+    }
+
+    /// Statistics for each log file in the store, in log index order.
+    fn file_stats(&self) -> Vec<LogFileStats> {
+        let usage: HashMap<_, _> = self.usage.files().collect();
+        let now = SystemTime::now();
+        self.readers
+            .log_indices()
+            .into_iter()
+            .map(|log_index| {
+                let usage = usage.get(&log_index).cloned().unwrap_or_default();
+                let created = fs::metadata(log_path(&self.path, log_index))
+                    .and_then(|metadata| metadata.created().or_else(|_| metadata.modified()));
+                let age_secs = created
+                    .ok()
+                    .and_then(|created| now.duration_since(created).ok())
+                    .map(|age| age.as_secs())
+                    .unwrap_or(0);
+                LogFileStats {
+                    log_index,
+                    bytes: usage.bytes,
+                    live_bytes: usage.live_bytes,
+                    dead_bytes: usage.bytes.saturating_sub(usage.live_bytes),
+                    age_secs,
+                }
+            })
+            .collect()
+    }
+
+    /// Write the current state of the log to the manifest.
+    fn save_manifest(&self) -> Result<()> {
+        self.manifest().save(&self.path)
     }
 
-    /// Compact the log directory to a single file.
+    /// A manifest for the current state of the log.
     ///
-    /// This will dump the keys and values currently in the index into a new log file and advance
-    /// the current log index/writer to another new log file. It also resets the `uncompacted` count
-    /// as the log will be minimal once `compact` completes.
+    /// The manifest has no clean shutdown marker, so saving it when the store is opened means a
+    /// crash will be detected on the next open.
+    fn manifest(&self) -> Manifest {
+        Manifest {
+            last_seq: self.seq,
+            compacted_index: self.compacted_index,
+            compacted_seq: self.compacted_seq,
+            log_indices: self.readers.log_indices(),
+            clock_epoch: self.epoch.latest,
+            clean_shutdown: None,
+            dictionary: self.dictionary,
+        }
+    }
+
+    /// Compact the whole log to a single file.
     fn compact(&mut self) -> Result<()> {
+        self.compact_oldest(usize::MAX)
+    }
+
+    /// Compact the oldest `max_files` log files (in the order they're replayed) to a single file.
+    ///
+    /// Commands are streamed from each file in turn, and those that the index still points to are
+    /// copied to a new log file, so that memory use doesn't grow with the size of the log. The
+    /// resulting file is free from `Remove` commands or duplicate `Set`s for the same key, and is
+    /// replayed before the files that weren't compacted. Future commands are written to another
+    /// new log file. The `uncompacted` count is reset to the stale bytes left in the files that
+    /// weren't compacted.
+    fn compact_oldest(&mut self, max_files: usize) -> Result<()> {
+        let started_at = self.epoch.now();
+        let timer = Instant::now();
+
+        // The compaction filter may change values without changing their sequence numbers.
+        self.memtable.clear();
+
+        // Choose the files to compact before any new ones are added.
+        let mut compacted = self.readers.log_indices();
+        manifest::replay_order(&mut compacted, self.compacted_index);
+        compacted.truncate(max_files);
+        let compacts_all = compacted.len() == self.readers.len();
+
         // Set up a file for the compacted log.
         let compaction_index = self.log_index + 1;
         let mut compaction_writer = open_writer(&self.path, compaction_index)?;
-        self.readers.insert(compaction_index, open_reader(&self.path, compaction_index)?);
+        self.readers.insert(compaction_index)?;
 
-        // Set up a file for future commands.
+        // Set up a file for future commands, syncing the previous one in case it isn't compacted.
         let write_index = compaction_index + 1;
         let writer = open_writer(&self.path, write_index)?;
+        self.writer.sync()?;
         self.log_index = write_index;
         self.writer = writer;
-        self.readers.insert(self.log_index, open_reader(&self.path, write_index)?);
-
-        // Go through the index and write out a `Command::Set` for each value. The resulting log
-        // file will be free from `Remove` commands or duplicate `Set`s for the same key, making it
-        // minimal.
-        for (key, entry) in self.index.iter_mut() {
-            let reader = self.readers.get_mut(&entry.log_index).expect("Missing reader");
-            let value = reader.read_value(&entry.offset)?;
-            let command = Command::Set { key: key.to_owned(), value };
-            let (offset, length) = compaction_writer.write(&command)?;
-
-            // Update the index in-place with the new details.
-            *entry = IndexEntry {
-                log_index: compaction_index,
-                offset,
-                length
-            };
+        self.watch_writer();
+        self.readers.insert(write_index)?;
+
+        // Copy each live command to the new file as a `Command::Set`, updating the index in-place
+        // with its new location. Entries dropped by the compaction filter aren't written, and are
+        // removed from the index once the compaction has been recorded.
+        //
+        // If a compression dictionary is configured, values are compressed with the one trained by
+        // the last compaction, and sampled to train the next.
+        let max_dictionary_len = self.config.compression_dictionary;
+        let mut compressor = match self.dictionary.filter(|_| max_dictionary_len.is_some()) {
+            Some(id) => Some(self.readers.dictionaries().compressor(id)?),
+            None => None,
+        };
+        let mut samples = Samples::default();
+        let (mut compressed_values, mut uncompressed_bytes, mut compressed_bytes) = (0, 0, 0);
+        let compaction_filter = self.config.compaction_filter.clone();
+        let mut dropped = Vec::new();
+        let mut last_seq = 0;
+        for &log_index in &compacted {
+            let mut reader = open_reader(&self.path, log_index)?;
+            for loaded in reader.load()? {
+                let (command, read_offset, _) = loaded?;
+                last_seq = last_seq.max(command.seq());
+                let key = command.key().to_vec();
+                let location = (log_index, *read_offset);
+                let old_entry = match self.index.get(&key)? {
+                    Some(entry) if (entry.log_index, *entry.offset) == location => entry,
+                    _ => continue,
+                };
+                let mut value = match command {
+                    Command::Set { value, .. } | Command::Rename { value, .. } => value,
+                    Command::SetCompressed { value, dictionary, .. } => {
+                        self.readers.dictionaries().decompress(dictionary, &value.0)?
+                    },
+                    Command::Remove { .. } => continue,
+                    Command::SetPrefixed { .. } => {
+                        unreachable!("Reader::load resolves prefixed commands")
+                    },
+                };
+                let filter = compaction_filter
+                    .as_ref()
+                    .filter(|_| !key.starts_with(SYSTEM_KEY_PREFIX.as_bytes()));
+                if let Some(filter) = filter {
+                    let decision = match (str::from_utf8(&key), str::from_utf8(&value)) {
+                        (Ok(key), Ok(value)) => filter.filter(key, value),
+                        _ => CompactionDecision::Keep,
+                    };
+                    match decision {
+                        CompactionDecision::Keep => {},
+                        CompactionDecision::Remove => {
+                            dropped.push(key);
+                            continue;
+                        },
+                        CompactionDecision::Change(changed) => value = changed.into_bytes(),
+                    }
+                }
+
+                let IndexEntry { seq, expires, modified, .. } = old_entry;
+                let inline = inline_value(&value, self.config.inline_values);
+                if max_dictionary_len.is_some() {
+                    samples.offer(&value);
+                }
+                let compressed = match &mut compressor {
+                    Some(compressor) => {
+                        compressor.compress(&value)?.map(|bytes| (compressor.id(), bytes))
+                    },
+                    None => None,
+                };
+                uncompressed_bytes += value.len() as u64;
+                let (offset, length) = match compressed {
+                    Some((dictionary, bytes)) => {
+                        compressed_values += 1;
+                        compressed_bytes += bytes.len() as u64;
+                        compaction_writer.write(&Command::SetCompressed {
+                            value: Compressed(bytes),
+                            dictionary,
+                            key: key.clone(),
+                            seq,
+                            expires,
+                            modified,
+                        })?
+                    },
+                    None if self.config.prefix_compression => {
+                        compressed_bytes += value.len() as u64;
+                        compaction_writer.write_prefixed(&key, value, seq, expires, modified)?
+                    },
+                    None => {
+                        compressed_bytes += value.len() as u64;
+                        let command = Command::Set {
+                            key: key.clone(),
+                            value,
+                            seq,
+                            expires,
+                            modified,
+                        };
+                        compaction_writer.write(&command)?
+                    },
+                };
+
+                let new_entry = IndexEntry {
+                    log_index: compaction_index,
+                    offset,
+                    length,
+                    seq,
+                    expires,
+                    modified,
+                    value: inline,
+                };
+                self.usage.removed(&old_entry);
+                self.usage.written(compaction_index, length);
+                self.usage.added(&new_entry);
+                self.index.insert(key, new_entry)?;
+            }
         }
 
-        // Delete the log files that are now redundant.
-        let old_log_indices: Vec<_> = self
-            .readers
-            .keys()
-            .filter(|&&log_index| log_index < compaction_index)
-            .cloned()
-            .collect();
-        for old_index in old_log_indices {
-            fs::remove_file(log_path(&self.path, old_index))?;
-            self.readers.remove(&old_index);
+        // Record the compaction in the manifest before deleting anything, so that an interrupted
+        // compaction never loses commands.
+        //
+        // The newly trained dictionary is saved first, so that the manifest never refers to a
+        // missing one. If training fails, the next compaction carries on with the current one.
+        compaction_writer.sync()?;
+        let used_dictionary = compressor.as_ref().map(|compressor| compressor.id());
+        match max_dictionary_len.and_then(|max_len| samples.train(max_len)) {
+            Some(dictionary) => {
+                let dictionaries = self.readers.dictionaries_mut();
+                dictionaries.save(&self.path, compaction_index, dictionary)?;
+                self.dictionary = Some(compaction_index);
+            },
+            None if max_dictionary_len.is_none() => self.dictionary = None,
+            None => {},
+        }
+        self.compacted_index = Some(compaction_index);
+        self.compacted_seq = if compacts_all { self.seq } else { last_seq };
+        self.readers.retain(|log_index| !compacted.contains(&log_index));
+        self.usage.retain(|log_index| !compacted.contains(&log_index));
+
+        // Followers can't read the manifest until the files it no longer lists are deleted, so
+        // that they never try to open one.
+        let lock = DirLock::exclusive(&self.path)?;
+        self.save_manifest()?;
+        for key in dropped {
+            let old_entry = self.index.remove(&key)?;
+            untrack_expiry(&mut self.expiries, &key, old_entry.as_ref());
+            if let Some(old_entry) = &old_entry {
+                self.usage.removed(old_entry);
+            }
+            self.read_expired.remove(&key);
+        }
+
+        // Delete the log files that are now redundant, and the dictionaries only they used. Only
+        // the file written by a compaction has compressed values, so it's the only file that can
+        // use a dictionary other than the current one.
+        for old_index in find_log_indices(&self.path)? {
+            if old_index < compaction_index && !self.readers.contains(old_index) {
+                fs::remove_file(log_path(&self.path, old_index))?;
+            }
         }
+        let current_dictionary = self.dictionary;
+        self.readers.dictionaries_mut().retain(&self.path, |id| {
+            Some(id) == used_dictionary || Some(id) == current_dictionary
+        })?;
+        drop(lock);
+        self.compressed_values = compressed_values;
+        self.uncompressed_value_bytes = uncompressed_bytes;
+        self.compressed_value_bytes = compressed_bytes;
 
-        // Reset the number of uncompacted bytes (if we don't do this `compact` will be called on
-        // every subsequent call to `set` - not good).
-        self.uncompacted = 0;
+        // Reset the number of uncompacted bytes to those left in the files that weren't compacted
+        // (if we don't do this `compact` will be called on every subsequent call to `set` - not
+        // good).
+        let uncompacted = self
+            .usage
+            .files()
+            .map(|(_, file)| file.bytes.saturating_sub(file.live_bytes))
+            .sum();
+        let reclaimed = self.uncompacted.saturating_sub(uncompacted);
+        self.compactions += 1;
+        self.compacted_bytes += reclaimed;
+        if self.recent_compactions.len() == RECENT_COMPACTIONS {
+            self.recent_compactions.pop_front();
+        }
+        self.recent_compactions.push_back(CompactionStats {
+            started_at,
+            finished_at: self.epoch.now(),
+            reclaimed_bytes: reclaimed,
+        });
+        self.metrics.counter("kvs_engine_compactions_total", 1);
+        self.metrics.counter("kvs_engine_compacted_bytes_total", reclaimed);
+        self.metrics.histogram("kvs_engine_compaction_seconds", timer.elapsed().as_secs_f64());
+        self.metrics.gauge("kvs_engine_uncompacted_bytes", uncompacted as f64);
+        self.uncompacted = uncompacted;
 
         Ok(())
     }
@@ -170,13 +1627,20 @@ impl Engine for Store {
     /// # }
     /// ```
     fn get(&mut self, key: String) -> Result<Option<String>> {
-        let entry = match self.index.get(&key) {
-            Some(entry) => entry,
-            None => return Ok(None),
-        };
+        Ok(self.lookup(key)?.ok())
+    }
 
-        let reader = self.readers.get_mut(&entry.log_index).expect("Missing reader");
-        Ok(Some(reader.read_value(&entry.offset)?))
+    /// Get the value of a key in a store, or [`Expired`] if it expired but hasn't been swept.
+    ///
+    /// [`Expired`]: enum.NotFoundReason.html#variant.Expired
+    fn lookup(
+        &mut self,
+        key: String,
+    ) -> Result<std::result::Result<String, Option<NotFoundReason>>> {
+        match self.lookup_bytes(key.as_bytes())? {
+            Ok(value) => Ok(Ok(utf8_value(&key, value)?)),
+            Err(reason) => Ok(Err(reason)),
+        }
     }
 
     /// Set a key to a value in a store.
@@ -192,29 +1656,130 @@ impl Engine for Store {
     /// # }
     /// ```
     fn set(&mut self, key: String, value: String) -> Result<()> {
-        let command = Command::Set {
-            key: key.clone(),
-            value: value.clone(),
-        };
+        self.set_entry(key.into_bytes(), value.into_bytes(), None)
+    }
 
-        let (offset, length) = self.writer.write(&command)?;
-        let new_entry = IndexEntry {
-            log_index: self.log_index,
-            offset,
-            length,
-        };
-        if let Some(old_entry) = self.index.insert(key, new_entry) {
-            self.uncompacted += old_entry.length;
+    /// Set a key to a value in a store, expiring after `ttl`.
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use std::time::Duration;
+    /// # use kvs::{KvsEngine, KvStore, Result};
+    /// # fn check() -> Result<()> {
+    /// # let path = PathBuf::new();
+    /// let mut store = KvStore::open(path)?;
+    /// store.set_with_ttl("foo".to_owned(), "bar".to_owned(), Duration::from_secs(60))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        let expires = self.epoch.now().saturating_add(ttl.as_millis() as u64);
+        self.set_entry(key.into_bytes(), value.into_bytes(), Some(expires))
+    }
+
+    /// Remove a key (and its value) from a store.
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use kvs::{KvsEngine, KvStore, Result};
+    /// # fn check() -> Result<()> {
+    /// # let path = PathBuf::new();
+    /// let mut store = KvStore::open(path)?;
+    /// store.remove("foo".to_owned())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.remove_bytes(key.as_bytes())
+    }
+
+    fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.lookup_bytes(key)?.ok())
+    }
+
+    fn set_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.set_entry(key.to_vec(), value.to_vec(), None)
+    }
+
+    fn remove_bytes(&mut self, key: &[u8]) -> Result<()> {
+        let now = self.epoch.now();
+        match self.index.get(key)? {
+            Some(entry) if !is_expired(entry.expires, now) => self.remove_entry(key.to_vec()),
+            Some(_) => {
+                self.expire_on_read(key.to_vec())?;
+                Err(Error::KeyNotFound)
+            },
+            None => Err(Error::KeyNotFound),
         }
+    }
 
-        if self.uncompacted > COMPACTION_THRESHOLD {
-            self.compact()?;
+    /// Rename a key in a store, keeping its time-to-live.
+    ///
+    /// The rename is written to the log as a single command, so it's never half applied.
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use kvs::{KvsEngine, KvStore, Result};
+    /// # fn check() -> Result<()> {
+    /// # let path = PathBuf::new();
+    /// let mut store = KvStore::open(path)?;
+    /// store.rename("draft:1".to_owned(), "post:1".to_owned(), false)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn rename(&mut self, from: String, to: String, overwrite: bool) -> Result<bool> {
+        let (from, to) = (from.into_bytes(), to.into_bytes());
+        let now = self.epoch.now();
+        let from_entry = match self.index.get(&from)? {
+            Some(entry) if !is_expired(entry.expires, now) => entry,
+            Some(_) => {
+                self.expire_on_read(from)?;
+                return Err(Error::KeyNotFound);
+            },
+            None => return Err(Error::KeyNotFound),
+        };
+        if from == to {
+            return Ok(true);
+        }
+        if !overwrite {
+            match self.index.get(&to)? {
+                Some(entry) if !is_expired(entry.expires, now) => return Ok(false),
+                _ => {},
+            }
         }
+        self.rename_entry(from, from_entry, to)?;
+        Ok(true)
+    }
 
-        Ok(())
+    /// Sync a store's log to disk.
+    fn sync(&mut self) -> Result<()> {
+        self.writer.sync()
     }
 
-    /// Remove a key (and its value) from a store.
+    /// A point at the end of the current log file: earlier log files are synced as the store
+    /// moves on from them.
+    fn sync_point(&mut self) -> Option<SyncPoint> {
+        Some(self.writer.sync_point())
+    }
+
+    /// Remove several keys from a store, syncing at most once for the whole batch.
+    ///
+    /// If writing a `Remove` fails, the keys before it are still removed.
+    fn remove_many(&mut self, keys: Vec<String>) -> Result<Vec<String>> {
+        let keys = keys.into_iter().map(String::into_bytes).collect();
+        let mut removals = Vec::new();
+        let written = self.write_removes(keys, &mut removals);
+        let removed = removals.iter().map(|(key, _, _)| lossy_key(key.clone())).collect();
+        self.apply_removes(removals)?;
+        written?;
+        Ok(removed)
+    }
+
+    /// Get every key starting with `prefix` from a store, with its value, in key order.
+    ///
+    /// Fails if a matching key or value isn't UTF-8 (see [`scan_bytes`]).
+    ///
+    /// [`scan_bytes`]: trait.KvsEngine.html#method.scan_bytes
     ///
     /// ```
     /// # use std::path::PathBuf;
@@ -222,29 +1787,383 @@ impl Engine for Store {
     /// # fn check() -> Result<()> {
     /// # let path = PathBuf::new();
     /// let mut store = KvStore::open(path)?;
-    /// store.remove("foo".to_owned())?;
+    /// for (key, value) in store.scan("user:")? {
+    ///     println!("{} = {}", key, value);
+    /// }
     /// # Ok(())
     /// # }
     /// ```
-    fn remove(&mut self, key: String) -> Result<()> {
-        if !self.index.contains_key(&key) {
-            return Err(Error::KeyNotFound);
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let scanned = self.index.scan(prefix.as_bytes())?;
+        utf8_entries(self.read_entries(scanned, None)?)
+    }
+
+    fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let scanned = self.index.scan(prefix)?;
+        self.read_entries(scanned, None)
+    }
+
+    /// Get every key from `start` (inclusive) to `end` (exclusive, or unbounded if `None`) from a
+    /// store, with its value, in key order.
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use kvs::{KvsEngine, KvStore, Result};
+    /// # fn check() -> Result<()> {
+    /// # let path = PathBuf::new();
+    /// let mut store = KvStore::open(path)?;
+    /// for (key, value) in store.scan_range("user:1", Some("user:5"))? {
+    ///     println!("{} = {}", key, value);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn scan_range(&mut self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>> {
+        let scanned = self.index.scan_range(start.as_bytes(), end.map(str::as_bytes))?;
+        utf8_entries(self.read_entries(scanned, None)?)
+    }
+
+    /// Get every key starting with `prefix` from a store, with its value, in key order, giving up
+    /// with [`Error::Timeout`] if `deadline` passes before every value has been read.
+    ///
+    /// [`Error::Timeout`]: enum.Error.html#variant.Timeout
+    fn scan_until(&mut self, prefix: &str, deadline: Instant) -> Result<Vec<(String, String)>> {
+        let scanned = self.index.scan(prefix.as_bytes())?;
+        utf8_entries(self.read_entries(scanned, Some(deadline))?)
+    }
+
+    /// Get the time left before a key in a store expires, or `None` if it doesn't expire.
+    fn ttl(&mut self, key: String) -> Result<Option<Duration>> {
+        let now = self.epoch.now();
+        let entry = self.live_entry(key.into_bytes())?;
+        Ok(entry.expires.map(|expires| Duration::from_millis(expires - now)))
+    }
+
+    /// Make a key in a store expire after `ttl`, by writing its value again with the new expiry.
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use std::time::Duration;
+    /// # use kvs::{KvsEngine, KvStore, Result};
+    /// # fn check() -> Result<()> {
+    /// # let path = PathBuf::new();
+    /// let mut store = KvStore::open(path)?;
+    /// store.expire("session:1".to_owned(), Duration::from_secs(60))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn expire(&mut self, key: String, ttl: Duration) -> Result<()> {
+        let key = key.into_bytes();
+        let value = self.lookup_bytes(&key)?.map_err(|_| Error::KeyNotFound)?;
+        let expires = self.epoch.now().saturating_add(ttl.as_millis() as u64);
+        self.set_entry(key, value, Some(expires))
+    }
+
+    /// Stop a key in a store from expiring, by writing its value again without an expiry (unless
+    /// it already has none).
+    fn persist(&mut self, key: String) -> Result<()> {
+        let key = key.into_bytes();
+        if self.live_entry(key.clone())?.expires.is_none() {
+            return Ok(());
+        }
+        let value = self.lookup_bytes(&key)?.map_err(|_| Error::KeyNotFound)?;
+        self.set_entry(key, value, None)
+    }
+
+    /// Remove up to `limit` expired keys from a store, soonest-expired first.
+    ///
+    /// Keys that aren't UTF-8 are returned with their invalid bytes replaced.
+    fn sweep_expired(&mut self, limit: usize) -> Result<Vec<String>> {
+        let now = self.epoch.now();
+        let mut expired = Vec::new();
+        while expired.len() < limit {
+            let key = match self.expiries.iter().next() {
+                Some((expires, key)) if *expires <= now => key.clone(),
+                _ => break,
+            };
+            let counted = self.read_expired.contains(&key);
+            self.remove_entry(key.clone())?;
+            if !counted {
+                self.expired += 1;
+            }
+            expired.push(lossy_key(key));
+        }
+        Ok(expired)
+    }
+
+    /// Re-read up to `limit` entries from the log in key order, continuing from where the last
+    /// scrub stopped, and check that each command still decodes and matches its index entry.
+    fn scrub(&mut self, limit: usize) -> Result<Vec<String>> {
+        let start = self.scrub_cursor.take().unwrap_or_default();
+        let mut scanned = self.index.scan_range(&start, None)?;
+        if scanned.len() > limit {
+            self.scrub_cursor = Some(scanned[limit].0.clone());
+            scanned.truncate(limit);
+        } else {
+            self.scrub_passes += 1;
+        }
+
+        let mut corrupt = Vec::new();
+        for (key, entry) in scanned {
+            let command = self.readers.get(entry.log_index)?.read_command(&entry.offset);
+            let intact = match command {
+                Ok(command) => is_intact(&key, &entry, command, self.readers.dictionaries()),
+                Err(Error::Decode(_)) | Err(Error::Corruption { .. }) => false,
+                Err(err) => return Err(err),
+            };
+            self.scrubbed += 1;
+            if !intact {
+                self.corrupt += 1;
+                corrupt.push(lossy_key(key));
+            }
+        }
+        Ok(corrupt)
+    }
+
+    /// Count the unexpired keys in the index starting with `prefix`, without reading from the log.
+    fn count_prefix(&mut self, prefix: &str) -> Result<u64> {
+        let now = self.epoch.now();
+        let entries = self.index.scan(prefix.as_bytes())?;
+        let live = entries.iter().filter(|(key, entry)| {
+            !is_expired(entry.expires, now) && !key.starts_with(SYSTEM_KEY_PREFIX.as_bytes())
+        });
+        Ok(live.count() as u64)
+    }
+
+    /// Remove every unexpired key starting with `prefix` from a store, except system keys,
+    /// without reading their values.
+    fn remove_prefix(&mut self, prefix: &str) -> Result<Vec<String>> {
+        let now = self.epoch.now();
+        let mut removed = Vec::new();
+        for (key, entry) in self.index.scan(prefix.as_bytes())? {
+            if !is_expired(entry.expires, now) && !key.starts_with(SYSTEM_KEY_PREFIX.as_bytes()) {
+                self.remove_entry(key.clone())?;
+                removed.push(lossy_key(key));
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Find unexpired keys that haven't been read or written for `unread_for`, using the index's
+    /// modification times and an approximate record of reads (see [`KvStore`]).
+    fn cold_keys(
+        &mut self,
+        prefix: &str,
+        unread_for: Duration,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let now = self.epoch.now();
+        let cutoff = now.saturating_sub(unread_for.as_millis() as u64);
+        let mut cold = Vec::new();
+        for (key, entry) in self.index.scan(prefix.as_bytes())? {
+            if cold.len() == limit {
+                break;
+            }
+            let last_access = self.access.last_read(&key).max(entry.modified.unwrap_or(0));
+            if last_access < cutoff
+                && !is_expired(entry.expires, now)
+                && !key.starts_with(SYSTEM_KEY_PREFIX.as_bytes())
+            {
+                cold.push(lossy_key(key));
+            }
+        }
+        Ok(cold)
+    }
+
+    /// Offer every unexpired key in the index to `sample`, without reading from the log.
+    fn offer_keys(&mut self, sample: &mut KeySample) -> Result<()> {
+        let now = self.epoch.now();
+        self.index.try_for_each(|key, entry| {
+            if !is_expired(entry.expires, now) {
+                sample.offer(&String::from_utf8_lossy(key));
+            }
+            Ok(())
+        })
+    }
+
+    fn name(&self) -> &str {
+        "kvs"
+    }
+
+    /// Compact the log (whether or not a [background job] slot is free), then read back every
+    /// live value to verify it.
+    ///
+    /// [background job]: struct.BackgroundJobs.html
+    fn maintain(&mut self) -> Result<()> {
+        self.compact()?;
+        for (_, entry) in self.index.scan(&[])? {
+            self.readers.read_log(&entry)?;
+        }
+        Ok(())
+    }
+
+    fn stats(&mut self) -> Result<EngineStats> {
+        Ok(EngineStats {
+            keys: self.index.len() as u64,
+            index_bytes: self.index_memory(),
+            log_files: self.readers.len() as u64,
+            open_files: self.readers.open_len() as u64,
+            file_evictions: self.readers.evictions(),
+            uncompacted_bytes: self.uncompacted,
+            log_bytes: self.usage.bytes(),
+            files: self.file_stats(),
+            compactions: self.compactions,
+            recent_compactions: self.recent_compactions.iter().cloned().collect(),
+            compacted_bytes: self.compacted_bytes,
+            written_bytes: self.written_bytes,
+            deferred_compactions: self.deferred_compactions,
+            slowed_writes: self.slowed_writes,
+            rejected_writes: self.rejected_writes,
+            synced_removes: self.synced_removes,
+            recovered: self.recovered,
+            read_only: self.read_only,
+            sequence: self.seq,
+            replay_duplicates: self.replay_duplicates,
+            expiring_keys: self.expiries.len() as u64,
+            expired_keys: self.expired,
+            memtable_hits: self.memtable.hits(),
+            scrubbed_entries: self.scrubbed,
+            scrub_passes: self.scrub_passes,
+            corrupt_entries: self.corrupt,
+            dictionary_bytes: self.dictionary.map_or(0, |id| {
+                self.readers.dictionaries().len_of(id)
+            }),
+            compressed_values: self.compressed_values,
+            uncompressed_value_bytes: self.uncompressed_value_bytes,
+            compressed_value_bytes: self.compressed_value_bytes,
+            rotations: self.rotations,
+            follower_rebuilds: 0,
+            torn_tail_bytes: self.torn_tail_bytes,
+            failed_syncs: self.flusher.as_ref().map_or(0, Flusher::failures),
+        })
+    }
+}
+
+impl Drop for Store {
+    /// Record the last sequence number in the manifest, so that commands lost from the end of the
+    /// log can be detected when the store is next opened.
+    ///
+    /// If the log can be synced, the manifest also gets a clean shutdown marker so that the next
+    /// open can skip crash recovery.
+    fn drop(&mut self) {
+        let mut manifest = self.manifest();
+        if self.writer.sync().is_ok() {
+            manifest.clean_shutdown = Some(CleanShutdown {
+                last_seq: self.seq,
+                log_index: self.log_index,
+                log_bytes: self.writer.offset(),
+            });
+        }
+        let _ = manifest.save(&self.path);
+        let _ = self.access.save(&self.path);
+    }
+}
+
+/// Tracks a store's view of the time, which never goes backwards.
+struct Epoch {
+    clock: Arc<dyn Clock>,
+    latest: u64,
+}
+
+impl Epoch {
+    /// The current time, in milliseconds since the UNIX epoch, or the latest time seen if the
+    /// clock has gone backwards since.
+    fn now(&mut self) -> u64 {
+        self.latest = self.latest.max(self.clock.now_millis());
+        self.latest
+    }
+}
+
+/// Tracks sequence numbers while replaying a log, so that each command is applied exactly once.
+struct Sequence {
+    last: u64,
+    duplicates: u64,
+    trusted: bool,
+}
+
+impl Sequence {
+    fn new(manifest: &Manifest) -> Self {
+        Sequence {
+            last: manifest.compacted_seq,
+            duplicates: 0,
+            trusted: false,
+        }
+    }
+
+    /// Check whether a command read from the log should be applied.
+    ///
+    /// Commands in the compacted file (which holds one command per live key) and commands without
+    /// sequence numbers are always applied. Other commands are skipped if they have already been
+    /// applied (e.g. if they were left behind by an interrupted compaction), and must otherwise
+    /// follow on from the last. If they don't, [`Error::SequenceGap`] is returned,
+    /// after which the gap is accepted so that checking can continue.
+    ///
+    /// Gaps aren't checked for if the sequence is trusted, i.e. the log is known to be consistent.
+    fn check(&mut self, seq: u64, is_compacted: bool) -> Result<bool> {
+        if is_compacted || seq == 0 {
+            return Ok(true);
         }
+        if seq <= self.last {
+            self.duplicates += 1;
+            return Ok(false);
+        }
+        let expected = self.last + 1;
+        self.last = seq;
+        if seq != expected && !self.trusted {
+            return Err(Error::SequenceGap { expected, found: seq });
+        }
+        Ok(true)
+    }
 
-        let command = Command::Remove { key: key.clone() };
-        self.writer.write(&command)?;
-        let old_entry = self.index.remove(&key).expect("Key not found after check");
-        self.uncompacted += old_entry.length;
+    /// Check that the log contained every command up to the manifest's last sequence number.
+    ///
+    /// If a command torn by a crash was discarded from the end of the log, its sequence number
+    /// was lost with it, so the log may end one command short of the manifest. Any larger gap
+    /// means commands before the torn one are missing too.
+    fn finish(&self, manifest: &Manifest, torn: bool) -> Result<()> {
+        let lost = if torn { 1 } else { 0 };
+        if manifest.last_seq.saturating_sub(lost) > self.last {
+            return Err(Error::SequenceGap {
+                expected: manifest.last_seq - lost,
+                found: self.last,
+            });
+        }
         Ok(())
     }
 }
 
 fn open_writer<P: AsRef<Path>>(path: P, log_index: u64) -> Result<Writer> {
-    Writer::init(OpenOptions::new().create(true).write(true).open(log_path(path, log_index))?)
+    Writer::init(OpenOptions::new().create(true).append(true).open(log_path(path, log_index))?)
+}
+
+/// Truncate the log file `log_index` at `offset`, where a corrupt command starts, returning the
+/// number of bytes discarded, if the command was torn by a crash (see [`Reader::is_torn_tail`]).
+/// Otherwise the corruption is returned as an error.
+fn truncate_torn_tail(
+    path: &Path,
+    reader: &mut Reader,
+    log_index: u64,
+    offset: u64,
+) -> Result<u64> {
+    let log_path = log_path(path, log_index);
+    if !reader.is_torn_tail(offset)? {
+        return Err(Error::Corruption { file: log_path, offset });
+    }
+    let file = OpenOptions::new().write(true).open(&log_path)?;
+    let discarded = file.metadata()?.len() - offset;
+    file.set_len(offset)?;
+    file.sync_all()?;
+    Ok(discarded)
 }
 
 fn open_reader<P: AsRef<Path>>(path: P, log_index: u64) -> Result<Reader> {
-    Ok(Reader::new(File::open(log_path(path, log_index))?))
+    let path = log_path(path, log_index);
+    Reader::new(File::open(&path)?, path)
+}
+
+/// Whether `dir` contains a store, i.e. any log files or a manifest.
+pub fn contains_store(dir: &Path) -> Result<bool> {
+    Ok(dir.join(MANIFEST_FILE).is_file() || !find_log_indices(dir)?.is_empty())
 }
 
 fn find_log_indices<P: AsRef<Path>>(path: P) -> Result<Vec<u64>> {
@@ -260,30 +2179,121 @@ fn find_log_indices<P: AsRef<Path>>(path: P) -> Result<Vec<u64>> {
 
 fn open_entry(
     log_index: u64,
-    index: &mut HashMap<String, IndexEntry>,
+    index: &mut Index,
+    expiries: &mut BTreeSet<(u64, Vec<u8>)>,
+    usage: &mut Usage,
+    dictionaries: &Dictionaries,
+    max_inline: Option<usize>,
     (command, offset, length): (Command, Offset, u64),
-) -> u64 {
+) -> Result<u64> {
+    let command = match command {
+        // Compressed values are only decompressed if they might be inlined.
+        Command::SetCompressed { value, dictionary, key, seq, expires, modified } => {
+            let value = match max_inline {
+                Some(_) => dictionaries.decompress(dictionary, &value.0)?,
+                None => Vec::new(),
+            };
+            Command::Set { key, value, seq, expires, modified }
+        },
+        command => command,
+    };
     match command {
-        Command::Set { key, .. } => {
+        Command::Set { key, value, seq, expires, modified } => {
             let new_entry = IndexEntry {
                 log_index,
                 offset,
                 length,
+                seq,
+                expires,
+                modified,
+                value: inline_value(&value, max_inline),
             };
-            if let Some(old_entry) = index.get_mut(&key) {
-                mem::replace(old_entry, new_entry);
-                old_entry.length
-            } else {
-                index.insert(key, new_entry);
-                0
+            usage.added(&new_entry);
+            let old_entry = index.insert(key.clone(), new_entry)?;
+            untrack_expiry(expiries, &key, old_entry.as_ref());
+            if let Some(old_entry) = &old_entry {
+                usage.removed(old_entry);
+            }
+            if let Some(expires) = expires {
+                expiries.insert((expires, key));
             }
+            Ok(old_entry.map(|e| e.length).unwrap_or(0))
         },
-        Command::Remove { key } => {
-            length + index.remove(&key).map(|e| e.length).unwrap_or(0)
-        }
+        Command::Remove { key, .. } => {
+            let old_entry = index.remove(&key)?;
+            untrack_expiry(expiries, &key, old_entry.as_ref());
+            if let Some(old_entry) = &old_entry {
+                usage.removed(old_entry);
+            }
+            Ok(length + old_entry.map(|e| e.length).unwrap_or(0))
+        },
+        // A rename is applied as a removal of `from` (which takes up no space of its own) and a
+        // set of `to`.
+        Command::Rename { value, to, from, seq, expires, modified } => {
+            let remove = (Command::Remove { key: from, seq }, offset.clone(), 0);
+            let set = (Command::Set { key: to, value, seq, expires, modified }, offset, length);
+            let mut uncompacted = 0;
+            for entry in [remove, set] {
+                uncompacted +=
+                    open_entry(log_index, index, expiries, usage, dictionaries, max_inline, entry)?;
+            }
+            Ok(uncompacted)
+        },
+        Command::SetPrefixed { .. } => unreachable!("Reader::load resolves prefixed commands"),
+        Command::SetCompressed { .. } => unreachable!("Compressed commands were converted above"),
+    }
+}
+
+/// A copy of `value` to keep in its index entry, if it's no longer than `max_len`.
+fn inline_value(value: &[u8], max_len: Option<usize>) -> Option<Vec<u8>> {
+    max_len.filter(|&max_len| value.len() <= max_len).map(|_| value.to_vec())
+}
+
+/// Stop tracking the expiry of a key's replaced or removed index entry, if it had one.
+fn untrack_expiry(
+    expiries: &mut BTreeSet<(u64, Vec<u8>)>,
+    key: &[u8],
+    old_entry: Option<&IndexEntry>,
+) {
+    if let Some(expires) = old_entry.and_then(|entry| entry.expires) {
+        expiries.remove(&(expires, key.to_vec()));
     }
 }
 
+/// Whether a key that expires at `expires` has expired at `now`.
+fn is_expired(expires: Option<u64>, now: u64) -> bool {
+    expires.is_some_and(|expires| expires <= now)
+}
+
+/// Whether `command`, read back from the log for `key`, still matches its index `entry`.
+///
+/// Only the suffix of a [`Command::SetPrefixed`] key can be checked, since the rest comes from the
+/// command before it.
+///
+/// A compressed value must decompress with its dictionary.
+fn is_intact(
+    key: &[u8],
+    entry: &IndexEntry,
+    command: Command,
+    dictionaries: &Dictionaries,
+) -> bool {
+    let (value, seq) = match command {
+        Command::Set { value, key: command_key, seq, .. } if command_key == key => (value, seq),
+        Command::SetPrefixed { value, suffix, seq, .. } if key.ends_with(&suffix) => (value, seq),
+        Command::Rename { value, to, seq, .. } if to == key => (value, seq),
+        Command::SetCompressed { value, dictionary, key: command_key, seq, .. }
+            if command_key == key =>
+        {
+            match dictionaries.decompress(dictionary, &value.0) {
+                Ok(value) => (value, seq),
+                Err(_) => return false,
+            }
+        },
+        _ => return false,
+    };
+    seq == entry.seq && entry.value.as_ref().is_none_or(|inline| *inline == value)
+}
+
 fn log_path<P: AsRef<Path>>(dir: P, index: u64) -> PathBuf {
     dir.as_ref().join(format!("{}.log", index))
 }
@@ -0,0 +1,94 @@
+use rmp_serde::decode::from_slice as decode_mp;
+use rmp_serde::encode::to_vec as encode_mp;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::Result;
+
+/// The name of the file a store's access sketch is saved to when it's closed.
+const ACCESS_FILE: &str = "ACCESS";
+
+/// The number of cells in the sketch (using 256KiB of memory).
+const CELLS: usize = 1 << 16;
+
+/// The number of cells each key maps to.
+const HASHES: u64 = 4;
+
+/// The resolution of the recorded times.
+const MINUTE_MILLIS: u64 = 60 * 1000;
+
+/// An approximate record of when each key was last read, in a fixed amount of memory.
+///
+/// Each key maps to several cells, and each read of the key stores the time (in minutes) in all of
+/// them. A key's last read is taken from the earliest of its cells, so collisions with more
+/// recently read keys can only make a key seem to have been read more recently than it was, never
+/// less. Reads are only known from when tracking started, so keys that haven't been read since
+/// then are treated as having been read when it started.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccessSketch {
+    started: u32,
+    cells: Vec<u32>,
+}
+
+impl AccessSketch {
+    /// Start tracking reads at `now` (in milliseconds since the UNIX epoch).
+    pub fn new(now: u64) -> Self {
+        AccessSketch { started: to_minutes(now), cells: vec![0; CELLS] }
+    }
+
+    /// Load the sketch saved in `dir`, or start tracking reads at `now` if there isn't one (or it
+    /// can't be read).
+    pub fn load(dir: &Path, now: u64) -> Self {
+        fs::read(dir.join(ACCESS_FILE))
+            .ok()
+            .and_then(|bytes| decode_mp::<AccessSketch>(&bytes).ok())
+            .filter(|sketch| sketch.cells.len() == CELLS)
+            .unwrap_or_else(|| AccessSketch::new(now))
+    }
+
+    /// Atomically replace the sketch saved in `dir`.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let temp_path = dir.join(format!("{}.tmp", ACCESS_FILE));
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&encode_mp(self)?)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, dir.join(ACCESS_FILE))?;
+        Ok(())
+    }
+
+    /// Record a read of `key` at `now`.
+    pub fn record(&mut self, key: &[u8], now: u64) {
+        let now = to_minutes(now);
+        for cell in cells(key) {
+            self.cells[cell] = self.cells[cell].max(now);
+        }
+    }
+
+    /// When `key` was last read (at the latest), in milliseconds since the UNIX epoch.
+    pub fn last_read(&self, key: &[u8]) -> u64 {
+        let last_read = cells(key).map(|cell| self.cells[cell]).min().unwrap_or(0);
+        u64::from(last_read.max(self.started)) * MINUTE_MILLIS
+    }
+}
+
+/// The cells that `key` maps to.
+///
+/// Keys are hashed as strings (lossily, if they aren't UTF-8), so that sketches saved before keys
+/// were bytes still map keys to the same cells.
+fn cells(key: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    let key = String::from_utf8_lossy(key);
+    (0..HASHES).map(move |seed| {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish() as usize % CELLS
+    })
+}
+
+fn to_minutes(millis: u64) -> u32 {
+    (millis / MINUTE_MILLIS) as u32
+}
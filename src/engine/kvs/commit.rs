@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::error::Result;
+
+/// Syncs a log file on behalf of every thread waiting for it to be durable, so that threads
+/// syncing at the same time share a single sync (a group commit).
+///
+/// The first thread to sync becomes the leader: it syncs everything written to the file so far,
+/// without holding the lock, so that writes carry on meanwhile. Threads that sync in the meantime
+/// wait for it, and are woken when it's done. Those whose writes it covered return straight away,
+/// and one of the rest leads the next sync.
+pub struct GroupCommit {
+    file: File,
+    state: Mutex<State>,
+    synced: Condvar,
+}
+
+struct State {
+    /// The length of the file, as far as the writer has told us.
+    written: u64,
+    /// How much of the file is known to be durable.
+    synced: u64,
+    /// Whether a leader is syncing.
+    syncing: bool,
+    /// The number of times the file has been truncated, so a leader can tell that what it synced
+    /// may since have been overwritten.
+    truncations: u64,
+}
+
+impl GroupCommit {
+    /// Coordinate syncs of `file`, which is `offset` bytes long and durable up to there.
+    pub fn new(file: File, offset: u64) -> Self {
+        GroupCommit {
+            file,
+            state: Mutex::new(State {
+                written: offset,
+                synced: offset,
+                syncing: false,
+                truncations: 0,
+            }),
+            synced: Condvar::new(),
+        }
+    }
+
+    /// Record that the file has been written up to `offset`.
+    pub fn written(&self, offset: u64) {
+        self.lock().written = offset;
+    }
+
+    /// Record that the file has been truncated at `offset`.
+    pub fn truncated(&self, offset: u64) {
+        let mut state = self.lock();
+        state.written = offset;
+        state.synced = state.synced.min(offset);
+        state.truncations += 1;
+    }
+
+    /// Whether the command at `offset` is known to be durable.
+    pub fn is_synced(&self, offset: u64) -> bool {
+        offset < self.lock().synced
+    }
+
+    /// Make everything written so far durable.
+    pub fn sync(&self) -> Result<()> {
+        let written = self.lock().written;
+        self.sync_to(written)
+    }
+
+    /// Make the file durable up to `offset`, leading a sync or waiting for another thread's.
+    ///
+    /// If a leader's sync fails, the threads waiting for it each try again, so every thread whose
+    /// writes aren't durable sees an error of its own.
+    pub fn sync_to(&self, offset: u64) -> Result<()> {
+        let mut state = self.lock();
+        while state.syncing && state.synced < offset {
+            state = self.synced.wait(state).expect("Group commit lock poisoned");
+        }
+        if state.synced >= offset {
+            return Ok(());
+        }
+        state.syncing = true;
+        let (target, truncations) = (state.written, state.truncations);
+        drop(state);
+
+        let result = self.file.sync_data();
+        let mut state = self.lock();
+        state.syncing = false;
+        if result.is_ok() && state.truncations == truncations {
+            state.synced = state.synced.max(target);
+        }
+        drop(state);
+        self.synced.notify_all();
+        Ok(result?)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("Group commit lock poisoned")
+    }
+}
+
+/// A point in a store's log that every write before it can be made durable at, without access to
+/// the store (see [`KvsEngine::sync_point`]).
+///
+/// Threads waiting at the same time share syncs: whichever one syncs first makes everything
+/// written by then durable for all of them, and the others just wait for it.
+///
+/// ```
+/// # use kvs::{KvsEngine, KvStore, Result};
+/// # fn check() -> Result<()> {
+/// # let path = std::path::PathBuf::new();
+/// let mut store = KvStore::open(path)?;
+/// store.set("key".to_owned(), "value".to_owned())?;
+/// let point = store.sync_point().expect("kvs stores have sync points");
+/// // Writes can carry on while another thread waits for the point.
+/// point.wait()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`KvsEngine::sync_point`]: trait.KvsEngine.html#method.sync_point
+pub struct SyncPoint {
+    commit: Arc<GroupCommit>,
+    offset: u64,
+}
+
+impl SyncPoint {
+    /// A point at `offset` in the file synced by `commit`.
+    pub(crate) fn new(commit: Arc<GroupCommit>, offset: u64) -> Self {
+        SyncPoint { commit, offset }
+    }
+
+    /// Wait until every write before the point is durable, syncing the log if no other thread is.
+    pub fn wait(self) -> Result<()> {
+        self.commit.sync_to(self.offset)
+    }
+}
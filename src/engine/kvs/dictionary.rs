@@ -0,0 +1,170 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::Result;
+use super::format::{self, DICTIONARY_MAGIC};
+
+/// The zstd compression level used for values compressed with a dictionary.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// The maximum number of values sampled during a compaction to train the next dictionary.
+const MAX_SAMPLES: usize = 4096;
+
+/// The fewest samples a dictionary is trained on. With fewer values than this, there's little to
+/// gain from a dictionary (and training is likely to fail anyway).
+const MIN_SAMPLES: usize = 64;
+
+/// Values longer than this aren't sampled, since dictionaries mostly help small values (and large
+/// samples would make training slow).
+const MAX_SAMPLE_LEN: usize = 4096;
+
+/// The compression dictionaries used by a store's log files.
+///
+/// Each dictionary is kept in a `<id>.dict` file alongside the log files, where `id` is the log
+/// index of the compaction that trained it. Compressed commands record the ID of the dictionary
+/// they were compressed with, so a dictionary is kept until no log file refers to it.
+#[derive(Clone, Debug, Default)]
+pub struct Dictionaries {
+    loaded: HashMap<u64, Arc<Vec<u8>>>,
+}
+
+impl Dictionaries {
+    /// Load every dictionary in a store directory.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut loaded = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension() != Some("dict".as_ref()) {
+                continue;
+            }
+            let id = match path.file_stem().and_then(|s| s.to_str()).map(str::parse) {
+                Some(Ok(id)) => id,
+                _ => continue,
+            };
+            let bytes = fs::read(&path)?;
+            let (_, dictionary) = format::split_header(&bytes, DICTIONARY_MAGIC)?;
+            loaded.insert(id, Arc::new(dictionary.to_vec()));
+        }
+        Ok(Dictionaries { loaded })
+    }
+
+    /// Whether dictionary `id` is loaded.
+    pub fn contains(&self, id: u64) -> bool {
+        self.loaded.contains_key(&id)
+    }
+
+    /// The size of dictionary `id`, in bytes, or `0` if it isn't loaded.
+    pub fn len_of(&self, id: u64) -> u64 {
+        self.loaded.get(&id).map_or(0, |dictionary| dictionary.len() as u64)
+    }
+
+    /// Construct a compressor for dictionary `id`.
+    pub fn compressor(&self, id: u64) -> Result<Compressor> {
+        let compressor = zstd::bulk::Compressor::with_dictionary(COMPRESSION_LEVEL, self.get(id)?)?;
+        Ok(Compressor { id, compressor })
+    }
+
+    /// Decompress a value compressed with dictionary `id`.
+    pub fn decompress(&self, id: u64, bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut decoder = zstd::stream::read::Decoder::with_dictionary(bytes, self.get(id)?)?;
+        let mut value = Vec::new();
+        decoder.read_to_end(&mut value)?;
+        Ok(value)
+    }
+
+    /// Atomically write dictionary `id` to a store directory, and load it.
+    pub fn save(&mut self, dir: &Path, id: u64, dictionary: Vec<u8>) -> Result<()> {
+        let temp_path = dir.join(format!("{}.dict.tmp", id));
+        let mut file = File::create(&temp_path)?;
+        format::write_header(&mut file, DICTIONARY_MAGIC)?;
+        file.write_all(&dictionary)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, dictionary_path(dir, id))?;
+        self.loaded.insert(id, Arc::new(dictionary));
+        Ok(())
+    }
+
+    /// Delete the dictionaries (and their files) for which `keep` returns false.
+    pub fn retain<F: Fn(u64) -> bool>(&mut self, dir: &Path, keep: F) -> Result<()> {
+        let removed: Vec<_> = self.loaded.keys().cloned().filter(|&id| !keep(id)).collect();
+        for id in removed {
+            self.loaded.remove(&id);
+            match fs::remove_file(dictionary_path(dir, id)) {
+                Err(ref err) if err.kind() == ErrorKind::NotFound => {},
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: u64) -> Result<&[u8]> {
+        match self.loaded.get(&id) {
+            Some(dictionary) => Ok(dictionary),
+            None => Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("missing compression dictionary {}", id),
+            ).into()),
+        }
+    }
+}
+
+/// Compresses values with one of a store's dictionaries.
+pub struct Compressor {
+    id: u64,
+    compressor: zstd::bulk::Compressor<'static>,
+}
+
+impl Compressor {
+    /// The ID of the dictionary values are compressed with.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Compress `value`, unless that wouldn't make it any smaller.
+    pub fn compress(&mut self, value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let compressed = self.compressor.compress(value)?;
+        Ok(Some(compressed).filter(|compressed| compressed.len() < value.len()))
+    }
+}
+
+/// A uniform random sample of the values written by a compaction, to train a dictionary on.
+#[derive(Default)]
+pub struct Samples {
+    samples: Vec<Vec<u8>>,
+    offered: usize,
+}
+
+impl Samples {
+    /// Offer a value to the sample.
+    pub fn offer(&mut self, value: &[u8]) {
+        if value.is_empty() || value.len() > MAX_SAMPLE_LEN {
+            return;
+        }
+        self.offered += 1;
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(value.to_vec());
+        } else {
+            let slot = rand::thread_rng().gen_range(0, self.offered);
+            if slot < MAX_SAMPLES {
+                self.samples[slot] = value.to_vec();
+            }
+        }
+    }
+
+    /// Train a dictionary of at most `max_len` bytes on the sampled values, or `None` if there
+    /// weren't enough of them (or zstd couldn't find anything worth putting in a dictionary).
+    pub fn train(&self, max_len: usize) -> Option<Vec<u8>> {
+        if self.samples.len() < MIN_SAMPLES {
+            return None;
+        }
+        zstd::dict::from_samples(&self.samples, max_len).ok()
+    }
+}
+
+fn dictionary_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.dict", id))
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use crate::error::Result;
+
+/// The name of the file holding a store's erasure audit log.
+const ERASURES_FILE: &str = "ERASURES";
+
+/// An audit record of a key erased by [`KvStore::erase`].
+///
+/// The key itself isn't recorded, only its hash, so that the audit log doesn't retain the data
+/// that was erased.
+///
+/// [`KvStore::erase`]: struct.KvStore.html#method.erase
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Erasure {
+    /// A hash of the erased key.
+    pub key_hash: u64,
+
+    /// The time of the erasure, in milliseconds since the UNIX epoch.
+    pub erased_at: u64,
+
+    /// The sequence number of the last command written before the log was scrubbed.
+    pub seq: u64,
+
+    /// Whether the key was live when it was erased (rather than already removed).
+    pub was_live: bool,
+}
+
+impl Erasure {
+    /// The hash recorded for `key`.
+    pub fn hash_key(key: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// Append `erasure` to the audit log in `dir`, syncing it to disk.
+pub fn record(dir: &Path, erasure: &Erasure) -> Result<()> {
+    let line = serde_json::to_string(erasure).expect("Erasures are serializable");
+    let mut file = OpenOptions::new().create(true).append(true).open(dir.join(ERASURES_FILE))?;
+    writeln!(file, "{}", line)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Read every erasure recorded in the audit log in `dir`, oldest first.
+///
+/// Records that can't be parsed (e.g. torn by a crash while being written) are skipped.
+pub fn load(dir: &Path) -> Result<Vec<Erasure>> {
+    let contents = match fs::read_to_string(dir.join(ERASURES_FILE)) {
+        Ok(contents) => contents,
+        Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
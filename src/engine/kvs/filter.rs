@@ -0,0 +1,52 @@
+use std::fmt;
+
+/// A hook called with each live entry while a [`KvStore`] compacts its log, deciding whether the
+/// entry is kept, dropped, or kept with a different value.
+///
+/// This allows application-level garbage collection (e.g. dropping entries whose values mark them
+/// as logically expired, or stripping fields that are no longer used) without scanning the store.
+/// Entries are only filtered when the log is compacted, so a filtered entry can still be read
+/// until the next compaction. Dropped entries are removed without writing a `Remove`, so they
+/// aren't reported to anything watching the store's writes (e.g. a server's watchers).
+///
+/// System keys (see [`SYSTEM_KEY_PREFIX`]) are never passed to the filter.
+///
+/// ```
+/// use kvs::{CompactionDecision, CompactionFilter, KvStore};
+///
+/// #[derive(Debug)]
+/// struct DropTombstones;
+///
+/// impl CompactionFilter for DropTombstones {
+///     fn filter(&self, _key: &str, value: &str) -> CompactionDecision {
+///         if value == "deleted" {
+///             CompactionDecision::Remove
+///         } else {
+///             CompactionDecision::Keep
+///         }
+///     }
+/// }
+///
+/// let builder = KvStore::builder().compaction_filter(DropTombstones);
+/// ```
+///
+/// [`KvStore`]: struct.KvStore.html
+/// [`SYSTEM_KEY_PREFIX`]: constant.SYSTEM_KEY_PREFIX.html
+pub trait CompactionFilter: fmt::Debug + Send + Sync {
+    /// Decide what to do with the entry for `key`, whose current value is `value`.
+    fn filter(&self, key: &str, value: &str) -> CompactionDecision;
+}
+
+/// What a [`CompactionFilter`] decided to do with an entry.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompactionDecision {
+    /// Keep the entry as it is.
+    Keep,
+
+    /// Drop the entry, as if its key had been removed.
+    Remove,
+
+    /// Keep the entry, replacing its value with the given one. The entry's sequence number, expiry
+    /// and modification time are unchanged.
+    Change(String),
+}
@@ -0,0 +1,160 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use super::commit::GroupCommit;
+
+/// When a store syncs the commands it writes to disk.
+///
+/// Whatever the policy, the log is synced when the store moves on to a new log file (after a
+/// compaction, or when a file reaches the maximum segment size), when it's closed, when a removed
+/// value may already be durable, and when [`sync`] is called. A policy only decides how soon
+/// other writes are made durable, so how many acknowledged writes a power cut can lose.
+///
+/// Policies can be parsed from `always`, `os`, or an interval in milliseconds like `100ms`.
+///
+/// ```
+/// use std::time::Duration;
+/// use kvs::SyncPolicy;
+///
+/// let policy: SyncPolicy = "100ms".parse().unwrap();
+/// assert_eq!(policy, SyncPolicy::Every(Duration::from_millis(100)));
+/// assert_eq!("always".parse::<SyncPolicy>().unwrap(), SyncPolicy::Always);
+/// ```
+///
+/// [`sync`]: trait.KvsEngine.html#method.sync
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SyncPolicy {
+    /// Sync every command as it's written, so that no acknowledged write is ever lost.
+    Always,
+
+    /// Sync the log from a background thread at this interval, if it's been written to since the
+    /// last sync, so that at most about one interval of acknowledged writes can be lost.
+    Every(Duration),
+
+    /// Leave writing the log back to disk to the operating system.
+    ///
+    /// This is the default. Writes survive the store's process crashing, but acknowledged writes
+    /// can be lost if the host loses power.
+    #[default]
+    Os,
+}
+
+impl FromStr for SyncPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "always" => Ok(SyncPolicy::Always),
+            "os" => Ok(SyncPolicy::Os),
+            _ => s
+                .strip_suffix("ms")
+                .and_then(|millis| millis.parse().ok())
+                .filter(|&millis| millis > 0)
+                .map(|millis| SyncPolicy::Every(Duration::from_millis(millis)))
+                .ok_or_else(|| {
+                    Error::Config(format!(
+                        "invalid sync policy {:?} (expected always, os or an interval like 100ms)",
+                        s
+                    ))
+                }),
+        }
+    }
+}
+
+/// Syncs the log file being written from a background thread, for [`SyncPolicy::Every`].
+///
+/// The thread wakes up once per interval, and syncs the file it was last given with
+/// [`watch`](#method.watch) if [`written`](#method.written) has been called since. It syncs through
+/// the file's [`GroupCommit`], so writes it makes durable don't need syncing again. It's stopped
+/// when the flusher is dropped.
+pub struct Flusher {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wakeup: Condvar,
+    failures: AtomicU64,
+}
+
+#[derive(Default)]
+struct State {
+    commit: Option<Arc<GroupCommit>>,
+    dirty: bool,
+    stopped: bool,
+}
+
+impl Flusher {
+    /// Start a thread syncing the log every `interval`.
+    pub fn start(interval: Duration) -> Result<Flusher> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            wakeup: Condvar::new(),
+            failures: AtomicU64::new(0),
+        });
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name("kvs-flusher".to_owned())
+            .spawn(move || flush(&thread_shared, interval))?;
+        Ok(Flusher { shared, thread: Some(thread) })
+    }
+
+    /// Sync the file synced by `commit` from now on, instead of the previous log file (which the
+    /// store syncs itself before moving on from it).
+    pub fn watch(&self, commit: Arc<GroupCommit>) {
+        let mut state = self.shared.state.lock().expect("Flusher lock poisoned");
+        state.commit = Some(commit);
+        state.dirty = false;
+    }
+
+    /// Record that the log file has been written to, so needs syncing.
+    pub fn written(&self) {
+        self.shared.state.lock().expect("Flusher lock poisoned").dirty = true;
+    }
+
+    /// The number of background syncs that have failed. A failed sync is retried at the next
+    /// interval.
+    pub fn failures(&self) -> u64 {
+        self.shared.failures.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.shared.state.lock().expect("Flusher lock poisoned").stopped = true;
+        self.shared.wakeup.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Sync the watched file every `interval` while it's dirty, until the flusher is stopped.
+fn flush(shared: &Shared, interval: Duration) {
+    let mut state = shared.state.lock().expect("Flusher lock poisoned");
+    loop {
+        state = shared.wakeup.wait_timeout(state, interval).expect("Flusher lock poisoned").0;
+        if state.stopped {
+            return;
+        }
+        if !state.dirty {
+            continue;
+        }
+        state.dirty = false;
+        let commit = state.commit.clone();
+
+        // Sync without holding the lock, so that writes aren't held up.
+        drop(state);
+        let failed = commit.is_some_and(|commit| commit.sync().is_err());
+        state = shared.state.lock().expect("Flusher lock poisoned");
+        if failed {
+            shared.failures.fetch_add(1, Ordering::SeqCst);
+            state.dirty = true;
+        }
+    }
+}
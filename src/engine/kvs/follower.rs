@@ -0,0 +1,399 @@
+use std::collections::BTreeSet;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::SystemClock;
+use crate::engine::{utf8_entries, utf8_value, Consistency, Engine};
+use crate::error::{Error, Result};
+use crate::protocol::NotFoundReason;
+use crate::stats::EngineStats;
+use super::dictionary::Dictionaries;
+use super::index::{Index, IndexEntry};
+use super::lock::DirLock;
+use super::manifest::{self, Manifest};
+use super::readers::Readers;
+use super::usage::Usage;
+use super::{is_expired, open_entry, Builder, Epoch, Sequence, DEFAULT_MAX_OPEN_FILES};
+
+/// How often a follower catches up with its store, unless configured otherwise.
+const DEFAULT_FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A read-only view of a [`KvStore`] that another process has open, kept up to date by following
+/// its log.
+///
+/// A follower builds an index of its own by reading the store's log files, without writing to
+/// the store's directory, so any number of processes on the same host can serve reads from the
+/// same files. Before a read, a follower that hasn't caught up for a [follow interval] reads the
+/// store's manifest and any commands appended since. Once the store has been compacted, the
+/// follower's index is rebuilt from the new files. Writes fail with [`Error::ReadOnly`].
+///
+/// Followers keep their index in memory (a [disk index] is ignored), and the store's commands
+/// become visible to them once they're written, whether or not they've been synced.
+///
+/// ```
+/// # use std::path::PathBuf;
+/// # use kvs::{KvsEngine, KvStore, Result};
+/// # fn check() -> Result<()> {
+/// # let path = PathBuf::new();
+/// let mut follower = KvStore::builder().open_follower(path)?;
+/// let name = follower.get("user:1".to_owned())?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`KvStore`]: struct.KvStore.html
+/// [follow interval]: struct.KvStoreBuilder.html#method.follow_interval
+/// [`Error::ReadOnly`]: enum.Error.html#variant.ReadOnly
+/// [disk index]: struct.KvStoreBuilder.html#method.disk_index
+pub struct Follower {
+    config: Builder,
+    path: PathBuf,
+    index: Index,
+    readers: Readers,
+    dictionaries: Dictionaries,
+    expiries: BTreeSet<(u64, Vec<u8>)>,
+    usage: Usage,
+    sequence: Sequence,
+    compacted_index: Option<u64>,
+    tail: Option<(u64, u64)>,
+    epoch: Epoch,
+    refreshed: Instant,
+    rebuilds: u64,
+}
+
+impl Follower {
+    /// Follow the store in `path` using the given configuration.
+    pub(super) fn open(config: Builder, path: PathBuf) -> Result<Self> {
+        let clock = config.clock.clone().unwrap_or_else(|| Arc::new(SystemClock));
+        let max_open_files = config.max_open_files.unwrap_or(DEFAULT_MAX_OPEN_FILES);
+        let mut follower = Follower {
+            index: new_index(&config),
+            readers: Readers::new(&path, max_open_files, Dictionaries::default()),
+            dictionaries: Dictionaries::default(),
+            expiries: BTreeSet::new(),
+            usage: Usage::default(),
+            sequence: Sequence::new(&Manifest::default()),
+            compacted_index: None,
+            tail: None,
+            epoch: Epoch { clock, latest: 0 },
+            refreshed: Instant::now(),
+            rebuilds: 0,
+            config,
+            path,
+        };
+        follower.sequence.trusted = true;
+        follower.refresh()?;
+        follower.rebuilds = 0;
+        Ok(follower)
+    }
+
+    /// Catch up with the commands written to the store since the follower last did.
+    ///
+    /// If the store has been compacted since, the index is rebuilt from scratch.
+    pub fn refresh(&mut self) -> Result<()> {
+        match self.catch_up() {
+            // A compaction deleted a file between reading the manifest and opening the file.
+            Err(Error::Io(ref err)) if err.kind() == ErrorKind::NotFound => self.catch_up(),
+            result => result,
+        }
+    }
+
+    /// Catch up with the store, if it's been longer than the follow interval since the last time.
+    fn refresh_if_due(&mut self) -> Result<()> {
+        let interval = self.config.follow_interval.unwrap_or(DEFAULT_FOLLOW_INTERVAL);
+        if self.refreshed.elapsed() >= interval {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    fn catch_up(&mut self) -> Result<()> {
+        self.refreshed = Instant::now();
+        let lock = DirLock::shared(&self.path)?;
+        let manifest = Manifest::load(&self.path)?.unwrap_or_default();
+        self.epoch.latest = self.epoch.latest.max(manifest.clock_epoch);
+        let followed = self.readers.log_indices();
+        let compacted = manifest.compacted_index != self.compacted_index
+            || followed.iter().any(|log_index| !manifest.log_indices.contains(log_index));
+        if compacted {
+            return self.rebuild(manifest, lock);
+        }
+
+        // Files are only ever added after the ones being followed, until the next compaction.
+        let mut added = Vec::new();
+        for &log_index in &manifest.log_indices {
+            if !self.readers.contains(log_index) {
+                self.readers.insert(log_index)?;
+                added.push(log_index);
+            }
+        }
+        drop(lock);
+
+        let tailed = self.tail.map(|(log_index, _)| log_index);
+        let files: Vec<_> = tailed.into_iter().chain(added).collect();
+        for (i, &log_index) in files.iter().enumerate() {
+            self.replay(log_index, i + 1 == files.len(), false)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild the index from the files listed in `manifest`, which are opened before `lock` is
+    /// released.
+    fn rebuild(&mut self, manifest: Manifest, lock: DirLock) -> Result<()> {
+        let dictionaries = Dictionaries::load(&self.path)?;
+        let max_open_files = self.config.max_open_files.unwrap_or(DEFAULT_MAX_OPEN_FILES);
+        let mut readers = Readers::new(&self.path, max_open_files, dictionaries.clone());
+        let mut replayed = manifest.log_indices.clone();
+        manifest::replay_order(&mut replayed, manifest.compacted_index);
+        for &log_index in &replayed {
+            readers.insert(log_index)?;
+        }
+        drop(lock);
+
+        self.index = new_index(&self.config);
+        self.readers = readers;
+        self.dictionaries = dictionaries;
+        self.expiries.clear();
+        self.usage = Usage::default();
+        self.sequence = Sequence::new(&manifest);
+        self.sequence.trusted = true;
+        self.compacted_index = manifest.compacted_index;
+        self.tail = None;
+        self.rebuilds += 1;
+        for (i, &log_index) in replayed.iter().enumerate() {
+            let is_compacted = manifest.compacted_index == Some(log_index);
+            self.replay(log_index, i + 1 == replayed.len(), is_compacted)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the commands in a log file that haven't been applied yet, and start tailing it.
+    ///
+    /// The store may be part way through writing a command to the last file, so reading it stops
+    /// at the first command that can't be read, and carries on from there next time.
+    fn replay(&mut self, log_index: u64, is_last: bool, is_compacted: bool) -> Result<()> {
+        let start = match self.tail {
+            Some((tailed, offset)) if tailed == log_index => offset,
+            _ => 0,
+        };
+        let mut entries = self.readers.get(log_index)?.load_from(start)?;
+        let mut applied = entries.offset();
+        loop {
+            let entry = match entries.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(_)) if is_last => break,
+                Some(Err(err)) => return Err(err),
+                None => break,
+            };
+            self.usage.written(log_index, entry.2);
+            if self.sequence.check(entry.0.seq(), is_compacted)? {
+                open_entry(
+                    log_index,
+                    &mut self.index,
+                    &mut self.expiries,
+                    &mut self.usage,
+                    &self.dictionaries,
+                    self.config.inline_values,
+                    entry,
+                )?;
+            }
+            applied = entries.offset();
+        }
+        self.tail = Some((log_index, applied));
+        Ok(())
+    }
+
+    /// Run `read`, refreshing and trying again if a file it needed has been deleted by a
+    /// compaction.
+    fn read<T, F>(&mut self, mut read: F) -> Result<T>
+    where
+        F: FnMut(&mut Self) -> Result<T>,
+    {
+        self.refresh_if_due()?;
+        match read(self) {
+            Err(Error::Io(ref err)) if err.kind() == ErrorKind::NotFound => {
+                self.refresh()?;
+                read(self)
+            },
+            result => result,
+        }
+    }
+
+    /// Read the value of every unexpired key in `scanned`.
+    fn read_entries(
+        &mut self,
+        scanned: Vec<(Vec<u8>, IndexEntry)>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let now = self.epoch.now();
+        let mut entries = Vec::new();
+        for (key, entry) in scanned {
+            if !is_expired(entry.expires, now) {
+                let value = self.readers.read(&entry)?;
+                entries.push((key, value));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Get the value of a key, or why it wasn't found (see [`Engine::lookup`]).
+    fn lookup_bytes(
+        &mut self,
+        key: &[u8],
+    ) -> Result<std::result::Result<Vec<u8>, Option<NotFoundReason>>> {
+        self.read(|follower| {
+            let now = follower.epoch.now();
+            match follower.index.get(key)? {
+                Some(entry) if is_expired(entry.expires, now) => {
+                    Ok(Err(Some(NotFoundReason::Expired)))
+                },
+                Some(entry) => Ok(Ok(follower.readers.read(&entry)?)),
+                None => Ok(Err(None)),
+            }
+        })
+    }
+}
+
+impl Engine for Follower {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        Ok(self.lookup(key)?.ok())
+    }
+
+    /// Get the value of a key, or [`Expired`] if it's expired but the store hasn't removed it.
+    ///
+    /// [`Expired`]: enum.NotFoundReason.html#variant.Expired
+    fn lookup(
+        &mut self,
+        key: String,
+    ) -> Result<std::result::Result<String, Option<NotFoundReason>>> {
+        match self.lookup_bytes(key.as_bytes())? {
+            Ok(value) => Ok(Ok(utf8_value(&key, value)?)),
+            Err(reason) => Ok(Err(reason)),
+        }
+    }
+
+    /// Fail with [`Error::ReadOnly`].
+    ///
+    /// [`Error::ReadOnly`]: enum.Error.html#variant.ReadOnly
+    fn set(&mut self, _key: String, _value: String) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Fail with [`Error::ReadOnly`].
+    ///
+    /// [`Error::ReadOnly`]: enum.Error.html#variant.ReadOnly
+    fn remove(&mut self, _key: String) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.lookup_bytes(key)?.ok())
+    }
+
+    /// Fail with [`Error::ReadOnly`].
+    ///
+    /// [`Error::ReadOnly`]: enum.Error.html#variant.ReadOnly
+    fn set_bytes(&mut self, _key: &[u8], _value: &[u8]) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Fail with [`Error::ReadOnly`].
+    ///
+    /// [`Error::ReadOnly`]: enum.Error.html#variant.ReadOnly
+    fn remove_bytes(&mut self, _key: &[u8]) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        utf8_entries(self.scan_bytes(prefix.as_bytes())?)
+    }
+
+    fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read(|follower| {
+            let scanned = follower.index.scan(prefix)?;
+            follower.read_entries(scanned)
+        })
+    }
+
+    fn scan_range(&mut self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>> {
+        let entries = self.read(|follower| {
+            let scanned = follower.index.scan_range(start.as_bytes(), end.map(str::as_bytes))?;
+            follower.read_entries(scanned)
+        })?;
+        utf8_entries(entries)
+    }
+
+    /// Fail with [`Error::ReadOnly`].
+    ///
+    /// [`Error::ReadOnly`]: enum.Error.html#variant.ReadOnly
+    fn set_with_ttl(&mut self, _key: String, _value: String, _ttl: Duration) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn ttl(&mut self, key: String) -> Result<Option<Duration>> {
+        self.refresh_if_due()?;
+        let now = self.epoch.now();
+        match self.index.get(key.as_bytes())? {
+            Some(entry) if !is_expired(entry.expires, now) => {
+                Ok(entry.expires.map(|expires| Duration::from_millis(expires - now)))
+            },
+            _ => Err(Error::KeyNotFound),
+        }
+    }
+
+    /// Fail with [`Error::ReadOnly`].
+    ///
+    /// [`Error::ReadOnly`]: enum.Error.html#variant.ReadOnly
+    fn expire(&mut self, _key: String, _ttl: Duration) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Fail with [`Error::ReadOnly`].
+    ///
+    /// [`Error::ReadOnly`]: enum.Error.html#variant.ReadOnly
+    fn persist(&mut self, _key: String) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn name(&self) -> &str {
+        "kvs"
+    }
+
+    /// Reads can miss writes made since the follower last caught up, up to a follow interval ago.
+    fn consistency(&self) -> Consistency {
+        Consistency::Stale(self.config.follow_interval.unwrap_or(DEFAULT_FOLLOW_INTERVAL))
+    }
+
+    /// Catch up with the store, whether or not the follow interval has passed.
+    fn maintain(&mut self) -> Result<()> {
+        self.refresh()
+    }
+
+    fn stats(&mut self) -> Result<EngineStats> {
+        self.refresh_if_due()?;
+        Ok(EngineStats {
+            keys: self.index.len() as u64,
+            index_bytes: self.index.memory_usage(),
+            log_files: self.readers.len() as u64,
+            open_files: self.readers.open_len() as u64,
+            file_evictions: self.readers.evictions(),
+            log_bytes: self.usage.bytes(),
+            read_only: true,
+            sequence: self.sequence.last,
+            replay_duplicates: self.sequence.duplicates,
+            expiring_keys: self.expiries.len() as u64,
+            follower_rebuilds: self.rebuilds,
+            ..EngineStats::default()
+        })
+    }
+}
+
+/// A new, empty index for a follower, which is always kept in memory.
+fn new_index(config: &Builder) -> Index {
+    if config.prefix_compression {
+        Index::prefix()
+    } else {
+        Index::memory()
+    }
+}
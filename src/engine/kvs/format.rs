@@ -0,0 +1,71 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::error::{Error, Result};
+
+/// The magic bytes at the start of a log file.
+pub const LOG_MAGIC: &[u8; 4] = b"KVSL";
+
+/// The magic bytes at the start of a manifest file.
+pub const MANIFEST_MAGIC: &[u8; 4] = b"KVSM";
+
+/// The magic bytes at the start of a compression dictionary file.
+pub const DICTIONARY_MAGIC: &[u8; 4] = b"KVSD";
+
+/// The format version written by this version of the crate.
+///
+/// - Version 1 files have no header, and commands may not have sequence numbers.
+/// - Version 2 files start with a header, and every command has a sequence number.
+/// - Version 3 log files frame every command with its length and a CRC32 checksum.
+/// - Version 4 log files hold keys and values as bytes rather than strings, so they needn't be
+///   UTF-8.
+///
+/// Files from any earlier version can be read, and are replaced with the current version when
+/// compacted (or upgraded with `kvs upgrade`). Files from later versions are rejected.
+pub const CURRENT_VERSION: u32 = 4;
+
+/// The first format version in which log commands are framed with a length and checksum.
+pub const FRAMED_VERSION: u32 = 3;
+
+/// The first format version in which keys and values are written as bytes.
+pub const BYTES_VERSION: u32 = 4;
+
+/// The format version of files written before headers were introduced.
+pub const LEGACY_VERSION: u32 = 1;
+
+/// The length of a file header: the magic bytes followed by a little-endian `u32` version.
+pub const HEADER_LEN: u64 = 8;
+
+/// Write a header for the current version.
+pub fn write_header<W: Write>(writer: &mut W, magic: &[u8; 4]) -> io::Result<()> {
+    writer.write_all(magic)?;
+    writer.write_all(&CURRENT_VERSION.to_le_bytes())
+}
+
+/// Split the header (if any) from the contents of a file, returning the version and the rest.
+///
+/// Contents without a header are [`LEGACY_VERSION`]. This can't be confused with a legacy file's
+/// contents, since MessagePack-encoded commands never start with an ASCII letter.
+pub fn split_header<'a>(bytes: &'a [u8], magic: &[u8; 4]) -> Result<(u32, &'a [u8])> {
+    if bytes.len() < HEADER_LEN as usize || !bytes.starts_with(magic) {
+        return Ok((LEGACY_VERSION, bytes));
+    }
+    let mut version = [0; 4];
+    version.copy_from_slice(&bytes[4..HEADER_LEN as usize]);
+    let version = u32::from_le_bytes(version);
+    if version > CURRENT_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    Ok((version, &bytes[HEADER_LEN as usize..]))
+}
+
+/// Read the header (if any) from the start of a file, returning the version and the offset at
+/// which the file's contents start.
+pub fn read_header<R: Read + Seek>(reader: &mut R, magic: &[u8; 4]) -> Result<(u32, u64)> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut bytes = Vec::with_capacity(HEADER_LEN as usize);
+    reader.by_ref().take(HEADER_LEN).read_to_end(&mut bytes)?;
+    let (version, rest) = split_header(&bytes, magic)?;
+    let start = (bytes.len() - rest.len()) as u64;
+    reader.seek(SeekFrom::Start(start))?;
+    Ok((version, start))
+}
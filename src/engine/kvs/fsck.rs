@@ -0,0 +1,338 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+use crate::engine::SYSTEM_KEY_PREFIX;
+use crate::error::{Error, Result};
+use super::log::Command;
+use super::manifest::{self, Manifest};
+use super::{find_log_indices, log_path, open_reader, Sequence};
+
+/// The result of checking a store's directory for consistency.
+///
+/// This is returned by [`KvStore::check`] and [`KvStore::repair`], and is designed to be
+/// serialized (e.g. as JSON by `kvs fsck`) so that automation can decide whether a repair is safe.
+///
+/// [`KvStore::check`]: struct.KvStore.html#method.check
+/// [`KvStore::repair`]: struct.KvStore.html#method.repair
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CheckReport {
+    /// Whether the store is free of problems.
+    pub clean: bool,
+
+    /// Whether every problem found can be fixed by [`repairs`](#structfield.repairs).
+    ///
+    /// Repairs never lose commands that could otherwise be read, but they may accept the loss of
+    /// commands that are already missing or corrupt at the end of the log.
+    pub repairable: bool,
+
+    /// Whether the repairs have been made.
+    pub repaired: bool,
+
+    /// The contents of the store's manifest, if it has one.
+    pub manifest: Option<ManifestReport>,
+
+    /// A report for each log file in use, in order.
+    pub files: Vec<FileReport>,
+
+    /// Log files that are in use but not listed in the manifest.
+    pub orphaned_files: Vec<u64>,
+
+    /// Log files that have been superseded by the latest compaction.
+    pub superseded_files: Vec<u64>,
+
+    /// Log files that are listed in the manifest but don't exist.
+    pub missing_files: Vec<u64>,
+
+    /// Places where commands are missing from the middle of the log.
+    pub sequence_gaps: Vec<SequenceGap>,
+
+    /// Commands the manifest records as written but which are missing from the end of the log.
+    pub missing_tail: Option<SequenceGap>,
+
+    /// The number of commands that have already been applied, and would be skipped on open.
+    pub duplicates: u64,
+
+    /// The number of live keys in the store, not counting those in the [system keyspace].
+    ///
+    /// [system keyspace]: constant.SYSTEM_KEY_PREFIX.html
+    pub keys: u64,
+
+    /// The repairs needed to fix the problems found.
+    pub repairs: Vec<Repair>,
+}
+
+/// The contents of a store's manifest.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ManifestReport {
+    /// The sequence number of the last command known to have been written.
+    pub last_seq: u64,
+
+    /// The log index of the file written by the latest compaction, if there has been one.
+    pub compacted_index: Option<u64>,
+
+    /// The sequence number of the last command included in the latest compaction.
+    pub compacted_seq: u64,
+
+    /// The log files that make up the store.
+    pub log_indices: Vec<u64>,
+}
+
+/// A report on a single log file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FileReport {
+    /// The file's log index.
+    pub log_index: u64,
+
+    /// The file's format version.
+    pub version: u32,
+
+    /// The size of the file in bytes.
+    pub bytes: u64,
+
+    /// The number of readable commands in the file.
+    pub records: u64,
+
+    /// The number of readable `Set` commands in the file.
+    pub sets: u64,
+
+    /// The number of readable `Remove` commands in the file.
+    pub removes: u64,
+
+    /// The number of readable `Rename` commands in the file.
+    #[serde(default)]
+    pub renames: u64,
+
+    /// The first sequence number in the file, if it contains sequenced commands.
+    pub first_seq: Option<u64>,
+
+    /// The last sequence number in the file, if it contains sequenced commands.
+    pub last_seq: Option<u64>,
+
+    /// Byte ranges of the file that couldn't be read.
+    pub corrupt_ranges: Vec<CorruptRange>,
+}
+
+/// A byte range of a log file that couldn't be read.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CorruptRange {
+    /// The offset of the start of the range.
+    pub start: u64,
+
+    /// The offset of the end of the range (exclusive).
+    pub end: u64,
+
+    /// A description of why the range couldn't be read.
+    pub error: String,
+}
+
+/// A place where commands are missing from a log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SequenceGap {
+    /// The sequence number of the first missing command.
+    pub expected: u64,
+
+    /// The sequence number of the command found in its place, or of the last command in the log.
+    pub found: u64,
+}
+
+/// A change to a store's directory that fixes a problem found by a check.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Repair {
+    /// Truncate a log file to remove a corrupt range at its end.
+    Truncate {
+        /// The log index of the file.
+        log_index: u64,
+
+        /// The length to truncate the file to.
+        length: u64,
+    },
+
+    /// Delete a log file that has been superseded by compaction.
+    Delete {
+        /// The log index of the file.
+        log_index: u64,
+    },
+
+    /// Rewrite the manifest to match the log files in use.
+    WriteManifest {
+        /// The sequence number of the last command in the log.
+        last_seq: u64,
+
+        /// The log files in use.
+        log_indices: Vec<u64>,
+    },
+}
+
+/// Check a store's directory for consistency without modifying it.
+pub fn check(dir: &Path) -> Result<CheckReport> {
+    let manifest = Manifest::load(dir)?;
+    let mut report = CheckReport {
+        manifest: manifest.as_ref().map(|manifest| ManifestReport {
+            last_seq: manifest.last_seq,
+            compacted_index: manifest.compacted_index,
+            compacted_seq: manifest.compacted_seq,
+            log_indices: manifest.log_indices.clone(),
+        }),
+        ..CheckReport::default()
+    };
+    let has_manifest = manifest.is_some();
+    let manifest = manifest.unwrap_or_default();
+
+    let (superseded, mut log_indices): (Vec<_>, Vec<_>) = find_log_indices(dir)?
+        .into_iter()
+        .partition(|&log_index| manifest.is_superseded(log_index));
+    report.superseded_files = superseded;
+    if has_manifest {
+        report.orphaned_files = log_indices
+            .iter()
+            .filter(|log_index| !manifest.log_indices.contains(log_index))
+            .cloned()
+            .collect();
+        report.missing_files = manifest
+            .log_indices
+            .iter()
+            .filter(|log_index| !log_indices.contains(log_index))
+            .cloned()
+            .collect();
+    }
+
+    let mut keys = HashSet::new();
+    let mut sequence = Sequence::new(&manifest);
+    manifest::replay_order(&mut log_indices, manifest.compacted_index);
+    for &log_index in &log_indices {
+        let is_compacted = manifest.compacted_index == Some(log_index);
+        let mut reader = open_reader(dir, log_index)?;
+        let mut file = FileReport {
+            log_index,
+            version: reader.version(),
+            bytes: fs::metadata(log_path(dir, log_index))?.len(),
+            ..FileReport::default()
+        };
+
+        let mut entries = reader.load()?;
+        loop {
+            let start = entries.offset();
+            let command = match entries.next() {
+                Some(Ok((command, _, _))) => command,
+                Some(Err(err)) => {
+                    file.corrupt_ranges.push(CorruptRange {
+                        start,
+                        end: file.bytes,
+                        error: err.to_string(),
+                    });
+                    break;
+                },
+                None if start < file.bytes => {
+                    file.corrupt_ranges.push(CorruptRange {
+                        start,
+                        end: file.bytes,
+                        error: "incomplete command".to_owned(),
+                    });
+                    break;
+                },
+                None => break,
+            };
+
+            file.records += 1;
+            let seq = command.seq();
+            if seq != 0 {
+                file.first_seq.get_or_insert(seq);
+                file.last_seq = Some(seq);
+            }
+
+            let apply = match sequence.check(seq, is_compacted) {
+                Ok(apply) => apply,
+                Err(Error::SequenceGap { expected, found }) => {
+                    report.sequence_gaps.push(SequenceGap { expected, found });
+                    true
+                },
+                Err(err) => return Err(err),
+            };
+            match command {
+                Command::Set { key, .. } | Command::SetCompressed { key, .. } => {
+                    file.sets += 1;
+                    if apply {
+                        keys.insert(key);
+                    }
+                },
+                Command::Remove { key, .. } => {
+                    file.removes += 1;
+                    if apply {
+                        keys.remove(&key);
+                    }
+                },
+                Command::Rename { from, to, .. } => {
+                    file.renames += 1;
+                    if apply {
+                        keys.remove(&from);
+                        keys.insert(to);
+                    }
+                },
+                Command::SetPrefixed { .. } => unreachable!("Reader::load resolves prefixed commands"),
+            }
+        }
+
+        for range in &file.corrupt_ranges {
+            report.repairs.push(Repair::Truncate {
+                log_index,
+                length: range.start,
+            });
+        }
+        report.files.push(file);
+    }
+    if let Err(Error::SequenceGap { expected, found }) = sequence.finish(&manifest, false) {
+        report.missing_tail = Some(SequenceGap { expected, found });
+    }
+    report.duplicates = sequence.duplicates;
+    let system = SYSTEM_KEY_PREFIX.as_bytes();
+    report.keys = keys.iter().filter(|key| !key.starts_with(system)).count() as u64;
+
+    for &log_index in &report.superseded_files {
+        report.repairs.push(Repair::Delete { log_index });
+    }
+    if !report.orphaned_files.is_empty()
+        || !report.missing_files.is_empty()
+        || report.missing_tail.is_some()
+    {
+        report.repairs.push(Repair::WriteManifest {
+            last_seq: sequence.last,
+            log_indices,
+        });
+    }
+
+    report.repairable = report.sequence_gaps.is_empty();
+    report.clean = report.repairable && report.repairs.is_empty();
+    Ok(report)
+}
+
+/// Check a store's directory for consistency, and make any repairs needed if they're all safe.
+pub fn repair(dir: &Path) -> Result<CheckReport> {
+    let mut report = check(dir)?;
+    if !report.repairable || report.repairs.is_empty() {
+        return Ok(report);
+    }
+
+    for repair in &report.repairs {
+        match repair {
+            Repair::Truncate { log_index, length } => {
+                OpenOptions::new()
+                    .write(true)
+                    .open(log_path(dir, *log_index))?
+                    .set_len(*length)?;
+            },
+            Repair::Delete { log_index } => fs::remove_file(log_path(dir, *log_index))?,
+            Repair::WriteManifest { last_seq, log_indices } => {
+                let mut manifest = Manifest::load(dir)?.unwrap_or_default();
+                manifest.last_seq = *last_seq;
+                manifest.log_indices = log_indices.clone();
+                manifest.clean_shutdown = None;
+                manifest.save(dir)?;
+            },
+        }
+    }
+    report.repaired = true;
+    Ok(report)
+}
@@ -0,0 +1,249 @@
+mod radix;
+
+use rmp_serde::decode::from_slice as decode_mp;
+use rmp_serde::encode::to_vec as encode_mp;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::mem;
+use std::ops::Bound;
+use std::path::Path;
+
+use crate::error::Result;
+use self::radix::RadixTree;
+use super::log::Offset;
+
+/// The name of the directory (within a store's directory) that holds a disk-resident index.
+pub const DISK_INDEX_DIR: &str = "index";
+
+/// The estimated memory used by a [`Index::Memory`] entry, excluding the key's contents.
+///
+/// This accounts for the key's `Vec` header, the `IndexEntry`, and a word of `BTreeMap` node
+/// overhead per entry.
+const MAP_ENTRY_OVERHEAD: u64 =
+    (mem::size_of::<Vec<u8>>() + mem::size_of::<IndexEntry>() + mem::size_of::<usize>()) as u64;
+
+/// An entry in a command index.
+///
+/// Small values may be inlined in their entry (see `Builder::inline_values`), in which case
+/// they're read from the entry rather than the log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IndexEntry {
+    pub log_index: u64,
+    pub offset: Offset,
+    pub length: u64,
+    pub seq: u64,
+    pub expires: Option<u64>,
+    pub modified: Option<u64>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// A mapping from keys to the location of their latest `Set` command in the log.
+pub enum Index {
+    /// An index held entirely in memory, sorted so that scans only visit the keys they return.
+    Memory {
+        map: BTreeMap<Vec<u8>, IndexEntry>,
+        memory: u64,
+    },
+
+    /// An index held in memory in a radix tree, storing shared key prefixes only once.
+    Prefix(RadixTree<IndexEntry>),
+
+    /// An index persisted in a B-tree on disk, with a bounded page cache.
+    ///
+    /// The index is rebuilt from the log on open, so its contents are never trusted across
+    /// restarts.
+    Disk(sled::Db),
+}
+
+impl Index {
+    /// Construct an empty in-memory index.
+    pub fn memory() -> Self {
+        Index::Memory {
+            map: BTreeMap::new(),
+            memory: 0,
+        }
+    }
+
+    /// Construct an empty in-memory index that compresses shared key prefixes.
+    pub fn prefix() -> Self {
+        Index::Prefix(RadixTree::default())
+    }
+
+    /// Open (and clear) a disk-resident index in the given store directory.
+    pub fn disk(dir: &Path, cache_capacity: u64) -> Result<Self> {
+        let config = sled::ConfigBuilder::new()
+            .path(dir.join(DISK_INDEX_DIR))
+            .cache_capacity(cache_capacity)
+            .build();
+        let db = sled::Db::start(config)?;
+        db.clear()?;
+        Ok(Index::Disk(db))
+    }
+
+    /// The estimated memory used by the index, in bytes.
+    ///
+    /// This is always zero for a disk-resident index.
+    pub fn memory_usage(&self) -> u64 {
+        match self {
+            Index::Memory { memory, .. } => *memory,
+            Index::Prefix(tree) => tree.memory_usage(),
+            Index::Disk(_) => 0,
+        }
+    }
+
+    /// An upper bound on the additional memory needed to index a new `key`.
+    ///
+    /// This is always zero for a disk-resident index.
+    pub fn insert_cost(&self, key: &[u8]) -> u64 {
+        match self {
+            Index::Memory { .. } => map_entry_memory(key),
+            Index::Prefix(_) => RadixTree::<IndexEntry>::insert_cost(key),
+            Index::Disk(_) => 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Index::Memory { map, .. } => map.len(),
+            Index::Prefix(tree) => tree.len(),
+            Index::Disk(db) => db.len(),
+        }
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        match self {
+            Index::Memory { map, .. } => Ok(map.contains_key(key)),
+            Index::Prefix(tree) => Ok(tree.get(key).is_some()),
+            Index::Disk(db) => Ok(db.contains_key(key)?),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<IndexEntry>> {
+        match self {
+            Index::Memory { map, .. } => Ok(map.get(key).cloned()),
+            Index::Prefix(tree) => Ok(tree.get(key).cloned()),
+            Index::Disk(db) => db.get(key)?.map(|bytes| decode_entry(&bytes)).transpose(),
+        }
+    }
+
+    /// Insert an entry for a key, returning the previous entry if there was one.
+    pub fn insert(&mut self, key: Vec<u8>, entry: IndexEntry) -> Result<Option<IndexEntry>> {
+        match self {
+            Index::Memory { map, memory } => {
+                let cost = map_entry_memory(&key);
+                let previous = map.insert(key, entry);
+                if previous.is_none() {
+                    *memory += cost;
+                }
+                Ok(previous)
+            },
+            Index::Prefix(tree) => Ok(tree.insert(&key, entry)),
+            Index::Disk(db) => db
+                .set(key, encode_mp(&entry)?)?
+                .map(|bytes| decode_entry(&bytes))
+                .transpose(),
+        }
+    }
+
+    /// Remove the entry for a key, returning it if there was one.
+    pub fn remove(&mut self, key: &[u8]) -> Result<Option<IndexEntry>> {
+        match self {
+            Index::Memory { map, memory } => {
+                let removed = map.remove(key);
+                if removed.is_some() {
+                    *memory -= map_entry_memory(key);
+                }
+                Ok(removed)
+            },
+            Index::Prefix(tree) => Ok(tree.remove(key)),
+            Index::Disk(db) => db.del(key)?.map(|bytes| decode_entry(&bytes)).transpose(),
+        }
+    }
+
+    /// Get every key starting with `prefix`, with its entry, in key order.
+    pub fn scan(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, IndexEntry)>> {
+        self.scan_from(prefix, |key| key.starts_with(prefix))
+    }
+
+    /// Get every key from `start` (inclusive) to `end` (exclusive, or unbounded if `None`), with
+    /// its entry, in key order.
+    pub fn scan_range(
+        &mut self,
+        start: &[u8],
+        end: Option<&[u8]>,
+    ) -> Result<Vec<(Vec<u8>, IndexEntry)>> {
+        self.scan_from(start, |key| end.is_none_or(|end| key < end))
+    }
+
+    /// Get the keys from `start` onwards, with their entries, in key order, stopping at the first
+    /// key for which `in_scan` is false.
+    ///
+    /// Every kind of index is sorted, so this only visits the keys it returns (and the one after).
+    fn scan_from<F>(&mut self, start: &[u8], in_scan: F) -> Result<Vec<(Vec<u8>, IndexEntry)>>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let mut entries = Vec::new();
+        match self {
+            Index::Memory { map, .. } => {
+                entries.extend(
+                    map.range::<[u8], _>((Bound::Included(start), Bound::Unbounded))
+                        .take_while(|(key, _)| in_scan(key))
+                        .map(|(key, entry)| (key.clone(), entry.clone())),
+                );
+            },
+            Index::Prefix(tree) => {
+                tree.scan_from(start, |key, entry| {
+                    if !in_scan(key) {
+                        return false;
+                    }
+                    entries.push((key.to_vec(), entry.clone()));
+                    true
+                });
+            },
+            Index::Disk(db) => {
+                for item in db.range(start..) {
+                    let (key, bytes) = item?;
+                    if !in_scan(key.as_ref()) {
+                        break;
+                    }
+                    entries.push((key.to_vec(), decode_entry(&bytes)?));
+                }
+            },
+        }
+        Ok(entries)
+    }
+
+    /// Call `f` with every key and entry in the index, in no particular order.
+    pub fn try_for_each<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &IndexEntry) -> Result<()>,
+    {
+        match self {
+            Index::Memory { map, .. } => {
+                for (key, entry) in map.iter() {
+                    f(key, entry)?;
+                }
+            },
+            Index::Prefix(tree) => {
+                tree.try_for_each_mut(|key, entry| f(key, entry))?;
+            },
+            Index::Disk(db) => {
+                for item in db.iter() {
+                    let (key, bytes) = item?;
+                    f(key.as_ref(), &decode_entry(&bytes)?)?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// The estimated memory used by a [`Index::Memory`] entry for `key`.
+fn map_entry_memory(key: &[u8]) -> u64 {
+    key.len() as u64 + MAP_ENTRY_OVERHEAD
+}
+
+fn decode_entry(bytes: &[u8]) -> Result<IndexEntry> {
+    Ok(decode_mp(bytes)?)
+}
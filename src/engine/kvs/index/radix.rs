@@ -0,0 +1,250 @@
+use std::cmp::Ordering;
+use std::mem;
+
+use crate::error::Result;
+
+/// A map from byte strings to values that stores shared key prefixes only once.
+///
+/// Each node is labelled with the bytes of the key it adds to its parent's, so keys with long
+/// common prefixes (URLs, paths, `tenant:user:...` style keys) share most of their storage. Nodes
+/// with a single child and no value are always merged into their child, so the tree never has more
+/// than `2 * len` nodes.
+#[derive(Debug)]
+pub struct RadixTree<V> {
+    root: Node<V>,
+    len: usize,
+    nodes: usize,
+    label_bytes: usize,
+}
+
+#[derive(Debug)]
+struct Node<V> {
+    label: Box<[u8]>,
+    value: Option<V>,
+    /// Children, sorted by the first byte of their label (which is unique among siblings).
+    children: Vec<Node<V>>,
+}
+
+impl<V> Node<V> {
+    fn new(label: &[u8], value: Option<V>) -> Self {
+        Node {
+            label: label.into(),
+            value,
+            children: Vec::new(),
+        }
+    }
+
+    fn find_child(&self, first: u8) -> std::result::Result<usize, usize> {
+        self.children.binary_search_by_key(&first, |child| child.label[0])
+    }
+}
+
+impl<V> Default for RadixTree<V> {
+    fn default() -> Self {
+        RadixTree {
+            root: Node::new(&[], None),
+            len: 0,
+            nodes: 1,
+            label_bytes: 0,
+        }
+    }
+}
+
+impl<V> RadixTree<V> {
+    /// The number of keys in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The estimated memory used by the tree, in bytes.
+    pub fn memory_usage(&self) -> u64 {
+        (self.nodes * mem::size_of::<Node<V>>() + self.label_bytes) as u64
+    }
+
+    /// An upper bound on the additional memory needed to insert a new `key`.
+    pub fn insert_cost(key: &[u8]) -> u64 {
+        (2 * mem::size_of::<Node<V>>() + key.len()) as u64
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        let mut node = &self.root;
+        let mut rest = key;
+        loop {
+            if rest.is_empty() {
+                return node.value.as_ref();
+            }
+            let child = &node.children[node.find_child(rest[0]).ok()?];
+            if !rest.starts_with(&child.label) {
+                return None;
+            }
+            rest = &rest[child.label.len()..];
+            node = child;
+        }
+    }
+
+    /// Insert a value for a key, returning the previous value if there was one.
+    pub fn insert(&mut self, key: &[u8], value: V) -> Option<V> {
+        let mut delta = Delta::default();
+        let previous = insert(&mut self.root, key, value, &mut delta);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        self.apply(delta);
+        previous
+    }
+
+    /// Remove the value for a key, returning it if there was one.
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let mut delta = Delta::default();
+        let removed = remove(&mut self.root, key, &mut delta);
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        self.apply(delta);
+        removed
+    }
+
+    /// Call `f` with every key and value in the tree, in lexicographic order of keys.
+    pub fn try_for_each_mut<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &mut V) -> Result<()>,
+    {
+        let mut key = Vec::new();
+        for_each_mut(&mut self.root, &mut key, &mut f)
+    }
+
+    /// Call `f` with each key from `start` onwards and its value, in lexicographic order of keys,
+    /// until it returns `false`.
+    ///
+    /// Subtrees whose keys all come before `start` are skipped without being visited.
+    pub fn scan_from<F>(&self, start: &[u8], mut f: F)
+    where
+        F: FnMut(&[u8], &V) -> bool,
+    {
+        let mut key = Vec::new();
+        scan_from(&self.root, &mut key, start, &mut f);
+    }
+
+    fn apply(&mut self, delta: Delta) {
+        self.nodes = (self.nodes as isize + delta.nodes) as usize;
+        self.label_bytes = (self.label_bytes as isize + delta.label_bytes) as usize;
+    }
+}
+
+/// Changes in the size of a tree during an operation.
+#[derive(Default)]
+struct Delta {
+    nodes: isize,
+    label_bytes: isize,
+}
+
+fn insert<V>(node: &mut Node<V>, rest: &[u8], value: V, delta: &mut Delta) -> Option<V> {
+    if rest.is_empty() {
+        return node.value.replace(value);
+    }
+
+    let index = match node.find_child(rest[0]) {
+        Ok(index) => index,
+        Err(index) => {
+            node.children.insert(index, Node::new(rest, Some(value)));
+            delta.nodes += 1;
+            delta.label_bytes += rest.len() as isize;
+            return None;
+        },
+    };
+
+    let child = &mut node.children[index];
+    let common = common_prefix_len(&child.label, rest);
+    if common < child.label.len() {
+        // Split the child so that it ends at the common prefix, and move the rest of its label
+        // into a new node holding its value and children.
+        let mut split = mem::replace(child, Node::new(&rest[..common], None));
+        split.label = split.label[common..].into();
+        child.children.push(split);
+        delta.nodes += 1;
+    }
+    insert(child, &rest[common..], value, delta)
+}
+
+fn remove<V>(node: &mut Node<V>, rest: &[u8], delta: &mut Delta) -> Option<V> {
+    if rest.is_empty() {
+        return node.value.take();
+    }
+
+    let index = node.find_child(rest[0]).ok()?;
+    let child = &mut node.children[index];
+    if !rest.starts_with(&child.label) {
+        return None;
+    }
+    let removed = remove(child, &rest[child.label.len()..], delta)?;
+
+    // Restore the invariant that valueless nodes have at least two children.
+    if child.value.is_none() {
+        match child.children.len() {
+            0 => {
+                let child = node.children.remove(index);
+                delta.nodes -= 1;
+                delta.label_bytes -= child.label.len() as isize;
+            },
+            1 => {
+                let grandchild = child.children.pop().expect("Child has one child");
+                let mut label = child.label.to_vec();
+                label.extend_from_slice(&grandchild.label);
+                *child = Node {
+                    label: label.into(),
+                    value: grandchild.value,
+                    children: grandchild.children,
+                };
+                delta.nodes -= 1;
+            },
+            _ => {},
+        }
+    }
+
+    Some(removed)
+}
+
+fn for_each_mut<V, F>(node: &mut Node<V>, key: &mut Vec<u8>, f: &mut F) -> Result<()>
+where
+    F: FnMut(&[u8], &mut V) -> Result<()>,
+{
+    key.extend_from_slice(&node.label);
+    if let Some(value) = node.value.as_mut() {
+        f(key, value)?;
+    }
+    for child in node.children.iter_mut() {
+        for_each_mut(child, key, f)?;
+    }
+    key.truncate(key.len() - node.label.len());
+    Ok(())
+}
+
+/// Visit the keys under `node` (whose parents' labels make up `key`) from `start` onwards,
+/// returning whether `f` wants to carry on.
+fn scan_from<V, F>(node: &Node<V>, key: &mut Vec<u8>, start: &[u8], f: &mut F) -> bool
+where
+    F: FnMut(&[u8], &V) -> bool,
+{
+    key.extend_from_slice(&node.label);
+    let len = key.len().min(start.len());
+    let carry_on = match key[..len].cmp(&start[..len]) {
+        // Every key under the node comes before `start`.
+        Ordering::Less => true,
+        // The node's key is a proper prefix of `start`, so only some of its children's keys may
+        // come after it.
+        Ordering::Equal if key.len() < start.len() => {
+            node.children.iter().all(|child| scan_from(child, key, start, f))
+        },
+        // Every key under the node comes after `start`.
+        _ => {
+            node.value.as_ref().is_none_or(|value| f(key, value))
+                && node.children.iter().all(|child| scan_from(child, key, &[], f))
+        },
+    };
+    key.truncate(key.len() - node.label.len());
+    carry_on
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A cap on the number of background jobs (currently compactions) running at once, shared by
+/// every store opened with it.
+///
+/// A store that needs to compact while every slot is taken skips compacting, and tries again on
+/// its next write.
+///
+/// ```
+/// use kvs::BackgroundJobs;
+///
+/// let jobs = BackgroundJobs::new(1);
+/// let slot = jobs.try_start().unwrap();
+/// assert!(jobs.try_start().is_none());
+/// drop(slot);
+/// assert_eq!(jobs.running(), 0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct BackgroundJobs {
+    max: usize,
+    running: Arc<AtomicUsize>,
+}
+
+impl BackgroundJobs {
+    /// Construct a cap allowing up to `max` jobs to run at once.
+    pub fn new(max: usize) -> Self {
+        BackgroundJobs {
+            max,
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Take a slot for a job, if there's one free. The slot is freed when it's dropped.
+    pub fn try_start(&self) -> Option<JobSlot> {
+        let max = self.max;
+        self.running
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+                if running < max {
+                    Some(running + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| JobSlot {
+                running: Arc::clone(&self.running),
+            })
+    }
+
+    /// The number of jobs currently running.
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+}
+
+/// A running job's slot in [`BackgroundJobs`], which is freed when dropped.
+#[derive(Debug)]
+pub struct JobSlot {
+    running: Arc<AtomicUsize>,
+}
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        self.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+use crate::error::Result;
+
+/// The name of the file locked to coordinate a store with its followers.
+const LOCK_FILE: &str = "LOCK";
+
+/// An advisory lock on a store's `LOCK` file, released when it's dropped.
+///
+/// A store holds the lock exclusively while it records a compaction in the manifest and deletes
+/// the files the compaction replaced, and followers hold it shared while they read the manifest
+/// and open the files it lists, so that a follower never opens a file that's just been deleted.
+/// Files are only locked on Unix. Elsewhere a follower that loses that race fails to open the
+/// file, and can retry once it's read the new manifest.
+pub struct DirLock {
+    _file: File,
+}
+
+impl DirLock {
+    /// Lock `dir` for a store replacing its log files.
+    pub fn exclusive(dir: &Path) -> Result<Self> {
+        lock(dir, true)
+    }
+
+    /// Lock `dir` for a follower reading the store's log files.
+    pub fn shared(dir: &Path) -> Result<Self> {
+        lock(dir, false)
+    }
+}
+
+#[cfg(unix)]
+fn lock(dir: &Path, exclusive: bool) -> Result<DirLock> {
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    let file = open(dir)?;
+    let operation = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(DirLock { _file: file })
+}
+
+#[cfg(not(unix))]
+fn lock(dir: &Path, _exclusive: bool) -> Result<DirLock> {
+    Ok(DirLock { _file: open(dir)? })
+}
+
+fn open(dir: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(LOCK_FILE))?;
+    Ok(file)
+}
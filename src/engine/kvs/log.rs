@@ -1,10 +1,16 @@
 use rmp_serde::decode::{Error::InvalidMarkerRead, from_read as read_mp};
 use rmp_serde::encode::write as write_mp;
-use serde::{Deserialize, Serialize};
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
 use std::fs::File;
-use std::io::{self, Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
+use super::commit::{GroupCommit, SyncPoint};
+use super::format::{self, BYTES_VERSION, FRAMED_VERSION, LOG_MAGIC};
 
 /// The offset of the value in a serialized Command.
 ///
@@ -19,7 +25,32 @@ use crate::error::Result;
 /// - The `fixarray` format marker for the number of fields in the variant.
 const VALUE_OFFSET: u64 = 3;
 
+/// The length of the frame before each command in a log file from [`FRAMED_VERSION`] on: the
+/// length of the serialized command, then its CRC32 checksum, each a little-endian `u32`.
+///
+/// The checksum is verified whenever a command is read, so that a corrupt command is reported as
+/// [`Error::Corruption`] rather than misread.
+///
+/// [`Error::Corruption`]: enum.Error.html#variant.Corruption
+const FRAME_LEN: u64 = 8;
+
 /// An enum representing the available KvStore commands.
+///
+/// Every command carries a `seq`uence number, which increases by one with each command written to
+/// the store. Commands written before sequence numbers were introduced have no `seq` field, and
+/// are read with a `seq` of `0`.
+///
+/// `Set` commands may carry the time at which the key `expires`, in milliseconds since the UNIX
+/// epoch. Commands written before TTLs were introduced are read as never expiring.
+///
+/// `Set` commands also carry the time at which the key was last `modified`, in milliseconds since
+/// the UNIX epoch. Commands written before modification times were introduced have none.
+///
+/// Compactions may write a `SetCompressed` in place of a `Set`, if the store is configured with a
+/// [compression dictionary](struct.KvStoreBuilder.html#method.compression_dictionary).
+///
+/// Keys and values are arbitrary bytes, written as MessagePack `bin`s. Commands written before
+/// [`BYTES_VERSION`] hold them as `str`s, which are read as their UTF-8 bytes.
 #[derive(Debug, Deserialize, Serialize)]
 pub enum Command {
     /// Set a given `key` to a given `value`.
@@ -27,15 +58,181 @@ pub enum Command {
     /// **Note:** the field ordering is important here as it ensures the value is serialized before
     /// the key. This allows [`Reader`] to read values from disk without having to first read keys
     /// (e.g. when the location is known from an index).
-    Set { value: String, key: String },
+    Set {
+        #[serde(with = "crate::bytes")]
+        value: Vec<u8>,
+        #[serde(with = "crate::bytes")]
+        key: Vec<u8>,
+        #[serde(default)]
+        seq: u64,
+        #[serde(default)]
+        expires: Option<u64>,
+        #[serde(default)]
+        modified: Option<u64>,
+    },
 
     /// Remove a given `key`.
-    Remove { key: String },
+    Remove {
+        #[serde(with = "crate::bytes")]
+        key: Vec<u8>,
+        #[serde(default)]
+        seq: u64,
+    },
+
+    /// Set a key to a given `value`, where the key is the first `shared` bytes of the previous
+    /// command's key followed by `suffix`.
+    ///
+    /// This is only written by [`Writer::write_prefixed`], and never returned by [`Reader::load`]
+    /// (which resolves it to a [`Command::Set`]).
+    SetPrefixed {
+        #[serde(with = "crate::bytes")]
+        value: Vec<u8>,
+        shared: u64,
+        #[serde(with = "crate::bytes")]
+        suffix: Vec<u8>,
+        #[serde(default)]
+        seq: u64,
+        #[serde(default)]
+        expires: Option<u64>,
+        #[serde(default)]
+        modified: Option<u64>,
+    },
+
+    /// Set a key to a `value` compressed with the store's compression `dictionary` with the given
+    /// ID.
+    ///
+    /// As for [`Command::Set`], the value is serialized first, followed by the dictionary, so that
+    /// [`Reader::read_value`] can decompress it without reading the rest of the command.
+    SetCompressed {
+        value: Compressed,
+        dictionary: u64,
+        #[serde(with = "crate::bytes")]
+        key: Vec<u8>,
+        seq: u64,
+        expires: Option<u64>,
+        modified: Option<u64>,
+    },
+
+    /// Move the value (and expiry) of key `from` to key `to`, replacing any value `to` had.
+    ///
+    /// Renames are a single command so that they're applied atomically: after a crash, either
+    /// `from` or `to` has the value, never both or neither. As for [`Command::Set`], the value is
+    /// serialized first, so the command can be indexed as a set of `to`.
+    Rename {
+        #[serde(with = "crate::bytes")]
+        value: Vec<u8>,
+        #[serde(with = "crate::bytes")]
+        to: Vec<u8>,
+        #[serde(with = "crate::bytes")]
+        from: Vec<u8>,
+        seq: u64,
+        expires: Option<u64>,
+        modified: Option<u64>,
+    },
 }
 
+impl Command {
+    /// The key the command writes: the new key of a `Rename`, or only the suffix of a
+    /// `SetPrefixed`.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Command::Set { key, .. }
+            | Command::Remove { key, .. }
+            | Command::SetCompressed { key, .. } => key,
+            Command::SetPrefixed { suffix, .. } => suffix,
+            Command::Rename { to, .. } => to,
+        }
+    }
 
-/// A marker struct indicating that the contained value is a valid log offset.
+    /// The command's sequence number, or `0` if it was written without one.
+    pub fn seq(&self) -> u64 {
+        match self {
+            Command::Set { seq, .. }
+            | Command::Remove { seq, .. }
+            | Command::SetPrefixed { seq, .. }
+            | Command::SetCompressed { seq, .. }
+            | Command::Rename { seq, .. } => *seq,
+        }
+    }
+}
+
+/// The compressed bytes of a value, serialized as a MessagePack `bin` rather than an array.
+#[derive(Debug)]
+pub struct Compressed(pub Vec<u8>);
+
+impl Serialize for Compressed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Compressed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        match deserializer.deserialize_bytes(StoredValueVisitor)? {
+            StoredValue::Compressed { bytes, .. } => Ok(Compressed(bytes)),
+            StoredValue::Plain(_) => Err(de::Error::custom("expected compressed bytes")),
+        }
+    }
+}
+
+/// A value read from the log, which is either plain or compressed with a dictionary.
 #[derive(Debug)]
+pub enum StoredValue {
+    Plain(Vec<u8>),
+    Compressed { bytes: Vec<u8>, dictionary: u64 },
+}
+
+impl<'de> Deserialize<'de> for StoredValue {
+    /// Deserialize a plain value from a string, or a compressed value from bytes. The dictionary
+    /// of a compressed value isn't part of it, and is left as `0`.
+    ///
+    /// This only tells them apart in files from before [`BYTES_VERSION`], whose plain values are
+    /// strings.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(StoredValueVisitor)
+    }
+}
+
+struct StoredValueVisitor;
+
+impl<'de> Visitor<'de> for StoredValueVisitor {
+    type Value = StoredValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> std::result::Result<StoredValue, E> {
+        Ok(StoredValue::Plain(value.as_bytes().to_vec()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> std::result::Result<StoredValue, E> {
+        Ok(StoredValue::Plain(value.into_bytes()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> std::result::Result<StoredValue, E> {
+        Ok(StoredValue::Compressed { bytes: bytes.to_vec(), dictionary: 0 })
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> std::result::Result<StoredValue, E> {
+        Ok(StoredValue::Compressed { bytes, dictionary: 0 })
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<StoredValue, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(StoredValue::Compressed { bytes, dictionary: 0 })
+    }
+}
+
+
+/// A marker struct indicating that the contained value is a valid log offset.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Offset(u64);
 
 impl std::ops::Deref for Offset {
@@ -54,47 +251,194 @@ impl std::convert::From<u64> for Offset {
 #[derive(Debug)]
 pub struct Reader {
   file: File,
+  path: PathBuf,
+  version: u32,
+  start: u64,
 }
 
 impl Reader {
-  pub fn new(file: File) -> Reader {
-    Reader { file }
+  /// Construct a reader for the log file at `path`, checking its format version.
+  pub fn new(mut file: File, path: PathBuf) -> Result<Reader> {
+    let (version, start) = format::read_header(&mut file, LOG_MAGIC)?;
+    Ok(Reader { file, path, version, start })
+  }
+
+  /// The format version of the log file.
+  pub fn version(&self) -> u32 {
+    self.version
   }
 
-  pub fn read_value(&mut self, offset: &Offset) -> Result<String> {
+  /// Read the value at `offset`, along with the dictionary it was compressed with (if it was).
+  ///
+  /// From [`BYTES_VERSION`], plain values can't be told from compressed ones by their encoding,
+  /// so the whole command is decoded.
+  pub fn read_value(&mut self, offset: &Offset) -> Result<StoredValue> {
+    if self.version >= BYTES_VERSION {
+      let start = **offset - VALUE_OFFSET - FRAME_LEN;
+      return match read_mp(&self.read_framed(offset)?[..])? {
+        Command::Set { value, .. }
+        | Command::SetPrefixed { value, .. }
+        | Command::Rename { value, .. } => Ok(StoredValue::Plain(value)),
+        Command::SetCompressed { value, dictionary, .. } => {
+          Ok(StoredValue::Compressed { bytes: value.0, dictionary })
+        },
+        Command::Remove { .. } => Err(Error::Corruption { file: self.path.clone(), offset: start }),
+      };
+    }
+    if self.version >= FRAMED_VERSION {
+      let command = self.read_framed(offset)?;
+      return read_stored_value(&mut &command[VALUE_OFFSET as usize..]);
+    }
     self.file.seek(SeekFrom::Start(**offset))?;
+    read_stored_value(&mut self.file)
+  }
+
+  /// Read the whole command whose value is at `offset`.
+  ///
+  /// A [`Command::SetPrefixed`] is returned as-is, since the previous command's key isn't known.
+  pub fn read_command(&mut self, offset: &Offset) -> Result<Command> {
+    if self.version >= FRAMED_VERSION {
+      let command = self.read_framed(offset)?;
+      return Ok(read_mp(&command[..])?);
+    }
+    self.file.seek(SeekFrom::Start(**offset - VALUE_OFFSET))?;
     Ok(read_mp(&mut self.file)?)
   }
 
   pub fn load(&mut self) -> Result<ReaderIterator<&mut File>> {
-    Ok(ReaderIterator::init(&mut self.file)?)
+    ReaderIterator::init(&mut self.file, &self.path, self.version, self.start)
+  }
+
+  /// Iterate over the commands from `offset` onwards, e.g. to pick up where an earlier iterator
+  /// stopped (see [`ReaderIterator::offset`]).
+  ///
+  /// The first command can't be a [`Command::SetPrefixed`], since the previous command's key
+  /// isn't known.
+  pub fn load_from(&mut self, offset: u64) -> Result<ReaderIterator<&mut File>> {
+    ReaderIterator::init(&mut self.file, &self.path, self.version, offset.max(self.start))
+  }
+
+  /// Whether the corrupt command at `offset` was torn by a crash while it was being written,
+  /// rather than corrupted afterwards: whether it runs to the end of the file, with nothing but
+  /// zeroes (which a file system may leave in space it allocated but never wrote) after it.
+  ///
+  /// Only framed files can tell, since otherwise the command's length isn't known.
+  pub fn is_torn_tail(&mut self, offset: u64) -> Result<bool> {
+    if self.version < FRAMED_VERSION {
+      return Ok(false);
+    }
+    let file_len = self.file.metadata()?.len();
+    self.file.seek(SeekFrom::Start(offset))?;
+    let mut frame = Vec::with_capacity(FRAME_LEN as usize);
+    (&mut self.file).take(FRAME_LEN).read_to_end(&mut frame)?;
+    if frame.len() < FRAME_LEN as usize {
+      return Ok(true);
+    }
+    let mut length = [0; 4];
+    length.copy_from_slice(&frame[..4]);
+    let end = offset + FRAME_LEN + u64::from(u32::from_le_bytes(length));
+    if end >= file_len {
+      return Ok(true);
+    }
+    self.file.seek(SeekFrom::Start(end))?;
+    let mut rest = Vec::new();
+    self.file.read_to_end(&mut rest)?;
+    Ok(rest.iter().all(|&byte| byte == 0))
+  }
+
+  /// Read and verify the serialized command whose value is at `offset`, in a framed file.
+  fn read_framed(&mut self, offset: &Offset) -> Result<Vec<u8>> {
+    let start = **offset - VALUE_OFFSET - FRAME_LEN;
+    self.file.seek(SeekFrom::Start(start))?;
+    match read_frame(&mut self.file, &self.path, start)? {
+      Some(command) if command.len() as u64 > VALUE_OFFSET => Ok(command),
+      _ => Err(Error::Corruption { file: self.path.clone(), offset: start }),
+    }
   }
 }
 
 pub struct ReaderIterator<R: io::Read + Seek> {
   reader: R,
+  path: PathBuf,
+  framed: bool,
   offset: u64,
+  last_key: Vec<u8>,
 }
 
 impl<R: io::Read + Seek> ReaderIterator<R> {
-  fn init(mut reader: R) -> Result<Self> {
-    reader.seek(SeekFrom::Start(0))?;
-    Ok(ReaderIterator { reader, offset: 0 })
+  fn init(mut reader: R, path: &Path, version: u32, start: u64) -> Result<Self> {
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(ReaderIterator {
+      reader,
+      path: path.to_owned(),
+      framed: version >= FRAMED_VERSION,
+      offset: start,
+      last_key: Vec::new(),
+    })
+  }
+
+  /// The offset of the next command to be read.
+  pub fn offset(&self) -> u64 {
+    self.offset
+  }
+
+  /// Read the next command, or `None` at the end of the file, returning the offset at which the
+  /// command itself starts (after its frame, if it has one).
+  fn read_command(&mut self) -> Option<Result<(Command, u64)>> {
+    let offset = self.offset;
+    if !self.framed {
+      return match read_mp(&mut *self) {
+        Ok(command) => Some(Ok((command, offset))),
+        Err(InvalidMarkerRead(_)) => None,
+        Err(err) => Some(Err(err.into())),
+      };
+    }
+    let path = self.path.clone();
+    match read_frame(&mut *self, &path, offset) {
+      Ok(Some(command)) => match read_mp(&command[..]) {
+        Ok(command) => Some(Ok((command, offset + FRAME_LEN))),
+        Err(err) => Some(Err(err.into())),
+      },
+      Ok(None) => None,
+      Err(err) => Some(Err(err)),
+    }
+  }
+
+  /// Resolve a [`Command::SetPrefixed`] to a [`Command::Set`] using the previous command's key.
+  fn resolve(&mut self, command: Command) -> Result<Command> {
+    let command = match command {
+      Command::SetPrefixed { value, shared, suffix, seq, expires, modified } => {
+        let shared = shared as usize;
+        if shared > self.last_key.len() {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid shared prefix length {} at offset {}", shared, self.offset),
+          ).into());
+        }
+        let mut key = Vec::with_capacity(shared + suffix.len());
+        key.extend_from_slice(&self.last_key[..shared]);
+        key.extend_from_slice(&suffix);
+        Command::Set { value, key, seq, expires, modified }
+      },
+      command => command,
+    };
+    self.last_key.clear();
+    self.last_key.extend_from_slice(command.key());
+    Ok(command)
   }
 }
 
 impl<R: io::Read + Seek> Iterator for ReaderIterator<R> {
   type Item = Result<(Command, Offset, u64)>;
 
+  /// Read the next command, with the offset of its value and its length (including its frame).
   fn next(&mut self) -> Option<Self::Item> {
-    let offset = self.offset;
-    match read_mp(&mut *self) {
-      Ok(command) => {
-        Some(Ok((command, offset.into(), self.offset - offset)))
-      },
-      Err(InvalidMarkerRead(_)) => None,
-      Err(err) => Some(Err(err.into())),
-    }
+    let start = self.offset;
+    let result = self.read_command()?.and_then(|(command, offset)| {
+      let command = self.resolve(command)?;
+      Ok((command, offset.into(), self.offset - start))
+    });
+    Some(result)
   }
 }
 
@@ -107,23 +451,161 @@ impl<R: io::Read + Seek> io::Read for ReaderIterator<R> {
   }
 }
 
+/// Read a value, followed by its dictionary if it's compressed.
+fn read_stored_value<R: Read>(reader: &mut R) -> Result<StoredValue> {
+  match read_mp(&mut *reader)? {
+    StoredValue::Compressed { bytes, .. } => {
+      let dictionary = read_mp(&mut *reader)?;
+      Ok(StoredValue::Compressed { bytes, dictionary })
+    },
+    value => Ok(value),
+  }
+}
+
+/// Read a framed command (see [`FRAME_LEN`]) starting at `offset` in the file at `path`, checking
+/// its checksum, or `None` at the end of the file.
+///
+/// A command that's empty, cut short or doesn't match its checksum is an [`Error::Corruption`].
+///
+/// [`Error::Corruption`]: enum.Error.html#variant.Corruption
+fn read_frame<R: Read>(reader: &mut R, path: &Path, offset: u64) -> Result<Option<Vec<u8>>> {
+  let corruption = || Error::Corruption { file: path.to_owned(), offset };
+  let mut frame = [0; FRAME_LEN as usize];
+  let mut filled = 0;
+  while filled < frame.len() {
+    match reader.read(&mut frame[filled..]) {
+      Ok(0) if filled == 0 => return Ok(None),
+      Ok(0) => return Err(corruption()),
+      Ok(read) => filled += read,
+      Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+      Err(err) => return Err(err.into()),
+    }
+  }
+  let mut length = [0; 4];
+  let mut checksum = [0; 4];
+  length.copy_from_slice(&frame[..4]);
+  checksum.copy_from_slice(&frame[4..]);
+  let length = u32::from_le_bytes(length);
+
+  let mut command = Vec::new();
+  reader.by_ref().take(u64::from(length)).read_to_end(&mut command)?;
+  let checksum = u32::from_le_bytes(checksum);
+  if length == 0 || command.len() != length as usize || crc32fast::hash(&command) != checksum {
+    return Err(corruption());
+  }
+  Ok(Some(command))
+}
+
 /// A Write + Seek implementor that tracks its offset.
+///
+/// The writer also tracks how much of the file is known to be durable: anything in the file when
+/// it was opened, and anything written before the last sync. Syncs go through a [`GroupCommit`],
+/// so they can be made from other threads with a [`SyncPoint`], sharing a single sync.
 pub struct Writer {
     file: File,
     offset: u64,
+    commit: Arc<GroupCommit>,
+    last_key: Option<Vec<u8>>,
 }
 
 impl Writer {
+    /// Construct a writer that appends to a log file, writing a header if the file is empty.
     pub fn init(mut file: File) -> Result<Writer> {
         let offset = file.seek(SeekFrom::End(0))?;
-        Ok(Writer { file, offset })
+        let commit = Arc::new(GroupCommit::new(file.try_clone()?, offset));
+        let mut writer = Writer { file, offset, commit, last_key: None };
+        if offset == 0 {
+            format::write_header(&mut writer, LOG_MAGIC)?;
+        }
+        Ok(writer)
     }
 
     pub fn write(&mut self, command: &Command) -> Result<(Offset, u64)> {
+        let written = self.write_framed(command)?;
+        self.last_key = Some(command.key().to_owned());
+        Ok(written)
+    }
+
+    /// Write a `Set` command with its key encoded relative to the previously written key.
+    ///
+    /// If the writer was opened on an existing file, the first key is written in full.
+    pub fn write_prefixed(
+        &mut self,
+        key: &[u8],
+        value: Vec<u8>,
+        seq: u64,
+        expires: Option<u64>,
+        modified: Option<u64>,
+    ) -> Result<(Offset, u64)> {
+        let shared = match &self.last_key {
+            Some(last_key) => last_key.iter().zip(key).take_while(|(a, b)| a == b).count(),
+            None => 0,
+        };
+        let command = Command::SetPrefixed {
+            value,
+            shared: shared as u64,
+            suffix: key[shared..].to_owned(),
+            seq,
+            expires,
+            modified,
+        };
+
+        let written = self.write_framed(&command)?;
+        self.last_key = Some(key.to_owned());
+        Ok(written)
+    }
+
+    /// The offset at which the next command will be written, i.e. the length of the file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Sync written commands to disk, unless they already have been (or another thread is
+    /// syncing them).
+    pub fn sync(&mut self) -> Result<()> {
+        self.commit.sync_to(self.offset)
+    }
+
+    /// A point that the commands written so far can be synced at from another thread.
+    pub fn sync_point(&self) -> SyncPoint {
+        SyncPoint::new(self.commit.clone(), self.offset)
+    }
+
+    /// The group commit syncing the file being written, e.g. to sync it from a background thread.
+    pub fn group_commit(&self) -> Arc<GroupCommit> {
+        self.commit.clone()
+    }
+
+    /// Whether the command at `offset` is known to be durable.
+    pub fn is_synced(&self, offset: u64) -> bool {
+        self.commit.is_synced(offset)
+    }
+
+    /// Write `command` with its frame (see [`FRAME_LEN`]), returning the offset of its value and
+    /// the length written.
+    fn write_framed(&mut self, command: &Command) -> Result<(Offset, u64)> {
+        let mut bytes = Vec::new();
+        write_mp(&mut bytes, command)?;
+        let mut record = Vec::with_capacity(FRAME_LEN as usize + bytes.len());
+        record.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32fast::hash(&bytes).to_le_bytes());
+        record.extend_from_slice(&bytes);
+
         let offset = self.offset;
-        write_mp(self, command)?;
-        let length = self.offset - offset;
-        Ok((offset.into(), length))
+        if let Err(err) = self.write_all(&record) {
+            self.truncate(offset)?;
+            return Err(err.into());
+        }
+        Ok(((offset + FRAME_LEN).into(), record.len() as u64))
+    }
+
+    /// Discard anything written after `offset`, i.e. the part of a command that failed to write
+    /// (e.g. because the disk is full), so that the log isn't left with a torn command.
+    fn truncate(&mut self, offset: u64) -> Result<()> {
+        self.file.set_len(offset)?;
+        self.offset = offset;
+        self.commit.truncated(offset);
+        Ok(())
     }
 }
 
@@ -132,6 +614,7 @@ impl io::Write for Writer {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let result = self.file.write(buf)?;
         self.offset += result as u64;
+        self.commit.written(self.offset);
         Ok(result)
     }
 
@@ -145,6 +628,7 @@ impl io::Seek for Writer {
     /// Wrap `file`'s `seek`, but also update the offset.
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         self.offset = self.file.seek(pos)?;
+        self.commit.written(self.offset);
         Ok(self.offset)
     }
 }
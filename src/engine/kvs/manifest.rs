@@ -0,0 +1,126 @@
+use rmp_serde::decode::from_slice as decode_mp;
+use rmp_serde::encode::to_vec as encode_mp;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use crate::error::Result;
+use super::format::{self, MANIFEST_MAGIC};
+
+/// The name of the manifest file within a store's directory.
+pub const MANIFEST_FILE: &str = "MANIFEST";
+
+/// A record of the state of a store's log, persisted alongside the log files.
+///
+/// Stores written before the manifest existed have no manifest file, which is equivalent to
+/// [`Manifest::default`].
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    /// The sequence number of the last command known to have been written.
+    pub last_seq: u64,
+
+    /// The log index of the file written by the latest compaction, if there has been one.
+    ///
+    /// Log files with a lower index are superseded by this file, unless they're in
+    /// [`log_indices`] (i.e. they were newer than the files that were compacted).
+    ///
+    /// [`log_indices`]: #structfield.log_indices
+    pub compacted_index: Option<u64>,
+
+    /// The sequence number of the last command included in the latest compaction.
+    pub compacted_seq: u64,
+
+    /// The log files that make up the store, in order.
+    pub log_indices: Vec<u64>,
+
+    /// The latest wall-clock time seen by the store, in milliseconds since the UNIX epoch.
+    ///
+    /// The store's view of the time never goes back past this, even across restarts, so that
+    /// expired keys stay expired if the clock jumps backwards.
+    #[serde(default)]
+    pub clock_epoch: u64,
+
+    /// Set when the store is closed cleanly, and cleared when it's next opened.
+    ///
+    /// If a store is opened without this marker it must have crashed (or been killed), and its
+    /// log is checked command-by-command as it's replayed.
+    #[serde(default)]
+    pub clean_shutdown: Option<CleanShutdown>,
+
+    /// The ID of the compression dictionary trained by the latest compaction, which the next
+    /// compaction compresses values with.
+    #[serde(default)]
+    pub dictionary: Option<u64>,
+}
+
+/// The state of a store's log when it was closed cleanly.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CleanShutdown {
+    /// The sequence number of the last command written.
+    pub last_seq: u64,
+
+    /// The log file that was being written to.
+    pub log_index: u64,
+
+    /// The length of that file, up to which the index had been built.
+    pub log_bytes: u64,
+}
+
+impl Manifest {
+    /// Load the manifest from a store directory, returning `None` if there isn't one.
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        match fs::read(dir.join(MANIFEST_FILE)) {
+            Ok(bytes) => {
+                let (_, contents) = format::split_header(&bytes, MANIFEST_MAGIC)?;
+                Ok(Some(decode_mp(contents)?))
+            },
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Whether the store in `dir` was closed cleanly, and its log files are exactly as they were
+    /// left, so that replaying them needn't check every command.
+    pub fn is_clean(&self, dir: &Path, log_indices: &[u64]) -> Result<bool> {
+        let shutdown = match &self.clean_shutdown {
+            Some(shutdown) => shutdown,
+            None => return Ok(false),
+        };
+        if log_indices != self.log_indices.as_slice()
+            || log_indices.last() != Some(&shutdown.log_index)
+        {
+            return Ok(false);
+        }
+        let log_path = dir.join(format!("{}.log", shutdown.log_index));
+        Ok(fs::metadata(log_path)?.len() == shutdown.log_bytes)
+    }
+
+    /// Whether a log file has been superseded by the latest compaction, so is safe to delete.
+    ///
+    /// Such files are left behind if compaction is interrupted after the manifest is updated.
+    pub fn is_superseded(&self, log_index: u64) -> bool {
+        let older = self.compacted_index.is_some_and(|compacted| log_index < compacted);
+        older && !self.log_indices.contains(&log_index)
+    }
+
+    /// Atomically replace the manifest in a store directory.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let temp_path = dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let mut file = File::create(&temp_path)?;
+        format::write_header(&mut file, MANIFEST_MAGIC)?;
+        file.write_all(&encode_mp(self)?)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+}
+
+/// Sort log indices into the order their files are replayed in: the compacted file first, then
+/// the rest in log index order.
+///
+/// Compacted files are written with a new log index, so a compaction of only the oldest files
+/// leaves files with lower indices behind, whose commands follow on from the compacted file's.
+pub fn replay_order(log_indices: &mut [u64], compacted_index: Option<u64>) {
+    log_indices.sort_unstable_by_key(|&log_index| (Some(log_index) != compacted_index, log_index));
+}
@@ -0,0 +1,66 @@
+use std::collections::{HashMap, VecDeque};
+
+/// The values of the most recently written keys, kept in memory so that reading a key back soon
+/// after writing it doesn't need a file seek.
+///
+/// The log stays the source of truth: each value is tagged with the sequence number of the
+/// command that wrote it, and is only returned if the index still points at that command.
+#[derive(Debug)]
+pub struct Memtable {
+    capacity: usize,
+    values: HashMap<Vec<u8>, (u64, Vec<u8>)>,
+    order: VecDeque<(Vec<u8>, u64)>,
+    hits: u64,
+}
+
+impl Memtable {
+    /// Construct an empty memtable holding at most `capacity` values.
+    pub fn new(capacity: usize) -> Self {
+        Memtable {
+            capacity,
+            values: HashMap::new(),
+            order: VecDeque::new(),
+            hits: 0,
+        }
+    }
+
+    /// Record that `key` was set to `value` by the command with sequence number `seq`, evicting
+    /// the oldest value if the memtable is full.
+    pub fn insert(&mut self, key: Vec<u8>, seq: u64, value: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.order.len() >= self.capacity {
+            let (oldest, oldest_seq) = self.order.pop_front().expect("Memtable isn't empty");
+            // The key may have been written again since, in which case its value is still recent.
+            if self.values.get(&oldest).is_some_and(|(seq, _)| *seq == oldest_seq) {
+                self.values.remove(&oldest);
+            }
+        }
+        self.order.push_back((key.clone(), seq));
+        self.values.insert(key, (seq, value));
+    }
+
+    /// The value of `key` written by the command with sequence number `seq`, if it's still held.
+    pub fn get(&mut self, key: &[u8], seq: u64) -> Option<Vec<u8>> {
+        let value = self.values.get(key).filter(|(held, _)| *held == seq)?.1.clone();
+        self.hits += 1;
+        Some(value)
+    }
+
+    /// Forget the value of `key`, e.g. because it's been removed.
+    pub fn remove(&mut self, key: &[u8]) {
+        self.values.remove(key);
+    }
+
+    /// Forget every value, e.g. because compaction may have changed them.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.order.clear();
+    }
+
+    /// The number of reads served from the memtable.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use super::dictionary::Dictionaries;
+use super::index::IndexEntry;
+use super::log::{Reader, StoredValue};
+use super::open_reader;
+
+/// The log files that make up a store, with an LRU cache of open readers.
+///
+/// Readers are opened on demand, and once `capacity` are open the least recently used is closed to
+/// make room for the next. Each file's format version is remembered when it's first opened, so it
+/// remains available after the reader is closed.
+///
+/// The dictionaries that compressed values are read with are kept here too.
+pub struct Readers {
+    dir: PathBuf,
+    capacity: usize,
+    versions: BTreeMap<u64, u32>,
+    open: HashMap<u64, Reader>,
+    recency: VecDeque<u64>,
+    evictions: u64,
+    dictionaries: Dictionaries,
+}
+
+impl Readers {
+    /// Construct an empty set of log files in `dir`, keeping at most `capacity` readers open, and
+    /// reading compressed values with `dictionaries`.
+    pub fn new(dir: &Path, capacity: usize, dictionaries: Dictionaries) -> Self {
+        Readers {
+            dir: dir.to_owned(),
+            capacity: capacity.max(1),
+            versions: BTreeMap::new(),
+            open: HashMap::new(),
+            recency: VecDeque::new(),
+            evictions: 0,
+            dictionaries,
+        }
+    }
+
+    /// Add a log file, returning a reader for it.
+    pub fn insert(&mut self, log_index: u64) -> Result<&mut Reader> {
+        let version = self.get(log_index)?.version();
+        self.versions.insert(log_index, version);
+        self.get(log_index)
+    }
+
+    /// Get a reader for a log file, (re)opening it if it isn't open.
+    pub fn get(&mut self, log_index: u64) -> Result<&mut Reader> {
+        if self.open.contains_key(&log_index) {
+            self.touch(log_index);
+        } else {
+            if self.open.len() >= self.capacity {
+                if let Some(least_recent) = self.recency.pop_back() {
+                    self.open.remove(&least_recent);
+                    self.evictions += 1;
+                }
+            }
+            let reader = open_reader(&self.dir, log_index)?;
+            self.open.insert(log_index, reader);
+            self.recency.push_front(log_index);
+        }
+        Ok(self.open.get_mut(&log_index).expect("Reader was just opened"))
+    }
+
+    /// Read the value of `entry`, from the entry itself if it's inlined, or from its log file.
+    pub fn read(&mut self, entry: &IndexEntry) -> Result<Vec<u8>> {
+        match &entry.value {
+            Some(value) => Ok(value.clone()),
+            None => self.read_log(entry),
+        }
+    }
+
+    /// Read the value of `entry` from its log file, decompressing it if need be.
+    pub fn read_log(&mut self, entry: &IndexEntry) -> Result<Vec<u8>> {
+        let value = self.get(entry.log_index)?.read_value(&entry.offset)?;
+        match value {
+            StoredValue::Plain(value) => Ok(value),
+            StoredValue::Compressed { bytes, dictionary } => {
+                self.dictionaries.decompress(dictionary, &bytes)
+            },
+        }
+    }
+
+    /// The dictionaries that compressed values are read with.
+    pub fn dictionaries(&self) -> &Dictionaries {
+        &self.dictionaries
+    }
+
+    /// The dictionaries that compressed values are read with, to add or remove one.
+    pub fn dictionaries_mut(&mut self) -> &mut Dictionaries {
+        &mut self.dictionaries
+    }
+
+    /// Whether a log file is part of the store.
+    pub fn contains(&self, log_index: u64) -> bool {
+        self.versions.contains_key(&log_index)
+    }
+
+    /// The format version of a log file that's part of the store.
+    pub fn version(&self, log_index: u64) -> u32 {
+        self.versions[&log_index]
+    }
+
+    /// The format versions of every log file in the store.
+    pub fn versions(&self) -> impl Iterator<Item = u32> + '_ {
+        self.versions.values().cloned()
+    }
+
+    /// The log indices of every file in the store, in order.
+    pub fn log_indices(&self) -> Vec<u64> {
+        self.versions.keys().cloned().collect()
+    }
+
+    /// Remove log files (closing their readers) for which `keep` returns false.
+    pub fn retain<F: Fn(u64) -> bool>(&mut self, keep: F) {
+        self.versions.retain(|&log_index, _| keep(log_index));
+        self.open.retain(|&log_index, _| keep(log_index));
+        self.recency.retain(|&log_index| keep(log_index));
+    }
+
+    /// The number of log files in the store.
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    /// The number of readers currently open.
+    pub fn open_len(&self) -> usize {
+        self.open.len()
+    }
+
+    /// The number of readers closed to make room for another.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Move a log file to the front of the recency list.
+    fn touch(&mut self, log_index: u64) {
+        if self.recency.front() != Some(&log_index) {
+            self.recency.retain(|&i| i != log_index);
+            self.recency.push_front(log_index);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use crate::error::Result;
+
+/// The name of the file holding a store's reserved disk space.
+const RESERVE_FILE: &str = "RESERVE";
+
+/// The number of bytes written to check for free space when none is reserved.
+pub const PROBE_BYTES: u64 = 64 * 1024;
+
+/// The size of the chunks in which reserved space is written.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Reserve `bytes` of disk space in `dir`, by writing a file of that size.
+///
+/// This fails with [`Error::DiskFull`] (leaving nothing reserved) if there isn't enough space.
+///
+/// [`Error::DiskFull`]: ../../enum.Error.html#variant.DiskFull
+pub fn reserve(dir: &Path, bytes: u64) -> Result<()> {
+    if bytes == 0 {
+        return release(dir);
+    }
+    let path = dir.join(RESERVE_FILE);
+    match fs::metadata(&path) {
+        Ok(ref metadata) if metadata.len() == bytes => return Ok(()),
+        Ok(_) => fs::remove_file(&path)?,
+        Err(ref err) if err.kind() == ErrorKind::NotFound => {},
+        Err(err) => return Err(err.into()),
+    }
+    let result = write_zeros(&path, bytes);
+    if result.is_err() {
+        let _ = fs::remove_file(&path);
+    }
+    result
+}
+
+/// Release the disk space reserved in `dir`, if any.
+pub fn release(dir: &Path) -> Result<()> {
+    match fs::remove_file(dir.join(RESERVE_FILE)) {
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+fn write_zeros(path: &Path, bytes: u64) -> Result<()> {
+    let mut file = File::create(path)?;
+    let chunk = [0; CHUNK_BYTES];
+    let mut remaining = bytes;
+    while remaining > 0 {
+        let len = remaining.min(CHUNK_BYTES as u64) as usize;
+        file.write_all(&chunk[..len])?;
+        remaining -= len as u64;
+    }
+    file.sync_all()?;
+    Ok(())
+}
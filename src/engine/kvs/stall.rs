@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+/// Thresholds at which a store slows down, and then rejects, writes while compaction is behind.
+///
+/// A store's compaction debt is the number of bytes of stale commands in its log. Debt normally
+/// stays small, since the store compacts as soon as there's enough of it, but it grows without
+/// bound if compaction is deferred for lack of a [background job](struct.BackgroundJobs.html)
+/// slot. Stalling writes stops the log from filling the disk in the meantime.
+///
+/// Only `Set`s are stalled: removing keys adds a little debt, but frees space once compacted.
+///
+/// ```
+/// use std::time::Duration;
+/// use kvs::{KvStore, WriteStall};
+///
+/// let builder = KvStore::builder().write_stall(WriteStall {
+///     slowdown_bytes: 64 * 1024 * 1024,
+///     delay: Duration::from_millis(1),
+///     stop_bytes: 256 * 1024 * 1024,
+/// });
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct WriteStall {
+    /// The compaction debt above which each write is delayed by `delay`.
+    pub slowdown_bytes: u64,
+
+    /// How long to delay each write once compaction debt exceeds `slowdown_bytes`.
+    pub delay: Duration,
+
+    /// The compaction debt above which writes fail with [`Error::Backpressure`].
+    ///
+    /// [`Error::Backpressure`]: enum.Error.html#variant.Backpressure
+    pub stop_bytes: u64,
+}
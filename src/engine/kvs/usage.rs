@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+
+use super::index::IndexEntry;
+
+/// The number of bytes of commands in each of a store's log files, and how many of them belong to
+/// live index entries.
+///
+/// The rest of each file's bytes are stale commands, which compaction reclaims. The bytes of
+/// values inlined in live index entries are also tracked, as they're held in memory.
+#[derive(Debug, Default)]
+pub struct Usage {
+    files: BTreeMap<u64, FileUsage>,
+    inlined_bytes: u64,
+}
+
+/// The bytes of commands in a single log file.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileUsage {
+    /// The number of bytes of commands in the file.
+    pub bytes: u64,
+
+    /// The number of those bytes belonging to live index entries.
+    pub live_bytes: u64,
+}
+
+impl Usage {
+    /// Record a command of `length` bytes written to a log file.
+    pub fn written(&mut self, log_index: u64, length: u64) {
+        self.files.entry(log_index).or_default().bytes += length;
+    }
+
+    /// Record that `entry` was added to the index.
+    pub fn added(&mut self, entry: &IndexEntry) {
+        self.files.entry(entry.log_index).or_default().live_bytes += entry.length;
+        self.inlined_bytes += inlined_len(entry);
+    }
+
+    /// Record that `entry` was replaced in, or removed from, the index.
+    pub fn removed(&mut self, entry: &IndexEntry) {
+        if let Some(file) = self.files.get_mut(&entry.log_index) {
+            file.live_bytes = file.live_bytes.saturating_sub(entry.length);
+        }
+        self.inlined_bytes = self.inlined_bytes.saturating_sub(inlined_len(entry));
+    }
+
+    /// Forget log files for which `keep` returns false.
+    pub fn retain<F: Fn(u64) -> bool>(&mut self, keep: F) {
+        self.files.retain(|&log_index, _| keep(log_index));
+    }
+
+    /// The usage of each log file, in log index order.
+    pub fn files(&self) -> impl Iterator<Item = (u64, FileUsage)> + '_ {
+        self.files.iter().map(|(&log_index, &usage)| (log_index, usage))
+    }
+
+    /// The number of bytes of commands across every log file.
+    pub fn bytes(&self) -> u64 {
+        self.files.values().map(|file| file.bytes).sum()
+    }
+
+    /// The number of bytes of values inlined in live index entries.
+    pub fn inlined_bytes(&self) -> u64 {
+        self.inlined_bytes
+    }
+}
+
+fn inlined_len(entry: &IndexEntry) -> u64 {
+    entry.value.as_ref().map_or(0, |value| value.len() as u64)
+}
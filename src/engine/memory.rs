@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use crate::engine::{utf8_entry, utf8_value, Engine};
+use crate::error::{Error, Result};
+use crate::stats::EngineStats;
+
+/// A key value store held entirely in memory.
+///
+/// Nothing is persisted, so the store is empty whenever it's created. This is useful for caches
+/// and tests.
+///
+/// Keys and values may be arbitrary bytes (see [`get_bytes`]).
+///
+/// [`get_bytes`]: trait.KvsEngine.html#method.get_bytes
+#[derive(Debug, Default)]
+pub struct Store {
+    map: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Store {
+    /// Construct an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entries from `start` onwards, in key order, stopping at the first key for which
+    /// `in_scan` is false.
+    fn entries_from<F>(&self, start: &[u8], in_scan: F) -> Vec<(&[u8], &[u8])>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        self.map
+            .range::<[u8], _>((Bound::Included(start), Bound::Unbounded))
+            .take_while(|(key, _)| in_scan(key))
+            .map(|(key, value)| (&key[..], &value[..]))
+            .collect()
+    }
+
+    /// Like [`entries_from`](#method.entries_from), but as strings, failing if any of them isn't
+    /// UTF-8.
+    fn utf8_entries_from<F>(&self, start: &[u8], in_scan: F) -> Result<Vec<(String, String)>>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let entries = self.entries_from(start, in_scan);
+        entries.into_iter().map(|(key, value)| utf8_entry(key, value)).collect()
+    }
+}
+
+impl Engine for Store {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        match self.map.get(key.as_bytes()) {
+            Some(value) => Ok(Some(utf8_value(&key, value.clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.map.insert(key.into_bytes(), value.into_bytes());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.remove_bytes(key.as_bytes())
+    }
+
+    fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.map.get(key).cloned())
+    }
+
+    fn set_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.map.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn remove_bytes(&mut self, key: &[u8]) -> Result<()> {
+        self.map.remove(key).map(|_| ()).ok_or(Error::KeyNotFound)
+    }
+
+    /// Fails if a matching key or value isn't UTF-8 (see [`scan_bytes`]).
+    ///
+    /// [`scan_bytes`]: trait.KvsEngine.html#method.scan_bytes
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.utf8_entries_from(prefix.as_bytes(), |key| key.starts_with(prefix.as_bytes()))
+    }
+
+    fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let entries = self.entries_from(prefix, |key| key.starts_with(prefix));
+        Ok(entries.into_iter().map(|(key, value)| (key.to_vec(), value.to_vec())).collect())
+    }
+
+    fn scan_range(&mut self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>> {
+        self.utf8_entries_from(start.as_bytes(), |key| {
+            end.is_none_or(|end| key < end.as_bytes())
+        })
+    }
+
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn stats(&mut self) -> Result<EngineStats> {
+        Ok(EngineStats {
+            keys: self.map.len() as u64,
+            ..EngineStats::default()
+        })
+    }
+}
@@ -0,0 +1,55 @@
+use rand::Rng;
+
+use crate::engine::SYSTEM_KEY_PREFIX;
+
+/// A uniform random sample of keys, collected by reservoir sampling (see
+/// [`KvsEngine::sample_keys`]).
+///
+/// Every key offered has the same chance of being in the sample, however many keys are offered
+/// and in whatever order, so keys can be offered from several engines in turn. System keys are
+/// never sampled.
+///
+/// [`KvsEngine::sample_keys`]: trait.KvsEngine.html#method.sample_keys
+#[derive(Clone, Debug)]
+pub struct KeySample {
+    size: usize,
+    keys: Vec<String>,
+    offered: u64,
+}
+
+impl KeySample {
+    /// Construct an empty sample of up to `size` keys.
+    pub fn new(size: usize) -> Self {
+        KeySample {
+            size,
+            keys: Vec::new(),
+            offered: 0,
+        }
+    }
+
+    /// Offer `key` to the sample, which keeps it with probability `size / offered`.
+    pub fn offer(&mut self, key: &str) {
+        if key.starts_with(SYSTEM_KEY_PREFIX) {
+            return;
+        }
+        self.offered += 1;
+        if self.keys.len() < self.size {
+            self.keys.push(key.to_owned());
+            return;
+        }
+        let slot = rand::thread_rng().gen_range(0, self.offered);
+        if slot < self.size as u64 {
+            self.keys[slot as usize] = key.to_owned();
+        }
+    }
+
+    /// The number of keys that have been offered to the sample, excluding system keys.
+    pub fn offered(&self) -> u64 {
+        self.offered
+    }
+
+    /// Take the sampled keys, in no particular order.
+    pub fn into_keys(self) -> Vec<String> {
+        self.keys
+    }
+}
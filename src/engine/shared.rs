@@ -0,0 +1,374 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Under loom (`--cfg kvs_loom`), the locks are loom's, so that tests can check every interleaving
+// of operations.
+#[cfg(kvs_loom)]
+use loom::sync::{Mutex, MutexGuard};
+#[cfg(not(kvs_loom))]
+use std::sync::{Mutex, MutexGuard};
+
+use crate::engine::transaction::{Transaction, WriteLog};
+use crate::engine::{Consistency, Engine, KeySample, SyncPoint};
+use crate::error::Result;
+use crate::protocol::NotFoundReason;
+use crate::stats::EngineStats;
+
+/// An engine that can be cloned and shared between threads, with every clone operating on the
+/// same underlying engine.
+///
+/// Operations take `&self`, and each one holds a lock on the engine for its duration, so
+/// operations from different threads are serialized rather than run in parallel. `Shared` is
+/// itself an engine, so it can still be served by a [`Server`].
+///
+/// ```
+/// # use kvs::{MemoryKvStore, Result, SharedEngine};
+/// # use std::thread;
+/// # fn check() -> Result<()> {
+/// let engine = SharedEngine::new(MemoryKvStore::new());
+/// let writer = engine.clone();
+/// let handle = thread::spawn(move || writer.set("foo".to_owned(), "bar".to_owned()).is_ok());
+/// assert!(handle.join().unwrap());
+/// assert_eq!(engine.get("foo".to_owned())?, Some("bar".to_owned()));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Several operations can also be grouped into a [`Transaction`], with
+/// [`begin_transaction`](#method.begin_transaction).
+///
+/// [`Server`]: struct.Server.html
+/// [`Transaction`]: struct.Transaction.html
+pub struct Shared<E> {
+    engine: Arc<Mutex<E>>,
+    write_log: Arc<Mutex<WriteLog>>,
+    name: Arc<str>,
+}
+
+impl<E: Engine> Shared<E> {
+    /// Share `engine`.
+    pub fn new(engine: E) -> Self {
+        let name = Arc::from(engine.name());
+        Shared {
+            engine: Arc::new(Mutex::new(engine)),
+            write_log: Arc::new(Mutex::new(WriteLog::default())),
+            name,
+        }
+    }
+
+    /// Get the value of a key.
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        self.lock().get(key)
+    }
+
+    /// Set a key to a given value.
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        self.with_write_log(|engine, write_log| {
+            write_log.record(&key);
+            engine.set(key, value)
+        })
+    }
+
+    /// Remove a key (and its value).
+    pub fn remove(&self, key: String) -> Result<()> {
+        self.with_write_log(|engine, write_log| {
+            write_log.record(&key);
+            engine.remove(key)
+        })
+    }
+
+    /// Set a key to `new` if its value is `expected` (or if it isn't in the engine, for an
+    /// `expected` of `None`), returning whether it was set. No other thread can write the key in
+    /// between.
+    pub fn compare_and_swap(
+        &self,
+        key: String,
+        expected: Option<String>,
+        new: String,
+    ) -> Result<bool> {
+        self.with_write_log(|engine, write_log| {
+            let swapped = engine.compare_and_swap(key.clone(), expected, new)?;
+            if swapped {
+                write_log.record(&key);
+            }
+            Ok(swapped)
+        })
+    }
+
+    /// Add `delta` to the integer value of a key (treating a missing key as `0`), returning the
+    /// new value. No other thread can write the key in between.
+    pub fn increment(&self, key: String, delta: i64) -> Result<i64> {
+        self.with_write_log(|engine, write_log| {
+            write_log.record(&key);
+            engine.increment(key, delta)
+        })
+    }
+
+    /// Move a key's value to another key, returning whether it was moved (see
+    /// [`Engine::rename`]).
+    ///
+    /// [`Engine::rename`]: trait.KvsEngine.html#method.rename
+    pub fn rename(&self, from: String, to: String, overwrite: bool) -> Result<bool> {
+        self.with_write_log(|engine, write_log| {
+            let renamed = engine.rename(from.clone(), to.clone(), overwrite)?;
+            if renamed {
+                write_log.record(&from);
+                write_log.record(&to);
+            }
+            Ok(renamed)
+        })
+    }
+
+    /// How up to date reads are with writes, which is the engine's own guarantee: each
+    /// operation holds the lock for its duration, so operations from different clones are
+    /// linearizable if the engine's are.
+    pub fn consistency(&self) -> Consistency {
+        self.lock().consistency()
+    }
+
+    /// Make every write so far durable.
+    ///
+    /// If the engine has sync points (see [`Engine::sync_point`]), the lock is only held to take
+    /// one, so other threads' writes carry on while this one syncs, and threads syncing at the same
+    /// time share a single sync.
+    ///
+    /// [`Engine::sync_point`]: trait.KvsEngine.html#method.sync_point
+    pub fn sync(&self) -> Result<()> {
+        let point = self.lock().sync_point();
+        match point {
+            Some(point) => point.wait(),
+            None => self.lock().sync(),
+        }
+    }
+
+    /// Begin a transaction, whose reads see the engine as it is now and whose writes are applied
+    /// together when it commits.
+    pub fn begin_transaction(&self) -> Transaction<E> {
+        let snapshot = self.write_log().begin();
+        Transaction::new(self.clone(), snapshot)
+    }
+
+    /// Call `f` with exclusive access to the engine, e.g. to make several changes without other
+    /// threads seeing the engine in between.
+    ///
+    /// Open [transactions](struct.Transaction.html) aren't told about writes made this way.
+    pub fn with<T, F: FnOnce(&mut E) -> T>(&self, f: F) -> T {
+        f(&mut self.lock())
+    }
+
+    /// Call `f` with exclusive access to both the engine and its write log.
+    pub(crate) fn with_write_log<T, F: FnOnce(&mut E, &mut WriteLog) -> T>(&self, f: F) -> T {
+        let mut engine = self.lock();
+        let mut write_log = self.write_log();
+        f(&mut engine, &mut write_log)
+    }
+
+    pub(crate) fn write_log(&self) -> MutexGuard<'_, WriteLog> {
+        self.write_log.lock().expect("write log lock poisoned")
+    }
+
+    fn lock(&self) -> MutexGuard<'_, E> {
+        self.engine.lock().expect("engine lock poisoned")
+    }
+}
+
+impl<E> Clone for Shared<E> {
+    fn clone(&self) -> Self {
+        Shared {
+            engine: Arc::clone(&self.engine),
+            write_log: Arc::clone(&self.write_log),
+            name: Arc::clone(&self.name),
+        }
+    }
+}
+
+impl<E: Engine> Engine for Shared<E> {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.lock().get(key)
+    }
+
+    fn lookup(
+        &mut self,
+        key: String,
+    ) -> Result<std::result::Result<String, Option<NotFoundReason>>> {
+        self.lock().lookup(key)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        Shared::set(self, key, value)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        Shared::remove(self, key)
+    }
+
+    fn force_remove(&mut self, key: String) -> Result<()> {
+        self.with_write_log(|engine, write_log| {
+            write_log.record(&key);
+            engine.force_remove(key)
+        })
+    }
+
+    fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.lock().get_bytes(key)
+    }
+
+    fn set_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.with_write_log(|engine, write_log| {
+            write_log.record(key);
+            engine.set_bytes(key, value)
+        })
+    }
+
+    fn remove_bytes(&mut self, key: &[u8]) -> Result<()> {
+        self.with_write_log(|engine, write_log| {
+            write_log.record(key);
+            engine.remove_bytes(key)
+        })
+    }
+
+    fn compare_and_swap(
+        &mut self,
+        key: String,
+        expected: Option<String>,
+        new: String,
+    ) -> Result<bool> {
+        Shared::compare_and_swap(self, key, expected, new)
+    }
+
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        Shared::increment(self, key, delta)
+    }
+
+    fn rename(&mut self, from: String, to: String, overwrite: bool) -> Result<bool> {
+        Shared::rename(self, from, to, overwrite)
+    }
+
+    fn get_many(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.lock().get_many(keys)
+    }
+
+    fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.with_write_log(|engine, write_log| {
+            for (key, _) in &pairs {
+                write_log.record(key);
+            }
+            engine.set_many(pairs)
+        })
+    }
+
+    fn remove_many(&mut self, keys: Vec<String>) -> Result<Vec<String>> {
+        self.with_write_log(|engine, write_log| {
+            for key in &keys {
+                write_log.record(key);
+            }
+            engine.remove_many(keys)
+        })
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Shared::sync(self)
+    }
+
+    fn sync_point(&mut self) -> Option<SyncPoint> {
+        self.lock().sync_point()
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.lock().scan(prefix)
+    }
+
+    fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.lock().scan_bytes(prefix)
+    }
+
+    fn scan_range(&mut self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>> {
+        self.lock().scan_range(start, end)
+    }
+
+    fn scan_until(&mut self, prefix: &str, deadline: Instant) -> Result<Vec<(String, String)>> {
+        self.lock().scan_until(prefix, deadline)
+    }
+
+    fn count_prefix(&mut self, prefix: &str) -> Result<u64> {
+        self.lock().count_prefix(prefix)
+    }
+
+    fn remove_prefix(&mut self, prefix: &str) -> Result<Vec<String>> {
+        self.with_write_log(|engine, write_log| {
+            let removed = engine.remove_prefix(prefix)?;
+            for key in &removed {
+                write_log.record(key);
+            }
+            Ok(removed)
+        })
+    }
+
+    fn cold_keys(
+        &mut self,
+        prefix: &str,
+        unread_for: Duration,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        self.lock().cold_keys(prefix, unread_for, limit)
+    }
+
+    fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        self.with_write_log(|engine, write_log| {
+            write_log.record(&key);
+            engine.set_with_ttl(key, value, ttl)
+        })
+    }
+
+    fn ttl(&mut self, key: String) -> Result<Option<Duration>> {
+        self.lock().ttl(key)
+    }
+
+    fn expire(&mut self, key: String, ttl: Duration) -> Result<()> {
+        self.with_write_log(|engine, write_log| {
+            write_log.record(&key);
+            engine.expire(key, ttl)
+        })
+    }
+
+    fn persist(&mut self, key: String) -> Result<()> {
+        self.with_write_log(|engine, write_log| {
+            write_log.record(&key);
+            engine.persist(key)
+        })
+    }
+
+    fn sweep_expired(&mut self, limit: usize) -> Result<Vec<String>> {
+        self.with_write_log(|engine, write_log| {
+            let expired = engine.sweep_expired(limit)?;
+            for key in &expired {
+                write_log.record(key);
+            }
+            Ok(expired)
+        })
+    }
+
+    fn scrub(&mut self, limit: usize) -> Result<Vec<String>> {
+        self.lock().scrub(limit)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn consistency(&self) -> Consistency {
+        Shared::consistency(self)
+    }
+
+    fn maintain(&mut self) -> Result<()> {
+        self.lock().maintain()
+    }
+
+    fn offer_keys(&mut self, sample: &mut KeySample) -> Result<()> {
+        self.lock().offer_keys(sample)
+    }
+
+    fn stats(&mut self) -> Result<EngineStats> {
+        self.lock().stats()
+    }
+}
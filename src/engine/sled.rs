@@ -1,13 +1,26 @@
 use sled::Tree;
+use std::path::Path;
 
-use crate::engine::Engine;
+use crate::engine::{utf8_entry, utf8_value, Engine};
 use crate::error::{Error, Result};
+use crate::stats::EngineStats;
 
 pub use sled::Db;
 
+/// The files sled keeps at the top level of a database directory.
+const DB_FILES: &[&str] = &["conf", "db"];
+
+/// Whether `dir` contains a sled database.
+pub fn contains_db(dir: &Path) -> bool {
+    DB_FILES.iter().any(|file| dir.join(file).is_file())
+}
+
 impl Engine for Db {
     fn get(&mut self, key: String) -> Result<Option<String>> {
-        Ok(Tree::get(self, key)?.map(|ivec| String::from_utf8_lossy(ivec.as_ref()).into_owned()))
+        match Tree::get(self, &key)? {
+            Some(ivec) => Ok(Some(utf8_value(&key, ivec.to_vec())?)),
+            None => Ok(None),
+        }
     }
 
     fn set(&mut self, key: String, value: String) -> Result<()> {
@@ -17,10 +30,120 @@ impl Engine for Db {
     }
 
     fn remove(&mut self, key: String) -> Result<()> {
-        if Tree::del(self, key)? == None {
+        if Tree::del(self, key)?.is_none() {
+            return Err(Error::KeyNotFound);
+        }
+        self.flush()?;
+        Ok(())
+    }
+
+    fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(Tree::get(self, key)?.map(|ivec| ivec.to_vec()))
+    }
+
+    fn set_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        Tree::set(self, key, value)?;
+        self.flush()?;
+        Ok(())
+    }
+
+    fn remove_bytes(&mut self, key: &[u8]) -> Result<()> {
+        if Tree::del(self, key)?.is_none() {
             return Err(Error::KeyNotFound);
         }
         self.flush()?;
         Ok(())
     }
+
+    /// Set every key, flushing once for the whole batch.
+    fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        for (key, value) in pairs {
+            Tree::set(self, key, value.as_bytes())?;
+        }
+        self.flush()?;
+        Ok(())
+    }
+
+    /// Remove every key that's present, flushing once for the whole batch.
+    fn remove_many(&mut self, keys: Vec<String>) -> Result<Vec<String>> {
+        let mut removed = Vec::new();
+        for key in keys {
+            if Tree::del(self, &key)?.is_some() {
+                removed.push(key);
+            }
+        }
+        if !removed.is_empty() {
+            self.flush()?;
+        }
+        Ok(removed)
+    }
+
+    /// Rename a key, flushing once for both keys.
+    fn rename(&mut self, from: String, to: String, overwrite: bool) -> Result<bool> {
+        let value = Tree::get(self, &from)?.ok_or(Error::KeyNotFound)?;
+        if from == to {
+            return Ok(true);
+        }
+        if !overwrite && Tree::get(self, &to)?.is_some() {
+            return Ok(false);
+        }
+        Tree::set(self, to, value)?;
+        Tree::del(self, from)?;
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Fails if a matching key or value isn't UTF-8 (see [`scan_bytes`]).
+    ///
+    /// [`scan_bytes`]: ../trait.KvsEngine.html#method.scan_bytes
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+        for (key, value) in self.scan_bytes(prefix.as_bytes())? {
+            entries.push(utf8_entry(&key, &value)?);
+        }
+        Ok(entries)
+    }
+
+    /// Iterate over the database from `prefix`, stopping at the first key without it.
+    fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        for item in Tree::range(self, prefix..) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            entries.push((key.to_vec(), value.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    /// Iterate over the database from `start`, stopping at `end`.
+    fn scan_range(&mut self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+        for item in Tree::range(self, start.as_bytes()..) {
+            let (key, value) = item?;
+            if end.is_some_and(|end| key.as_ref() >= end.as_bytes()) {
+                break;
+            }
+            entries.push(utf8_entry(&key, &value)?);
+        }
+        Ok(entries)
+    }
+
+    fn name(&self) -> &str {
+        "sled"
+    }
+
+    /// Flush the database to disk.
+    fn maintain(&mut self) -> Result<()> {
+        self.flush()?;
+        Ok(())
+    }
+
+    fn stats(&mut self) -> Result<EngineStats> {
+        Ok(EngineStats {
+            keys: self.len() as u64,
+            ..EngineStats::default()
+        })
+    }
 }
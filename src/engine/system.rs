@@ -0,0 +1,78 @@
+use rand::Rng;
+
+use crate::engine::Engine;
+use crate::error::Result;
+
+/// The key prefix reserved for internal metadata.
+///
+/// Servers refuse client requests that would modify keys with this prefix.
+pub const SYSTEM_KEY_PREFIX: &str = "__kvs/";
+
+/// The name of the system key holding the store's unique ID.
+const STORE_ID: &str = "store_id";
+
+/// The name of the system key holding the version of the system keyspace's layout.
+const SCHEMA_VERSION: &str = "schema_version";
+
+/// The version of the system keyspace's layout written by this version of the crate.
+const CURRENT_SCHEMA_VERSION: &str = "1";
+
+/// Access to an engine's reserved system keyspace, for use by internal components.
+///
+/// Keys are given by name, without the [`SYSTEM_KEY_PREFIX`].
+pub struct SystemKeys<'a, E: ?Sized> {
+    engine: &'a mut E,
+}
+
+impl<'a, E: Engine + ?Sized> SystemKeys<'a, E> {
+    /// Access the system keyspace of `engine`.
+    pub fn new(engine: &'a mut E) -> Self {
+        SystemKeys { engine }
+    }
+
+    /// Get the value of a system key.
+    pub fn get(&mut self, name: &str) -> Result<Option<String>> {
+        self.engine.get(system_key(name))
+    }
+
+    /// Set a system key to a given value.
+    pub fn set(&mut self, name: &str, value: String) -> Result<()> {
+        self.engine.set(system_key(name), value)
+    }
+
+    /// Remove a system key.
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        self.engine.remove(system_key(name))
+    }
+
+    /// Initialise the system keyspace, if it hasn't been already.
+    ///
+    /// This generates the store's ID and records the schema version.
+    pub fn init(&mut self) -> Result<()> {
+        if self.get(SCHEMA_VERSION)?.is_none() {
+            self.set(SCHEMA_VERSION, CURRENT_SCHEMA_VERSION.to_owned())?;
+        }
+        if self.get(STORE_ID)?.is_none() {
+            self.set(STORE_ID, random_uuid())?;
+        }
+        Ok(())
+    }
+
+    /// The store's unique ID, if the system keyspace has been initialised.
+    pub fn store_id(&mut self) -> Result<Option<String>> {
+        self.get(STORE_ID)
+    }
+}
+
+fn system_key(name: &str) -> String {
+    format!("{}{}", SYSTEM_KEY_PREFIX, name)
+}
+
+/// Generate a random (version 4) UUID.
+fn random_uuid() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
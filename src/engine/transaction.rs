@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::engine::{Engine, SharedEngine};
+use crate::error::{Error, Result};
+
+/// Records which keys have been written since each open transaction began, so that transactions
+/// can tell when a key they use has changed under them.
+///
+/// Writes are only recorded while a transaction is open, and only for as long as a transaction
+/// that began before them is still open.
+#[derive(Debug, Default)]
+pub(crate) struct WriteLog {
+    seq: u64,
+    snapshots: BTreeMap<u64, usize>,
+    written: HashMap<Vec<u8>, u64>,
+}
+
+impl WriteLog {
+    /// Open a transaction, returning its snapshot: the point in the log its reads must see.
+    pub(crate) fn begin(&mut self) -> u64 {
+        *self.snapshots.entry(self.seq).or_insert(0) += 1;
+        self.seq
+    }
+
+    /// Close a transaction that began at `snapshot`, forgetting the writes no open transaction
+    /// needs any more.
+    pub(crate) fn end(&mut self, snapshot: u64) {
+        if let Some(count) = self.snapshots.get_mut(&snapshot) {
+            *count -= 1;
+            if *count == 0 {
+                self.snapshots.remove(&snapshot);
+            }
+        }
+        match self.snapshots.keys().next() {
+            Some(&oldest) => self.written.retain(|_, seq| *seq > oldest),
+            None => self.written.clear(),
+        }
+    }
+
+    /// Record a write to `key`.
+    pub(crate) fn record<K: AsRef<[u8]>>(&mut self, key: K) {
+        if self.snapshots.is_empty() {
+            return;
+        }
+        self.seq += 1;
+        self.written.insert(key.as_ref().to_vec(), self.seq);
+    }
+
+    /// Fail with [`Error::Conflict`] if `key` has been written since `snapshot`.
+    pub(crate) fn check(&self, key: &str, snapshot: u64) -> Result<()> {
+        match self.written.get(key.as_bytes()) {
+            Some(&seq) if seq > snapshot => Err(Error::Conflict(format!(
+                "key {:?} was written after the transaction began",
+                key
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply a transaction's buffered `writes` to `engine`, unless any of their keys have been
+    /// written since `snapshot` (in which case nothing is applied). This doesn't end the
+    /// transaction.
+    pub(crate) fn commit<E: Engine>(
+        &mut self,
+        engine: &mut E,
+        snapshot: u64,
+        writes: BTreeMap<String, Option<String>>,
+    ) -> Result<()> {
+        for key in writes.keys() {
+            self.check(key, snapshot)?;
+        }
+        for key in writes.keys() {
+            self.record(key);
+        }
+        let (sets, removes): (Vec<_>, Vec<_>) =
+            writes.into_iter().partition(|(_, value)| value.is_some());
+        if !sets.is_empty() {
+            let pairs = sets.into_iter().filter_map(|(key, value)| Some((key, value?)));
+            engine.set_many(pairs.collect())?;
+        }
+        if !removes.is_empty() {
+            engine.remove_many(removes.into_iter().map(|(key, _)| key).collect())?;
+        }
+        Ok(())
+    }
+}
+
+/// A transaction on a [`SharedEngine`], started with [`begin_transaction`].
+///
+/// Reads see the engine as it was when the transaction began (along with the transaction's own
+/// writes), and writes are buffered until [`commit`], which applies them together. Rather than
+/// keeping old versions of keys, a read of a key that another writer has changed since the
+/// transaction began fails with [`Error::Conflict`], as does committing a write to one. Either way
+/// the transaction can be retried from the start. Dropping a transaction rolls it back.
+///
+/// ```
+/// # use kvs::{MemoryKvStore, Result, SharedEngine};
+/// # fn check() -> Result<()> {
+/// let engine = SharedEngine::new(MemoryKvStore::new());
+/// engine.set("balance:a".to_owned(), "10".to_owned())?;
+/// let mut transaction = engine.begin_transaction();
+/// let balance = transaction.get("balance:a".to_owned())?;
+/// transaction.set("balance:a".to_owned(), "5".to_owned());
+/// transaction.set("balance:b".to_owned(), "5".to_owned());
+/// transaction.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Writes made through [`SharedEngine::with`] bypass the engine's write log, so aren't detected
+/// as conflicts.
+///
+/// [`begin_transaction`]: struct.SharedEngine.html#method.begin_transaction
+/// [`commit`]: #method.commit
+/// [`Error::Conflict`]: enum.Error.html#variant.Conflict
+/// [`SharedEngine::with`]: struct.SharedEngine.html#method.with
+pub struct Transaction<E: Engine> {
+    engine: SharedEngine<E>,
+    snapshot: u64,
+    writes: BTreeMap<String, Option<String>>,
+    open: bool,
+}
+
+impl<E: Engine> Transaction<E> {
+    pub(crate) fn new(engine: SharedEngine<E>, snapshot: u64) -> Self {
+        Transaction { engine, snapshot, writes: BTreeMap::new(), open: true }
+    }
+
+    /// Get the value of a key, as of when the transaction began or as the transaction has since
+    /// set it.
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        if let Some(value) = self.writes.get(&key) {
+            return Ok(value.clone());
+        }
+        let snapshot = self.snapshot;
+        self.engine.with_write_log(|engine, write_log| {
+            write_log.check(&key, snapshot)?;
+            engine.get(key)
+        })
+    }
+
+    /// Set a key to a given value when the transaction commits.
+    pub fn set(&mut self, key: String, value: String) {
+        self.writes.insert(key, Some(value));
+    }
+
+    /// Remove a key (if it exists) when the transaction commits.
+    pub fn remove(&mut self, key: String) {
+        self.writes.insert(key, None);
+    }
+
+    /// Apply the transaction's writes, failing with [`Error::Conflict`] (and applying none of
+    /// them) if any of their keys have been written since the transaction began.
+    ///
+    /// [`Error::Conflict`]: enum.Error.html#variant.Conflict
+    pub fn commit(mut self) -> Result<()> {
+        let (snapshot, writes) = (self.snapshot, std::mem::take(&mut self.writes));
+        self.open = false;
+        self.engine.with_write_log(|engine, write_log| {
+            let result = write_log.commit(engine, snapshot, writes);
+            write_log.end(snapshot);
+            result
+        })
+    }
+
+    /// Discard the transaction's writes.
+    pub fn rollback(self) {}
+}
+
+impl<E: Engine> Drop for Transaction<E> {
+    fn drop(&mut self) {
+        if self.open {
+            self.engine.write_log().end(self.snapshot);
+        }
+    }
+}
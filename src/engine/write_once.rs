@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::engine::{Consistency, Engine, IfModified, KeySample, SyncPoint, SYSTEM_KEY_PREFIX};
+use crate::error::{Error, Result};
+use crate::protocol::NotFoundReason;
+use crate::stats::EngineStats;
+
+/// An engine whose keys are immutable once they're set, e.g. for storing content-addressed
+/// blobs, wrapping another engine.
+///
+/// Writes that would change or remove a key that's already in the store fail with
+/// [`Error::Immutable`]: setting it to a different value, swapping or incrementing it, renaming it
+/// (or overwriting it with a rename), changing its time-to-live, and removing it. Setting a key
+/// to the value it already has succeeds without writing anything, so retrying a write is safe.
+/// Immutable keys can only be removed with [`force_remove`], e.g. once a blob is no longer
+/// referenced, and keys set with a time-to-live still expire.
+///
+/// System keys are never immutable, so a server can keep its own state in the engine.
+///
+/// Keys and values written as bytes (see [`set_bytes`]) must be UTF-8, so that they can be
+/// checked in the same way.
+///
+/// ```
+/// use kvs::{Error, KvsEngine, MemoryKvStore, Result, WriteOnce};
+///
+/// # fn main() -> Result<()> {
+/// let mut engine = WriteOnce::new(Box::new(MemoryKvStore::new()));
+/// engine.set("blob".to_owned(), "1".to_owned())?;
+/// engine.set("blob".to_owned(), "1".to_owned())?;
+/// assert!(matches!(engine.set("blob".to_owned(), "2".to_owned()), Err(Error::Immutable(_))));
+/// assert!(matches!(engine.remove("blob".to_owned()), Err(Error::Immutable(_))));
+/// engine.force_remove("blob".to_owned())?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Error::Immutable`]: enum.Error.html#variant.Immutable
+/// [`force_remove`]: trait.KvsEngine.html#method.force_remove
+/// [`set_bytes`]: trait.KvsEngine.html#method.set_bytes
+pub struct WriteOnce {
+    engine: Box<dyn Engine + Send>,
+}
+
+impl WriteOnce {
+    /// Make the keys stored in `engine` immutable.
+    pub fn new(engine: Box<dyn Engine + Send>) -> Self {
+        WriteOnce { engine }
+    }
+
+    /// The value of `key` if it's immutable, i.e. it's in the store and isn't a system key.
+    fn existing(&mut self, key: &str) -> Result<Option<String>> {
+        if key.starts_with(SYSTEM_KEY_PREFIX) {
+            return Ok(None);
+        }
+        self.engine.get(key.to_owned())
+    }
+
+    /// Fail with [`Error::Immutable`] if `key` is immutable.
+    fn check_absent(&mut self, key: &str) -> Result<()> {
+        match self.existing(key)? {
+            Some(_) => Err(Error::Immutable(key.to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether setting `key` to `value` needs writing: not if it already has that value, and
+    /// failing with [`Error::Immutable`] if it has another.
+    fn check_set(&mut self, key: &str, value: &str) -> Result<bool> {
+        match self.existing(key)? {
+            Some(existing) if existing == value => Ok(false),
+            Some(_) => Err(Error::Immutable(key.to_owned())),
+            None => Ok(true),
+        }
+    }
+}
+
+impl Engine for WriteOnce {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.engine.get(key)
+    }
+
+    fn lookup(
+        &mut self,
+        key: String,
+    ) -> Result<std::result::Result<String, Option<NotFoundReason>>> {
+        self.engine.lookup(key)
+    }
+
+    fn get_if_modified(&mut self, key: String, known_version: u64) -> Result<IfModified> {
+        self.engine.get_if_modified(key, known_version)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        if self.check_set(&key, &value)? {
+            self.engine.set(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.check_absent(&key)?;
+        self.engine.remove(key)
+    }
+
+    fn force_remove(&mut self, key: String) -> Result<()> {
+        self.engine.force_remove(key)
+    }
+
+    fn get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.engine.get_bytes(key)
+    }
+
+    fn get_many(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.engine.get_many(keys)
+    }
+
+    /// Set the keys that aren't already set, failing without setting any if a key would be
+    /// changed (including by an earlier pair in the batch).
+    fn set_many(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        let mut batch: HashMap<String, String> = HashMap::new();
+        let mut writes = Vec::new();
+        for (key, value) in pairs {
+            if key.starts_with(SYSTEM_KEY_PREFIX) {
+                writes.push((key, value));
+                continue;
+            }
+            match batch.get(&key) {
+                Some(earlier) if *earlier == value => continue,
+                Some(_) => return Err(Error::Immutable(key)),
+                None => {},
+            }
+            if self.check_set(&key, &value)? {
+                batch.insert(key.clone(), value.clone());
+                writes.push((key, value));
+            }
+        }
+        self.engine.set_many(writes)
+    }
+
+    /// Remove the keys, failing without removing any if one of them is immutable.
+    fn remove_many(&mut self, keys: Vec<String>) -> Result<Vec<String>> {
+        for key in &keys {
+            self.check_absent(key)?;
+        }
+        self.engine.remove_many(keys)
+    }
+
+    fn compare_and_swap(
+        &mut self,
+        key: String,
+        expected: Option<String>,
+        new: String,
+    ) -> Result<bool> {
+        match self.existing(&key)? {
+            Some(existing) if expected.as_ref() != Some(&existing) => Ok(false),
+            Some(existing) if existing == new => Ok(true),
+            Some(_) => Err(Error::Immutable(key)),
+            None => self.engine.compare_and_swap(key, expected, new),
+        }
+    }
+
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        self.check_absent(&key)?;
+        self.engine.increment(key, delta)
+    }
+
+    fn rename(&mut self, from: String, to: String, overwrite: bool) -> Result<bool> {
+        self.check_absent(&from)?;
+        if overwrite {
+            self.check_absent(&to)?;
+        }
+        self.engine.rename(from, to, overwrite)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.engine.sync()
+    }
+
+    fn sync_point(&mut self) -> Option<SyncPoint> {
+        self.engine.sync_point()
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.engine.scan(prefix)
+    }
+
+    fn scan_bytes(&mut self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.engine.scan_bytes(prefix)
+    }
+
+    fn scan_until(&mut self, prefix: &str, deadline: Instant) -> Result<Vec<(String, String)>> {
+        self.engine.scan_until(prefix, deadline)
+    }
+
+    fn scan_range(&mut self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>> {
+        self.engine.scan_range(start, end)
+    }
+
+    fn count_prefix(&mut self, prefix: &str) -> Result<u64> {
+        self.engine.count_prefix(prefix)
+    }
+
+    /// Fail with [`Error::Immutable`] if any key starts with `prefix` (system keys aside), since
+    /// they'd all be removed.
+    ///
+    /// [`Error::Immutable`]: enum.Error.html#variant.Immutable
+    fn remove_prefix(&mut self, prefix: &str) -> Result<Vec<String>> {
+        let entries = self.engine.scan(prefix)?;
+        match entries.into_iter().find(|(key, _)| !key.starts_with(SYSTEM_KEY_PREFIX)) {
+            Some((key, _)) => Err(Error::Immutable(key)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn cold_keys(
+        &mut self,
+        prefix: &str,
+        unread_for: Duration,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        self.engine.cold_keys(prefix, unread_for, limit)
+    }
+
+    fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        if self.check_set(&key, &value)? {
+            self.engine.set_with_ttl(key, value, ttl)?;
+        }
+        Ok(())
+    }
+
+    fn ttl(&mut self, key: String) -> Result<Option<Duration>> {
+        self.engine.ttl(key)
+    }
+
+    fn expire(&mut self, key: String, ttl: Duration) -> Result<()> {
+        self.check_absent(&key)?;
+        self.engine.expire(key, ttl)
+    }
+
+    fn persist(&mut self, key: String) -> Result<()> {
+        self.check_absent(&key)?;
+        self.engine.persist(key)
+    }
+
+    fn sweep_expired(&mut self, limit: usize) -> Result<Vec<String>> {
+        self.engine.sweep_expired(limit)
+    }
+
+    fn scrub(&mut self, limit: usize) -> Result<Vec<String>> {
+        self.engine.scrub(limit)
+    }
+
+    fn name(&self) -> &str {
+        self.engine.name()
+    }
+
+    fn consistency(&self) -> Consistency {
+        self.engine.consistency()
+    }
+
+    fn maintain(&mut self) -> Result<()> {
+        self.engine.maintain()
+    }
+
+    fn offer_keys(&mut self, sample: &mut KeySample) -> Result<()> {
+        self.engine.offer_keys(sample)
+    }
+
+    fn stats(&mut self) -> Result<EngineStats> {
+        self.engine.stats()
+    }
+}
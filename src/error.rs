@@ -21,10 +21,116 @@ pub enum Error {
     KeyNotFound,
 
     /// Indicates that a DB was loaded with the wrong engine.
-    WrongEngine,
+    WrongEngine {
+        /// The engine that was asked for.
+        requested: String,
+
+        /// The engine the DB's files belong to.
+        found: String,
+    },
+
+    /// Indicates that a new key could not be stored because the index has reached its memory cap.
+    IndexFull,
+
+    /// Indicates that a key could not be given a time-to-live because the engine doesn't support
+    /// them.
+    TtlUnsupported,
+
+    /// Indicates that a write was rejected because compaction has fallen too far behind, and
+    /// should be retried later.
+    Backpressure,
+
+    /// Indicates that a write failed because the disk is full.
+    DiskFull,
+
+    /// Indicates that a write was rejected because the server is in maintenance mode, with how
+    /// long until maintenance mode ends.
+    Maintenance(std::time::Duration),
+
+    /// Indicates that a request took longer than a server's request timeout. Writes may still
+    /// have been applied.
+    Timeout,
+
+    /// Indicates that a key was rejected by a server's key rules, with the reason why.
+    InvalidKey(String),
+
+    /// Indicates that a new key could not be stored because the given tenant has reached its key
+    /// quota.
+    KeyQuotaExceeded(String),
+
+    /// Indicates that a value could not be stored because the given tenant has reached its byte
+    /// quota.
+    ByteQuotaExceeded(String),
+
+    /// Indicates that a request was rejected because the given tenant has exceeded its request
+    /// rate quota.
+    RateLimited(String),
+
+    /// Indicates that commands are missing from a store's log.
+    SequenceGap {
+        /// The sequence number of the missing command.
+        expected: u64,
+
+        /// The sequence number of the command found in its place, or of the last command in the
+        /// log if the log ended early.
+        found: u64,
+    },
+
+    /// Indicates that a command in a log file failed its checksum or was cut short, rather than
+    /// being misread.
+    Corruption {
+        /// The log file the command is in.
+        file: std::path::PathBuf,
+
+        /// The offset in the file at which the corrupt command starts.
+        offset: u64,
+    },
+
+    /// Indicates that a store's files were written in a newer format version than is supported.
+    UnsupportedVersion(u32),
+
+    /// Indicates that a config file is invalid.
+    Config(String),
+
+    /// Indicates that a value could not be converted to or from a type with a [`ValueFormat`], or
+    /// could not be [incremented] because it isn't an integer.
+    ///
+    /// [`ValueFormat`]: enum.ValueFormat.html
+    /// [incremented]: trait.KvsEngine.html#method.increment
+    InvalidValue {
+        /// The key the value is stored at.
+        key: String,
+
+        /// Why the value couldn't be converted.
+        reason: String,
+    },
+
+    /// Indicates that a [`Query`] could not be parsed, with the reason why.
+    ///
+    /// [`Query`]: struct.Query.html
+    InvalidQuery(String),
 
     /// Indicates that a server replied with the wrong thing.
-    ProtocolError(Request, Response),
+    ProtocolError(Box<Request>, Box<Response>),
+
+    /// Indicates that a transaction can't continue, with the reason why: a key it used was
+    /// written after it began, or it's no longer open. It can be retried from the start.
+    Conflict(String),
+
+    /// Indicates that a write was rejected because the store is a read-only
+    /// [follower](struct.KvFollower.html) of a store that another process writes to.
+    ReadOnly,
+
+    /// Indicates that a write was rejected because it would change or remove the given key, which
+    /// is [immutable](struct.WriteOnce.html). It can only be removed with a forced remove.
+    Immutable(String),
+}
+
+impl Error {
+    /// Construct an [`Error::ProtocolError`] for an unexpected `response` to `request`.
+    pub(crate) fn protocol(request: Request, response: Response) -> Self {
+        Error::ProtocolError(Box::new(request), Box::new(response))
+    }
 }
 
 impl std::error::Error for Error {
@@ -47,7 +153,53 @@ impl std::fmt::Display for Error {
             Error::Encode(err) => write!(f, "Encode error: {}", err),
             Error::Sled(err) => write!(f, "Sled error: {}", err),
             Error::KeyNotFound => write!(f, "Key not found"),
-            Error::WrongEngine => write!(f, "Wrong engine"),
+            Error::WrongEngine { requested, found } => write!(
+                f,
+                "Wrong engine: {} was requested, but the data is for {}",
+                requested, found
+            ),
+            Error::IndexFull => write!(f, "Index full"),
+            Error::TtlUnsupported => write!(f, "Engine does not support TTLs"),
+            Error::Backpressure => write!(f, "Writes are stalled until compaction catches up"),
+            Error::DiskFull => write!(f, "Disk full: the store is read-only until space is freed"),
+            Error::Maintenance(retry_after) => write!(
+                f,
+                "The server is in maintenance mode: retry writes after {:.1}s",
+                retry_after.as_secs_f64()
+            ),
+            Error::Timeout => write!(f, "The request timed out (writes may have been applied)"),
+            Error::Config(message) => write!(f, "Invalid config: {}", message),
+            Error::InvalidQuery(reason) => write!(f, "Invalid query: {}", reason),
+            Error::InvalidValue { key, reason } => {
+                write!(f, "Invalid value for key {:?}: {}", key, reason)
+            },
+            Error::InvalidKey(reason) => write!(f, "Invalid key: {}", reason),
+            Error::KeyQuotaExceeded(tenant) => {
+                write!(f, "Tenant {:?} has reached its key quota", tenant)
+            },
+            Error::ByteQuotaExceeded(tenant) => {
+                write!(f, "Tenant {:?} has reached its byte quota", tenant)
+            },
+            Error::RateLimited(tenant) => {
+                write!(f, "Tenant {:?} has exceeded its request rate quota", tenant)
+            },
+            Error::Corruption { file, offset } => {
+                write!(f, "Corrupt log file {}: bad command at offset {}", file.display(), offset)
+            },
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported format version {} (the latest supported version is {})",
+                version,
+                crate::engine::FORMAT_VERSION
+            ),
+            Error::SequenceGap { expected, found } => {
+                write!(f, "Log sequence gap: expected command {} but found {}", expected, found)
+            },
+            Error::Conflict(reason) => write!(f, "Transaction conflict: {}", reason),
+            Error::ReadOnly => write!(f, "Read-only: writes must be made to the followed store"),
+            Error::Immutable(key) => {
+                write!(f, "Key {:?} is immutable: it can only be removed by force", key)
+            },
             Error::ProtocolError(request, response) => {
                 write!(
                     f,
@@ -62,7 +214,11 @@ impl std::fmt::Display for Error {
 
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Error {
-        Error::Io(err)
+        if err.kind() == std::io::ErrorKind::StorageFull {
+            Error::DiskFull
+        } else {
+            Error::Io(err)
+        }
     }
 }
 
@@ -74,7 +230,18 @@ impl From<rmp_serde::decode::Error> for Error {
 
 impl From<rmp_serde::encode::Error> for Error {
     fn from(err: rmp_serde::encode::Error) -> Error {
-        Error::Encode(err)
+        use rmp::encode::ValueWriteError::{InvalidDataWrite, InvalidMarkerWrite};
+        use rmp_serde::encode::Error::InvalidValueWrite;
+
+        match err {
+            InvalidValueWrite(InvalidMarkerWrite(ref err))
+            | InvalidValueWrite(InvalidDataWrite(ref err))
+                if err.kind() == std::io::ErrorKind::StorageFull =>
+            {
+                Error::DiskFull
+            },
+            err => Error::Encode(err),
+        }
     }
 }
 
@@ -0,0 +1,61 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+
+/// How values are serialized by the typed helpers, [`Client::get_as`] and [`Client::set_from`]
+/// (and the same methods on [`KvStore`]).
+///
+/// Values are always strings, so MessagePack values are stored as lowercase hex. That doubles
+/// their size, but they can still be read back by any client.
+///
+/// [`Client::get_as`]: struct.Client.html#method.get_as
+/// [`Client::set_from`]: struct.Client.html#method.set_from
+/// [`KvStore`]: struct.KvStore.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ValueFormat {
+    /// JSON (the default).
+    #[default]
+    Json,
+
+    /// MessagePack, hex-encoded.
+    MessagePack,
+}
+
+impl ValueFormat {
+    /// Serialize `value` to be stored at `key`.
+    pub(crate) fn encode<T: Serialize + ?Sized>(self, key: &str, value: &T) -> Result<String> {
+        match self {
+            ValueFormat::Json => {
+                serde_json::to_string(value).map_err(|err| invalid_value(key, err))
+            },
+            ValueFormat::MessagePack => {
+                let bytes = rmp_serde::to_vec(value).map_err(|err| invalid_value(key, err))?;
+                Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+            },
+        }
+    }
+
+    /// Deserialize `value`, stored at `key`.
+    pub(crate) fn decode<T: DeserializeOwned>(self, key: &str, value: &str) -> Result<T> {
+        match self {
+            ValueFormat::Json => serde_json::from_str(value).map_err(|err| invalid_value(key, err)),
+            ValueFormat::MessagePack => {
+                let bytes = from_hex(value).ok_or_else(|| invalid_value(key, "invalid hex"))?;
+                rmp_serde::from_slice(&bytes).map_err(|err| invalid_value(key, err))
+            },
+        }
+    }
+}
+
+/// The bytes encoded in `hex`, or `None` if it isn't valid hex.
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+fn invalid_value<E: ToString>(key: &str, err: E) -> Error {
+    Error::InvalidValue { key: key.to_owned(), reason: err.to_string() }
+}
@@ -4,17 +4,60 @@
 
 #![deny(missing_docs)]
 
+mod bytes;
+mod channel;
 mod client;
+mod clock;
+mod config;
 mod engine;
 mod error;
+mod format;
+mod logging;
+mod metrics;
 mod protocol;
+mod query;
+mod rebalance;
+mod ring;
 mod server;
+mod stats;
+mod transfer;
 
-pub use client::Client;
-pub use engine::{Engine as KvsEngine, KvStore, SledKvStore};
+pub mod r#async;
+pub mod cli;
+pub mod recovery;
+pub mod testing;
+
+pub use client::{CacheConfig, CachingClient, Client, ClientBuilder, EmbeddedClient, KvsClient};
+pub use client::{ClientTransaction, Watch};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use config::{BucketConfig, Config, ENV_ADDR, ENV_DATA_DIR, ENV_ENGINE, ENV_LOG_LEVEL};
+pub use config::ENV_SYNC_POLICY;
+pub use engine::{Engine as KvsEngine, KvFollower, KvStore, KvStoreBuilder, MemoryKvStore};
+pub use engine::{BackgroundJobs, Buckets, JobSlot, WriteStall, BUCKET_SEPARATOR, FORMAT_VERSION};
+pub use engine::{CompactionDecision, CompactionFilter, Erasure, KeySample, SharedEngine};
+pub use engine::Consistency;
+pub use engine::{detect_engines, SystemKeys, Transaction, WriteOnce, SYSTEM_KEY_PREFIX};
+pub use engine::{content_key, value_version, IfModified, SledKvStore, SyncPoint, SyncPolicy};
+pub use engine::CONTENT_KEY_PREFIX;
+pub use engine::{CheckReport, CorruptRange, FileReport, ManifestReport, Repair, SequenceGap};
 pub use error::{Error, Result};
-pub use protocol::{Request, Response};
-pub use server::Server;
+pub use format::ValueFormat;
+pub use logging::{JsonDrain, RotatingFile, RotationConfig};
+pub use metrics::{MetricsSink, NoopMetrics, PrometheusMetrics};
+pub use protocol::{decode_request, decode_response, encode_request, encode_response};
+pub use protocol::{Decoder, ErrorKind, Request, RequestDecoder, Response, ResponseDecoder};
+pub use protocol::{Change, Durability, NotFoundReason, ServerInfo, PROTOCOL_VERSION};
+pub use protocol::{Schema, SchemaField, SchemaFormat, SchemaType, SchemaVariant};
+pub use query::Query;
+pub use rebalance::{Rebalance, RebalanceProgress};
+pub use ring::{Ring, RingMove};
+pub use server::{KeyCharset, KeyRules, Peer, Sample, Sampler, SamplerConfig, Server};
+pub use server::{Chaos, ChaosConfig, Fault, FaultScript, ServerConfig, Sweeper, SweeperConfig};
+pub use server::{Mirror, MirrorConfig, RequestInterceptor, Scrubber, ScrubberConfig};
+pub use server::{TenantQuota, TenantUsage, Tenants, Warmup, WarmupConfig, TENANT_SEPARATOR};
+pub use stats::{CompactionStats, EngineStats, Histogram, LifetimeStats, LogFileStats, OpStats};
+pub use stats::{SizeHistogram, Stats};
+pub use transfer::{Transfer, TransferProgress};
 
 /// The default address for a KVS server.
-pub const DEFAULT_ADDRESS: &'static str = "127.0.0.1:4001";
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:4001";
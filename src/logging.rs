@@ -0,0 +1,261 @@
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::Result;
+
+/// Configures when a [`RotatingFile`] is rotated, and how many rotated files are kept.
+#[derive(Clone, Debug)]
+pub struct RotationConfig {
+    /// The size (in bytes) at which the file is rotated, if any.
+    pub max_file_size: Option<u64>,
+
+    /// How long the file is written to before it's rotated, if there's a limit.
+    pub max_age: Option<Duration>,
+
+    /// The number of rotated files to keep, in addition to the current file.
+    pub max_files: usize,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        RotationConfig {
+            max_file_size: Some(64 * 1024 * 1024),
+            max_age: None,
+            max_files: 4,
+        }
+    }
+}
+
+/// A file that's rotated once it's big enough or old enough, for writing logs to.
+///
+/// Rotated files are named with a numeric suffix (e.g. `kvs.log.1` is the most recent). Files are
+/// only rotated between lines, so a line is never split across files. The file is opened in
+/// append mode, so it can also be rotated externally (e.g. by `logrotate` with `copytruncate`).
+pub struct RotatingFile {
+    path: PathBuf,
+    config: RotationConfig,
+    file: File,
+    written: u64,
+    opened: Instant,
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    /// Open the file at `path`, appending to it if it already exists.
+    pub fn open<P: Into<PathBuf>>(path: P, config: RotationConfig) -> Result<Self> {
+        let path = path.into();
+        let file = open_append(&path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            config,
+            file,
+            written,
+            opened: Instant::now(),
+            at_line_start: true,
+        })
+    }
+
+    /// Whether the file should be rotated before writing `len` more bytes.
+    fn is_rotation_due(&self, len: usize) -> bool {
+        let too_big = self.config.max_file_size.is_some_and(|max| self.written + len as u64 > max);
+        let too_old = self.config.max_age.is_some_and(|max_age| self.opened.elapsed() >= max_age);
+        self.at_line_start && self.written > 0 && (too_big || too_old)
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_rotation_due(buf.len()) {
+            rotate(&self.path, self.config.max_files).map_err(into_io_error)?;
+            self.file = open_append(&self.path)?;
+            self.written = 0;
+            self.opened = Instant::now();
+        }
+        let written = self.file.write(buf)?;
+        if written > 0 {
+            self.written += written as u64;
+            self.at_line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A [`slog::Drain`] that writes each record as a JSON object on its own line.
+///
+/// Each object has the record's `ts` (an RFC 3339 timestamp in UTC), `level` (one of `CRITICAL`,
+/// `ERROR`, `WARN`, `INFO`, `DEBUG` or `TRACE`) and `msg`, along with its key-value pairs and those
+/// of its logger.
+///
+/// [`slog::Drain`]: https://docs.rs/slog/2/slog/trait.Drain.html
+pub struct JsonDrain<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> JsonDrain<W> {
+    /// Construct a drain writing to `writer`.
+    pub fn new(writer: W) -> Self {
+        JsonDrain { writer: Mutex::new(writer) }
+    }
+}
+
+impl<W: Write> slog::Drain for JsonDrain<W> {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &slog::Record, values: &slog::OwnedKVList) -> io::Result<()> {
+        let mut object = Map::new();
+        object.insert("ts".to_owned(), Value::String(rfc3339(SystemTime::now())));
+        object.insert("level".to_owned(), Value::String(level_name(record.level()).to_owned()));
+        object.insert("msg".to_owned(), Value::String(record.msg().to_string()));
+
+        let mut serializer = JsonSerializer(&mut object);
+        slog::KV::serialize(values, record, &mut serializer).map_err(into_io_error)?;
+        slog::KV::serialize(&record.kv(), record, &mut serializer).map_err(into_io_error)?;
+
+        let mut line = serde_json::to_vec(&Value::Object(object)).map_err(into_io_error)?;
+        line.push(b'\n');
+        let mut writer = self.writer.lock().expect("log writer lock poisoned");
+        writer.write_all(&line)?;
+        writer.flush()
+    }
+}
+
+/// The name a level is written as, spelled out rather than left to slog, whose names have changed
+/// between versions.
+fn level_name(level: slog::Level) -> &'static str {
+    match level {
+        slog::Level::Critical => "CRITICAL",
+        slog::Level::Error => "ERROR",
+        slog::Level::Warning => "WARN",
+        slog::Level::Info => "INFO",
+        slog::Level::Debug => "DEBUG",
+        slog::Level::Trace => "TRACE",
+    }
+}
+
+/// Collects key-value pairs into a JSON object, keeping numbers and booleans as they are.
+struct JsonSerializer<'a>(&'a mut Map<String, Value>);
+
+impl JsonSerializer<'_> {
+    fn insert<V: Into<Value>>(&mut self, key: slog::Key, value: V) -> slog::Result {
+        self.0.insert(key.to_string(), value.into());
+        Ok(())
+    }
+}
+
+impl slog::Serializer for JsonSerializer<'_> {
+    fn emit_arguments(&mut self, key: slog::Key, value: &fmt::Arguments) -> slog::Result {
+        self.insert(key, value.to_string())
+    }
+
+    fn emit_str(&mut self, key: slog::Key, value: &str) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_bool(&mut self, key: slog::Key, value: bool) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_u64(&mut self, key: slog::Key, value: u64) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_i64(&mut self, key: slog::Key, value: i64) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_usize(&mut self, key: slog::Key, value: usize) -> slog::Result {
+        self.insert(key, value as u64)
+    }
+
+    fn emit_isize(&mut self, key: slog::Key, value: isize) -> slog::Result {
+        self.insert(key, value as i64)
+    }
+
+    fn emit_u32(&mut self, key: slog::Key, value: u32) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_i32(&mut self, key: slog::Key, value: i32) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_f64(&mut self, key: slog::Key, value: f64) -> slog::Result {
+        self.insert(key, value)
+    }
+
+    fn emit_none(&mut self, key: slog::Key) -> slog::Result {
+        self.insert(key, Value::Null)
+    }
+}
+
+/// Shift the rotated copies of the file at `path` up by one suffix, discarding the oldest, and
+/// move the file itself to the first suffix (or delete it, if no copies are kept).
+pub(crate) fn rotate(path: &Path, max_files: usize) -> Result<()> {
+    if max_files == 0 {
+        fs::remove_file(path)?;
+    } else {
+        for n in (1..max_files).rev() {
+            let from = rotated_path(path, n);
+            if from.exists() {
+                fs::rename(&from, rotated_path(path, n + 1))?;
+            }
+        }
+        fs::rename(path, rotated_path(path, 1))?;
+    }
+    Ok(())
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn into_io_error<E: fmt::Debug>(error: E) -> io::Error {
+    io::Error::other(format!("{:?}", error))
+}
+
+/// Format `time` as an RFC 3339 timestamp in UTC, with millisecond precision.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's `civil_from_days`).
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096)
+        / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
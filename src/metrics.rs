@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// The upper bounds of the buckets used by [`PrometheusMetrics`] histograms.
+///
+/// These suit durations in seconds, from 100µs to 10s.
+const HISTOGRAM_BOUNDS: &[f64] = &[
+    0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0,
+];
+
+/// Receives metrics from a store and a server, so that they can be wired into an embedder's own
+/// telemetry.
+///
+/// Every method does nothing by default, so implementations only need to handle the kinds of
+/// metric they're interested in. Metric names follow Prometheus conventions: counters end in
+/// `_total`, and durations are in seconds. A [`KvStore`] reports:
+///
+/// - `kvs_engine_writes_total` and `kvs_engine_written_bytes_total` (counters)
+/// - `kvs_engine_slowed_writes_total` and `kvs_engine_rejected_writes_total` (counters)
+/// - `kvs_engine_compactions_total` and `kvs_engine_compacted_bytes_total` (counters)
+/// - `kvs_engine_rotations_total` (counter)
+/// - `kvs_engine_compaction_seconds` (histogram)
+/// - `kvs_engine_uncompacted_bytes` (gauge)
+///
+/// A [`Server`] reports, for each of `get`, `set` and `remove`:
+///
+/// - `kvs_server_<op>_requests_total` and `kvs_server_<op>_errors_total` (counters)
+/// - `kvs_server_<op>_seconds` (histogram)
+///
+/// [`KvStore`]: struct.KvStore.html
+/// [`Server`]: struct.Server.html
+pub trait MetricsSink: fmt::Debug + Send + Sync {
+    /// Add `value` to the counter `name`.
+    fn counter(&self, _name: &str, _value: u64) {}
+
+    /// Set the gauge `name` to `value`.
+    fn gauge(&self, _name: &str, _value: f64) {}
+
+    /// Record `value` in the histogram `name`.
+    fn histogram(&self, _name: &str, _value: f64) {}
+}
+
+/// A [`MetricsSink`] that discards every metric.
+///
+/// This is used when no sink is configured.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetrics;
+
+impl MetricsSink for NoopMetrics {}
+
+/// A [`MetricsSink`] that keeps the latest value of each metric, and renders them in the
+/// Prometheus text exposition format.
+///
+/// Clones share the same metrics, so a clone can be given to a store (and a server) and the
+/// original rendered when the embedder's exporter is scraped.
+///
+/// ```
+/// use kvs::{MetricsSink, PrometheusMetrics};
+///
+/// let metrics = PrometheusMetrics::new();
+/// metrics.clone().counter("kvs_engine_writes_total", 1);
+/// assert!(metrics.render().contains("kvs_engine_writes_total 1\n"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PrometheusMetrics {
+    metrics: Arc<Mutex<BTreeMap<String, Metric>>>,
+}
+
+#[derive(Debug)]
+enum Metric {
+    Counter(u64),
+    Gauge(f64),
+    Histogram {
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+impl PrometheusMetrics {
+    /// Construct a sink with no metrics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render every metric recorded so far, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.lock().expect("metrics lock poisoned");
+        let mut out = String::new();
+        for (name, metric) in metrics.iter() {
+            let _ = match metric {
+                Metric::Counter(value) => {
+                    writeln!(out, "# TYPE {} counter\n{} {}", name, name, value)
+                },
+                Metric::Gauge(value) => writeln!(out, "# TYPE {} gauge\n{} {}", name, name, value),
+                Metric::Histogram { buckets, sum, count } => {
+                    let _ = writeln!(out, "# TYPE {} histogram", name);
+                    let mut cumulative = 0;
+                    for (bound, bucket) in HISTOGRAM_BOUNDS.iter().zip(buckets) {
+                        cumulative += bucket;
+                        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+                    }
+                    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+                    writeln!(out, "{}_sum {}\n{}_count {}", name, sum, name, count)
+                },
+            };
+        }
+        out
+    }
+
+    /// Update the metric `name`, creating it with `init` if it doesn't exist.
+    ///
+    /// Updates to a metric of a different kind than it was created with are ignored.
+    fn update<I, U>(&self, name: &str, init: I, update: U)
+    where
+        I: FnOnce() -> Metric,
+        U: FnOnce(&mut Metric),
+    {
+        let mut metrics = self.metrics.lock().expect("metrics lock poisoned");
+        update(metrics.entry(name.to_owned()).or_insert_with(init));
+    }
+}
+
+impl MetricsSink for PrometheusMetrics {
+    fn counter(&self, name: &str, value: u64) {
+        self.update(name, || Metric::Counter(0), |metric| {
+            if let Metric::Counter(total) = metric {
+                *total += value;
+            }
+        });
+    }
+
+    fn gauge(&self, name: &str, value: f64) {
+        self.update(name, || Metric::Gauge(0.0), |metric| {
+            if let Metric::Gauge(current) = metric {
+                *current = value;
+            }
+        });
+    }
+
+    fn histogram(&self, name: &str, value: f64) {
+        let init = || Metric::Histogram {
+            buckets: vec![0; HISTOGRAM_BOUNDS.len()],
+            sum: 0.0,
+            count: 0,
+        };
+        self.update(name, init, |metric| {
+            if let Metric::Histogram { buckets, sum, count } = metric {
+                if let Some(i) = HISTOGRAM_BOUNDS.iter().position(|&bound| value <= bound) {
+                    buckets[i] += 1;
+                }
+                *sum += value;
+                *count += 1;
+            }
+        });
+    }
+}
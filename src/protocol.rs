@@ -1,9 +1,27 @@
+mod codec;
+mod schema;
+
 use crate::error::Error;
+use crate::server::TenantUsage;
+use crate::stats::Stats;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 
+pub use self::codec::{decode_request, decode_response, encode_request, encode_response};
+pub use self::codec::{Decoder, RequestDecoder, ResponseDecoder};
+pub use self::schema::{Schema, SchemaField, SchemaFormat, SchemaType, SchemaVariant};
+
+/// The version of the protocol spoken by this crate's servers and clients, as reported in
+/// [`ServerInfo::protocol_version`].
+///
+/// This is incremented whenever [`Request`] or [`Response`] change in a way that an older peer
+/// couldn't understand.
+///
+/// [`ServerInfo::protocol_version`]: struct.ServerInfo.html#structfield.protocol_version
+pub const PROTOCOL_VERSION: u32 = 3;
+
 /// An enum representing a request to a server.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Request {
     /// Retrieve the value of a given key from a kvs server.
     ///
@@ -22,7 +40,16 @@ pub enum Request {
         key: String,
 
         /// The value to set for the key.
-        value: String
+        value: String,
+
+        /// The key's time-to-live in milliseconds, if it should expire.
+        #[serde(default)]
+        ttl_ms: Option<u64>,
+
+        /// When the server should acknowledge the write. This is left out of the encoding when
+        /// it's [`Durability::Applied`], so only servers that support it are sent anything new.
+        #[serde(default, skip_serializing_if = "Durability::is_applied")]
+        durability: Durability,
     },
 
     /// Remove a given key from the store.
@@ -32,6 +59,454 @@ pub enum Request {
         /// The key to remove.
         key: String
     },
+
+    /// Retrieve every key starting with a given prefix, with its value.
+    ///
+    /// The server will respond with [`Entries`] (or [`Err`]).
+    Scan {
+        /// The prefix of the keys to retrieve.
+        prefix: String
+    },
+
+    /// Retrieve statistics from a kvs server.
+    ///
+    /// The server will respond with [`Stats`] (or [`Err`]).
+    Stats,
+
+    /// Retrieve the most frequently requested keys from a kvs server.
+    ///
+    /// The server will respond with [`HotKeys`] (or [`Err`]).
+    HotKeys {
+        /// The maximum number of keys to return.
+        limit: u64
+    },
+
+    /// Retrieve the usage and quotas of each tenant of a kvs server.
+    ///
+    /// The server will respond with [`Tenants`] (or [`Err`]).
+    Tenants,
+
+    /// Retrieve a kvs server's version, engine and enabled features.
+    ///
+    /// The server will respond with [`Info`] (or [`Err`]).
+    Info,
+
+    /// Put a kvs server into maintenance mode for a given duration, or end maintenance mode if
+    /// the duration is zero.
+    ///
+    /// In maintenance mode, reads are served as usual but writes are rejected with
+    /// [`ErrorKind::Maintenance`], and the engine's maintenance tasks are run once. The server
+    /// will respond with [`Ok`] (or [`Err`]).
+    Maintenance {
+        /// How long to stay in maintenance mode, in milliseconds.
+        duration_ms: u64
+    },
+
+    /// Retrieve a uniform random sample of a kvs server's keys, e.g. to estimate the distribution
+    /// of value sizes without a full scan.
+    ///
+    /// The server will respond with [`Keys`] (or [`Err`]).
+    SampleKeys {
+        /// The maximum number of keys to return.
+        count: u64
+    },
+
+    /// Watch for changes to keys starting with a given prefix.
+    ///
+    /// The server will respond with [`Ok`] (or [`Err`]), then keep the connection open, sending a
+    /// [`Change`] for each matching key that's set or removed until the client disconnects.
+    Watch {
+        /// The prefix of the keys to watch.
+        prefix: String
+    },
+
+    /// Count the keys starting with a given prefix.
+    ///
+    /// The server will respond with [`Count`] (or [`Err`]).
+    CountPrefix {
+        /// The prefix of the keys to count.
+        prefix: String
+    },
+
+    /// Remove every key starting with a given prefix from the store.
+    ///
+    /// The server will respond with [`Count`], with the number of keys removed (or [`Err`]).
+    RemovePrefix {
+        /// The prefix of the keys to remove.
+        prefix: String
+    },
+
+    /// Check that a kvs server is responding.
+    ///
+    /// The server will respond with [`Pong`].
+    Ping,
+
+    /// Find keys starting with a given prefix that haven't been read or written for a while, e.g.
+    /// to move them to cheaper storage.
+    ///
+    /// The server will respond with [`Keys`], in key order (or [`Err`]).
+    ColdKeys {
+        /// The prefix of the keys to look for.
+        prefix: String,
+
+        /// How long a key must have gone unread and unwritten, in seconds.
+        unread_for_secs: u64,
+
+        /// The maximum number of keys to return.
+        limit: u64
+    },
+
+    /// Retrieve every key in a range, with its value.
+    ///
+    /// The server will respond with [`Entries`], in key order (or [`Err`]).
+    ScanRange {
+        /// The first key of the range.
+        start: String,
+
+        /// The key the range ends before, or `None` to scan to the last key.
+        end: Option<String>,
+    },
+
+    /// Retrieve the values of several keys.
+    ///
+    /// The server will respond with [`Values`], in the same order as the keys (or [`Err`]).
+    MultiGet {
+        /// The keys whose values to get.
+        keys: Vec<String>
+    },
+
+    /// Set several keys to their values, in order.
+    ///
+    /// Batches aren't atomic: if the server responds with [`Err`], some of the keys may have been
+    /// set. Otherwise it will respond with [`Ok`].
+    MultiSet {
+        /// The keys to set, with their values.
+        pairs: Vec<(String, String)>,
+
+        /// When the server should acknowledge the writes, which are synced together. This is
+        /// left out of the encoding when it's [`Durability::Applied`].
+        #[serde(default, skip_serializing_if = "Durability::is_applied")]
+        durability: Durability,
+    },
+
+    /// Remove several keys from the store, skipping any that aren't in it.
+    ///
+    /// The server will respond with [`Count`], with the number of keys removed (or [`Err`]). As
+    /// with [`MultiSet`], batches aren't atomic.
+    MultiRemove {
+        /// The keys to remove.
+        keys: Vec<String>
+    },
+
+    /// Begin a transaction, whose reads see the store as it is now and whose writes are sent
+    /// together when it commits.
+    ///
+    /// The server will respond with [`Transaction`], with the transaction's ID (or [`Err`]).
+    /// Transactions that go unused for a while are rolled back.
+    Begin,
+
+    /// Retrieve the value of a given key within an open transaction.
+    ///
+    /// The server will respond as to a [`Get`], or with [`Err`] of kind [`ErrorKind::Conflict`] if
+    /// the key has been written since the transaction began.
+    TransactionGet {
+        /// The transaction's ID.
+        id: u64,
+
+        /// The key whose value to get.
+        key: String,
+    },
+
+    /// Commit a transaction, applying its writes unless any of their keys have been written since
+    /// it began.
+    ///
+    /// The server will respond with [`Ok`], or with [`Err`] of kind [`ErrorKind::Conflict`] if
+    /// none of the writes were applied because of a conflict. The transaction is closed either
+    /// way.
+    Commit {
+        /// The transaction's ID.
+        id: u64,
+
+        /// The keys to set, with their values, or `None` for keys to remove.
+        writes: Vec<(String, Option<String>)>,
+    },
+
+    /// Abandon a transaction.
+    ///
+    /// The server will respond with [`Ok`].
+    Rollback {
+        /// The transaction's ID.
+        id: u64
+    },
+
+    /// Set a key to a new value if its current value is as expected, e.g. to update it without
+    /// overwriting a concurrent change.
+    ///
+    /// The server will respond with [`Swapped`], saying whether the key was set (or [`Err`]).
+    CompareAndSwap {
+        /// The key to set.
+        key: String,
+
+        /// The value the key must have, or `None` if it mustn't be in the store.
+        expected: Option<String>,
+
+        /// The value to set the key to.
+        new: String,
+    },
+
+    /// Add to the integer value of a key, treating a key that isn't in the store as `0`.
+    ///
+    /// The server will respond with [`Counter`], with the key's new value (or [`Err`], e.g. if its
+    /// value isn't an integer).
+    Increment {
+        /// The key to increment.
+        key: String,
+
+        /// The amount to add, which may be negative.
+        delta: i64,
+    },
+
+    /// Move a key's value (and time-to-live) to another key, in a single write.
+    ///
+    /// The server will respond with [`Renamed`], saying whether the key was renamed (or
+    /// [`NotFound`] if `from` isn't in the store, or [`Err`]).
+    Rename {
+        /// The key to rename.
+        from: String,
+
+        /// The key's new name.
+        to: String,
+
+        /// Whether to replace the value of `to` if it's already in the store. If not, the rename
+        /// isn't made.
+        overwrite: bool,
+    },
+
+    /// Get the value of a key, unless it has a given version (see [`value_version`]).
+    ///
+    /// The server will respond with [`NotModified`] if the key's value has `version`, or
+    /// otherwise as for a [`Get`].
+    ///
+    /// [`value_version`]: fn.value_version.html
+    GetIfModified {
+        /// The key to get.
+        key: String,
+
+        /// The version of the value the client already has.
+        version: u64,
+    },
+
+    /// Get the time left before a key expires.
+    ///
+    /// The server will respond with [`Ttl`] (or [`NotFound`], or [`Err`]).
+    Ttl {
+        /// The key to inspect.
+        key: String,
+    },
+
+    /// Make an existing key expire after a given time, replacing any time-to-live it had.
+    ///
+    /// The server will respond with [`Ok`] (or [`NotFound`], or [`Err`], e.g. if its engine
+    /// doesn't support TTLs).
+    Expire {
+        /// The key to expire.
+        key: String,
+
+        /// How long until the key expires, in milliseconds.
+        ttl_ms: u64,
+    },
+
+    /// Stop a key from expiring.
+    ///
+    /// The server will respond with [`Ok`] (or [`NotFound`], or [`Err`]).
+    Persist {
+        /// The key to persist.
+        key: String,
+    },
+
+    /// Remove a given key from the store, even if it's immutable (see [`WriteOnce`]).
+    ///
+    /// The server will respond with [`Ok`] (or [`NotFound`], or [`Err`]).
+    ///
+    /// [`WriteOnce`]: struct.WriteOnce.html
+    ForceRemove {
+        /// The key to remove.
+        key: String,
+    },
+
+    /// Store a value under a key derived from its content (see [`content_key`]), e.g. to use the
+    /// server as a blob store. Storing a value that's already stored writes nothing.
+    ///
+    /// The server will respond with [`Stored`] (or [`Err`]).
+    ///
+    /// [`content_key`]: fn.content_key.html
+    PutCas {
+        /// The value to store.
+        value: String,
+    },
+
+    /// Retrieve the value of a key, either of which may be arbitrary bytes rather than UTF-8.
+    ///
+    /// The server will respond with either [`NotFound`], if the key is not in the store, or
+    /// [`FoundBytes`], if the key is in the store (or [`Err`]).
+    GetBytes {
+        /// The key whose value to get.
+        #[serde(with = "crate::bytes")]
+        key: Vec<u8>,
+    },
+
+    /// Set a key to a value, either of which may be arbitrary bytes rather than UTF-8.
+    ///
+    /// Keys with a time-to-live are set with a [`Set`]. The server will respond with [`Ok`] (or
+    /// [`Err`]).
+    SetBytes {
+        /// The key whose value to set.
+        #[serde(with = "crate::bytes")]
+        key: Vec<u8>,
+
+        /// The value to set for the key.
+        #[serde(with = "crate::bytes")]
+        value: Vec<u8>,
+
+        /// When the server should acknowledge the write.
+        durability: Durability,
+    },
+
+    /// Remove a key that may be arbitrary bytes rather than UTF-8.
+    ///
+    /// The server will respond with [`Ok`] (or [`Err`]).
+    RemoveBytes {
+        /// The key to remove.
+        #[serde(with = "crate::bytes")]
+        key: Vec<u8>,
+    },
+
+    /// Retrieve every key starting with a given prefix, with its value, any of which may be
+    /// arbitrary bytes rather than UTF-8.
+    ///
+    /// The server will respond with [`ByteEntries`] (or [`Err`]).
+    ScanBytes {
+        /// The prefix of the keys to retrieve.
+        #[serde(with = "crate::bytes")]
+        prefix: Vec<u8>,
+    },
+}
+
+/// A coarse classification of requests, used for accounting.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum RequestKind {
+    Get,
+    Set,
+    Remove,
+    Admin,
+}
+
+impl Request {
+    /// The key the request operates on, if any.
+    pub(crate) fn key(&self) -> Option<&[u8]> {
+        match self {
+            Request::GetBytes { key }
+            | Request::SetBytes { key, .. }
+            | Request::RemoveBytes { key } => Some(key),
+            Request::Get { key }
+            | Request::Set { key, .. }
+            | Request::Remove { key }
+            | Request::TransactionGet { key, .. }
+            | Request::CompareAndSwap { key, .. }
+            | Request::Increment { key, .. }
+            | Request::GetIfModified { key, .. }
+            | Request::Ttl { key }
+            | Request::Expire { key, .. }
+            | Request::Persist { key }
+            | Request::ForceRemove { key } => Some(key.as_bytes()),
+            Request::Scan { .. }
+            | Request::Stats
+            | Request::HotKeys { .. }
+            | Request::Tenants
+            | Request::Info
+            | Request::Maintenance { .. }
+            | Request::SampleKeys { .. }
+            | Request::Watch { .. }
+            | Request::CountPrefix { .. }
+            | Request::RemovePrefix { .. }
+            | Request::Ping
+            | Request::ColdKeys { .. }
+            | Request::ScanRange { .. }
+            | Request::MultiGet { .. }
+            | Request::MultiSet { .. }
+            | Request::MultiRemove { .. }
+            | Request::Begin
+            | Request::Commit { .. }
+            | Request::Rollback { .. }
+            | Request::Rename { .. }
+            | Request::PutCas { .. }
+            | Request::ScanBytes { .. } => None,
+        }
+    }
+
+    /// Every key the request operates on, in order, as bytes (which needn't be UTF-8 for
+    /// requests like [`SetBytes`]).
+    ///
+    /// Requests on a prefix or range of keys (e.g. [`Scan`]) have none.
+    ///
+    /// [`Scan`]: #variant.Scan
+    /// [`SetBytes`]: #variant.SetBytes
+    pub fn keys(&self) -> Vec<&[u8]> {
+        match self {
+            Request::MultiGet { keys } | Request::MultiRemove { keys } => {
+                keys.iter().map(String::as_bytes).collect()
+            },
+            Request::MultiSet { pairs, .. } => {
+                pairs.iter().map(|(key, _)| key.as_bytes()).collect()
+            },
+            Request::Commit { writes, .. } => {
+                writes.iter().map(|(key, _)| key.as_bytes()).collect()
+            },
+            Request::Rename { from, to, .. } => vec![from.as_bytes(), to.as_bytes()],
+            request => request.key().into_iter().collect(),
+        }
+    }
+
+    pub(crate) fn kind(&self) -> RequestKind {
+        match self {
+            Request::Get { .. }
+            | Request::MultiGet { .. }
+            | Request::TransactionGet { .. }
+            | Request::GetIfModified { .. }
+            | Request::Ttl { .. }
+            | Request::GetBytes { .. } => RequestKind::Get,
+            Request::Set { .. }
+            | Request::MultiSet { .. }
+            | Request::Commit { .. }
+            | Request::CompareAndSwap { .. }
+            | Request::Increment { .. }
+            | Request::Rename { .. }
+            | Request::Expire { .. }
+            | Request::Persist { .. }
+            | Request::PutCas { .. }
+            | Request::SetBytes { .. } => RequestKind::Set,
+            Request::Remove { .. }
+            | Request::RemovePrefix { .. }
+            | Request::MultiRemove { .. }
+            | Request::ForceRemove { .. }
+            | Request::RemoveBytes { .. } => RequestKind::Remove,
+            Request::Scan { .. }
+            | Request::Stats
+            | Request::HotKeys { .. }
+            | Request::Tenants
+            | Request::Info
+            | Request::Maintenance { .. }
+            | Request::SampleKeys { .. }
+            | Request::Watch { .. }
+            | Request::CountPrefix { .. }
+            | Request::Ping
+            | Request::ColdKeys { .. }
+            | Request::ScanRange { .. }
+            | Request::Begin
+            | Request::Rollback { .. }
+            | Request::ScanBytes { .. } => RequestKind::Admin,
+        }
+    }
 }
 
 /// An enum representing a response from a server.
@@ -41,7 +516,13 @@ pub enum Response {
     Ok,
 
     /// Indicates that the key in a [`Get`] request was not found in the store.
-    NotFound,
+    NotFound {
+        /// Why the key wasn't found, if the engine could tell cheaply. This is left out of the
+        /// encoding when it's `None`, so the response is the same as before reasons were added,
+        /// and is otherwise encoded as the reason's [code](enum.NotFoundReason.html#method.code).
+        #[serde(default, skip_serializing_if = "Option::is_none", with = "reason_code")]
+        reason: Option<NotFoundReason>,
+    },
 
     /// Indicates that the key in a [`Get`] request was found in the store.
     Found {
@@ -49,6 +530,65 @@ pub enum Response {
         value: String
     },
 
+    /// Contains the matching keys and values, in key order, in response to a [`Scan`] request.
+    Entries {
+        /// The matching keys, with their values.
+        entries: Vec<(String, String)>
+    },
+
+    /// Contains the server's statistics in response to a [`Stats`] request.
+    Stats {
+        /// The server's statistics.
+        stats: Box<Stats>
+    },
+
+    /// Contains the most frequently requested keys in response to a [`HotKeys`] request.
+    HotKeys {
+        /// The hottest keys with their approximate request counts, hottest first.
+        keys: Vec<(String, u64)>
+    },
+
+    /// Contains each tenant's usage in response to a [`Tenants`] request.
+    Tenants {
+        /// The usage and quotas of each tenant, sorted by tenant.
+        tenants: Vec<TenantUsage>
+    },
+
+    /// Contains the server's version, engine and enabled features in response to an [`Info`]
+    /// request.
+    Info {
+        /// The server's information.
+        info: ServerInfo
+    },
+
+    /// Contains a random sample of keys, in no particular order, in response to a [`SampleKeys`]
+    /// request, or the keys found for a [`ColdKeys`] request.
+    Keys {
+        /// The keys.
+        keys: Vec<String>
+    },
+
+    /// Describes a change to a watched key, sent for as long as a [`Watch`] request's connection
+    /// stays open.
+    Change {
+        /// The change that was made.
+        change: Change
+    },
+
+    /// Contains the number of keys counted or removed in response to a [`CountPrefix`] or
+    /// [`RemovePrefix`] request.
+    Count {
+        /// The number of keys.
+        count: u64
+    },
+
+    /// Indicates that the server is responding, in response to a [`Ping`] request.
+    Pong,
+
+    /// Sent on a [`Watch`] request's connection when no change has been sent for the server's
+    /// heartbeat interval, so that the client can tell the connection is still alive.
+    Heartbeat,
+
     /// Indicates that an error occurred whilst attempting to process a request.
     Err {
         /// The kind of error that occurred.
@@ -57,6 +597,214 @@ pub enum Response {
         /// An error message.
         message: String
     },
+
+    /// Contains the value of each key in a [`MultiGet`] request, or `None` for keys that aren't
+    /// in the store.
+    Values {
+        /// The values, in the same order as the request's keys.
+        values: Vec<Option<String>>
+    },
+
+    /// Contains the ID of the transaction started by a [`Begin`] request.
+    Transaction {
+        /// The transaction's ID, to send with its later requests.
+        id: u64
+    },
+
+    /// Says whether a [`CompareAndSwap`] request set its key.
+    Swapped {
+        /// Whether the key had the expected value, and so was set.
+        swapped: bool
+    },
+
+    /// Contains the new value of a key incremented by an [`Increment`] request.
+    Counter {
+        /// The key's new value.
+        value: i64
+    },
+
+    /// Says whether a [`Rename`] request renamed its key.
+    Renamed {
+        /// Whether the key was renamed, i.e. the new key wasn't in the store or was overwritten.
+        renamed: bool
+    },
+
+    /// Says that the value of the key requested by a [`GetIfModified`] still has the version the
+    /// client knows.
+    NotModified,
+
+    /// Contains the time left before the key requested by a [`Ttl`] expires.
+    Ttl {
+        /// The time left in milliseconds, or `None` if the key doesn't expire.
+        ttl_ms: Option<u64>,
+    },
+
+    /// Contains the key a [`PutCas`] request stored its value under.
+    Stored {
+        /// The key derived from the value.
+        key: String,
+
+        /// Whether the value was already stored, so nothing was written.
+        deduplicated: bool,
+    },
+
+    /// Indicates that the key in a [`GetBytes`] request was found in the store.
+    FoundBytes {
+        /// The value stored for the key.
+        #[serde(with = "crate::bytes")]
+        value: Vec<u8>,
+    },
+
+    /// Contains the matching keys and values, in key order, in response to a [`ScanBytes`]
+    /// request.
+    ByteEntries {
+        /// The matching keys, with their values.
+        #[serde(with = "crate::bytes::entries")]
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    },
+}
+
+/// A server's build and configuration, as returned for a [`Request::Info`].
+///
+/// Tooling can check this before running admin operations, to make sure the server supports them.
+///
+/// [`Request::Info`]: enum.Request.html#variant.Info
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ServerInfo {
+    /// The version of the server's build.
+    pub version: String,
+
+    /// The version of the protocol the server speaks (see [`PROTOCOL_VERSION`]).
+    ///
+    /// [`PROTOCOL_VERSION`]: constant.PROTOCOL_VERSION.html
+    pub protocol_version: u32,
+
+    /// The name of the server's storage engine (e.g. `"kvs"` or `"sled"`).
+    pub engine: String,
+
+    /// The optional server features that are enabled (e.g. `"tenants"` or `"sweeper"`).
+    pub features: Vec<String>,
+
+    /// The unique ID of the server's store.
+    pub store_id: String,
+}
+
+/// A change to a key, as sent to clients watching its prefix with a [`Request::Watch`].
+///
+/// Keys and values are bytes, since they may have been written with requests like
+/// [`Request::SetBytes`] and needn't be UTF-8. Those that are UTF-8 are still encoded as strings.
+///
+/// [`Request::Watch`]: enum.Request.html#variant.Watch
+/// [`Request::SetBytes`]: enum.Request.html#variant.SetBytes
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Change {
+    /// The change's position among every write the server has applied since it started, so
+    /// watchers can order changes and see how many writes to other keys happened in between.
+    pub seq: u64,
+
+    /// When the change was applied, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+
+    /// The key that changed.
+    #[serde(with = "crate::bytes::text")]
+    pub key: Vec<u8>,
+
+    /// The key's new value, or `None` if it was removed.
+    #[serde(with = "crate::bytes::text::option")]
+    pub value: Option<Vec<u8>>,
+}
+
+/// How durable a write must be before a server acknowledges it, in a [`Set`] or [`MultiSet`]
+/// request.
+///
+/// [`Set`]: enum.Request.html#variant.Set
+/// [`MultiSet`]: enum.Request.html#variant.MultiSet
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Durability {
+    /// Acknowledge the write once the engine has applied it (the default). It's made durable
+    /// whenever the engine next syncs, so may be lost if the server's machine crashes first.
+    #[default]
+    Applied,
+
+    /// Acknowledge the write once the engine has synced it to disk.
+    Fsynced,
+}
+
+impl Durability {
+    fn is_applied(&self) -> bool {
+        *self == Durability::Applied
+    }
+}
+
+/// Why a key wasn't found, in a [`NotFound`] response.
+///
+/// [`NotFound`]: enum.Response.html#variant.NotFound
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum NotFoundReason {
+    /// The key has never been set.
+    NeverExisted,
+
+    /// The key expired, but hasn't been swept yet.
+    Expired,
+
+    /// The key was evicted to make room for others.
+    Evicted,
+
+    /// The key was removed.
+    Deleted,
+}
+
+impl NotFoundReason {
+    /// The number a reason is encoded as in a [`NotFound`] response: its position in the enum.
+    ///
+    /// [`NotFound`]: enum.Response.html#variant.NotFound
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// The reason encoded as `code`, if it's one this version of the crate knows about.
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(NotFoundReason::NeverExisted),
+            1 => Some(NotFoundReason::Expired),
+            2 => Some(NotFoundReason::Evicted),
+            3 => Some(NotFoundReason::Deleted),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes a [`NotFound`] response's reason as a plain number, since MessagePack has no way to
+/// tell an optional enum from the variant it wraps.
+///
+/// [`NotFound`]: enum.Response.html#variant.NotFound
+mod reason_code {
+    use super::NotFoundReason;
+    use serde::de::{Error, Unexpected};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        reason: &Option<NotFoundReason>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match reason {
+            Some(reason) => serializer.serialize_u8(reason.code()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<NotFoundReason>, D::Error> {
+        let code = u8::deserialize(deserializer)?;
+        match NotFoundReason::from_code(code) {
+            Some(reason) => Ok(Some(reason)),
+            None => Err(D::Error::invalid_value(
+                Unexpected::Unsigned(code.into()),
+                &"a not found reason code",
+            )),
+        }
+    }
 }
 
 /// An enum representing response error kinds.
@@ -67,6 +815,65 @@ pub enum ErrorKind {
 
     /// Indicates an error occurred in the storage engine.
     EngineError,
+
+    /// Indicates that a new key could not be stored because the engine's index is full.
+    IndexFull,
+
+    /// Indicates that a key was rejected by the server's key rules. The message explains why.
+    InvalidKey,
+
+    /// Indicates that a new key could not be stored because its tenant has reached its key quota.
+    /// The message is the tenant's name.
+    KeyQuotaExceeded,
+
+    /// Indicates that a value could not be stored because its tenant has reached its byte quota.
+    /// The message is the tenant's name.
+    ByteQuotaExceeded,
+
+    /// Indicates that a request was rejected because its tenant has exceeded its request rate
+    /// quota. The message is the tenant's name.
+    RateLimited,
+
+    /// Indicates that a key could not be given a time-to-live because the engine doesn't support
+    /// them.
+    TtlUnsupported,
+
+    /// Indicates that a write was rejected because the engine's compaction has fallen too far
+    /// behind. The request can be retried later.
+    Backpressure,
+
+    /// Indicates that a write was rejected because the engine's disk is full.
+    DiskFull,
+
+    /// Indicates that a write was rejected because the server is in maintenance mode. The message
+    /// is the number of milliseconds until maintenance mode ends, after which the request can be
+    /// retried.
+    Maintenance,
+
+    /// Indicates that a request took longer than the server's request timeout. Scans are
+    /// abandoned, but writes may still have been applied.
+    Timeout,
+
+    /// Indicates that a transaction can't continue, because a key it used was written after it
+    /// began or because it's no longer open (e.g. it was idle for too long). The message explains
+    /// which, and the transaction can be retried from the start.
+    Conflict,
+
+    /// Indicates that a key's value couldn't be used by a request, e.g. an [`Increment`] of a
+    /// value that isn't an integer. The message explains why.
+    ///
+    /// [`Increment`]: enum.Request.html#variant.Increment
+    InvalidValue,
+
+    /// Indicates that a write was rejected because the server's engine is a read-only follower of
+    /// a store written by another process.
+    ReadOnly,
+
+    /// Indicates that a write was rejected because it would change or remove an immutable key.
+    /// The message is the key, which can only be removed with a [`ForceRemove`].
+    ///
+    /// [`ForceRemove`]: enum.Request.html#variant.ForceRemove
+    Immutable,
 }
 
 impl From<std::io::Error> for Response {
@@ -94,7 +901,63 @@ impl TryFrom<Error> for Response {
         match error {
             Error::Io(err) => Ok(err.into()),
             Error::Decode(err) => Ok(err.into()),
-            Error::KeyNotFound => Ok(Response::NotFound),
+            Error::KeyNotFound => Ok(Response::NotFound { reason: None }),
+            Error::IndexFull => Ok(Response::Err {
+                kind: ErrorKind::IndexFull,
+                message: format!("{}", Error::IndexFull),
+            }),
+            Error::InvalidKey(reason) => Ok(Response::Err {
+                kind: ErrorKind::InvalidKey,
+                message: reason,
+            }),
+            Error::KeyQuotaExceeded(tenant) => Ok(Response::Err {
+                kind: ErrorKind::KeyQuotaExceeded,
+                message: tenant,
+            }),
+            Error::ByteQuotaExceeded(tenant) => Ok(Response::Err {
+                kind: ErrorKind::ByteQuotaExceeded,
+                message: tenant,
+            }),
+            Error::RateLimited(tenant) => Ok(Response::Err {
+                kind: ErrorKind::RateLimited,
+                message: tenant,
+            }),
+            Error::TtlUnsupported => Ok(Response::Err {
+                kind: ErrorKind::TtlUnsupported,
+                message: format!("{}", Error::TtlUnsupported),
+            }),
+            Error::Backpressure => Ok(Response::Err {
+                kind: ErrorKind::Backpressure,
+                message: format!("{}", Error::Backpressure),
+            }),
+            Error::DiskFull => Ok(Response::Err {
+                kind: ErrorKind::DiskFull,
+                message: format!("{}", Error::DiskFull),
+            }),
+            Error::Maintenance(retry_after) => Ok(Response::Err {
+                kind: ErrorKind::Maintenance,
+                message: retry_after.as_millis().to_string(),
+            }),
+            Error::Timeout => Ok(Response::Err {
+                kind: ErrorKind::Timeout,
+                message: format!("{}", Error::Timeout),
+            }),
+            Error::Conflict(reason) => Ok(Response::Err {
+                kind: ErrorKind::Conflict,
+                message: reason,
+            }),
+            Error::InvalidValue { reason, .. } => Ok(Response::Err {
+                kind: ErrorKind::InvalidValue,
+                message: reason,
+            }),
+            Error::ReadOnly => Ok(Response::Err {
+                kind: ErrorKind::ReadOnly,
+                message: format!("{}", Error::ReadOnly),
+            }),
+            Error::Immutable(key) => Ok(Response::Err {
+                kind: ErrorKind::Immutable,
+                message: key,
+            }),
             err => Err(err),
         }
     }
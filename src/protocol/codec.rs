@@ -0,0 +1,107 @@
+use rmp_serde::decode::{from_read as read_mp, Error as DecodeError};
+use rmp_serde::encode::write as write_mp;
+use serde::de::DeserializeOwned;
+use std::io::{self, Cursor, Read, Write};
+use std::marker::PhantomData;
+
+use crate::error::Result;
+use super::{Request, Response};
+
+/// Write a request to `writer`.
+pub fn encode_request<W: Write>(writer: &mut W, request: &Request) -> Result<()> {
+    Ok(write_mp(writer, request)?)
+}
+
+/// Read a request from `reader`, blocking until it has been read in full.
+pub fn decode_request<R: Read>(reader: R) -> Result<Request> {
+    Ok(read_mp(reader)?)
+}
+
+/// Write a response to `writer`.
+pub fn encode_response<W: Write>(writer: &mut W, response: &Response) -> Result<()> {
+    Ok(write_mp(writer, response)?)
+}
+
+/// Read a response from `reader`, blocking until it has been read in full.
+pub fn decode_response<R: Read>(reader: R) -> Result<Response> {
+    Ok(read_mp(reader)?)
+}
+
+/// Incrementally decodes [`Request`]s from a stream of bytes.
+pub type RequestDecoder = Decoder<Request>;
+
+/// Incrementally decodes [`Response`]s from a stream of bytes.
+pub type ResponseDecoder = Decoder<Response>;
+
+/// Incrementally decodes messages from a stream of bytes, for use where reads can't block (e.g.
+/// with non-blocking sockets).
+///
+/// Bytes are buffered as they're received with [`extend`](#method.extend), and messages are
+/// taken from the buffer with [`decode`](#method.decode) once they're complete.
+///
+/// ```
+/// use kvs::{encode_request, Request, RequestDecoder};
+///
+/// # fn check() -> kvs::Result<()> {
+/// let mut bytes = Vec::new();
+/// encode_request(&mut bytes, &Request::Stats)?;
+///
+/// let mut decoder = RequestDecoder::new();
+/// decoder.extend(&bytes[..1]);
+/// assert!(decoder.decode()?.is_none());
+/// decoder.extend(&bytes[1..]);
+/// assert!(decoder.decode()?.is_some());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Decoder<T> {
+    buffer: Vec<u8>,
+    message: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> Decoder<T> {
+    /// Construct a decoder with an empty buffer.
+    pub fn new() -> Self {
+        Decoder {
+            buffer: Vec::new(),
+            message: PhantomData,
+        }
+    }
+
+    /// Buffer bytes received from the stream.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Take the next message from the buffer, or return `None` if it hasn't been received in
+    /// full yet.
+    pub fn decode(&mut self) -> Result<Option<T>> {
+        let mut cursor = Cursor::new(&self.buffer[..]);
+        match read_mp(&mut cursor) {
+            Ok(message) => {
+                let consumed = cursor.position() as usize;
+                self.buffer.drain(..consumed);
+                Ok(Some(message))
+            },
+            Err(DecodeError::InvalidMarkerRead(ref err))
+            | Err(DecodeError::InvalidDataRead(ref err))
+                if err.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                Ok(None)
+            },
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The number of bytes buffered that haven't been decoded yet.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl<T: DeserializeOwned> Default for Decoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
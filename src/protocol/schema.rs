@@ -0,0 +1,521 @@
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{Durability, ErrorKind, NotFoundReason, Request, Response, PROTOCOL_VERSION};
+
+/// How values are encoded on the wire, for [`Schema::encoding`].
+const ENCODING: &str = "MessagePack, as written by rmp-serde: structs (and struct variants) are \
+                        arrays of their fields in declaration order, and enum values are a \
+                        two-element array of the variant's index and an array of its fields. Each \
+                        request and response is a single value, and servers read one request per \
+                        connection.";
+
+/// A machine-readable description of the protocol spoken by this crate's servers and clients, for
+/// implementing clients in other languages.
+///
+/// The schema is generated from the [`Request`] and [`Response`] types themselves (by tracing how
+/// they're deserialized), so it can't drift from what servers actually accept.
+///
+/// ```
+/// use kvs::{Schema, SchemaType};
+///
+/// let schema = Schema::current();
+/// match &schema.types["Request"] {
+///     SchemaType::Enum(variants) => assert_eq!(variants[0].name, "Get"),
+///     _ => panic!("requests are an enum"),
+/// }
+/// ```
+///
+/// [`Request`]: enum.Request.html
+/// [`Response`]: enum.Response.html
+#[derive(Clone, Debug, Serialize)]
+pub struct Schema {
+    /// The protocol version described (see [`PROTOCOL_VERSION`]).
+    ///
+    /// [`PROTOCOL_VERSION`]: constant.PROTOCOL_VERSION.html
+    pub protocol_version: u32,
+
+    /// How values are encoded on the wire.
+    pub encoding: String,
+
+    /// Every named type used by requests and responses (including `Request` and `Response`
+    /// themselves), by name.
+    pub types: BTreeMap<String, SchemaType>,
+}
+
+/// A named type in a [`Schema`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaType {
+    /// A struct, with its fields in order. Tuple structs' fields are named by position.
+    Struct(Vec<SchemaField>),
+
+    /// A struct wrapping a single value, which is encoded as the value itself.
+    Newtype(SchemaFormat),
+
+    /// An enum, with its variants in order.
+    Enum(Vec<SchemaVariant>),
+}
+
+/// A field of a struct or enum variant in a [`Schema`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SchemaField {
+    /// The field's name.
+    pub name: String,
+
+    /// The field's format.
+    pub format: SchemaFormat,
+}
+
+/// A variant of an enum in a [`Schema`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SchemaVariant {
+    /// The variant's index, which identifies it on the wire.
+    pub index: u32,
+
+    /// The variant's name.
+    pub name: String,
+
+    /// The variant's fields, in order. Tuple variants' fields are named by position.
+    pub fields: Vec<SchemaField>,
+}
+
+/// The format of a value in a [`Schema`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaFormat {
+    /// A value whose format couldn't be traced.
+    Unknown,
+    /// No value.
+    Unit,
+    /// A boolean.
+    Bool,
+    /// A signed 8-bit integer.
+    I8,
+    /// A signed 16-bit integer.
+    I16,
+    /// A signed 32-bit integer.
+    I32,
+    /// A signed 64-bit integer.
+    I64,
+    /// An unsigned 8-bit integer.
+    U8,
+    /// An unsigned 16-bit integer.
+    U16,
+    /// An unsigned 32-bit integer.
+    U32,
+    /// An unsigned 64-bit integer.
+    U64,
+    /// A 32-bit float.
+    F32,
+    /// A 64-bit float.
+    F64,
+    /// A single character.
+    Char,
+    /// A UTF-8 string.
+    String,
+    /// A byte string.
+    Bytes,
+    /// A value that may be absent (encoded as nil).
+    Option(Box<SchemaFormat>),
+    /// A variable-length sequence of values.
+    Seq(Box<SchemaFormat>),
+    /// A fixed-length sequence of values of the same format.
+    Array {
+        /// The format of each item.
+        item: Box<SchemaFormat>,
+        /// The number of items.
+        len: usize,
+    },
+    /// A fixed-length sequence of values of different formats.
+    Tuple(Vec<SchemaFormat>),
+    /// A map.
+    Map {
+        /// The format of each key.
+        key: Box<SchemaFormat>,
+        /// The format of each value.
+        value: Box<SchemaFormat>,
+    },
+    /// A value of one of the schema's [named types](struct.Schema.html#structfield.types).
+    Named(String),
+}
+
+impl Schema {
+    /// The schema of the protocol spoken by this version of the crate.
+    pub fn current() -> Self {
+        let mut tracer = Tracer::default();
+        tracer.trace::<Request>().expect("Requests can be traced");
+        tracer.trace::<Response>().expect("Responses can be traced");
+        tracer.trace::<ErrorKind>().expect("Error kinds can be traced");
+        tracer.trace::<NotFoundReason>().expect("Not found reasons can be traced");
+        tracer.trace::<Durability>().expect("Durabilities can be traced");
+        Schema {
+            protocol_version: PROTOCOL_VERSION,
+            encoding: ENCODING.to_owned(),
+            types: tracer.finish().expect("Every protocol enum is traced"),
+        }
+    }
+}
+
+/// Records the formats of the types deserialized through its [`FormatDeserializer`]s.
+///
+/// Deserializing a value only visits one variant of each enum, so types are traced repeatedly,
+/// taking the next untraced variant of each enum every time. Enums that are only reached through
+/// other enums (e.g. `ErrorKind`) must be traced themselves too. Recursive types can't be traced.
+#[derive(Default)]
+struct Tracer {
+    types: BTreeMap<String, SchemaType>,
+    variants: BTreeMap<&'static str, Vec<Option<SchemaVariant>>>,
+}
+
+impl Tracer {
+    /// Trace `T` until every variant of the enums it reaches has been traced (or no more can be).
+    fn trace<T: DeserializeOwned>(&mut self) -> Result<(), TraceError> {
+        loop {
+            let traced = self.traced_variants();
+            let mut format = SchemaFormat::Unknown;
+            T::deserialize(FormatDeserializer { tracer: &mut *self, format: &mut format })?;
+            if self.traced_variants() == traced {
+                return Ok(());
+            }
+        }
+    }
+
+    /// The types traced, failing if any enum has variants that haven't been traced.
+    fn finish(mut self) -> Result<BTreeMap<String, SchemaType>, TraceError> {
+        for (name, variants) in self.variants {
+            let variants = variants
+                .into_iter()
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| TraceError(format!("{} has untraced variants", name)))?;
+            self.types.insert(name.to_owned(), SchemaType::Enum(variants));
+        }
+        Ok(self.types)
+    }
+
+    fn traced_variants(&self) -> usize {
+        self.variants.values().flatten().filter(|variant| variant.is_some()).count()
+    }
+
+    /// The index of the variant of enum `name` to trace next: the first untraced one, if any.
+    fn next_variant(&mut self, name: &'static str, count: usize) -> u32 {
+        let variants = self.variants.entry(name).or_insert_with(|| vec![None; count]);
+        variants.iter().position(Option::is_none).unwrap_or(0) as u32
+    }
+}
+
+/// The error from tracing a type that can't be described.
+#[derive(Debug)]
+struct TraceError(String);
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl de::Error for TraceError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        TraceError(msg.to_string())
+    }
+}
+
+/// Deserializes a placeholder value of whatever type asks for one, recording its format.
+struct FormatDeserializer<'a> {
+    tracer: &'a mut Tracer,
+    format: &'a mut SchemaFormat,
+}
+
+/// Implements the deserializer methods for primitives, which record their format and visit a
+/// placeholder value.
+macro_rules! deserialize_primitives {
+    ($($method:ident => $format:ident, $visit:ident($($value:expr)?);)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+                *self.format = SchemaFormat::$format;
+                visitor.$visit($($value)?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for FormatDeserializer<'a> {
+    type Error = TraceError;
+
+    deserialize_primitives! {
+        deserialize_bool => Bool, visit_bool(false);
+        deserialize_i8 => I8, visit_i8(0);
+        deserialize_i16 => I16, visit_i16(0);
+        deserialize_i32 => I32, visit_i32(0);
+        deserialize_i64 => I64, visit_i64(0);
+        deserialize_u8 => U8, visit_u8(0);
+        deserialize_u16 => U16, visit_u16(0);
+        deserialize_u32 => U32, visit_u32(0);
+        deserialize_u64 => U64, visit_u64(0);
+        deserialize_f32 => F32, visit_f32(0.0);
+        deserialize_f64 => F64, visit_f64(0.0);
+        deserialize_char => Char, visit_char('a');
+        deserialize_str => String, visit_str("");
+        deserialize_string => String, visit_str("");
+        deserialize_bytes => Bytes, visit_bytes(&[]);
+        deserialize_byte_buf => Bytes, visit_bytes(&[]);
+        deserialize_unit => Unit, visit_unit();
+        deserialize_ignored_any => Unit, visit_unit();
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+        Err(TraceError("self-describing types can't be traced".to_owned()))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let mut item = SchemaFormat::Unknown;
+        let deserializer = FormatDeserializer { tracer: self.tracer, format: &mut item };
+        let value = visitor.visit_some(deserializer)?;
+        *self.format = SchemaFormat::Option(Box::new(item));
+        Ok(value)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let mut items = vec![SchemaFormat::Unknown];
+        let seq = SeqTracer { tracer: self.tracer, formats: items.iter_mut() };
+        let value = visitor.visit_seq(seq)?;
+        *self.format = SchemaFormat::Seq(Box::new(items.remove(0)));
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let mut items = vec![SchemaFormat::Unknown; len];
+        let seq = SeqTracer { tracer: self.tracer, formats: items.iter_mut() };
+        let value = visitor.visit_seq(seq)?;
+        *self.format = if len > 1 && items.windows(2).all(|pair| pair[0] == pair[1]) {
+            SchemaFormat::Array { item: Box::new(items.remove(0)), len }
+        } else {
+            SchemaFormat::Tuple(items)
+        };
+        Ok(value)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let (mut key, mut value) = (SchemaFormat::Unknown, SchemaFormat::Unknown);
+        let map = MapTracer { tracer: self.tracer, key: Some(&mut key), value: Some(&mut value) };
+        let result = visitor.visit_map(map)?;
+        *self.format = SchemaFormat::Map { key: Box::new(key), value: Box::new(value) };
+        Ok(result)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.tracer.types.insert(name.to_owned(), SchemaType::Struct(Vec::new()));
+        *self.format = SchemaFormat::Named(name.to_owned());
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let mut inner = SchemaFormat::Unknown;
+        let deserializer = FormatDeserializer { tracer: &mut *self.tracer, format: &mut inner };
+        let value = visitor.visit_newtype_struct(deserializer)?;
+        self.tracer.types.insert(name.to_owned(), SchemaType::Newtype(inner));
+        *self.format = SchemaFormat::Named(name.to_owned());
+        Ok(value)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let names: Vec<_> = (0..len).map(|index| index.to_string()).collect();
+        let (value, fields) = trace_fields(&mut *self.tracer, &names, visitor)?;
+        self.tracer.types.insert(name.to_owned(), SchemaType::Struct(fields));
+        *self.format = SchemaFormat::Named(name.to_owned());
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let (value, fields) = trace_fields(&mut *self.tracer, fields, visitor)?;
+        self.tracer.types.insert(name.to_owned(), SchemaType::Struct(fields));
+        *self.format = SchemaFormat::Named(name.to_owned());
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let index = self.tracer.next_variant(name, variants.len());
+        let mut fields = Vec::new();
+        let access = EnumTracer { tracer: &mut *self.tracer, index, fields: &mut fields };
+        let value = visitor.visit_enum(access)?;
+
+        let variant = SchemaVariant { index, name: variants[index as usize].to_owned(), fields };
+        self.tracer.variants.get_mut(name).expect("Enum is registered")[index as usize] =
+            Some(variant);
+        *self.format = SchemaFormat::Named(name.to_owned());
+        Ok(value)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Visit a struct-like value with fields called `names`, as a sequence, returning the value and
+/// its fields' formats.
+fn trace_fields<'de, V, N>(
+    tracer: &mut Tracer,
+    names: &[N],
+    visitor: V,
+) -> Result<(V::Value, Vec<SchemaField>), TraceError>
+where
+    V: Visitor<'de>,
+    N: AsRef<str>,
+{
+    let mut formats = vec![SchemaFormat::Unknown; names.len()];
+    let value = visitor.visit_seq(SeqTracer { tracer, formats: formats.iter_mut() })?;
+    let fields = names
+        .iter()
+        .zip(formats)
+        .map(|(name, format)| SchemaField { name: name.as_ref().to_owned(), format })
+        .collect();
+    Ok((value, fields))
+}
+
+/// Provides a placeholder element for each of `formats`, recording its format there.
+struct SeqTracer<'a> {
+    tracer: &'a mut Tracer,
+    formats: std::slice::IterMut<'a, SchemaFormat>,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for SeqTracer<'a> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, TraceError> {
+        match self.formats.next() {
+            Some(format) => {
+                seed.deserialize(FormatDeserializer { tracer: &mut *self.tracer, format }).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Provides a single placeholder entry, recording its key and value formats.
+struct MapTracer<'a> {
+    tracer: &'a mut Tracer,
+    key: Option<&'a mut SchemaFormat>,
+    value: Option<&'a mut SchemaFormat>,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for MapTracer<'a> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, TraceError> {
+        match self.key.take() {
+            Some(format) => {
+                seed.deserialize(FormatDeserializer { tracer: &mut *self.tracer, format }).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, TraceError> {
+        let format = self.value.take().expect("Values follow keys");
+        seed.deserialize(FormatDeserializer { tracer: &mut *self.tracer, format })
+    }
+}
+
+/// Provides the variant of an enum at `index`, recording its fields' formats in `fields`.
+struct EnumTracer<'a> {
+    tracer: &'a mut Tracer,
+    index: u32,
+    fields: &'a mut Vec<SchemaField>,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for EnumTracer<'a> {
+    type Error = TraceError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), TraceError> {
+        let deserializer: de::value::U32Deserializer<TraceError> = self.index.into_deserializer();
+        Ok((seed.deserialize(deserializer)?, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for EnumTracer<'a> {
+    type Error = TraceError;
+
+    fn unit_variant(self) -> Result<(), TraceError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, TraceError> {
+        let mut format = SchemaFormat::Unknown;
+        let deserializer = FormatDeserializer { tracer: self.tracer, format: &mut format };
+        let value = seed.deserialize(deserializer)?;
+        self.fields.push(SchemaField { name: "0".to_owned(), format });
+        Ok(value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let names: Vec<_> = (0..len).map(|index| index.to_string()).collect();
+        let (value, fields) = trace_fields(self.tracer, &names, visitor)?;
+        *self.fields = fields;
+        Ok(value)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let (value, fields) = trace_fields(self.tracer, fields, visitor)?;
+        *self.fields = fields;
+        Ok(value)
+    }
+}
@@ -0,0 +1,274 @@
+use std::str::FromStr;
+
+use crate::client::KvsClient;
+use crate::error::{Error, Result};
+
+/// An ad-hoc query over a store's keys, compiled into a scan and a filter.
+///
+/// Queries have the form:
+///
+/// ```text
+/// (GET | DEL) <pattern> [WHERE <condition> [AND <condition>]...] [LIMIT <n>]
+/// ```
+///
+/// A pattern is either an exact key, or a prefix followed by `*`. Conditions compare the `key` or
+/// `value` of each matching entry with a quoted string, using `=`, `!=` or `CONTAINS`. Keywords
+/// are case-insensitive.
+///
+/// ```
+/// use kvs::{EmbeddedClient, KvsClient, MemoryKvStore, Query};
+///
+/// # fn main() -> kvs::Result<()> {
+/// let mut client = EmbeddedClient::new(MemoryKvStore::new());
+/// client.set("user:1".to_owned(), "alice".to_owned())?;
+/// client.set("user:2".to_owned(), "bob".to_owned())?;
+///
+/// let query: Query = "GET user:* WHERE value != 'bob' LIMIT 10".parse()?;
+/// assert_eq!(query.run(&mut client)?, vec![("user:1".to_owned(), "alice".to_owned())]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    action: Action,
+    prefix: String,
+    exact: bool,
+    conditions: Vec<Condition>,
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Get,
+    Del,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Condition {
+    field: Field,
+    op: Op,
+    operand: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Key,
+    Value,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Contains,
+}
+
+impl Query {
+    /// Whether the query removes the entries it matches.
+    pub fn is_delete(&self) -> bool {
+        self.action == Action::Del
+    }
+
+    /// Run the query with `client`, returning the matching entries in key order.
+    ///
+    /// For `DEL` queries the matching entries are removed, and the entries returned are those that
+    /// were removed. Entries removed concurrently by another client are skipped.
+    pub fn run<C: KvsClient>(&self, client: &mut C) -> Result<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+        for (key, value) in client.scan(self.prefix.clone())? {
+            if self.limit.is_some_and(|limit| entries.len() >= limit) {
+                break;
+            }
+            if self.exact && key != self.prefix {
+                continue;
+            }
+            if !self.conditions.iter().all(|condition| condition.matches(&key, &value)) {
+                continue;
+            }
+            if self.action == Action::Del {
+                match client.remove(key.clone()) {
+                    Ok(()) => {},
+                    Err(Error::KeyNotFound) => continue,
+                    Err(error) => return Err(error),
+                }
+            }
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(query: &str) -> Result<Self> {
+        let mut tokens = tokenize(query)?.into_iter().peekable();
+
+        let action = match tokens.next() {
+            Some(Token::Word(ref word)) if is_keyword(word, "GET") => Action::Get,
+            Some(Token::Word(ref word)) if is_keyword(word, "DEL") => Action::Del,
+            Some(token) => return Err(invalid(format!("expected GET or DEL, found {}", token))),
+            None => return Err(invalid("the query is empty")),
+        };
+
+        let (prefix, exact) = match tokens.next() {
+            Some(Token::Word(pattern)) | Some(Token::Quoted(pattern)) => {
+                match pattern.find('*') {
+                    None => (pattern, true),
+                    Some(star) if star == pattern.len() - 1 => {
+                        (pattern[..star].to_owned(), false)
+                    },
+                    Some(_) => return Err(invalid("`*` is only allowed at the end of a pattern")),
+                }
+            },
+            None => return Err(invalid("expected a key pattern")),
+        };
+
+        let mut conditions = Vec::new();
+        if tokens.peek().is_some_and(|token| token.is_keyword("WHERE")) {
+            tokens.next();
+            loop {
+                conditions.push(Condition::parse(&mut tokens)?);
+                if !tokens.peek().is_some_and(|token| token.is_keyword("AND")) {
+                    break;
+                }
+                tokens.next();
+            }
+        }
+
+        let mut limit = None;
+        if tokens.peek().is_some_and(|token| token.is_keyword("LIMIT")) {
+            tokens.next();
+            limit = match tokens.next() {
+                Some(Token::Word(ref n)) => match n.parse() {
+                    Ok(n) => Some(n),
+                    Err(_) => return Err(invalid(format!("invalid limit {:?}", n))),
+                },
+                _ => return Err(invalid("expected a number after LIMIT")),
+            };
+        }
+
+        if let Some(token) = tokens.next() {
+            return Err(invalid(format!("unexpected {}", token)));
+        }
+        Ok(Query { action, prefix, exact, conditions, limit })
+    }
+}
+
+impl Condition {
+    fn parse<I: Iterator<Item = Token>>(tokens: &mut I) -> Result<Self> {
+        let field = match tokens.next() {
+            Some(Token::Word(ref word)) if is_keyword(word, "KEY") => Field::Key,
+            Some(Token::Word(ref word)) if is_keyword(word, "VALUE") => Field::Value,
+            Some(Token::Word(ref word)) if is_keyword(word, "TTL_EXPIRED") => {
+                return Err(invalid(
+                    "expired keys are never returned by the server (they're removed by its \
+                     sweeper)",
+                ))
+            },
+            Some(token) => return Err(invalid(format!("expected key or value, found {}", token))),
+            None => return Err(invalid("expected a condition after WHERE or AND")),
+        };
+        let op = match tokens.next() {
+            Some(Token::Word(ref word)) if word == "=" => Op::Eq,
+            Some(Token::Word(ref word)) if word == "!=" => Op::Ne,
+            Some(Token::Word(ref word)) if is_keyword(word, "CONTAINS") => Op::Contains,
+            Some(token) => {
+                return Err(invalid(format!("expected =, != or CONTAINS, found {}", token)))
+            },
+            None => return Err(invalid("expected =, != or CONTAINS")),
+        };
+        let operand = match tokens.next() {
+            Some(Token::Quoted(operand)) => operand,
+            Some(token) => {
+                return Err(invalid(format!("expected a quoted string, found {}", token)))
+            },
+            None => return Err(invalid("expected a quoted string")),
+        };
+        Ok(Condition { field, op, operand })
+    }
+
+    fn matches(&self, key: &str, value: &str) -> bool {
+        let subject = match self.field {
+            Field::Key => key,
+            Field::Value => value,
+        };
+        match self.op {
+            Op::Eq => subject == self.operand,
+            Op::Ne => subject != self.operand,
+            Op::Contains => subject.contains(&self.operand),
+        }
+    }
+}
+
+/// A token of a query: a bare word, or a string in single or double quotes.
+#[derive(Debug)]
+enum Token {
+    Word(String),
+    Quoted(String),
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self {
+            Token::Word(word) => is_keyword(word, keyword),
+            Token::Quoted(_) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{}`", word),
+            Token::Quoted(string) => write!(f, "{:?}", string),
+        }
+    }
+}
+
+/// Split a query into whitespace-separated tokens.
+///
+/// Quoted strings may contain whitespace, and a backslash escapes the next character.
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some(next) if next == c => break,
+                    Some('\\') => match chars.next() {
+                        Some(escaped) => string.push(escaped),
+                        None => return Err(invalid("unterminated string")),
+                    },
+                    Some(next) => string.push(next),
+                    None => return Err(invalid("unterminated string")),
+                }
+            }
+            tokens.push(Token::Quoted(string));
+        } else {
+            let mut word = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_whitespace() {
+                    break;
+                }
+                word.push(next);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_keyword(word: &str, keyword: &str) -> bool {
+    word.eq_ignore_ascii_case(keyword)
+}
+
+fn invalid<M: Into<String>>(message: M) -> Error {
+    Error::InvalidQuery(message.into())
+}
@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::KvsClient;
+use crate::engine::SYSTEM_KEY_PREFIX;
+use crate::error::{Error, Result};
+use crate::ring::{Ring, RingMove};
+
+/// The default number of entries moved between progress reports.
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Moves the entries of a sharded deployment whose servers change between two [`Ring`]s, e.g.
+/// after adding or removing a server.
+///
+/// Only the ranges of the ring that change hands (see [`Ring::moves`]) are migrated. Each server
+/// that loses a range is scanned, and its entries in that range are written in batches to their
+/// new servers, then removed from the old one. Progress is reported after each batch.
+///
+/// Since moved entries are removed from their old servers, an interrupted rebalance picks up where
+/// it left off when it's run again: entries from a batch that was written but not yet removed are
+/// just written again. Entries written to the moving ranges while a rebalance runs may be lost, so
+/// clients should write through the new ring before it starts.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use kvs::{EmbeddedClient, KvsClient, MemoryKvStore, Rebalance, Ring, SharedEngine};
+///
+/// # fn main() -> kvs::Result<()> {
+/// let old = Ring::new(vec!["a".to_owned()], 16)?;
+/// let new = Ring::new(vec!["a".to_owned(), "b".to_owned()], 16)?;
+/// let servers: HashMap<_, _> = new
+///     .nodes()
+///     .iter()
+///     .map(|node| (node.clone(), SharedEngine::new(MemoryKvStore::new())))
+///     .collect();
+/// for i in 0..100 {
+///     EmbeddedClient::new(servers["a"].clone()).set(format!("key{}", i), i.to_string())?;
+/// }
+///
+/// let connect = |node: &str| Ok(EmbeddedClient::new(servers[node].clone()));
+/// let progress = Rebalance::new(old, new.clone()).run(connect, |_| {})?;
+/// let on_b = EmbeddedClient::new(servers["b"].clone()).scan(String::new())?;
+/// assert_eq!(progress.moved, on_b.len() as u64);
+/// assert!(on_b.iter().all(|(key, _)| new.owner(key) == "b"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Rebalance {
+    old: Ring,
+    new: Ring,
+    batch_size: usize,
+    max_keys_per_sec: Option<f64>,
+}
+
+/// How far a [`Rebalance`] has got.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RebalanceProgress {
+    /// The number of entries moved so far.
+    pub moved: u64,
+
+    /// The server entries are being moved from.
+    pub source: Option<String>,
+
+    /// The last key moved from `source`.
+    pub last_key: Option<String>,
+}
+
+impl Rebalance {
+    /// Construct a rebalance from the `old` ring to the `new` one.
+    pub fn new(old: Ring, new: Ring) -> Self {
+        Rebalance { old, new, batch_size: DEFAULT_BATCH_SIZE, max_keys_per_sec: None }
+    }
+
+    /// Move `batch_size` entries (1000 by default) between progress reports.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Move at most `max_keys_per_sec` entries per second (with no limit by default), so that the
+    /// rebalance doesn't starve the servers' other clients.
+    pub fn max_keys_per_sec(mut self, max_keys_per_sec: f64) -> Self {
+        self.max_keys_per_sec = Some(max_keys_per_sec);
+        self
+    }
+
+    /// The ranges of the ring whose entries will be moved.
+    pub fn moves(&self) -> Vec<RingMove> {
+        self.old.moves(&self.new)
+    }
+
+    /// Move the entries, using clients for each server made with `connect`.
+    ///
+    /// `progress` is called after each batch has been moved. If a request fails, the rebalance
+    /// stops with its error, and can be run again to carry on. System keys are never moved.
+    pub fn run<C, K, P>(&self, mut connect: C, mut progress: P) -> Result<RebalanceProgress>
+    where
+        C: FnMut(&str) -> Result<K>,
+        K: KvsClient,
+        P: FnMut(&RebalanceProgress),
+    {
+        let moves = self.moves();
+        let started = Instant::now();
+        let mut clients = HashMap::new();
+        let mut report = RebalanceProgress::default();
+        for source in self.old.nodes() {
+            let moving: Vec<_> = moves.iter().filter(|range| range.from == *source).collect();
+            if moving.is_empty() {
+                continue;
+            }
+
+            let mut source_client = connect(source)?;
+            let mut entries = source_client.scan(String::new())?;
+            entries.retain(|(key, _)| {
+                let position = Ring::position(key);
+                !key.starts_with(SYSTEM_KEY_PREFIX)
+                    && moving.iter().any(|range| range.contains(position))
+            });
+            for batch in entries.chunks(self.batch_size) {
+                self.throttle(started, report.moved);
+
+                let mut destinations: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+                for (key, value) in batch {
+                    let pairs = destinations.entry(self.new.owner(key)).or_default();
+                    pairs.push((key.clone(), value.clone()));
+                }
+                for (destination, pairs) in destinations {
+                    if !clients.contains_key(destination) {
+                        clients.insert(destination.to_owned(), connect(destination)?);
+                    }
+                    let client = clients.get_mut(destination).expect("Clients are connected");
+                    client.set_many(pairs)?;
+                }
+                for (key, _) in batch {
+                    match source_client.remove(key.clone()) {
+                        Ok(()) | Err(Error::KeyNotFound) => {},
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                report.moved += batch.len() as u64;
+                report.source = Some(source.clone());
+                report.last_key = batch.last().map(|(key, _)| key.clone());
+                progress(&report);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Wait until moving another batch wouldn't exceed the rate limit, given that `moved` entries
+    /// have been moved since `started`.
+    fn throttle(&self, started: Instant, moved: u64) {
+        if let Some(max_keys_per_sec) = self.max_keys_per_sec.filter(|&rate| rate > 0.0) {
+            let due = started + Duration::from_secs_f64(moved as f64 / max_keys_per_sec);
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! Crash-recovery testing for engines, by simulating power cuts part-way through writing the log.
+//!
+//! A [`Simulation`] runs a workload of [`Step`]s against an engine stored in a directory. After
+//! each step, it copies the directory as a power cut would have left it at each of the configured
+//! offsets (see [`Cuts`]) through the log written by the step, and opens the engine on the copy.
+//! Each recovered engine must hold a state the workload passed through during the step: the state
+//! before it, after it, or between two of the commands it writes. So, for example, a key can't
+//! lose its TTL or be found under both names of a rename, and a batch is recovered as a prefix of
+//! its writes. Once the log survives in full, every write must be recovered.
+//!
+//! The harness works with any engine that appends to log files, so engine implementers can reuse
+//! it. By default the log files are found as kvs names them (see [`log_files`]).
+//!
+//! ```
+//! use std::path::Path;
+//! use std::time::Duration;
+//! use kvs::recovery::{Cuts, Simulation, Step};
+//! use kvs::{KvStore, Result};
+//!
+//! # fn check() -> Result<()> {
+//! # let dir = std::path::PathBuf::new();
+//! let workload = vec![
+//!     Step::Set("key".to_owned(), "value".to_owned()),
+//!     Step::SetWithTtl("session".to_owned(), "1".to_owned(), Duration::from_secs(3600)),
+//!     Step::Rename { from: "key".to_owned(), to: "renamed".to_owned(), overwrite: false },
+//! ];
+//! let simulation = Simulation::new(dir, |path: &Path| KvStore::open(path));
+//! let report = simulation.cuts(Cuts::Every(4)).run(&workload)?;
+//! assert!(report.violations.is_empty(), "{:?}", report.violations);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::time::Duration;
+
+use crate::engine::{Engine, SYSTEM_KEY_PREFIX};
+use crate::error::{Error, Result};
+
+/// A write made by a workload.
+///
+/// Steps must succeed against the engine (e.g. keys must be in the store to be removed), or the
+/// simulation fails. TTLs must be long enough that keys don't expire while it runs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// Set a key to a value.
+    Set(String, String),
+
+    /// Set a key to a value that expires after a time-to-live.
+    SetWithTtl(String, String, Duration),
+
+    /// Remove a key.
+    Remove(String),
+
+    /// Set several keys (see [`KvsEngine::set_many`]).
+    ///
+    /// [`KvsEngine::set_many`]: ../trait.KvsEngine.html#method.set_many
+    SetMany(Vec<(String, String)>),
+
+    /// Remove several keys (see [`KvsEngine::remove_many`]).
+    ///
+    /// [`KvsEngine::remove_many`]: ../trait.KvsEngine.html#method.remove_many
+    RemoveMany(Vec<String>),
+
+    /// Move a key's value, and its time-to-live, to another key (see [`KvsEngine::rename`]).
+    ///
+    /// [`KvsEngine::rename`]: ../trait.KvsEngine.html#method.rename
+    Rename {
+        /// The key to move.
+        from: String,
+        /// The key to move it to.
+        to: String,
+        /// Whether to replace `to` if it's already set.
+        overwrite: bool,
+    },
+}
+
+/// The keys in a store, with their values and the time-to-live they were set with.
+type State = BTreeMap<String, (String, Option<Duration>)>;
+
+impl Step {
+    /// Apply the step to `engine`.
+    fn apply(&self, engine: &mut dyn Engine) -> Result<()> {
+        match self.clone() {
+            Step::Set(key, value) => engine.set(key, value),
+            Step::SetWithTtl(key, value, ttl) => engine.set_with_ttl(key, value, ttl),
+            Step::Remove(key) => engine.remove(key),
+            Step::SetMany(pairs) => engine.set_many(pairs),
+            Step::RemoveMany(keys) => engine.remove_many(keys).map(drop),
+            Step::Rename { from, to, overwrite } => engine.rename(from, to, overwrite).map(drop),
+        }
+    }
+
+    /// The states the step passes through from `state`, one for each write, ending with the
+    /// state it leaves.
+    fn states(&self, state: &State) -> Vec<State> {
+        let mut state = state.clone();
+        let mut states = Vec::new();
+        match self {
+            Step::Set(key, value) => {
+                state.insert(key.clone(), (value.clone(), None));
+                states.push(state);
+            },
+            Step::SetWithTtl(key, value, ttl) => {
+                state.insert(key.clone(), (value.clone(), Some(*ttl)));
+                states.push(state);
+            },
+            Step::Remove(key) => {
+                state.remove(key);
+                states.push(state);
+            },
+            Step::SetMany(pairs) => {
+                for (key, value) in pairs {
+                    state.insert(key.clone(), (value.clone(), None));
+                    states.push(state.clone());
+                }
+            },
+            Step::RemoveMany(keys) => {
+                for key in keys {
+                    state.remove(key);
+                    states.push(state.clone());
+                }
+            },
+            Step::Rename { from, to, overwrite } => {
+                if *overwrite || !state.contains_key(to) {
+                    if let Some(entry) = state.remove(from) {
+                        state.insert(to.clone(), entry);
+                    }
+                }
+                states.push(state);
+            },
+        }
+        states
+    }
+}
+
+/// Where to cut the log written by each step.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cuts {
+    /// Cut every `n` bytes through the log written by each step.
+    Every(u64),
+
+    /// Cut at these offsets of the log file, where a step wrote them.
+    At(Vec<u64>),
+}
+
+impl Default for Cuts {
+    /// Cut at every byte.
+    fn default() -> Self {
+        Cuts::Every(1)
+    }
+}
+
+impl Cuts {
+    /// The offsets to cut at between `start` and `end` (exclusive), where a step wrote a log file
+    /// from `start` to `end`.
+    fn offsets(&self, start: u64, end: u64) -> Vec<u64> {
+        match self {
+            Cuts::Every(n) => {
+                let n = (*n).max(1);
+                (1..).map(|i| start + i * n).take_while(|&offset| offset < end).collect()
+            },
+            Cuts::At(offsets) => {
+                offsets.iter().copied().filter(|&offset| offset > start && offset < end).collect()
+            },
+        }
+    }
+}
+
+/// An invariant that didn't hold after recovering from a power cut.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    /// The index of the step that was cut in the workload.
+    pub step: usize,
+
+    /// The offset the log was cut at, or `None` if the whole log survived.
+    pub offset: Option<u64>,
+
+    /// What went wrong.
+    pub problem: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "step {}, log cut at {}", self.step, offset)?,
+            None => write!(f, "step {}, log intact", self.step)?,
+        }
+        write!(f, ": {}", self.problem)
+    }
+}
+
+/// The outcome of a [`Simulation`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    /// The number of power cuts recovered from.
+    pub power_cuts: u64,
+
+    /// The number of steps that moved the engine on to new log files (e.g. by compacting the log),
+    /// which are only cut once they've completed.
+    pub log_changes: u64,
+
+    /// The invariants that didn't hold, in the order they were found.
+    pub violations: Vec<Violation>,
+}
+
+/// Opens the engine under test in a directory.
+type Open = Box<dyn FnMut(&Path) -> Result<Box<dyn Engine>>>;
+
+/// Runs a workload against an engine, recovering from simulated power cuts after each step.
+///
+/// See the [module documentation](index.html).
+pub struct Simulation {
+    dir: PathBuf,
+    open: Open,
+    cuts: Cuts,
+    log_files: fn(&Path) -> Result<Vec<PathBuf>>,
+}
+
+impl Simulation {
+    /// Simulate power cuts to engines opened in `dir` by `open`.
+    ///
+    /// The engine is stored in `dir/store`, and each power cut is recovered from in `dir/crash`.
+    /// `dir` should be empty, e.g. a new temporary directory.
+    pub fn new<E, F>(dir: impl Into<PathBuf>, mut open: F) -> Self
+    where
+        E: Engine + 'static,
+        F: FnMut(&Path) -> Result<E> + 'static,
+    {
+        Simulation {
+            dir: dir.into(),
+            open: Box::new(move |path: &Path| Ok(Box::new(open(path)?) as Box<dyn Engine>)),
+            cuts: Cuts::default(),
+            log_files,
+        }
+    }
+
+    /// Cut the log at `cuts`, rather than at every byte.
+    pub fn cuts(mut self, cuts: Cuts) -> Self {
+        self.cuts = cuts;
+        self
+    }
+
+    /// Find an engine's log files with `log_files`, rather than as kvs names them.
+    ///
+    /// Given a directory, `log_files` should return the log files in it, oldest first. Only the
+    /// newest is cut.
+    pub fn log_files(mut self, log_files: fn(&Path) -> Result<Vec<PathBuf>>) -> Self {
+        self.log_files = log_files;
+        self
+    }
+
+    /// Run `workload`, simulating power cuts through each step, then check the engine recovers
+    /// every write after it's closed.
+    ///
+    /// Fails if the engine can't be opened, if a step fails, or if the store can't be copied.
+    /// Recoveries that fail are reported as violations.
+    pub fn run(&mut self, workload: &[Step]) -> Result<Report> {
+        let store_dir = self.dir.join("store");
+        let crash_dir = self.dir.join("crash");
+        let mut report = Report::default();
+        let mut state = State::new();
+        fs::create_dir_all(&store_dir)?;
+        let mut engine = (self.open)(&store_dir)?;
+
+        for (step_index, step) in workload.iter().enumerate() {
+            let logs_before = (self.log_files)(&store_dir)?;
+            let start = match logs_before.last() {
+                Some(log) => fs::metadata(log)?.len(),
+                None => 0,
+            };
+            step.apply(&mut *engine)?;
+            let mut states = vec![state.clone()];
+            states.extend(step.states(&state));
+            let after = states.last().expect("Every step leaves a state").clone();
+            if let Some(problem) = check(&mut *engine, std::slice::from_ref(&after))? {
+                let problem = format!("the engine itself {}", problem);
+                report.violations.push(Violation { step: step_index, offset: None, problem });
+            }
+
+            let logs = (self.log_files)(&store_dir)?;
+            let offsets = match logs.last() {
+                _ if logs != logs_before => {
+                    report.log_changes += 1;
+                    Vec::new()
+                },
+                Some(log) => self.cuts.offsets(start, fs::metadata(log)?.len()),
+                None => Vec::new(),
+            };
+            for offset in offsets.into_iter().map(Some).chain(iter::once(None)) {
+                match (offset, logs.last()) {
+                    (Some(offset), Some(log)) => power_cut(&store_dir, &crash_dir, log, offset)?,
+                    _ => copy_dir(&store_dir, &crash_dir)?,
+                }
+                report.power_cuts += 1;
+                let expected = match offset {
+                    Some(_) => &states[..],
+                    None => slice::from_ref(&after),
+                };
+                let problem = match (self.open)(&crash_dir) {
+                    Ok(mut recovered) => check(&mut *recovered, expected)?,
+                    Err(err) => Some(format!("failed to recover: {}", err)),
+                };
+                if let Some(problem) = problem {
+                    report.violations.push(Violation { step: step_index, offset, problem });
+                }
+            }
+            state = after;
+        }
+
+        drop(engine);
+        let problem = match (self.open)(&store_dir) {
+            Ok(mut reopened) => check(&mut *reopened, &[state])?,
+            Err(err) => Some(format!("failed to reopen: {}", err)),
+        };
+        if let Some(problem) = problem {
+            let step = workload.len().saturating_sub(1);
+            report.violations.push(Violation { step, offset: None, problem });
+        }
+        Ok(report)
+    }
+}
+
+/// Copy the store in `from` to `to` as a power cut would have left it if its log file `log` had
+/// only been written up to `offset`. Anything already in `to` is replaced.
+///
+/// `log` must be a file directly in `from`.
+pub fn power_cut(from: &Path, to: &Path, log: &Path, offset: u64) -> Result<()> {
+    let name = log.file_name().ok_or_else(|| {
+        Error::Config(format!("{} is not a log file in {}", log.display(), from.display()))
+    })?;
+    copy_dir(from, to)?;
+    let file = OpenOptions::new().write(true).open(to.join(name))?;
+    if file.metadata()?.len() > offset {
+        file.set_len(offset)?;
+    }
+    Ok(())
+}
+
+/// The log files in `dir` as kvs names them, `<index>.log`, oldest first.
+pub fn log_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut logs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension() != Some("log".as_ref()) {
+            continue;
+        }
+        if let Some(index) = path.file_stem().and_then(|s| s.to_str()?.parse::<u64>().ok()) {
+            logs.push((index, path));
+        }
+    }
+    logs.sort_unstable();
+    Ok(logs.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Replace `to` with a copy of `from`.
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    if to.exists() {
+        fs::remove_dir_all(to)?;
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        let copy = to.join(path.file_name().expect("Directory entries have names"));
+        if path.is_dir() {
+            copy_dir(&path, &copy)?;
+        } else {
+            fs::copy(&path, &copy)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that `engine` holds one of the `expected` states, describing how it doesn't if not.
+///
+/// A key's time-to-live may have counted down since it was set, but must not have grown, been
+/// added, or been lost.
+fn check(engine: &mut dyn Engine, expected: &[State]) -> Result<Option<String>> {
+    let mut found = BTreeMap::new();
+    for (key, value) in engine.scan("")? {
+        if !key.starts_with(SYSTEM_KEY_PREFIX) {
+            let ttl = engine.ttl(key.clone())?;
+            found.insert(key, (value, ttl));
+        }
+    }
+    let matches = |state: &State| {
+        state.len() == found.len()
+            && state.iter().zip(&found).all(|((key, (value, ttl)), (found_key, found))| {
+                let ttl_held = match (ttl, found.1) {
+                    (None, None) => true,
+                    (Some(ttl), Some(left)) => left <= *ttl,
+                    _ => false,
+                };
+                key == found_key && *value == found.0 && ttl_held
+            })
+    };
+    if expected.iter().any(matches) {
+        return Ok(None);
+    }
+    let found: BTreeMap<_, _> = found.into_iter().map(|(key, (value, _))| (key, value)).collect();
+    Ok(Some(format!("holds a state the workload never passed through: {:?}", found)))
+}
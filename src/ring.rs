@@ -0,0 +1,153 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+/// The default number of points each node has on a ring.
+const DEFAULT_VNODES: usize = 64;
+
+/// A consistent hashing ring, assigning each key to one of a sharded deployment's servers.
+///
+/// Each node (a server's address) is placed at `vnodes` points around the ring, and a key belongs
+/// to the node at the first point at or after the key's [position](#method.position), wrapping
+/// around at the end. Adding or removing a node only moves the keys next to its points, which
+/// [`moves`](#method.moves) describes.
+///
+/// Rings are usually loaded from a TOML file listing the nodes:
+///
+/// ```toml
+/// nodes = ["10.0.0.1:4001", "10.0.0.2:4001"]
+/// vnodes = 64
+/// ```
+#[derive(Clone, Debug)]
+pub struct Ring {
+    nodes: Vec<String>,
+    points: Vec<(u64, usize)>,
+}
+
+/// A range of positions on a ring whose keys belong to a different node on another ring.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RingMove {
+    /// The first position in the range.
+    pub start: u64,
+
+    /// The last position in the range (inclusive).
+    pub end: u64,
+
+    /// The node the keys belong to on the old ring.
+    pub from: String,
+
+    /// The node the keys belong to on the new ring.
+    pub to: String,
+}
+
+impl RingMove {
+    /// Whether a key at `position` is in the range.
+    pub fn contains(&self, position: u64) -> bool {
+        self.start <= position && position <= self.end
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RingFile {
+    nodes: Vec<String>,
+    #[serde(default = "default_vnodes")]
+    vnodes: usize,
+}
+
+fn default_vnodes() -> usize {
+    DEFAULT_VNODES
+}
+
+impl Ring {
+    /// Construct a ring of `nodes`, each placed at `vnodes` points.
+    pub fn new(nodes: Vec<String>, vnodes: usize) -> Result<Self> {
+        if nodes.is_empty() || vnodes == 0 {
+            return Err(Error::Config("a ring needs at least one node and vnode".to_owned()));
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            if nodes[..i].contains(node) {
+                return Err(Error::Config(format!("node {:?} is on the ring twice", node)));
+            }
+        }
+        let mut points: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, node)| {
+                (0..vnodes).map(move |vnode| (Ring::position(&format!("{}#{}", node, vnode)), i))
+            })
+            .collect();
+        points.sort();
+        Ok(Ring { nodes, points })
+    }
+
+    /// Load a ring from a TOML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let file: RingFile = toml::from_str(&contents)
+            .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+        Ring::new(file.nodes, file.vnodes)
+    }
+
+    /// The ring's nodes, in the order they were given.
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    /// The position of `key` on any ring: its 64-bit FNV-1a hash.
+    ///
+    /// This is fixed, so that every version of every client agrees on where keys belong.
+    pub fn position(key: &str) -> u64 {
+        key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// The node that `key` belongs to.
+    pub fn owner(&self, key: &str) -> &str {
+        self.owner_at(Ring::position(key))
+    }
+
+    /// The ranges of positions whose keys belong to a different node on the `new` ring, in
+    /// position order.
+    pub fn moves(&self, new: &Ring) -> Vec<RingMove> {
+        // Between consecutive points of either ring, every position belongs to the same node on
+        // each ring (that of the next point), so comparing owners at each point is enough.
+        let points = self.points.iter().chain(&new.points);
+        let mut ends: Vec<_> = points.map(|&(point, _)| point).collect();
+        ends.push(u64::MAX);
+        ends.sort();
+        ends.dedup();
+
+        let mut moves: Vec<RingMove> = Vec::new();
+        let mut start = 0;
+        for end in ends {
+            let (from, to) = (self.owner_at(end), new.owner_at(end));
+            if from != to {
+                match moves.last_mut() {
+                    Some(last) if last.end + 1 == start && last.from == from && last.to == to => {
+                        last.end = end;
+                    },
+                    _ => moves.push(RingMove {
+                        start,
+                        end,
+                        from: from.to_owned(),
+                        to: to.to_owned(),
+                    }),
+                }
+            }
+            start = end.wrapping_add(1);
+        }
+        moves
+    }
+
+    /// The node that the keys at `position` belong to.
+    fn owner_at(&self, position: u64) -> &str {
+        let next = self.points.partition_point(|&(point, _)| point < position);
+        let (_, node) = self.points.get(next).unwrap_or(&self.points[0]);
+        &self.nodes[*node]
+    }
+}
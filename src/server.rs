@@ -1,87 +1,1240 @@
-use rmp_serde::decode::from_read as read_mp;
-use rmp_serde::encode::write as write_mp;
+mod chaos;
+mod interceptor;
+mod lifetime;
+mod maintenance;
+mod mirror;
+mod peer;
+mod pending;
+mod sampler;
+mod scrubber;
+mod socket;
+mod sweeper;
+mod tenants;
+mod transactions;
+mod validation;
+mod warmup;
+mod watch;
+
 use slog::{debug, info, o, warn};
 use std::convert::TryFrom;
-use std::net::{TcpListener, ToSocketAddrs, TcpStream};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, ToSocketAddrs, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::channel::{self, ChannelListener, ChannelStream};
+use crate::client::Client;
+use crate::engine::{content_key, Engine, IfModified, SYSTEM_KEY_PREFIX};
+use crate::error::{Error, Result};
+use crate::metrics::{MetricsSink, NoopMetrics};
+use crate::protocol::{decode_request, encode_response, ErrorKind, Request, RequestKind, Response};
+use crate::protocol::{Durability, ServerInfo, PROTOCOL_VERSION};
+use crate::stats::{DistinctCount, Stats, TopK};
+use self::lifetime::Lifetime;
+use self::maintenance::Maintenance;
+use self::pending::Pending;
+use self::sampler::RequestSummary;
+use self::tenants::UsageChange;
+use self::transactions::Transactions;
+use self::watch::Watchers;
+
+pub use self::chaos::{Chaos, ChaosConfig, Fault, FaultScript};
+pub use self::interceptor::RequestInterceptor;
+pub use self::mirror::{Mirror, MirrorConfig};
+pub use self::peer::Peer;
+pub use self::sampler::{Sample, Sampler, SamplerConfig};
+pub use self::scrubber::{Scrubber, ScrubberConfig};
+pub use self::socket::ServerConfig;
+pub use self::sweeper::{Sweeper, SweeperConfig};
+pub use self::tenants::{TenantQuota, TenantUsage, Tenants, TENANT_SEPARATOR};
+pub use self::validation::{KeyCharset, KeyRules};
+pub use self::warmup::{Warmup, WarmupConfig};
+
+/// The number of key prefixes to track for [`Stats::hot_prefixes`].
+const HOT_PREFIXES_TRACKED: usize = 64;
+
+/// The number of key prefixes to report in [`Stats::hot_prefixes`].
+const HOT_PREFIXES_REPORTED: usize = 10;
+
+/// The maximum length of a key prefix tracked for [`Stats::hot_prefixes`].
+const MAX_PREFIX_LEN: usize = 16;
+
+/// The number of keys to track for [`Request::HotKeys`].
+///
+/// Any key receiving more than `1 / HOT_KEYS_TRACKED` of requests is guaranteed to be reported.
+const HOT_KEYS_TRACKED: usize = 256;
+
+/// How long to wait for a connection before running background work (sweeps, scrubs and warm-up),
+/// when any is configured.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long to wait before reading from pending connections again, when none of them had sent
+/// any more of their requests.
+const PENDING_POLL_INTERVAL: Duration = Duration::from_millis(1);
 
-use crate::engine::Engine;
-use crate::error::Result;
-use crate::protocol::{Request, Response};
+/// How often watching connections are sent a heartbeat, when nothing else has been sent to them.
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait for a watching connection to accept a change before dropping it, so that a
+/// slow watcher can't hold up the server.
+const WATCHER_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The request count, error count and latency metrics reported for each kind of request.
+const GET_METRICS: [&str; 3] =
+    ["kvs_server_get_requests_total", "kvs_server_get_errors_total", "kvs_server_get_seconds"];
+const SET_METRICS: [&str; 3] =
+    ["kvs_server_set_requests_total", "kvs_server_set_errors_total", "kvs_server_set_seconds"];
+const REMOVE_METRICS: [&str; 3] = [
+    "kvs_server_remove_requests_total",
+    "kvs_server_remove_errors_total",
+    "kvs_server_remove_seconds",
+];
+
+/// Where a server accepts connections from.
+enum Listener {
+    /// One or more TCP sockets. With more than one, each is non-blocking and they're polled in
+    /// turn.
+    Tcp(Vec<TcpListener>),
+    InProcess(ChannelListener),
+}
+
+/// A connection accepted by a [`Listener`].
+enum Connection {
+    Tcp(TcpStream),
+    InProcess(ChannelStream),
+}
 
 /// Implements a key-value server with a swappable storage engine.
+///
+/// Requests are read in chunks, interleaved across connections, so a large request being received
+/// doesn't hold up small ones. They're then handled one at a time, so concurrent clients see a
+/// linearizable store: each request takes effect at a single point between being sent and its
+/// response being received, and later requests see its effects. Background work (e.g. compaction
+/// and sweeping) runs between requests, so it's never observed part-way through. That holds as
+/// long as the engine is itself linearizable (see [`KvsEngine::consistency`]): a [follower]'s
+/// reads can be stale, however they're served.
+///
+/// [`KvsEngine::consistency`]: trait.KvsEngine.html#method.consistency
+/// [follower]: struct.KvFollower.html
 pub struct Server<E> {
     log: slog::Logger,
     engine: E,
-    listener: TcpListener,
+    listener: Listener,
+    started: Instant,
+    store_id: String,
+    stats: Stats,
+    hot_prefixes: TopK,
+    hot_keys: TopK,
+    distinct_keys: DistinctCount,
+    sampler: Option<Sampler>,
+    key_rules: KeyRules,
+    tenants: Option<Tenants>,
+    sweeper: Option<Sweeper>,
+    scrubber: Option<Scrubber>,
+    transactions: Transactions,
+    warmup: Option<Warmup>,
+    chaos: Option<Chaos>,
+    mirror: Option<Mirror>,
+    interceptors: Vec<Box<dyn RequestInterceptor>>,
+    metrics: Arc<dyn MetricsSink>,
+    lifetime: Lifetime,
+    maintenance: Option<Maintenance>,
+    config: ServerConfig,
+    pending: Vec<Pending>,
+    request_timeout: Option<Duration>,
+    watchers: Watchers,
+    heartbeat_interval: Duration,
+    connections: u64,
 }
 
 impl<E: Engine> Server<E> {
     /// Start the server.
+    ///
+    /// This initialises the engine's system keyspace, if it hasn't been already.
     pub fn start<A: ToSocketAddrs>(log: slog::Logger, engine: E, address: A) -> Result<Self> {
-        info!(log, "Starting server");
+        Self::start_with_config(log, engine, address, ServerConfig::default())
+    }
+
+    /// Start the server, with the socket options in `config`.
+    pub fn start_with_config<A: ToSocketAddrs>(
+        log: slog::Logger,
+        engine: E,
+        address: A,
+        config: ServerConfig,
+    ) -> Result<Self> {
+        let listener = Listener::Tcp(vec![socket::bind_any(address, &config)?]);
+        let mut server = Self::with_listener(log, engine, listener)?;
+        server.config = config;
+        Ok(server)
+    }
+
+    /// Start a server listening on `port` on every interface, over both IPv6 and IPv4.
+    ///
+    /// Where the IPv6 socket also accepts IPv4 connections (the default on Linux), it's the only
+    /// socket. Otherwise a separate IPv4 socket is bound on the same port. If IPv6 isn't available
+    /// at all, the server listens over IPv4 alone.
+    ///
+    /// A `port` of 0 picks a free port, which [`local_addrs`](#method.local_addrs) reports.
+    pub fn start_dual_stack(log: slog::Logger, engine: E, port: u16) -> Result<Self> {
+        Self::start_dual_stack_with_config(log, engine, port, ServerConfig::default())
+    }
+
+    /// Start a server listening on `port` on every interface, over both IPv6 and IPv4 (see
+    /// [`start_dual_stack`](#method.start_dual_stack)), with the socket options in `config`.
+    pub fn start_dual_stack_with_config(
+        log: slog::Logger,
+        engine: E,
+        port: u16,
+        config: ServerConfig,
+    ) -> Result<Self> {
+        let mut listeners = Vec::new();
+        let ipv6_address = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+        let ipv4_port = match socket::bind(ipv6_address, &config) {
+            Ok(listener) => {
+                let port = listener.local_addr()?.port();
+                listeners.push(listener);
+                port
+            },
+            Err(error) => {
+                warn!(log, "Listening over IPv4 only: {}", error);
+                port
+            },
+        };
+        let ipv6 = !listeners.is_empty();
+        match socket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, ipv4_port)), &config) {
+            Ok(listener) => listeners.push(listener),
+            // The IPv6 socket already accepts IPv4 connections.
+            Err(ref error) if ipv6 && error.kind() == io::ErrorKind::AddrInUse => {},
+            Err(error) => return Err(error.into()),
+        }
+        if listeners.len() > 1 {
+            for listener in &listeners {
+                listener.set_nonblocking(true)?;
+            }
+        }
+        let mut server = Self::with_listener(log, engine, Listener::Tcp(listeners))?;
+        server.config = config;
+        Ok(server)
+    }
+
+    /// Start a server that's only reachable in-process, returning a [`Client`] connected to it.
+    ///
+    /// Requests and responses are encoded just as they would be over TCP, but are passed over
+    /// channels rather than sockets. The server must be [`run`](#method.run) on another thread,
+    /// and its [`local_addr`](#method.local_addr) is an error.
+    ///
+    /// ```
+    /// use std::thread;
+    /// use kvs::{MemoryKvStore, Result, Server};
+    ///
+    /// # fn check() -> Result<()> {
+    /// let log = slog::Logger::root(slog::Discard, slog::o!());
+    /// let (mut server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    /// thread::spawn(move || server.run());
+    /// client.set("key".to_owned(), "value".to_owned())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn start_in_process(log: slog::Logger, engine: E) -> Result<(Self, Client)> {
+        let (listener, connector) = channel::listener();
+        let server = Self::with_listener(log, engine, Listener::InProcess(listener))?;
+        Ok((server, Client::in_process(connector)?))
+    }
+
+    fn with_listener(log: slog::Logger, mut engine: E, listener: Listener) -> Result<Self> {
+        let mut system_keys = engine.system_keys();
+        system_keys.init()?;
+        let store_id = system_keys.store_id()?.unwrap_or_default();
+        let lifetime = Lifetime::load(&mut engine)?;
+
+        info!(log, "Starting server"; "store_id" => &store_id);
         Ok(Server {
             log,
             engine,
-            listener: TcpListener::bind(address)?,
+            listener,
+            started: Instant::now(),
+            store_id,
+            stats: Stats::default(),
+            hot_prefixes: TopK::new(HOT_PREFIXES_TRACKED),
+            hot_keys: TopK::new(HOT_KEYS_TRACKED),
+            distinct_keys: DistinctCount::new(),
+            sampler: None,
+            key_rules: KeyRules::default(),
+            tenants: None,
+            sweeper: None,
+            scrubber: None,
+            transactions: Transactions::new(),
+            warmup: None,
+            chaos: None,
+            mirror: None,
+            interceptors: Vec::new(),
+            metrics: Arc::new(NoopMetrics),
+            lifetime,
+            maintenance: None,
+            config: ServerConfig::default(),
+            pending: Vec::new(),
+            request_timeout: None,
+            watchers: Watchers::new(),
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            connections: 0,
         })
     }
 
+    /// Capture a sample of requests to a file using the given `sampler`.
+    pub fn with_sampler(mut self, sampler: Sampler) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    /// Reject requests with keys that break the given `rules`.
+    pub fn with_key_rules(mut self, rules: KeyRules) -> Self {
+        self.key_rules = rules;
+        self
+    }
+
+    /// Track each tenant's usage and enforce their quotas using `tenants`.
+    pub fn with_tenants(mut self, tenants: Tenants) -> Self {
+        self.tenants = Some(tenants);
+        self
+    }
+
+    /// Remove expired keys from the engine in the background using `sweeper`.
+    ///
+    /// The listener is switched to non-blocking mode so that sweeps still run while the server is
+    /// idle.
+    pub fn with_sweeper(mut self, sweeper: Sweeper) -> Result<Self> {
+        self.set_nonblocking(true)?;
+        self.sweeper = Some(sweeper);
+        Ok(self)
+    }
+
+    /// Verify the engine's stored entries in the background using `scrubber`, logging any that are
+    /// found to be corrupt.
+    ///
+    /// The listener is switched to non-blocking mode so that scrubs still run while the server is
+    /// idle.
+    pub fn with_scrubber(mut self, scrubber: Scrubber) -> Result<Self> {
+        self.set_nonblocking(true)?;
+        self.scrubber = Some(scrubber);
+        Ok(self)
+    }
+
+    /// Prefetch the keys that were hottest before the server last stopped using `warmup`, and
+    /// record the hottest keys for next time.
+    ///
+    /// The listener is switched to non-blocking mode so that warm-up still runs while the server
+    /// is idle.
+    pub fn with_warmup(mut self, mut warmup: Warmup) -> Result<Self> {
+        self.set_nonblocking(true)?;
+        let keys = warmup.load(&mut self.engine)?;
+        if keys > 0 {
+            info!(self.log, "Warming up"; "keys" => keys);
+        }
+        self.warmup = Some(warmup);
+        Ok(self)
+    }
+
+    /// Inject artificial latency and faults into requests using `chaos`.
+    ///
+    /// This is for testing clients, and should never be used in production.
+    pub fn with_chaos(mut self, chaos: Chaos) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Mirror a sample of requests to a secondary server using `mirror`, logging any differences
+    /// between its responses and this server's.
+    pub fn with_mirror(mut self, mirror: Mirror) -> Self {
+        self.mirror = Some(mirror);
+        self
+    }
+
+    /// Call `interceptor`'s hooks with every request and response, after those of any interceptors
+    /// added before it (see [`RequestInterceptor`]).
+    ///
+    /// [`RequestInterceptor`]: trait.RequestInterceptor.html
+    pub fn with_interceptor<I: RequestInterceptor + 'static>(mut self, interceptor: I) -> Self {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// Report request counts and latencies to `metrics`.
+    ///
+    /// See [`MetricsSink`] for the metrics reported.
+    ///
+    /// [`MetricsSink`]: trait.MetricsSink.html
+    pub fn with_metrics<M: MetricsSink + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = Arc::new(metrics);
+        self
+    }
+
+    /// Record the store-lifetime statistics (see [`Stats::lifetime`]) every `interval`, rather
+    /// than every 10 seconds.
+    ///
+    /// Statistics are recorded between connections, so they're only as fresh as the last
+    /// connection after each interval.
+    ///
+    /// [`Stats::lifetime`]: struct.Stats.html#structfield.lifetime
+    pub fn with_stats_save_interval(mut self, interval: Duration) -> Self {
+        self.lifetime.set_save_interval(interval);
+        self
+    }
+
+    /// Fail requests that spend longer than `timeout` in the engine with [`Error::Timeout`].
+    ///
+    /// Scans are abandoned once the timeout passes. Other requests can't be interrupted, so they
+    /// run to completion and then fail, even though writes will have been applied.
+    ///
+    /// [`Error::Timeout`]: enum.Error.html#variant.Timeout
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Send watching connections a heartbeat after `interval` (5 seconds by default) without any
+    /// changes, so that clients can tell the connection is alive, and connections to clients that
+    /// have gone are dropped.
+    pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// The address the server is listening on.
+    ///
+    /// If the server is listening on more than one address, this is the first (see
+    /// [`local_addrs`](#method.local_addrs)).
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local_addrs()?[0])
+    }
+
+    /// Every address the server is listening on.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        match self.listener {
+            Listener::Tcp(ref listeners) => {
+                Ok(listeners.iter().map(TcpListener::local_addr).collect::<io::Result<_>>()?)
+            },
+            Listener::InProcess(_) => {
+                let message = "in-process servers have no address";
+                Err(io::Error::new(io::ErrorKind::AddrNotAvailable, message).into())
+            },
+        }
+    }
+
+    /// Switch the server's sockets to (or from) non-blocking mode, so that background work and
+    /// pending connections still progress while no new connections arrive.
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        if let Listener::Tcp(ref listeners) = self.listener {
+            for listener in listeners {
+                listener.set_nonblocking(nonblocking)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the server's sockets are always non-blocking, regardless of pending connections.
+    fn is_polling(&self) -> bool {
+        match self.listener {
+            Listener::Tcp(ref listeners) => {
+                listeners.len() > 1
+                    || self.sweeper.is_some()
+                    || self.scrubber.is_some()
+                    || self.warmup.is_some()
+                    || !self.watchers.is_empty()
+            },
+            Listener::InProcess(_) => false,
+        }
+    }
+
     /// Run the server, accepting connections forever.
     pub fn run(&mut self) -> ! {
         loop {
-            let connection = self.listener.accept();
-            match connection {
-                Ok((stream, peer_addr)) => {
-                    let log = self.log.new(o!("peer_addr" => peer_addr));
-                    if let Err(error) = self.handle_stream(log, stream) {
+            let polling = self.is_polling();
+            match self.accept() {
+                Ok(Some((connection, address))) => {
+                    self.connections += 1;
+                    let peer = Peer::new(self.connections, address);
+                    let peer_addr = peer.to_string();
+                    let log = peer_log(&self.log, &peer);
+                    let result = match connection {
+                        Connection::Tcp(stream) => {
+                            self.start_pending(Pending::new(stream, peer, log))
+                        },
+                        Connection::InProcess(stream) => self.handle_stream(log, peer, stream),
+                    };
+                    if let Err(error) = result {
                         warn!(self.log, "Connection error: {}", error; "peer_addr" => peer_addr);
                     }
                 },
+                Ok(None) => {},
                 Err(error) => {
                     warn!(self.log, "Failed connection due to: {}", error);
                 }
             }
+            self.poll_pending();
+            self.run_background();
+
+            // Watchers come and go while the server runs, so it may need to start or stop polling.
+            let now_polling = self.is_polling();
+            if now_polling != polling && self.pending.is_empty() {
+                if let Err(error) = self.set_nonblocking(now_polling) {
+                    warn!(self.log, "Failed to switch listener blocking mode: {}", error);
+                }
+            }
         }
     }
 
-    fn handle_stream(&mut self, log: slog::Logger, mut stream: TcpStream) -> Result<()> {
+    /// Run whatever background work is due: sweeps, scrubs, warm-up, saving lifetime statistics,
+    /// maintenance and heartbeats.
+    pub(crate) fn run_background(&mut self) {
+        self.sweep();
+        self.scrub();
+        self.warm_up();
+        self.save_lifetime();
+        self.maintain();
+        self.heartbeat();
+    }
+
+    /// Take the server's TCP listeners, to accept connections from them elsewhere (e.g. on an
+    /// asynchronous runtime). Fails for in-process servers.
+    pub(crate) fn take_listeners(&mut self) -> Result<Vec<TcpListener>> {
+        match self.listener {
+            Listener::Tcp(ref mut listeners) => Ok(std::mem::take(listeners)),
+            Listener::InProcess(_) => {
+                let message = "in-process servers have no listeners";
+                Err(io::Error::new(io::ErrorKind::AddrNotAvailable, message).into())
+            },
+        }
+    }
+
+    /// The server's logger.
+    pub(crate) fn log(&self) -> &slog::Logger {
+        &self.log
+    }
+
+    /// The server's socket options.
+    pub(crate) fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Wait for the next connection, returning it with the peer's address (if it has one).
+    ///
+    /// When a [`Sweeper`], [`Scrubber`] or [`Warmup`] is configured, or the server listens on more
+    /// than one address, this gives up after [`POLL_INTERVAL`], returning `None`. While requests
+    /// are still being received from pending connections, it returns `None` straight away.
+    fn accept(&self) -> Result<Option<(Connection, Option<SocketAddr>)>> {
+        match self.listener {
+            Listener::Tcp(ref listeners) => {
+                for listener in listeners {
+                    match listener.accept() {
+                        Ok((stream, peer_addr)) => {
+                            stream.set_nonblocking(true)?;
+                            stream.set_nodelay(self.config.nodelay)?;
+                            return Ok(Some((Connection::Tcp(stream), Some(peer_addr))));
+                        },
+                        Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => {},
+                        Err(error) => return Err(error.into()),
+                    }
+                }
+                if self.pending.is_empty() {
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Ok(None)
+            },
+            Listener::InProcess(ref listener) => {
+                let polling = self.sweeper.is_some()
+                    || self.scrubber.is_some()
+                    || self.warmup.is_some()
+                    || !self.watchers.is_empty();
+                let timeout = if polling { Some(POLL_INTERVAL) } else { None };
+                let stream = listener.accept(timeout);
+                Ok(stream.map(|stream| (Connection::InProcess(stream), None)))
+            },
+        }
+    }
+
+    /// Remove expired keys if a sweep is due, logging an event for each and sending its removal to
+    /// the connections watching it.
+    fn sweep(&mut self) {
+        let sweeper = match self.sweeper.as_mut() {
+            Some(sweeper) => sweeper,
+            None => return,
+        };
+        match sweeper.sweep_if_due(&mut self.engine) {
+            Ok(expired) => {
+                for key in expired {
+                    self.transactions.record(&key);
+                    info!(self.log, "Key expired"; "key" => &key);
+                    self.watchers.removed(key);
+                }
+            },
+            Err(error) => warn!(self.log, "Failed to sweep expired keys: {}", error),
+        }
+    }
+
+    /// Verify stored entries if a scrub is due, logging an event for each corrupt one.
+    ///
+    /// There's nowhere to repair corrupt entries from, so they're left for an operator to restore
+    /// (e.g. from a backup).
+    fn scrub(&mut self) {
+        let scrubber = match self.scrubber.as_mut() {
+            Some(scrubber) => scrubber,
+            None => return,
+        };
+        match scrubber.scrub_if_due(&mut self.engine) {
+            Ok(corrupt) => {
+                for key in corrupt {
+                    warn!(self.log, "Corrupt entry found by scrub"; "key" => key);
+                }
+            },
+            Err(error) => warn!(self.log, "Failed to scrub entries: {}", error),
+        }
+    }
+
+    /// Prefetch warm-up keys if any are due, and record the hottest keys if a save is due.
+    fn warm_up(&mut self) {
+        let warmup = match self.warmup.as_mut() {
+            Some(warmup) => warmup,
+            None => return,
+        };
+        if !warmup.is_done() {
+            match warmup.prefetch(&mut self.engine) {
+                Ok(prefetched) => {
+                    self.stats.prefetched_keys += prefetched as u64;
+                    if prefetched > 0 && warmup.is_done() {
+                        info!(self.log, "Warm-up complete"; "keys" => self.stats.prefetched_keys);
+                    }
+                },
+                Err(error) => warn!(self.log, "Failed to prefetch keys: {}", error),
+            }
+        }
+        if warmup.is_save_due() {
+            let hot_keys = self.hot_keys.top(warmup.max_keys()).into_iter().map(|(key, _)| key);
+            if let Err(error) = warmup.save(&mut self.engine, hot_keys.collect()) {
+                warn!(self.log, "Failed to record hot keys: {}", error);
+            }
+        }
+    }
+
+    /// Run the engine's maintenance tasks if maintenance mode has just started, and leave
+    /// maintenance mode once it's over.
+    fn maintain(&mut self) {
+        let maintenance = match self.maintenance.as_mut() {
+            Some(maintenance) => maintenance,
+            None => return,
+        };
+        if maintenance.take_tasks() {
+            let start = Instant::now();
+            match self.engine.maintain() {
+                Ok(()) => {
+                    let elapsed_ms = start.elapsed().as_millis() as u64;
+                    info!(self.log, "Maintenance complete"; "elapsed_ms" => elapsed_ms);
+                },
+                Err(error) => warn!(self.log, "Maintenance failed: {}", error),
+            }
+        }
+        if maintenance.remaining().is_none() {
+            info!(self.log, "Leaving maintenance mode");
+            self.maintenance = None;
+        }
+    }
+
+    /// Send heartbeats to watching connections that have been idle for the heartbeat interval.
+    fn heartbeat(&mut self) {
+        if !self.watchers.is_empty() {
+            self.watchers.heartbeat(self.heartbeat_interval);
+        }
+    }
+
+    /// Record the store-lifetime statistics if a save is due.
+    fn save_lifetime(&mut self) {
+        if !self.lifetime.is_save_due() {
+            return;
+        }
+        let result = match self.engine.stats() {
+            Ok(engine) => {
+                let totals = self.lifetime.totals(&self.stats, &engine);
+                self.lifetime.save(&mut self.engine, &totals)
+            },
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            warn!(self.log, "Failed to record lifetime statistics: {}", error);
+        }
+    }
+
+    /// Start receiving the request from a newly accepted TCP connection, handling it straight away
+    /// if it's already been received in full.
+    fn start_pending(&mut self, mut pending: Pending) -> Result<()> {
+        debug!(pending.log, "Client connected");
+        match pending.poll(self.config.buffer_size) {
+            Ok(None) => {
+                if self.pending.is_empty() && !self.is_polling() {
+                    self.set_nonblocking(true)?;
+                }
+                self.pending.push(pending);
+                Ok(())
+            },
+            Ok(Some(request)) => self.finish_pending(pending, Ok(request)),
+            Err(error) => self.finish_pending(pending, Err(error)),
+        }
+    }
+
+    /// Read the next part of each pending connection's request, handling those that have been
+    /// received in full.
+    fn poll_pending(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut progressed = false;
+        let mut i = 0;
+        while i < self.pending.len() {
+            let received = self.pending[i].received();
+            let result = match self.pending[i].poll(self.config.buffer_size) {
+                Ok(None) => {
+                    progressed |= self.pending[i].received() != received;
+                    i += 1;
+                    continue;
+                },
+                Ok(Some(request)) => Ok(request),
+                Err(error) => Err(error),
+            };
+            progressed = true;
+            let pending = self.pending.remove(i);
+            let peer_addr = pending.peer.to_string();
+            if let Err(error) = self.finish_pending(pending, result) {
+                warn!(self.log, "Connection error: {}", error; "peer_addr" => peer_addr);
+            }
+        }
+
+        if self.pending.is_empty() && !self.is_polling() {
+            if let Err(error) = self.set_nonblocking(false) {
+                warn!(self.log, "Failed to make listener blocking: {}", error);
+            }
+        }
+        if !progressed {
+            thread::sleep(PENDING_POLL_INTERVAL);
+        }
+    }
+
+    /// Handle the request received from a pending connection.
+    fn finish_pending(&mut self, pending: Pending, request: Result<Request>) -> Result<()> {
+        self.handle_received(pending.log, pending.peer, pending.stream, request)
+    }
+
+    /// Respond to a request received in full from a TCP connection, or to the error from
+    /// receiving it.
+    pub(crate) fn handle_received(
+        &mut self,
+        log: slog::Logger,
+        peer: Peer,
+        stream: TcpStream,
+        request: Result<Request>,
+    ) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        if let Ok(Request::Watch { .. }) = request {
+            stream.set_write_timeout(Some(WATCHER_WRITE_TIMEOUT))?;
+        }
+        self.handle_decoded(log, peer, stream, request)
+    }
+
+    fn handle_stream<S: Read + Write + Send + 'static>(
+        &mut self,
+        log: slog::Logger,
+        peer: Peer,
+        mut stream: S,
+    ) -> Result<()> {
         debug!(log, "Client connected");
 
-        let request = match read_mp(&stream) {
+        let reader = BufReader::with_capacity(self.config.buffer_size, &mut stream);
+        let request = decode_request(reader);
+        self.handle_decoded(log, peer, stream, request)
+    }
+
+    /// Respond to a request read from `stream` by `peer`, or to the error from decoding it.
+    fn handle_decoded<S: Write + Send + 'static>(
+        &mut self,
+        log: slog::Logger,
+        peer: Peer,
+        mut stream: S,
+        request: Result<Request>,
+    ) -> Result<()> {
+        let request = match request {
             Ok(request) => request,
             Err(error) => {
                 warn!(log, "Invalid request: {}", error);
-                let response: Response = error.into();
-                write_mp(&mut stream, &response)?;
+                let response = Response::try_from(error)?;
+                self.respond(&mut stream, &response)?;
                 return Ok(())
             }
         };
 
-        match self.handle_request(request) {
-            Ok(response) => write_mp(&mut stream, &response)?,
-            Err(error) => write_mp(&mut stream, &Response::try_from(error)?)?,
+        match self.chaos.as_ref().and_then(Chaos::inject) {
+            Some(Fault::Drop) => {
+                debug!(log, "Dropping connection (chaos)");
+                return Ok(());
+            },
+            Some(Fault::Error) => {
+                debug!(log, "Injecting error (chaos)");
+                let response = Response::Err {
+                    kind: ErrorKind::EngineError,
+                    message: "Injected error (chaos mode)".to_owned(),
+                };
+                self.respond(&mut stream, &response)?;
+                return Ok(());
+            },
+            None => {},
+        }
+
+        let request = match self.intercept(&peer, request) {
+            Ok(request) => request,
+            Err(response) => {
+                debug!(log, "Request intercepted");
+                self.respond(&mut stream, &response)?;
+                return Ok(());
+            },
         };
 
+        let watch = match request {
+            Request::Watch { ref prefix } => Some(prefix.clone()),
+            _ => None,
+        };
+        let mirrored = match &self.mirror {
+            Some(mirror) if mirror.sample(&request) => Some(request.clone()),
+            _ => None,
+        };
+        let mut response = match self.handle_request(&peer, request) {
+            Ok(response) => response,
+            Err(error) => Response::try_from(error)?,
+        };
+        for interceptor in self.interceptors.iter_mut().rev() {
+            response = interceptor.after(&peer, response);
+        }
+        self.respond(&mut stream, &response)?;
+        if let (Some(mirror), Some(request)) = (self.mirror.as_mut(), mirrored) {
+            mirror.send(&log, request, &response);
+        }
+
+        if let (Some(prefix), Response::Ok) = (watch, response) {
+            debug!(log, "Watching for changes"; "prefix" => &prefix);
+            self.watchers.watch(prefix, Box::new(stream), log);
+            return Ok(());
+        }
         debug!(log, "Closing connection");
 
         Ok(())
     }
 
-    fn handle_request(&mut self, request: Request) -> Result<Response> {
+    /// Pass a request through each interceptor's `before` hook, returning the request to handle, or
+    /// the response an interceptor returned in its place.
+    fn intercept(
+        &mut self,
+        peer: &Peer,
+        mut request: Request,
+    ) -> std::result::Result<Request, Response> {
+        for interceptor in &mut self.interceptors {
+            request = interceptor.before(peer, request)?;
+        }
+        Ok(request)
+    }
+
+    /// Write `response` to `stream`, buffering it so that it's sent in as few packets as possible.
+    fn respond<S: Write>(&self, stream: &mut S, response: &Response) -> Result<()> {
+        let mut writer = BufWriter::with_capacity(self.config.buffer_size, stream);
+        encode_response(&mut writer, response)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn handle_request(&mut self, peer: &Peer, request: Request) -> Result<Response> {
+        for key in request.keys() {
+            let key = String::from_utf8_lossy(key);
+            self.hot_prefixes.observe(key_prefix(&key));
+            self.hot_keys.observe(&key);
+        }
+
+        let start = Instant::now();
+        let kind = request.kind();
+        let summary = self.sampler.as_ref().map(|_| RequestSummary::new(&request, peer));
+        let deadline = self.request_timeout.map(|timeout| start + timeout);
+        let watched = self.watchers.changes(&request);
+        // Commits record their own writes, and only if they succeed.
+        let written: Vec<Vec<u8>> = match kind {
+            RequestKind::Set | RequestKind::Remove
+                if !self.transactions.is_empty() && !matches!(request, Request::Commit { .. }) =>
+            {
+                request.keys().into_iter().map(<[u8]>::to_vec).collect()
+            },
+            _ => Vec::new(),
+        };
+        let response = self.check_request(&request).and_then(|change| {
+            let response = self.dispatch(request, deadline)?;
+            if let (Some(tenants), Some(change)) = (self.tenants.as_mut(), change) {
+                tenants.apply(change, &mut self.engine)?;
+            }
+            Ok(response)
+        });
+        // Even a failed batch may have applied some of its writes, so they all count.
+        for key in &written {
+            self.transactions.record(key);
+        }
+        let elapsed = start.elapsed();
+        if let (RequestKind::Set | RequestKind::Remove, Ok(Response::Ok)) = (kind, &response) {
+            for change in watched {
+                self.watchers.applied(change);
+            }
+        }
+        // Scans check the deadline as they go, and other admin requests aren't subject to it.
+        let timed_out =
+            kind != RequestKind::Admin && deadline.is_some_and(|at| Instant::now() > at);
+        let response = match response {
+            Ok(_) if timed_out => Err(Error::Timeout),
+            response => response,
+        };
+        match response {
+            Err(Error::Timeout) => {
+                warn!(self.log, "Request timed out";
+                    "peer_addr" => peer.to_string(),
+                    "connection_id" => peer.connection_id,
+                    "elapsed_ms" => elapsed.as_millis() as u64);
+            },
+            Err(ref error @ Error::InvalidKey(_))
+            | Err(ref error @ Error::KeyQuotaExceeded(_))
+            | Err(ref error @ Error::ByteQuotaExceeded(_))
+            | Err(ref error @ Error::RateLimited(_)) => {
+                info!(self.log, "Request rejected: {}", error;
+                    "peer_addr" => peer.to_string(),
+                    "connection_id" => peer.connection_id);
+            },
+            _ => {},
+        }
+
+        if let (Some(sampler), Some(summary)) = (self.sampler.as_mut(), summary) {
+            if let Err(error) = sampler.record(summary, &response, elapsed) {
+                warn!(self.log, "Failed to capture request sample: {}", error);
+            }
+        }
+
+        let is_err = matches!(response, Ok(Response::Err { .. }) | Err(_));
+        let (op_stats, [requests, errors, seconds]) = match kind {
+            RequestKind::Get => (&mut self.stats.gets, GET_METRICS),
+            RequestKind::Set => (&mut self.stats.sets, SET_METRICS),
+            RequestKind::Remove => (&mut self.stats.removes, REMOVE_METRICS),
+            RequestKind::Admin => return response,
+        };
+        op_stats.record(elapsed, is_err);
+        self.metrics.counter(requests, 1);
+        if is_err {
+            self.metrics.counter(errors, 1);
+        }
+        self.metrics.histogram(seconds, elapsed.as_secs_f64());
+        response
+    }
+
+    /// Check that a request's key (if any) is allowed by the key rules, that it doesn't modify
+    /// the system keyspace, and that it's within its tenant's quotas.
+    ///
+    /// Returns the change in the tenant's usage to apply if the request succeeds.
+    fn check_request(&mut self, request: &Request) -> Result<Option<UsageChange>> {
+        for key in request.keys() {
+            self.key_rules.check(key)?;
+            if request.kind() != RequestKind::Get && key.starts_with(SYSTEM_KEY_PREFIX.as_bytes()) {
+                return Err(Error::InvalidKey(format!(
+                    "key is in the reserved system keyspace {:?}",
+                    SYSTEM_KEY_PREFIX
+                )));
+            }
+        }
+        if let Request::RemovePrefix { prefix } = request {
+            if prefix.starts_with(SYSTEM_KEY_PREFIX) {
+                return Err(Error::InvalidKey(format!(
+                    "prefix is in the reserved system keyspace {:?}",
+                    SYSTEM_KEY_PREFIX
+                )));
+            }
+        }
+        if let RequestKind::Set | RequestKind::Remove = request.kind() {
+            let remaining = self.maintenance.as_ref().and_then(Maintenance::remaining);
+            if let Some(retry_after) = remaining {
+                return Err(Error::Maintenance(retry_after));
+            }
+        }
+        match self.tenants.as_mut() {
+            Some(tenants) => tenants.check(request, &mut self.engine),
+            None => Ok(None),
+        }
+    }
+
+    fn dispatch(&mut self, request: Request, deadline: Option<Instant>) -> Result<Response> {
         match request {
             Request::Get { key } => {
-                Ok(self.engine.get(key)?
-                    .map(|value| Response::Found { value })
-                    .unwrap_or(Response::NotFound))
+                Ok(match self.engine.lookup(key)? {
+                    Ok(value) => Response::Found { value },
+                    Err(reason) => Response::NotFound { reason },
+                })
             },
-            Request::Set { key, value } => {
-                self.engine.set(key, value)?;
+            Request::Set { key, value, ttl_ms, durability } => {
+                let (key_length, value_size) = (key.len() as u64, value.len() as u64);
+                self.distinct_keys.observe(&key);
+                match ttl_ms {
+                    Some(ttl_ms) => {
+                        self.engine.set_with_ttl(key, value, Duration::from_millis(ttl_ms))?
+                    },
+                    None => self.engine.set(key, value)?,
+                }
+                if durability == Durability::Fsynced {
+                    self.engine.sync()?;
+                }
+                self.stats.key_lengths.record(key_length);
+                self.stats.value_sizes.record(value_size);
                 Ok(Response::Ok)
             },
             Request::Remove { key } => {
                 self.engine.remove(key)?;
                 Ok(Response::Ok)
-            }
+            },
+            Request::Scan { prefix } => {
+                let entries = match deadline {
+                    Some(deadline) => self.engine.scan_until(&prefix, deadline)?,
+                    None => self.engine.scan(&prefix)?,
+                };
+                Ok(Response::Entries { entries })
+            },
+            Request::ScanRange { start, end } => {
+                let entries = self.engine.scan_range(&start, end.as_deref())?;
+                Ok(Response::Entries { entries })
+            },
+            Request::GetBytes { key } => {
+                Ok(match self.engine.get_bytes(&key)? {
+                    Some(value) => Response::FoundBytes { value },
+                    None => Response::NotFound { reason: None },
+                })
+            },
+            Request::SetBytes { key, value, durability } => {
+                self.distinct_keys.observe(&String::from_utf8_lossy(&key));
+                self.engine.set_bytes(&key, &value)?;
+                if durability == Durability::Fsynced {
+                    self.engine.sync()?;
+                }
+                self.stats.key_lengths.record(key.len() as u64);
+                self.stats.value_sizes.record(value.len() as u64);
+                Ok(Response::Ok)
+            },
+            Request::RemoveBytes { key } => {
+                self.engine.remove_bytes(&key)?;
+                Ok(Response::Ok)
+            },
+            Request::ScanBytes { prefix } => {
+                Ok(Response::ByteEntries { entries: self.engine.scan_bytes(&prefix)? })
+            },
+            Request::MultiGet { keys } => {
+                Ok(Response::Values { values: self.engine.get_many(keys)? })
+            },
+            Request::MultiSet { pairs, durability } => {
+                let sizes: Vec<_> = pairs
+                    .iter()
+                    .map(|(key, value)| {
+                        self.distinct_keys.observe(key);
+                        (key.len() as u64, value.len() as u64)
+                    })
+                    .collect();
+                self.engine.set_many(pairs)?;
+                if durability == Durability::Fsynced {
+                    self.engine.sync()?;
+                }
+                for (key_length, value_size) in sizes {
+                    self.stats.key_lengths.record(key_length);
+                    self.stats.value_sizes.record(value_size);
+                }
+                Ok(Response::Ok)
+            },
+            Request::MultiRemove { keys } => {
+                let removed = self.engine.remove_many(keys)?;
+                let count = removed.len() as u64;
+                for key in removed {
+                    self.watchers.removed(key);
+                }
+                Ok(Response::Count { count })
+            },
+            Request::Stats => {
+                let engine = self.engine.stats()?;
+                let stats = Stats {
+                    store_id: self.store_id.clone(),
+                    uptime_secs: self.started.elapsed().as_secs(),
+                    hot_prefixes: self.hot_prefixes.top(HOT_PREFIXES_REPORTED),
+                    distinct_keys_set: self.distinct_keys.estimate(),
+                    lifetime: self.lifetime.totals(&self.stats, &engine),
+                    engine,
+                    ..self.stats.clone()
+                };
+                Ok(Response::Stats { stats: Box::new(stats) })
+            },
+            Request::HotKeys { limit } => {
+                let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+                Ok(Response::HotKeys { keys: self.hot_keys.top(limit) })
+            },
+            Request::Tenants => {
+                let tenants = match self.tenants.as_mut() {
+                    Some(tenants) => tenants.usage(&mut self.engine)?,
+                    None => Vec::new(),
+                };
+                Ok(Response::Tenants { tenants })
+            },
+            Request::Info => {
+                let features = [
+                    ("chaos", self.chaos.is_some()),
+                    ("sampler", self.sampler.is_some()),
+                    ("scrubber", self.scrubber.is_some()),
+                    ("sweeper", self.sweeper.is_some()),
+                    ("tenants", self.tenants.is_some()),
+                    ("warmup", self.warmup.is_some()),
+                ];
+                let info = ServerInfo {
+                    version: env!("CARGO_PKG_VERSION").to_owned(),
+                    protocol_version: PROTOCOL_VERSION,
+                    engine: self.engine.name().to_owned(),
+                    features: features
+                        .iter()
+                        .filter(|(_, enabled)| *enabled)
+                        .map(|(feature, _)| (*feature).to_owned())
+                        .collect(),
+                    store_id: self.store_id.clone(),
+                };
+                Ok(Response::Info { info })
+            },
+            Request::SampleKeys { count } => {
+                let count = usize::try_from(count).unwrap_or(usize::MAX);
+                Ok(Response::Keys { keys: self.engine.sample_keys(count)? })
+            },
+            // The connection starts watching once the response has been sent.
+            Request::Watch { .. } => Ok(Response::Ok),
+            Request::Ping => Ok(Response::Pong),
+            Request::ColdKeys { prefix, unread_for_secs, limit } => {
+                let unread_for = Duration::from_secs(unread_for_secs);
+                let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+                Ok(Response::Keys { keys: self.engine.cold_keys(&prefix, unread_for, limit)? })
+            },
+            Request::CountPrefix { prefix } => {
+                Ok(Response::Count { count: self.engine.count_prefix(&prefix)? })
+            },
+            Request::RemovePrefix { prefix } => {
+                let removed = self.engine.remove_prefix(&prefix)?;
+                let count = removed.len() as u64;
+                for key in removed {
+                    self.transactions.record(&key);
+                    self.watchers.removed(key);
+                }
+                Ok(Response::Count { count })
+            },
+            Request::CompareAndSwap { key, expected, new } => {
+                let (key_length, value_size) = (key.len() as u64, new.len() as u64);
+                self.distinct_keys.observe(&key);
+                let swapped = self.engine.compare_and_swap(key.clone(), expected, new.clone())?;
+                if swapped {
+                    self.stats.key_lengths.record(key_length);
+                    self.stats.value_sizes.record(value_size);
+                    self.watchers.set(key, new);
+                }
+                Ok(Response::Swapped { swapped })
+            },
+            Request::Increment { key, delta } => {
+                self.distinct_keys.observe(&key);
+                let value = self.engine.increment(key.clone(), delta)?;
+                self.watchers.set(key, value.to_string());
+                Ok(Response::Counter { value })
+            },
+            Request::Rename { from, to, overwrite } => {
+                // The value is only needed to tell watchers of the new key about it.
+                let value = if self.watchers.is_watched(to.as_bytes()) {
+                    self.engine.get(from.clone())?
+                } else {
+                    None
+                };
+                let renamed = self.engine.rename(from.clone(), to.clone(), overwrite)?;
+                if renamed && from != to {
+                    self.distinct_keys.observe(&to);
+                    self.watchers.removed(from);
+                    self.watchers.set(to, value.unwrap_or_default());
+                }
+                Ok(Response::Renamed { renamed })
+            },
+            Request::GetIfModified { key, version } => {
+                Ok(match self.engine.get_if_modified(key, version)? {
+                    IfModified::NotModified => Response::NotModified,
+                    IfModified::Modified(Some(value)) => Response::Found { value },
+                    IfModified::Modified(None) => Response::NotFound { reason: None },
+                })
+            },
+            Request::Ttl { key } => {
+                let ttl = self.engine.ttl(key)?;
+                Ok(Response::Ttl { ttl_ms: ttl.map(|ttl| ttl.as_millis() as u64) })
+            },
+            Request::Expire { key, ttl_ms } => {
+                self.engine.expire(key, Duration::from_millis(ttl_ms))?;
+                Ok(Response::Ok)
+            },
+            Request::Persist { key } => {
+                self.engine.persist(key)?;
+                Ok(Response::Ok)
+            },
+            Request::ForceRemove { key } => {
+                self.engine.force_remove(key)?;
+                Ok(Response::Ok)
+            },
+            Request::PutCas { value } => {
+                let key = content_key(&value);
+                self.distinct_keys.observe(&key);
+                let deduplicated = self.engine.get(key.clone())?.is_some();
+                if !deduplicated {
+                    self.stats.key_lengths.record(key.len() as u64);
+                    self.stats.value_sizes.record(value.len() as u64);
+                    self.engine.set(key.clone(), value.clone())?;
+                    self.watchers.set(key.clone(), value);
+                }
+                Ok(Response::Stored { key, deduplicated })
+            },
+            Request::Begin => Ok(Response::Transaction { id: self.transactions.begin() }),
+            Request::TransactionGet { id, key } => {
+                Ok(match self.transactions.get(&mut self.engine, id, key)? {
+                    Ok(value) => Response::Found { value },
+                    Err(reason) => Response::NotFound { reason },
+                })
+            },
+            Request::Commit { id, writes } => {
+                self.transactions.commit(&mut self.engine, id, writes)?;
+                Ok(Response::Ok)
+            },
+            Request::Rollback { id } => {
+                self.transactions.rollback(id);
+                Ok(Response::Ok)
+            },
+            Request::Maintenance { duration_ms } => {
+                if duration_ms == 0 {
+                    info!(self.log, "Leaving maintenance mode");
+                    self.maintenance = None;
+                } else {
+                    info!(self.log, "Entering maintenance mode"; "duration_ms" => duration_ms);
+                    self.maintenance = Some(Maintenance::new(Duration::from_millis(duration_ms)));
+                }
+                Ok(Response::Ok)
+            },
         }
     }
 }
+
+/// A logger for the events of `peer`'s connection.
+pub(crate) fn peer_log(log: &slog::Logger, peer: &Peer) -> slog::Logger {
+    log.new(o!("peer_addr" => peer.to_string(), "connection_id" => peer.connection_id))
+}
+
+/// The prefix of `key` used to group keys for [`Stats::hot_prefixes`].
+///
+/// This is everything up to and including the first `:` or `/`, truncated to [`MAX_PREFIX_LEN`].
+fn key_prefix(key: &str) -> &str {
+    let end = key.find([':', '/']).map(|i| i + 1).unwrap_or_else(|| key.len());
+    let mut end = end.min(MAX_PREFIX_LEN);
+    while !key.is_char_boundary(end) {
+        end -= 1;
+    }
+    &key[..end]
+}
@@ -0,0 +1,162 @@
+use rand::Rng;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+
+/// Configures a [`Chaos`] fault injector.
+///
+/// Configs can be parsed from a comma-separated list of settings, e.g.
+/// `latency=50ms,drop=0.01,error=0.05`. Latencies take an `ms` or `s` suffix, and rates are
+/// fractions of requests between `0.0` and `1.0`. Unspecified settings inject nothing.
+///
+/// ```
+/// use std::time::Duration;
+/// use kvs::ChaosConfig;
+///
+/// let config: ChaosConfig = "latency=50ms,drop=0.01".parse().unwrap();
+/// assert_eq!(config.latency, Duration::from_millis(50));
+/// assert_eq!(config.drop_rate, 0.01);
+/// assert_eq!(config.error_rate, 0.0);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChaosConfig {
+    /// The artificial latency added to every request.
+    pub latency: Duration,
+
+    /// The fraction of connections to close without handling the request or responding.
+    pub drop_rate: f64,
+
+    /// The fraction of requests to fail with an error response, without handling them.
+    pub error_rate: f64,
+}
+
+impl FromStr for ChaosConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut config = ChaosConfig::default();
+        for setting in s.split(',').filter(|setting| !setting.is_empty()) {
+            let (name, value) = setting.split_once('=').ok_or_else(|| {
+                Error::Config(format!("expected SETTING=VALUE, found {:?}", setting))
+            })?;
+            let invalid = || Error::Config(format!("invalid value for {}: {:?}", name, value));
+            match name {
+                "latency" => config.latency = parse_latency(value).ok_or_else(invalid)?,
+                "drop" => config.drop_rate = parse_rate(value).ok_or_else(invalid)?,
+                "error" => config.error_rate = parse_rate(value).ok_or_else(invalid)?,
+                _ => {
+                    return Err(Error::Config(format!(
+                        "unknown chaos setting {:?} (expected latency, drop or error)",
+                        name
+                    )))
+                },
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// A fault to inject into a request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// Close the connection without responding.
+    Drop,
+
+    /// Respond with an error.
+    Error,
+}
+
+/// A queue of faults to inject into a server's next requests, in order.
+///
+/// Clones share the same queue, so a clone can be given to a [`Chaos`] and the original used to
+/// script faults while the server is running.
+#[derive(Clone, Debug, Default)]
+pub struct FaultScript {
+    faults: Arc<Mutex<VecDeque<Fault>>>,
+}
+
+impl FaultScript {
+    /// Construct an empty script.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inject `fault` into the next request that isn't already scripted.
+    pub fn push(&self, fault: Fault) {
+        self.faults.lock().expect("Fault script poisoned").push_back(fault);
+    }
+
+    fn pop(&self) -> Option<Fault> {
+        self.faults.lock().expect("Fault script poisoned").pop_front()
+    }
+}
+
+/// Injects artificial latency and faults into a server's requests.
+///
+/// This is for testing clients (e.g. their retry logic) against a real server, and should never be
+/// enabled in production. Faults are injected before requests are handled, so a dropped or failed
+/// request never reaches the engine.
+pub struct Chaos {
+    config: ChaosConfig,
+    script: FaultScript,
+}
+
+impl Chaos {
+    /// Construct a fault injector with the given configuration.
+    pub fn new(config: ChaosConfig) -> Self {
+        Chaos {
+            config,
+            script: FaultScript::new(),
+        }
+    }
+
+    /// Inject the faults in `script` before any random faults.
+    pub fn with_script(mut self, script: FaultScript) -> Self {
+        self.script = script;
+        self
+    }
+
+    /// Delay the current request by the configured latency, then pick a fault to inject into it,
+    /// if any.
+    pub(crate) fn inject(&self) -> Option<Fault> {
+        if self.config.latency > Duration::from_secs(0) {
+            thread::sleep(self.config.latency);
+        }
+        if let Some(fault) = self.script.pop() {
+            return Some(fault);
+        }
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(self.config.drop_rate.clamp(0.0, 1.0)) {
+            Some(Fault::Drop)
+        } else if rng.gen_bool(self.config.error_rate.clamp(0.0, 1.0)) {
+            Some(Fault::Error)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parse a latency such as `50ms` or `2s`.
+fn parse_latency(value: &str) -> Option<Duration> {
+    if let Some(millis) = value.strip_suffix("ms") {
+        millis.parse().ok().map(Duration::from_millis)
+    } else if let Some(secs) = value.strip_suffix('s') {
+        let secs: f64 = secs.parse().ok()?;
+        if secs.is_finite() && secs >= 0.0 {
+            Some(Duration::from_secs_f64(secs))
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+/// Parse a rate between `0.0` and `1.0`.
+fn parse_rate(value: &str) -> Option<f64> {
+    value.parse().ok().filter(|rate| (0.0..=1.0).contains(rate))
+}
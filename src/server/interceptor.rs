@@ -0,0 +1,60 @@
+use crate::protocol::{Request, Response};
+use super::peer::Peer;
+
+/// Hooks called with every request a [`Server`] handles, to veto or rewrite it before it's
+/// handled, and to inspect or replace its response before it's sent.
+///
+/// This lets a binary embedding a server enforce its own policies (e.g. that every key a client
+/// uses starts with its tenant's prefix) without changing the server. Interceptors are added with
+/// [`Server::with_interceptor`]. Their `before` hooks are called in the order they were added,
+/// each with the request returned by the last, once the request has been decoded but before its
+/// key rules and quotas are checked. Their `after` hooks are called in the reverse order.
+///
+/// ```
+/// use kvs::{ErrorKind, Peer, Request, RequestInterceptor, Response};
+///
+/// struct RequirePrefix(String);
+///
+/// impl RequestInterceptor for RequirePrefix {
+///     fn before(&mut self, _peer: &Peer, request: Request) -> Result<Request, Response> {
+///         let keys = request.keys();
+///         match keys.iter().find(|key| !key.starts_with(self.0.as_bytes())) {
+///             Some(key) => Err(Response::Err {
+///                 kind: ErrorKind::InvalidKey,
+///                 message: format!(
+///                     "key {:?} doesn't start with {:?}",
+///                     String::from_utf8_lossy(key),
+///                     self.0
+///                 ),
+///             }),
+///             None => Ok(request),
+///         }
+///     }
+/// }
+/// ```
+///
+/// [`Server`]: struct.Server.html
+/// [`Server::with_interceptor`]: struct.Server.html#method.with_interceptor
+pub trait RequestInterceptor: Send {
+    /// Inspect a request from `peer` before it's handled, returning the request to handle (as it
+    /// is, or rewritten), or a response to send in its place (e.g. a [`Response::Err`] rejecting
+    /// it).
+    ///
+    /// A response returned here is sent straight away, without calling any `after` hooks. The
+    /// default implementation handles every request as it is.
+    ///
+    /// [`Response::Err`]: enum.Response.html#variant.Err
+    fn before(&mut self, _peer: &Peer, request: Request) -> Result<Request, Response> {
+        Ok(request)
+    }
+
+    /// Inspect the response to a request from `peer` before it's sent, returning the response to
+    /// send (as it is, or replaced).
+    ///
+    /// The server handles one request at a time, so this is always the response to the request
+    /// last passed to [`before`](#method.before). The default implementation sends every response
+    /// as it is.
+    fn after(&mut self, _peer: &Peer, response: Response) -> Response {
+        response
+    }
+}
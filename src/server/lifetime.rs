@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use crate::engine::Engine;
+use crate::error::Result;
+use crate::stats::{EngineStats, LifetimeStats, Stats};
+
+/// The name of the system key holding the store-lifetime statistics.
+const LIFETIME_STATS: &str = "lifetime_stats";
+
+/// How often to record the store-lifetime statistics, by default.
+const DEFAULT_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Tracks statistics over the lifetime of a store, across server restarts.
+///
+/// The totals from previous runs are read from the engine's system keyspace when the server
+/// starts, and the running totals are recorded periodically (every [`DEFAULT_SAVE_INTERVAL`]
+/// unless configured otherwise). Servers run until they're killed, so anything counted since the
+/// last save is lost when the server stops.
+pub(crate) struct Lifetime {
+    previous: LifetimeStats,
+    save_interval: Duration,
+    next_save: Instant,
+}
+
+impl Lifetime {
+    /// Read the totals recorded in `engine` by previous runs.
+    pub(crate) fn load<E: Engine>(engine: &mut E) -> Result<Self> {
+        let previous = match engine.system_keys().get(LIFETIME_STATS)? {
+            Some(stats) => serde_json::from_str(&stats).unwrap_or_default(),
+            None => LifetimeStats::default(),
+        };
+        Ok(Lifetime {
+            previous,
+            save_interval: DEFAULT_SAVE_INTERVAL,
+            next_save: Instant::now() + DEFAULT_SAVE_INTERVAL,
+        })
+    }
+
+    /// Record the totals every `save_interval`.
+    pub(crate) fn set_save_interval(&mut self, save_interval: Duration) {
+        self.next_save = Instant::now() + save_interval;
+        self.save_interval = save_interval;
+    }
+
+    /// The totals over the store's lifetime, given the server's and engine's statistics for this
+    /// run.
+    pub(crate) fn totals(&self, stats: &Stats, engine: &EngineStats) -> LifetimeStats {
+        LifetimeStats {
+            gets: self.previous.gets + stats.gets.count,
+            sets: self.previous.sets + stats.sets.count,
+            removes: self.previous.removes + stats.removes.count,
+            compactions: self.previous.compactions + engine.compactions,
+            written_bytes: self.previous.written_bytes + engine.written_bytes,
+        }
+    }
+
+    /// Whether it's time to record the totals.
+    pub(crate) fn is_save_due(&self) -> bool {
+        Instant::now() >= self.next_save
+    }
+
+    /// Record `totals` in `engine`.
+    pub(crate) fn save<E: Engine>(&mut self, engine: &mut E, totals: &LifetimeStats) -> Result<()> {
+        self.next_save = Instant::now() + self.save_interval;
+        let value = serde_json::to_string(totals).expect("Stats are serializable");
+        engine.system_keys().set(LIFETIME_STATS, value)
+    }
+}
@@ -0,0 +1,37 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// A period of maintenance mode, started by a [`Request::Maintenance`].
+///
+/// While it lasts the server rejects writes, and the engine's maintenance tasks are run once.
+///
+/// [`Request::Maintenance`]: enum.Request.html#variant.Maintenance
+pub(crate) struct Maintenance {
+    until: Instant,
+    tasks_pending: bool,
+}
+
+impl Maintenance {
+    /// Start maintenance mode for `duration`.
+    pub(crate) fn new(duration: Duration) -> Self {
+        Maintenance {
+            until: Instant::now() + duration,
+            tasks_pending: true,
+        }
+    }
+
+    /// How long until maintenance mode ends, or `None` if it already has.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        let now = Instant::now();
+        if now < self.until {
+            Some(self.until - now)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the maintenance tasks still need to be run, marking them as run.
+    pub(crate) fn take_tasks(&mut self) -> bool {
+        mem::replace(&mut self.tasks_pending, false)
+    }
+}
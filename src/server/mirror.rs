@@ -0,0 +1,134 @@
+use rand::Rng;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use slog::{debug, info, warn};
+
+use crate::engine::SYSTEM_KEY_PREFIX;
+use crate::error::Result;
+use crate::protocol::{decode_response, encode_request, Request, RequestKind, Response};
+
+/// Configures a [`Mirror`].
+#[derive(Clone, Debug)]
+pub struct MirrorConfig {
+    /// The address of the secondary server requests are mirrored to.
+    pub address: SocketAddr,
+
+    /// The fraction of requests to mirror (between `0.0` and `1.0`).
+    pub rate: f64,
+
+    /// The number of mirrored requests that can wait to be sent to the secondary. Requests are
+    /// dropped, rather than mirrored, while the queue is full.
+    pub queue_size: usize,
+
+    /// How long to wait for the secondary to respond to each mirrored request.
+    pub timeout: Duration,
+}
+
+impl MirrorConfig {
+    /// Construct a config mirroring every request to the server at `address`.
+    pub fn new(address: SocketAddr) -> Self {
+        MirrorConfig {
+            address,
+            rate: 1.0,
+            queue_size: 1024,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Mirrors a sample of a server's requests to a secondary server, logging a warning whenever the
+/// secondary's response differs from the primary's.
+///
+/// This is for validating a new engine or `kvs` version against production traffic: run it
+/// behind a secondary server, loaded with a copy of the primary's data, and watch for
+/// divergences. Mirrored requests are sent from a background thread, one at a time and in the
+/// order the primary handled them, so the secondary can never slow the primary down, and the
+/// secondary's responses are never sent to clients.
+///
+/// Only gets, sets and removals of non-system keys are mirrored. Admin requests (e.g. stats and
+/// watches) describe the server itself, so they'd always differ.
+#[derive(Debug)]
+pub struct Mirror {
+    rate: f64,
+    sender: SyncSender<(Request, String)>,
+    dropped: u64,
+}
+
+impl Mirror {
+    /// Start mirroring requests as configured by `config`, logging divergences to `log`.
+    pub fn start(log: slog::Logger, config: MirrorConfig) -> Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(config.queue_size);
+        let rate = config.rate.clamp(0.0, 1.0);
+        info!(log, "Mirroring requests"; "address" => %config.address, "rate" => rate);
+        thread::Builder::new()
+            .name("kvs-mirror".to_owned())
+            .spawn(move || mirror(log, config, receiver))?;
+        Ok(Mirror { rate, sender, dropped: 0 })
+    }
+
+    /// Whether to mirror `request`.
+    ///
+    /// Requests within transactions aren't mirrored, since the secondary doesn't know about them.
+    pub(crate) fn sample(&self, request: &Request) -> bool {
+        if let Request::TransactionGet { .. } | Request::Commit { .. } = request {
+            return false;
+        }
+        let mirrored = match request.kind() {
+            RequestKind::Get | RequestKind::Set | RequestKind::Remove => {
+                !request.keys().iter().any(|key| key.starts_with(SYSTEM_KEY_PREFIX.as_bytes()))
+            },
+            RequestKind::Admin => false,
+        };
+        mirrored && rand::thread_rng().gen_bool(self.rate)
+    }
+
+    /// Mirror `request`, which the primary answered with `response`, unless the queue is full.
+    pub(crate) fn send(&mut self, log: &slog::Logger, request: Request, response: &Response) {
+        match self.sender.try_send((request, format!("{:?}", response))) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                debug!(log, "Mirror queue is full, dropping request"; "dropped" => self.dropped);
+            },
+            Err(TrySendError::Disconnected(_)) => {
+                debug!(log, "Mirror has stopped, dropping request");
+            },
+        }
+    }
+}
+
+/// Send each request from `receiver` to the secondary server, until the [`Mirror`] is dropped.
+fn mirror(log: slog::Logger, config: MirrorConfig, receiver: Receiver<(Request, String)>) {
+    for (request, expected) in receiver {
+        let key = String::from_utf8_lossy(request.key().unwrap_or_default()).into_owned();
+        match send(&config, &request) {
+            Ok(response) => {
+                let found = format!("{:?}", response);
+                if found != expected {
+                    warn!(log, "Mirrored response diverged";
+                        "key" => key,
+                        "primary" => expected,
+                        "secondary" => found);
+                }
+            },
+            Err(error) => warn!(log, "Failed to mirror request: {}", error; "key" => key),
+        }
+    }
+}
+
+/// Send `request` to the secondary server over a new connection, returning its response.
+fn send(config: &MirrorConfig, request: &Request) -> Result<Response> {
+    let mut stream = TcpStream::connect_timeout(&config.address, config.timeout)?;
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+    {
+        let mut writer = BufWriter::new(&mut stream);
+        encode_request(&mut writer, request)?;
+        writer.flush()?;
+    }
+    decode_response(BufReader::new(stream))
+}
@@ -0,0 +1,39 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+/// The client on the other end of a connection, as passed down with each of its requests.
+///
+/// Servers don't authenticate clients, so a peer is identified by its address, and by a
+/// connection ID to tell apart connections from the same address.
+#[derive(Clone, Debug)]
+pub struct Peer {
+    /// The connection's ID, unique among the connections accepted by a server.
+    pub connection_id: u64,
+
+    /// The client's address, or `None` for in-process connections.
+    pub address: Option<SocketAddr>,
+
+    /// When the connection was accepted.
+    pub connected_at: SystemTime,
+}
+
+impl Peer {
+    pub(crate) fn new(connection_id: u64, address: Option<SocketAddr>) -> Self {
+        Peer {
+            connection_id,
+            address,
+            connected_at: SystemTime::now(),
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    /// Formats the peer's address, or `in-process` for in-process connections.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "{}", address),
+            None => write!(f, "in-process"),
+        }
+    }
+}
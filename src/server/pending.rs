@@ -0,0 +1,61 @@
+use std::io::{self, Read};
+use std::net::TcpStream;
+
+use crate::error::Result;
+use crate::server::Peer;
+use crate::protocol::{Request, RequestDecoder};
+
+/// The most bytes read from a pending connection before moving on to the next, so that a large
+/// request being received doesn't hold up small ones.
+const READ_BUDGET: usize = 256 * 1024;
+
+/// A TCP connection whose request is still being received.
+///
+/// The connection's socket is non-blocking, and the request is read in chunks, interleaved with
+/// reading (and handling) requests from other connections.
+pub(crate) struct Pending {
+    pub(crate) stream: TcpStream,
+    pub(crate) peer: Peer,
+    pub(crate) log: slog::Logger,
+    decoder: RequestDecoder,
+}
+
+impl Pending {
+    /// Start receiving a request from `stream`, which must be non-blocking.
+    pub(crate) fn new(stream: TcpStream, peer: Peer, log: slog::Logger) -> Self {
+        Pending { stream, peer, log, decoder: RequestDecoder::new() }
+    }
+
+    /// Read what's available of the request, up to [`READ_BUDGET`] bytes in chunks of
+    /// `chunk_size`, returning the request once it's been received in full.
+    pub(crate) fn poll(&mut self, chunk_size: usize) -> Result<Option<Request>> {
+        let mut chunk = vec![0; chunk_size.max(1)];
+        let mut read = 0;
+        while read < READ_BUDGET {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    return match self.decoder.decode()? {
+                        Some(request) => Ok(Some(request)),
+                        None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                    };
+                },
+                Ok(n) => {
+                    self.decoder.extend(&chunk[..n]);
+                    read += n;
+                },
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+                Err(error) => return Err(error.into()),
+            }
+        }
+        if read == 0 {
+            return Ok(None);
+        }
+        self.decoder.decode()
+    }
+
+    /// The number of bytes of the request received so far.
+    pub(crate) fn received(&self) -> usize {
+        self.decoder.buffered()
+    }
+}
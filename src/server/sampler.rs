@@ -0,0 +1,230 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+use crate::logging;
+use crate::protocol::{Request, Response};
+use crate::server::Peer;
+
+/// Configuration for a [`Sampler`].
+#[derive(Clone, Debug)]
+pub struct SamplerConfig {
+    /// The path of the capture file. Rotated files are named with a numeric suffix (e.g.
+    /// `samples.json.1`).
+    pub path: PathBuf,
+
+    /// The fraction of requests to sample (between `0.0` and `1.0`).
+    pub rate: f64,
+
+    /// Requests slower than this are always captured, regardless of `rate`.
+    pub slow_threshold: Option<Duration>,
+
+    /// The size (in bytes) at which the capture file is rotated.
+    pub max_file_size: u64,
+
+    /// The number of rotated files to keep, in addition to the current capture file.
+    pub max_files: usize,
+}
+
+impl SamplerConfig {
+    /// Construct a config capturing to `path` with the default rotation settings and nothing
+    /// sampled.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        SamplerConfig {
+            path: path.into(),
+            rate: 0.0,
+            slow_threshold: None,
+            max_file_size: 64 * 1024 * 1024,
+            max_files: 4,
+        }
+    }
+}
+
+/// A sampled request, as written to capture files (one JSON object per line).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Sample {
+    /// When the request was received, in microseconds since the Unix epoch.
+    pub timestamp_micros: u64,
+
+    /// The operation requested (`get`, `set`, `rm`, `multi_get`, `multi_set`, `multi_rm`,
+    /// `tx_get`, `commit`, `cas`, `incr`, `rename`, `get_if_modified`, `ttl`, `expire`, `persist`,
+    /// or `admin`).
+    ///
+    /// Batches aren't captured key by key, so their key hash and sizes are left empty.
+    pub op: String,
+
+    /// A hash of the requested key, if any. Keys themselves are not captured.
+    pub key_hash: Option<u64>,
+
+    /// The size of the requested key, in bytes.
+    pub key_size: u64,
+
+    /// The size of the value in the request, in bytes.
+    pub value_size: u64,
+
+    /// The time taken to handle the request, in microseconds.
+    pub latency_micros: u64,
+
+    /// The kind of response sent (`ok`, `found`, `not_found`, `not_modified`, `err`, or
+    /// `admin`).
+    pub response: String,
+
+    /// Whether the request was captured because it was slow.
+    pub slow: bool,
+
+    /// The address of the client that made the request, or `in-process`.
+    #[serde(default)]
+    pub peer: String,
+
+    /// The ID of the connection the request was made on (see [`Peer::connection_id`]).
+    ///
+    /// [`Peer::connection_id`]: struct.Peer.html#structfield.connection_id
+    #[serde(default)]
+    pub connection_id: u64,
+}
+
+/// The parts of a request we sample, captured before the request is handed to the engine.
+pub(crate) struct RequestSummary {
+    timestamp: SystemTime,
+    op: &'static str,
+    key_hash: Option<u64>,
+    key_size: u64,
+    value_size: u64,
+    peer: String,
+    connection_id: u64,
+}
+
+impl RequestSummary {
+    pub(crate) fn new(request: &Request, peer: &Peer) -> Self {
+        let (op, key, value) = match request {
+            Request::Get { key } => ("get", Some(key.as_bytes()), None),
+            Request::Set { key, value, .. } => {
+                ("set", Some(key.as_bytes()), Some(value.as_bytes()))
+            },
+            Request::Remove { key } => ("rm", Some(key.as_bytes()), None),
+            Request::MultiGet { .. } => ("multi_get", None, None),
+            Request::MultiSet { .. } => ("multi_set", None, None),
+            Request::MultiRemove { .. } => ("multi_rm", None, None),
+            Request::TransactionGet { key, .. } => ("tx_get", Some(key.as_bytes()), None),
+            Request::Commit { .. } => ("commit", None, None),
+            Request::CompareAndSwap { key, new, .. } => {
+                ("cas", Some(key.as_bytes()), Some(new.as_bytes()))
+            },
+            Request::Increment { key, .. } => ("incr", Some(key.as_bytes()), None),
+            Request::Rename { from, .. } => ("rename", Some(from.as_bytes()), None),
+            Request::GetIfModified { key, .. } => ("get_if_modified", Some(key.as_bytes()), None),
+            Request::Ttl { key } => ("ttl", Some(key.as_bytes()), None),
+            Request::Expire { key, .. } => ("expire", Some(key.as_bytes()), None),
+            Request::Persist { key } => ("persist", Some(key.as_bytes()), None),
+            Request::ForceRemove { key } => ("force_rm", Some(key.as_bytes()), None),
+            Request::PutCas { value } => ("put_cas", None, Some(value.as_bytes())),
+            Request::GetBytes { key } => ("get_bytes", Some(&key[..]), None),
+            Request::SetBytes { key, value, .. } => ("set_bytes", Some(&key[..]), Some(&value[..])),
+            Request::RemoveBytes { key } => ("rm_bytes", Some(&key[..]), None),
+            _ => ("admin", None, None),
+        };
+        RequestSummary {
+            timestamp: SystemTime::now(),
+            op,
+            // Keys are hashed as bytes, so a key has the same hash however it was sent.
+            key_hash: key.map(|key| {
+                let mut hasher = DefaultHasher::new();
+                hasher.write(key);
+                hasher.finish()
+            }),
+            key_size: key.map(|key| key.len() as u64).unwrap_or(0),
+            value_size: value.map(|value| value.len() as u64).unwrap_or(0),
+            peer: peer.to_string(),
+            connection_id: peer.connection_id,
+        }
+    }
+}
+
+/// Captures a sample of requests, and all slow requests, to a rotating file.
+pub struct Sampler {
+    config: SamplerConfig,
+    writer: BufWriter<File>,
+    written: u64,
+}
+
+impl Sampler {
+    /// Open the capture file for a sampler, appending to any existing file.
+    pub fn open(config: SamplerConfig) -> Result<Self> {
+        let file = open_capture_file(&config.path)?;
+        let written = file.metadata()?.len();
+        Ok(Sampler {
+            config,
+            writer: BufWriter::new(file),
+            written,
+        })
+    }
+
+    /// Record a request, if it is selected for sampling or was slow.
+    pub(crate) fn record(
+        &mut self,
+        summary: RequestSummary,
+        response: &Result<Response>,
+        elapsed: Duration,
+    ) -> Result<()> {
+        let slow = self.config.slow_threshold.map(|t| elapsed >= t).unwrap_or(false);
+        if !slow && !rand::thread_rng().gen_bool(self.config.rate.clamp(0.0, 1.0)) {
+            return Ok(());
+        }
+
+        let sample = Sample {
+            timestamp_micros: summary
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or(0),
+            op: summary.op.to_owned(),
+            key_hash: summary.key_hash,
+            key_size: summary.key_size,
+            value_size: summary.value_size,
+            latency_micros: elapsed.as_micros() as u64,
+            response: match response {
+                Ok(Response::Ok) => "ok",
+                Ok(Response::Found { .. }) => "found",
+                Ok(Response::NotFound { .. }) | Err(Error::KeyNotFound) => "not_found",
+                Ok(Response::NotModified) => "not_modified",
+                Ok(Response::Err { .. }) | Err(_) => "err",
+                Ok(_) => "admin",
+            }
+            .to_owned(),
+            slow,
+            peer: summary.peer,
+            connection_id: summary.connection_id,
+        };
+
+        let mut line = serde_json::to_vec(&sample).expect("Sample is always serializable");
+        line.push(b'\n');
+
+        if self.written > 0 && self.written + line.len() as u64 > self.config.max_file_size {
+            self.rotate()?;
+        }
+        self.writer.write_all(&line)?;
+        self.writer.flush()?;
+        self.written += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// Shift existing capture files up by one suffix, discarding the oldest, and start a new file.
+    fn rotate(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        logging::rotate(&self.config.path, self.config.max_files)?;
+        self.writer = BufWriter::new(open_capture_file(&self.config.path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn open_capture_file(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
@@ -0,0 +1,95 @@
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+use crate::engine::Engine;
+use crate::error::Result;
+
+/// Configures a [`Scrubber`].
+#[derive(Clone, Debug)]
+pub struct ScrubberConfig {
+    /// The average time between scrubs.
+    pub interval: Duration,
+
+    /// The fraction of `interval` by which the time between scrubs is randomly varied (e.g. `0.25`
+    /// for ±25%), so that servers started together don't scrub in lockstep.
+    pub jitter: f64,
+
+    /// The maximum number of entries to verify in a single scrub.
+    pub max_entries_per_scrub: usize,
+
+    /// The maximum sustained number of entries to verify per second.
+    pub max_entries_per_sec: f64,
+}
+
+impl Default for ScrubberConfig {
+    fn default() -> Self {
+        ScrubberConfig {
+            interval: Duration::from_secs(1),
+            jitter: 0.25,
+            max_entries_per_scrub: 100,
+            max_entries_per_sec: 100.0,
+        }
+    }
+}
+
+/// Periodically re-reads a few of a server's stored entries and verifies them, so that silent
+/// disk corruption is found before a client reads the affected keys.
+///
+/// Each scrub continues from where the last one stopped, so every entry is eventually verified.
+/// Scrubs are kept small and slow by [`max_entries_per_scrub`] and a [`max_entries_per_sec`] rate
+/// cap, since they compete with requests for the disk. Progress and findings are reported in the
+/// engine's stats.
+///
+/// [`max_entries_per_scrub`]: struct.ScrubberConfig.html#structfield.max_entries_per_scrub
+/// [`max_entries_per_sec`]: struct.ScrubberConfig.html#structfield.max_entries_per_sec
+pub struct Scrubber {
+    config: ScrubberConfig,
+    next_scrub: Instant,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Scrubber {
+    /// Construct a scrubber with the given configuration.
+    pub fn new(config: ScrubberConfig) -> Self {
+        let now = Instant::now();
+        let mut scrubber = Scrubber {
+            tokens: config.max_entries_per_scrub as f64,
+            config,
+            next_scrub: now,
+            refilled: now,
+        };
+        scrubber.schedule(now);
+        scrubber
+    }
+
+    /// Verify entries in `engine` if a scrub is due, returning the keys of any found to be
+    /// corrupt.
+    pub(crate) fn scrub_if_due<E: Engine>(&mut self, engine: &mut E) -> Result<Vec<String>> {
+        let now = Instant::now();
+        if now < self.next_scrub {
+            return Ok(Vec::new());
+        }
+        self.schedule(now);
+
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        let burst = self.config.max_entries_per_scrub as f64;
+        self.tokens = (self.tokens + elapsed * self.config.max_entries_per_sec).min(burst);
+        self.refilled = now;
+
+        let limit = self.tokens.floor() as usize;
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        // Engines don't say how many entries they verified, so charge for the whole limit.
+        self.tokens -= limit as f64;
+        engine.scrub(limit)
+    }
+
+    /// Schedule the next scrub for a jittered interval after `now`.
+    fn schedule(&mut self, now: Instant) {
+        let jitter = self.config.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + rand::thread_rng().gen_range(-jitter, jitter + f64::EPSILON);
+        self.next_scrub = now + self.config.interval.mul_f64(factor.max(0.0));
+    }
+}
@@ -0,0 +1,175 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+
+/// The maximum number of pending connections queued on a listening socket.
+#[cfg(unix)]
+const BACKLOG: libc::c_int = 128;
+
+/// The default capacity of the buffers used to read requests and write responses.
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Socket options for a [`Server`]'s listening sockets and the connections it accepts.
+///
+/// [`Server`]: struct.Server.html
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Allow the server to bind its address while connections from a previous process are still
+    /// in `TIME_WAIT`, so that it can be restarted straight away (`SO_REUSEADDR`).
+    pub reuse_address: bool,
+
+    /// Allow several servers to bind the same address, with the kernel balancing connections
+    /// between them (`SO_REUSEPORT`), e.g. to run one process per core.
+    ///
+    /// Only supported on Unix.
+    pub reuse_port: bool,
+
+    /// Send responses immediately rather than waiting to coalesce small writes, i.e. disable
+    /// Nagle's algorithm on accepted connections (`TCP_NODELAY`).
+    pub nodelay: bool,
+
+    /// The size of each connection's send buffer in bytes (`SO_SNDBUF`), if not the system
+    /// default.
+    ///
+    /// Only supported on Unix.
+    pub send_buffer_size: Option<usize>,
+
+    /// The size of each connection's receive buffer in bytes (`SO_RCVBUF`), if not the system
+    /// default.
+    ///
+    /// Only supported on Unix.
+    pub recv_buffer_size: Option<usize>,
+
+    /// The capacity (in bytes) of the buffers used to read each request and write each response,
+    /// so that they're sent in as few packets as possible.
+    pub buffer_size: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            reuse_address: true,
+            reuse_port: false,
+            nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+}
+
+/// Bind a listening socket to the first of `address`'s addresses that's available.
+pub(crate) fn bind_any<A: ToSocketAddrs>(
+    address: A,
+    config: &ServerConfig,
+) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for address in address.to_socket_addrs()? {
+        match bind(address, config) {
+            Ok(listener) => return Ok(listener),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "could not resolve to any addresses")
+    }))
+}
+
+/// Bind a listening socket to `address`, with the socket options in `config`.
+///
+/// Options that must be set before binding aren't exposed by `std`, so the socket is created
+/// with `libc` and handed to a [`TcpListener`] once it's listening.
+#[cfg(unix)]
+pub(crate) fn bind(address: SocketAddr, config: &ServerConfig) -> io::Result<TcpListener> {
+    use std::mem;
+    use std::os::unix::io::FromRawFd;
+
+    let domain = if address.is_ipv6() { libc::AF_INET6 } else { libc::AF_INET };
+    // SAFETY: `socket` has no memory-safety preconditions.
+    let fd = cvt(unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) })?;
+    // SAFETY: `fd` is a new socket that nothing else owns, so the listener can close it (which
+    // it does if any of the following fails).
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    // SAFETY: `fcntl` has no memory-safety preconditions.
+    cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+
+    set_option(fd, libc::SO_REUSEADDR, libc::c_int::from(config.reuse_address))?;
+    if config.reuse_port {
+        set_option(fd, libc::SO_REUSEPORT, 1)?;
+    }
+    // Accepted connections inherit the listening socket's buffer sizes.
+    if let Some(size) = config.send_buffer_size {
+        set_option(fd, libc::SO_SNDBUF, buffer_size(size)?)?;
+    }
+    if let Some(size) = config.recv_buffer_size {
+        set_option(fd, libc::SO_RCVBUF, buffer_size(size)?)?;
+    }
+
+    // SAFETY: an all-zero `sockaddr_storage` is valid, and it's large and aligned enough to hold
+    // either kind of address.
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match address {
+        SocketAddr::V4(address) => {
+            // SAFETY: see above.
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = address.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(address.ip().octets());
+            mem::size_of::<libc::sockaddr_in>()
+        },
+        SocketAddr::V6(address) => {
+            // SAFETY: see above.
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = address.port().to_be();
+            sin6.sin6_flowinfo = address.flowinfo();
+            sin6.sin6_addr.s6_addr = address.ip().octets();
+            sin6.sin6_scope_id = address.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        },
+    };
+    let addr = &storage as *const _ as *const libc::sockaddr;
+    // SAFETY: `addr` points to an initialised address of length `len`.
+    cvt(unsafe { libc::bind(fd, addr, len as libc::socklen_t) })?;
+    // SAFETY: `listen` has no memory-safety preconditions.
+    cvt(unsafe { libc::listen(fd, BACKLOG) })?;
+    Ok(listener)
+}
+
+/// Bind a listening socket to `address`, failing if `config` has options that aren't supported
+/// on this platform.
+#[cfg(not(unix))]
+pub(crate) fn bind(address: SocketAddr, config: &ServerConfig) -> io::Result<TcpListener> {
+    if config.reuse_port || config.send_buffer_size.is_some() || config.recv_buffer_size.is_some()
+    {
+        let message = "reuse_port and buffer sizes are only supported on Unix";
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+    TcpListener::bind(address)
+}
+
+#[cfg(unix)]
+fn set_option(fd: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    let value_ptr = &value as *const libc::c_int as *const libc::c_void;
+    let len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `value_ptr` points to a `c_int` of length `len`.
+    cvt(unsafe { libc::setsockopt(fd, libc::SOL_SOCKET, name, value_ptr, len) })?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn buffer_size(size: usize) -> io::Result<libc::c_int> {
+    use std::convert::TryFrom;
+
+    libc::c_int::try_from(size).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("buffer size {} is too big", size))
+    })
+}
+
+#[cfg(unix)]
+fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
@@ -0,0 +1,94 @@
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+use crate::engine::Engine;
+use crate::error::Result;
+
+/// Configures a [`Sweeper`].
+#[derive(Clone, Debug)]
+pub struct SweeperConfig {
+    /// The average time between sweeps.
+    pub interval: Duration,
+
+    /// The fraction of `interval` by which the time between sweeps is randomly varied (e.g. `0.25`
+    /// for ±25%), so that servers started together don't sweep in lockstep.
+    pub jitter: f64,
+
+    /// The maximum number of expired keys to remove in a single sweep.
+    pub max_keys_per_sweep: usize,
+
+    /// The maximum sustained number of expired keys to remove per second.
+    pub max_keys_per_sec: f64,
+}
+
+impl Default for SweeperConfig {
+    fn default() -> Self {
+        SweeperConfig {
+            interval: Duration::from_millis(100),
+            jitter: 0.25,
+            max_keys_per_sweep: 100,
+            max_keys_per_sec: 1000.0,
+        }
+    }
+}
+
+/// Periodically removes expired keys from a server's engine, so that they don't use storage until
+/// they're next requested.
+///
+/// Each sweep removes a small number of the soonest-expired keys, limited by both
+/// [`max_keys_per_sweep`] and a [`max_keys_per_sec`] rate cap, so that sweeping never stalls the
+/// server for long. Servers handle connections one at a time, so sweeps run between connections
+/// once they're due.
+///
+/// [`max_keys_per_sweep`]: struct.SweeperConfig.html#structfield.max_keys_per_sweep
+/// [`max_keys_per_sec`]: struct.SweeperConfig.html#structfield.max_keys_per_sec
+pub struct Sweeper {
+    config: SweeperConfig,
+    next_sweep: Instant,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Sweeper {
+    /// Construct a sweeper with the given configuration.
+    pub fn new(config: SweeperConfig) -> Self {
+        let now = Instant::now();
+        let mut sweeper = Sweeper {
+            tokens: config.max_keys_per_sweep as f64,
+            config,
+            next_sweep: now,
+            refilled: now,
+        };
+        sweeper.schedule(now);
+        sweeper
+    }
+
+    /// Remove expired keys from `engine` if a sweep is due, returning the keys that were removed.
+    pub(crate) fn sweep_if_due<E: Engine>(&mut self, engine: &mut E) -> Result<Vec<String>> {
+        let now = Instant::now();
+        if now < self.next_sweep {
+            return Ok(Vec::new());
+        }
+        self.schedule(now);
+
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        let burst = self.config.max_keys_per_sweep as f64;
+        self.tokens = (self.tokens + elapsed * self.config.max_keys_per_sec).min(burst);
+        self.refilled = now;
+
+        let limit = self.tokens.floor() as usize;
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let expired = engine.sweep_expired(limit)?;
+        self.tokens -= expired.len() as f64;
+        Ok(expired)
+    }
+
+    /// Schedule the next sweep for a jittered interval after `now`.
+    fn schedule(&mut self, now: Instant) {
+        let jitter = self.config.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + rand::thread_rng().gen_range(-jitter, jitter + f64::EPSILON);
+        self.next_sweep = now + self.config.interval.mul_f64(factor.max(0.0));
+    }
+}
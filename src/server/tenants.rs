@@ -0,0 +1,364 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str;
+use std::time::Instant;
+
+use crate::engine::{incremented, Engine, SYSTEM_KEY_PREFIX};
+use crate::error::{Error, Result};
+use crate::protocol::Request;
+
+/// The separator between a key's tenant and the rest of the key.
+pub const TENANT_SEPARATOR: char = '/';
+
+/// The name of the system key prefix under which tenant usage is persisted.
+const USAGE_KEY_PREFIX: &str = "tenant_usage/";
+
+/// Limits on a tenant's use of a server. Limits that are `None` are not enforced.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TenantQuota {
+    /// The maximum number of keys the tenant may store.
+    pub max_keys: Option<u64>,
+
+    /// The maximum number of bytes (of keys and values) the tenant may store.
+    pub max_bytes: Option<u64>,
+
+    /// The maximum sustained rate of requests the tenant may make, per second. Bursts of up to a
+    /// second's worth of requests (or a single request, for rates below one per second) are
+    /// allowed.
+    pub max_requests_per_sec: Option<f64>,
+}
+
+/// A tenant's usage of a server, as returned for a [`Request::Tenants`].
+///
+/// [`Request::Tenants`]: enum.Request.html#variant.Tenants
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TenantUsage {
+    /// The tenant's name.
+    pub tenant: String,
+
+    /// The number of keys the tenant has stored.
+    pub keys: u64,
+
+    /// The number of bytes (of keys and values) the tenant has stored.
+    pub bytes: u64,
+
+    /// The number of requests the tenant has made since the server started.
+    pub requests: u64,
+
+    /// The number of the tenant's requests rejected for exceeding a quota since the server
+    /// started.
+    pub rejected: u64,
+
+    /// The quota applied to the tenant.
+    pub quota: TenantQuota,
+}
+
+/// The parts of a tenant's usage that are persisted in the system keyspace.
+#[derive(Default, Deserialize, Serialize)]
+struct StoredUsage {
+    keys: u64,
+    bytes: u64,
+}
+
+/// A tenant's usage, along with its request rate limiter.
+struct Tenant {
+    usage: TenantUsage,
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// A change in a tenant's stored usage, to be applied once a request succeeds.
+pub(crate) struct UsageChange {
+    tenant: String,
+    keys: i64,
+    bytes: i64,
+}
+
+/// Tracks each tenant's usage of a server and enforces their quotas.
+///
+/// A key's tenant is the part of the key before the first [`TENANT_SEPARATOR`] (so `app1/users/1`
+/// belongs to the tenant `app1`). Keys without a separator, and system keys, don't belong to a
+/// tenant and are not subject to quotas.
+///
+/// Stored keys and bytes are persisted in the engine's system keyspace, so they survive restarts.
+/// Only changes made while tenancy is enabled are counted.
+#[derive(Default)]
+pub struct Tenants {
+    quotas: HashMap<String, TenantQuota>,
+    default_quota: TenantQuota,
+    tenants: HashMap<String, Tenant>,
+}
+
+impl Tenants {
+    /// Construct a tracker that applies `default_quota` to tenants without their own quota.
+    pub fn new(default_quota: TenantQuota) -> Self {
+        Tenants {
+            default_quota,
+            ..Tenants::default()
+        }
+    }
+
+    /// Apply a specific quota to `tenant`.
+    pub fn with_quota<T: Into<String>>(mut self, tenant: T, quota: TenantQuota) -> Self {
+        self.quotas.insert(tenant.into(), quota);
+        self
+    }
+
+    /// Check that a request is within its tenant's quotas, returning the change in the tenant's
+    /// usage to apply if the request succeeds.
+    pub(crate) fn check<E: Engine>(
+        &mut self,
+        request: &Request,
+        engine: &mut E,
+    ) -> Result<Option<UsageChange>> {
+        let keys = request.keys();
+        let tenant_name = match request {
+            // Usage is tracked per tenant, so prefix removals mustn't span tenants.
+            Request::RemovePrefix { prefix } => match tenant_of(prefix.as_bytes())? {
+                Some(tenant_name) => tenant_name,
+                None => {
+                    return Err(Error::InvalidKey(format!(
+                        "prefix must be within a single tenant (e.g. \"tenant{}\")",
+                        TENANT_SEPARATOR
+                    )))
+                },
+            },
+            // As with prefix removals, a batch's (or rename's) usage is tracked against a single
+            // tenant.
+            Request::MultiGet { .. }
+            | Request::MultiSet { .. }
+            | Request::MultiRemove { .. }
+            | Request::Commit { .. }
+            | Request::Rename { .. } => {
+                let tenants = keys.iter().map(|key| tenant_of(key)).collect::<Result<Vec<_>>>()?;
+                let tenant_name = tenants.first().copied().flatten();
+                if tenants.iter().any(|&tenant| tenant != tenant_name) {
+                    return Err(Error::InvalidKey(format!(
+                        "keys must be within a single tenant (e.g. \"tenant{}\")",
+                        TENANT_SEPARATOR
+                    )));
+                }
+                match tenant_name {
+                    Some(tenant_name) => tenant_name,
+                    None => return Ok(None),
+                }
+            },
+            _ => match keys.first().map(|key| tenant_of(key)).transpose()?.flatten() {
+                Some(tenant_name) => tenant_name,
+                None => return Ok(None),
+            },
+        };
+        self.load(tenant_name, engine)?;
+        let tenant = self.tenants.get_mut(tenant_name).expect("Tenant is loaded");
+        tenant.usage.requests += 1;
+
+        if let Some(max_requests_per_sec) = tenant.usage.quota.max_requests_per_sec {
+            let now = Instant::now();
+            let elapsed = now.duration_since(tenant.refilled).as_secs_f64();
+            tenant.tokens =
+                (tenant.tokens + elapsed * max_requests_per_sec).min(burst(max_requests_per_sec));
+            tenant.refilled = now;
+            if tenant.tokens < 1.0 {
+                tenant.usage.rejected += 1;
+                return Err(Error::RateLimited(tenant_name.to_owned()));
+            }
+            tenant.tokens -= 1.0;
+        }
+
+        let (keys, bytes) = match request {
+            Request::Set { key, value, .. } => set_usage(engine, key.as_bytes(), value.as_bytes())?,
+            Request::SetBytes { key, value, .. } => set_usage(engine, key, value)?,
+            Request::Remove { key } | Request::ForceRemove { key } => {
+                match remove_usage(engine, key.as_bytes())? {
+                    Some(usage) => usage,
+                    None => return Ok(None),
+                }
+            },
+            Request::RemoveBytes { key } => match remove_usage(engine, key)? {
+                Some(usage) => usage,
+                None => return Ok(None),
+            },
+            // The server handles one request at a time, so the key can't change before the
+            // request is dispatched.
+            Request::CompareAndSwap { key, expected, new } => {
+                if engine.get(key.clone())? != *expected {
+                    return Ok(None);
+                }
+                set_usage(engine, key.as_bytes(), new.as_bytes())?
+            },
+            Request::Increment { key, delta } => {
+                let old = engine.get(key.clone())?;
+                match incremented(key, old.as_deref(), *delta) {
+                    Ok(value) => set_usage(engine, key.as_bytes(), value.to_string().as_bytes())?,
+                    Err(_) => return Ok(None),
+                }
+            },
+            Request::Rename { from, to, overwrite } => {
+                let value = match engine.get(from.clone())? {
+                    Some(value) if from != to => value,
+                    _ => return Ok(None),
+                };
+                let (added_keys, added_bytes) = match engine.get(to.clone())? {
+                    Some(_) if !overwrite => return Ok(None),
+                    Some(old) => (0, value.len() as i64 - old.len() as i64),
+                    None => (1, (to.len() + value.len()) as i64),
+                };
+                (added_keys - 1, added_bytes - (from.len() + value.len()) as i64)
+            },
+            Request::RemovePrefix { prefix } => {
+                let entries = engine.scan(prefix)?;
+                let bytes: usize = entries.iter().map(|(key, value)| key.len() + value.len()).sum();
+                (-(entries.len() as i64), -(bytes as i64))
+            },
+            Request::MultiSet { pairs, .. } => {
+                let (mut keys, mut bytes) = (0, 0);
+                for (key, value) in pairs {
+                    let (added_keys, added_bytes) =
+                        set_usage(engine, key.as_bytes(), value.as_bytes())?;
+                    keys += added_keys;
+                    bytes += added_bytes;
+                }
+                (keys, bytes)
+            },
+            Request::Commit { writes, .. } => {
+                let (mut keys, mut bytes) = (0, 0);
+                for (key, value) in writes {
+                    let (added_keys, added_bytes) = match value {
+                        Some(value) => set_usage(engine, key.as_bytes(), value.as_bytes())?,
+                        None => match engine.get(key.clone())? {
+                            Some(old) => (-1, -((key.len() + old.len()) as i64)),
+                            None => (0, 0),
+                        },
+                    };
+                    keys += added_keys;
+                    bytes += added_bytes;
+                }
+                (keys, bytes)
+            },
+            Request::MultiRemove { keys: removed } => {
+                let (mut keys, mut bytes) = (0, 0);
+                for key in removed {
+                    if let Some(old) = engine.get(key.clone())? {
+                        keys -= 1;
+                        bytes -= (key.len() + old.len()) as i64;
+                    }
+                }
+                (keys, bytes)
+            },
+            _ => return Ok(None),
+        };
+
+        let usage = &mut tenant.usage;
+        if keys > 0 && usage.quota.max_keys.is_some_and(|max| usage.keys + keys as u64 > max) {
+            usage.rejected += 1;
+            return Err(Error::KeyQuotaExceeded(tenant_name.to_owned()));
+        }
+        let over_bytes = |max| usage.bytes + bytes as u64 > max;
+        if bytes > 0 && usage.quota.max_bytes.is_some_and(over_bytes) {
+            usage.rejected += 1;
+            return Err(Error::ByteQuotaExceeded(tenant_name.to_owned()));
+        }
+
+        Ok(Some(UsageChange {
+            tenant: tenant_name.to_owned(),
+            keys,
+            bytes,
+        }))
+    }
+
+    /// Apply a change in usage after a request has succeeded, persisting the tenant's new usage.
+    pub(crate) fn apply<E: Engine>(&mut self, change: UsageChange, engine: &mut E) -> Result<()> {
+        let usage = &mut self.tenants.get_mut(&change.tenant).expect("Tenant is loaded").usage;
+        usage.keys = (usage.keys as i64 + change.keys).max(0) as u64;
+        usage.bytes = (usage.bytes as i64 + change.bytes).max(0) as u64;
+
+        let stored = StoredUsage {
+            keys: usage.keys,
+            bytes: usage.bytes,
+        };
+        engine.system_keys().set(
+            &usage_key(&change.tenant),
+            serde_json::to_string(&stored).expect("Usage is serializable"),
+        )
+    }
+
+    /// The usage of every tenant with a specific quota or that has been seen since the server
+    /// started, sorted by tenant.
+    pub(crate) fn usage<E: Engine>(&mut self, engine: &mut E) -> Result<Vec<TenantUsage>> {
+        let configured: Vec<_> = self.quotas.keys().cloned().collect();
+        for tenant in configured {
+            self.load(&tenant, engine)?;
+        }
+        let mut usage: Vec<_> = self.tenants.values().map(|t| t.usage.clone()).collect();
+        usage.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        Ok(usage)
+    }
+
+    /// Load a tenant's persisted usage, if it isn't already loaded.
+    fn load<E: Engine>(&mut self, tenant: &str, engine: &mut E) -> Result<()> {
+        if self.tenants.contains_key(tenant) {
+            return Ok(());
+        }
+
+        let stored = match engine.system_keys().get(&usage_key(tenant))? {
+            Some(json) => serde_json::from_str(&json).unwrap_or_default(),
+            None => StoredUsage::default(),
+        };
+        let quota = self.quotas.get(tenant).unwrap_or(&self.default_quota).clone();
+        self.tenants.insert(
+            tenant.to_owned(),
+            Tenant {
+                tokens: quota.max_requests_per_sec.map(burst).unwrap_or(0.0),
+                refilled: Instant::now(),
+                usage: TenantUsage {
+                    tenant: tenant.to_owned(),
+                    keys: stored.keys,
+                    bytes: stored.bytes,
+                    quota,
+                    ..TenantUsage::default()
+                },
+            },
+        );
+        Ok(())
+    }
+}
+
+/// The tenant a key belongs to, if any.
+///
+/// Keys may be any bytes, but tenant names must be UTF-8.
+fn tenant_of(key: &[u8]) -> Result<Option<&str>> {
+    if key.starts_with(SYSTEM_KEY_PREFIX.as_bytes()) {
+        return Ok(None);
+    }
+    match key.iter().position(|&byte| byte == TENANT_SEPARATOR as u8) {
+        Some(0) | None => Ok(None),
+        Some(end) => match str::from_utf8(&key[..end]) {
+            Ok(tenant) => Ok(Some(tenant)),
+            Err(_) => Err(Error::InvalidKey("key's tenant isn't UTF-8".to_owned())),
+        },
+    }
+}
+
+/// The change in a tenant's stored keys and bytes from setting `key` to `value`.
+fn set_usage<E: Engine>(engine: &mut E, key: &[u8], value: &[u8]) -> Result<(i64, i64)> {
+    Ok(match engine.get_bytes(key)? {
+        Some(old) => (0, value.len() as i64 - old.len() as i64),
+        None => (1, (key.len() + value.len()) as i64),
+    })
+}
+
+/// The change in a tenant's stored keys and bytes from removing `key`, or `None` if it isn't in
+/// the store.
+fn remove_usage<E: Engine>(engine: &mut E, key: &[u8]) -> Result<Option<(i64, i64)>> {
+    let old = engine.get_bytes(key)?;
+    Ok(old.map(|old| (-1, -((key.len() + old.len()) as i64))))
+}
+
+/// The number of requests a tenant may make in a burst, given its maximum request rate.
+fn burst(max_requests_per_sec: f64) -> f64 {
+    max_requests_per_sec.max(1.0)
+}
+
+fn usage_key(tenant: &str) -> String {
+    format!("{}{}", USAGE_KEY_PREFIX, tenant)
+}
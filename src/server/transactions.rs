@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::engine::{Engine, WriteLog};
+use crate::error::{Error, Result};
+use crate::protocol::NotFoundReason;
+
+/// How long a transaction can go without a request before it's rolled back, so that transactions
+/// abandoned by their clients don't make the server track writes forever.
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The transactions open on a server, started by [`Request::Begin`]s.
+///
+/// Servers handle one request per connection, so a transaction is identified by its ID rather
+/// than its connection. Every write the server makes must be [`record`]ed, so that transactions
+/// can detect conflicting writes.
+///
+/// [`Request::Begin`]: enum.Request.html#variant.Begin
+/// [`record`]: #method.record
+pub(crate) struct Transactions {
+    write_log: WriteLog,
+    open: HashMap<u64, Open>,
+    next_id: u64,
+}
+
+/// An open transaction.
+struct Open {
+    snapshot: u64,
+    used: Instant,
+}
+
+impl Transactions {
+    pub(crate) fn new() -> Self {
+        Transactions { write_log: WriteLog::default(), open: HashMap::new(), next_id: 1 }
+    }
+
+    /// Whether no transactions are open, so writes needn't be recorded.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.open.is_empty()
+    }
+
+    /// Begin a transaction, returning its ID, and roll back any that have timed out.
+    pub(crate) fn begin(&mut self) -> u64 {
+        let timed_out: Vec<_> = self
+            .open
+            .iter()
+            .filter(|(_, open)| open.used.elapsed() > TRANSACTION_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in timed_out {
+            self.rollback(id);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let snapshot = self.write_log.begin();
+        self.open.insert(id, Open { snapshot, used: Instant::now() });
+        id
+    }
+
+    /// Get the value of `key` within transaction `id`, or why it wasn't found (as with
+    /// [`Engine::lookup`]).
+    ///
+    /// [`Engine::lookup`]: trait.KvsEngine.html#method.lookup
+    pub(crate) fn get<E: Engine>(
+        &mut self,
+        engine: &mut E,
+        id: u64,
+        key: String,
+    ) -> Result<std::result::Result<String, Option<NotFoundReason>>> {
+        let snapshot = self.touch(id)?;
+        self.write_log.check(&key, snapshot)?;
+        engine.lookup(key)
+    }
+
+    /// Commit transaction `id`, applying `writes` unless any of their keys have been written
+    /// since it began. The transaction is closed either way.
+    pub(crate) fn commit<E: Engine>(
+        &mut self,
+        engine: &mut E,
+        id: u64,
+        writes: Vec<(String, Option<String>)>,
+    ) -> Result<()> {
+        let snapshot = self.touch(id)?;
+        self.open.remove(&id);
+        let result = self.write_log.commit(engine, snapshot, writes.into_iter().collect());
+        self.write_log.end(snapshot);
+        result
+    }
+
+    /// Roll back transaction `id`, if it's open.
+    pub(crate) fn rollback(&mut self, id: u64) {
+        if let Some(open) = self.open.remove(&id) {
+            self.write_log.end(open.snapshot);
+        }
+    }
+
+    /// Record a write to `key`.
+    pub(crate) fn record<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.write_log.record(key);
+    }
+
+    /// The snapshot of transaction `id`, marking it as used, or [`Error::Conflict`] if it isn't
+    /// open (e.g. because it timed out).
+    fn touch(&mut self, id: u64) -> Result<u64> {
+        let open = self.open.get_mut(&id);
+        match open.filter(|open| open.used.elapsed() <= TRANSACTION_TIMEOUT) {
+            Some(open) => {
+                open.used = Instant::now();
+                Ok(open.snapshot)
+            },
+            None => {
+                self.rollback(id);
+                Err(Error::Conflict(format!("transaction {} is not open", id)))
+            },
+        }
+    }
+}
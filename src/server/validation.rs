@@ -0,0 +1,93 @@
+use crate::error::{Error, Result};
+use std::str;
+
+/// The characters a [`KeyRules`] allows in keys.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum KeyCharset {
+    /// Any key, including bytes that aren't UTF-8.
+    #[default]
+    Any,
+
+    /// Any UTF-8 string.
+    Utf8,
+
+    /// ASCII characters only.
+    Ascii,
+
+    /// Printable ASCII characters only, excluding spaces and control characters.
+    Printable,
+}
+
+impl KeyCharset {
+    fn allows(self, c: char) -> bool {
+        match self {
+            KeyCharset::Any | KeyCharset::Utf8 => true,
+            KeyCharset::Ascii => c.is_ascii(),
+            KeyCharset::Printable => c.is_ascii_graphic(),
+        }
+    }
+}
+
+/// Rules that keys must satisfy for a [`Server`] to accept a request.
+///
+/// Requests with keys that break the rules are rejected with [`Error::InvalidKey`] before they
+/// reach the engine. The default rules accept any key.
+///
+/// [`Server`]: struct.Server.html
+/// [`Error::InvalidKey`]: enum.Error.html#variant.InvalidKey
+#[derive(Clone, Debug, Default)]
+pub struct KeyRules {
+    /// The maximum length of a key, in bytes.
+    pub max_length: Option<usize>,
+
+    /// The characters allowed in keys.
+    pub charset: KeyCharset,
+
+    /// Key prefixes reserved for system use, which requests may not use.
+    pub reserved_prefixes: Vec<String>,
+}
+
+impl KeyRules {
+    /// Check that `key` satisfies the rules, returning an [`Error::InvalidKey`] describing the
+    /// first rule it breaks if it doesn't.
+    ///
+    /// [`Error::InvalidKey`]: enum.Error.html#variant.InvalidKey
+    pub fn check(&self, key: &[u8]) -> Result<()> {
+        if let Some(max_length) = self.max_length {
+            if key.len() > max_length {
+                return Err(Error::InvalidKey(format!(
+                    "key is {} bytes, longer than the maximum of {}",
+                    key.len(),
+                    max_length
+                )));
+            }
+        }
+        if self.charset != KeyCharset::Any {
+            let (valid, invalid_at) = match str::from_utf8(key) {
+                Ok(valid) => (valid, None),
+                Err(err) => {
+                    let valid_up_to = err.valid_up_to();
+                    (str::from_utf8(&key[..valid_up_to]).unwrap(), Some(valid_up_to))
+                },
+            };
+            if let Some((index, c)) = valid.char_indices().find(|&(_, c)| !self.charset.allows(c)) {
+                return Err(Error::InvalidKey(format!(
+                    "key contains disallowed character {:?} at byte {}",
+                    c, index
+                )));
+            }
+            if let Some(index) = invalid_at {
+                return Err(Error::InvalidKey(format!("key isn't UTF-8 at byte {}", index)));
+            }
+        }
+        if let Some(prefix) =
+            self.reserved_prefixes.iter().find(|prefix| key.starts_with(prefix.as_bytes()))
+        {
+            return Err(Error::InvalidKey(format!(
+                "key starts with reserved prefix {:?}",
+                prefix
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::engine::{Engine, SystemKeys};
+use crate::error::Result;
+
+/// The name of the system key holding the keys to prefetch when a server starts.
+const HOT_KEYS: &str = "hot_keys";
+
+/// Configures a [`Warmup`].
+#[derive(Clone, Debug)]
+pub struct WarmupConfig {
+    /// The number of the hottest keys to record, and to prefetch when the server next starts.
+    pub max_keys: usize,
+
+    /// The maximum number of keys to prefetch per second, so that warm-up doesn't hold up
+    /// requests.
+    pub max_keys_per_sec: f64,
+
+    /// How often to record the hottest keys.
+    pub save_interval: Duration,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        WarmupConfig {
+            max_keys: 100,
+            max_keys_per_sec: 500.0,
+            save_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Prefetches the keys that were hottest before a server restarted, so that the first requests
+/// for them don't pay for a cold read (e.g. reopening log files, or reading index pages and
+/// values from disk).
+///
+/// The server's hottest keys (see [`Request::HotKeys`]) are recorded in the engine's system
+/// keyspace every [`save_interval`]. Servers run until they're killed, so this happens
+/// periodically rather than on shutdown. When the server next starts, the recorded keys are read
+/// between connections, hottest first, at no more than [`max_keys_per_sec`].
+///
+/// [`Request::HotKeys`]: enum.Request.html#variant.HotKeys
+/// [`save_interval`]: struct.WarmupConfig.html#structfield.save_interval
+/// [`max_keys_per_sec`]: struct.WarmupConfig.html#structfield.max_keys_per_sec
+pub struct Warmup {
+    config: WarmupConfig,
+    pending: VecDeque<String>,
+    started: Instant,
+    prefetched: u64,
+    next_save: Instant,
+    saved: Vec<String>,
+}
+
+impl Warmup {
+    /// Construct a warm-up with the given configuration.
+    pub fn new(config: WarmupConfig) -> Self {
+        let now = Instant::now();
+        Warmup {
+            next_save: now + config.save_interval,
+            config,
+            pending: VecDeque::new(),
+            started: now,
+            prefetched: 0,
+            saved: Vec::new(),
+        }
+    }
+
+    /// Queue the keys recorded in `engine` by a previous run for prefetching, returning how many
+    /// there are.
+    pub(crate) fn load<E: Engine>(&mut self, engine: &mut E) -> Result<usize> {
+        let keys: Vec<String> = match SystemKeys::new(engine).get(HOT_KEYS)? {
+            Some(keys) => serde_json::from_str(&keys).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        self.pending = keys.iter().take(self.config.max_keys).cloned().collect();
+        self.saved = keys;
+        self.started = Instant::now();
+        Ok(self.pending.len())
+    }
+
+    /// Prefetch as many queued keys as the rate cap allows, returning how many were prefetched.
+    pub(crate) fn prefetch<E: Engine>(&mut self, engine: &mut E) -> Result<usize> {
+        let allowed = self.started.elapsed().as_secs_f64() * self.config.max_keys_per_sec;
+        let limit = (allowed as u64).saturating_sub(self.prefetched) as usize;
+        let mut prefetched = 0;
+        while prefetched < limit {
+            let key = match self.pending.pop_front() {
+                Some(key) => key,
+                None => break,
+            };
+            engine.get(key)?;
+            prefetched += 1;
+            self.prefetched += 1;
+        }
+        Ok(prefetched)
+    }
+
+    /// Whether every queued key has been prefetched.
+    pub(crate) fn is_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Whether it's time to record the hottest keys.
+    pub(crate) fn is_save_due(&self) -> bool {
+        Instant::now() >= self.next_save
+    }
+
+    /// Record `hot_keys` (hottest first) in `engine`, unless they haven't changed.
+    pub(crate) fn save<E: Engine>(&mut self, engine: &mut E, hot_keys: Vec<String>) -> Result<()> {
+        self.next_save = Instant::now() + self.config.save_interval;
+        if hot_keys.is_empty() || hot_keys == self.saved {
+            return Ok(());
+        }
+        let value = serde_json::to_string(&hot_keys).expect("Keys are serializable");
+        SystemKeys::new(engine).set(HOT_KEYS, value)?;
+        self.saved = hot_keys;
+        Ok(())
+    }
+
+    /// The number of keys to record.
+    pub(crate) fn max_keys(&self) -> usize {
+        self.config.max_keys
+    }
+}
@@ -0,0 +1,169 @@
+use std::io::{BufWriter, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use slog::debug;
+
+use crate::error::Result;
+use crate::protocol::{encode_response, Change, Request, Response};
+
+/// The connections watching for changes to keys, opened by [`Request::Watch`]es.
+///
+/// [`Request::Watch`]: enum.Request.html#variant.Watch
+pub(crate) struct Watchers {
+    watchers: Vec<Watcher>,
+    writes: u64,
+}
+
+/// A connection watching for changes to keys starting with `prefix`.
+struct Watcher {
+    prefix: String,
+    stream: Box<dyn Write + Send>,
+    log: slog::Logger,
+    last_sent: Instant,
+}
+
+impl Watchers {
+    /// Construct an empty set of watchers.
+    pub(crate) fn new() -> Self {
+        Watchers {
+            watchers: Vec::new(),
+            writes: 0,
+        }
+    }
+
+    /// Send changes to keys starting with `prefix` to `stream` until it's closed.
+    pub(crate) fn watch(
+        &mut self,
+        prefix: String,
+        stream: Box<dyn Write + Send>,
+        log: slog::Logger,
+    ) {
+        self.watchers.push(Watcher { prefix, stream, log, last_sent: Instant::now() });
+    }
+
+    /// Whether no connections are watching.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.watchers.is_empty()
+    }
+
+    /// Send a heartbeat to each connection that hasn't been sent anything for `interval`.
+    ///
+    /// Connections that can't be written to (e.g. because the client has gone) are dropped.
+    pub(crate) fn heartbeat(&mut self, interval: Duration) {
+        self.watchers.retain_mut(|watcher| {
+            watcher.last_sent.elapsed() < interval || watcher.send(&Response::Heartbeat)
+        });
+    }
+
+    /// The writes `request` will make if it responds with `Ok`, each with its change if any
+    /// connection is watching its key.
+    ///
+    /// Each change's sequence number and timestamp are filled in by [`Watchers::applied`].
+    pub(crate) fn changes(&self, request: &Request) -> Vec<Option<Change>> {
+        match request {
+            Request::Set { key, value, .. } => {
+                vec![self.change(key.as_bytes(), Some(value.as_bytes()))]
+            },
+            Request::Remove { key } | Request::ForceRemove { key } => {
+                vec![self.change(key.as_bytes(), None)]
+            },
+            Request::SetBytes { key, value, .. } => vec![self.change(key, Some(value))],
+            Request::RemoveBytes { key } => vec![self.change(key, None)],
+            Request::MultiSet { pairs, .. } => pairs
+                .iter()
+                .map(|(key, value)| self.change(key.as_bytes(), Some(value.as_bytes())))
+                .collect(),
+            Request::Commit { writes, .. } => writes
+                .iter()
+                .map(|(key, value)| {
+                    self.change(key.as_bytes(), value.as_deref().map(str::as_bytes))
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Count a set that's been applied (by a request whose new value isn't known until it's
+    /// handled), and send it to the connections watching its key.
+    pub(crate) fn set<K: Into<Vec<u8>>, V: Into<Vec<u8>>>(&mut self, key: K, value: V) {
+        let key = key.into();
+        let change = if self.is_watched(&key) {
+            Some(Change { seq: 0, timestamp_ms: 0, key, value: Some(value.into()) })
+        } else {
+            None
+        };
+        self.applied(change);
+    }
+
+    /// Count a removal that's been applied, and send it to the connections watching its key.
+    pub(crate) fn removed<K: Into<Vec<u8>>>(&mut self, key: K) {
+        let key = key.into();
+        let change = if self.is_watched(&key) {
+            Some(Change { seq: 0, timestamp_ms: 0, key, value: None })
+        } else {
+            None
+        };
+        self.applied(change);
+    }
+
+    /// Count a write that's been applied, and send its `change` (if it's watched) to the
+    /// connections watching its key.
+    ///
+    /// Connections that can't be written to (e.g. because they've been closed) are dropped.
+    pub(crate) fn applied(&mut self, change: Option<Change>) {
+        self.writes += 1;
+        let mut change = match change {
+            Some(change) => change,
+            None => return,
+        };
+        change.seq = self.writes;
+        change.timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |timestamp| timestamp.as_millis() as u64);
+
+        let key = change.key.clone();
+        let response = Response::Change { change };
+        self.watchers.retain_mut(|watcher| {
+            !key.starts_with(watcher.prefix.as_bytes()) || watcher.send(&response)
+        });
+    }
+}
+
+impl Watchers {
+    /// Whether any connection is watching `key`.
+    pub(crate) fn is_watched(&self, key: &[u8]) -> bool {
+        self.watchers.iter().any(|watcher| key.starts_with(watcher.prefix.as_bytes()))
+    }
+
+    /// The change setting `key` to `value` (or removing it) makes, if it's watched.
+    fn change(&self, key: &[u8], value: Option<&[u8]>) -> Option<Change> {
+        if !self.is_watched(key) {
+            return None;
+        }
+        let value = value.map(<[u8]>::to_vec);
+        Some(Change { seq: 0, timestamp_ms: 0, key: key.to_vec(), value })
+    }
+}
+
+impl Watcher {
+    /// Send `response` to the watching connection, returning whether it's still connected.
+    fn send(&mut self, response: &Response) -> bool {
+        match self.write(response) {
+            Ok(()) => {
+                self.last_sent = Instant::now();
+                true
+            },
+            Err(error) => {
+                debug!(self.log, "Watcher disconnected: {}", error);
+                false
+            },
+        }
+    }
+
+    fn write(&mut self, response: &Response) -> Result<()> {
+        let mut writer = BufWriter::new(&mut self.stream);
+        encode_response(&mut writer, response)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+mod distinct;
+mod top_k;
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub use self::distinct::DistinctCount;
+pub use self::top_k::TopK;
+
+/// The number of buckets in a [`Histogram`].
+///
+/// Bucket `i` counts samples in the range `[2^(i-1), 2^i)` microseconds (bucket 0 counts samples
+/// under 1µs), so the last bucket catches anything slower than ~18 minutes.
+const HISTOGRAM_BUCKETS: usize = 32;
+
+/// The number of buckets in a [`SizeHistogram`], so the last bucket catches anything over 1GiB.
+const SIZE_HISTOGRAM_BUCKETS: usize = 32;
+
+/// A snapshot of a server's statistics, as returned for a [`Request::Stats`].
+///
+/// Counters are cumulative since the server started. Consumers interested in rates (e.g.
+/// `kvs-top`) should diff successive snapshots.
+///
+/// [`Request::Stats`]: enum.Request.html#variant.Stats
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Stats {
+    /// The unique ID of the server's store.
+    pub store_id: String,
+
+    /// The number of seconds since the server started.
+    pub uptime_secs: u64,
+
+    /// Statistics for `Get` requests.
+    pub gets: OpStats,
+
+    /// Statistics for `Set` requests.
+    pub sets: OpStats,
+
+    /// Statistics for `Remove` requests.
+    pub removes: OpStats,
+
+    /// The most frequently requested key prefixes, with approximate request counts, hottest first.
+    pub hot_prefixes: Vec<(String, u64)>,
+
+    /// The number of keys prefetched by warm-up since the server started.
+    pub prefetched_keys: u64,
+
+    /// The distribution of the lengths of keys set, in bytes.
+    pub key_lengths: SizeHistogram,
+
+    /// The distribution of the sizes of values set, in bytes.
+    pub value_sizes: SizeHistogram,
+
+    /// The approximate number of distinct keys set (within a few percent).
+    pub distinct_keys_set: u64,
+
+    /// Totals over the lifetime of the store, including previous runs of the server.
+    ///
+    /// The other statistics cover only the current run.
+    pub lifetime: LifetimeStats,
+
+    /// Statistics reported by the storage engine.
+    pub engine: EngineStats,
+}
+
+/// Cumulative statistics over the lifetime of a store, which survive server restarts.
+///
+/// These are recorded periodically in the store's system keyspace, so counts since the last
+/// recording are lost if the server is killed.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct LifetimeStats {
+    /// The number of `Get` requests handled.
+    pub gets: u64,
+
+    /// The number of `Set` requests handled.
+    pub sets: u64,
+
+    /// The number of `Remove` requests handled.
+    pub removes: u64,
+
+    /// The number of compactions run by the engine.
+    pub compactions: u64,
+
+    /// The number of bytes of commands written by the engine (excluding compactions).
+    pub written_bytes: u64,
+}
+
+/// Statistics for a single kind of request.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct OpStats {
+    /// The number of requests handled.
+    pub count: u64,
+
+    /// The number of requests that resulted in an error.
+    pub errors: u64,
+
+    /// The distribution of time spent handling requests.
+    pub latency: Histogram,
+}
+
+impl OpStats {
+    /// Record a request that took `elapsed`.
+    pub fn record(&mut self, elapsed: Duration, is_err: bool) {
+        self.count += 1;
+        if is_err {
+            self.errors += 1;
+        }
+        self.latency.record(elapsed);
+    }
+}
+
+/// Statistics reported by a storage engine.
+///
+/// Engines report what they can cheaply determine – fields an engine doesn't track are left as `0`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct EngineStats {
+    /// The number of live keys in the store.
+    pub keys: u64,
+
+    /// The estimated memory used by the store's index, in bytes.
+    pub index_bytes: u64,
+
+    /// The number of log files currently in use.
+    pub log_files: u64,
+
+    /// The number of log files currently open for reading.
+    pub open_files: u64,
+
+    /// The number of log files closed to stay within the cap on open files, since the engine was
+    /// opened.
+    pub file_evictions: u64,
+
+    /// The number of bytes in the log that are occupied by stale commands.
+    pub uncompacted_bytes: u64,
+
+    /// The number of bytes in the log, occupied by live or stale commands.
+    pub log_bytes: u64,
+
+    /// Statistics for each log file currently in use.
+    pub files: Vec<LogFileStats>,
+
+    /// The number of compactions run since the engine was opened.
+    pub compactions: u64,
+
+    /// The total number of bytes reclaimed by compactions since the engine was opened.
+    pub compacted_bytes: u64,
+
+    /// The number of bytes of commands written since the engine was opened, excluding those
+    /// written by compactions.
+    pub written_bytes: u64,
+
+    /// The most recent compactions since the engine was opened, oldest first.
+    pub recent_compactions: Vec<CompactionStats>,
+
+    /// The number of compactions put off because the cap on background jobs had been reached.
+    pub deferred_compactions: u64,
+
+    /// The number of writes delayed because compaction had fallen behind.
+    pub slowed_writes: u64,
+
+    /// The number of writes rejected because compaction had fallen too far behind.
+    pub rejected_writes: u64,
+
+    /// The number of removes synced to disk so that they're as durable as the values they removed.
+    pub synced_removes: u64,
+
+    /// Whether the engine was recovered from a crash (rather than a clean shutdown) when opened.
+    pub recovered: bool,
+
+    /// Whether the engine has stopped accepting writes because its disk is full.
+    pub read_only: bool,
+
+    /// The sequence number of the last command written to the store.
+    pub sequence: u64,
+
+    /// The number of already-applied commands that were skipped when the store was opened.
+    pub replay_duplicates: u64,
+
+    /// The number of keys with a time-to-live (including expired keys not yet swept).
+    pub expiring_keys: u64,
+
+    /// The number of keys found to have expired (by a sweep or a read) since the engine was
+    /// opened.
+    pub expired_keys: u64,
+
+    /// The number of reads served from recently written values held in memory, without reading
+    /// the log.
+    pub memtable_hits: u64,
+
+    /// The number of entries re-read and verified by scrubbing since the engine was opened.
+    pub scrubbed_entries: u64,
+
+    /// The number of complete scrubbing passes over every entry since the engine was opened.
+    pub scrub_passes: u64,
+
+    /// The number of entries found to be corrupt by scrubbing since the engine was opened.
+    pub corrupt_entries: u64,
+
+    /// The size of the compression dictionary the next compaction will compress values with, in
+    /// bytes (`0` if there isn't one).
+    pub dictionary_bytes: u64,
+
+    /// The number of values compressed by the latest compaction since the engine was opened.
+    pub compressed_values: u64,
+
+    /// The total size of the values written by the latest compaction since the engine was opened,
+    /// before compression.
+    pub uncompressed_value_bytes: u64,
+
+    /// The total size of the values written by the latest compaction since the engine was opened,
+    /// after compression. Dividing [`uncompressed_value_bytes`] by this gives the compression
+    /// ratio.
+    ///
+    /// [`uncompressed_value_bytes`]: #structfield.uncompressed_value_bytes
+    pub compressed_value_bytes: u64,
+
+    /// The number of times writing moved on to a new log file because the current one reached
+    /// the maximum segment size, since the engine was opened.
+    pub rotations: u64,
+
+    /// The number of times a [follower](struct.KvFollower.html) rebuilt its index because the
+    /// store it follows was compacted, since it was opened.
+    pub follower_rebuilds: u64,
+
+    /// The number of bytes of a command torn by a crash that were truncated from the end of the
+    /// log when the engine was opened (see
+    /// [`KvStoreBuilder::strict_recovery`](struct.KvStoreBuilder.html#method.strict_recovery)).
+    pub torn_tail_bytes: u64,
+
+    /// The number of times syncing the log in the background failed, since the engine was opened
+    /// (see [`SyncPolicy::Every`](enum.SyncPolicy.html#variant.Every)).
+    pub failed_syncs: u64,
+}
+
+/// Statistics for a single log file of a storage engine.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct LogFileStats {
+    /// The file's log index, which identifies it within the store.
+    pub log_index: u64,
+
+    /// The number of bytes of commands in the file.
+    pub bytes: u64,
+
+    /// The number of bytes of commands in the file that hold live values.
+    pub live_bytes: u64,
+
+    /// The number of bytes of stale commands in the file, which compaction would reclaim.
+    pub dead_bytes: u64,
+
+    /// The number of seconds since the file was created.
+    pub age_secs: u64,
+}
+
+/// A record of a single compaction by a storage engine.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CompactionStats {
+    /// When the compaction started, in milliseconds since the UNIX epoch.
+    pub started_at: u64,
+
+    /// When the compaction finished, in milliseconds since the UNIX epoch.
+    pub finished_at: u64,
+
+    /// The number of bytes of stale commands the compaction reclaimed.
+    pub reclaimed_bytes: u64,
+}
+
+/// A latency histogram with exponentially sized buckets.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    total_micros: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: vec![0; HISTOGRAM_BUCKETS],
+            total_micros: 0,
+        }
+    }
+}
+
+impl Histogram {
+    /// Record a sample.
+    pub fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u128::from(u64::MAX)) as u64;
+        let bucket = (64 - micros.leading_zeros() as usize).min(HISTOGRAM_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.total_micros = self.total_micros.saturating_add(micros);
+    }
+
+    /// The number of recorded samples.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The mean of the recorded samples, or zero if there are none.
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::from_micros(0),
+            count => Duration::from_micros(self.total_micros / count),
+        }
+    }
+
+    /// An upper bound for the given percentile (between `0.0` and `100.0`) of recorded samples.
+    ///
+    /// Since samples are bucketed, this is accurate to within a factor of two.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::from_micros(0);
+        }
+
+        let rank = ((percentile / 100.0) * count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Duration::from_micros(1 << bucket);
+            }
+        }
+        Duration::from_micros(1 << (HISTOGRAM_BUCKETS - 1))
+    }
+
+    /// Subtract an earlier snapshot of this histogram, leaving the samples recorded in between.
+    pub fn since(&self, earlier: &Histogram) -> Histogram {
+        Histogram {
+            buckets: self
+                .buckets
+                .iter()
+                .zip(earlier.buckets.iter().chain(std::iter::repeat(&0)))
+                .map(|(now, then)| now.saturating_sub(*then))
+                .collect(),
+            total_micros: self.total_micros.saturating_sub(earlier.total_micros),
+        }
+    }
+}
+
+/// A histogram of sizes in bytes, with exponentially sized buckets.
+///
+/// Bucket `i` counts sizes in the range `[2^(i-1), 2^i)` bytes (bucket 0 counts empty keys or
+/// values).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SizeHistogram {
+    buckets: Vec<u64>,
+    total_bytes: u64,
+}
+
+impl Default for SizeHistogram {
+    fn default() -> Self {
+        SizeHistogram {
+            buckets: vec![0; SIZE_HISTOGRAM_BUCKETS],
+            total_bytes: 0,
+        }
+    }
+}
+
+impl SizeHistogram {
+    /// Record a size.
+    pub fn record(&mut self, bytes: u64) {
+        let bucket = (64 - bytes.leading_zeros() as usize).min(SIZE_HISTOGRAM_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.total_bytes = self.total_bytes.saturating_add(bytes);
+    }
+
+    /// The number of recorded sizes.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The total of the recorded sizes, in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    /// The mean of the recorded sizes, or zero if there are none.
+    pub fn mean(&self) -> u64 {
+        match self.count() {
+            0 => 0,
+            count => self.total_bytes / count,
+        }
+    }
+
+    /// An upper bound for the given percentile (between `0.0` and `100.0`) of recorded sizes.
+    ///
+    /// Since sizes are bucketed, this is accurate to within a factor of two.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+
+        let rank = ((percentile / 100.0) * count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return bucket_limit(bucket);
+            }
+        }
+        bucket_limit(SIZE_HISTOGRAM_BUCKETS - 1)
+    }
+
+    /// The number of sizes recorded in each bucket, with the bucket's (exclusive) upper bound in
+    /// bytes, smallest first.
+    pub fn buckets(&self) -> Vec<(u64, u64)> {
+        self.buckets.iter().enumerate().map(|(bucket, &n)| (bucket_limit(bucket), n)).collect()
+    }
+}
+
+/// The (exclusive) upper bound of a [`SizeHistogram`] bucket.
+fn bucket_limit(bucket: usize) -> u64 {
+    1 << bucket
+}
+
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+/// An approximate tracker of the most frequently observed items, using the SpaceSaving algorithm.
+///
+/// At most `capacity` items are tracked. When a new item is observed and the tracker is full, the
+/// least frequent item is evicted and the new item inherits its count (plus one). This means counts
+/// are over-estimates, but any item that accounts for more than `1/capacity` of observations is
+/// guaranteed to be tracked.
+#[derive(Debug)]
+pub struct TopK {
+    capacity: usize,
+    counts: HashMap<String, u64>,
+}
+
+impl TopK {
+    /// Construct a tracker for at most `capacity` items.
+    pub fn new(capacity: usize) -> Self {
+        TopK {
+            capacity,
+            counts: HashMap::with_capacity(capacity),
+        }
+    }
+
+    /// Record an observation of an item.
+    pub fn observe(&mut self, item: &str) {
+        if let Some(count) = self.counts.get_mut(item) {
+            *count += 1;
+            return;
+        }
+
+        if self.counts.len() < self.capacity {
+            self.counts.insert(item.to_owned(), 1);
+            return;
+        }
+
+        let min = self
+            .counts
+            .iter()
+            .min_by_key(|(_, &count)| count)
+            .map(|(item, &count)| (item.clone(), count));
+        if let Some((evicted, count)) = min {
+            self.counts.remove(&evicted);
+            self.counts.insert(item.to_owned(), count + 1);
+        }
+    }
+
+    /// The `n` most frequently observed items with their approximate counts, most frequent first.
+    pub fn top(&self, n: usize) -> Vec<(String, u64)> {
+        let mut top: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(item, &count)| (item.clone(), count))
+            .collect();
+        top.sort_unstable_by(|(a_item, a), (b_item, b)| b.cmp(a).then_with(|| a_item.cmp(b_item)));
+        top.truncate(n);
+        top
+    }
+}
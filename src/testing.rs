@@ -0,0 +1,159 @@
+//! Test doubles for code that uses kvs, so it can be tested without a real server or store.
+//!
+//! [`MockServer`] runs a real server in-process over the memory engine, with scripted faults.
+//! [`MockClient`] implements [`KvsClient`] with canned responses, and never touches the network.
+//!
+//! [`KvsClient`]: ../trait.KvsClient.html
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::client::{entries_response, ok_response, value_response, Client, KvsClient};
+use crate::engine::MemoryKvStore;
+use crate::error::Result;
+use crate::protocol::{Durability, Request, Response};
+use crate::server::{Chaos, ChaosConfig, Fault, FaultScript, Server};
+
+/// A server listening on a free local port, storing keys in memory.
+///
+/// The server runs on a background thread until the process exits.
+///
+/// ```
+/// use kvs::testing::MockServer;
+/// use kvs::{Fault, KvsClient, Result};
+///
+/// # fn check() -> Result<()> {
+/// let server = MockServer::start()?;
+/// server.fail_next(Fault::Drop);
+/// assert!(server.client()?.get("key".to_owned()).is_err());
+/// assert_eq!(server.client()?.get("key".to_owned())?, None);
+/// # Ok(())
+/// # }
+/// ```
+pub struct MockServer {
+    address: SocketAddr,
+    script: FaultScript,
+}
+
+impl MockServer {
+    /// Start a server with an empty store.
+    pub fn start() -> Result<Self> {
+        let script = FaultScript::new();
+        let chaos = Chaos::new(ChaosConfig::default()).with_script(script.clone());
+        let (sender, receiver) = mpsc::sync_channel(0);
+        thread::spawn(move || {
+            let log = slog::Logger::root(slog::Discard, slog::o!());
+            let server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")
+                .and_then(|server| server.local_addr().map(|address| (server, address)));
+            match server {
+                Ok((server, address)) => {
+                    let _ = sender.send(Ok(address));
+                    server.with_chaos(chaos).run()
+                },
+                Err(error) => {
+                    let _ = sender.send(Err(error));
+                },
+            }
+        });
+        let address = receiver.recv().expect("Mock server thread exited")?;
+        Ok(MockServer { address, script })
+    }
+
+    /// The address the server is listening on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Connect a client to the server.
+    pub fn client(&self) -> Result<Client> {
+        Client::connect(self.address)
+    }
+
+    /// Inject `fault` into the next request that doesn't already have a fault scripted.
+    pub fn fail_next(&self, fault: Fault) {
+        self.script.push(fault);
+    }
+}
+
+/// A [`KvsClient`] that replies to each request with the next of a queue of canned responses, and
+/// records the requests it was sent.
+///
+/// Responses are interpreted just as a [`Client`] would interpret them from a server.
+///
+/// ```
+/// use kvs::testing::MockClient;
+/// use kvs::{KvsClient, Request, Response, Result};
+///
+/// # fn check() -> Result<()> {
+/// let mut client = MockClient::new();
+/// client.respond(Response::Found { value: "bar".to_owned() });
+/// assert_eq!(client.get("foo".to_owned())?, Some("bar".to_owned()));
+/// assert!(matches!(client.requests(), [Request::Get { key }] if key == "foo"));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Panics
+///
+/// Requests panic if no response has been queued for them.
+#[derive(Debug, Default)]
+pub struct MockClient {
+    responses: VecDeque<Response>,
+    requests: Vec<Request>,
+}
+
+impl MockClient {
+    /// Construct a client with no responses queued.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a response for the next request without one.
+    pub fn respond(&mut self, response: Response) -> &mut Self {
+        self.responses.push_back(response);
+        self
+    }
+
+    /// The requests sent so far, in order.
+    pub fn requests(&self) -> &[Request] {
+        &self.requests
+    }
+
+    /// Record a request and take its response.
+    fn send(&mut self, request: &Request) -> Response {
+        let response = match self.responses.pop_front() {
+            Some(response) => response,
+            None => panic!("MockClient has no response queued for {:?}", request),
+        };
+        self.requests.push(request.clone());
+        response
+    }
+}
+
+impl KvsClient for MockClient {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        let request = Request::Get { key };
+        let response = self.send(&request);
+        value_response(request, response)
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        let request = Request::Set { key, value, ttl_ms: None, durability: Durability::Applied };
+        let response = self.send(&request);
+        ok_response(request, response)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        let request = Request::Remove { key };
+        let response = self.send(&request);
+        ok_response(request, response)
+    }
+
+    fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        let request = Request::Scan { prefix };
+        let response = self.send(&request);
+        entries_response(request, response)
+    }
+}
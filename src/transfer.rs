@@ -0,0 +1,138 @@
+use std::thread;
+
+use crate::client::KvsClient;
+use crate::engine::SYSTEM_KEY_PREFIX;
+use crate::error::Result;
+
+/// The default number of entries written between progress reports.
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// The default number of connections each batch is written over.
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Copies the entries with a given prefix from one store to another, e.g. to move data between
+/// servers without an intermediate dump.
+///
+/// Entries are scanned from the source in key order, and written to the destination in batches,
+/// with each batch's writes spread over several concurrent clients. Progress is reported after
+/// each batch, and an interrupted transfer can be picked up again from the last key reported with
+/// [`Transfer::resume_after`].
+///
+/// ```
+/// use kvs::{EmbeddedClient, KvsClient, MemoryKvStore, Transfer};
+///
+/// # fn main() -> kvs::Result<()> {
+/// let mut source = EmbeddedClient::new(MemoryKvStore::new());
+/// source.set("user:1".to_owned(), "alice".to_owned())?;
+/// source.set("group:1".to_owned(), "admins".to_owned())?;
+///
+/// let connect = || Ok(EmbeddedClient::new(MemoryKvStore::new()));
+/// let progress = Transfer::new("user:").run(&mut source, connect, |_| {})?;
+/// assert_eq!(progress.copied, 1);
+/// assert_eq!(progress.last_key, Some("user:1".to_owned()));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Transfer {
+    prefix: String,
+    batch_size: usize,
+    concurrency: usize,
+    resume_after: Option<String>,
+}
+
+/// How far a [`Transfer`] has got.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransferProgress {
+    /// The number of entries copied so far.
+    pub copied: u64,
+
+    /// The last key copied, after which every entry has yet to be copied.
+    pub last_key: Option<String>,
+}
+
+impl Transfer {
+    /// Construct a transfer of the entries whose keys start with `prefix`.
+    pub fn new(prefix: &str) -> Self {
+        Transfer {
+            prefix: prefix.to_owned(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            resume_after: None,
+        }
+    }
+
+    /// Write `batch_size` entries (1000 by default) between progress reports.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Spread each batch's writes over `concurrency` clients (4 by default).
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Only copy entries whose keys sort after `key`, e.g. the last key reported by an
+    /// interrupted transfer.
+    pub fn resume_after(mut self, key: String) -> Self {
+        self.resume_after = Some(key);
+        self
+    }
+
+    /// Copy the entries from `source` to the destination, using clients made with `connect`.
+    ///
+    /// `progress` is called after each batch has been written. If a write fails, the transfer
+    /// stops with its error, and the last progress reported is where it can be resumed from.
+    /// System keys are never copied.
+    pub fn run<S, C, D, P>(
+        &self,
+        source: &mut S,
+        connect: C,
+        mut progress: P,
+    ) -> Result<TransferProgress>
+    where
+        S: KvsClient,
+        C: Fn() -> Result<D>,
+        D: KvsClient + Send,
+        P: FnMut(&TransferProgress),
+    {
+        let mut entries = source.scan(self.prefix.clone())?;
+        entries.retain(|(key, _)| {
+            let pending = match self.resume_after {
+                Some(ref after) => key > after,
+                None => true,
+            };
+            pending && !key.starts_with(SYSTEM_KEY_PREFIX)
+        });
+        let mut clients = (0..self.concurrency).map(|_| connect()).collect::<Result<Vec<_>>>()?;
+
+        let mut report = TransferProgress { copied: 0, last_key: self.resume_after.clone() };
+        for batch in entries.chunks(self.batch_size) {
+            let per_client = batch.len().div_ceil(clients.len());
+            thread::scope(|scope| {
+                let workers: Vec<_> = batch
+                    .chunks(per_client)
+                    .zip(clients.iter_mut())
+                    .map(|(entries, client)| {
+                        scope.spawn(move || -> Result<()> {
+                            for (key, value) in entries {
+                                client.set(key.clone(), value.clone())?;
+                            }
+                            Ok(())
+                        })
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .try_for_each(|worker| worker.join().expect("Transfer worker panicked"))
+            })?;
+
+            report.copied += batch.len() as u64;
+            report.last_key = batch.last().map(|(key, _)| key.clone());
+            progress(&report);
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,135 @@
+use kvs::r#async;
+use kvs::{decode_response, encode_request, Response};
+use kvs::{Client, Durability, MemoryKvStore, Request, Result, Server};
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// Start an asynchronous server on a free port, returning its address and the runtime it's
+/// running on.
+fn start() -> Result<(SocketAddr, Runtime)> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let server = r#async::Server::new(Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?)?;
+    let addr = server.local_addr()?;
+    let runtime = Runtime::new()?;
+    runtime.spawn(server.run());
+    Ok((addr, runtime))
+}
+
+// Should set, get, scan and remove keys through an asynchronous client
+#[test]
+fn round_trip() -> Result<()> {
+    let (addr, runtime) = start()?;
+    runtime.block_on(async {
+        let mut client = r#async::Client::connect(addr).await?;
+        client.set("user:1".to_owned(), "alice".to_owned()).await?;
+        client.set("user:2".to_owned(), "bob".to_owned()).await?;
+        assert_eq!(client.get("user:1".to_owned()).await?, Some("alice".to_owned()));
+        assert_eq!(client.get("user:3".to_owned()).await?, None);
+        assert_eq!(client.increment("count".to_owned(), 2).await?, 2);
+
+        client.remove("user:1".to_owned()).await?;
+        assert_eq!(
+            client.scan("user:".to_owned()).await?,
+            vec![("user:2".to_owned(), "bob".to_owned())]
+        );
+        client.set_bytes(&[0xff, 0x01], &[0xc3, 0x28]).await?;
+        assert_eq!(client.get_bytes(&[0xff, 0x01]).await?, Some(vec![0xc3, 0x28]));
+        assert_eq!(client.scan_bytes(&[0xff]).await?.len(), 1);
+        client.remove_bytes(&[0xff, 0x01]).await?;
+        assert_eq!(client.info().await?.engine, "memory");
+        client.ping().await?;
+        Ok(())
+    })
+}
+
+// Requests from many clients at once should all be handled
+#[test]
+fn concurrent_clients() -> Result<()> {
+    let (addr, runtime) = start()?;
+    runtime.block_on(async {
+        let tasks: Vec<_> = (0..100)
+            .map(|i| {
+                tokio::spawn(async move {
+                    let mut client = r#async::Client::connect(addr).await?;
+                    client.set(format!("key{}", i), format!("value{}", i)).await?;
+                    client.get(format!("key{}", i)).await
+                })
+            })
+            .collect();
+        for (i, task) in tasks.into_iter().enumerate() {
+            assert_eq!(task.await.expect("task panicked")?, Some(format!("value{}", i)));
+        }
+        Ok(())
+    })
+}
+
+// The asynchronous and synchronous clients and servers should each work with the other
+#[test]
+fn interoperates_with_sync() -> Result<()> {
+    let (addr, runtime) = start()?;
+    let mut client = Client::connect(addr)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let addr = server.local_addr()?;
+    thread::spawn(move || server.run());
+    runtime.block_on(async {
+        let mut client = r#async::Client::connect(addr).await?;
+        client.set("key".to_owned(), "value".to_owned()).await?;
+        assert_eq!(client.get("key".to_owned()).await?, Some("value".to_owned()));
+        Ok(())
+    })
+}
+
+// A client that's slow to send its request shouldn't hold up the others
+#[test]
+fn slow_client() -> Result<()> {
+    let (addr, runtime) = start()?;
+    let durability = Durability::Applied;
+    let (key, value) = ("slow".to_owned(), "1".to_owned());
+    let request = Request::Set { key, value, ttl_ms: None, durability };
+    let mut bytes = Vec::new();
+    encode_request(&mut bytes, &request)?;
+    let mut slow = TcpStream::connect(addr)?;
+    slow.write_all(&bytes[..bytes.len() / 2])?;
+
+    runtime.block_on(async {
+        let mut client = r#async::Client::connect(addr).await?;
+        let fast = client.set("fast".to_owned(), "1".to_owned());
+        tokio::time::timeout(Duration::from_secs(5), fast).await.expect("held up")?;
+        Ok::<_, kvs::Error>(())
+    })?;
+
+    slow.write_all(&bytes[bytes.len() / 2..])?;
+    assert!(matches!(decode_response(&mut slow)?, Response::Ok));
+    assert_eq!(Client::connect(addr)?.get("slow".to_owned())?, Some("1".to_owned()));
+    Ok(())
+}
+
+// Watches should see the changes made through an asynchronous server
+#[test]
+fn watch() -> Result<()> {
+    let (addr, runtime) = start()?;
+    let mut watch = Client::connect(addr)?.watch("user:".to_owned())?;
+    runtime.block_on(async {
+        let mut client = r#async::Client::connect(addr).await?;
+        client.set("user:1".to_owned(), "alice".to_owned()).await
+    })?;
+    let change = watch.next().expect("watch ended")?;
+    assert_eq!((change.key, change.value), (b"user:1".to_vec(), Some(b"alice".to_vec())));
+    Ok(())
+}
+
+// In-process servers have no connections to serve asynchronously
+#[test]
+fn in_process_server() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, _client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    assert!(r#async::Server::new(server).is_err());
+    Ok(())
+}
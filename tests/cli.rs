@@ -1,10 +1,13 @@
+#![allow(clippy::needless_borrows_for_generic_args, clippy::zombie_processes)]
+
 use assert_cmd::prelude::*;
+use kvs::{KvStore, KvsEngine, ManualClock};
 use predicates::str::{contains, is_empty};
 use std::fs::{self, File};
 use std::process::Command;
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 
 // `kvs-client` with no args should exit with a non-zero code.
@@ -118,6 +121,40 @@ fn client_cli_invalid_rm() {
         .failure();
 }
 
+#[test]
+fn client_cli_invalid_query() {
+    let temp_dir = TempDir::new().unwrap();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["query"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["query", "GET user:* WHERE ttl_expired"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Invalid query"));
+}
+
+// `kvs-client set --ttl` should reject TTLs that aren't a valid number of seconds
+#[test]
+fn client_cli_invalid_ttl() {
+    let temp_dir = TempDir::new().unwrap();
+    for ttl in &["-1", "nan", "inf", "1e30"] {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["set", "key", "value", &format!("--ttl={}", ttl)])
+            .current_dir(&temp_dir)
+            .assert()
+            .failure()
+            .stderr(contains("isn't a valid number of seconds"));
+    }
+}
+
 #[test]
 fn client_cli_invalid_subcommand() {
     let temp_dir = TempDir::new().unwrap();
@@ -151,6 +188,34 @@ fn server_cli_version() {
         .stdout(contains(env!("CARGO_PKG_VERSION")));
 }
 
+// `kvs-client completions <shell>` and `kvs-server completions <shell>` should print completions
+#[test]
+fn cli_completions() {
+    let temp_dir = TempDir::new().unwrap();
+    for bin in &["kvs-client", "kvs-server"] {
+        Command::cargo_bin(bin)
+            .unwrap()
+            .args(["completions", "bash"])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+            .stdout(contains(format!("_{}()", bin)));
+        Command::cargo_bin(bin)
+            .unwrap()
+            .args(["completions", "zsh"])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+            .stdout(contains(format!("#compdef {}", bin)));
+    }
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["completions", "tcsh"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
 #[test]
 fn cli_log_configuration() {
     let temp_dir = TempDir::new().unwrap();
@@ -212,12 +277,44 @@ fn cli_wrong_engine() {
     }
 }
 
-fn cli_access_server(engine: &str, addr: &str) {
+// `kvs-server` should detect another engine's data files even if the engine marker is missing,
+// unless `--force-engine` is given.
+#[test]
+fn cli_detect_engine() {
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let mut store = KvStore::open(temp_dir.path()).unwrap();
+        store.set("key".to_owned(), "value".to_owned()).unwrap();
+    }
+    assert!(!temp_dir.path().join("engine").exists());
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    cmd.args(["--engine", "sled", "--addr", "127.0.0.1:4017"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Wrong engine: sled was requested, but the data is for kvs"));
+    assert!(!temp_dir.path().join("engine").exists());
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--engine", "sled", "--addr", "127.0.0.1:4017", "--force-engine"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait for server");
+    assert_eq!(fs::read_to_string(temp_dir.path().join("engine")).unwrap(), "sled");
+}
+
+fn cli_access_server(engine: &str, addr: &str, options: &[&str]) {
     let (sender, receiver) = mpsc::sync_channel(0);
     let temp_dir = TempDir::new().unwrap();
     let mut server = Command::cargo_bin("kvs-server").unwrap();
     let mut child = server
         .args(&["--engine", engine, "--addr", addr])
+        .args(options)
         .current_dir(&temp_dir)
         .spawn()
         .unwrap();
@@ -328,10 +425,1015 @@ fn cli_access_server(engine: &str, addr: &str) {
 
 #[test]
 fn cli_access_server_kvs_engine() {
-    cli_access_server("kvs", "127.0.0.1:4004");
+    cli_access_server("kvs", "127.0.0.1:4004", &[]);
 }
 
 #[test]
 fn cli_access_server_sled_engine() {
-    cli_access_server("sled", "127.0.0.1:4005");
+    cli_access_server("sled", "127.0.0.1:4005", &[]);
+}
+
+#[test]
+fn cli_access_async_server() {
+    cli_access_server("kvs", "127.0.0.1:4024", &["--async"]);
+}
+
+#[test]
+fn cli_top_once() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4006";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "user:1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-top")
+        .unwrap()
+        .args(["--once", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("HOT PREFIXES"))
+        .stdout(contains("\"user:\""));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// `kvs-top --interval` should reject intervals that aren't a positive number of seconds
+#[test]
+fn cli_top_invalid_interval() {
+    let temp_dir = TempDir::new().unwrap();
+    for interval in &["0", "-1", "nan", "1e30", "soon"] {
+        Command::cargo_bin("kvs-top")
+            .unwrap()
+            .args(["--once", &format!("--interval={}", interval)])
+            .current_dir(&temp_dir)
+            .assert()
+            .failure()
+            .stderr(contains("Invalid value"));
+    }
+}
+
+#[test]
+fn cli_hot_keys() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4007";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    for key in &["hot", "hot", "hot", "cold"] {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["get", key, "--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .success();
+    }
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["hot-keys", "--limit", "1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("3\thot\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn cli_sample_requests() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let sample_path = temp_dir.path().join("samples.json");
+    let addr = "127.0.0.1:4008";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr, "--sample-rate", "1", "--sample-file"])
+        .arg(&sample_path)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key2", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+
+    let content = fs::read_to_string(&sample_path).expect("unable to read sample file");
+    let lines: Vec<_> = content.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains(r#""op":"set""#));
+    assert!(lines[0].contains(r#""value_size":6"#));
+    assert!(lines[1].contains(r#""response":"not_found""#));
+    assert!(!content.contains("key1"));
+    assert!(lines[0].contains(r#""peer":"127.0.0.1:"#));
+    assert!(lines[0].contains(r#""connection_id":1"#));
+    assert!(lines[1].contains(r#""connection_id":2"#));
+}
+
+#[test]
+fn cli_replay() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let capture_path = temp_dir.path().join("capture.json");
+    fs::write(
+        &capture_path,
+        concat!(
+            r#"{"timestamp_micros":1000,"op":"set","key_hash":1,"key_size":4,"value_size":3,"latency_micros":10,"response":"ok","slow":false}"#, "\n",
+            r#"{"timestamp_micros":2000,"op":"get","key_hash":1,"key_size":4,"value_size":0,"latency_micros":10,"response":"found","slow":false}"#, "\n",
+            r#"{"timestamp_micros":3000,"op":"admin","key_hash":null,"key_size":0,"value_size":0,"latency_micros":10,"response":"admin","slow":false}"#, "\n",
+        ),
+    )
+    .unwrap();
+
+    let addr = "127.0.0.1:4009";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-replay")
+        .unwrap()
+        .args(["--speed", "0", "--addr", addr])
+        .arg(&capture_path)
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("replayed 2 requests"))
+        .stdout(contains("1 skipped, 0 errors"));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "replay-0000000000000001", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("xxx\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn cli_upgrade() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // A version 1 log file containing `Set { value: "v", key: "k" }`.
+    fs::write(temp_dir.path().join("0.log"), b"\x92\x00\x92\xa1v\xa1k").unwrap();
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["upgrade", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("upgraded 1 log files"));
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["upgrade", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("already at format version"));
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["upgrade", "missing"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn cli_fsck() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // A version 1 log file containing `Set { value: "v", key: "k" }`, followed by garbage.
+    fs::write(temp_dir.path().join("0.log"), b"\x92\x00\x92\xa1v\xa1k\xc1").unwrap();
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["fsck", "--dry-run", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stdout(contains(r#""action": "truncate""#))
+        .stdout(contains(r#""repaired": false"#));
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["fsck", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains(r#""repaired": true"#));
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["fsck", "--dry-run", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains(r#""clean": true"#));
+}
+
+// `kvs fsck` should leave keys in the system keyspace out of its key count
+#[test]
+fn cli_fsck_system_keys() {
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let mut store = KvStore::open(temp_dir.path()).unwrap();
+        store.set("key".to_owned(), "value".to_owned()).unwrap();
+        store.set("__kvs/custom".to_owned(), "value".to_owned()).unwrap();
+    }
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["fsck", "--dry-run", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("\"keys\": 1,"));
+}
+
+#[test]
+fn cli_compact() {
+    let temp_dir = TempDir::new().unwrap();
+    let clock = ManualClock::new(1_700_000_000_000);
+    let mut store = KvStore::builder().clock(clock.clone()).open(temp_dir.path()).unwrap();
+    for iter in 0..100 {
+        store.set("key".to_owned(), format!("value{}", iter)).unwrap();
+    }
+    store.set_with_ttl("session".to_owned(), "s".to_owned(), Duration::from_secs(1)).unwrap();
+    drop(store);
+    let log_bytes = || -> u64 {
+        fs::read_dir(&temp_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "log"))
+            .map(|path| fs::metadata(path).unwrap().len())
+            .sum()
+    };
+    let before = log_bytes();
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["compact", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("removing 1 expired keys"));
+    assert!(log_bytes() < before / 10);
+
+    let mut store = KvStore::open(temp_dir.path()).unwrap();
+    assert_eq!(store.get("key".to_owned()).unwrap(), Some("value99".to_owned()));
+    assert_eq!(store.get("session".to_owned()).unwrap(), None);
+    drop(store);
+
+    // A corrupt directory isn't compacted.
+    let log = fs::read_dir(&temp_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|extension| extension == "log"))
+        .unwrap();
+    let mut contents = fs::read(&log).unwrap();
+    contents.push(0xc1);
+    fs::write(&log, contents).unwrap();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["compact", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("kvs fsck"));
+}
+
+#[test]
+fn cli_dump() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // 2023-11-14, then 2024-02-12.
+    let clock = ManualClock::new(1_700_000_000_000);
+    let mut store = KvStore::builder().clock(clock.clone()).open(temp_dir.path()).unwrap();
+    store.set("user:1".to_owned(), "alice".to_owned()).unwrap();
+    store.set("group:1".to_owned(), "admins".to_owned()).unwrap();
+    clock.advance(Duration::from_secs(90 * 24 * 60 * 60));
+    store.set("user:2".to_owned(), "bob".to_owned()).unwrap();
+    drop(store);
+
+    let dump = |args: &[&str]| {
+        let output = Command::cargo_bin("kvs")
+            .unwrap()
+            .arg("dump")
+            .args(args)
+            .arg(".")
+            .current_dir(&temp_dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(
+        dump(&[]),
+        concat!(
+            r#"{"key":"group:1","value":"admins"}"#, "\n",
+            r#"{"key":"user:1","value":"alice"}"#, "\n",
+            r#"{"key":"user:2","value":"bob"}"#, "\n",
+        )
+    );
+    assert_eq!(
+        dump(&["--prefix", "user:", "--modified-since", "2024-01-01"]),
+        concat!(r#"{"key":"user:2","value":"bob"}"#, "\n")
+    );
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["dump", "--modified-since", "yesterday", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// `kvs-verify-backup` checks a backup's consistency, and spot-checks its keys against a live store.
+#[test]
+fn cli_verify_backup() {
+    let temp_dir = TempDir::new().unwrap();
+    let (backup, live) = (temp_dir.path().join("backup"), temp_dir.path().join("live"));
+    for (dir, alice) in [(&backup, "alice"), (&live, "alicia")] {
+        let mut store = KvStore::open(dir).unwrap();
+        store.set("user:1".to_owned(), alice.to_owned()).unwrap();
+        store.set("user:2".to_owned(), "bob".to_owned()).unwrap();
+        store.set("group:1".to_owned(), "admins".to_owned()).unwrap();
+    }
+
+    Command::cargo_bin("kvs-verify-backup")
+        .unwrap()
+        .arg(&backup)
+        .assert()
+        .success()
+        .stdout(contains(r#""pass": true"#))
+        .stdout(contains(r#""spot_check": null"#));
+
+    Command::cargo_bin("kvs-verify-backup")
+        .unwrap()
+        .args([&backup, &backup])
+        .assert()
+        .success()
+        .stdout(contains(r#""checked": 3"#));
+
+    Command::cargo_bin("kvs-verify-backup")
+        .unwrap()
+        .args([&backup, &live])
+        .args(["--spot-checks", "10"])
+        .assert()
+        .failure()
+        .stdout(contains(r#""pass": false"#))
+        .stdout(contains(r#""live": "alicia""#));
+
+    for entry in fs::read_dir(&backup).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|extension| extension == "log") {
+            let mut contents = fs::read(&path).unwrap();
+            contents.push(0xc1);
+            fs::write(&path, contents).unwrap();
+        }
+    }
+    Command::cargo_bin("kvs-verify-backup")
+        .unwrap()
+        .arg(&backup)
+        .assert()
+        .failure()
+        .stdout(contains(r#""clean": false"#));
+}
+
+#[test]
+fn cli_key_rules() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4010";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr])
+        .args(["--max-key-length", "8", "--key-charset", "printable"])
+        .args(["--reserved-key-prefix", "__sys/", "--reserved-key-prefix", "__tmp/"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    let rejected = [
+        ("too-long-key", "longer than the maximum of 8"),
+        ("a key", "disallowed character ' ' at byte 1"),
+        ("__tmp/a", "reserved prefix \"__tmp/\""),
+    ];
+    for (key, reason) in &rejected {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["set", key, "value", "--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .failure()
+            .stderr(contains(*reason));
+    }
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key", "value", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn cli_system_keys() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4011";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    // System keys can be read, but not modified.
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "__kvs/schema_version", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    for args in &[&["set", "__kvs/store_id", "mine"][..], &["rm", "__kvs/store_id"][..]] {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(*args)
+            .args(["--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .failure()
+            .stderr(contains("reserved system keyspace"));
+    }
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "__kvs/store_id", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("-4"));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn cli_tenant_quotas() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4012";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr])
+        .args(["--tenant-quota", "app1=keys:2,bytes:100"])
+        .args(["--tenant-quota", "*=rps:0.1"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    let set = |key: &str, value: &str| {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["set", key, value, "--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+    };
+
+    set("app1/a", "1").success();
+    set("app1/b", "2").success();
+    set("app1/c", "3").failure().stderr(contains("\"app1\" has reached its key quota"));
+    set("app1/a", &"x".repeat(100)).failure().stderr(contains("reached its byte quota"));
+    set("app1/a", "4").success();
+
+    set("app2/a", "1").success();
+    set("app2/b", "2").failure().stderr(contains("\"app2\" has exceeded its request rate"));
+
+    // Keys without a tenant aren't subject to quotas.
+    set("c", "3").success();
+    set("d", "4").success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["tenants", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("app1\t2/2\t14/100\t5\t2\t-\n"))
+        .stdout(contains("app2\t1\t7\t2\t1\t0.1/s\n"));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// `kvs-server --config` stores each configured bucket in its own engine.
+#[test]
+fn cli_bucket_engines() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4013";
+    fs::write(
+        temp_dir.path().join("kvs.toml"),
+        format!(
+            "addr = \"{}\"\nengine = \"kvs\"\n\n[buckets.cache]\nengine = \"memory\"\n\n\
+             [buckets.data]\nengine = \"sled\"\n",
+            addr
+        ),
+    )
+    .unwrap();
+
+    let start_server = || {
+        let (sender, receiver) = mpsc::sync_channel::<()>(0);
+        let mut child = Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--config", "kvs.toml"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        let handle = thread::spawn(move || {
+            let _ = receiver.recv(); // wait for main thread to finish
+            child.kill().expect("server exited before killed");
+            child.wait().expect("failed to wait for server");
+        });
+        thread::sleep(Duration::from_secs(1));
+        (sender, handle)
+    };
+    let client = |args: &[&str]| {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(args)
+            .args(["--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+    };
+
+    let (sender, handle) = start_server();
+    client(&["set", "cache/a", "1"]);
+    client(&["set", "data/a", "2"]);
+    client(&["set", "other/a", "3"]);
+    client(&["get", "cache/a"]).stdout("1\n");
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+
+    assert_eq!(fs::read_to_string(temp_dir.path().join("engine")).unwrap(), "kvs");
+    let data_engine = temp_dir.path().join("buckets").join("data").join("engine");
+    assert_eq!(fs::read_to_string(data_engine).unwrap(), "sled");
+    assert!(!temp_dir.path().join("buckets").join("cache").exists());
+
+    // Only the memory bucket's keys are lost on restart.
+    let (sender, handle) = start_server();
+    client(&["get", "cache/a"]).stdout("Key not found\n");
+    client(&["get", "data/a"]).stdout("2\n");
+    client(&["get", "other/a"]).stdout("3\n");
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// `kvs-client set --ttl` and `kvs-client expire` make keys expire, to be swept by the server, and
+// `kvs-client ttl` and `kvs-client persist` inspect and remove expiries.
+#[test]
+fn cli_ttl() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4014";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr, "--sweep-interval-ms", "10"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    let client = |args: &[&str]| {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(args)
+            .args(["--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+    };
+
+    client(&["set", "session", "abc", "--ttl", "0.5"]);
+    client(&["set", "user", "def"]);
+    client(&["set", "token", "ghi"]);
+    client(&["get", "session"]).stdout("abc\n");
+    client(&["ttl", "user"]).stdout("No expiry\n");
+    client(&["expire", "token", "0.5"]);
+    client(&["expire", "user", "60"]);
+    let output = client(&["ttl", "user"]).get_output().stdout.clone();
+    let ttl: f64 = String::from_utf8(output).unwrap().trim().parse().unwrap();
+    assert!(ttl > 50.0 && ttl <= 60.0, "unexpected TTL {}", ttl);
+    client(&["persist", "user"]);
+    client(&["ttl", "user"]).stdout("No expiry\n");
+    thread::sleep(Duration::from_secs(1));
+    client(&["get", "session"]).stdout("Key not found\n");
+    client(&["get", "token"]).stdout("Key not found\n");
+    client(&["get", "user"]).stdout("def\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+
+    // The sweeper removed the expired key from the log.
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["fsck", "--dry-run", "."])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("\"keys\": 1,"));
+}
+
+// `kvs-server --chaos` delays requests and injects faults, and rejects invalid settings.
+#[test]
+fn cli_chaos() {
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--chaos", "latency=fast"])
+        .assert()
+        .failure()
+        .stderr(contains("invalid value for latency"));
+
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4015";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr, "--chaos", "latency=200ms,error=1"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    let start = Instant::now();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key", "value", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Injected error"));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// `kvs-server --log-file` writes its logs to the file, in JSON with `--log-format json`.
+#[test]
+fn cli_log_file() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let log_path = temp_dir.path().join("server.log");
+    let addr = "127.0.0.1:4018";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr, "--log-format", "json", "--log-file"])
+        .arg(&log_path)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+
+    let content = fs::read_to_string(&log_path).expect("unable to read log file");
+    let first = content.lines().next().expect("nothing was logged");
+    assert!(first.starts_with('{'));
+    assert!(first.contains(r#""msg":"Starting engine""#));
+    assert!(first.contains(r#""engine":"kvs""#));
+}
+
+// `kvs-client rm --prefix` only reports the count with `--dry-run`, and needs `--yes` to remove
+#[test]
+fn cli_remove_prefix() {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4019";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    for key in &["session:1", "session:2", "user:1"] {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["set", key, "value", "--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .success();
+    }
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "--prefix", "session:", "--dry-run", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("Would remove 2 keys\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "--prefix", "session:", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("--yes"));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "--prefix", "session:", "--yes", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("Removed 2 keys\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "session:1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("Key not found\n");
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// Both binaries read settings from the environment, which override the config file and are
+// overridden by flags.
+#[test]
+fn cli_env_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4020";
+    let data_dir = temp_dir.path().join("data");
+    fs::write(
+        temp_dir.path().join("kvs.toml"),
+        concat!(
+            "addr = \"127.0.0.1:4021\"\nengine = \"memory\"\n",
+            "log_level = \"debug\"\nsync_policy = \"os\"\n",
+        ),
+    )
+    .unwrap();
+
+    // An invalid level fails, even though the config file sets a valid one.
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--config", "kvs.toml"])
+        .env("KVS_LOG_LEVEL", "loud")
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("invalid log level \"loud\""));
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--config", "kvs.toml"])
+        .env("KVS_SYNC_POLICY", "sometimes")
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("invalid sync policy \"sometimes\""));
+
+    let (sender, receiver) = mpsc::sync_channel::<()>(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--config", "kvs.toml", "--engine", "sled"])
+        .env("KVS_ADDR", addr)
+        .env("KVS_ENGINE", "kvs")
+        .env("KVS_DATA_DIR", &data_dir)
+        .env("KVS_LOG_LEVEL", "warning")
+        .env("KVS_SYNC_POLICY", "always")
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1"])
+        .env("KVS_ADDR", addr)
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", addr])
+        .env("KVS_ADDR", "127.0.0.1:4021")
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1"])
+        .env("KVS_ADDR", "127.0.0.1:4021")
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+
+    assert_eq!(fs::read_to_string(data_dir.join("engine")).unwrap(), "sled");
+    assert!(!temp_dir.path().join("engine").exists());
+}
+
+// `kvs-client` exits with a distinct code for each kind of failure.
+#[test]
+fn cli_exit_codes() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4022";
+    let client = |args: &[&str]| {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(args)
+            .args(["--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+    };
+
+    client(&["get", "key1"]).code(4);
+    client(&["set", "key1"]).code(2);
+    client(&["set", "key1", "value1", "--ttl", "soon"]).code(2);
+    client(&["set", "key1", "value1", "--ttl=-1"]).code(2);
+    client(&["expire", "key1", "NaN"]).code(2);
+    client(&["maintenance", "inf"]).code(2);
+
+    let (sender, receiver) = mpsc::sync_channel::<()>(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    client(&["rm", "key1"]).code(3).stderr(contains("Key not found"));
+    client(&["query", "FETCH everything"]).code(2);
+    client(&["set", "key1", "value1"]).code(0);
+    client(&["rm", "key1"]).code(0);
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// `kvs-client scan` should list the keys with a prefix, or in a range, with their values
+#[test]
+fn cli_scan() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4023";
+    let (sender, receiver) = mpsc::sync_channel::<()>(0);
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    let handle = thread::spawn(move || {
+        let _ = receiver.recv(); // wait for main thread to finish
+        child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait for server");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    let client = |args: &[&str]| {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(args)
+            .args(["--addr", addr])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+    };
+    for key in ["user:1", "user:2", "user:3", "group:1"] {
+        client(&["set", key, "value"]);
+    }
+    client(&["scan", "user:"]).stdout("user:1\tvalue\nuser:2\tvalue\nuser:3\tvalue\n");
+    client(&["scan", "--from", "user:2"]).stdout("user:2\tvalue\nuser:3\tvalue\n");
+    client(&["scan", "--from", "group:", "--to", "user:2"])
+        .stdout("group:1\tvalue\nuser:1\tvalue\n");
+    client(&["scan"]).stdout(contains("group:1\tvalue\nuser:1"));
+
+    sender.send(()).unwrap();
+    handle.join().unwrap();
 }
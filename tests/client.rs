@@ -0,0 +1,1162 @@
+use kvs::{CacheConfig, CachingClient, Chaos, ChaosConfig, Client, ClientBuilder, EmbeddedClient};
+use kvs::{Buckets, Error, Fault, FaultScript, WriteOnce};
+use kvs::{KeyCharset, KeyRules, TenantQuota, Tenants};
+use kvs::{KvStore, KvsClient, KvsEngine, ManualClock, MemoryKvStore, Mirror, MirrorConfig};
+use kvs::{Durability, ErrorKind, NotFoundReason, Peer, Query, RequestInterceptor, Result};
+use kvs::{Rebalance, RebalanceProgress, Ring, Server, ServerConfig, SharedEngine};
+use kvs::{Sweeper, SweeperConfig, Transfer, TransferProgress, ValueFormat, Warmup, WarmupConfig};
+use kvs::{PROTOCOL_VERSION, SYSTEM_KEY_PREFIX};
+use kvs::{decode_response, encode_request, Request, Response};
+use kvs::{content_key, value_version, IfModified, CONTENT_KEY_PREFIX};
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// Exercise a client, which must start out empty.
+fn exercise<C: KvsClient>(client: &mut C) -> Result<()> {
+    client.set("user:2".to_owned(), "bob".to_owned())?;
+    client.set("user:1".to_owned(), "alice".to_owned())?;
+    client.set("group:1".to_owned(), "admins".to_owned())?;
+    assert_eq!(client.get("user:1".to_owned())?, Some("alice".to_owned()));
+    assert_eq!(client.get("user:3".to_owned())?, None);
+
+    assert_eq!(
+        client.scan("user:".to_owned())?,
+        vec![
+            ("user:1".to_owned(), "alice".to_owned()),
+            ("user:2".to_owned(), "bob".to_owned()),
+        ]
+    );
+
+    client.remove("user:1".to_owned())?;
+    match client.remove("user:1".to_owned()) {
+        Err(Error::KeyNotFound) => {},
+        result => panic!("expected KeyNotFound, got {:?}", result),
+    }
+    assert_eq!(client.scan("user:".to_owned())?, vec![("user:2".to_owned(), "bob".to_owned())]);
+
+    Ok(())
+}
+
+// Should behave the same embedded in memory, embedded on disk, and against a server
+#[test]
+fn kvs_client() -> Result<()> {
+    exercise(&mut EmbeddedClient::new(MemoryKvStore::new()))?;
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    exercise(&mut EmbeddedClient::new(KvStore::open(temp_dir.path())?))?;
+
+    let addr = "127.0.0.1:4016";
+    thread::spawn(move || {
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        Server::start(log, MemoryKvStore::new(), addr).unwrap().run()
+    });
+    thread::sleep(Duration::from_secs(1));
+    exercise(&mut Client::connect(addr)?)?;
+
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    thread::spawn(move || server.run());
+    exercise(&mut client)?;
+
+    Ok(())
+}
+
+// In-process servers should have all the behaviour of a server, but no address
+#[test]
+fn in_process_server() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    assert!(server.local_addr().is_err());
+
+    let script = FaultScript::new();
+    script.push(Fault::Drop);
+    let mut server = server.with_chaos(Chaos::new(ChaosConfig::default()).with_script(script));
+    thread::spawn(move || server.run());
+
+    assert!(client.get("key".to_owned()).is_err());
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+    assert_eq!(client.stats()?.sets.count, 1);
+    Ok(())
+}
+
+// Interceptors should be able to rewrite requests, reject them with their own errors, and see
+// every response
+#[test]
+fn interceptors() -> Result<()> {
+    struct Namespace {
+        responses: Arc<AtomicUsize>,
+    }
+
+    impl RequestInterceptor for Namespace {
+        fn before(
+            &mut self,
+            _peer: &Peer,
+            request: Request,
+        ) -> std::result::Result<Request, Response> {
+            match request {
+                Request::Set { key, .. } | Request::Get { key } if key.starts_with("secret") => {
+                    Err(Response::Err {
+                        kind: ErrorKind::InvalidKey,
+                        message: format!("{} is off limits", key),
+                    })
+                },
+                Request::Set { key, value, ttl_ms, durability } => {
+                    Ok(Request::Set { key: format!("ns:{}", key), value, ttl_ms, durability })
+                },
+                Request::Get { key } => Ok(Request::Get { key: format!("ns:{}", key) }),
+                request => Ok(request),
+            }
+        }
+
+        fn after(&mut self, _peer: &Peer, response: Response) -> Response {
+            self.responses.fetch_add(1, Ordering::SeqCst);
+            response
+        }
+    }
+
+    let responses = Arc::new(AtomicUsize::new(0));
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    let mut server = server.with_interceptor(Namespace { responses: Arc::clone(&responses) });
+    thread::spawn(move || server.run());
+
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+    let entries = vec![("ns:key".to_owned(), "value".to_owned())];
+    assert_eq!(client.scan("ns:".to_owned())?, entries);
+    match client.set("secret".to_owned(), "value".to_owned()) {
+        Err(Error::InvalidKey(message)) => assert_eq!(message, "secret is off limits"),
+        result => panic!("expected InvalidKey, got {:?}", result),
+    }
+
+    // Rejected requests don't reach the `after` hooks.
+    assert_eq!(responses.load(Ordering::SeqCst), 3);
+    Ok(())
+}
+
+// Servers should report their version, engine and enabled features
+#[test]
+fn server_info() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    let mut server = server.with_sweeper(Sweeper::new(SweeperConfig::default()))?;
+    thread::spawn(move || server.run());
+
+    let info = client.info()?;
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(info.engine, "memory");
+    assert_eq!(info.features, vec!["sweeper".to_owned()]);
+    assert_eq!(info.store_id, client.stats()?.store_id);
+    assert!(!info.store_id.is_empty());
+    Ok(())
+}
+
+// Servers should record their hottest keys, and prefetch them when they next start
+#[test]
+fn warmup() -> Result<()> {
+    let config = WarmupConfig { save_interval: Duration::from_millis(0), ..Default::default() };
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    let mut server = server.with_warmup(Warmup::new(config.clone()))?;
+    thread::spawn(move || server.run());
+
+    for key in &["hot", "hot", "warm", "hot"] {
+        client.get((*key).to_owned())?;
+    }
+    let hot_keys = client.get(format!("{}hot_keys", SYSTEM_KEY_PREFIX))?;
+    assert_eq!(hot_keys.as_deref(), Some(r#"["hot","warm"]"#));
+
+    // A restarted server prefetches the recorded keys, whether or not they're still in the store
+    let mut engine = MemoryKvStore::new();
+    engine.set("hot".to_owned(), "1".to_owned())?;
+    engine.system_keys().set("hot_keys", hot_keys.unwrap())?;
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, mut client) = Server::start_in_process(log, engine)?;
+    let mut server = server.with_warmup(Warmup::new(config))?;
+    thread::spawn(move || server.run());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while client.stats()?.prefetched_keys < 2 {
+        assert!(Instant::now() < deadline, "keys weren't prefetched");
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+// Servers should keep statistics over the lifetime of the store, across restarts
+#[test]
+fn lifetime_stats() -> Result<()> {
+    let mut engine = MemoryKvStore::new();
+    let previous = r#"{"gets":5,"sets":2,"removes":1,"compactions":0,"written_bytes":0}"#;
+    engine.system_keys().set("lifetime_stats", previous.to_owned())?;
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, mut client) = Server::start_in_process(log, engine)?;
+    let mut server = server.with_stats_save_interval(Duration::from_millis(0));
+    thread::spawn(move || server.run());
+
+    client.get("key".to_owned())?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    let stats = client.stats()?;
+    assert_eq!((stats.gets.count, stats.sets.count), (1, 1));
+    assert_eq!(stats.lifetime.gets, 6);
+    assert_eq!(stats.lifetime.sets, 3);
+    assert_eq!(stats.lifetime.removes, 1);
+
+    let recorded = client.get(format!("{}lifetime_stats", SYSTEM_KEY_PREFIX))?;
+    assert!(recorded.unwrap().starts_with(r#"{"gets":6,"sets":3,"removes":1,"#));
+    Ok(())
+}
+
+// Servers should track the sizes of keys and values set, and approximately how many were distinct
+#[test]
+fn size_stats() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    thread::spawn(move || server.run());
+
+    for i in 0..2000 {
+        client.set(format!("key{:04}", i % 1000), "x".repeat(i % 200))?;
+    }
+    let stats = client.stats()?;
+    assert_eq!(stats.key_lengths.count(), 2000);
+    assert_eq!(stats.key_lengths.mean(), 7);
+    assert_eq!(stats.key_lengths.percentile(50.0), 8);
+    assert_eq!(stats.value_sizes.total_bytes(), 10 * (0..200).sum::<u64>());
+    assert_eq!(stats.value_sizes.percentile(100.0), 256);
+    assert_eq!(stats.value_sizes.buckets()[0], (1, 10));
+    assert!(stats.distinct_keys_set >= 950 && stats.distinct_keys_set <= 1050);
+    Ok(())
+}
+
+// Servers should return a sample of their keys, excluding system keys
+#[test]
+fn sample_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, KvStore::open(temp_dir.path())?)?;
+    thread::spawn(move || server.run());
+
+    for i in 0..5 {
+        client.set(format!("key{}", i), "value".to_owned())?;
+    }
+    let mut keys = client.sample_keys(10)?;
+    keys.sort();
+    assert_eq!(keys, (0..5).map(|i| format!("key{}", i)).collect::<Vec<_>>());
+    assert_eq!(client.sample_keys(2)?.len(), 2);
+    Ok(())
+}
+
+// Queries should be compiled into a scan and a filter, and remove what they match with `DEL`
+#[test]
+fn queries() -> Result<()> {
+    let mut client = EmbeddedClient::new(MemoryKvStore::new());
+    for (key, value) in &[("user:1", "alice"), ("user:2", "bob"), ("user:3", "carol")] {
+        client.set((*key).to_owned(), (*value).to_owned())?;
+    }
+    client.set("session:1".to_owned(), "alice".to_owned())?;
+    client.set("session:2".to_owned(), "bob".to_owned())?;
+
+    let run = |client: &mut EmbeddedClient<MemoryKvStore>, query: &str| -> Result<Vec<String>> {
+        let entries = query.parse::<Query>()?.run(client)?;
+        Ok(entries.into_iter().map(|(key, _)| key).collect())
+    };
+    assert_eq!(run(&mut client, "GET user:* LIMIT 2")?, vec!["user:1", "user:2"]);
+    assert_eq!(run(&mut client, "get user:2")?, vec!["user:2"]);
+    assert_eq!(run(&mut client, "GET user: LIMIT 2")?, Vec::<String>::new());
+    assert_eq!(
+        run(&mut client, r#"GET * WHERE value = "alice" AND key CONTAINS 'user'"#)?,
+        vec!["user:1"]
+    );
+    assert_eq!(run(&mut client, "DEL session:* WHERE value != 'bob'")?, vec!["session:1"]);
+    assert_eq!(client.scan("session:".to_owned())?.len(), 1);
+
+    for query in &["", "PUT user:*", "GET user:*:1", "GET * LIMIT x", "GET * WHERE value = bob"] {
+        match query.parse::<Query>() {
+            Err(Error::InvalidQuery(_)) => {},
+            result => panic!("expected InvalidQuery for {:?}, got {:?}", query, result),
+        }
+    }
+    Ok(())
+}
+
+// Servers in maintenance mode should serve reads but reject writes until it ends
+#[test]
+fn maintenance_mode() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, KvStore::open(temp_dir.path())?)?;
+    thread::spawn(move || server.run());
+
+    client.set("key".to_owned(), "old".to_owned())?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    client.maintenance(Duration::from_secs(60))?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+    match client.set("key".to_owned(), "other".to_owned()) {
+        Err(Error::Maintenance(retry_after)) => assert!(retry_after <= Duration::from_secs(60)),
+        result => panic!("expected Maintenance, got {:?}", result),
+    }
+    match client.remove("key".to_owned()) {
+        Err(Error::Maintenance(_)) => {},
+        result => panic!("expected Maintenance, got {:?}", result),
+    }
+    let stats = client.stats()?;
+    assert_eq!(stats.engine.compactions, 1);
+    assert_eq!(stats.engine.uncompacted_bytes, 0);
+
+    client.maintenance(Duration::from_secs(0))?;
+    client.set("key".to_owned(), "other".to_owned())?;
+
+    client.maintenance(Duration::from_millis(50))?;
+    thread::sleep(Duration::from_millis(100));
+    client.remove("key".to_owned())?;
+    Ok(())
+}
+
+// Requests that outlast the server's request timeout should fail, though writes are still applied
+#[test]
+fn request_timeout() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, mut client) = Server::start_in_process(log, KvStore::open(temp_dir.path())?)?;
+    let mut server = server.with_request_timeout(Duration::from_secs(0));
+    thread::spawn(move || server.run());
+
+    match client.set("key".to_owned(), "value".to_owned()) {
+        Err(Error::Timeout) => {},
+        result => panic!("expected Timeout, got {:?}", result),
+    }
+    match client.scan("k".to_owned()) {
+        Err(Error::Timeout) => {},
+        result => panic!("expected Timeout, got {:?}", result),
+    }
+    assert_eq!(client.sample_keys(10)?, vec!["key".to_owned()]);
+    assert_eq!(client.stats()?.sets.errors, 1);
+    Ok(())
+}
+
+// Watchers should be sent each change to keys with their prefix, in order, until they disconnect
+#[test]
+fn watch() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    thread::spawn(move || server.run());
+
+    let mut watch = client.watch("user:".to_owned())?;
+    let other = client.watch("other:".to_owned())?;
+    drop(other);
+    client.set("user:1".to_owned(), "alice".to_owned())?;
+    client.set("other:1".to_owned(), "bob".to_owned())?;
+    client.remove("user:1".to_owned())?;
+    assert!(client.remove("user:2".to_owned()).is_err());
+
+    let set = watch.next().expect("watch ended")?;
+    assert_eq!((set.seq, &set.key[..], set.value), (1, &b"user:1"[..], Some(b"alice".to_vec())));
+    let removed = watch.next().expect("watch ended")?;
+    assert_eq!((removed.seq, &removed.key[..], removed.value), (3, &b"user:1"[..], None));
+    assert!(removed.timestamp_ms >= set.timestamp_ms);
+    Ok(())
+}
+
+// Watchers should be sent the removal of keys the sweeper expires
+#[test]
+fn watch_expiry() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = SweeperConfig { interval: Duration::from_millis(10), ..Default::default() };
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, mut client) = Server::start_in_process(log, KvStore::open(temp_dir.path())?)?;
+    let mut server = server.with_sweeper(Sweeper::new(config))?;
+    thread::spawn(move || server.run());
+
+    let mut watch = client.watch("session:".to_owned())?;
+    client.set_with_ttl("session:1".to_owned(), "token".to_owned(), Duration::from_millis(50))?;
+
+    let set = watch.next().expect("watch ended")?;
+    assert_eq!((&set.key[..], set.value), (&b"session:1"[..], Some(b"token".to_vec())));
+    let expired = watch.next().expect("watch ended")?;
+    assert_eq!((expired.seq, &expired.key[..], expired.value), (2, &b"session:1"[..], None));
+    Ok(())
+}
+
+// Caching clients should answer repeated reads from their cache, and drop values as soon as
+// they're changed by another client
+#[test]
+fn caching_client() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let address = server.local_addr()?;
+    thread::spawn(move || server.run());
+
+    let mut other = Client::connect(address)?;
+    other.set("user:1".to_owned(), "alice".to_owned())?;
+    let config = CacheConfig { capacity: 2, ..CacheConfig::default() };
+    let mut client = CachingClient::new(Client::connect(address)?, config)?;
+    assert_eq!(client.get("user:1".to_owned())?, Some("alice".to_owned()));
+    assert_eq!(client.get("user:1".to_owned())?, Some("alice".to_owned()));
+    assert_eq!(client.get("user:2".to_owned())?, None);
+    assert_eq!((client.hits(), client.misses()), (1, 2));
+
+    other.set("user:1".to_owned(), "carol".to_owned())?;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while client.get("user:1".to_owned())?.as_deref() != Some("carol") {
+        assert!(Instant::now() < deadline, "cached value wasn't invalidated");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // The least recently read key is evicted to make room.
+    assert_eq!(client.get("user:3".to_owned())?, None);
+    let misses = client.misses();
+    assert_eq!(client.get("user:2".to_owned())?, None);
+    assert_eq!(client.misses(), misses + 1);
+    assert!(client.is_watching());
+    Ok(())
+}
+
+// Transactions should see their own writes, and fail to read or commit keys another client has
+// written since they began
+#[test]
+fn transactions() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let address = server.local_addr()?;
+    thread::spawn(move || server.run());
+
+    let mut client = Client::connect(address)?;
+    let mut other = Client::connect(address)?;
+    other.set("balance:a".to_owned(), "10".to_owned())?;
+
+    let mut transaction = client.begin_transaction()?;
+    assert_eq!(transaction.get("balance:a".to_owned())?, Some("10".to_owned()));
+    transaction.set("balance:a".to_owned(), "5".to_owned());
+    transaction.set("balance:b".to_owned(), "5".to_owned());
+    assert_eq!(transaction.get("balance:b".to_owned())?, Some("5".to_owned()));
+    other.set("balance:c".to_owned(), "1".to_owned())?;
+    transaction.commit()?;
+    assert_eq!(other.get("balance:a".to_owned())?, Some("5".to_owned()));
+    assert_eq!(other.get("balance:b".to_owned())?, Some("5".to_owned()));
+
+    let mut transaction = client.begin_transaction()?;
+    transaction.set("balance:a".to_owned(), "0".to_owned());
+    other.set("balance:a".to_owned(), "7".to_owned())?;
+    assert!(matches!(transaction.commit(), Err(Error::Conflict(_))));
+    assert_eq!(other.get("balance:a".to_owned())?, Some("7".to_owned()));
+
+    let mut transaction = client.begin_transaction()?;
+    other.remove("balance:b".to_owned())?;
+    assert!(matches!(transaction.get("balance:b".to_owned()), Err(Error::Conflict(_))));
+    transaction.rollback()?;
+
+    let mut transaction = client.begin_transaction()?;
+    transaction.set("balance:d".to_owned(), "1".to_owned());
+    drop(transaction);
+    assert_eq!(client.get("balance:d".to_owned())?, None);
+    Ok(())
+}
+
+// Compare-and-swaps and increments should be applied atomically by the server, and sent to
+// watchers with their new values
+#[test]
+fn compare_and_swap_and_increment() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let address = server.local_addr()?;
+    thread::spawn(move || server.run());
+
+    let mut client = Client::connect(address)?;
+    let mut watch = Client::connect(address)?.watch("counter:".to_owned())?;
+    assert!(client.compare_and_swap("lock".to_owned(), None, "a".to_owned())?);
+    assert!(!client.compare_and_swap("lock".to_owned(), None, "b".to_owned())?);
+    assert_eq!(client.get("lock".to_owned())?, Some("a".to_owned()));
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            thread::spawn(move || -> Result<()> {
+                let mut client = Client::connect(address)?;
+                for _ in 0..25 {
+                    client.increment("counter:hits".to_owned(), 2)?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("thread panicked")?;
+    }
+    assert_eq!(client.increment("counter:hits".to_owned(), -200)?, 0);
+    let change = watch.next().expect("watch ended")?;
+    assert_eq!(change.key, b"counter:hits");
+    assert_eq!(change.value, Some(b"2".to_vec()));
+
+    assert!(matches!(
+        client.increment("lock".to_owned(), 1),
+        Err(Error::InvalidValue { ref key, .. }) if key == "lock"
+    ));
+    assert_eq!(client.get("lock".to_owned())?, Some("a".to_owned()));
+    Ok(())
+}
+
+// Renames should move keys on the server, telling watchers of both keys
+#[test]
+fn rename() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let address = server.local_addr()?;
+    thread::spawn(move || server.run());
+
+    let mut client = Client::connect(address)?;
+    let mut watch = Client::connect(address)?.watch("user:".to_owned())?;
+    client.set("user:1".to_owned(), "alice".to_owned())?;
+    client.set("user:2".to_owned(), "bob".to_owned())?;
+    assert!(!client.rename("user:1".to_owned(), "user:2".to_owned(), false)?);
+    assert!(client.rename("user:1".to_owned(), "user:3".to_owned(), false)?);
+    assert!(matches!(
+        client.rename("user:1".to_owned(), "user:4".to_owned(), true),
+        Err(Error::KeyNotFound)
+    ));
+    assert_eq!(client.get("user:3".to_owned())?, Some("alice".to_owned()));
+
+    let changes = watch.by_ref().take(4).collect::<Result<Vec<_>>>()?;
+    let changes: Vec<_> = changes.into_iter().map(|change| (change.key, change.value)).collect();
+    assert_eq!(
+        changes[2..],
+        [(b"user:1".to_vec(), None), (b"user:3".to_vec(), Some(b"alice".to_vec()))]
+    );
+    Ok(())
+}
+
+// Conditional gets should only send values back when their version has changed
+#[test]
+fn get_if_modified() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let address = server.local_addr()?;
+    thread::spawn(move || server.run());
+
+    let mut client = Client::connect(address)?;
+    client.set("config".to_owned(), "a".repeat(1000))?;
+    let version = value_version(&"a".repeat(1000));
+    assert_eq!(client.get_if_modified("config".to_owned(), version)?, IfModified::NotModified);
+
+    client.set("config".to_owned(), "b".to_owned())?;
+    assert_eq!(
+        client.get_if_modified("config".to_owned(), version)?,
+        IfModified::Modified(Some("b".to_owned()))
+    );
+    assert_eq!(
+        client.get_if_modified("config".to_owned(), value_version("b"))?,
+        IfModified::NotModified
+    );
+    client.remove("config".to_owned())?;
+    assert_eq!(client.get_if_modified("config".to_owned(), version)?, IfModified::Modified(None));
+    Ok(())
+}
+
+// Servers should answer pings, and keep idle watches alive with heartbeats, which clients should
+// give up on when they stop
+#[test]
+fn heartbeats() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log.clone(), MemoryKvStore::new(), "127.0.0.1:0")?
+        .with_heartbeat_interval(Duration::from_millis(10));
+    let mut quiet = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?
+        .with_heartbeat_interval(Duration::from_secs(60));
+    let (address, quiet_address) = (server.local_addr()?, quiet.local_addr()?);
+    thread::spawn(move || server.run());
+    thread::spawn(move || quiet.run());
+
+    let builder = Client::builder().heartbeat_timeout(Duration::from_millis(200));
+    let mut client = builder.clone().connect(address)?;
+    assert!(client.ping()? < Duration::from_secs(5));
+    let mut watch = client.watch("user:".to_owned())?;
+    thread::sleep(Duration::from_millis(500));
+    client.set("user:1".to_owned(), "alice".to_owned())?;
+    assert_eq!(watch.next().expect("watch ended")?.key, b"user:1");
+
+    let mut quiet_watch = builder.connect(quiet_address)?.watch("user:".to_owned())?;
+    match quiet_watch.next() {
+        Some(Err(Error::Io(ref error))) if error.kind() == io::ErrorKind::TimedOut => {},
+        result => panic!("expected a heartbeat timeout, got {:?}", result),
+    }
+    assert!(quiet_watch.next().is_none());
+    Ok(())
+}
+
+// Servers should mirror writes to a secondary server, in order, without waiting for it
+#[test]
+fn mirror() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut secondary = Server::start(log.clone(), MemoryKvStore::new(), "127.0.0.1:0")?;
+    let mirror = Mirror::start(log.clone(), MirrorConfig::new(secondary.local_addr()?))?;
+    let mut primary =
+        Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?.with_mirror(mirror);
+    let (address, secondary_address) = (primary.local_addr()?, secondary.local_addr()?);
+    thread::spawn(move || secondary.run());
+    thread::spawn(move || primary.run());
+
+    let mut client = Client::connect(address)?;
+    for i in 0..5 {
+        client.set(format!("user:{}", i), format!("value {}", i))?;
+    }
+    client.remove("user:0".to_owned())?;
+    client.set("user:1".to_owned(), "changed".to_owned())?;
+    assert_eq!(client.get("user:1".to_owned())?, Some("changed".to_owned()));
+
+    let mut secondary = Client::connect(secondary_address)?;
+    let mut expected: Vec<_> =
+        (1..5).map(|i| (format!("user:{}", i), format!("value {}", i))).collect();
+    expected[0].1 = "changed".to_owned();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while secondary.scan("user:".to_owned())? != expected {
+        assert!(Instant::now() < deadline, "writes weren't mirrored");
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+// Transfers should copy every key with their prefix between servers, reporting progress after
+// each batch, and pick up where they left off when resumed
+#[test]
+fn transfer() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut source = Server::start(log.clone(), MemoryKvStore::new(), "127.0.0.1:0")?;
+    let mut destination = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let (from, to) = (source.local_addr()?, destination.local_addr()?);
+    thread::spawn(move || source.run());
+    thread::spawn(move || destination.run());
+
+    let mut client = Client::connect(from)?;
+    for i in 0..10 {
+        client.set(format!("user:{}", i), format!("value {}", i))?;
+    }
+    client.set("group:1".to_owned(), "admins".to_owned())?;
+
+    let mut reports = Vec::new();
+    let transfer =
+        Transfer::new("user:").batch_size(4).concurrency(3).resume_after("user:1".to_owned());
+    let progress = transfer.run(&mut client, || Client::connect(to), |progress| {
+        reports.push(progress.clone())
+    })?;
+    assert_eq!(progress, TransferProgress { copied: 8, last_key: Some("user:9".to_owned()) });
+    let last_keys: Vec<_> = reports.iter().map(|report| report.last_key.as_deref()).collect();
+    assert_eq!(last_keys, vec![Some("user:5"), Some("user:9")]);
+
+    let mut copied = Client::connect(to)?;
+    let keys: Vec<_> = copied.scan("user:".to_owned())?.into_iter().map(|(key, _)| key).collect();
+    let expected: Vec<_> = (2..10).map(|i| format!("user:{}", i)).collect();
+    assert_eq!(keys, expected);
+    assert_eq!(copied.get("user:2".to_owned())?, Some("value 2".to_owned()));
+    assert_eq!(copied.get("group:1".to_owned())?, None);
+    Ok(())
+}
+
+// Rebalancing should move only the keys whose server changes between rings, and nothing when
+// run again
+#[test]
+fn rebalance() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut addresses = Vec::new();
+    for _ in 0..3 {
+        let mut server = Server::start(log.clone(), MemoryKvStore::new(), "127.0.0.1:0")?;
+        addresses.push(server.local_addr()?.to_string());
+        thread::spawn(move || server.run());
+    }
+    let old = Ring::new(addresses[..2].to_vec(), 16)?;
+    let new = Ring::new(addresses.clone(), 16)?;
+
+    // Adding a server only moves ranges to it.
+    let moves = old.moves(&new);
+    assert!(!moves.is_empty());
+    assert!(moves.iter().all(|range| range.to == addresses[2] && range.start <= range.end));
+    assert!(old.moves(&old).is_empty());
+
+    for i in 0..100 {
+        let key = format!("key{}", i);
+        Client::connect(old.owner(&key))?.set(key.clone(), format!("value {}", i))?;
+    }
+    let moving = (0..100).filter(|i| new.owner(&format!("key{}", i)) == addresses[2]).count();
+
+    let mut reports = Vec::new();
+    let rebalance = Rebalance::new(old.clone(), new.clone()).batch_size(10);
+    let connect = |node: &str| Client::connect(node);
+    let progress = rebalance.run(connect, |progress| reports.push(progress.clone()))?;
+    assert_eq!(progress.moved, moving as u64);
+    assert!(reports.windows(2).all(|pair| pair[1].moved > pair[0].moved));
+
+    for i in 0..100 {
+        let key = format!("key{}", i);
+        for address in &addresses {
+            let value = Client::connect(address.as_str())?.get(key.clone())?;
+            if address == new.owner(&key) {
+                assert_eq!(value, Some(format!("value {}", i)));
+            } else {
+                assert_eq!(value, None);
+            }
+        }
+    }
+
+    let progress = rebalance.run(connect, |_| {})?;
+    assert_eq!(progress, RebalanceProgress::default());
+    Ok(())
+}
+
+// Clients with a key prefix should behave like any other client, but only see their own keys
+#[test]
+fn key_prefix() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let addr = server.local_addr()?;
+    thread::spawn(move || server.run());
+
+    let mut app1 = ClientBuilder::new().key_prefix("app1:").connect(addr)?;
+    exercise(&mut app1)?;
+    let mut app2 = ClientBuilder::new().key_prefix("app2:").connect(addr)?;
+    exercise(&mut app2)?;
+    let mut watch = app2.watch("user:".to_owned())?;
+    app2.set("user:3".to_owned(), "carol".to_owned())?;
+    let change = watch.next().expect("watch ended")?;
+    assert_eq!((&change.key[..], change.value), (&b"user:3"[..], Some(b"carol".to_vec())));
+    assert_eq!(
+        app2.scan_range("group:".to_owned(), Some("user:3".to_owned()))?,
+        vec![("group:1".to_owned(), "admins".to_owned()), ("user:2".to_owned(), "bob".to_owned())]
+    );
+    let entries = app1.scan_range("h".to_owned(), None)?;
+    assert_eq!(entries, vec![("user:2".to_owned(), "bob".to_owned())]);
+
+    let mut client = Client::connect(addr)?;
+    assert_eq!(client.get("app1:user:2".to_owned())?, Some("bob".to_owned()));
+    assert_eq!(client.count_prefix("app2:".to_owned())?, 3);
+    assert_eq!(app1.remove_prefix(String::new())?, 2);
+    assert_eq!(client.count_prefix(String::new())?, 3);
+    assert_eq!(app2.count_prefix("user:".to_owned())?, 2);
+    Ok(())
+}
+
+// Typed values should be stored in the client's format, and readable by other clients
+#[test]
+fn typed_values() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let addr = server.local_addr()?;
+    thread::spawn(move || server.run());
+
+    let mut json = Client::connect(addr)?;
+    json.set_from("scores".to_owned(), &[1, 2, 3])?;
+    assert_eq!(json.get("scores".to_owned())?, Some("[1,2,3]".to_owned()));
+    assert_eq!(json.get_as("scores".to_owned())?, Some(vec![1, 2, 3]));
+
+    let mut msgpack = ClientBuilder::new().value_format(ValueFormat::MessagePack).connect(addr)?;
+    msgpack.set_from("scores".to_owned(), &[4, 5])?;
+    assert_eq!(json.get("scores".to_owned())?, Some("920405".to_owned()));
+    assert_eq!(msgpack.get_as("scores".to_owned())?, Some((4, 5)));
+    match json.get_as::<Vec<u32>>("scores".to_owned()) {
+        Err(Error::InvalidValue { key, .. }) => assert_eq!(key, "scores"),
+        result => panic!("expected InvalidValue, got {:?}", result),
+    }
+    Ok(())
+}
+
+// Keys that expired but haven't been swept should be reported as expired, and other missing keys
+// without a reason
+#[test]
+fn not_found_reasons() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let clock = ManualClock::new(1_000_000);
+    let store = KvStore::builder().clock(clock.clone()).open(temp_dir.path())?;
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, store)?;
+    thread::spawn(move || server.run());
+
+    client.set_with_ttl("session".to_owned(), "1".to_owned(), Duration::from_secs(10))?;
+    assert_eq!(client.lookup("session".to_owned())?, Ok("1".to_owned()));
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(client.lookup("session".to_owned())?, Err(Some(NotFoundReason::Expired)));
+    assert_eq!(client.get("session".to_owned())?, None);
+    assert_eq!(client.lookup("user".to_owned())?, Err(None));
+    Ok(())
+}
+
+// Prefix removals should be counted and applied by the server, reported to watchers, and never
+// remove system keys
+#[test]
+fn remove_prefix() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    thread::spawn(move || server.run());
+
+    for i in 0..3 {
+        client.set(format!("session:{}", i), "value".to_owned())?;
+    }
+    client.set("user:1".to_owned(), "alice".to_owned())?;
+    let mut watch = client.watch("session:".to_owned())?;
+
+    assert_eq!(client.count_prefix("session:".to_owned())?, 3);
+    assert_eq!(client.remove_prefix("session:".to_owned())?, 3);
+    assert_eq!(client.count_prefix("session:".to_owned())?, 0);
+    for i in 0..3 {
+        let change = watch.next().expect("watch ended")?;
+        assert_eq!((change.key, change.value), (format!("session:{}", i).into_bytes(), None));
+    }
+
+    match client.remove_prefix(SYSTEM_KEY_PREFIX.to_owned()) {
+        Err(Error::InvalidKey(_)) => {},
+        result => panic!("expected InvalidKey, got {:?}", result),
+    }
+    let store_id = client.info()?.store_id;
+    assert_eq!(client.remove_prefix(String::new())?, 1);
+    assert_eq!(client.info()?.store_id, store_id);
+    Ok(())
+}
+
+// Should reject changes to keys in an immutable bucket, which can only be removed by force
+#[test]
+fn immutable_keys() -> Result<()> {
+    let blobs = WriteOnce::new(Box::new(MemoryKvStore::new()));
+    let engine = Buckets::new(Box::new(MemoryKvStore::new())).with_bucket("blobs", Box::new(blobs));
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, engine)?;
+    thread::spawn(move || server.run());
+
+    client.set("blobs/a".to_owned(), "1".to_owned())?;
+    client.set("blobs/a".to_owned(), "1".to_owned())?;
+    match client.set("blobs/a".to_owned(), "2".to_owned()) {
+        Err(Error::Immutable(key)) => assert_eq!(key, "blobs/a"),
+        result => panic!("expected Immutable, got {:?}", result),
+    }
+    let batch = vec![
+        ("blobs/b".to_owned(), "1".to_owned()),
+        ("blobs/a".to_owned(), "2".to_owned()),
+    ];
+    assert!(matches!(client.set_many(batch), Err(Error::Immutable(_))));
+    assert!(matches!(client.remove("blobs/a".to_owned()), Err(Error::Immutable(_))));
+    assert!(matches!(client.increment("blobs/a".to_owned(), 1), Err(Error::Immutable(_))));
+    let renamed = client.rename("blobs/a".to_owned(), "blobs/c".to_owned(), false);
+    assert!(matches!(renamed, Err(Error::Immutable(_))));
+    assert_eq!(client.get("blobs/a".to_owned())?, Some("1".to_owned()));
+
+    client.force_remove("blobs/a".to_owned())?;
+    assert_eq!(client.get("blobs/a".to_owned())?, None);
+    client.set("blobs/a".to_owned(), "2".to_owned())?;
+
+    // Keys in other buckets can still be changed.
+    client.set("user:1".to_owned(), "alice".to_owned())?;
+    client.set("user:1".to_owned(), "bob".to_owned())?;
+    client.remove("user:1".to_owned())?;
+    Ok(())
+}
+
+// Should store values under keys derived from their content, writing each value only once
+#[test]
+fn content_addressed_puts() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    thread::spawn(move || server.run());
+    let mut watch = client.watch(CONTENT_KEY_PREFIX.to_owned())?;
+
+    let key = client.put_cas("blob".to_owned())?;
+    assert_eq!(key, content_key("blob"));
+    assert_eq!(client.get(key.clone())?, Some("blob".to_owned()));
+    assert_eq!(client.put_cas("blob".to_owned())?, key);
+    let other = client.put_cas("other".to_owned())?;
+    assert_ne!(other, key);
+    assert_eq!(client.scan(CONTENT_KEY_PREFIX.to_owned())?.len(), 2);
+
+    // The duplicate put wasn't written, so watchers only see the two new values.
+    for (key, value) in [(key, "blob"), (other, "other")] {
+        let change = watch.next().expect("watch ended")?;
+        assert_eq!((change.key, change.value), (key.into_bytes(), Some(value.into())));
+    }
+    Ok(())
+}
+
+// Batches should be applied by the server in a single request each, reported to watchers, and
+// checked against the key rules like single-key requests
+#[test]
+fn batches() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (mut server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    thread::spawn(move || server.run());
+    let mut watch = client.watch("session:".to_owned())?;
+
+    let pairs = (0..3).map(|i| (format!("session:{}", i), format!("value{}", i))).collect();
+    client.set_many(pairs)?;
+    let keys = vec!["session:1".to_owned(), "user:1".to_owned()];
+    assert_eq!(client.get_many(keys)?, vec![Some("value1".to_owned()), None]);
+    let keys = vec!["session:0".to_owned(), "session:2".to_owned(), "user:1".to_owned()];
+    assert_eq!(client.remove_many(keys)?, 2);
+    assert_eq!(client.count_prefix("session:".to_owned())?, 1);
+
+    let expected = vec![
+        ("session:0", true),
+        ("session:1", true),
+        ("session:2", true),
+        ("session:0", false),
+        ("session:2", false),
+    ];
+    for (key, set) in expected {
+        let change = watch.next().expect("watch ended")?;
+        assert_eq!((&change.key[..], change.value.is_some()), (key.as_bytes(), set));
+    }
+
+    let pairs = vec![
+        ("user:1".to_owned(), "alice".to_owned()),
+        (format!("{}store_id", SYSTEM_KEY_PREFIX), "stolen".to_owned()),
+    ];
+    match client.set_many(pairs) {
+        Err(Error::InvalidKey(_)) => {},
+        result => panic!("expected InvalidKey, got {:?}", result),
+    }
+    assert_eq!(client.get("user:1".to_owned())?, None);
+    Ok(())
+}
+
+// Sets should be applied whether the client waits for them to be synced or not
+#[test]
+fn durability() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SharedEngine::new(KvStore::open(temp_dir.path())?);
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, store.clone(), "127.0.0.1:0")?;
+    let addr = server.local_addr()?;
+    thread::spawn(move || server.run());
+
+    let mut client = ClientBuilder::new().durability(Durability::Fsynced).connect(addr)?;
+    client.set("user:1".to_owned(), "alice".to_owned())?;
+    client.set_many(vec![("user:2".to_owned(), "bob".to_owned())])?;
+    let mut client = Client::connect(addr)?;
+    client.set("user:3".to_owned(), "carol".to_owned())?;
+
+    assert_eq!(store.with(|store| store.count_prefix("user:"))?, 3);
+    Ok(())
+}
+
+// Binary keys and values should round-trip through a server, within a client's key prefix, and
+// still be subject to the system keyspace
+#[test]
+fn binary_keys_and_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, KvStore::open(temp_dir.path())?, "127.0.0.1:0")?;
+    let addr = server.local_addr()?;
+    thread::spawn(move || server.run());
+
+    let mut client = ClientBuilder::new().key_prefix("app1:").connect(addr)?;
+    let (key, value) = (&[0xff, 0x00, b'k'][..], &[0xc3, 0x28, 0x00][..]);
+    client.set_bytes(key, value)?;
+    client.set_bytes(b"text", value)?;
+    assert_eq!(client.get_bytes(key)?, Some(value.to_vec()));
+    assert_eq!(client.scan_bytes(&[0xff])?, vec![(key.to_vec(), value.to_vec())]);
+    assert!(matches!(client.get("text".to_owned()), Err(Error::InvalidValue { .. })));
+    client.remove_bytes(key)?;
+    assert_eq!(client.get_bytes(key)?, None);
+    assert!(matches!(client.remove_bytes(key), Err(Error::KeyNotFound)));
+
+    let mut client = Client::connect(addr)?;
+    assert_eq!(client.get_bytes(b"app1:text")?, Some(value.to_vec()));
+    let system_key = [SYSTEM_KEY_PREFIX.as_bytes(), &[0xff]].concat();
+    assert!(matches!(client.set_bytes(&system_key, value), Err(Error::InvalidKey(_))));
+    Ok(())
+}
+
+// Binary keys should be checked against key rules and tenants as the bytes they are, and sent to
+// watchers intact
+#[test]
+fn binary_keys_checked_and_watched_as_bytes() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let (server, mut client) = Server::start_in_process(log.clone(), MemoryKvStore::new())?;
+    let rules = KeyRules { max_length: Some(6), ..KeyRules::default() };
+    let quota = TenantQuota { max_keys: Some(2), ..TenantQuota::default() };
+    let mut server = server.with_key_rules(rules).with_tenants(Tenants::new(quota));
+    thread::spawn(move || server.run());
+
+    // Both keys are 4 bytes, and distinct, though they'd be the same 8 bytes with `U+FFFD`s.
+    let mut watch = client.watch("t/".to_owned())?;
+    let (first, second) = (&b"t/\xff\xfe"[..], &b"t/\xfe\xff"[..]);
+    client.set_bytes(first, &[0xc3, 0x28])?;
+    client.set_bytes(second, &[0x00])?;
+    let changes = watch.by_ref().take(2).collect::<Result<Vec<_>>>()?;
+    let changes: Vec<_> = changes.into_iter().map(|change| (change.key, change.value)).collect();
+    assert_eq!(
+        changes,
+        [(first.to_vec(), Some(vec![0xc3, 0x28])), (second.to_vec(), Some(vec![0x00]))]
+    );
+
+    assert!(matches!(client.set_bytes(b"t/\xfd", b"v"), Err(Error::KeyQuotaExceeded(_))));
+    assert!(matches!(client.set_bytes(b"\xff/k", b"v"), Err(Error::InvalidKey(_))));
+    assert!(matches!(client.set_bytes(b"t/\xff\xff\xff\xff\xff", b"v"), Err(Error::InvalidKey(_))));
+
+    let (server, mut client) = Server::start_in_process(log, MemoryKvStore::new())?;
+    let rules = KeyRules { charset: KeyCharset::Utf8, ..KeyRules::default() };
+    let mut server = server.with_key_rules(rules);
+    thread::spawn(move || server.run());
+    client.set_bytes("ключ".as_bytes(), b"v")?;
+    match client.set_bytes(b"k\xff", b"v") {
+        Err(Error::InvalidKey(message)) => assert!(message.contains("at byte 1"), "{}", message),
+        result => panic!("expected InvalidKey, got {:?}", result),
+    }
+    Ok(())
+}
+
+// Dual-stack servers should accept connections over both IPv4 and (where available) IPv6, and
+// clients should connect to whichever of a name's addresses accepts
+#[test]
+fn dual_stack() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start_dual_stack(log, MemoryKvStore::new(), 0)?;
+    let addresses = server.local_addrs()?;
+    let port = addresses[0].port();
+    assert!(addresses.iter().all(|address| address.port() == port));
+    thread::spawn(move || server.run());
+
+    let mut client = Client::connect(("127.0.0.1", port))?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    if addresses[0].is_ipv6() {
+        let mut client = Client::connect(("::1", port))?;
+        assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+    }
+    let mut client = Client::connect(("localhost", port))?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// Servers with `reuse_port` should be able to share a port, and socket options shouldn't affect
+// requests
+#[cfg(unix)]
+#[test]
+fn server_config() -> Result<()> {
+    let config = ServerConfig {
+        reuse_port: true,
+        nodelay: false,
+        send_buffer_size: Some(64 * 1024),
+        recv_buffer_size: Some(64 * 1024),
+        ..ServerConfig::default()
+    };
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    // The servers share an engine, since either may accept any of the client's connections.
+    let engine = SharedEngine::new(MemoryKvStore::new());
+    let mut server =
+        Server::start_with_config(log.clone(), engine.clone(), "127.0.0.1:0", config.clone())?;
+    let address = server.local_addrs()?[0];
+    let mut other = Server::start_with_config(log, engine, address, config)?;
+    assert_eq!(other.local_addrs()?, vec![address]);
+    thread::spawn(move || server.run());
+    thread::spawn(move || other.run());
+
+    let mut client = Client::connect(address)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let config = ServerConfig { reuse_port: false, ..ServerConfig::default() };
+    assert!(Server::start_with_config(log, MemoryKvStore::new(), address, config).is_err());
+    Ok(())
+}
+
+// Clients and servers should work with Nagle's algorithm enabled, and with any buffer size
+#[test]
+fn buffering() -> Result<()> {
+    for &buffer_size in &[0, 1, 64 * 1024] {
+        let config = ServerConfig { nodelay: false, buffer_size, ..ServerConfig::default() };
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let mut server =
+            Server::start_with_config(log, MemoryKvStore::new(), "127.0.0.1:0", config)?;
+        let address = server.local_addrs()?[0];
+        thread::spawn(move || server.run());
+
+        let builder = ClientBuilder::new().nodelay(false).buffer_size(buffer_size);
+        exercise(&mut builder.connect(address)?)?;
+    }
+    Ok(())
+}
+
+// Concurrent clients should see their own writes, never see another client's writes go
+// backwards, and not be disturbed by compactions
+#[test]
+fn concurrent_clients() -> Result<()> {
+    const CLIENTS: usize = 4;
+    const WRITES: u64 = 200;
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = KvStore::builder().compaction_ratio(0.1, 1024).open(temp_dir.path())?;
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, engine, "127.0.0.1:0")?;
+    let address = server.local_addrs()?[0];
+    thread::spawn(move || server.run());
+
+    let workers: Vec<_> = (0..CLIENTS)
+        .map(|id| {
+            thread::spawn(move || -> Result<()> {
+                let mut client = Client::connect(address)?;
+                let mut last_shared = 0;
+                for n in 1..=WRITES {
+                    let key = format!("client{}:{}", id, n % 10);
+                    client.set(key.clone(), n.to_string())?;
+                    assert_eq!(client.get(key)?, Some(n.to_string()));
+                    if id == 0 {
+                        client.set("shared".to_owned(), n.to_string())?;
+                    }
+                    if let Some(shared) = client.get("shared".to_owned())? {
+                        let shared: u64 = shared.parse().expect("invalid shared value");
+                        assert!(shared >= last_shared, "{} went back to {}", last_shared, shared);
+                        last_shared = shared;
+                    }
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().expect("client thread panicked")?;
+    }
+
+    let mut client = Client::connect(address)?;
+    assert_eq!(client.get("shared".to_owned())?, Some(WRITES.to_string()));
+    for id in 0..CLIENTS {
+        assert_eq!(client.scan(format!("client{}:", id))?.len(), 10);
+    }
+    assert!(client.stats()?.engine.compactions > 0);
+    Ok(())
+}
+
+// Small requests should be handled while a large request is still being received
+#[test]
+fn large_request_fairness() -> Result<()> {
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut server = Server::start(log, MemoryKvStore::new(), "127.0.0.1:0")?;
+    let address = server.local_addrs()?[0];
+    thread::spawn(move || server.run());
+
+    let value = "x".repeat(4 * 1024 * 1024);
+    let mut bytes = Vec::new();
+    let durability = Durability::Applied;
+    let request = Request::Set { key: "large".to_owned(), value, ttl_ms: None, durability };
+    encode_request(&mut bytes, &request)?;
+    let mut large = TcpStream::connect(address)?;
+    large.write_all(&bytes[..bytes.len() / 2])?;
+
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || {
+        let result = Client::connect(address).and_then(|mut client| {
+            client.set("small".to_owned(), "value".to_owned())?;
+            client.get("small".to_owned())
+        });
+        done.send(result).expect("test finished");
+    });
+    let small = finished.recv_timeout(Duration::from_secs(10)).expect("small request blocked");
+    assert_eq!(small?, Some("value".to_owned()));
+
+    large.write_all(&bytes[bytes.len() / 2..])?;
+    match decode_response(&mut large)? {
+        Response::Ok => {},
+        response => panic!("expected Ok, got {:?}", response),
+    }
+    let mut client = Client::connect(address)?;
+    assert_eq!(client.get("large".to_owned())?.map(|value| value.len()), Some(4 * 1024 * 1024));
+    Ok(())
+}
+
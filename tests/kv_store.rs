@@ -1,4 +1,13 @@
-use kvs::{KvStore, KvsEngine, Result};
+use std::fs;
+use std::io;
+use std::mem;
+use std::thread;
+use std::time::Duration;
+use kvs::{BackgroundJobs, Error, KvStore, KvsEngine, ManualClock, PrometheusMetrics, Result};
+use kvs::{CompactionDecision, CompactionFilter, KeySample, WriteStall};
+use kvs::{MemoryKvStore, SharedEngine, SledKvStore, SyncPolicy, ValueFormat, SYSTEM_KEY_PREFIX};
+use kvs::Consistency;
+use serde::{Deserialize, Serialize};
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -124,3 +133,1745 @@ fn compaction() -> Result<()> {
 
     panic!("No compaction detected");
 }
+
+// Should refuse new keys once the index is full, but still allow existing keys to be updated
+#[test]
+fn max_index_memory() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::builder().max_index_memory(1024).open(temp_dir.path())?;
+
+    let mut stored = 0;
+    loop {
+        match store.set(format!("key{}", stored), "value".to_owned()) {
+            Ok(()) => stored += 1,
+            Err(Error::IndexFull) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    assert!(stored > 0);
+    assert!(store.stats()?.index_bytes <= 1024);
+
+    store.set("key0".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key0".to_owned())?, Some("value2".to_owned()));
+
+    store.remove("key0".to_owned())?;
+    store.set(format!("key{}", stored), "value".to_owned())?;
+
+    Ok(())
+}
+
+// Should behave the same with the index on disk, across compactions and reopens
+#[test]
+fn disk_index() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let open = || KvStore::builder().disk_index(1024 * 1024).open(temp_dir.path());
+    let mut store = open()?;
+
+    let value = "v".repeat(1024);
+    for iter in 0..2000 {
+        store.set(format!("key{}", iter % 10), format!("{}{}", value, iter))?;
+    }
+    store.remove("key0".to_owned())?;
+    assert!(store.stats()?.compactions > 0);
+    assert_eq!(store.get("key1".to_owned())?, Some(format!("{}{}", value, 1991)));
+    assert_eq!(store.get("key0".to_owned())?, None);
+
+    drop(store);
+    let mut store = open()?;
+    assert_eq!(store.stats()?.keys, 9);
+    assert_eq!(store.get("key9".to_owned())?, Some(format!("{}{}", value, 1999)));
+    assert_eq!(store.get("key0".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should behave the same with prefix compression, across compactions and reopens
+#[test]
+fn prefix_compression() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let open = || KvStore::builder().prefix_compression(true).open(temp_dir.path());
+    let mut store = open()?;
+
+    let keys = [
+        "https://example.com/",
+        "https://example.com/a",
+        "https://example.com/ab",
+        "https://example.org/é",
+        "https://example.org/ê",
+        "other",
+    ];
+    let value = "v".repeat(1024);
+    for iter in 0..2000 {
+        store.set(keys[iter % keys.len()].to_owned(), format!("{}{}", value, iter))?;
+    }
+    store.remove("https://example.com/a".to_owned())?;
+    assert!(store.stats()?.compactions > 0);
+
+    for store in &mut [store, open()?] {
+        assert_eq!(store.stats()?.keys, keys.len() as u64 - 1);
+        assert_eq!(store.get("https://example.com/a".to_owned())?, None);
+        assert_eq!(store.get("https://example.com".to_owned())?, None);
+        for (i, key) in keys.iter().enumerate().filter(|(i, _)| *i != 1) {
+            let last_iter = 1999 - (1999 - i) % keys.len();
+            assert_eq!(store.get(key.to_string())?, Some(format!("{}{}", value, last_iter)));
+        }
+    }
+
+    Ok(())
+}
+
+// Compactions should train a dictionary on the values they write, and compress the values with
+// it in the next compaction, across reopens
+#[test]
+fn compression_dictionary() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let builder = KvStore::builder().compression_dictionary(4096).inline_values(16);
+    let open = || builder.clone().open(temp_dir.path());
+    let mut store = open()?;
+
+    let value = |i| {
+        format!(r#"{{"name":"user{}","email":"user{}@example.com","role":"member"}}"#, i, i)
+    };
+    for i in 0..1000 {
+        store.set(format!("user:{}", i), value(i))?;
+    }
+    store.set("short".to_owned(), "value".to_owned())?;
+
+    store.maintain()?;
+    let stats = store.stats()?;
+    assert!(stats.dictionary_bytes > 0);
+    assert_eq!(stats.compressed_values, 0);
+    assert_eq!(stats.compressed_value_bytes, stats.uncompressed_value_bytes);
+
+    store.maintain()?;
+    let stats = store.stats()?;
+    assert!(stats.compressed_values > 0);
+    assert!(stats.compressed_value_bytes < stats.uncompressed_value_bytes);
+    assert_eq!(store.scrub(2000)?, Vec::<String>::new());
+
+    for store in &mut [store, open()?] {
+        assert_eq!(store.get("user:7".to_owned())?, Some(value(7)));
+        assert_eq!(store.get("user:999".to_owned())?, Some(value(999)));
+        assert_eq!(store.get("short".to_owned())?, Some("value".to_owned()));
+    }
+    let dictionaries = fs::read_dir(temp_dir.path())?
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("dict".as_ref()))
+        .count();
+    assert_eq!(dictionaries, 2);
+    Ok(())
+}
+
+// Commands should be numbered in sequence, and replay should skip duplicates and reject gaps
+#[test]
+fn sequence_numbers() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.remove("key0".to_owned())?;
+    assert_eq!(store.stats()?.sequence, 11);
+    drop(store);
+
+    // A copy of the log is a sequence of duplicates.
+    fs::copy(temp_dir.path().join("0.log"), temp_dir.path().join("1.log"))?;
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.stats()?.sequence, 11);
+    assert_eq!(store.stats()?.replay_duplicates, 11);
+    assert_eq!(store.get("key0".to_owned())?, None);
+    assert_eq!(store.get("key9".to_owned())?, Some("value9".to_owned()));
+
+    store.set("key10".to_owned(), "value10".to_owned())?;
+    assert_eq!(store.stats()?.sequence, 12);
+    drop(store);
+
+    // Losing commands that the manifest knows about is an error.
+    fs::remove_file(temp_dir.path().join("1.log"))?;
+    match KvStore::open(temp_dir.path()) {
+        Err(Error::SequenceGap { expected: 12, found: 11 }) => {},
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("expected a sequence gap"),
+    }
+
+    Ok(())
+}
+
+// Files from older format versions should be readable and upgradable, and newer ones rejected
+#[test]
+fn format_versions() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    // A version 1 log file has no header, and commands have no sequence numbers. This is
+    // `Set { value: "v", key: "k" }`.
+    fs::write(temp_dir.path().join("0.log"), b"\x92\x00\x92\xa1v\xa1k")?;
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("k".to_owned())?, Some("v".to_owned()));
+    store.set("k2".to_owned(), "v2".to_owned())?;
+    assert!(fs::read(temp_dir.path().join("1.log"))?.starts_with(b"KVSL"));
+    assert_eq!(fs::read(temp_dir.path().join("0.log"))?.len(), 7);
+
+    assert_eq!(store.upgrade()?, 1);
+    assert_eq!(store.upgrade()?, 0);
+    assert!(!temp_dir.path().join("0.log").exists());
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("k".to_owned())?, Some("v".to_owned()));
+    assert_eq!(store.get("k2".to_owned())?, Some("v2".to_owned()));
+    drop(store);
+
+    // A log file from a future version can't be read.
+    fs::write(temp_dir.path().join("99.log"), b"KVSL\x63\x00\x00\x00")?;
+    match KvStore::open(temp_dir.path()) {
+        Err(Error::UnsupportedVersion(99)) => {},
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("expected an unsupported version"),
+    }
+
+    Ok(())
+}
+
+// Checks should report problems without fixing them, and repairs should fix them
+#[test]
+fn check_and_repair() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    drop(store);
+
+    let report = KvStore::check(temp_dir.path())?;
+    assert!(report.clean);
+    assert_eq!(report.keys, 10);
+    assert_eq!(report.files.len(), 1);
+    assert_eq!(report.files[0].records, 10);
+    assert_eq!(report.files[0].last_seq, Some(10));
+
+    // Copy the log into an orphaned file, and corrupt the end of the original.
+    let log_path = temp_dir.path().join("0.log");
+    let length = fs::metadata(&log_path)?.len();
+    fs::copy(&log_path, temp_dir.path().join("1.log"))?;
+    let mut contents = fs::read(&log_path)?;
+    contents.extend_from_slice(b"\xc1garbage");
+    fs::write(&log_path, contents)?;
+
+    let report = KvStore::check(temp_dir.path())?;
+    assert!(!report.clean);
+    assert!(report.repairable);
+    assert_eq!(report.orphaned_files, vec![1]);
+    assert_eq!(report.duplicates, 10);
+    assert_eq!(report.files[0].corrupt_ranges.len(), 1);
+    assert_eq!(report.files[0].corrupt_ranges[0].start, length);
+    assert_eq!(report.files[0].corrupt_ranges[0].end, length + 8);
+    assert_eq!(fs::metadata(&log_path)?.len(), length + 8);
+
+    let report = KvStore::repair(temp_dir.path())?;
+    assert!(report.repaired);
+    assert_eq!(fs::metadata(&log_path)?.len(), length);
+    assert!(KvStore::check(temp_dir.path())?.clean);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key9".to_owned())?, Some("value9".to_owned()));
+
+    Ok(())
+}
+
+// The system keyspace should be initialised once, and keep its store ID across restarts
+#[test]
+fn system_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.system_keys().store_id()?, None);
+
+    store.system_keys().init()?;
+    let store_id = store.system_keys().store_id()?.expect("store ID not set");
+    assert_eq!(store.get(format!("{}store_id", SYSTEM_KEY_PREFIX))?, Some(store_id.clone()));
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.system_keys().init()?;
+    assert_eq!(store.system_keys().store_id()?, Some(store_id));
+
+    Ok(())
+}
+
+// Expired keys should be hidden immediately, and removed from the log by a sweep
+#[test]
+fn ttl_expiry() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    store.set_with_ttl("short".to_owned(), "1".to_owned(), Duration::from_millis(50))?;
+    store.set_with_ttl("long".to_owned(), "2".to_owned(), Duration::from_secs(3600))?;
+    store.set_with_ttl("persisted".to_owned(), "3".to_owned(), Duration::from_millis(50))?;
+    store.set("persisted".to_owned(), "4".to_owned())?;
+    assert_eq!(store.get("short".to_owned())?, Some("1".to_owned()));
+    assert_eq!(store.stats()?.expiring_keys, 2);
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(store.get("short".to_owned())?, None);
+    assert_eq!(store.get("persisted".to_owned())?, Some("4".to_owned()));
+    match store.remove("short".to_owned()) {
+        Err(Error::KeyNotFound) => {},
+        result => panic!("expected KeyNotFound, got {:?}", result),
+    }
+
+    // Expiry times survive a restart.
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.stats()?.expiring_keys, 2);
+    assert_eq!(store.get("short".to_owned())?, None);
+
+    assert_eq!(store.sweep_expired(10)?, vec!["short".to_owned()]);
+    assert_eq!(store.sweep_expired(10)?, Vec::<String>::new());
+    let stats = store.stats()?;
+    assert_eq!((stats.keys, stats.expiring_keys, stats.expired_keys), (2, 1, 1));
+    assert_eq!(store.get("long".to_owned())?, Some("2".to_owned()));
+
+    Ok(())
+}
+
+// Expiry should follow the store's clock, and never go backwards, even across restarts
+#[test]
+fn ttl_clock_jumps() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let clock = ManualClock::new(1_000_000);
+    let mut store = KvStore::builder().clock(clock.clone()).open(temp_dir.path())?;
+
+    store.set_with_ttl("a".to_owned(), "1".to_owned(), Duration::from_secs(10))?;
+    clock.advance(Duration::from_secs(9));
+    assert_eq!(store.get("a".to_owned())?, Some("1".to_owned()));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(store.get("a".to_owned())?, None);
+
+    // A backwards jump doesn't bring the key back, or extend new TTLs.
+    clock.set(0);
+    assert_eq!(store.get("a".to_owned())?, None);
+    store.set_with_ttl("b".to_owned(), "2".to_owned(), Duration::from_secs(10))?;
+    clock.set(1_015_000);
+    assert_eq!(store.get("b".to_owned())?, Some("2".to_owned()));
+    clock.set(1_020_000);
+    assert_eq!(store.get("b".to_owned())?, None);
+
+    // The latest time is remembered across a restart.
+    drop(store);
+    let mut store = KvStore::builder().clock(ManualClock::new(0)).open(temp_dir.path())?;
+    assert_eq!(store.get("a".to_owned())?, None);
+    assert_eq!(store.sweep_expired(10)?.len(), 2);
+
+    Ok(())
+}
+
+// Should report, set and remove the TTLs of existing keys, keeping them across a restart
+#[test]
+fn ttl_commands() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let clock = ManualClock::new(1_000_000);
+    let mut store = KvStore::builder().clock(clock.clone()).open(temp_dir.path())?;
+
+    store.set("a".to_owned(), "1".to_owned())?;
+    store.set_with_ttl("b".to_owned(), "2".to_owned(), Duration::from_secs(10))?;
+    assert_eq!(store.ttl("a".to_owned())?, None);
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(store.ttl("b".to_owned())?, Some(Duration::from_secs(6)));
+
+    store.expire("a".to_owned(), Duration::from_secs(20))?;
+    store.persist("b".to_owned())?;
+    store.persist("b".to_owned())?;
+    assert_eq!(store.ttl("a".to_owned())?, Some(Duration::from_secs(20)));
+    assert_eq!(store.ttl("b".to_owned())?, None);
+    assert_eq!(store.get("a".to_owned())?, Some("1".to_owned()));
+    assert_eq!(store.stats()?.expiring_keys, 1);
+
+    drop(store);
+    let mut store = KvStore::builder().clock(clock.clone()).open(temp_dir.path())?;
+    assert_eq!(store.ttl("a".to_owned())?, Some(Duration::from_secs(20)));
+    assert_eq!(store.ttl("b".to_owned())?, None);
+
+    // Expired and missing keys aren't found.
+    clock.advance(Duration::from_secs(20));
+    for key in &["a", "c"] {
+        match store.ttl(key.to_string()) {
+            Err(Error::KeyNotFound) => {},
+            result => panic!("expected KeyNotFound, got {:?}", result),
+        }
+        match store.expire(key.to_string(), Duration::from_secs(10)) {
+            Err(Error::KeyNotFound) => {},
+            result => panic!("expected KeyNotFound, got {:?}", result),
+        }
+        match store.persist(key.to_string()) {
+            Err(Error::KeyNotFound) => {},
+            result => panic!("expected KeyNotFound, got {:?}", result),
+        }
+    }
+
+    // Keys in engines without TTLs never expire, and can't be made to.
+    let mut engine = MemoryKvStore::new();
+    engine.set("a".to_owned(), "1".to_owned())?;
+    assert_eq!(engine.ttl("a".to_owned())?, None);
+    engine.persist("a".to_owned())?;
+    match engine.expire("a".to_owned(), Duration::from_secs(10)) {
+        Err(Error::TtlUnsupported) => {},
+        result => panic!("expected TtlUnsupported, got {:?}", result),
+    }
+
+    Ok(())
+}
+
+// Reads of expired but unswept keys should behave as if the key was removed, counting it as
+// expired once, and either leaving it to be swept or removing it straight away
+#[test]
+fn expired_reads() -> Result<()> {
+    for &remove_on_read in &[false, true] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let clock = ManualClock::new(1_000_000);
+        let mut store = KvStore::builder()
+            .clock(clock.clone())
+            .remove_expired_on_read(remove_on_read)
+            .open(temp_dir.path())?;
+        for key in &["a", "b", "c"] {
+            store.set_with_ttl(key.to_string(), "1".to_owned(), Duration::from_secs(10))?;
+        }
+        clock.advance(Duration::from_secs(10));
+
+        assert_eq!(store.get("a".to_owned())?, None);
+        assert_eq!(store.get("a".to_owned())?, None);
+        match store.remove("b".to_owned()) {
+            Err(Error::KeyNotFound) => {},
+            result => panic!("expected KeyNotFound, got {:?}", result),
+        }
+        let stats = store.stats()?;
+        assert_eq!(stats.expired_keys, 2);
+        assert_eq!(stats.expiring_keys, if remove_on_read { 1 } else { 3 });
+
+        let swept = store.sweep_expired(10)?;
+        if remove_on_read {
+            assert_eq!(swept, vec!["c".to_owned()]);
+        } else {
+            assert_eq!(swept, vec!["a".to_owned(), "b".to_owned(), "c".to_owned()]);
+        }
+        let stats = store.stats()?;
+        assert_eq!((stats.keys, stats.expiring_keys, stats.expired_keys), (0, 0, 3));
+
+        // A key set again before it's swept isn't swept, or counted again.
+        store.set_with_ttl("d".to_owned(), "1".to_owned(), Duration::from_secs(10))?;
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(store.get("d".to_owned())?, None);
+        store.set("d".to_owned(), "2".to_owned())?;
+        assert!(store.sweep_expired(10)?.is_empty());
+        assert_eq!(store.get("d".to_owned())?, Some("2".to_owned()));
+        assert_eq!(store.stats()?.expired_keys, 4);
+
+        // Removed keys stay removed after a restart.
+        drop(store);
+        let mut store = KvStore::builder().clock(clock.clone()).open(temp_dir.path())?;
+        assert_eq!(store.get("a".to_owned())?, None);
+        assert!(store.sweep_expired(10)?.is_empty());
+    }
+
+    // The other engines don't support TTLs, so never have expired keys.
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut engines: Vec<Box<dyn KvsEngine>> = vec![
+        Box::new(MemoryKvStore::new()),
+        Box::new(SledKvStore::start_default(temp_dir.path())?),
+    ];
+    for engine in &mut engines {
+        match engine.set_with_ttl("a".to_owned(), "1".to_owned(), Duration::from_secs(10)) {
+            Err(Error::TtlUnsupported) => {},
+            result => panic!("expected TtlUnsupported, got {:?}", result),
+        }
+        assert_eq!(engine.get("a".to_owned())?, None);
+        assert!(engine.sweep_expired(10)?.is_empty());
+    }
+
+    Ok(())
+}
+
+// Should find keys that haven't been read or written for a while, remembering reads across a
+// restart
+#[test]
+fn cold_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let day = Duration::from_secs(24 * 60 * 60);
+    let clock = ManualClock::new(1_700_000_000_000);
+    let mut store = KvStore::builder().clock(clock.clone()).open(temp_dir.path())?;
+    for key in &["user:1", "user:2", "user:3", "user:4", "group:1"] {
+        store.set(key.to_string(), "value".to_owned())?;
+    }
+    store.set(format!("{}marker", SYSTEM_KEY_PREFIX), "value".to_owned())?;
+
+    clock.advance(day * 10);
+    assert_eq!(store.get("user:1".to_owned())?, Some("value".to_owned()));
+    store.set("user:2".to_owned(), "changed".to_owned())?;
+    clock.advance(day * 10);
+    assert_eq!(store.cold_keys("", day * 30, 10)?, Vec::<String>::new());
+    assert_eq!(store.cold_keys("user:", day * 15, 10)?, vec!["user:3", "user:4"]);
+    assert_eq!(store.cold_keys("", day * 15, 2)?, vec!["group:1", "user:3"]);
+
+    drop(store);
+    let mut store = KvStore::builder().clock(clock.clone()).open(temp_dir.path())?;
+    assert_eq!(store.cold_keys("user:", day * 15, 10)?, vec!["user:3", "user:4"]);
+    assert_eq!(store.cold_keys("user:", day * 5, 10)?.len(), 4);
+
+    Ok(())
+}
+
+// Should defer compaction while every background job slot is taken, and read across log files
+// with a single open reader
+#[test]
+fn resource_limits() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let jobs = BackgroundJobs::new(1);
+    let mut store = KvStore::builder()
+        .max_open_files(1)
+        .background_jobs(jobs.clone())
+        .open(temp_dir.path())?;
+
+    let value = "v".repeat(1024);
+    store.set("first".to_owned(), "1".to_owned())?;
+    let slot = jobs.try_start().expect("no free job slot");
+    for iter in 0..2000 {
+        store.set(format!("key{}", iter % 10), format!("{}{}", value, iter))?;
+    }
+    let stats = store.stats()?;
+    assert_eq!(stats.compactions, 0);
+    assert!(stats.deferred_compactions > 0);
+
+    drop(slot);
+    store.set("last".to_owned(), "2".to_owned())?;
+    assert_eq!(store.stats()?.compactions, 1);
+    assert_eq!(jobs.running(), 0);
+
+    store.set("key1".to_owned(), "3".to_owned())?;
+    for _ in 0..2 {
+        assert_eq!(store.get("first".to_owned())?, Some("1".to_owned()));
+        assert_eq!(store.get("key1".to_owned())?, Some("3".to_owned()));
+    }
+    let stats = store.stats()?;
+    assert_eq!((stats.log_files, stats.open_files), (2, 1));
+
+    Ok(())
+}
+
+// Should close the least recently read log file to stay within the cap, and reopen it on demand
+#[test]
+fn reader_cache() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    // Without a memtable, so that every read goes to a log file.
+    let open = || KvStore::builder().max_open_files(1).memtable_size(0).open(temp_dir.path());
+    let mut store = open()?;
+
+    let value = "v".repeat(1024);
+    store.set("old".to_owned(), "1".to_owned())?;
+    for iter in 0..1100 {
+        store.set(format!("key{}", iter % 10), format!("{}{}", value, iter))?;
+    }
+    store.set("new".to_owned(), "2".to_owned())?;
+    assert_eq!(store.stats()?.log_files, 2);
+
+    let evictions = store.stats()?.file_evictions;
+    for _ in 0..3 {
+        assert_eq!(store.get("old".to_owned())?, Some("1".to_owned()));
+        assert_eq!(store.get("new".to_owned())?, Some("2".to_owned()));
+    }
+    let stats = store.stats()?;
+    assert_eq!(stats.open_files, 1);
+    assert!(stats.file_evictions >= evictions + 5);
+
+    drop(store);
+    let mut store = open()?;
+    assert_eq!(store.get("old".to_owned())?, Some("1".to_owned()));
+    assert_eq!(store.get("new".to_owned())?, Some("2".to_owned()));
+
+    Ok(())
+}
+
+// Should compact as soon as there are more stale bytes than the configured threshold
+#[test]
+fn compaction_threshold() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::builder().compaction_threshold(100).open(temp_dir.path())?;
+
+    for iter in 0..10 {
+        store.set("key".to_owned(), format!("value{}", iter))?;
+        assert!(store.stats()?.uncompacted_bytes <= 100);
+    }
+    assert!(store.stats()?.compactions > 0);
+
+    Ok(())
+}
+
+// Should compact once stale commands make up enough of the log, regardless of its size
+#[test]
+fn compaction_ratio() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::builder().compaction_ratio(0.5, 0).open(temp_dir.path())?;
+
+    // Half the log is stale after one overwrite, and more than half after two
+    store.set("key".to_owned(), "value1".to_owned())?;
+    store.set("key".to_owned(), "value2".to_owned())?;
+    let stats = store.stats()?;
+    assert_eq!(stats.compactions, 0);
+    assert_eq!(stats.uncompacted_bytes * 2, stats.log_bytes);
+    store.set("key".to_owned(), "value3".to_owned())?;
+    let stats = store.stats()?;
+    assert_eq!(stats.compactions, 1);
+    assert_eq!(stats.uncompacted_bytes, 0);
+    assert!(stats.log_bytes > 0);
+
+    // The stale bytes are still counted when the store is reopened
+    store.set("key".to_owned(), "value4".to_owned())?;
+    let stats = store.stats()?;
+    drop(store);
+    let mut store = KvStore::builder().compaction_ratio(0.5, 0).open(temp_dir.path())?;
+    let reopened = store.stats()?;
+    assert_eq!(reopened.uncompacted_bytes, stats.uncompacted_bytes);
+    assert_eq!(reopened.log_bytes, stats.log_bytes);
+
+    // Nothing is compacted below the minimum, however stale the log is
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::builder().compaction_ratio(0.5, 1024).open(temp_dir.path())?;
+    for iter in 0..10 {
+        store.set("key".to_owned(), format!("value{}", iter))?;
+    }
+    assert_eq!(store.stats()?.compactions, 0);
+
+    Ok(())
+}
+
+// Compacting only the oldest log files should keep every live value and the sequence of commands
+// intact, across reopens
+#[test]
+fn max_compaction_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let open = || {
+        KvStore::builder()
+            .compaction_ratio(0.5, 4096)
+            .max_compaction_files(2)
+            .open(temp_dir.path())
+    };
+    let mut store = open()?;
+
+    for iter in 0..100 {
+        for key_id in 0..50 {
+            store.set(format!("key{}", key_id), format!("{}", iter))?;
+        }
+    }
+    store.rename("key0".to_owned(), "moved".to_owned(), false)?;
+    store.remove("key1".to_owned())?;
+    assert!(store.stats()?.compactions > 1);
+
+    for store in &mut [store, open()?] {
+        assert_eq!(store.stats()?.keys, 49);
+        assert_eq!(store.get("key0".to_owned())?, None);
+        assert_eq!(store.get("key1".to_owned())?, None);
+        assert_eq!(store.get("moved".to_owned())?, Some("99".to_owned()));
+        assert_eq!(store.get("key49".to_owned())?, Some("99".to_owned()));
+    }
+    let report = KvStore::check(temp_dir.path())?;
+    assert!(report.clean);
+    assert!(report.superseded_files.is_empty());
+
+    Ok(())
+}
+
+// Should start a new log file whenever the current one reaches the maximum segment size
+#[test]
+fn segment_rotation() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let open = || KvStore::builder().max_segment_size(1024).open(temp_dir.path());
+    let mut store = open()?;
+
+    for key_id in 0..500 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+    let stats = store.stats()?;
+    assert!(stats.rotations >= 5);
+    assert_eq!(stats.log_files, stats.rotations + 1);
+    assert!(stats.files.iter().all(|file| file.bytes < 1024 + 64));
+
+    for store in &mut [store, open()?] {
+        assert_eq!(store.stats()?.keys, 500);
+        assert_eq!(store.get("key0".to_owned())?, Some("value0".to_owned()));
+        assert_eq!(store.get("key499".to_owned())?, Some("value499".to_owned()));
+    }
+    assert!(KvStore::check(temp_dir.path())?.clean);
+
+    Ok(())
+}
+
+// Should follow a store that's open elsewhere, seeing its writes, rotations and compactions, but
+// reject writes of its own, and report that its reads can be stale
+#[test]
+fn follower() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::builder()
+        .compaction_threshold(1024)
+        .max_segment_size(512)
+        .open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let mut follower = KvStore::builder()
+        .follow_interval(Duration::from_secs(0))
+        .open_follower(temp_dir.path())?;
+    assert_eq!(follower.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.consistency(), Consistency::Linearizable);
+    assert_eq!(follower.consistency(), Consistency::Stale(Duration::from_secs(0)));
+
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key1".to_owned())?;
+    assert_eq!(follower.get("key1".to_owned())?, None);
+    assert_eq!(follower.scan("key")?, vec![("key2".to_owned(), "value2".to_owned())]);
+    match follower.set("key3".to_owned(), "value3".to_owned()) {
+        Err(Error::ReadOnly) => {},
+        result => panic!("expected ReadOnly, got {:?}", result),
+    }
+
+    for iter in 0..200 {
+        store.set("key2".to_owned(), format!("value{}", iter))?;
+    }
+    let stats = store.stats()?;
+    assert!(stats.rotations > 0);
+    assert!(stats.compactions > 0);
+    assert_eq!(follower.get("key2".to_owned())?, Some("value199".to_owned()));
+    let stats = follower.stats()?;
+    assert_eq!(stats.keys, 1);
+    assert!(stats.follower_rebuilds > 0);
+    assert!(stats.read_only);
+
+    Ok(())
+}
+
+// Should report the live and dead bytes in each log file, and a history of compactions
+#[test]
+fn file_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let clock = ManualClock::new(1_000);
+    let open = || {
+        KvStore::builder().compaction_ratio(0.5, 0).clock(clock.clone()).open(temp_dir.path())
+    };
+    let mut store = open()?;
+
+    store.set("key".to_owned(), "value1".to_owned())?;
+    store.set("key".to_owned(), "value2".to_owned())?;
+    let stats = store.stats()?;
+    assert_eq!(stats.files.len(), 1);
+    let file = &stats.files[0];
+    assert_eq!(file.bytes, stats.log_bytes);
+    assert_eq!(file.live_bytes, file.bytes / 2);
+    assert_eq!(file.dead_bytes, file.bytes / 2);
+    assert!(stats.recent_compactions.is_empty());
+
+    store.set("key".to_owned(), "value3".to_owned())?;
+    let stats = store.stats()?;
+    let live: Vec<_> = stats.files.iter().map(|f| (f.live_bytes, f.dead_bytes)).collect();
+    assert_eq!(live, vec![(file.live_bytes, 0), (0, 0)]);
+    assert_eq!(stats.recent_compactions.len(), 1);
+    let compaction = &stats.recent_compactions[0];
+    assert_eq!((compaction.started_at, compaction.finished_at), (1_000, 1_000));
+    assert_eq!(compaction.reclaimed_bytes, file.bytes);
+
+    // Usage is rebuilt from the log when the store is reopened
+    store.set("key".to_owned(), "value4".to_owned())?;
+    let files: Vec<_> = store.stats()?.files.iter().map(|f| (f.bytes, f.live_bytes)).collect();
+    drop(store);
+    let mut store = open()?;
+    let reopened: Vec<_> = store.stats()?.files.iter().map(|f| (f.bytes, f.live_bytes)).collect();
+    assert_eq!(reopened, files);
+
+    Ok(())
+}
+
+// Should slow down and then reject writes while compaction can't run, until it catches up
+#[test]
+fn write_stall() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let jobs = BackgroundJobs::new(1);
+    let mut store = KvStore::builder()
+        .compaction_ratio(0.5, 0)
+        .background_jobs(jobs.clone())
+        .write_stall(WriteStall {
+            slowdown_bytes: 1,
+            delay: Duration::from_millis(1),
+            stop_bytes: 200,
+        })
+        .open(temp_dir.path())?;
+
+    let slot = jobs.try_start().expect("no background job slot");
+    let mut writes = 0;
+    let result = loop {
+        writes += 1;
+        assert!(writes < 100, "writes were never rejected");
+        match store.set("key".to_owned(), format!("value{}", writes)) {
+            Ok(()) => continue,
+            result => break result,
+        }
+    };
+    match result {
+        Err(Error::Backpressure) => {},
+        result => panic!("expected Backpressure, got {:?}", result),
+    }
+    let stats = store.stats()?;
+    assert!(stats.uncompacted_bytes > 200);
+    assert!(stats.slowed_writes > 0);
+    assert_eq!(stats.rejected_writes, 1);
+    assert_eq!(store.get("key".to_owned())?, Some(format!("value{}", writes - 1)));
+
+    drop(slot);
+    store.set("key".to_owned(), "value".to_owned())?;
+    assert!(store.stats()?.uncompacted_bytes < 200);
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+
+    Ok(())
+}
+
+// Should reserve disk space, and treat a full disk as its own error
+#[test]
+fn reserved_space() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let reserve = temp_dir.path().join("RESERVE");
+
+    let mut store = KvStore::builder().reserved_space(100_000).open(temp_dir.path())?;
+    assert_eq!(fs::metadata(&reserve)?.len(), 100_000);
+    store.set("key".to_owned(), "value".to_owned())?;
+    assert!(!store.stats()?.read_only);
+    drop(store);
+
+    let mut store = KvStore::builder().reserved_space(1000).open(temp_dir.path())?;
+    assert_eq!(fs::metadata(&reserve)?.len(), 1000);
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    drop(store);
+
+    KvStore::open(temp_dir.path())?;
+    assert!(!reserve.exists());
+
+    match Error::from(io::Error::from(io::ErrorKind::StorageFull)) {
+        Error::DiskFull => {},
+        err => panic!("expected DiskFull, got {:?}", err),
+    }
+
+    Ok(())
+}
+
+// Should skip crash recovery after a clean shutdown, unless the log has changed since
+#[test]
+fn clean_shutdown() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(!store.stats()?.recovered);
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(!store.stats()?.recovered);
+    assert_eq!(store.get("key9".to_owned())?, Some("value9".to_owned()));
+    store.set("key10".to_owned(), "value10".to_owned())?;
+
+    // Leaking the store leaves its manifest as it would be after a crash.
+    mem::forget(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(store.stats()?.recovered);
+    assert_eq!(store.stats()?.sequence, 11);
+    assert_eq!(store.get("key10".to_owned())?, Some("value10".to_owned()));
+    drop(store);
+
+    fs::copy(temp_dir.path().join("0.log"), temp_dir.path().join("1.log"))?;
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(store.stats()?.recovered);
+    assert_eq!(store.stats()?.replay_duplicates, 11);
+
+    Ok(())
+}
+
+// Should check every command when rebuilding the index, even after a clean shutdown
+#[test]
+fn rebuild_index() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    drop(store);
+
+    let mut store = KvStore::open_with_rebuild(temp_dir.path())?;
+    assert!(!store.stats()?.recovered);
+    assert_eq!(store.get("key9".to_owned())?, Some("value9".to_owned()));
+    drop(store);
+
+    // Corrupt a sequence number without changing the log's length, which a clean open can't
+    // notice. The command's checksum is rewritten to match, so only the sequence check can.
+    let log_path = temp_dir.path().join("0.log");
+    let mut log = fs::read(&log_path)?;
+    let command: &[u8] = b"\xc4\x04key5\x06";
+    let at = log.windows(command.len()).position(|bytes| bytes == command).expect("key5 is set");
+    log[at + command.len() - 1] = 12;
+    rewrite_checksum(&mut log, b"\xc4\x06value5");
+    fs::write(&log_path, log)?;
+    match KvStore::open_with_rebuild(temp_dir.path()) {
+        Err(Error::SequenceGap { expected: 6, found: 12 }) => {},
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("expected a sequence gap"),
+    }
+
+    Ok(())
+}
+
+// Should report a command that fails its checksum as corrupt, rather than misreading it
+#[test]
+fn corrupt_command() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    drop(store);
+
+    // Flip a byte of a value behind the open store's back, without changing the log's length.
+    let mut store = KvStore::open(temp_dir.path())?;
+    let log_path = temp_dir.path().join("0.log");
+    let mut log = fs::read(&log_path)?;
+    let value: &[u8] = b"\xc4\x06value5";
+    let at = log.windows(value.len()).position(|bytes| bytes == value).expect("key5 is set");
+    log[at + 2] = b'V';
+    fs::write(&log_path, log)?;
+
+    let command_start = at as u64 - 3 - 8;
+    match store.get("key5".to_owned()) {
+        Err(Error::Corruption { file, offset }) => {
+            assert_eq!(file, log_path);
+            assert_eq!(offset, command_start);
+        },
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(value) => panic!("expected corruption, got {:?}", value),
+    }
+    assert_eq!(store.get("key4".to_owned())?, Some("value4".to_owned()));
+    drop(store);
+
+    match KvStore::open(temp_dir.path()) {
+        Err(Error::Corruption { offset, .. }) => assert_eq!(offset, command_start),
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("expected corruption"),
+    }
+
+    Ok(())
+}
+
+// Should discard a command torn by a crash from the end of the log when opening, unless recovery
+// is strict
+#[test]
+fn torn_tail() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    drop(store);
+
+    // Leave the start of another command at the end of the log.
+    let log_path = temp_dir.path().join("0.log");
+    let length = fs::metadata(&log_path)?.len();
+    let mut log = fs::read(&log_path)?;
+    log.extend_from_slice(b"\x20\x00\x00\x00\x12\x34");
+    fs::write(&log_path, log)?;
+
+    match KvStore::builder().strict_recovery(true).open(temp_dir.path()) {
+        Err(Error::Corruption { offset, .. }) => assert_eq!(offset, length),
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("expected corruption"),
+    }
+    assert_eq!(fs::metadata(&log_path)?.len(), length + 6);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.stats()?.torn_tail_bytes, 6);
+    assert_eq!(fs::metadata(&log_path)?.len(), length);
+    assert_eq!(store.get("key9".to_owned())?, Some("value9".to_owned()));
+    store.set("key10".to_owned(), "value10".to_owned())?;
+    drop(store);
+
+    let mut store = KvStore::builder().strict_recovery(true).open(temp_dir.path())?;
+    assert_eq!(store.stats()?.torn_tail_bytes, 0);
+    assert_eq!(store.get("key10".to_owned())?, Some("value10".to_owned()));
+    Ok(())
+}
+
+// Discarding a torn command should only excuse that command's sequence number from the manifest's,
+// not a larger gap hidden behind it
+#[test]
+fn torn_tail_sequence_gap() -> Result<()> {
+    // Write ten commands, then cut the log partway through the command setting `key`.
+    let cut_at = |key: &[u8]| -> Result<TempDir> {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let mut store = KvStore::open(temp_dir.path())?;
+        for i in 0..10 {
+            store.set(format!("key{}", i), format!("value{}", i))?;
+        }
+        drop(store);
+
+        let log_path = temp_dir.path().join("0.log");
+        let mut log = fs::read(&log_path)?;
+        let at = log.windows(key.len()).position(|bytes| bytes == key).expect("key is set");
+        log.truncate(at + 2);
+        fs::write(&log_path, log)?;
+        Ok(temp_dir)
+    };
+
+    // Only the last command was torn.
+    let temp_dir = cut_at(b"\xc4\x04key9")?;
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(store.stats()?.torn_tail_bytes > 0);
+    assert_eq!(store.get("key8".to_owned())?, Some("value8".to_owned()));
+    assert_eq!(store.get("key9".to_owned())?, None);
+    drop(store);
+
+    // The last command was lost entirely, and the one before it torn.
+    let temp_dir = cut_at(b"\xc4\x04key8")?;
+    match KvStore::open(temp_dir.path()) {
+        Err(Error::SequenceGap { expected: 9, found: 8 }) => {},
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("expected a sequence gap"),
+    }
+    Ok(())
+}
+
+// Should keep every write with each sync policy, including across rotations and compactions
+#[test]
+fn sync_policies() -> Result<()> {
+    assert_eq!("os".parse::<SyncPolicy>()?, SyncPolicy::Os);
+    assert_eq!("250ms".parse::<SyncPolicy>()?, SyncPolicy::Every(Duration::from_millis(250)));
+    assert!("0ms".parse::<SyncPolicy>().is_err());
+    assert!("sometimes".parse::<SyncPolicy>().is_err());
+
+    let every = SyncPolicy::Every(Duration::from_millis(5));
+    for &policy in &[SyncPolicy::Always, every, SyncPolicy::Os] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let mut store = KvStore::builder()
+            .sync_policy(policy)
+            .max_segment_size(256)
+            .compaction_threshold(512)
+            .open(temp_dir.path())?;
+        for i in 0..100 {
+            store.set(format!("key{}", i % 20), format!("value{}", i))?;
+            if i % 10 == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        let stats = store.stats()?;
+        assert!(stats.rotations > 0 && stats.compactions > 0);
+        assert_eq!(stats.failed_syncs, 0);
+        drop(store);
+
+        let mut store = KvStore::open(temp_dir.path())?;
+        for i in 80..100 {
+            assert_eq!(store.get(format!("key{}", i % 20))?, Some(format!("value{}", i)));
+        }
+    }
+    Ok(())
+}
+
+// Recompute the checksum of the command in `log` whose value is `value`, after the command has
+// been edited in place.
+fn rewrite_checksum(log: &mut [u8], value: &[u8]) {
+    let at = log.windows(value.len()).position(|bytes| bytes == value).expect("value is set");
+    let (frame, command) = (at - 3 - 8, at - 3);
+    let mut length = [0; 4];
+    length.copy_from_slice(&log[frame..frame + 4]);
+    let end = command + u32::from_le_bytes(length) as usize;
+    let checksum = crc32fast::hash(&log[command..end]);
+    log[frame + 4..command].copy_from_slice(&checksum.to_le_bytes());
+}
+
+// Should report writes and compactions to a metrics sink
+#[test]
+fn metrics() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let metrics = PrometheusMetrics::new();
+    let mut store = KvStore::builder()
+        .compaction_ratio(0.5, 0)
+        .metrics(metrics.clone())
+        .open(temp_dir.path())?;
+
+    for i in 0..10 {
+        store.set("key".to_owned(), format!("value{}", i))?;
+    }
+    store.remove("key".to_owned())?;
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("# TYPE kvs_engine_writes_total counter\n"));
+    assert!(rendered.contains("kvs_engine_writes_total 11\n"));
+    assert!(rendered.contains("# TYPE kvs_engine_compaction_seconds histogram\n"));
+    let compactions = store.stats()?.compactions;
+    assert!(compactions > 0);
+    assert!(rendered.contains(&format!("kvs_engine_compactions_total {}\n", compactions)));
+    assert!(rendered.contains(&format!("kvs_engine_compaction_seconds_count {}\n", compactions)));
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct LegacyFilter;
+
+impl CompactionFilter for LegacyFilter {
+    fn filter(&self, _key: &str, value: &str) -> CompactionDecision {
+        if value == "deleted" {
+            CompactionDecision::Remove
+        } else if let Some(value) = value.strip_prefix("v1:") {
+            CompactionDecision::Change(value.to_owned())
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+}
+
+// Should drop or change entries as the compaction filter decides, but never system keys
+#[test]
+fn compaction_filter() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let builder = || KvStore::builder().compaction_ratio(0.5, 0).compaction_filter(LegacyFilter);
+    let mut store = builder().open(temp_dir.path())?;
+    let system_key = format!("{}marker", SYSTEM_KEY_PREFIX);
+    store.set("user:1".to_owned(), "deleted".to_owned())?;
+    store.set("user:2".to_owned(), "v1:bob".to_owned())?;
+    store.set("user:3".to_owned(), "alice".to_owned())?;
+    store.set(system_key.clone(), "deleted".to_owned())?;
+
+    // Entries are only filtered when the log is compacted
+    assert_eq!(store.get("user:1".to_owned())?, Some("deleted".to_owned()));
+    for iter in 0..20 {
+        store.set("counter".to_owned(), format!("value{}", iter))?;
+    }
+    assert!(store.stats()?.compactions > 0);
+
+    for _ in 0..2 {
+        assert_eq!(store.get("user:1".to_owned())?, None);
+        assert_eq!(store.get("user:2".to_owned())?, Some("bob".to_owned()));
+        assert_eq!(store.get("user:3".to_owned())?, Some("alice".to_owned()));
+        assert_eq!(store.get(system_key.clone())?, Some("deleted".to_owned()));
+        assert_eq!(store.get("counter".to_owned())?, Some("value19".to_owned()));
+        drop(store);
+        store = builder().open(temp_dir.path())?;
+    }
+
+    Ok(())
+}
+
+// Should serve recently written values from memory, and read older ones from the log
+#[test]
+fn memtable() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::builder().memtable_size(2).open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    store.set("key2".to_owned(), "value4".to_owned())?;
+
+    assert_eq!(store.get("key2".to_owned())?, Some("value4".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.stats()?.memtable_hits, 2);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.stats()?.memtable_hits, 2);
+
+    store.remove("key3".to_owned())?;
+    assert_eq!(store.get("key3".to_owned())?, None);
+    drop(store);
+
+    let mut store = KvStore::builder().memtable_size(0).open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value5".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value5".to_owned()));
+    assert_eq!(store.stats()?.memtable_hits, 0);
+
+    Ok(())
+}
+
+// Should keep small values in the index, counting them towards its memory, across reopens and
+// compactions
+#[test]
+fn inline_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let plain_dir = TempDir::new().expect("unable to create temporary working directory");
+    let builder = || KvStore::builder().inline_values(8);
+    let mut store = builder().open(temp_dir.path())?;
+    let mut plain = KvStore::open(plain_dir.path())?;
+    for store in [&mut store, &mut plain] {
+        store.set("small".to_owned(), "value".to_owned())?;
+        store.set("large".to_owned(), "a much larger value".to_owned())?;
+    }
+    let plain_bytes = plain.stats()?.index_bytes;
+
+    for _ in 0..2 {
+        assert_eq!(store.get("small".to_owned())?, Some("value".to_owned()));
+        assert_eq!(store.get("large".to_owned())?, Some("a much larger value".to_owned()));
+        assert_eq!(store.stats()?.index_bytes, plain_bytes + 5);
+        store.maintain()?;
+        assert_eq!(store.stats()?.index_bytes, plain_bytes + 5);
+        drop(store);
+        store = builder().open(temp_dir.path())?;
+    }
+
+    store.remove("small".to_owned())?;
+    assert_eq!(store.get("small".to_owned())?, None);
+    assert!(store.stats()?.index_bytes < plain_bytes);
+
+    Ok(())
+}
+
+// Should scrub every version of an erased key from the log, and record the erasure
+#[test]
+fn erase() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("user:1".to_owned(), "secret-v1".to_owned())?;
+    store.set("user:1".to_owned(), "secret-v2".to_owned())?;
+    store.set("user:2".to_owned(), "public".to_owned())?;
+    store.set("user:3".to_owned(), "secret-v3".to_owned())?;
+    store.remove("user:3".to_owned())?;
+
+    let erasure = store.erase("user:1".to_owned())?;
+    assert!(erasure.was_live);
+    assert!(!store.erase("user:3".to_owned())?.was_live);
+    assert_eq!(store.get("user:1".to_owned())?, None);
+    assert_eq!(store.get("user:2".to_owned())?, Some("public".to_owned()));
+
+    for entry in WalkDir::new(temp_dir.path()) {
+        let entry = entry.expect("unable to read directory entry");
+        if entry.path().extension() == Some("log".as_ref()) {
+            let contents = String::from_utf8_lossy(&fs::read(entry.path())?).into_owned();
+            assert!(!contents.contains("secret"), "erased value in {}", entry.path().display());
+            assert!(!contents.contains("user:1"), "erased key in {}", entry.path().display());
+        }
+    }
+
+    drop(store);
+    let erasures = KvStore::erasures(temp_dir.path())?;
+    assert_eq!(erasures.len(), 2);
+    assert_eq!(erasures[0], erasure);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("user:1".to_owned())?, None);
+    assert_eq!(store.get("user:2".to_owned())?, Some("public".to_owned()));
+
+    Ok(())
+}
+
+// Removes should be synced if the value they remove may be durable, and never be undone by replay
+#[test]
+fn durable_removes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    // Neither the value nor the remove have been synced, so they're equally durable.
+    store.set("a".to_owned(), "1".to_owned())?;
+    store.remove("a".to_owned())?;
+    assert_eq!(store.stats()?.synced_removes, 0);
+    store.set("b".to_owned(), "2".to_owned())?;
+    drop(store);
+
+    // The value was synced on close, so the remove must be too.
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.remove("b".to_owned())?;
+    assert_eq!(store.stats()?.synced_removes, 1);
+
+    // Leaking the store leaves it as it would be after a crash.
+    mem::forget(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(store.stats()?.recovered);
+    assert_eq!(store.get("a".to_owned())?, None);
+    assert_eq!(store.get("b".to_owned())?, None);
+
+    Ok(())
+}
+
+// Key samples should be uniform, and only include live keys that aren't system keys
+#[test]
+fn sample_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let clock = ManualClock::new(1_000_000);
+    let mut store = KvStore::builder().clock(clock.clone()).open(temp_dir.path())?;
+    store.system_keys().init()?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), "value".to_owned())?;
+    }
+    store.set_with_ttl("expired".to_owned(), "value".to_owned(), Duration::from_secs(1))?;
+    clock.advance(Duration::from_secs(1));
+
+    let mut keys = store.sample_keys(100)?;
+    keys.sort();
+    assert_eq!(keys, (0..10).map(|i| format!("key{}", i)).collect::<Vec<_>>());
+    let keys = store.sample_keys(3)?;
+    assert_eq!(keys.len(), 3);
+    assert!(keys.iter().all(|key| key.starts_with("key")));
+    assert!(store.sample_keys(0)?.is_empty());
+
+    // Each of 10 keys should be sampled about 1000 times in 10000 samples of 1.
+    let mut counts = [0; 10];
+    for _ in 0..10_000 {
+        let mut sample = KeySample::new(1);
+        for i in 0..10 {
+            sample.offer(&i.to_string());
+        }
+        assert_eq!(sample.offered(), 10);
+        counts[sample.into_keys()[0].parse::<usize>().unwrap()] += 1;
+    }
+    assert!(counts.iter().all(|&count| count > 800 && count < 1200), "{:?}", counts);
+
+    Ok(())
+}
+
+// Prefix removals should remove every live matching key without touching system keys, and the
+// removals should survive a restart
+#[test]
+fn remove_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.system_keys().init()?;
+    for i in 0..5 {
+        store.set(format!("session:{}", i), "value".to_owned())?;
+    }
+    store.set_with_ttl("session:expired".to_owned(), "value".to_owned(), Duration::from_millis(1))?;
+    store.set("user:1".to_owned(), "alice".to_owned())?;
+    thread::sleep(Duration::from_millis(10));
+
+    assert_eq!(store.count_prefix("session:")?, 5);
+    assert_eq!(store.count_prefix("")?, 6);
+    let removed = store.remove_prefix("session:")?;
+    assert_eq!(removed, (0..5).map(|i| format!("session:{}", i)).collect::<Vec<_>>());
+    assert_eq!(store.count_prefix("session:")?, 0);
+    assert_eq!(store.remove_prefix("")?, vec!["user:1".to_owned()]);
+    assert!(store.system_keys().store_id()?.is_some());
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("session:1".to_owned())?, None);
+    assert_eq!(store.count_prefix("")?, 0);
+    Ok(())
+}
+
+// Clones of a shared store should all operate on the same store, from any thread
+#[test]
+fn shared_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SharedEngine::new(KvStore::open(temp_dir.path())?);
+
+    let handles: Vec<_> = (0..4)
+        .map(|thread| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 0..100 {
+                    let key = format!("key{}-{}", thread, i);
+                    store.set(key.clone(), i.to_string()).expect("set failed");
+                    assert_eq!(store.get(key).expect("get failed"), Some(i.to_string()));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("thread panicked");
+    }
+
+    let mut engine = store.clone();
+    assert_eq!(engine.name(), "kvs");
+    assert_eq!(engine.count_prefix("key")?, 400);
+    store.remove("key0-0".to_owned())?;
+    assert_eq!(store.with(|store| store.get("key0-0".to_owned()))?, None);
+    assert_eq!(engine.stats()?.keys, 399);
+    Ok(())
+}
+
+// Threads sharing a store should each see their own writes, and never see another thread's
+// writes go backwards, while compactions run between their operations
+#[test]
+fn shared_engine_under_compaction() -> Result<()> {
+    const THREADS: usize = 4;
+    const WRITES: u64 = 300;
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder().compaction_threshold(1024).open(temp_dir.path())?;
+    let store = SharedEngine::new(store);
+    assert_eq!(store.consistency(), Consistency::Linearizable);
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let store = store.clone();
+            thread::spawn(move || -> Result<()> {
+                let mut seen = [0; THREADS];
+                for n in 1..=WRITES {
+                    store.set(format!("counter{}", thread), n.to_string())?;
+                    store.set(format!("scratch{}", thread), n.to_string())?;
+                    store.remove(format!("scratch{}", thread))?;
+                    for (other, last) in seen.iter_mut().enumerate() {
+                        let counter = store.get(format!("counter{}", other))?;
+                        let counter = counter.map_or(0, |n| n.parse().expect("invalid counter"));
+                        assert!(counter >= *last, "counter{} went back to {}", other, counter);
+                        if other == thread {
+                            assert_eq!(counter, n);
+                        }
+                        *last = counter;
+                    }
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("thread panicked")?;
+    }
+
+    let mut engine = store.clone();
+    for thread in 0..THREADS {
+        assert_eq!(store.get(format!("counter{}", thread))?, Some(WRITES.to_string()));
+    }
+    assert_eq!(engine.count_prefix("scratch")?, 0);
+    assert!(engine.stats()?.compactions > 0);
+    Ok(())
+}
+
+// Every engine should round-trip binary keys and values, which fail cleanly when read as strings
+#[test]
+fn binary_keys_and_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engines: Vec<Box<dyn KvsEngine>> = vec![
+        Box::new(MemoryKvStore::new()),
+        Box::new(SledKvStore::start_default(temp_dir.path().join("sled"))?),
+        Box::new(KvStore::open(temp_dir.path().join("kvs"))?),
+    ];
+    let (key, value) = (&[0xff, 0x00, b'k'][..], &[0xc3, 0x28, 0x00][..]);
+    for mut engine in engines {
+        engine.set_bytes(key, value)?;
+        assert_eq!(engine.get_bytes(key)?, Some(value.to_vec()));
+        assert_eq!(engine.scan_bytes(&[0xff])?, vec![(key.to_vec(), value.to_vec())]);
+        engine.set_bytes(b"text", value)?;
+        assert!(matches!(engine.get("text".to_owned()), Err(Error::InvalidValue { .. })));
+        engine.set("name".to_owned(), "value".to_owned())?;
+        assert_eq!(engine.get_bytes(b"name")?, Some(b"value".to_vec()));
+        engine.remove_bytes(key)?;
+        assert_eq!(engine.get_bytes(key)?, None);
+        assert!(matches!(engine.remove_bytes(key), Err(Error::KeyNotFound)));
+    }
+
+    Ok(())
+}
+
+// Binary keys and values should survive prefix compression, compactions and reopens, and string
+// reads should reject them
+#[test]
+fn binary_log() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let open = || {
+        KvStore::builder().prefix_compression(true).compaction_ratio(0.5, 0).open(temp_dir.path())
+    };
+    let mut store = open()?;
+
+    let keys = [&[0xff, 0x00, 0x01][..], &[0xff, 0x00, 0x02], &[0xff, 0xfe]];
+    for iter in 0..300 {
+        store.set_bytes(keys[iter % keys.len()], &[0xc3, 0x28, iter as u8])?;
+    }
+    store.set("text".to_owned(), "value".to_owned())?;
+    assert!(store.stats()?.compactions > 0);
+
+    for store in &mut [store, open()?] {
+        for (i, key) in keys.iter().enumerate() {
+            let last_iter = 299 - (299 - i) % keys.len();
+            assert_eq!(store.get_bytes(key)?, Some(vec![0xc3, 0x28, last_iter as u8]));
+        }
+        assert_eq!(store.scan_bytes(&[0xff, 0x00])?.len(), 2);
+        assert!(matches!(store.scan(""), Err(Error::InvalidKey(_))));
+        assert_eq!(store.get("text".to_owned())?, Some("value".to_owned()));
+    }
+
+    Ok(())
+}
+
+// Threads syncing a shared store should share syncs through sync points, which can be waited on
+// without the store, including while it moves on to new log files
+#[test]
+fn group_commit() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::builder().max_segment_size(1024).open(temp_dir.path())?;
+    store.set("before".to_owned(), "point".to_owned())?;
+    let point = store.sync_point().expect("kvs stores have sync points");
+    store.set("after".to_owned(), "point".to_owned())?;
+    point.wait()?;
+    assert!(MemoryKvStore::new().sync_point().is_none());
+
+    let store = SharedEngine::new(store);
+    let handles: Vec<_> = (0..8)
+        .map(|thread| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 0..50 {
+                    store.set(format!("key{}-{}", thread, i), i.to_string()).expect("set failed");
+                    store.sync().expect("sync failed");
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("thread panicked");
+    }
+    let mut engine = store.clone();
+    assert!(engine.stats()?.rotations > 0);
+    engine.sync()?;
+    drop((store, engine));
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.count_prefix("key")?, 400);
+    assert_eq!(store.get("key7-49".to_owned())?, Some("49".to_owned()));
+    assert_eq!(store.get("after".to_owned())?, Some("point".to_owned()));
+    Ok(())
+}
+
+// Transactions should see their own writes, and fail to read or commit keys written by another
+// handle since they began
+#[test]
+fn transactions() -> Result<()> {
+    let store = SharedEngine::new(MemoryKvStore::new());
+    store.set("balance:a".to_owned(), "10".to_owned())?;
+
+    let mut transaction = store.begin_transaction();
+    assert_eq!(transaction.get("balance:a".to_owned())?, Some("10".to_owned()));
+    transaction.set("balance:a".to_owned(), "5".to_owned());
+    transaction.remove("balance:a".to_owned());
+    assert_eq!(transaction.get("balance:a".to_owned())?, None);
+    transaction.set("balance:b".to_owned(), "5".to_owned());
+    store.set("balance:c".to_owned(), "1".to_owned())?;
+    transaction.commit()?;
+    assert_eq!(store.get("balance:a".to_owned())?, None);
+    assert_eq!(store.get("balance:b".to_owned())?, Some("5".to_owned()));
+
+    let mut transaction = store.begin_transaction();
+    let mut other = store.begin_transaction();
+    transaction.set("balance:b".to_owned(), "0".to_owned());
+    other.set("balance:b".to_owned(), "9".to_owned());
+    other.commit()?;
+    assert!(matches!(transaction.commit(), Err(Error::Conflict(_))));
+    assert_eq!(store.get("balance:b".to_owned())?, Some("9".to_owned()));
+
+    let mut transaction = store.begin_transaction();
+    store.remove("balance:c".to_owned())?;
+    assert!(matches!(transaction.get("balance:c".to_owned()), Err(Error::Conflict(_))));
+    transaction.set("balance:d".to_owned(), "1".to_owned());
+    drop(transaction);
+    assert_eq!(store.get("balance:d".to_owned())?, None);
+    Ok(())
+}
+
+// Compare-and-swaps should only set keys with the expected value, and concurrent increments
+// from clones of a shared store should never be lost
+#[test]
+fn compare_and_swap_and_increment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SharedEngine::new(KvStore::open(temp_dir.path())?);
+
+    assert!(store.compare_and_swap("lock".to_owned(), None, "a".to_owned())?);
+    assert!(!store.compare_and_swap("lock".to_owned(), None, "b".to_owned())?);
+    assert!(!store.compare_and_swap("lock".to_owned(), Some("b".to_owned()), "c".to_owned())?);
+    assert!(store.compare_and_swap("lock".to_owned(), Some("a".to_owned()), "c".to_owned())?);
+    assert_eq!(store.get("lock".to_owned())?, Some("c".to_owned()));
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    store.increment("counter".to_owned(), 1).expect("increment failed");
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("thread panicked");
+    }
+    assert_eq!(store.get("counter".to_owned())?, Some("400".to_owned()));
+    assert_eq!(store.increment("counter".to_owned(), -401)?, -1);
+
+    assert!(matches!(
+        store.increment("lock".to_owned(), 1),
+        Err(Error::InvalidValue { ref key, .. }) if key == "lock"
+    ));
+    store.set("max".to_owned(), i64::MAX.to_string())?;
+    assert!(matches!(store.increment("max".to_owned(), 1), Err(Error::InvalidValue { .. })));
+    assert_eq!(store.get("max".to_owned())?, Some(i64::MAX.to_string()));
+
+    // Engines get the same behaviour without a shared handle.
+    let mut store = MemoryKvStore::new();
+    assert_eq!(store.increment("counter".to_owned(), 5)?, 5);
+    assert!(store.compare_and_swap("counter".to_owned(), Some("5".to_owned()), "7".to_owned())?);
+    assert_eq!(store.increment("counter".to_owned(), 1)?, 8);
+    Ok(())
+}
+
+// Renames should move a key's value and expiry in a single log record, which survives a restart
+// and compaction
+#[test]
+fn rename() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("draft:1".to_owned(), "hello".to_owned())?;
+    store.set_with_ttl("draft:2".to_owned(), "bye".to_owned(), Duration::from_secs(3600))?;
+    store.set("post:2".to_owned(), "old".to_owned())?;
+
+    assert!(store.rename("draft:1".to_owned(), "post:1".to_owned(), false)?);
+    assert!(!store.rename("draft:2".to_owned(), "post:2".to_owned(), false)?);
+    assert!(store.rename("draft:2".to_owned(), "post:2".to_owned(), true)?);
+    assert!(store.rename("post:1".to_owned(), "post:1".to_owned(), false)?);
+    match store.rename("draft:1".to_owned(), "post:3".to_owned(), true) {
+        Err(Error::KeyNotFound) => {},
+        result => panic!("expected KeyNotFound, got {:?}", result),
+    }
+    assert_eq!(store.get("post:1".to_owned())?, Some("hello".to_owned()));
+    assert_eq!(store.get("post:2".to_owned())?, Some("bye".to_owned()));
+    assert_eq!(store.count_prefix("draft:")?, 0);
+    assert_eq!(store.stats()?.expiring_keys, 1);
+
+    drop(store);
+    let report = KvStore::check(temp_dir.path())?;
+    assert!(report.clean);
+    assert_eq!(report.keys, 2);
+    assert_eq!(report.files.iter().map(|file| file.renames).sum::<u64>(), 2);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("draft:1".to_owned())?, None);
+    assert_eq!(store.get("post:1".to_owned())?, Some("hello".to_owned()));
+    assert_eq!(store.stats()?.expiring_keys, 1);
+    store.maintain()?;
+    assert_eq!(store.scan("")?.len(), 2);
+    assert_eq!(store.get("post:2".to_owned())?, Some("bye".to_owned()));
+
+    // Other engines rename with separate writes.
+    let mut store = MemoryKvStore::new();
+    store.set("a".to_owned(), "1".to_owned())?;
+    assert!(store.rename("a".to_owned(), "b".to_owned(), false)?);
+    assert_eq!(store.get("a".to_owned())?, None);
+    assert_eq!(store.get("b".to_owned())?, Some("1".to_owned()));
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct User {
+    name: String,
+    age: u32,
+}
+
+// Typed values should round trip through either format, failing clearly if they can't be read
+#[test]
+fn typed_values() -> Result<()> {
+    let alice = User { name: "alice".to_owned(), age: 30 };
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set_from("user:1".to_owned(), &alice)?;
+    assert_eq!(store.get("user:1".to_owned())?, Some(r#"{"name":"alice","age":30}"#.to_owned()));
+    assert_eq!(store.get_as("user:1".to_owned())?, Some(alice));
+    assert_eq!(store.get_as::<User>("user:2".to_owned())?, None);
+    match store.get_as::<Vec<u32>>("user:1".to_owned()) {
+        Err(Error::InvalidValue { key, .. }) => assert_eq!(key, "user:1"),
+        result => panic!("expected InvalidValue, got {:?}", result),
+    }
+    drop(store);
+
+    let builder = KvStore::builder().value_format(ValueFormat::MessagePack);
+    let mut store = builder.open(temp_dir.path())?;
+    match store.get_as::<User>("user:1".to_owned()) {
+        Err(Error::InvalidValue { reason, .. }) => assert_eq!(reason, "invalid hex"),
+        result => panic!("expected InvalidValue, got {:?}", result),
+    }
+    let bob = User { name: "bob".to_owned(), age: 40 };
+    store.set_from("user:2".to_owned(), &bob)?;
+    assert_eq!(store.get("user:2".to_owned())?, Some("92a3626f6228".to_owned()));
+    assert_eq!(store.get_as("user:2".to_owned())?, Some(bob));
+    Ok(())
+}
+
+// Range scans should return the keys from the start (inclusive) to the end (exclusive), in key
+// order, with every engine and index
+#[test]
+fn scan_range() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut engines: Vec<Box<dyn KvsEngine>> = vec![
+        Box::new(MemoryKvStore::new()),
+        Box::new(KvStore::open(temp_dir.path().join("memory"))?),
+        Box::new(KvStore::builder().prefix_compression(true).open(temp_dir.path().join("prefix"))?),
+        Box::new(KvStore::builder().disk_index(1024 * 1024).open(temp_dir.path().join("disk"))?),
+        Box::new(SledKvStore::start_default(temp_dir.path().join("sled"))?),
+    ];
+    let entry = |key: &str| (key.to_owned(), format!("value of {}", key));
+    for engine in &mut engines {
+        for key in &["b", "a", "c", "ab", "d"] {
+            engine.set(key.to_string(), format!("value of {}", key))?;
+        }
+        assert_eq!(engine.scan_range("ab", Some("d"))?, vec![entry("ab"), entry("b"), entry("c")]);
+        assert_eq!(engine.scan_range("c", None)?, vec![entry("c"), entry("d")]);
+        assert_eq!(engine.scan_range("", Some("b"))?, vec![entry("a"), entry("ab")]);
+        assert!(engine.scan_range("c", Some("c"))?.is_empty());
+        assert_eq!(engine.scan_range("aa", Some("b"))?, vec![entry("ab")]);
+        assert_eq!(engine.scan_range("abc", Some("c"))?, vec![entry("b")]);
+        assert_eq!(engine.scan("a")?, vec![entry("a"), entry("ab")]);
+    }
+    Ok(())
+}
+
+// Batches should get, set and remove every key, skipping missing keys when removing, with every
+// engine
+#[test]
+fn batches() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut engines: Vec<Box<dyn KvsEngine>> = vec![
+        Box::new(MemoryKvStore::new()),
+        Box::new(KvStore::open(temp_dir.path().join("kvs"))?),
+        Box::new(SledKvStore::start_default(temp_dir.path().join("sled"))?),
+    ];
+    for engine in &mut engines {
+        let pairs = (0..3).map(|i| (format!("key{}", i), format!("value{}", i))).collect();
+        engine.set_many(pairs)?;
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            engine.get_many(keys(&["key2", "key3", "key0"]))?,
+            vec![Some("value2".to_owned()), None, Some("value0".to_owned())]
+        );
+        let removed = engine.remove_many(keys(&["key2", "key3", "key0", "key2"]))?;
+        assert_eq!(removed, keys(&["key2", "key0"]));
+        assert_eq!(engine.scan("")?, vec![("key1".to_owned(), "value1".to_owned())]);
+    }
+
+    // Removals are still durable after a restart.
+    drop(engines);
+    let mut store = KvStore::open(temp_dir.path().join("kvs"))?;
+    assert_eq!(store.get("key0".to_owned())?, None);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// Should verify entries a few at a time, finding any corrupted on disk
+#[test]
+fn scrub() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for i in 0..5 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    assert_eq!(store.scrub(2)?, Vec::<String>::new());
+    assert_eq!(store.stats()?.scrubbed_entries, 2);
+    assert_eq!(store.stats()?.scrub_passes, 0);
+    assert_eq!(store.scrub(2)?, Vec::<String>::new());
+    assert_eq!(store.scrub(2)?, Vec::<String>::new());
+    assert_eq!(store.stats()?.scrubbed_entries, 5);
+    assert_eq!(store.stats()?.scrub_passes, 1);
+
+    // Corrupt a key behind the open store's back, without changing the log's length.
+    let log_path = temp_dir.path().join("0.log");
+    let mut log = fs::read(&log_path)?;
+    let key: &[u8] = b"\xc4\x04key3";
+    let at = log.windows(key.len()).position(|bytes| bytes == key).expect("key3 is set");
+    log[at + 4] = b'z';
+    fs::write(&log_path, log)?;
+
+    assert_eq!(store.scrub(10)?, vec!["key3".to_owned()]);
+    let stats = store.stats()?;
+    assert_eq!(stats.scrubbed_entries, 10);
+    assert_eq!(stats.scrub_passes, 2);
+    assert_eq!(stats.corrupt_entries, 1);
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use kvs::{JsonDrain, Result, RotatingFile, RotationConfig};
+use slog::{info, o, warn, Drain};
+use std::fs;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Log files should be rotated between lines once they're too big, keeping `max_files` old files
+#[test]
+fn rotate_by_size() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("kvs.log");
+    let config = RotationConfig { max_file_size: Some(10), max_age: None, max_files: 2 };
+    let mut file = RotatingFile::open(&path, config)?;
+
+    // Lines written in pieces aren't split across files, even once they're over the limit
+    for line in &["first", "second", "third", "fourth"] {
+        file.write_all(line.as_bytes())?;
+        file.write_all(b" line\n")?;
+    }
+    assert_eq!(fs::read_to_string(&path)?, "fourth line\n");
+    assert_eq!(fs::read_to_string(temp_dir.path().join("kvs.log.1"))?, "third line\n");
+    assert_eq!(fs::read_to_string(temp_dir.path().join("kvs.log.2"))?, "second line\n");
+    assert!(!temp_dir.path().join("kvs.log.3").exists());
+    Ok(())
+}
+
+// Log files should be rotated once they've been written to for `max_age`
+#[test]
+fn rotate_by_age() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("kvs.log");
+    let max_age = Some(Duration::from_millis(50));
+    let config = RotationConfig { max_file_size: None, max_age, max_files: 1 };
+    let mut file = RotatingFile::open(&path, config)?;
+
+    file.write_all(b"old\n")?;
+    file.write_all(b"still old\n")?;
+    thread::sleep(Duration::from_millis(100));
+    file.write_all(b"new\n")?;
+    assert_eq!(fs::read_to_string(&path)?, "new\n");
+    assert_eq!(fs::read_to_string(temp_dir.path().join("kvs.log.1"))?, "old\nstill old\n");
+    Ok(())
+}
+
+// JSON logs should have one object per record, with its level, message and key-value pairs
+#[test]
+fn json_drain() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("kvs.log");
+    let file = RotatingFile::open(&path, RotationConfig::default())?;
+    let log = slog::Logger::root(JsonDrain::new(file).fuse(), o!("address" => "here"));
+    info!(log, "Starting {}", "server"; "keys" => 3, "ready" => true);
+    warn!(log, "Stopping");
+
+    let content = fs::read_to_string(&path)?;
+    let lines: Vec<serde_json::Value> =
+        content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["level"], "INFO");
+    assert_eq!(lines[0]["msg"], "Starting server");
+    assert_eq!(lines[0]["address"], "here");
+    assert_eq!(lines[0]["keys"], 3);
+    assert_eq!(lines[0]["ready"], true);
+    assert!(lines[0]["ts"].as_str().unwrap().ends_with('Z'));
+    assert_eq!(lines[1]["level"], "WARN");
+    Ok(())
+}
@@ -0,0 +1,201 @@
+// Model checks of a shared engine used from several threads, run by loom in every interleaving
+// of their operations:
+//
+//     RUSTFLAGS="--cfg kvs_loom" cargo test --release --test loom
+#![cfg(kvs_loom)]
+
+use std::collections::BTreeMap;
+use kvs::{Error, KvsEngine, MemoryKvStore, Result, SharedEngine};
+use loom::thread;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Get(&'static str),
+    Set(&'static str, &'static str),
+    Remove(&'static str),
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Value(Option<String>),
+    Done,
+    KeyNotFound,
+}
+
+type Model = BTreeMap<String, String>;
+
+fn apply(engine: &SharedEngine<MemoryKvStore>, op: &Op) -> Outcome {
+    match *op {
+        Op::Get(key) => Outcome::Value(engine.get(key.to_owned()).expect("get failed")),
+        Op::Set(key, value) => {
+            engine.set(key.to_owned(), value.to_owned()).expect("set failed");
+            Outcome::Done
+        }
+        Op::Remove(key) => match engine.remove(key.to_owned()) {
+            Ok(()) => Outcome::Done,
+            Err(Error::KeyNotFound) => Outcome::KeyNotFound,
+            Err(err) => panic!("remove failed: {}", err),
+        },
+    }
+}
+
+fn apply_model(model: &mut Model, op: &Op) -> Outcome {
+    match *op {
+        Op::Get(key) => Outcome::Value(model.get(key).cloned()),
+        Op::Set(key, value) => {
+            model.insert(key.to_owned(), value.to_owned());
+            Outcome::Done
+        }
+        Op::Remove(key) => match model.remove(key) {
+            Some(_) => Outcome::Done,
+            None => Outcome::KeyNotFound,
+        },
+    }
+}
+
+// Whether running the threads' remaining operations one at a time on `model`, in some order that
+// keeps each thread's operations in order, gives the outcomes they saw and leaves `end`
+fn explains(
+    model: &Model,
+    ops: &[Vec<Op>],
+    outcomes: &[Vec<Outcome>],
+    done: &mut [usize],
+    end: &Model,
+) -> bool {
+    if done.iter().zip(ops).all(|(&done, ops)| done == ops.len()) {
+        return model == end;
+    }
+    (0..ops.len()).any(|thread| {
+        let next = done[thread];
+        if next == ops[thread].len() {
+            return false;
+        }
+        let mut model = model.clone();
+        if apply_model(&mut model, &ops[thread][next]) != outcomes[thread][next] {
+            return false;
+        }
+        done[thread] += 1;
+        let explained = explains(&model, ops, outcomes, done, end);
+        done[thread] -= 1;
+        explained
+    })
+}
+
+// Run each list of operations on its own thread against a store holding `initial`, checking that
+// every interleaving is explained by running the operations one at a time
+fn check_linearizable(initial: &'static [(&'static str, &'static str)], ops: Vec<Vec<Op>>) {
+    loom::model(move || {
+        let engine = SharedEngine::new(MemoryKvStore::new());
+        let mut model = Model::new();
+        for &(key, value) in initial {
+            engine.set(key.to_owned(), value.to_owned()).expect("set failed");
+            model.insert(key.to_owned(), value.to_owned());
+        }
+
+        let handles: Vec<_> = ops
+            .iter()
+            .cloned()
+            .map(|ops| {
+                let engine = engine.clone();
+                thread::spawn(move || ops.iter().map(|op| apply(&engine, op)).collect::<Vec<_>>())
+            })
+            .collect();
+        let outcomes: Vec<_> =
+            handles.into_iter().map(|handle| handle.join().expect("thread panicked")).collect();
+
+        let end: Model = engine.clone().scan("").expect("scan failed").into_iter().collect();
+        let mut done = vec![0; ops.len()];
+        assert!(
+            explains(&model, &ops, &outcomes, &mut done, &end),
+            "{:?} saw {:?}, leaving {:?}",
+            ops,
+            outcomes,
+            end
+        );
+    });
+}
+
+// A read racing a write should see either the old value or the new one, and once it's seen the
+// new one it shouldn't see the old one again
+#[test]
+fn get_racing_set() {
+    check_linearizable(
+        &[("key", "old")],
+        vec![vec![Op::Set("key", "new")], vec![Op::Get("key"), Op::Get("key")]],
+    );
+}
+
+// Racing writes to a key should leave the value of whichever was last, as seen by reads after
+// them
+#[test]
+fn racing_sets() {
+    check_linearizable(
+        &[],
+        vec![
+            vec![Op::Set("key", "a"), Op::Get("key")],
+            vec![Op::Set("key", "b"), Op::Get("key")],
+        ],
+    );
+}
+
+// Exactly one of two racing removes of a key should succeed, and a racing set should either
+// come before both or outlive them
+#[test]
+fn racing_removes() {
+    check_linearizable(
+        &[("key", "value")],
+        vec![
+            vec![Op::Remove("key")],
+            vec![Op::Remove("key")],
+            vec![Op::Set("key", "new"), Op::Get("key")],
+        ],
+    );
+}
+
+// Operations on different keys should not disturb each other
+#[test]
+fn different_keys() {
+    check_linearizable(
+        &[("a", "1")],
+        vec![
+            vec![Op::Set("b", "2"), Op::Get("a")],
+            vec![Op::Remove("a"), Op::Get("b")],
+        ],
+    );
+}
+
+// Increment the counter in a transaction
+fn increment(engine: &SharedEngine<MemoryKvStore>) -> Result<()> {
+    let mut transaction = engine.begin_transaction();
+    let counter = transaction.get("counter".to_owned())?.expect("no counter");
+    let counter: u64 = counter.parse().expect("not a number");
+    transaction.set("counter".to_owned(), (counter + 1).to_string());
+    transaction.commit()
+}
+
+// Of two transactions incrementing a counter, at least one should commit, and neither update
+// should be lost: one that read the counter before the other committed should conflict
+#[test]
+fn racing_transactions() {
+    loom::model(|| {
+        let engine = SharedEngine::new(MemoryKvStore::new());
+        engine.set("counter".to_owned(), "0".to_owned()).expect("set failed");
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let engine = engine.clone();
+                thread::spawn(move || match increment(&engine) {
+                    Ok(()) => 1,
+                    Err(Error::Conflict(_)) => 0,
+                    Err(err) => panic!("increment failed: {}", err),
+                })
+            })
+            .collect();
+        let commits: u64 =
+            handles.into_iter().map(|handle| handle.join().expect("thread panicked")).sum();
+
+        assert!(commits >= 1);
+        let counter = engine.get("counter".to_owned()).expect("get failed");
+        assert_eq!(counter, Some(commits.to_string()));
+    });
+}
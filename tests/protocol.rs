@@ -0,0 +1,533 @@
+use kvs::{
+    decode_request, decode_response, encode_request, encode_response, Change, Durability,
+    EngineStats, ErrorKind, NotFoundReason, Request, RequestDecoder, Response, ResponseDecoder,
+    Result, Schema, SchemaFormat, SchemaType, ServerInfo, Stats, TenantQuota, TenantUsage,
+    PROTOCOL_VERSION,
+};
+use std::collections::BTreeMap;
+
+fn requests() -> Vec<Request> {
+    vec![
+        Request::Get { key: "key".to_owned() },
+        Request::Set {
+            key: "key".to_owned(),
+            value: "value".to_owned(),
+            ttl_ms: None,
+            durability: Durability::Applied,
+        },
+        Request::Set {
+            key: "ключ".to_owned(),
+            value: String::new(),
+            ttl_ms: Some(1500),
+            durability: Durability::Fsynced,
+        },
+        Request::Remove { key: "key".to_owned() },
+        Request::Scan { prefix: "user:".to_owned() },
+        Request::Stats,
+        Request::HotKeys { limit: 10 },
+        Request::Tenants,
+        Request::Info,
+        Request::Maintenance { duration_ms: 60_000 },
+        Request::SampleKeys { count: 100 },
+        Request::Watch { prefix: "user:".to_owned() },
+        Request::CountPrefix { prefix: "session:".to_owned() },
+        Request::RemovePrefix { prefix: "session:".to_owned() },
+        Request::Ping,
+        Request::ColdKeys { prefix: "user:".to_owned(), unread_for_secs: 86_400, limit: 10 },
+        Request::ScanRange { start: "user:1".to_owned(), end: Some("user:5".to_owned()) },
+        Request::ScanRange { start: String::new(), end: None },
+        Request::MultiGet { keys: vec!["a".to_owned(), "b".to_owned()] },
+        Request::MultiSet {
+            pairs: vec![("a".to_owned(), "1".to_owned())],
+            durability: Durability::Fsynced,
+        },
+        Request::MultiRemove { keys: Vec::new() },
+        Request::Begin,
+        Request::TransactionGet { id: 7, key: "key".to_owned() },
+        Request::Commit { id: 7, writes: vec![("a".to_owned(), Some("1".to_owned()))] },
+        Request::Rollback { id: 7 },
+        Request::CompareAndSwap { key: "key".to_owned(), expected: None, new: "1".to_owned() },
+        Request::Increment { key: "key".to_owned(), delta: 1 },
+        Request::Rename { from: "a".to_owned(), to: "b".to_owned(), overwrite: false },
+        Request::GetIfModified { key: "key".to_owned(), version: u64::MAX },
+        Request::Ttl { key: "key".to_owned() },
+        Request::Expire { key: "key".to_owned(), ttl_ms: u64::MAX },
+        Request::Persist { key: "key".to_owned() },
+        Request::ForceRemove { key: "key".to_owned() },
+        Request::PutCas { value: "value".to_owned() },
+        Request::GetBytes { key: b"\xffkey".to_vec() },
+        Request::SetBytes {
+            key: b"\xffkey".to_vec(),
+            value: vec![0xc3, 0x28],
+            durability: Durability::Fsynced,
+        },
+        Request::RemoveBytes { key: Vec::new() },
+        Request::ScanBytes { prefix: vec![0xff] },
+    ]
+}
+
+fn responses() -> Vec<Response> {
+    let stats = Stats {
+        store_id: "store".to_owned(),
+        uptime_secs: 42,
+        hot_prefixes: vec![("user:".to_owned(), 7)],
+        engine: EngineStats { keys: 3, log_files: 2, ..EngineStats::default() },
+        ..Stats::default()
+    };
+    let tenant = TenantUsage {
+        tenant: "app1".to_owned(),
+        keys: 1,
+        bytes: 8,
+        requests: 5,
+        rejected: 1,
+        quota: TenantQuota { max_keys: Some(10), max_requests_per_sec: Some(0.5), max_bytes: None },
+    };
+    let errors = [
+        ErrorKind::InvalidRequest,
+        ErrorKind::EngineError,
+        ErrorKind::IndexFull,
+        ErrorKind::InvalidKey,
+        ErrorKind::KeyQuotaExceeded,
+        ErrorKind::ByteQuotaExceeded,
+        ErrorKind::RateLimited,
+        ErrorKind::TtlUnsupported,
+        ErrorKind::Backpressure,
+        ErrorKind::DiskFull,
+        ErrorKind::Maintenance,
+        ErrorKind::Timeout,
+        ErrorKind::Conflict,
+        ErrorKind::InvalidValue,
+        ErrorKind::ReadOnly,
+        ErrorKind::Immutable,
+    ];
+
+    let mut responses = vec![
+        Response::Ok,
+        Response::NotFound { reason: None },
+        Response::Found { value: "value".to_owned() },
+        Response::Entries { entries: vec![("user:1".to_owned(), "alice".to_owned())] },
+        Response::Stats { stats: Box::new(stats) },
+        Response::HotKeys { keys: vec![("a".to_owned(), 2), ("b".to_owned(), 1)] },
+        Response::Tenants { tenants: vec![tenant] },
+        Response::Info {
+            info: ServerInfo {
+                version: "0.1.0".to_owned(),
+                protocol_version: PROTOCOL_VERSION,
+                engine: "kvs".to_owned(),
+                features: vec!["tenants".to_owned()],
+                store_id: "store".to_owned(),
+            },
+        },
+        Response::Keys { keys: vec!["a".to_owned(), "b".to_owned()] },
+        Response::Change {
+            change: Change {
+                seq: 3,
+                timestamp_ms: 1_500_000_000_000,
+                key: b"user:1".to_vec(),
+                value: None,
+            },
+        },
+        Response::Count { count: 12 },
+        Response::Pong,
+        Response::Heartbeat,
+        Response::Values { values: vec![Some("1".to_owned()), None] },
+        Response::Transaction { id: 7 },
+        Response::Swapped { swapped: true },
+        Response::Counter { value: -1 },
+        Response::Renamed { renamed: false },
+        Response::NotModified,
+        Response::Ttl { ttl_ms: Some(1500) },
+        Response::Ttl { ttl_ms: None },
+        Response::Stored { key: "cas/key".to_owned(), deduplicated: false },
+        Response::FoundBytes { value: vec![0xc3, 0x28] },
+        Response::ByteEntries { entries: vec![(b"\xffkey".to_vec(), Vec::new())] },
+    ];
+    for kind in errors {
+        responses.push(Response::Err { kind, message: "message".to_owned() });
+    }
+    responses
+}
+
+/// The golden encodings in `tests/fixtures/protocol.txt`, by case name.
+fn fixtures() -> BTreeMap<String, Vec<u8>> {
+    include_str!("fixtures/protocol.txt")
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (name, bytes) = line.split_once(':').expect("fixtures are `case: bytes`");
+            let bytes = bytes
+                .split_whitespace()
+                .map(|byte| u8::from_str_radix(byte, 16).expect("fixture bytes are hex"))
+                .collect();
+            (name.to_owned(), bytes)
+        })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+fn request_cases() -> Vec<(&'static str, Request)> {
+    let (key, value) = (|| "key".to_owned(), || "value".to_owned());
+    let set = |key: String, value: String, ttl_ms: Option<u64>| Request::Set {
+        key,
+        value,
+        ttl_ms,
+        durability: Durability::Applied,
+    };
+    vec![
+        ("get", Request::Get { key: key() }),
+        ("get_empty_key", Request::Get { key: String::new() }),
+        ("set", set(key(), value(), None)),
+        ("set_ttl", set("ключ".to_owned(), String::new(), Some(1500))),
+        ("set_max_ttl", set(key(), value(), Some(u64::MAX))),
+        ("remove", Request::Remove { key: key() }),
+        ("scan", Request::Scan { prefix: "user:".to_owned() }),
+        ("stats", Request::Stats),
+        ("hot_keys", Request::HotKeys { limit: 10 }),
+        ("tenants", Request::Tenants),
+        ("info", Request::Info),
+        ("maintenance", Request::Maintenance { duration_ms: 60_000 }),
+        ("sample_keys", Request::SampleKeys { count: 100 }),
+        ("watch", Request::Watch { prefix: "user:".to_owned() }),
+        ("count_prefix", Request::CountPrefix { prefix: "session:".to_owned() }),
+        ("remove_prefix", Request::RemovePrefix { prefix: "session:".to_owned() }),
+        ("ping", Request::Ping),
+        (
+            "cold_keys",
+            Request::ColdKeys { prefix: "user:".to_owned(), unread_for_secs: 86_400, limit: 10 },
+        ),
+        (
+            "scan_range",
+            Request::ScanRange { start: "user:1".to_owned(), end: Some("user:5".to_owned()) },
+        ),
+        ("scan_range_unbounded", Request::ScanRange { start: "user:1".to_owned(), end: None }),
+        ("multi_get", Request::MultiGet { keys: vec!["a".to_owned(), "b".to_owned()] }),
+        (
+            "multi_set",
+            Request::MultiSet {
+                pairs: vec![("a".to_owned(), "1".to_owned())],
+                durability: Durability::Applied,
+            },
+        ),
+        (
+            "multi_set_fsynced",
+            Request::MultiSet {
+                pairs: vec![("a".to_owned(), "1".to_owned())],
+                durability: Durability::Fsynced,
+            },
+        ),
+        (
+            "set_fsynced",
+            Request::Set {
+                key: key(),
+                value: value(),
+                ttl_ms: None,
+                durability: Durability::Fsynced,
+            },
+        ),
+        ("multi_remove", Request::MultiRemove { keys: vec!["a".to_owned(), "b".to_owned()] }),
+        ("begin", Request::Begin),
+        ("transaction_get", Request::TransactionGet { id: 7, key: key() }),
+        (
+            "commit",
+            Request::Commit {
+                id: 7,
+                writes: vec![("a".to_owned(), Some("1".to_owned())), ("b".to_owned(), None)],
+            },
+        ),
+        ("rollback", Request::Rollback { id: 7 }),
+        (
+            "compare_and_swap",
+            Request::CompareAndSwap {
+                key: key(),
+                expected: Some("old".to_owned()),
+                new: "new".to_owned(),
+            },
+        ),
+        ("increment", Request::Increment { key: key(), delta: -2 }),
+        (
+            "rename",
+            Request::Rename { from: "a".to_owned(), to: "b".to_owned(), overwrite: true },
+        ),
+        ("get_if_modified", Request::GetIfModified { key: key(), version: 42 }),
+        ("ttl", Request::Ttl { key: key() }),
+        ("expire", Request::Expire { key: key(), ttl_ms: 1000 }),
+        ("persist", Request::Persist { key: key() }),
+        ("force_remove", Request::ForceRemove { key: key() }),
+        ("put_cas", Request::PutCas { value: value() }),
+        ("get_bytes", Request::GetBytes { key: b"\xffk".to_vec() }),
+        (
+            "set_bytes",
+            Request::SetBytes {
+                key: b"\xffk".to_vec(),
+                value: vec![0x00, 0x01],
+                durability: Durability::Applied,
+            },
+        ),
+        ("remove_bytes", Request::RemoveBytes { key: b"\xffk".to_vec() }),
+        ("scan_bytes", Request::ScanBytes { prefix: vec![0xff] }),
+    ]
+}
+
+/// Every response but `Stats`, which is extended by appending fields and is only read by tools
+/// that ship with the server.
+fn response_cases() -> Vec<(String, Response)> {
+    let tenant = TenantUsage {
+        tenant: "app1".to_owned(),
+        keys: 1,
+        bytes: 8,
+        requests: 5,
+        rejected: 1,
+        quota: TenantQuota { max_keys: Some(10), max_requests_per_sec: Some(0.5), max_bytes: None },
+    };
+    let info = ServerInfo {
+        version: "0.1.0".to_owned(),
+        protocol_version: 1,
+        engine: "kvs".to_owned(),
+        features: vec!["tenants".to_owned()],
+        store_id: "store".to_owned(),
+    };
+    let change = |seq, value: Option<&str>| Response::Change {
+        change: Change {
+            seq,
+            timestamp_ms: 1_500_000_000_000,
+            key: b"user:1".to_vec(),
+            value: value.map(|value| value.as_bytes().to_vec()),
+        },
+    };
+    let errors = [
+        ("invalid_request", ErrorKind::InvalidRequest),
+        ("engine_error", ErrorKind::EngineError),
+        ("index_full", ErrorKind::IndexFull),
+        ("invalid_key", ErrorKind::InvalidKey),
+        ("key_quota_exceeded", ErrorKind::KeyQuotaExceeded),
+        ("byte_quota_exceeded", ErrorKind::ByteQuotaExceeded),
+        ("rate_limited", ErrorKind::RateLimited),
+        ("ttl_unsupported", ErrorKind::TtlUnsupported),
+        ("backpressure", ErrorKind::Backpressure),
+        ("disk_full", ErrorKind::DiskFull),
+        ("maintenance", ErrorKind::Maintenance),
+        ("timeout", ErrorKind::Timeout),
+        ("conflict", ErrorKind::Conflict),
+        ("invalid_value", ErrorKind::InvalidValue),
+        ("read_only", ErrorKind::ReadOnly),
+        ("immutable", ErrorKind::Immutable),
+    ];
+
+    let cases = vec![
+        ("ok", Response::Ok),
+        ("not_found", Response::NotFound { reason: None }),
+        ("not_found_expired", Response::NotFound { reason: Some(NotFoundReason::Expired) }),
+        ("found", Response::Found { value: "value".to_owned() }),
+        ("found_empty", Response::Found { value: String::new() }),
+        ("entries", Response::Entries { entries: vec![("user:1".to_owned(), "alice".to_owned())] }),
+        ("entries_empty", Response::Entries { entries: Vec::new() }),
+        ("hot_keys", Response::HotKeys { keys: vec![("a".to_owned(), 2), ("b".to_owned(), 1)] }),
+        ("tenants", Response::Tenants { tenants: vec![tenant] }),
+        ("info", Response::Info { info }),
+        ("keys", Response::Keys { keys: vec!["a".to_owned(), "b".to_owned()] }),
+        ("change_removed", change(3, None)),
+        ("change_set", change(4, Some("alice"))),
+        (
+            "change_bytes",
+            Response::Change {
+                change: Change {
+                    seq: 5,
+                    timestamp_ms: 1_500_000_000_000,
+                    key: b"\xffk".to_vec(),
+                    value: Some(vec![0xc3, 0x28]),
+                },
+            },
+        ),
+        ("count", Response::Count { count: 12 }),
+        ("pong", Response::Pong),
+        ("heartbeat", Response::Heartbeat),
+        ("values", Response::Values { values: vec![Some("1".to_owned()), None] }),
+        ("transaction", Response::Transaction { id: 7 }),
+        ("swapped", Response::Swapped { swapped: true }),
+        ("counter", Response::Counter { value: 42 }),
+        ("renamed", Response::Renamed { renamed: true }),
+        ("not_modified", Response::NotModified),
+        ("ttl", Response::Ttl { ttl_ms: Some(1500) }),
+        ("ttl_none", Response::Ttl { ttl_ms: None }),
+        ("stored", Response::Stored { key: "key".to_owned(), deduplicated: true }),
+        ("found_bytes", Response::FoundBytes { value: vec![0xc3, 0x28] }),
+        (
+            "byte_entries",
+            Response::ByteEntries { entries: vec![(b"\xffk".to_vec(), vec![0x00])] },
+        ),
+    ];
+    let mut cases: Vec<_> = cases.into_iter().map(|(name, case)| (name.to_owned(), case)).collect();
+    for (name, kind) in errors {
+        let message = "message".to_owned();
+        cases.push((format!("err_{}", name), Response::Err { kind, message }));
+    }
+    cases
+}
+
+// Every request should decode to what was encoded
+#[test]
+fn request_round_trip() -> Result<()> {
+    for request in requests() {
+        let mut bytes = Vec::new();
+        encode_request(&mut bytes, &request)?;
+        let decoded = decode_request(&bytes[..])?;
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", request));
+    }
+    Ok(())
+}
+
+// Every response should decode to what was encoded
+#[test]
+fn response_round_trip() -> Result<()> {
+    for response in responses() {
+        let mut bytes = Vec::new();
+        encode_response(&mut bytes, &response)?;
+        let decoded = decode_response(&bytes[..])?;
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", response));
+    }
+    Ok(())
+}
+
+// The schema should describe every request and response variant, with their wire indices and
+// fields, as they're actually encoded
+#[test]
+fn schema() -> Result<()> {
+    let schema = Schema::current();
+    assert_eq!(schema.protocol_version, PROTOCOL_VERSION);
+    let variants = |name: &str| match &schema.types[name] {
+        SchemaType::Enum(variants) => variants.clone(),
+        other => panic!("{} is not an enum: {:?}", name, other),
+    };
+    let names = |name: &str| -> Vec<String> {
+        variants(name).into_iter().map(|variant| variant.name).collect()
+    };
+    for request in requests() {
+        let debug = format!("{:?}", request);
+        assert!(names("Request").iter().any(|name| debug.starts_with(name.as_str())), "{}", debug);
+    }
+    for response in responses() {
+        let debug = format!("{:?}", response);
+        assert!(names("Response").iter().any(|name| debug.starts_with(name.as_str())), "{}", debug);
+    }
+    assert_eq!(names("ErrorKind").last().map(String::as_str), Some("Immutable"));
+
+    let set = variants("Request").into_iter().find(|variant| variant.name == "Set").unwrap();
+    let fields: Vec<_> = set.fields.iter().map(|field| field.name.as_str()).collect();
+    assert_eq!(fields, vec!["key", "value", "ttl_ms", "durability"]);
+    assert_eq!(set.fields[2].format, SchemaFormat::Option(Box::new(SchemaFormat::U64)));
+    let set_bytes = variants("Request").into_iter().find(|variant| variant.name == "SetBytes");
+    assert_eq!(set_bytes.unwrap().fields[1].format, SchemaFormat::Bytes);
+    assert!(schema.types.contains_key("Stats"));
+
+    // `Get` is variant 0, encoded as [0, ["k"]].
+    let mut bytes = Vec::new();
+    encode_request(&mut bytes, &Request::Get { key: "k".to_owned() })?;
+    assert_eq!(bytes, b"\x92\x00\x91\xa1k");
+    assert!(serde_json::to_string(&schema).is_ok());
+    Ok(())
+}
+
+// Every message should encode to exactly the bytes in its golden fixture, which should decode back
+// to it, so that changes can't break existing clients or servers
+#[test]
+fn golden_encodings() -> Result<()> {
+    let mut fixtures = fixtures();
+    let mut golden = |name: String, bytes: Vec<u8>| {
+        let expected = fixtures.remove(&name).unwrap_or_else(|| panic!("no fixture for {}", name));
+        assert_eq!(hex(&bytes), hex(&expected), "the encoding of {} changed", name);
+        expected
+    };
+    for (name, request) in request_cases() {
+        let mut bytes = Vec::new();
+        encode_request(&mut bytes, &request)?;
+        let expected = golden(format!("request {}", name), bytes);
+        assert_eq!(format!("{:?}", decode_request(&expected[..])?), format!("{:?}", request));
+    }
+    for (name, response) in response_cases() {
+        let mut bytes = Vec::new();
+        encode_response(&mut bytes, &response)?;
+        let expected = golden(format!("response {}", name), bytes);
+        assert_eq!(format!("{:?}", decode_response(&expected[..])?), format!("{:?}", response));
+    }
+    assert!(fixtures.is_empty(), "fixtures without cases: {:?}", fixtures.keys());
+    Ok(())
+}
+
+// Strings should switch to longer length headers at the MessagePack boundaries, up to strings
+// longer than 64KiB
+#[test]
+fn string_lengths() -> Result<()> {
+    let headers: &[(usize, &[u8])] = &[
+        (0, b"\xa0"),
+        (31, b"\xbf"),
+        (32, b"\xd9\x20"),
+        (255, b"\xd9\xff"),
+        (256, b"\xda\x01\x00"),
+        (65_535, b"\xda\xff\xff"),
+        (65_536, b"\xdb\x00\x01\x00\x00"),
+    ];
+    for &(len, header) in headers {
+        let key = "k".repeat(len);
+        let mut bytes = Vec::new();
+        encode_request(&mut bytes, &Request::Get { key: key.clone() })?;
+        assert_eq!(bytes, [&b"\x92\x00\x91"[..], header, key.as_bytes()].concat(), "{} bytes", len);
+        match decode_request(&bytes[..])? {
+            Request::Get { key: decoded } => assert_eq!(decoded, key),
+            other => panic!("expected a get, decoded {:?}", other),
+        }
+    }
+    Ok(())
+}
+
+// Streaming decoders should only yield messages once they've been received in full, one byte at a
+// time, and leave the rest of the stream buffered
+#[test]
+fn streaming_round_trip() -> Result<()> {
+    let mut bytes = Vec::new();
+    for request in requests() {
+        encode_request(&mut bytes, &request)?;
+    }
+    let mut decoder = RequestDecoder::new();
+    let mut decoded = Vec::new();
+    for byte in &bytes {
+        decoder.extend(&[*byte]);
+        if let Some(request) = decoder.decode()? {
+            decoded.push(format!("{:?}", request));
+        }
+    }
+    let expected: Vec<_> = requests().iter().map(|request| format!("{:?}", request)).collect();
+    assert_eq!(decoded, expected);
+    assert_eq!(decoder.buffered(), 0);
+
+    let mut bytes = Vec::new();
+    for response in responses() {
+        encode_response(&mut bytes, &response)?;
+    }
+    let mut decoder = ResponseDecoder::new();
+    decoder.extend(&bytes);
+    let mut decoded = Vec::new();
+    while let Some(response) = decoder.decode()? {
+        decoded.push(format!("{:?}", response));
+    }
+    let expected: Vec<_> = responses().iter().map(|response| format!("{:?}", response)).collect();
+    assert_eq!(decoded, expected);
+    assert_eq!(decoder.buffered(), 0);
+    Ok(())
+}
+
+// Malformed input should be an error, rather than waiting for more bytes
+#[test]
+fn streaming_invalid() {
+    let mut decoder = RequestDecoder::new();
+    decoder.extend(&[0xc1]);
+    assert!(decoder.decode().is_err());
+}
+
+// A reason code from a newer peer should be an error, rather than being mistaken for another
+#[test]
+fn unknown_not_found_reason() {
+    assert!(decode_response(&[0x92, 0x01, 0x91, 0x04][..]).is_err());
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use kvs::recovery::{self, Cuts, Simulation, Step};
+use kvs::{KvStore, KvsEngine, MemoryKvStore, Result};
+use tempfile::TempDir;
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+fn s(value: &str) -> String {
+    value.to_owned()
+}
+
+// A workload covering TTLs, batches, renames and removes, then overwriting a key until the log is
+// compacted.
+fn workload() -> Vec<Step> {
+    let mut steps = vec![
+        Step::Set(s("a"), s("1")),
+        Step::SetWithTtl(s("session"), s("token"), HOUR),
+        Step::SetMany(vec![(s("b"), s("2")), (s("c"), s("3")), (s("d"), s("4"))]),
+        Step::Rename { from: s("a"), to: s("e"), overwrite: false },
+        Step::Rename { from: s("session"), to: s("session2"), overwrite: false },
+        Step::Rename { from: s("b"), to: s("c"), overwrite: true },
+        Step::Remove(s("d")),
+        Step::RemoveMany(vec![s("c"), s("e")]),
+    ];
+    for i in 0..30 {
+        steps.push(Step::Set(s("counter"), i.to_string()));
+    }
+    steps.push(Step::SetWithTtl(s("counter"), s("done"), HOUR));
+    steps
+}
+
+// A kvs store should recover from a power cut anywhere in the log to a state its writes passed
+// through, keeping TTLs, applying renames atomically and batches as a prefix, including across
+// compactions
+#[test]
+fn kvs_store_recovers_from_power_cuts() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let open = |path: &Path| KvStore::builder().compaction_threshold(256).open(path);
+    let report = Simulation::new(temp_dir.path(), open).cuts(Cuts::Every(2)).run(&workload())?;
+
+    let violations: Vec<_> = report.violations.iter().map(ToString::to_string).collect();
+    assert!(violations.is_empty(), "{:#?}", violations);
+    assert!(report.log_changes > 0);
+    assert!(report.power_cuts > 4 * workload().len() as u64);
+    Ok(())
+}
+
+// Cuts can be made at chosen offsets of the log, and a cut through the last command should leave
+// it torn and discarded
+#[test]
+fn power_cuts_at_offsets() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let workload = &workload()[..4];
+    let offsets = (0..1024).step_by(5).collect();
+    let report = Simulation::new(temp_dir.path(), |path: &Path| KvStore::open(path))
+        .cuts(Cuts::At(offsets))
+        .run(workload)?;
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(report.log_changes, 0);
+    assert!(report.power_cuts > workload.len() as u64);
+
+    let store = temp_dir.path().join("store");
+    let log = recovery::log_files(&store)?.pop().expect("the store has a log file");
+    let length = fs::metadata(&log)?.len();
+    let copy = temp_dir.path().join("copy");
+    recovery::power_cut(&store, &copy, &log, length - 1)?;
+    assert_eq!(fs::metadata(copy.join(log.file_name().unwrap()))?.len(), length - 1);
+    let mut recovered = KvStore::open(&copy)?;
+    assert!(recovered.stats()?.torn_tail_bytes > 0);
+    assert_eq!(recovered.get(s("a"))?, Some(s("1")));
+    assert_eq!(recovered.get(s("e"))?, None);
+    Ok(())
+}
+
+// Writes an engine loses when its directory is copied should be reported
+#[test]
+fn lost_writes_are_reported() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let workload = [Step::Set(s("key"), s("value"))];
+    let report =
+        Simulation::new(temp_dir.path(), |_: &Path| Ok(MemoryKvStore::new())).run(&workload)?;
+    assert_eq!(report.power_cuts, 1);
+    assert_eq!(report.violations.len(), 2);
+    assert!(report.violations.iter().all(|violation| violation.step == 0));
+    assert!(report.violations[0].problem.contains("never passed through"));
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use kvs::testing::{MockClient, MockServer};
+use kvs::{Durability, Error, ErrorKind, Fault, KvsClient, Request, Response, Result};
+
+// A mock server should serve requests from memory, failing the ones it's told to
+#[test]
+fn mock_server() -> Result<()> {
+    let server = MockServer::start()?;
+    server.client()?.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(server.client()?.get("key".to_owned())?, Some("value".to_owned()));
+
+    server.fail_next(Fault::Error);
+    server.fail_next(Fault::Drop);
+    assert!(server.client()?.get("key".to_owned()).is_err());
+    assert!(server.client()?.get("key".to_owned()).is_err());
+    assert_eq!(server.client()?.get("key".to_owned())?, Some("value".to_owned()));
+
+    // Each mock server has its own port and store
+    let other = MockServer::start()?;
+    assert_ne!(other.address(), server.address());
+    assert_eq!(other.client()?.get("key".to_owned())?, None);
+    Ok(())
+}
+
+// A mock client should return its canned responses in order, and record its requests
+#[test]
+fn mock_client() -> Result<()> {
+    let mut client = MockClient::new();
+    client
+        .respond(Response::Ok)
+        .respond(Response::NotFound { reason: None })
+        .respond(Response::Err { kind: ErrorKind::RateLimited, message: "slow down".to_owned() })
+        .respond(Response::Entries { entries: vec![("a".to_owned(), "1".to_owned())] });
+
+    client.set("a".to_owned(), "1".to_owned())?;
+    match client.remove("b".to_owned()) {
+        Err(Error::KeyNotFound) => {},
+        result => panic!("expected KeyNotFound, got {:?}", result),
+    }
+    assert!(client.get("a".to_owned()).is_err());
+    assert_eq!(client.scan("".to_owned())?, vec![("a".to_owned(), "1".to_owned())]);
+
+    let requests: Vec<_> =
+        client.requests().iter().map(|request| format!("{:?}", request)).collect();
+    let expected = [
+        Request::Set {
+            key: "a".to_owned(),
+            value: "1".to_owned(),
+            ttl_ms: None,
+            durability: Durability::Applied,
+        },
+        Request::Remove { key: "b".to_owned() },
+        Request::Get { key: "a".to_owned() },
+        Request::Scan { prefix: "".to_owned() },
+    ];
+    let expected: Vec<_> = expected.iter().map(|request| format!("{:?}", request)).collect();
+    assert_eq!(requests, expected);
+    Ok(())
+}
+
+// Running out of canned responses is a bug in the test
+#[test]
+#[should_panic(expected = "no response queued")]
+fn mock_client_exhausted() {
+    let _ = MockClient::new().get("key".to_owned());
+}